@@ -48,11 +48,18 @@ class CASTCompressor:
         if isinstance(sample, str):
             sample = sample.encode("utf-8", errors="ignore")
 
+        # Same rule as is_likely_binary in rust/src/cast.rs (see BINARY GUARD there):
+        # control bytes other than whitespace, except ESC '[' (ANSI colors)
         suspicious_chars = 0
         total_chars = len(sample)
 
-        for byte_val in sample:
-            if byte_val == 0 or (0 < byte_val < 32 and byte_val not in (9, 10, 13)):
+        for i, byte_val in enumerate(sample):
+            if byte_val in (9, 10, 11, 12, 13):
+                continue
+            if byte_val == 0x1B:
+                if sample[i + 1:i + 2] != b"[":
+                    suspicious_chars += 1
+            elif byte_val < 32:
                 suspicious_chars += 1
 
         if total_chars > 0 and (suspicious_chars / total_chars > 0.01):
@@ -0,0 +1,1368 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher as _};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use crc32fast::Hasher;
+
+use crate::auth::MacTrailer;
+use crate::cancel::CancellationToken;
+use crate::cast::{column_stream_codecs, describe_id_flag, is_passthrough, stored_layout, u32_len, CASTCompressor, IdsTransform, NativeCompressor, ParserConfig, ID_FLAG_DUPLICATE, ID_FLAG_MAC_TRAILER};
+use crate::cast_lzma::{xz_check_name, xz_dict_size};
+use crate::error::CastError;
+
+// ============================================================================
+//  ARCHIVE LAYOUT
+// ============================================================================
+//
+//  [Archive Header] (absent in version 1 archives)
+//      magic "CAST" (4) | version u8 | flags u8 | original_size u64
+//      | labels_len u32 | [key_len u16 | key | value_len u16 | value]* (UTF-8)
+//  [Chunk]*
+//      crc u32 | len_reg u32 | len_ids u32 | len_vars u32 | id_flag u8 | reg | ids | vars
+//
+//  Columnar chunks of version 2 open their vars with the parser record, the tokenizer
+//  settings the chunk was parsed with (see "PARSER CONFIGURATION" in cast.rs). Readers take
+//  it off before decoding (`Chunk::vars`), so the blocks themselves are unchanged.
+//
+//  A zero-length segment is an empty stream: it is written without calling the backend and
+//  decodes to nothing, whichever backend wrote the archive. Chunks whose reg and ids are both
+//  empty hold one unified block in vars.
+//
+//  A duplicate chunk (id_flag 253) stores no data: its vars are the 1-based number
+//  (u64) of an earlier chunk holding the same input, and its CRC is that chunk's CRC. Readers
+//  resolve it by reading the earlier chunk again (see `ChunkReader::resolve`).
+//
+//  An authenticated archive (FLAG_AUTHENTICATED) ends with one more chunk, the
+//  authentication trailer (id_flag 252, see auth.rs). It holds no data: readers stop at it.
+//
+//  Segment lengths are u32 (so are the lengths inside a decoded block): an input chunk whose
+//  segments would reach MAX_SEGMENT_LEN is written as several chunks, split at line boundaries.
+
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"CAST";
+pub const LEGACY_VERSION: u8 = 1;
+// Version 1 is the headerless format of the first release, read but no longer written. Version
+// 2 added the archive header, and with it every chunk kind and id_flag bit past those of
+// version 1 (see "TEMPLATE ID STREAM" in cast.rs) and the parser record of columnar chunks
+pub const MIN_FORMAT_VERSION: u8 = 2;
+pub const FORMAT_VERSION: u8 = 2;
+// Header size without labels
+pub const ARCHIVE_HEADER_SIZE: usize = 18;
+// Fixed fields in front of the original size, and in front of the labels section
+const BASE_HEADER_SIZE: usize = 6;
+const SIZED_HEADER_SIZE: usize = 14;
+// Upper bound of the encoded labels section
+pub const MAX_LABELS_SIZE: usize = 64 * 1024;
+// Stored when the original size was not known when the header was written
+const ORIGINAL_SIZE_UNKNOWN: u64 = u64::MAX;
+pub const CHUNK_HEADER_SIZE: usize = 17;
+pub const MAX_SEGMENT_LEN: u64 = u32::MAX as u64;
+
+// Archive flags
+pub const FLAG_NO_CHECKSUM: u8 = 0x01;
+// The input started with a UTF-8 BOM, stripped before chunking and re-emitted on restore
+pub const FLAG_INPUT_BOM: u8 = 0x02;
+// The input shrank or went back in time while it was read (--allow-truncation): the archive
+// holds what was read, which may mix old and new content
+pub const FLAG_INPUT_CHANGED: u8 = 0x04;
+// The archive ends with an authentication trailer (--hmac-key-file, see auth.rs)
+pub const FLAG_AUTHENTICATED: u8 = 0x08;
+
+pub const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChecksumKind { Crc32, None }
+
+impl ChecksumKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "crc32" | "crc" => Some(ChecksumKind::Crc32),
+            "none" | "off" => Some(ChecksumKind::None),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChecksumKind::Crc32 => "CRC32",
+            ChecksumKind::None => "None (Unverified)",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ArchiveHeader {
+    pub version: u8,
+    pub flags: u8,
+    /// Total bytes of the original input (BOM included); `None` for version 1 archives, and
+    /// when the size was not known.
+    pub original_size: Option<u64>,
+    /// Free-form `key=value` metadata, in insertion order (always empty in version 1).
+    pub labels: Vec<(String, String)>,
+}
+
+impl ArchiveHeader {
+    pub fn new(checksum: ChecksumKind) -> Self {
+        let mut flags = 0;
+        if checksum == ChecksumKind::None { flags |= FLAG_NO_CHECKSUM; }
+        Self { version: FORMAT_VERSION, flags, original_size: None, labels: Vec::new() }
+    }
+
+    /// Sets the labels, checking that they fit in the labels section.
+    pub fn with_labels(mut self, labels: Vec<(String, String)>) -> Result<Self, String> {
+        check_labels(&labels)?;
+        self.labels = labels;
+        Ok(self)
+    }
+
+    pub fn with_original_size(mut self, size: u64) -> Self {
+        self.original_size = Some(size);
+        self
+    }
+
+    /// Bytes taken by this header on disk (0 for version 1 archives, which have none).
+    pub fn encoded_len(&self) -> usize {
+        if self.version == LEGACY_VERSION { 0 } else { ARCHIVE_HEADER_SIZE + labels_len(&self.labels) }
+    }
+
+    pub fn with_bom(mut self, has_bom: bool) -> Self {
+        if has_bom { self.flags |= FLAG_INPUT_BOM; } else { self.flags &= !FLAG_INPUT_BOM; }
+        self
+    }
+
+    pub fn has_bom(&self) -> bool { self.flags & FLAG_INPUT_BOM != 0 }
+
+    pub fn input_changed(&self) -> bool { self.flags & FLAG_INPUT_CHANGED != 0 }
+
+    pub fn with_authentication(mut self, authenticated: bool) -> Self {
+        if authenticated { self.flags |= FLAG_AUTHENTICATED; } else { self.flags &= !FLAG_AUTHENTICATED; }
+        self
+    }
+
+    pub fn authenticated(&self) -> bool { self.flags & FLAG_AUTHENTICATED != 0 }
+
+    /// The header bytes exactly as `write_to` writes them (what the archive MAC covers).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        self.write_to(&mut bytes).expect("writing to a Vec cannot fail");
+        bytes
+    }
+
+    /// Column names of the header row recorded at compression (see `detect_column_names`).
+    pub fn column_names(&self) -> Option<Vec<&str>> {
+        self.labels.iter().find(|(k, _)| k == COLUMNS_LABEL).map(|(_, v)| v.split('\t').collect())
+    }
+
+    pub fn checksum(&self) -> ChecksumKind {
+        if self.flags & FLAG_NO_CHECKSUM != 0 { ChecksumKind::None } else { ChecksumKind::Crc32 }
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        bytes.extend_from_slice(&ARCHIVE_MAGIC);
+        bytes.push(self.version);
+        bytes.push(self.flags);
+        bytes.extend_from_slice(&self.original_size.unwrap_or(ORIGINAL_SIZE_UNKNOWN).to_le_bytes());
+        bytes.extend_from_slice(&(labels_len(&self.labels) as u32).to_le_bytes());
+        for (key, value) in &self.labels {
+            bytes.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(value.as_bytes());
+        }
+        bytes.truncate(self.encoded_len());
+        w.write_all(&bytes)
+    }
+
+    /// Byte ranges of the fields written by `write_to`, in order (for `--dump-format`).
+    pub fn layout(&self) -> Vec<(&'static str, Range<usize>)> {
+        let mut fields = vec![("magic", 0..4), ("version", 4..5), ("flags", 5..6), ("original_size", 6..14), ("labels_len", 14..ARCHIVE_HEADER_SIZE)];
+        let mut pos = ARCHIVE_HEADER_SIZE;
+        for (key, value) in &self.labels {
+            for (name, len) in [("key_len", 2), ("key", key.len()), ("value_len", 2), ("value", value.len())] {
+                fields.push((name, pos..pos + len));
+                pos += len;
+            }
+        }
+        fields.retain(|(_, r)| r.end <= self.encoded_len());
+        fields
+    }
+
+    /// Rewrites the original size of an archive whose header was written at the start of `w`
+    /// (used when the input turned out longer or shorter than announced).
+    pub fn patch_original_size<W: Write + Seek>(w: &mut W, size: u64) -> io::Result<()> {
+        w.seek(SeekFrom::Start(BASE_HEADER_SIZE as u64))?;
+        w.write_all(&size.to_le_bytes())
+    }
+
+    /// Rewrites the value of label `key` in an archive whose header (`self`, as written) is at
+    /// the start of `w`. The new value must be as long as the old one.
+    pub fn patch_label<W: Write + Seek>(&self, w: &mut W, key: &str, value: &str) -> io::Result<()> {
+        let mut pos = ARCHIVE_HEADER_SIZE;
+        for (k, v) in &self.labels {
+            pos += 2 + k.len() + 2;
+            if k == key {
+                if v.len() != value.len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("label '{}' would change length", key)));
+                }
+                w.seek(SeekFrom::Start(pos as u64))?;
+                return w.write_all(value.as_bytes());
+            }
+            pos += v.len();
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("no label '{}' in the archive header", key)))
+    }
+
+    /// SHA-256 of the original recorded at compression (see `CONTENT_DIGEST_LABEL`).
+    pub fn content_digest(&self) -> Option<[u8; 32]> {
+        let (_, value) = self.labels.iter().find(|(k, _)| k == CONTENT_DIGEST_LABEL)?;
+        let value = value.as_bytes();
+        if value.len() != 64 { return None; }
+        let mut digest = [0u8; 32];
+        for (byte, pair) in digest.iter_mut().zip(value.chunks_exact(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        // The placeholder of a run that never finished
+        if digest == [0; 32] { return None; }
+        Some(digest)
+    }
+
+    /// Row order checksum recorded at compression (see `ROW_ORDER_LABEL`).
+    pub fn row_order(&self) -> Option<RowOrder> {
+        let (_, value) = self.labels.iter().find(|(k, _)| k == ROW_ORDER_LABEL)?;
+        RowOrder::parse(value)
+    }
+
+    /// Rewrites the flags of an archive whose header was written at the start of `w` (used to
+    /// record `FLAG_INPUT_CHANGED` once the input is read).
+    pub fn patch_flags<W: Write + Seek>(w: &mut W, flags: u8) -> io::Result<()> {
+        w.seek(SeekFrom::Start(5))?;
+        w.write_all(&[flags])
+    }
+
+    /// Consumes the archive header if present. Version 1 archives start directly with a
+    /// chunk header, so nothing is consumed for them.
+    pub fn read_from<R: BufRead>(r: &mut R) -> Result<Self, CastError> {
+        let buf = r.fill_buf()?;
+        if buf.len() < BASE_HEADER_SIZE || buf[0..4] != ARCHIVE_MAGIC {
+            return Ok(Self { version: LEGACY_VERSION, flags: 0, original_size: None, labels: Vec::new() });
+        }
+        let version = buf[4];
+        let flags = buf[5];
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+            return Err(CastError::UnsupportedFormat { version });
+        }
+        let truncated = || CastError::CorruptHeader { chunk: 0, detail: "Truncated archive header".to_string() };
+        let mut header = Self { version, flags, original_size: None, labels: Vec::new() };
+        if buf.len() < SIZED_HEADER_SIZE { return Err(truncated()); }
+        let size = u64::from_le_bytes(buf[BASE_HEADER_SIZE..SIZED_HEADER_SIZE].try_into().unwrap());
+        if size != ORIGINAL_SIZE_UNKNOWN { header.original_size = Some(size); }
+
+        // The labels section can be larger than the reader's buffer: read it past the fixed part
+        r.consume(SIZED_HEADER_SIZE);
+        let mut len = [0u8; 4];
+        r.read_exact(&mut len).map_err(|_| truncated())?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_LABELS_SIZE {
+            return Err(CastError::CorruptHeader { chunk: 0, detail: format!("Labels section too large ({} bytes)", len) });
+        }
+        let mut section = vec![0u8; len];
+        r.read_exact(&mut section).map_err(|_| truncated())?;
+        header.labels = decode_labels(&section)
+            .ok_or_else(|| CastError::CorruptHeader { chunk: 0, detail: "Malformed labels section".to_string() })?;
+        Ok(header)
+    }
+}
+
+// ============================================================================
+//  LABELS
+// ============================================================================
+
+/// Parses a `key=value` label. The key ends at the first '='; the value may contain
+/// anything (including '=' and newlines). Keys must be non-empty and printable.
+pub fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| format!("'{}' is not in key=value form", s))?;
+    if key.is_empty() { return Err("Label key is empty".to_string()); }
+    if key.chars().any(|c| c.is_control()) { return Err(format!("Label key '{}' contains control characters", key.escape_debug())); }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Inserts or replaces `key` (keeping the position of an existing key).
+pub fn set_label(labels: &mut Vec<(String, String)>, key: String, value: String) {
+    match labels.iter_mut().find(|(k, _)| *k == key) {
+        Some(entry) => entry.1 = value,
+        None => labels.push((key, value)),
+    }
+}
+
+fn labels_len(labels: &[(String, String)]) -> usize {
+    labels.iter().map(|(k, v)| 4 + k.len() + v.len()).sum()
+}
+
+// The section limit also keeps every key and value within its u16 length prefix
+fn check_labels(labels: &[(String, String)]) -> Result<(), String> {
+    let len = labels_len(labels);
+    if len > MAX_LABELS_SIZE {
+        return Err(format!("Labels take {} bytes, the limit is {} bytes", len, MAX_LABELS_SIZE));
+    }
+    Ok(())
+}
+
+fn decode_labels(mut section: &[u8]) -> Option<Vec<(String, String)>> {
+    let take = |section: &mut &[u8]| -> Option<String> {
+        if section.len() < 2 { return None; }
+        let len = u16::from_le_bytes([section[0], section[1]]) as usize;
+        let bytes = section.get(2..2 + len)?;
+        let text = std::str::from_utf8(bytes).ok()?.to_string();
+        *section = &section[2 + len..];
+        Some(text)
+    };
+    let mut labels = Vec::new();
+    while !section.is_empty() {
+        let key = take(&mut section)?;
+        let value = take(&mut section)?;
+        labels.push((key, value));
+    }
+    Some(labels)
+}
+
+// ============================================================================
+//  CONTENT DIGEST
+// ============================================================================
+//
+//  Compression records the SHA-256 of the whole original (BOM included) as the
+//  CONTENT_DIGEST_LABEL label, in lowercase hex. The whole input is only known at the end, so
+//  the label is written as zeros and patched with `ArchiveHeader::patch_label` like the
+//  original size. `cast -d --skip-existing` compares it with an existing output.
+
+pub const CONTENT_DIGEST_LABEL: &str = "sha256";
+
+/// Lowercase hex of a digest, as stored in CONTENT_DIGEST_LABEL.
+pub fn digest_hex(digest: &[u8; 32]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// ============================================================================
+//  ROW ORDER CHECKSUM
+// ============================================================================
+//
+//  Chunk CRCs check each chunk on its own: chunks written in the wrong order, or one left
+//  out, still pass. With --order-checksum, compression folds every row into a polynomial hash
+//  (modulo 2^61 - 1) in archive order and records it with the row count as the
+//  ROW_ORDER_LABEL label, `<hash> <rows>` in 16-digit hex each, patched at the end like the
+//  content digest. A row is its length and first byte; rows end after each '\n' and at the end
+//  of every stored chunk, so a chunk split at MAX_SEGMENT_LEN gives two rows where the line
+//  was cut. The checksum of consecutive chunks is that of the first times BASE to the power
+//  of the second's rows plus that of the second: chunks restored in parallel are checked by
+//  combining their checksums in archive order. Full restores and -v recompute it.
+//  Chunks whose rows have the same lengths and first bytes trade places unnoticed; the CRCs
+//  and the content digest still cover their bytes. Older readers ignore the label.
+
+pub const ROW_ORDER_LABEL: &str = "row_order";
+const ROW_ORDER_MODULUS: u64 = (1 << 61) - 1;
+const ROW_ORDER_BASE: u64 = 0x1F35_A7BD_4C2E_9D1B % ROW_ORDER_MODULUS;
+
+fn mul_mod(a: u64, b: u64) -> u64 {
+    ((a as u128 * b as u128) % ROW_ORDER_MODULUS as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64) -> u64 {
+    let mut result = 1;
+    while exp > 0 {
+        if exp & 1 == 1 { result = mul_mod(result, base); }
+        base = mul_mod(base, base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Row order checksum of a run of chunks (see "ROW ORDER CHECKSUM"). Feed each chunk's restored
+/// bytes with `update` (in as many pieces as convenient) and close it with `end_chunk`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RowOrder {
+    pub hash: u64,
+    pub rows: u64,
+    // Length and first byte of the row still open at the end of the last update
+    open: Option<(u64, u8)>,
+}
+
+impl RowOrder {
+    /// Checksum of one chunk restored as `data`.
+    pub fn of_chunk(data: &[u8]) -> Self {
+        let mut order = Self::default();
+        order.update(data);
+        order.end_chunk();
+        order
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let (len, first) = self.open.take().unwrap_or((0, data[0]));
+            match memchr::memchr(b'\n', data) {
+                Some(end) => {
+                    self.push_row(len + end as u64 + 1, first);
+                    data = &data[end + 1..];
+                }
+                None => {
+                    self.open = Some((len + data.len() as u64, first));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Closes the row a chunk ends in without a line ending.
+    pub fn end_chunk(&mut self) {
+        if let Some((len, first)) = self.open.take() { self.push_row(len, first); }
+    }
+
+    fn push_row(&mut self, len: u64, first: u8) {
+        // Lengths of 2^56 bytes and more lose their top bits: the row still counts
+        let row = ((len << 8) | first as u64) % ROW_ORDER_MODULUS;
+        self.hash = (mul_mod(self.hash, ROW_ORDER_BASE) + row + 1) % ROW_ORDER_MODULUS;
+        self.rows += 1;
+    }
+
+    /// Appends the (ended) checksum of the chunks that follow.
+    pub fn append(&mut self, next: &RowOrder) {
+        self.end_chunk();
+        self.hash = (mul_mod(self.hash, pow_mod(ROW_ORDER_BASE, next.rows)) + next.hash) % ROW_ORDER_MODULUS;
+        self.rows += next.rows;
+    }
+
+    /// Value of ROW_ORDER_LABEL.
+    pub fn to_label(&self) -> String {
+        format!("{:016x} {:016x}", self.hash, self.rows)
+    }
+
+    /// Reads a ROW_ORDER_LABEL value. The placeholder of a run that never finished (and the
+    /// checksum of an empty input) reads as none.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (hash, rows) = value.split_once(' ')?;
+        if hash.len() != 16 || rows.len() != 16 { return None; }
+        let order = Self { hash: u64::from_str_radix(hash, 16).ok()?, rows: u64::from_str_radix(rows, 16).ok()?, open: None };
+        (order.rows > 0).then_some(order)
+    }
+}
+
+// ============================================================================
+//  COLUMN NAMES
+// ============================================================================
+//
+//  The field names of a delimited input's header row are kept as the COLUMNS_LABEL label
+//  (tab-separated). They are metadata only: the header line is still compressed and restored
+//  as data like any other line.
+
+pub const COLUMNS_LABEL: &str = "columns";
+// Input inspected for a header row, and data rows it is checked against
+pub const HEADER_SAMPLE_LEN: usize = 64 * 1024;
+const HEADER_SAMPLE_ROWS: usize = 100;
+const HEADER_MIN_ROWS: usize = 2;
+const HEADER_MAX_NAME_LEN: usize = 128;
+const HEADER_DELIMITERS: [char; 4] = [',', '\t', ';', '|'];
+
+/// Whether a delimited input starts with a header row (`--header yes|no|auto`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderRow { Yes, No, Auto }
+
+impl HeaderRow {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "yes" | "y" => Some(HeaderRow::Yes),
+            "no" | "n" => Some(HeaderRow::No),
+            "auto" => Some(HeaderRow::Auto),
+            _ => None,
+        }
+    }
+}
+
+fn split_fields(line: &str, delimiter: char) -> Vec<&str> {
+    line.split(delimiter).map(|f| f.trim().trim_matches('"').trim()).collect()
+}
+
+fn is_numeric_field(field: &str) -> bool {
+    !field.is_empty() && field.parse::<f64>().is_ok()
+}
+
+/// Column names from the first line of `head` (the start of the input). With `Auto` the line
+/// is only taken as a header when it clearly is one: at least two distinct, non-numeric names
+/// that split on the same delimiter into as many fields as every sampled data row, and at least
+/// one position where all those rows hold numbers. `Yes` takes the first line as is, split on
+/// the delimiter that gives the most fields.
+pub fn detect_column_names(head: &[u8], mode: HeaderRow) -> Option<Vec<String>> {
+    if mode == HeaderRow::No { return None; }
+    let text = std::str::from_utf8(head).unwrap_or_else(|e| std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or(""));
+    // Complete lines only: the last one may be cut by the sample
+    let complete = &text[..text.rfind('\n')? + 1];
+    let mut lines = complete.lines().map(|l| l.trim_end_matches('\r'));
+    let first = lines.next()?;
+    let rows: Vec<&str> = lines.filter(|l| !l.trim().is_empty()).take(HEADER_SAMPLE_ROWS).collect();
+
+    let valid_names = |names: &[&str]| {
+        names.len() >= 2 && names.iter().all(|n| !n.is_empty() && n.len() <= HEADER_MAX_NAME_LEN && !n.chars().any(|c| c.is_control()))
+    };
+    let names = HEADER_DELIMITERS.iter().map(|&d| (d, split_fields(first, d))).filter(|(d, names)| {
+        if !valid_names(names) { return false; }
+        if mode == HeaderRow::Yes { return true; }
+        let distinct: std::collections::HashSet<&&str> = names.iter().collect();
+        if distinct.len() != names.len() || names.iter().any(|n| is_numeric_field(n)) { return false; }
+        if rows.len() < HEADER_MIN_ROWS { return false; }
+        let split_rows: Vec<Vec<&str>> = rows.iter().map(|r| split_fields(r, *d)).collect();
+        if split_rows.iter().any(|r| r.len() != names.len()) { return false; }
+        (0..names.len()).any(|i| split_rows.iter().all(|r| is_numeric_field(r[i])))
+    }).max_by_key(|(_, names)| names.len())?.1;
+    Some(names.into_iter().map(str::to_string).collect())
+}
+
+// ============================================================================
+//  CHUNKS
+// ============================================================================
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkHeader {
+    pub crc: u32,
+    pub len_reg: u32,
+    pub len_ids: u32,
+    pub len_vars: u32,
+    pub id_flag: u8,
+}
+
+impl ChunkHeader {
+    pub fn to_bytes(&self) -> [u8; CHUNK_HEADER_SIZE] {
+        let mut b = [0u8; CHUNK_HEADER_SIZE];
+        b[0..4].copy_from_slice(&self.crc.to_le_bytes());
+        b[4..8].copy_from_slice(&self.len_reg.to_le_bytes());
+        b[8..12].copy_from_slice(&self.len_ids.to_le_bytes());
+        b[12..16].copy_from_slice(&self.len_vars.to_le_bytes());
+        b[16] = self.id_flag;
+        b
+    }
+
+    pub fn from_bytes(b: &[u8; CHUNK_HEADER_SIZE]) -> Self {
+        Self {
+            crc: u32::from_le_bytes(b[0..4].try_into().unwrap()),
+            len_reg: u32::from_le_bytes(b[4..8].try_into().unwrap()),
+            len_ids: u32::from_le_bytes(b[8..12].try_into().unwrap()),
+            len_vars: u32::from_le_bytes(b[12..16].try_into().unwrap()),
+            id_flag: b[16],
+        }
+    }
+
+    /// Byte ranges of the fields written by `to_bytes` (for `--dump-format`).
+    pub fn layout() -> [(&'static str, Range<usize>); 5] {
+        [("crc", 0..4), ("len_reg", 4..8), ("len_ids", 8..12), ("len_vars", 12..16), ("id_flag", 16..CHUNK_HEADER_SIZE)]
+    }
+
+    pub fn body_len(&self) -> usize {
+        self.len_reg as usize + self.len_ids as usize + self.len_vars as usize
+    }
+}
+
+pub struct Chunk {
+    pub header: ChunkHeader,
+    pub body: Vec<u8>,
+    // Bytes of the parser record at the start of the vars (0 when the chunk has none)
+    parser_len: usize,
+}
+
+/// Whether a chunk with `id_flag` in an archive of `version` opens its vars with a parser record.
+pub fn has_parser_record(version: u8, id_flag: u8) -> bool {
+    version != LEGACY_VERSION && !is_passthrough(id_flag) && id_flag != ID_FLAG_DUPLICATE && id_flag != ID_FLAG_MAC_TRAILER
+}
+
+// Chunks are extended to the end of the line they stop in, by at most this much (and a
+// quarter of the chunk size); lines longer than that are split at the nominal size
+const LINE_ALIGN_MAX_OVERSHOOT: usize = 1024 * 1024;
+
+/// Extra bytes to read past `chunk_size` so a chunk can end on a line boundary.
+pub fn line_align_overshoot(chunk_size: usize) -> usize {
+    (chunk_size / 4).clamp(1, LINE_ALIGN_MAX_OVERSHOOT)
+}
+
+/// Length of the next chunk in `buf` (read up to `chunk_size` + overshoot): through the
+/// first newline at or after the nominal size, the whole tail at end of input, or exactly
+/// `chunk_size` when no newline shows up (binary data). The rest starts the next chunk.
+pub fn line_aligned_len(buf: &[u8], chunk_size: usize, at_eof: bool) -> usize {
+    if buf.len() <= chunk_size { return buf.len(); }
+    let from = chunk_size.saturating_sub(1);
+    match memchr::memchr(b'\n', &buf[from..]) {
+        Some(p) => from + p + 1,
+        None if at_eof => buf.len(),
+        None => chunk_size,
+    }
+}
+
+/// Cuts an input stream into the chunks compression works on: `chunk_size` bytes extended to
+/// the end of their line (see `line_aligned_len`), or one solid chunk when `chunk_size` is
+/// `None` (of up to `solid_len` bytes, the input size).
+pub struct InputChunker<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    chunk_size: Option<usize>,
+    // Bytes read into the buffer, of which the first `consumed` were returned as the last chunk
+    filled: usize,
+    consumed: usize,
+    done: bool,
+}
+
+impl<R: Read> InputChunker<R> {
+    pub fn new(reader: R, chunk_size: Option<usize>, solid_len: usize) -> Self {
+        let buffer_len = match chunk_size {
+            Some(size) => size + line_align_overshoot(size),
+            None => solid_len,
+        };
+        Self { reader, buffer: vec![0u8; buffer_len], chunk_size, filled: 0, consumed: 0, done: false }
+    }
+
+    /// The next chunk, or `None` at the end of the input.
+    pub fn next_chunk(&mut self) -> io::Result<Option<&[u8]>> {
+        if self.done { return Ok(None); }
+        // Bytes read past the previous chunk start the next one
+        self.buffer.copy_within(self.consumed..self.filled, 0);
+        self.filled -= self.consumed;
+        self.consumed = 0;
+
+        let mut at_eof = false;
+        while self.filled < self.buffer.len() {
+            let n = self.reader.read(&mut self.buffer[self.filled..])?;
+            if n == 0 { at_eof = true; break; }
+            self.filled += n;
+        }
+        if self.filled == 0 { return Ok(None); }
+
+        self.consumed = match self.chunk_size {
+            Some(size) => line_aligned_len(&self.buffer[..self.filled], size, at_eof),
+            None => {
+                self.done = true;
+                self.filled
+            },
+        };
+        Ok(Some(&self.buffer[..self.consumed]))
+    }
+}
+
+// How often a followed input is checked for new data once its end is reached
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Reads a file that is still being appended to (`tail -f`): at the end of the data it waits
+/// for more instead of reporting end of input, until nothing was added for `idle_timeout` or
+/// `stop` is cancelled. Behind an `InputChunker`, an incomplete last line stays in the buffer
+/// until it is completed or the input ends.
+pub struct FollowReader<R: Read> {
+    inner: R,
+    idle_timeout: Duration,
+    stop: CancellationToken,
+    last_data: Instant,
+    watch: Option<InputWatch>,
+    read: u64,
+}
+
+impl<R: Read> FollowReader<R> {
+    pub fn new(inner: R, idle_timeout: Duration, stop: CancellationToken) -> Self {
+        Self { inner, idle_timeout, stop, last_data: Instant::now(), watch: None, read: 0 }
+    }
+
+    /// Also ends the input as soon as `watch` sees the file truncated while waiting for more,
+    /// instead of after the idle timeout. The caller's own check then reports the change.
+    pub fn with_watch(mut self, watch: InputWatch) -> Self {
+        self.watch = Some(watch);
+        self
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.inner.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.last_data = Instant::now();
+                self.read += n as u64;
+                return Ok(n);
+            }
+            if self.stop.is_cancelled() || self.last_data.elapsed() >= self.idle_timeout { return Ok(0); }
+            if let Some(watch) = self.watch.as_mut() {
+                if watch.check(self.read)?.is_some() { return Ok(0); }
+            }
+            thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Size and modification time of an input file, checked while it is compressed to notice it
+/// being truncated or rewritten under the run (e.g. logrotate with `copytruncate`). Reading
+/// past the new end only returns an early end of input (or zeros on some filesystems), so the
+/// archive would silently hold a torn view of the file. Growth is expected (`--follow`, live
+/// logs) and not reported. Watches the open file, so a file renamed away is still the one
+/// checked; pipes and devices, which have no size, are not checked.
+#[derive(Debug)]
+pub struct InputWatch {
+    file: File,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl InputWatch {
+    pub fn new(file: File) -> io::Result<Self> {
+        let meta = file.metadata()?;
+        Ok(Self { len: meta.len(), modified: meta.modified().ok(), file })
+    }
+
+    /// What changed since the last check, `None` if the file still holds at least the `read`
+    /// bytes already read and did not shrink or go back in time.
+    pub fn check(&mut self, read: u64) -> io::Result<Option<String>> {
+        let meta = self.file.metadata()?;
+        if !meta.is_file() { return Ok(None); }
+        let (len, modified) = (meta.len(), meta.modified().ok());
+        let change = if len < self.len {
+            Some(format!("it shrank from {} to {} bytes", self.len, len))
+        } else if len < read {
+            Some(format!("it holds {} bytes, {} were read", len, read))
+        } else if matches!((self.modified, modified), (Some(before), Some(now)) if now < before) {
+            Some("its modification time went back".to_string())
+        } else {
+            None
+        };
+        self.len = len;
+        self.modified = modified;
+        Ok(change)
+    }
+}
+
+/// CRC32 stored in the header of a chunk holding `data` (with `ChecksumKind::Crc32`).
+pub fn chunk_crc(data: &[u8]) -> u32 {
+    let mut h = Hasher::new();
+    h.update(data);
+    h.finalize()
+}
+
+/// A compressed chunk ready to be written: serialized header, reg, ids and vars, in order.
+/// An input too large for the u32 segment lengths is encoded as several such chunks.
+pub struct EncodedChunk {
+    pub parts: Vec<Vec<u8>>,
+    /// Header of each chunk in `parts` with the number of input bytes it covers
+    pub chunks: Vec<(usize, ChunkHeader)>,
+    /// Parsing strategy (or passthrough reason) reported by the compressor
+    pub strategy: String,
+    /// This chunk's share of the run totals
+    pub stats: CompressionStats,
+}
+
+/// How much of the input went through the columnar transform and how much was stored as is
+/// (passthrough), summed over the chunks of a run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompressionStats {
+    pub columnar_chunks: u64,
+    pub columnar_rows: u64,
+    pub columnar_bytes: u64,
+    pub passthrough_chunks: u64,
+    pub passthrough_rows: u64,
+    pub passthrough_bytes: u64,
+    /// Values of columnar chunks left in their templates for exceeding the variable length cap
+    pub long_values: u64,
+    /// Raw registry bytes saved by front coding the skeletons (before the backend)
+    pub registry_saved: u64,
+    /// Columnar chunks stored split (id stream compressed on its own), and those of them whose
+    /// id stream went through move-to-front or delta first
+    pub split_chunks: u64,
+    pub mtf_id_chunks: u64,
+    pub delta_id_chunks: u64,
+    /// Input chunks stored as references to an identical earlier chunk (--dedup-chunks)
+    pub duplicate_chunks: u64,
+    pub duplicate_rows: u64,
+    pub duplicate_bytes: u64,
+    /// Archive bytes the references saved over storing the chunks again
+    pub dedup_saved: u64,
+}
+
+impl CompressionStats {
+    fn for_chunk(data: &[u8], id_flag: u8) -> Self {
+        // Rows are lines, the last one counted even without a trailing newline
+        let rows = memchr::memchr_iter(b'\n', data).count() as u64 + u64::from(data.last().is_some_and(|&b| b != b'\n'));
+        let bytes = data.len() as u64;
+        if id_flag == ID_FLAG_DUPLICATE {
+            Self { duplicate_chunks: 1, duplicate_rows: rows, duplicate_bytes: bytes, ..Self::default() }
+        } else if is_passthrough(id_flag) {
+            Self { passthrough_chunks: 1, passthrough_rows: rows, passthrough_bytes: bytes, ..Self::default() }
+        } else {
+            Self { columnar_chunks: 1, columnar_rows: rows, columnar_bytes: bytes, ..Self::default() }
+        }
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        self.columnar_chunks += other.columnar_chunks;
+        self.columnar_rows += other.columnar_rows;
+        self.columnar_bytes += other.columnar_bytes;
+        self.passthrough_chunks += other.passthrough_chunks;
+        self.passthrough_rows += other.passthrough_rows;
+        self.passthrough_bytes += other.passthrough_bytes;
+        self.long_values += other.long_values;
+        self.registry_saved += other.registry_saved;
+        self.split_chunks += other.split_chunks;
+        self.mtf_id_chunks += other.mtf_id_chunks;
+        self.delta_id_chunks += other.delta_id_chunks;
+        self.duplicate_chunks += other.duplicate_chunks;
+        self.duplicate_rows += other.duplicate_rows;
+        self.duplicate_bytes += other.duplicate_bytes;
+        self.dedup_saved += other.dedup_saved;
+    }
+
+    pub fn chunks(&self) -> u64 { self.columnar_chunks + self.passthrough_chunks + self.duplicate_chunks }
+
+    pub fn rows(&self) -> u64 { self.columnar_rows + self.passthrough_rows + self.duplicate_rows }
+
+    pub fn bytes(&self) -> u64 { self.columnar_bytes + self.passthrough_bytes + self.duplicate_bytes }
+
+    /// Share of the input bytes (0-100) that went through the columnar transform.
+    pub fn columnar_percent(&self) -> f64 {
+        if self.bytes() == 0 { 0.0 } else { self.columnar_bytes as f64 * 100.0 / self.bytes() as f64 }
+    }
+}
+
+/// Compresses one chunk of input with a fresh compressor (chunks are independent).
+/// The CRC is stored as 0 when checksums are disabled; `column_codecs` selects the column
+/// streams layout, `front_coding` front codes the registry (see
+/// `CASTCompressor::with_front_coded_registry`), `column_dicts` allows column dictionaries (see
+/// `CASTCompressor::with_column_dictionaries`) and `max_var_len` caps captured values (see
+/// `CASTCompressor::with_max_var_len`). When a length would not fit its u32 field the input is split in halves
+/// (recursively) instead of being written with a truncated length.
+pub fn encode_chunk<C: NativeCompressor>(backend: C, data: &[u8], checksum: ChecksumKind, column_codecs: bool, front_coding: bool, column_dicts: bool, max_var_len: usize) -> Result<EncodedChunk, CastError> {
+    let mut encoded = EncodedChunk { parts: Vec::new(), chunks: Vec::new(), strategy: String::new(), stats: CompressionStats::default() };
+    encode_split(&backend, data, checksum, column_codecs, front_coding, column_dicts, max_var_len, &mut encoded)?;
+    Ok(encoded)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encode_split<C: NativeCompressor>(backend: &C, data: &[u8], checksum: ChecksumKind, column_codecs: bool, front_coding: bool, column_dicts: bool, max_var_len: usize, out: &mut EncodedChunk) -> Result<(), CastError> {
+    let SingleChunk { header, parts, strategy, stats } = match encode_single(backend, data, checksum, column_codecs, front_coding, column_dicts, max_var_len) {
+        Err(CastError::LengthOverflow { .. }) if data.len() > 1 => {
+            let half = data.len() / 2;
+            let cut = line_aligned_len(&data[..half + line_align_overshoot(half)], half, false);
+            let cut = if cut == 0 || cut >= data.len() { half } else { cut };
+            encode_split(backend, &data[..cut], checksum, column_codecs, front_coding, column_dicts, max_var_len, out)?;
+            return encode_split(backend, &data[cut..], checksum, column_codecs, front_coding, column_dicts, max_var_len, out);
+        },
+        result => result?,
+    };
+    out.stats.add(&stats);
+    if out.chunks.is_empty() {
+        out.strategy = strategy;
+    } else if out.strategy != strategy {
+        out.strategy = "Mixed".to_string();
+    }
+    out.chunks.push((data.len(), header));
+    out.parts.extend(parts);
+    Ok(())
+}
+
+/// One chunk as written: header, the segments to write (header, reg, ids, vars) and its stats.
+struct SingleChunk {
+    header: ChunkHeader,
+    parts: Vec<Vec<u8>>,
+    strategy: String,
+    stats: CompressionStats,
+}
+
+fn encode_single<C: NativeCompressor>(backend: &C, data: &[u8], checksum: ChecksumKind, column_codecs: bool, front_coding: bool, column_dicts: bool, max_var_len: usize) -> Result<SingleChunk, CastError> {
+    let crc = match checksum {
+        ChecksumKind::Crc32 => chunk_crc(data),
+        ChecksumKind::None => 0,
+    };
+
+    let mut compressor = CASTCompressor::new(backend).with_column_codecs(column_codecs).with_front_coded_registry(front_coding).with_column_dictionaries(column_dicts).with_max_var_len(max_var_len);
+    let (c_reg, c_ids, mut c_vars, id_flag, strategy) = compressor.compress(data)?;
+    if has_parser_record(FORMAT_VERSION, id_flag) {
+        c_vars.splice(0..0, compressor.parser_config().to_record());
+    }
+    let header = ChunkHeader {
+        crc,
+        len_reg: u32_len(c_reg.len(), "compressed registry")?,
+        len_ids: u32_len(c_ids.len(), "compressed id stream")?,
+        len_vars: u32_len(c_vars.len(), "compressed vars")?,
+        id_flag,
+    };
+    let mut stats = CompressionStats::for_chunk(data, id_flag);
+    if !is_passthrough(id_flag) {
+        stats.long_values = compressor.long_values();
+        stats.registry_saved = compressor.registry_saved();
+        stats.split_chunks = u64::from(!c_reg.is_empty() && !c_ids.is_empty());
+        match compressor.ids_transform() {
+            IdsTransform::MoveToFront => stats.mtf_id_chunks = 1,
+            IdsTransform::Delta => stats.delta_id_chunks = 1,
+            IdsTransform::None => {},
+        }
+    }
+    Ok(SingleChunk { header, parts: vec![header.to_bytes().to_vec(), c_reg, c_ids, c_vars], strategy, stats })
+}
+
+// `encode_chunk` only splits a chunk when a field of its block would pass 4GB, which takes
+// far more input than twice this (the registry, the largest field, stays under 11 bytes per
+// input byte), so no piece is smaller
+const SPLIT_MIN_PIECE: usize = 128 * 1024 * 1024;
+
+/// Upper bound of the archive written for `input_len` bytes of input: `header` as it will be
+/// written (labels, authentication trailer), the input cut by `InputChunker` with
+/// `chunk_size` (`None` = solid) and each chunk compressed with `backend`. `None` when the
+/// backend has no size bound (see `NativeCompressor::max_compressed_len`).
+///
+/// The bound holds because chunks whose columnar form would exceed the backend's bound are
+/// stored as passthrough. Each extra chunk is assumed to cost the backend no more than its
+/// bound for a single byte (true of xz: one more block and LZMA2 chunk header). Duplicate
+/// chunks are always smaller than the chunk they replace.
+pub fn max_compressed_size<C: NativeCompressor>(input_len: usize, chunk_size: Option<usize>, header: &ArchiveHeader, backend: &C) -> Option<usize> {
+    // Every chunk but the last is at least `chunk_size` long (see `line_aligned_len`)
+    let chunks = match chunk_size {
+        Some(size) => input_len.div_ceil(size.max(1)).max(1),
+        None => 1,
+    };
+    let chunks = chunks.saturating_add(input_len / SPLIT_MIN_PIECE);
+    let data = backend.max_compressed_len(input_len)?
+        .saturating_add(backend.max_compressed_len(1)?.saturating_mul(chunks - 1))
+        .saturating_add(CHUNK_HEADER_SIZE.saturating_mul(chunks));
+    let trailer = if header.authenticated() { MacTrailer::archive_len(chunks) } else { 0 };
+    Some(header.encoded_len().saturating_add(data).saturating_add(trailer))
+}
+
+/// Input chunks already written, for --dedup-chunks: a later identical chunk is stored as a
+/// duplicate chunk referring to the first copy. Candidates are looked up by length, CRC32 and
+/// a 64-bit SipHash; the caller confirms a match byte for byte before using it.
+#[derive(Default)]
+pub struct ChunkDedup {
+    seen: HashMap<DedupKey, DedupTarget>,
+}
+
+/// Length, CRC32 and SipHash of an input chunk.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DedupKey(usize, u32, u64);
+
+/// Where the first copy of an input chunk was written.
+#[derive(Clone, Copy, Debug)]
+pub struct DedupTarget {
+    /// Archive chunk number (1-based)
+    pub chunk: usize,
+    /// Offset of the chunk in the input
+    pub data_offset: u64,
+    /// Size of the chunk in the archive, header included
+    pub compressed_len: u64,
+}
+
+impl ChunkDedup {
+    pub fn key(data: &[u8]) -> DedupKey {
+        let mut h = DefaultHasher::new();
+        data.hash(&mut h);
+        DedupKey(data.len(), chunk_crc(data), h.finish())
+    }
+
+    /// The first copy of an input chunk with this key, if any.
+    pub fn get(&self, key: &DedupKey) -> Option<DedupTarget> {
+        self.seen.get(key).copied()
+    }
+
+    /// Records where an input chunk was written (an earlier copy is kept).
+    pub fn insert(&mut self, key: DedupKey, target: DedupTarget) {
+        self.seen.entry(key).or_insert(target);
+    }
+}
+
+/// The duplicate chunk stored for an input chunk identical to the one written as `target`.
+pub fn encode_duplicate(data: &[u8], checksum: ChecksumKind, target: &DedupTarget) -> EncodedChunk {
+    let crc = match checksum {
+        ChecksumKind::Crc32 => chunk_crc(data),
+        ChecksumKind::None => 0,
+    };
+    let vars = (target.chunk as u64).to_le_bytes().to_vec();
+    let header = ChunkHeader { crc, len_reg: 0, len_ids: 0, len_vars: vars.len() as u32, id_flag: ID_FLAG_DUPLICATE };
+    let mut stats = CompressionStats::for_chunk(data, ID_FLAG_DUPLICATE);
+    stats.dedup_saved = target.compressed_len.saturating_sub((CHUNK_HEADER_SIZE + vars.len()) as u64);
+    EncodedChunk {
+        parts: vec![header.to_bytes().to_vec(), Vec::new(), Vec::new(), vars],
+        chunks: vec![(data.len(), header)],
+        strategy: "Duplicate".to_string(),
+        stats,
+    }
+}
+
+impl Chunk {
+    /// A chunk of an archive of `version` as read: `body` holds reg, ids and vars.
+    pub fn new(header: ChunkHeader, body: Vec<u8>, version: u8) -> Self {
+        let vars = (header.len_reg as usize + header.len_ids as usize).min(body.len());
+        // A record overrunning the vars leaves nothing to decode, so the chunk fails as corrupt
+        let parser_len = if has_parser_record(version, header.id_flag) {
+            ParserConfig::record_len(&body[vars..]).unwrap_or(body.len() - vars)
+        } else { 0 };
+        Self { header, body, parser_len }
+    }
+
+    pub fn reg(&self) -> &[u8] { &self.body[..self.header.len_reg as usize] }
+
+    pub fn ids(&self) -> &[u8] {
+        let s = self.header.len_reg as usize;
+        &self.body[s..s + self.header.len_ids as usize]
+    }
+
+    /// The vars as the decoder reads them (after the parser record).
+    pub fn vars(&self) -> &[u8] {
+        &self.body[self.header.len_reg as usize + self.header.len_ids as usize + self.parser_len..]
+    }
+
+    /// The parser record as stored (empty when the chunk has none).
+    pub fn parser_record(&self) -> &[u8] {
+        let start = self.header.len_reg as usize + self.header.len_ids as usize;
+        &self.body[start..start + self.parser_len]
+    }
+
+    /// Configuration the chunk was tokenized with, `None` for chunks without templates and
+    /// for version 1 archives.
+    pub fn parser_config(&self) -> Option<ParserConfig> {
+        ParserConfig::from_record(self.parser_record())
+    }
+
+    /// Expected CRC of the reconstructed chunk, or `None` when the archive stores no checksums.
+    pub fn expected_crc(&self, archive: &ArchiveHeader) -> Option<u32> {
+        match archive.checksum() {
+            ChecksumKind::Crc32 => Some(self.header.crc),
+            ChecksumKind::None => None,
+        }
+    }
+
+    /// Number (1-based) of the chunk a duplicate chunk refers to, `None` for other chunks.
+    pub fn duplicate_of(&self) -> Option<usize> {
+        if self.header.id_flag != ID_FLAG_DUPLICATE { return None; }
+        // A malformed reference names chunk 0, which `ChunkReader::resolve` rejects
+        Some(<[u8; 8]>::try_from(self.vars()).map_or(0, |b| u64::from_le_bytes(b) as usize))
+    }
+}
+
+// Chunk bodies are allocated up to this size before any of their bytes are read
+const BODY_PREALLOC_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Reads the chunk header at the current position and its body, or `None` at the end of the
+/// data. `chunk` (1-based) only labels errors; `version` is the archive's.
+fn read_chunk<R: Read>(reader: &mut R, chunk: usize, version: u8) -> Result<Option<Chunk>, CastError> {
+    let mut raw = [0u8; CHUNK_HEADER_SIZE];
+    match reader.read_exact(&mut raw) {
+        Ok(_) => {},
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let header = ChunkHeader::from_bytes(&raw);
+    // Grown as the bytes arrive: a corrupt header declaring gigabytes past the end of the
+    // file fails on the short read instead of allocating its declared size up front
+    let len = header.body_len() as u64;
+    let mut body = Vec::with_capacity(header.body_len().min(BODY_PREALLOC_LIMIT));
+    reader.take(len).read_to_end(&mut body)?;
+    if body.len() as u64 != len { return Err(CastError::LengthOverflow { chunk, detail: "Truncated file body".to_string() }); }
+    Ok(Some(Chunk::new(header, body, version)))
+}
+
+/// Sequential reader over the chunks of an archive (any supported version). The
+/// authentication trailer ends the chunks: it is kept aside (see `mac_trailer`).
+pub struct ChunkReader<R: BufRead> {
+    reader: R,
+    archive: ArchiveHeader,
+    chunks_read: usize,
+    // Archive offset of every chunk read so far (to resolve duplicate chunks) and of the next
+    offsets: Vec<u64>,
+    position: u64,
+    trailer: Option<Chunk>,
+}
+
+impl<R: BufRead> ChunkReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, CastError> {
+        let archive = ArchiveHeader::read_from(&mut reader)?;
+        let position = archive.encoded_len() as u64;
+        Ok(Self { reader, archive, chunks_read: 0, offsets: Vec::new(), position, trailer: None })
+    }
+
+    pub fn archive_header(&self) -> &ArchiveHeader { &self.archive }
+
+    /// The authentication trailer, once the chunks were read to their end (`None` before, and
+    /// for archives without one).
+    pub fn mac_trailer(&self) -> Option<&Chunk> { self.trailer.as_ref() }
+
+    /// Keeps `chunk` aside if it is the authentication trailer, which must end the archive;
+    /// returns it otherwise.
+    fn take_trailer(&mut self, chunk: Option<Chunk>) -> Result<Option<Chunk>, CastError> {
+        match chunk {
+            Some(c) if c.header.id_flag == ID_FLAG_MAC_TRAILER => {
+                if !self.reader.fill_buf()?.is_empty() {
+                    return Err(CastError::CorruptHeader { chunk: self.chunks_read + 2, detail: "Data after the authentication trailer".to_string() });
+                }
+                self.trailer = Some(c);
+                Ok(None)
+            },
+            other => Ok(other),
+        }
+    }
+
+    /// Reads the next chunk as stored: a duplicate chunk is returned as is (see `resolve`).
+    pub fn next_chunk(&mut self) -> Result<Option<Chunk>, CastError> {
+        if self.trailer.is_some() { return Ok(None); }
+        let chunk = read_chunk(&mut self.reader, self.chunks_read + 1, self.archive.version)?;
+        let Some(chunk) = self.take_trailer(chunk)? else { return Ok(None) };
+        self.chunks_read += 1;
+        self.offsets.push(self.position);
+        self.position += (CHUNK_HEADER_SIZE + chunk.body.len()) as u64;
+        Ok(Some(chunk))
+    }
+    /// Reads the next chunk header and skips its body without buffering it.
+    pub fn skip_chunk(&mut self) -> Result<Option<ChunkHeader>, CastError> {
+        Ok(self.skip_chunk_parsed()?.map(|(header, _)| header))
+    }
+
+    /// `skip_chunk` that also reads the parser record on its way (see `Chunk::parser_config`).
+    pub fn skip_chunk_parsed(&mut self) -> Result<Option<(ChunkHeader, Option<ParserConfig>)>, CastError> {
+        if self.trailer.is_some() { return Ok(None); }
+        let mut raw = [0u8; CHUNK_HEADER_SIZE];
+        match self.reader.read_exact(&mut raw) {
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let header = ChunkHeader::from_bytes(&raw);
+        if header.id_flag == ID_FLAG_MAC_TRAILER {
+            // Small: read in full and kept like in `next_chunk`
+            let chunk = read_chunk(&mut (&raw[..]).chain(&mut self.reader), self.chunks_read + 1, self.archive.version)?;
+            self.take_trailer(chunk)?;
+            return Ok(None);
+        }
+        self.chunks_read += 1;
+        let chunk = self.chunks_read;
+        let len = header.body_len() as u64;
+        let mut body = (&mut self.reader).take(len);
+        let mut skipped = io::copy(&mut (&mut body).take(header.len_reg as u64 + header.len_ids as u64), &mut io::sink())?;
+        let mut parser = None;
+        if has_parser_record(self.archive.version, header.id_flag) {
+            let mut record = Vec::new();
+            skipped += (&mut body).take(1).read_to_end(&mut record)? as u64;
+            if let Some(&fields) = record.first() {
+                skipped += (&mut body).take(fields as u64).read_to_end(&mut record)? as u64;
+            }
+            parser = ParserConfig::from_record(&record);
+        }
+        skipped += io::copy(&mut body, &mut io::sink())?;
+        if skipped != len { return Err(CastError::LengthOverflow { chunk, detail: "Truncated file body".to_string() }); }
+        self.offsets.push(self.position);
+        self.position += CHUNK_HEADER_SIZE as u64 + len;
+        Ok(Some((header, parser)))
+    }
+}
+
+impl<R: BufRead + Seek> ChunkReader<R> {
+    /// The chunk a duplicate chunk refers to, read again from its offset; any other chunk is
+    /// returned unchanged. `chunk` must be the one `next_chunk` just returned.
+    pub fn resolve(&mut self, chunk: Chunk) -> Result<Chunk, CastError> {
+        let Some(target) = chunk.duplicate_of() else { return Ok(chunk) };
+        let current = self.chunks_read;
+        let corrupt = |detail: String| CastError::CorruptHeader { chunk: current, detail };
+        let offset = match target.checked_sub(1).and_then(|i| self.offsets.get(i)) {
+            Some(&offset) if target < current => offset,
+            _ => return Err(corrupt(format!("Duplicate of chunk {}, which does not precede it", target))),
+        };
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let original = read_chunk(&mut self.reader, target, self.archive.version);
+        self.reader.seek(SeekFrom::Start(self.position))?;
+        match original? {
+            Some(original) if original.header.id_flag != ID_FLAG_DUPLICATE && original.header.crc == chunk.header.crc => Ok(original),
+            _ => Err(corrupt(format!("Duplicate of chunk {}, which does not match it", target))),
+        }
+    }
+
+    /// `next_chunk` with duplicate chunks resolved to the chunk they refer to.
+    pub fn next_resolved(&mut self) -> Result<Option<Chunk>, CastError> {
+        match self.next_chunk()? {
+            Some(chunk) => self.resolve(chunk).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// ============================================================================
+//  WRITE PIPELINE
+// ============================================================================
+//
+//  Compression hands finished chunks to a writer thread, so writing overlaps the encoding of
+//  the next chunk. A panic while encoding (a bug in a backend or in the transform) is turned
+//  into an error by `catch_panics`; dropping the pipeline then waits for the writer, so the
+//  run can clean up its output with no thread left behind.
+
+/// Finished chunks queued for the writer thread. One chunk may wait in the queue while
+/// another is being written, so memory grows by at most one extra chunk buffer.
+const WRITE_QUEUE_CHUNKS: usize = 1;
+
+/// Writer thread fed with the parts of finished chunks, in order (see `spawn`).
+pub struct WritePipeline {
+    tx: Option<SyncSender<Vec<Vec<u8>>>>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl WritePipeline {
+    /// Starts the writer thread on `out`.
+    pub fn spawn<W: Write + Send + 'static>(mut out: W) -> Self {
+        let (tx, rx) = sync_channel::<Vec<Vec<u8>>>(WRITE_QUEUE_CHUNKS);
+        let handle = thread::spawn(move || -> io::Result<()> {
+            for parts in rx {
+                for part in &parts { out.write_all(part)?; }
+            }
+            out.flush()
+        });
+        Self { tx: Some(tx), handle: Some(handle) }
+    }
+
+    /// Queues a chunk (blocks while the queue is full). If the writer thread has stopped,
+    /// its I/O error is returned so compression can abort immediately.
+    pub fn send(&mut self, parts: Vec<Vec<u8>>) -> io::Result<()> {
+        let sent = self.tx.as_ref().map(|tx| tx.send(parts).is_ok()).unwrap_or(false);
+        if sent { Ok(()) } else { self.finish() }
+    }
+
+    /// Waits for the writer to write and flush everything queued, and returns its result.
+    pub fn finish(&mut self) -> io::Result<()> {
+        drop(self.tx.take());
+        match self.handle.take() {
+            Some(h) => h.join().unwrap_or_else(|p| Err(io::Error::other(CastError::from_panic(p)))),
+            None => Err(io::Error::other("Writer thread already stopped")),
+        }
+    }
+}
+
+// An aborted compression still waits for the writer, so no thread outlives the run and
+// the output file is closed before the caller removes it
+impl Drop for WritePipeline {
+    fn drop(&mut self) {
+        if self.handle.is_some() { let _ = self.finish(); }
+    }
+}
+
+/// Runs `f`, turning a panic (a bug in a backend or in the transform) into an error so the
+/// caller can stop its workers and clean up instead of aborting mid-write.
+pub fn catch_panics<T>(f: impl FnOnce() -> Result<T, CastError>) -> Result<T, CastError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|p| Err(CastError::from_panic(p)))
+}
+
+// ============================================================================
+//  BLOCK SETTINGS
+// ============================================================================
+//
+//  Chunks of one archive need not share their settings: each picks its own layout and id
+//  mode, the backend clamps the dictionary to the chunk's input, and merged or edited archives
+//  hold chunks of several runs. What a chunk records of how it was written is read from its
+//  header, its stream headers and its parser record, without decoding anything: the layout,
+//  the id flag, the backend (xz with the largest LZMA2 dictionary of its streams and their
+//  integrity check, or stored as is), the codecs of column streams and the parse mode.
+//  `info --detailed` and `-d --verbose` list them per chunk, with a histogram of the mix.
+//  Not recorded: the xz preset and thread count. Chunks --max-time encoded fast, and chunks
+//  re-encoded natively after a 7-Zip failure, hold ordinary xz streams like the others; only
+//  the run's --manifest names the backend of each chunk.
+
+/// What a stored chunk records of how it was written (see "BLOCK SETTINGS").
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockSettings {
+    /// Layout as `cast::stored_layout` names it ("Unified", "Split", "Duplicate", ...)
+    pub layout: &'static str,
+    pub id_flag: u8,
+    /// "xz", "stored" (streams written by a backend that keeps the bytes as they are) or
+    /// "none" (no stream, as in duplicate chunks)
+    pub backend: &'static str,
+    /// Largest LZMA2 dictionary among the xz streams (the backend shrinks it to fit small
+    /// streams), and the integrity check of the first one
+    pub dict_size: Option<u32>,
+    pub check: Option<&'static str>,
+    /// Column streams only: the codecs of the segments, each once, in order of first use
+    pub column_codecs: Vec<&'static str>,
+    pub parser: Option<ParserConfig>,
+    /// Whether the chunk's format has a parser record, so that a missing one means a damaged chunk
+    pub parser_recorded: bool,
+    pub duplicate_of: Option<usize>,
+}
+
+// Dictionary sizes are 2 or 3 times a power of two
+fn dict_label(size: u32) -> String {
+    if size.is_multiple_of(1024 * 1024) { format!("{}MB", size / (1024 * 1024)) }
+    else if size.is_multiple_of(1024) { format!("{}KB", size / 1024) }
+    else { format!("{} bytes", size) }
+}
+
+impl BlockSettings {
+    /// The settings of `chunk`, from an archive of `version`.
+    pub fn of_chunk(chunk: &Chunk, version: u8) -> Self {
+        let id_flag = chunk.header.id_flag;
+        let layout = stored_layout(chunk.reg(), chunk.ids(), id_flag);
+        let mut column_codecs: Vec<&'static str> = Vec::new();
+        let vars = if layout == "Column streams" {
+            let (codecs, backend) = column_stream_codecs(chunk.vars()).unwrap_or((vec!["unknown"], &[]));
+            for codec in codecs {
+                if !column_codecs.contains(&codec) { column_codecs.push(codec); }
+            }
+            backend
+        } else if id_flag == ID_FLAG_DUPLICATE {
+            &[]
+        } else {
+            chunk.vars()
+        };
+        let stream = [chunk.reg(), chunk.ids(), vars].into_iter().find(|s| !s.is_empty());
+        let backend = match stream {
+            None => "none",
+            Some(s) if xz_check_name(s).is_some() => "xz",
+            Some(_) => "stored",
+        };
+        Self {
+            layout,
+            id_flag,
+            backend,
+            dict_size: [chunk.reg(), chunk.ids(), vars].into_iter().filter_map(xz_dict_size).max(),
+            check: stream.and_then(xz_check_name),
+            column_codecs,
+            parser: chunk.parser_config(),
+            parser_recorded: has_parser_record(version, id_flag),
+            duplicate_of: chunk.duplicate_of(),
+        }
+    }
+
+    /// The settings that group chunks in a histogram, e.g. "Unified, xz 8MB dictionary
+    /// (CRC32), strict".
+    pub fn summary(&self) -> String {
+        let mut parts = vec![match self.column_codecs.is_empty() {
+            true => self.layout.to_string(),
+            false => format!("{} ({})", self.layout, self.column_codecs.join(", ")),
+        }];
+        match self.backend {
+            "xz" => parts.push(format!("xz {} dictionary ({})",
+                self.dict_size.map_or_else(|| "unknown".to_string(), dict_label), self.check.unwrap_or("unknown"))),
+            "stored" => parts.push("stored".to_string()),
+            _ => {},
+        }
+        match self.parser {
+            Some(parser) => parts.push(if parser.aggressive { "aggressive" } else { "strict" }.to_string()),
+            None if self.parser_recorded => parts.push("parser record unreadable".to_string()),
+            None => {},
+        }
+        parts.join(", ")
+    }
+
+    /// `summary`, with the id flag and the chunk a duplicate repeats.
+    pub fn describe(&self) -> String {
+        match self.duplicate_of {
+            Some(target) => format!("{} of #{}", self.summary(), target),
+            None => format!("{} [{}]", self.summary(), describe_id_flag(self.id_flag)),
+        }
+    }
+}
+
+/// Chunk counts of each distinct `summary` among `settings`, most frequent first (ties in
+/// order of first appearance).
+pub fn settings_histogram(settings: &[BlockSettings]) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for summary in settings.iter().map(BlockSettings::summary) {
+        match counts.iter_mut().find(|(s, _)| *s == summary) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((summary, 1)),
+        }
+    }
+    counts.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    counts
+}
@@ -0,0 +1,164 @@
+use std::io::{self, Cursor};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+use crate::archive::{encode_chunk, line_align_overshoot, line_aligned_len, ArchiveHeader, ChecksumKind, ChunkHeader, EncodedChunk, ARCHIVE_HEADER_SIZE, ARCHIVE_MAGIC, CHUNK_HEADER_SIZE, MAX_LABELS_SIZE, UTF8_BOM};
+use crate::cast::{CASTDecompressor, NativeCompressor, NativeDecompressor, DEFAULT_MAX_VAR_LEN, ID_FLAG_MAC_TRAILER};
+use crate::error::CastError;
+
+// ============================================================================
+//  ASYNC STREAMS (feature "async")
+// ============================================================================
+//
+//  Thin tokio orchestrators over the sync chunk codec: the async side only reads
+//  and writes, every transform + backend call runs on `spawn_blocking`. At most
+//  one chunk is in flight while the next one is read, which bounds memory to two
+//  chunks and applies back-pressure to the reader.
+
+/// Compresses `input` into a classic CAST archive in `chunk_size` chunks, ending each chunk on
+/// a line boundary like the CLI. `make_backend` builds the backend of each chunk (chunks are
+/// independent). Returns (bytes read, bytes written).
+pub async fn async_compress_stream<R, W, C, F>(mut input: R, mut output: W, chunk_size: usize, checksum: ChecksumKind, make_backend: F) -> Result<(u64, u64), CastError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    C: NativeCompressor + Send + 'static,
+    F: Fn() -> C + Send + Sync + 'static,
+{
+    let make_backend = Arc::new(make_backend);
+    let chunk_size = chunk_size.max(1);
+
+    // Reads go a little past the chunk size so every chunk ends with a whole line
+    let window = chunk_size + line_align_overshoot(chunk_size);
+
+    // The header depends on whether the stream starts with a BOM, so the first window is read first
+    let mut buf = read_full(&mut input, window).await?;
+    let has_bom = buf.starts_with(&UTF8_BOM);
+    if has_bom {
+        buf.drain(..UTF8_BOM.len());
+        buf.extend(read_full(&mut input, UTF8_BOM.len()).await?);
+    }
+    let mut at_eof = buf.len() < window;
+    let mut total_in = (buf.len() + if has_bom { UTF8_BOM.len() } else { 0 }) as u64;
+
+    let header = ArchiveHeader::new(checksum).with_bom(has_bom);
+    let mut header_bytes = Vec::with_capacity(ARCHIVE_HEADER_SIZE);
+    header.write_to(&mut header_bytes)?;
+    output.write_all(&header_bytes).await?;
+    let mut total_out = header_bytes.len() as u64;
+
+    let mut in_flight: Option<JoinHandle<Result<EncodedChunk, CastError>>> = None;
+    while !buf.is_empty() {
+        let rest = buf.split_off(line_aligned_len(&buf, chunk_size, at_eof));
+        let chunk = std::mem::replace(&mut buf, rest);
+        let backend = make_backend.clone();
+        let task = tokio::task::spawn_blocking(move || encode_chunk(backend(), &chunk, checksum, false, false, true, DEFAULT_MAX_VAR_LEN));
+        if let Some(prev) = in_flight.replace(task) {
+            total_out += write_encoded(&mut output, join(prev).await?).await?;
+        }
+        let wanted = window - buf.len();
+        let more = read_full(&mut input, wanted).await?;
+        at_eof = more.len() < wanted;
+        total_in += more.len() as u64;
+        buf.extend(more);
+    }
+    if let Some(last) = in_flight {
+        total_out += write_encoded(&mut output, join(last).await?).await?;
+    }
+    output.flush().await?;
+    Ok((total_in, total_out))
+}
+
+/// Restores a CAST archive (any supported version) from `input` into `output`, verifying
+/// chunk CRCs when the archive stores them. Returns the number of bytes written.
+pub async fn async_decompress_stream<R, W, D>(mut input: R, mut output: W, backend: D) -> Result<u64, CastError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    D: NativeDecompressor + Send + Sync + 'static,
+{
+    // Parse the archive header with the sync reader (fixed part, then the labels section); bytes
+    // it does not consume belong to the first chunk
+    let mut head = read_full(&mut input, ARCHIVE_HEADER_SIZE).await?;
+    if head.len() == ARCHIVE_HEADER_SIZE && head[0..4] == ARCHIVE_MAGIC {
+        let labels_len = u32::from_le_bytes(head[ARCHIVE_HEADER_SIZE - 4..].try_into().unwrap()) as usize;
+        head.extend(read_full(&mut input, labels_len.min(MAX_LABELS_SIZE)).await?);
+    }
+    let mut cursor = Cursor::new(head.as_slice());
+    let archive = ArchiveHeader::read_from(&mut cursor)?;
+    let mut pending = head[cursor.position() as usize..].to_vec();
+    let decompressor = Arc::new(CASTDecompressor::new(backend).with_original_size(archive.original_size));
+
+    let mut total_out = 0u64;
+    if archive.has_bom() {
+        output.write_all(&UTF8_BOM).await?;
+        total_out += UTF8_BOM.len() as u64;
+    }
+
+    let mut in_flight: Option<JoinHandle<Result<Vec<u8>, CastError>>> = None;
+    let mut chunk_idx = 0;
+    loop {
+        // Chunk header: leftover header bytes first, then the stream
+        let needed = CHUNK_HEADER_SIZE.saturating_sub(pending.len());
+        pending.extend(read_full(&mut input, needed).await?);
+        if pending.is_empty() { break; }
+        chunk_idx += 1;
+        if pending.len() < CHUNK_HEADER_SIZE {
+            return Err(CastError::LengthOverflow { chunk: chunk_idx, detail: "Truncated chunk header".to_string() });
+        }
+        let header = ChunkHeader::from_bytes(pending[..CHUNK_HEADER_SIZE].try_into().unwrap());
+        // The authentication trailer holds no data (it is checked by -v --hmac-key-file)
+        if header.id_flag == ID_FLAG_MAC_TRAILER { break; }
+        let mut body = pending.split_off(CHUNK_HEADER_SIZE);
+        pending.clear();
+        let missing = header.body_len().saturating_sub(body.len());
+        body.extend(read_full(&mut input, missing).await?);
+        if body.len() != header.body_len() {
+            return Err(CastError::LengthOverflow { chunk: chunk_idx, detail: "Truncated file body".to_string() });
+        }
+
+        let chunk = crate::archive::Chunk::new(header, body, archive.version);
+        let expected_crc = chunk.expected_crc(&archive);
+        let dec = decompressor.clone();
+        let idx = chunk_idx;
+        let task = tokio::task::spawn_blocking(move || {
+            let mut restored = Vec::new();
+            dec.decompress(chunk.reg(), chunk.ids(), chunk.vars(), expected_crc, chunk.header.id_flag, &mut restored)
+                .map_err(|e| e.at_chunk(idx))?;
+            Ok(restored)
+        });
+        if let Some(prev) = in_flight.replace(task) {
+            let restored = join(prev).await?;
+            output.write_all(&restored).await?;
+            total_out += restored.len() as u64;
+        }
+    }
+    if let Some(last) = in_flight {
+        let restored = join(last).await?;
+        output.write_all(&restored).await?;
+        total_out += restored.len() as u64;
+    }
+    output.flush().await?;
+    Ok(total_out)
+}
+
+/// Reads until `len` bytes or EOF.
+async fn read_full<R: AsyncRead + Unpin>(input: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(len);
+    (&mut *input).take(len as u64).read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn write_encoded<W: AsyncWrite + Unpin>(output: &mut W, chunk: EncodedChunk) -> io::Result<u64> {
+    let mut written = 0;
+    for part in &chunk.parts {
+        output.write_all(part).await?;
+        written += part.len() as u64;
+    }
+    Ok(written)
+}
+
+async fn join<T>(task: JoinHandle<Result<T, CastError>>) -> Result<T, CastError> {
+    task.await.map_err(|e| CastError::Io(io::Error::other(format!("Worker task failed: {}", e))))?
+}
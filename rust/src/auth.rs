@@ -0,0 +1,300 @@
+use std::fmt;
+
+use hmac::Mac as _;
+use sha2::Digest;
+use subtle::ConstantTimeEq;
+
+use crate::archive::{chunk_crc, Chunk, ChunkHeader, CHUNK_HEADER_SIZE};
+use crate::cast::ID_FLAG_MAC_TRAILER;
+use crate::error::CastError;
+
+// ============================================================================
+//  AUTHENTICATION (--hmac-key-file)
+// ============================================================================
+//
+//  CRC32 only catches accidents: whoever changes a chunk can fix its CRC as well. With a key,
+//  the restored content of every chunk is also covered by an HMAC-SHA256, and the archive as a
+//  whole by one more over its header and the chunk MACs. They are kept in a trailer after the
+//  last chunk, so chunks are still written as soon as they are compressed. The key is never
+//  stored; a short check value derived from it tells a wrong key apart from tampering.
+//
+//  Trailer: a chunk with id_flag 252 (archive flag FLAG_AUTHENTICATED), empty
+//  reg and ids, crc = CRC32 of its vars:
+//      version u8 | key_check [8] | count u32 | [chunk MAC [32]]* | archive MAC [32]
+//
+//  chunk MAC   = HMAC(key, "chunk" | number u64 (1-based) | restored content)
+//  archive MAC = HMAC(key, "archive" | archive header as stored | count u32 | chunk MACs)
+//  key check   = first 8 bytes of HMAC(key, "key check")
+//
+//  The chunk number binds each MAC to its place and the count to the end of the archive, so
+//  reordered, dropped or appended chunks fail like modified ones.
+
+pub const TRAILER_VERSION: u8 = 1;
+pub const MAC_LEN: usize = 32;
+const KEY_CHECK_LEN: usize = 8;
+const TRAILER_FIXED_LEN: usize = 1 + KEY_CHECK_LEN + 4 + MAC_LEN;
+// Key files are secrets, not data: anything larger is almost certainly the wrong file
+pub const MAX_KEY_FILE_LEN: u64 = 64 * 1024;
+
+pub type Mac = [u8; MAC_LEN];
+
+/// Compares two CRC32 values in constant time, so a mismatch does not reveal how many leading
+/// bytes were right.
+pub fn crc_eq(a: u32, b: u32) -> bool {
+    a.ct_eq(&b).into()
+}
+
+// ============================================================================
+//  SHA-256 / HMAC-SHA256 (sha2, hmac)
+// ============================================================================
+
+/// SHA-256 with its digest as a plain array (content digests).
+#[derive(Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl Sha256 {
+    pub fn update(&mut self, data: &[u8]) {
+        Digest::update(&mut self.0, data);
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        Digest::finalize(self.0).into()
+    }
+
+    pub fn digest(data: &[u8]) -> [u8; 32] {
+        sha2::Sha256::digest(data).into()
+    }
+}
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+/// Secret of --hmac-key-file, as the keyed HMAC state, never printed: there is deliberately no
+/// `Debug`.
+#[derive(Clone)]
+pub struct MacKey {
+    hmac: HmacSha256,
+}
+
+/// An HMAC in progress (see `MacKey::start`).
+pub struct Hmac(HmacSha256);
+
+impl Hmac {
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finalize(self) -> Mac {
+        self.0.finalize().into_bytes().into()
+    }
+}
+
+impl MacKey {
+    pub fn new(key: &[u8]) -> Self {
+        // HMAC takes keys of any length (longer than a block they are hashed first)
+        Self { hmac: <HmacSha256 as hmac::Mac>::new_from_slice(key).expect("HMAC accepts keys of any length") }
+    }
+
+    /// Reads a key file: its bytes, exactly as stored, are the key. Errors are printable and
+    /// never quote the file's content.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let len = std::fs::metadata(path).map_err(|e| format!("Cannot read HMAC key file '{}': {}", path, e))?.len();
+        if len > MAX_KEY_FILE_LEN {
+            return Err(format!("HMAC key file '{}' is too large ({} bytes, at most {}): is it the right file?", path, len, MAX_KEY_FILE_LEN));
+        }
+        let key = std::fs::read(path).map_err(|e| format!("Cannot read HMAC key file '{}': {}", path, e))?;
+        if key.is_empty() { return Err(format!("HMAC key file '{}' is empty.", path)); }
+        Ok(Self::new(&key))
+    }
+
+    pub fn start(&self) -> Hmac {
+        Hmac(self.hmac.clone())
+    }
+
+    pub fn mac(&self, data: &[u8]) -> Mac {
+        let mut h = self.start();
+        h.update(data);
+        h.finalize()
+    }
+
+    /// MAC of chunk `number` (1-based) restoring to `content`.
+    pub fn chunk_mac(&self, number: u64, content: &[u8]) -> Mac {
+        let mut h = self.start();
+        h.update(b"chunk");
+        h.update(&number.to_le_bytes());
+        h.update(content);
+        h.finalize()
+    }
+
+    /// MAC of the archive: its header bytes as stored and the MACs of all its chunks.
+    pub fn archive_mac(&self, header: &[u8], chunk_macs: &[Mac]) -> Mac {
+        let mut h = self.start();
+        h.update(b"archive");
+        h.update(header);
+        h.update(&(chunk_macs.len() as u32).to_le_bytes());
+        for mac in chunk_macs { h.update(mac); }
+        h.finalize()
+    }
+
+    fn key_check(&self) -> [u8; KEY_CHECK_LEN] {
+        self.mac(b"key check")[..KEY_CHECK_LEN].try_into().unwrap()
+    }
+
+    /// Trailer of an archive whose header is `header` (as stored) and whose chunks have the
+    /// MACs `chunk_macs`, in order.
+    pub fn trailer(&self, header: &[u8], chunk_macs: Vec<Mac>) -> MacTrailer {
+        MacTrailer { key_check: self.key_check(), archive_mac: self.archive_mac(header, &chunk_macs), chunk_macs }
+    }
+}
+
+// ============================================================================
+//  AUTHENTICATION TRAILER
+// ============================================================================
+
+pub struct MacTrailer {
+    key_check: [u8; KEY_CHECK_LEN],
+    pub chunk_macs: Vec<Mac>,
+    archive_mac: Mac,
+}
+
+/// Why an archive failed authentication. All but `WrongKey` mean it was modified after it was
+/// written (content, header, chunk order or length).
+#[derive(Debug, PartialEq)]
+pub enum AuthFailure {
+    WrongKey,
+    Missing,
+    ChunkCount { stored: usize, found: usize },
+    Chunk(usize),
+    Archive,
+}
+
+impl fmt::Display for AuthFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthFailure::WrongKey => write!(f, "Wrong HMAC key: the archive was authenticated with a different key (or its trailer replaced)"),
+            AuthFailure::Missing => write!(f, "Authentication trailer missing: the archive was cut short or its trailer removed"),
+            AuthFailure::ChunkCount { stored, found } => write!(f, "Archive holds {} chunk(s), its trailer authenticates {}: chunks were added or removed", found, stored),
+            AuthFailure::Chunk(n) => write!(f, "HMAC mismatch at Chunk {}: its content was modified", n),
+            AuthFailure::Archive => write!(f, "Archive HMAC mismatch: its header was modified"),
+        }
+    }
+}
+
+impl MacTrailer {
+    /// Bytes the trailer of an archive with `chunks` data chunks takes, chunk header included.
+    pub fn archive_len(chunks: usize) -> usize {
+        CHUNK_HEADER_SIZE + TRAILER_FIXED_LEN + chunks * MAC_LEN
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(TRAILER_FIXED_LEN + self.chunk_macs.len() * MAC_LEN);
+        body.push(TRAILER_VERSION);
+        body.extend_from_slice(&self.key_check);
+        body.extend_from_slice(&(self.chunk_macs.len() as u32).to_le_bytes());
+        for mac in &self.chunk_macs { body.extend_from_slice(mac); }
+        body.extend_from_slice(&self.archive_mac);
+        body
+    }
+
+    /// The trailer as written after the last chunk: chunk header, empty reg and ids, body.
+    pub fn to_parts(&self) -> Vec<Vec<u8>> {
+        let body = self.encode();
+        let header = ChunkHeader { crc: chunk_crc(&body), len_reg: 0, len_ids: 0, len_vars: body.len() as u32, id_flag: ID_FLAG_MAC_TRAILER };
+        vec![header.to_bytes().to_vec(), Vec::new(), Vec::new(), body]
+    }
+
+    /// Parses the trailer chunk `chunk` (the chunk after the last one, 1-based).
+    pub fn from_chunk(trailer: &Chunk, chunk: usize) -> Result<Self, CastError> {
+        let corrupt = |detail: &str| CastError::CorruptHeader { chunk, detail: format!("Authentication trailer: {}", detail) };
+        let body = trailer.vars();
+        let actual = chunk_crc(body);
+        if !crc_eq(actual, trailer.header.crc) { return Err(CastError::CrcMismatch { chunk, expected: trailer.header.crc, actual }); }
+        if body.len() < TRAILER_FIXED_LEN { return Err(corrupt("too short")); }
+        if body[0] != TRAILER_VERSION { return Err(corrupt(&format!("unknown version {}", body[0]))); }
+        let count = u32::from_le_bytes(body[1 + KEY_CHECK_LEN..TRAILER_FIXED_LEN - MAC_LEN].try_into().unwrap()) as usize;
+        if body.len() != TRAILER_FIXED_LEN + count * MAC_LEN { return Err(corrupt("length does not match its chunk count")); }
+        let macs = &body[TRAILER_FIXED_LEN - MAC_LEN..];
+        Ok(Self {
+            key_check: body[1..1 + KEY_CHECK_LEN].try_into().unwrap(),
+            chunk_macs: macs.chunks_exact(MAC_LEN).take(count).map(|m| m.try_into().unwrap()).collect(),
+            archive_mac: macs[count * MAC_LEN..].try_into().unwrap(),
+        })
+    }
+
+    /// Checks the MACs computed while reading the archive (`header` as stored, `chunk_macs` from
+    /// the restored chunks) against the stored ones, with `key`.
+    pub fn check(&self, key: &MacKey, header: &[u8], chunk_macs: &[Mac]) -> Result<(), AuthFailure> {
+        if !bool::from(key.key_check().ct_eq(&self.key_check)) { return Err(AuthFailure::WrongKey); }
+        if chunk_macs.len() != self.chunk_macs.len() {
+            return Err(AuthFailure::ChunkCount { stored: self.chunk_macs.len(), found: chunk_macs.len() });
+        }
+        if let Some(i) = chunk_macs.iter().zip(&self.chunk_macs).position(|(found, stored)| !bool::from(found.ct_eq(stored))) {
+            return Err(AuthFailure::Chunk(i + 1));
+        }
+        if !bool::from(key.archive_mac(header, chunk_macs).ct_eq(&self.archive_mac)) { return Err(AuthFailure::Archive); }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::digest_hex;
+
+    // Known answers of SHA-256 (FIPS 180-4 examples: the empty message, "abc" and the 448-bit
+    // message) and of HMAC-SHA256 (RFC 4231 test cases 1-4, 6 and 7; 6 and 7 have keys longer
+    // than a block). The million "a" message of FIPS 180-4 is fed in uneven pieces by its test.
+    const SHA256_VECTORS: [(&[u8], &str); 3] = [
+        (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+        (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+        (b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+    ];
+    const SHA256_MILLION_A: &str = "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0";
+    const HMAC_VECTORS: [(&[u8], &[u8], &str); 6] = [
+        (&[0x0b; 20], b"Hi There", "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+        (b"Jefe", b"what do ya want for nothing?", "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+        (&[0xaa; 20], &[0xdd; 50], "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+        (&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25], &[0xcd; 50], "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"),
+        (&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First", "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"),
+        (&[0xaa; 131], b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.", "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"),
+    ];
+
+    fn printable(message: &[u8]) -> String {
+        String::from_utf8_lossy(&message[..message.len().min(24)]).into_owned()
+    }
+
+    /// Each message whole and one byte at a time, so block boundaries fall everywhere.
+    #[test]
+    fn sha256_known_answers() {
+        for (message, expected) in SHA256_VECTORS {
+            let mut bytewise = Sha256::default();
+            message.iter().for_each(|b| bytewise.update(&[*b]));
+            assert_eq!(digest_hex(&Sha256::digest(message)), expected, "SHA-256 of \"{}\"", printable(message));
+            assert_eq!(digest_hex(&bytewise.finalize()), expected, "SHA-256 of \"{}\" byte by byte", printable(message));
+        }
+    }
+
+    #[test]
+    fn sha256_million_a_in_pieces() {
+        let million = vec![b'a'; 1_000_000];
+        let mut hasher = Sha256::default();
+        let mut rest = &million[..];
+        for piece_len in (1..128).cycle() {
+            if rest.is_empty() { break; }
+            let (piece, tail) = rest.split_at(piece_len.min(rest.len()));
+            hasher.update(piece);
+            rest = tail;
+        }
+        assert_eq!(digest_hex(&hasher.finalize()), SHA256_MILLION_A);
+    }
+
+    #[test]
+    fn hmac_known_answers() {
+        for (key, message, expected) in HMAC_VECTORS {
+            let key = MacKey::new(key);
+            let mut bytewise = key.start();
+            message.iter().for_each(|b| bytewise.update(&[*b]));
+            assert_eq!(digest_hex(&key.mac(message)), expected, "HMAC-SHA256 of \"{}\"", printable(message));
+            assert_eq!(digest_hex(&bytewise.finalize()), expected, "HMAC-SHA256 of \"{}\" byte by byte", printable(message));
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::env;
+use std::path::Path;
+use std::time::Instant;
+
+use cast::archive::{encode_chunk, ChecksumKind, InputChunker, ARCHIVE_HEADER_SIZE};
+use cast::cast::{CASTCompressor, IdsTransform, DEFAULT_MAX_VAR_LEN};
+use cast::cli::{self, Binary};
+use cast::cast_lzma::LzmaBackend;
+use cast::datagen::{generate_vec, template_usage_vec, Profile, TemplateUsage, DEFAULT_SEED};
+
+// ============================================================================
+//  RATIO REGRESSION GATE
+// ============================================================================
+//
+//  Compresses one seeded `cast generate` fixture per profile with a few fixed settings and
+//  compares the archive sizes with the committed baselines. Everything that goes into the
+//  sizes is deterministic (seeded data, single-threaded native LZMA, fixed dictionary), so
+//  any difference comes from the code.
+//
+//  The effect of the id stream transforms is measured on the side (it is not checked): the
+//  compressed id stream of each `template_usage_vec` fixture without a transform, with each
+//  one forced and with the one the compressor picks.
+
+const FIXTURE_SIZE: usize = 4 * 1024 * 1024;
+const DICT_SIZE: u32 = 16 * 1024 * 1024;
+const CHUNKED_SIZE: usize = 1024 * 1024;
+// Rows of the id stream fixtures: more than the transform trial samples
+const TEMPLATE_USAGE_ROWS: usize = 100_000;
+// Allowed growth of a compressed size before it counts as a regression (percent)
+const DEFAULT_TOLERANCE: f64 = 0.5;
+const DEFAULT_BASELINES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/baselines.toml");
+
+// Settings profile: name, chunk size (None = solid), per-column codecs
+const SETTINGS: [(&str, Option<usize>, bool); 3] = [
+    ("solid", None, false),
+    ("chunked", Some(CHUNKED_SIZE), false),
+    ("column-codecs", None, true),
+];
+
+struct Baselines {
+    fixture_size: usize,
+    seed: u64,
+    // (fixture, settings, compressed bytes)
+    sizes: Vec<(String, String, u64)>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print_usage();
+        return;
+    }
+    if let Some(unknown) = cli::unknown_option(Binary::BenchRegression, &args[1..]) {
+        eprintln!("[!]  Error: Unknown option '{}' (see --help).", unknown);
+        std::process::exit(2);
+    }
+    let update = args.iter().any(|arg| arg == "--update");
+    let baselines_path = args.windows(2).find(|w| w[0] == "--baselines").map(|w| w[1].clone()).unwrap_or_else(|| DEFAULT_BASELINES.to_string());
+    let tolerance = match args.windows(2).find(|w| w[0] == "--tolerance") {
+        Some(w) => w[1].trim_end_matches('%').parse::<f64>().unwrap_or_else(|_| {
+            eprintln!("[!]  Error: Invalid tolerance: '{}'.", w[1]);
+            std::process::exit(2);
+        }),
+        None => DEFAULT_TOLERANCE,
+    };
+
+    println!("\n[*]  Ratio regression check");
+    println!("       Fixtures:    {} per profile (seed {})", format_bytes(FIXTURE_SIZE), DEFAULT_SEED);
+    println!("       Backend:     Native LZMA, single thread, dict {}", format_bytes(DICT_SIZE as usize));
+    println!("       Baselines:   {}", baselines_path);
+
+    let start = Instant::now();
+    let mut current = Vec::new();
+    for profile in Profile::ALL {
+        let data = generate_vec(profile, FIXTURE_SIZE, DEFAULT_SEED);
+        for (settings, chunk_size, column_codecs) in SETTINGS {
+            let size = archive_size(&data, chunk_size, column_codecs).unwrap_or_else(|e| {
+                eprintln!("[!]  Error: {} / {}: {}", profile.name(), settings, e);
+                std::process::exit(2);
+            });
+            current.push((profile.name().to_string(), settings.to_string(), size));
+        }
+    }
+    println!("       Time:        {:.2}s", start.elapsed().as_secs_f64());
+    report_ids_transforms();
+
+    if update {
+        let baselines = Baselines { fixture_size: FIXTURE_SIZE, seed: DEFAULT_SEED, sizes: current };
+        if let Err(e) = std::fs::write(&baselines_path, baselines.to_toml()) {
+            eprintln!("[!]  Error: Cannot write '{}': {}", baselines_path, e);
+            std::process::exit(2);
+        }
+        println!("\n[+]  Baselines written: {} ({} entries)", baselines_path, baselines.sizes.len());
+        return;
+    }
+
+    let baselines = match std::fs::read_to_string(&baselines_path).map_err(|e| e.to_string()).and_then(|s| Baselines::parse(&s)) {
+        Ok(b) => b,
+        Err(e) => {
+            eprintln!("[!]  Error: Cannot load baselines '{}': {}", baselines_path, e);
+            eprintln!("     Create them with: cargo run --release --bin bench_regression -- --update");
+            std::process::exit(2);
+        }
+    };
+    if baselines.fixture_size != FIXTURE_SIZE || baselines.seed != DEFAULT_SEED {
+        eprintln!("[!]  Error: Baselines were recorded for {} fixtures with seed {}: regenerate them with --update.",
+            format_bytes(baselines.fixture_size), baselines.seed);
+        std::process::exit(2);
+    }
+
+    println!("\n     {:<12} {:<14} {:>12} {:>12} {:>9}", "Fixture", "Settings", "Baseline", "Current", "Delta");
+    let mut regressions = 0;
+    let mut improvements = 0;
+    for (fixture, settings, size) in &current {
+        let baseline = baselines.sizes.iter().find(|(f, s, _)| f == fixture && s == settings).map(|e| e.2);
+        let (baseline_col, delta_col, note) = match baseline {
+            Some(base) => {
+                let delta = (*size as f64 - base as f64) * 100.0 / base.max(1) as f64;
+                let note = if delta > tolerance {
+                    regressions += 1;
+                    "  REGRESSION"
+                } else if delta < -tolerance {
+                    improvements += 1;
+                    "  improved"
+                } else { "" };
+                (base.to_string(), format!("{:+.2}%", delta), note)
+            },
+            None => ("-".to_string(), "new".to_string(), ""),
+        };
+        println!("     {:<12} {:<14} {:>12} {:>12} {:>9}{}", fixture, settings, baseline_col, size, delta_col, note);
+    }
+
+    if regressions > 0 {
+        println!("\n[!]  {} size regression(s) beyond {:.2}%.", regressions, tolerance);
+        println!("     If the change is intended, regenerate the baselines with --update and commit them.");
+        std::process::exit(1);
+    }
+    println!("\n[+]  No size regression beyond {:.2}%.", tolerance);
+    if improvements > 0 {
+        println!("     {} size(s) improved: run with --update to lock the gain in.", improvements);
+    }
+}
+
+/// Size of the archive `cast -c` would write for `data` (header and chunks).
+fn archive_size(data: &[u8], chunk_size: Option<usize>, column_codecs: bool) -> Result<u64, String> {
+    let mut total = ARCHIVE_HEADER_SIZE as u64;
+    let mut chunker = InputChunker::new(data, chunk_size, data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? {
+        let encoded = encode_chunk(LzmaBackend::new(false, DICT_SIZE), chunk, ChecksumKind::Crc32, column_codecs, false, true, DEFAULT_MAX_VAR_LEN)
+            .map_err(|e| e.to_string())?;
+        total += encoded.parts.iter().map(|p| p.len() as u64).sum::<u64>();
+    }
+    Ok(total)
+}
+
+/// Prints the compressed id stream of each template usage fixture per transform (solid).
+fn report_ids_transforms() {
+    let settings = [("none", Some(IdsTransform::None)), ("move-to-front", Some(IdsTransform::MoveToFront)), ("delta", Some(IdsTransform::Delta)), ("picked", None)];
+    println!("\n     {:<12} {:>12} {:>14} {:>12} {:>12}   (compressed id stream, {} rows)", "Id streams", "none", "move-to-front", "delta", "picked", TEMPLATE_USAGE_ROWS);
+    for usage in TemplateUsage::ALL {
+        let data = template_usage_vec(usage, TEMPLATE_USAGE_ROWS, DEFAULT_SEED);
+        let sizes: Vec<String> = settings.iter().map(|&(name, transform)| {
+            match CASTCompressor::new(LzmaBackend::new(false, DICT_SIZE)).with_ids_transform(transform).compress(&data) {
+                Ok(chunk) => chunk.1.len().to_string(),
+                Err(e) => {
+                    eprintln!("[!]  Error: {} / {}: {}", usage.name(), name, e);
+                    std::process::exit(2);
+                },
+            }
+        }).collect();
+        println!("     {:<12} {:>12} {:>14} {:>12} {:>12}", usage.name(), sizes[0], sizes[1], sizes[2], sizes[3]);
+    }
+}
+
+impl Baselines {
+    // Minimal TOML: top-level integer keys, then one [fixture] table of settings = size
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut fixture_size = None;
+        let mut seed = None;
+        let mut sizes = Vec::new();
+        let mut table: Option<String> = None;
+        for (n, raw) in text.lines().enumerate() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            if line.is_empty() { continue; }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                table = Some(name.trim().trim_matches('"').to_string());
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| format!("line {}: expected key = value", n + 1))?;
+            let (key, value) = (key.trim().trim_matches('"'), value.trim());
+            let value: u64 = value.parse().map_err(|_| format!("line {}: '{}' is not an integer", n + 1, value))?;
+            match (&table, key) {
+                (None, "fixture_size") => fixture_size = Some(value as usize),
+                (None, "seed") => seed = Some(value),
+                (None, _) => return Err(format!("line {}: unknown key '{}'", n + 1, key)),
+                (Some(fixture), _) => sizes.push((fixture.clone(), key.to_string(), value)),
+            }
+        }
+        Ok(Self {
+            fixture_size: fixture_size.ok_or("missing fixture_size")?,
+            seed: seed.ok_or("missing seed")?,
+            sizes,
+        })
+    }
+
+    fn to_toml(&self) -> String {
+        let mut out = String::from("# Archive sizes (bytes) of the seeded `cast generate` fixtures, per settings profile.\n");
+        out.push_str("# Checked by `cargo run --release --bin bench_regression`.\n");
+        out.push_str("# Regenerate on purpose with `cargo run --release --bin bench_regression -- --update`.\n\n");
+        out.push_str(&format!("fixture_size = {}\nseed = {}\n", self.fixture_size, self.seed));
+        let mut last: Option<&str> = None;
+        for (fixture, settings, size) in &self.sizes {
+            if last != Some(fixture.as_str()) {
+                out.push_str(&format!("\n[{}]\n", fixture));
+                last = Some(fixture);
+            }
+            out.push_str(&format!("{} = {}\n", settings, size));
+        }
+        out
+    }
+}
+
+fn format_bytes(n: usize) -> String {
+    let s = n.to_string();
+    let mut result = String::new();
+    for (i, c) in s.chars().rev().enumerate() {
+        if i > 0 && i % 3 == 0 {
+            result.push(',');
+        }
+        result.push(c);
+    }
+    format!("{} bytes", result.chars().rev().collect::<String>())
+}
+
+fn print_usage() {
+    let exe_name = env::args().next().and_then(|a| Path::new(&a).file_name().map(|n| n.to_string_lossy().into_owned())).unwrap_or_else(|| "bench_regression".to_string());
+    println!(
+        "\nCAST ratio regression check (v{})\n\n\
+        Usage:\n  \
+          {} [--tolerance <PCT>] [--baselines <FILE>] [--update]\n\n\
+        Options:\n{}\n\
+        Exit status: 0 = no regression, 1 = regression, 2 = error",
+        env!("CARGO_PKG_VERSION"),
+        exe_name, cli::render_options(Binary::BenchRegression)
+    );
+}
@@ -0,0 +1,2328 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::io::Cursor;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "async")]
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use cast::archive::{catch_panics, chunk_crc, encode_chunk, encode_duplicate, max_compressed_size, settings_histogram, ArchiveHeader, BlockSettings, ChecksumKind, Chunk, ChunkDedup, ChunkReader, DedupTarget, InputChunker, RowOrder, CHUNK_HEADER_SIZE, FORMAT_VERSION, UTF8_BOM, LEGACY_VERSION, MAX_SEGMENT_LEN, ROW_ORDER_LABEL, WritePipeline};
+use cast::cancel::{CancelPolicy, CancellationToken};
+#[cfg(feature = "async")]
+use cast::async_io::{async_compress_stream, async_decompress_stream};
+use cast::auth::{AuthFailure, Mac, MacKey, MacTrailer, Sha256};
+use cast::cast::{describe_id_flag, is_passthrough, sample_strategy, CASTCompressor, CASTDecompressor, ColumnKind, IdentityBackend, IdsTransform, LayoutSample, NativeCompressor, ProgressEvent, NativeDecompressor, OutputLimit, ParserConfig, DEFAULT_MAX_VAR_LEN, ID_FLAG_DUPLICATE, ID_FLAG_MAC_TRAILER, LAYOUT_SAMPLE_BYTES, LAYOUT_SAMPLE_CELLS, STRATEGY_SAMPLE_BYTES};
+use cast::cast_lzma::{try_find_7zip_path, LzmaBackend, LzmaDecompressorBackend, RuntimeLzmaCompressor, SevenZipBackend};
+use cast::cli::{self, Binary};
+use cast::datagen::{distinct_templates_vec, generate_vec, template_usage_vec, Profile, TemplateUsage, DEFAULT_SEED};
+use cast::encoded_out::{EncodedWriter, OutputEncoding};
+use cast::error::CastError;
+use cast::positioned_out::PositionedSink;
+#[cfg(unix)]
+use cast::sparse_out::SparseWriter;
+use cast::templates::{render_template, TemplateSet, PARSER_COMMENT};
+#[cfg(feature = "async")]
+use tokio::io::AsyncWriteExt;
+
+// ============================================================================
+//  DIFFERENTIAL ROUND-TRIP CHECK
+// ============================================================================
+//
+//  Every fixture is compressed with each transform setting, decoded again and compared with
+//  the original byte for byte. A transform that folds several line shapes into one template
+//  is only correct if the captured variables tell them apart: the adversarial fixtures are the
+//  inputs where that is easy to get wrong. A new transform registers its setting in
+//  TRANSFORMS and the inputs that stress it in ADVERSARIAL_FIXTURES. `cargo test` runs this
+//  binary (tests/roundtrip.rs). The checks that run the cast binary are tests of their own in
+//  tests/cli.rs, and those of a single module (hashes, --7z-args, xz headers, the chunk size
+//  model, the I/O budget, multithreading plans) are unit tests of that module.
+//
+//  Each fixture also goes through (the doc comment of each check has the details):
+//    - dedup_round_trip, auth_round_trip: 4KB-chunk archives with --dedup-chunks (DEDUP_FIXTURES
+//      must shrink) and with an HMAC trailer (wrong key, swapped chunk)
+//    - cell_count_check, crafted_registry_check: damaged blocks fail where the damage is, within
+//      a heap bound
+//    - reuse_round_trip: a reused compressor writes the blocks a fresh one writes (debug builds
+//      also poison the buffers it recycles)
+//    - ids_transform_check, split_determinism_check: forced and picked id stream transforms,
+//      forced split layouts
+//    - columns_check, random_access_check: rows rebuilt from the columns, partial restores
+//
+//  Checks on dedicated inputs:
+//    - every_flag_check: every id_flag bit of a data chunk, under the chunk kinds from 252 up
+//    - bom_check: a leading BOM recorded in the header and restored, through the async stream
+//    - binary_guard_check: text with ANSI escapes is parsed; UTF-16, NUL padding and PDF
+//      streams are passthrough
+//    - single_template_check, narrow_ids_memory_check, wide_ids_check: compact and legacy
+//      single-template blocks, u8 ids under a heap bound, u32 ids
+//    - strategy_sample_check, layout_sample_check: parsing mode and unified/split samples
+//      within their budgets
+//    - positioned_restore_check, sparse_check, async_stream_check, length_overflow_check:
+//      restore paths, sparse files, async streams, 4GB fields
+//    - parser_config_check, row_order_check, block_settings_check, legacy_golden_check,
+//      current_golden_check: recorded parser settings, row order, mixed chunk settings, the
+//      golden archives of both versions
+//    - encoded_output_check: restores compressed on the way out (gzip, zstd)
+//    - progress_check: progress inside a solid chunk, from the library hook
+//    - panic_check, cancellation_check: the write pipeline under a panicking backend and a
+//      cancelled token
+
+const PROFILE_FIXTURE_SIZE: usize = 512 * 1024;
+const SMALL_CHUNK_SIZE: usize = 4 * 1024;
+const DICT_SIZE: u32 = 1024 * 1024;
+
+// Transform setting: name, per-column codecs, front-coded registry
+const TRANSFORMS: [(&str, bool, bool); 3] = [
+    ("plain", false, false),
+    ("column-codecs", true, false),
+    ("front-coded registry", false, true),
+];
+
+// Chunking: name, chunk size (None = solid)
+const CHUNKINGS: [(&str, Option<usize>); 2] = [
+    ("solid", None),
+    ("4KB chunks", Some(SMALL_CHUNK_SIZE)),
+];
+
+// Backends: LZMA as shipped, and pass-through (its poor sample ratio picks the split layout
+// on every input large enough for the layout probe, which LZMA rarely does on small fixtures)
+const BACKENDS: [&str; 2] = ["lzma", "identity"];
+
+// Inputs built to trip normalizing transforms: name, transform it targets, generator
+type Fixture = (&'static str, &'static str, fn() -> Vec<u8>);
+
+const ADVERSARIAL_FIXTURES: [Fixture; 25] = [
+    ("json-duplicate-keys", "templating", json_duplicate_keys),
+    ("csv-trailing-delimiters", "templating", csv_trailing_delimiters),
+    ("quoted-whitespace", "templating", quoted_whitespace),
+    ("quote-edges", "quote scanner", quote_edges),
+    ("line-endings", "templating", line_endings),
+    ("bom-crlf", "byte order mark", bom_crlf),
+    ("bom-only", "byte order mark", bom_only),
+    ("similar-templates", "registry front coding", similar_templates),
+    ("separator-bytes", "escaping", separator_bytes),
+    ("timestamp-request-ids", "tokenizer", timestamp_request_ids),
+    ("ansi-colors", "binary guard", ansi_colors),
+    ("utf16-text", "binary guard", utf16_text),
+    ("nul-padded", "binary guard", nul_padded),
+    ("pdf-like", "binary guard", pdf_like),
+    ("long-values", "variable length cap", long_values),
+    ("latin1", "encoding", latin1),
+    ("tiny", "edge cases", tiny),
+    ("repeated-chunks", "chunk deduplication", repeated_chunks),
+    ("incompressible-text", "size bound", incompressible_text),
+    ("escape-heavy", "size bound", escape_heavy),
+    ("stable-template-ids", "id stream transforms", stable_template_ids),
+    ("shifting-template-ids", "id stream transforms", shifting_template_ids),
+    ("alternating-template-ids", "id stream transforms", alternating_template_ids),
+    ("distinct-templates", "u32 template ids", distinct_templates),
+    ("every-flag", "id_flag layout", every_flag),
+];
+
+// Binary guard fixtures and whether their chunk must be stored as passthrough (see BINARY
+// GUARD in cast.rs): escape sequences are text, NULs and binary streams are not
+const BINARY_GUARD_CASES: [(&str, bool); 4] = [
+    ("ansi-colors", false),
+    ("utf16-text", true),
+    ("nul-padded", true),
+    ("pdf-like", true),
+];
+
+// Byte order mark fixtures: the header must record the BOM the compressors strip, and the
+// restore must put it back
+#[cfg(feature = "async")]
+const BOM_FIXTURES: [&str; 2] = ["bom-crlf", "bom-only"];
+
+// Rows of the *-template-ids fixtures: enough ids for the transform trial, few enough for the
+// trial to cover all of them
+const TEMPLATE_IDS_ROWS: usize = 12_000;
+
+// Templates of the distinct-templates fixture (past the u16 ids) and rows per template (enough
+// lines for the chunk not to fall back to passthrough)
+const DISTINCT_TEMPLATES: usize = 70_000;
+const DISTINCT_TEMPLATE_REPEATS: usize = 5;
+
+// Rows of the short-lines fixture, picked in scrambled order among its (variable-free) lines,
+// and the heap compressing it may take per row beyond the input: the u8 id stream and its
+// encoded copy take 2 bytes (u16 ids would take 3, u32 ids 5)
+const SHORT_LINES_ROWS: usize = 1 << 20;
+const SHORT_LINES: [&str; 4] = ["ok\n", "up\n", "no\n", "go\n"];
+const SHORT_LINES_HEAP_PER_ROW: f64 = 2.5;
+
+// Lines of the single-template checks (one template with vars, one without) and the row
+// counts each is checked at, in compact (id mode 4) and legacy (id mode 3) blocks
+const SINGLE_TEMPLATE_LINES: [(&str, &str, bool); 2] = [("with vars", "job 42 finished in 17 ms\n", true), ("without vars", "\n", false)];
+const SINGLE_TEMPLATE_ROWS: [usize; 3] = [0, 1, 100];
+
+// Rows of the every-flag fixture, and the run of rows each of its templates gets in turn
+const EVERY_FLAG_ROWS: usize = 3000;
+const EVERY_FLAG_RUN: usize = 40;
+
+// Fixtures whose archive --dedup-chunks must shrink
+const DEDUP_FIXTURES: [&str; 1] = ["repeated-chunks"];
+
+// Parsing mode the strategy sample picks for each generated profile (Aggressive = true)
+const PROFILE_MODES: [(Profile, bool); 5] = [
+    (Profile::WebAccess, false),
+    (Profile::Csv, false),
+    (Profile::Ndjson, false),
+    (Profile::Syslog, false),
+    (Profile::Uuid, true),
+];
+
+// Minified JSON of the strategy sample check: lines of about 20KB, enough of them to pass the
+// sample's byte cap
+const MINIFIED_JSON_LINES: usize = 1000;
+// Heap the strategy sample may hold at its peak: one line's skeleton and the skeleton hashes
+const STRATEGY_MEMORY_BOUND: usize = 1024 * 1024;
+
+// Columns of the wide template of the layout sample check (40x the cells the sample takes), its
+// rows, and the length and rows of the huge values of the other fixture
+const WIDE_COLUMNS: usize = 2000;
+const WIDE_ROWS: usize = 40;
+const HUGE_VALUE_LEN: usize = 48 * 1024;
+const HUGE_VALUE_ROWS: usize = 16;
+
+// Headerless version 1 archive written by the first release (`cast -c --chunk-size 8KB --mode
+// native`, three chunks) and the file it holds: decoding it must keep working
+const LEGACY_GOLDEN_ARCHIVE: &[u8] = include_bytes!("../../tests/golden/legacy-v1.log.cast");
+const LEGACY_GOLDEN_ORIGINAL: &[u8] = include_bytes!("../../tests/golden/legacy-v1.log");
+const LEGACY_GOLDEN_CHUNKS: usize = 3;
+// The same file as a current version archive (same command)
+const CURRENT_GOLDEN_ARCHIVE: &[u8] = include_bytes!("../../tests/golden/current-v2.log.cast");
+
+// Threads and chunks per group of the positioned restore check
+const RESTORE_THREADS: usize = 4;
+const RESTORE_GROUP_CHUNKS: usize = 8;
+
+// Variable length cap of the second archive of the parser check: short enough to keep values
+// of the generated fixtures in their templates
+const PARSER_CHECK_MAX_VAR_LEN: usize = 8;
+
+// Chunk size and the two LZMA dictionaries of the block settings check: the streams of a chunk
+// outgrow both, so neither is shrunk to fit
+const BLOCK_CHUNK_SIZE: usize = 128 * 1024;
+const BLOCK_DICTS: [u32; 2] = [16 * 1024, 64 * 1024];
+
+// Chunk (1-based) the backend of the panic check panics on, and how long the failed run may
+// take at most
+const PANIC_CHUNK: usize = 3;
+const PANIC_TIME_BOUND: Duration = Duration::from_secs(10);
+
+// Rows of the byte value check: few enough for the unified layout (under the layout probe's
+// 64KB), and enough for the pass-through backend to get the split one
+const BYTE_CELL_LAYOUTS: [(&str, usize); 2] = [("Unified", 512), ("Split", 3072)];
+
+// Async stream check (feature "async"): chunk size, pipe buffer the input is written through,
+// runtime ticks each backend call waits for, and how long it waits for them at most
+#[cfg(feature = "async")]
+const ASYNC_CHUNK_SIZE: usize = 64 * 1024;
+#[cfg(feature = "async")]
+const ASYNC_PIPE_SIZE: usize = 4 * 1024;
+#[cfg(feature = "async")]
+const ASYNC_TICKS: usize = 8;
+#[cfg(feature = "async")]
+const ASYNC_TICK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Sparse check (Unix): size of the sparse source, offsets of its data regions (the last one
+// ends the file), their length, the chunk size it is compressed with, and the blocks (of 512
+// bytes) a sparse restore may allocate beyond those of the source
+#[cfg(unix)]
+const SPARSE_FIXTURE_SIZE: u64 = 8 * 1024 * 1024;
+#[cfg(unix)]
+const SPARSE_DATA_OFFSETS: [u64; 3] = [0, 3 * 1024 * 1024, SPARSE_FIXTURE_SIZE - SPARSE_DATA_LEN as u64];
+#[cfg(unix)]
+const SPARSE_DATA_LEN: usize = 64 * 1024;
+#[cfg(unix)]
+const SPARSE_CHUNK_SIZE: usize = 1024 * 1024;
+#[cfg(unix)]
+const SPARSE_BLOCK_SLACK: u64 = 16;
+
+// Length-overflow check: input size, and the stream length past which its backend returns
+// more than MAX_SEGMENT_LEN bytes
+const OVERFLOW_FIXTURE_SIZE: usize = 64 * 1024;
+const OVERFLOW_STREAM_LEN: usize = 16 * 1024;
+
+// Progress check: a solid archive of this size, decoded with a progress hook, which must report
+// at most once per PROGRESS_MIN_STEP compressed bytes (not once per row)
+const PROGRESS_FIXTURE_SIZE: usize = 4 * 1024 * 1024;
+const PROGRESS_MIN_STEP: usize = 1024;
+
+// The strict verification test of tests/cli.rs runs this binary as the 7-Zip of cast -v
+// --strict-verify, with this variable set to how it should behave (see `fake_seven_zip`)
+const FAKE_7Z_ENV: &str = "CAST_ROUNDTRIP_FAKE_7Z";
+
+// Encoded output check: the fixture, in chunks of this size, restored through each of these
+// encodings
+const ENCODED_FIXTURE_SIZE: usize = 512 * 1024;
+const ENCODED_CHUNK_SIZE: usize = 64 * 1024;
+const OUTPUT_ENCODINGS: [OutputEncoding; 2] = [OutputEncoding::Gzip, OutputEncoding::Zstd];
+
+// Cancellation check: fixture size, chunk size and the chunk whose backend cancels the token,
+// and the first chunk of the restore cancelled in its second chunk (which spans several output
+// flushes)
+const CANCEL_FIXTURE_SIZE: usize = 4 * 1024 * 1024;
+const CANCEL_CHUNK_SIZE: usize = 64 * 1024;
+const CANCEL_CHUNK: usize = 3;
+const CANCEL_FIRST_CHUNK: usize = 256 * 1024;
+
+// Heap a block whose registry declares more columns than its vars stream holds may take
+// before it is rejected, beyond that of restoring the intact block: per byte the registry
+// grew (its copy; an index entry per placeholder would take more)
+const CRAFTED_REGISTRY_HEAP_PER_BYTE: usize = 2;
+
+// Keys of the authentication check: the one the archives are written with, and another
+const AUTH_KEY: &[u8] = b"roundtrip-check key";
+const WRONG_KEY: &[u8] = b"roundtrip-check other key";
+
+// Live heap bytes and their peak since the last reset, for the strategy sample check
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() { count_alloc(layout.size()); }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            count_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn count_alloc(size: usize) {
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Ok(behaviour) = env::var(FAKE_7Z_ENV) {
+        std::process::exit(fake_seven_zip(&behaviour, &args[1..]));
+    }
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print_usage();
+        return;
+    }
+    if let Some(unknown) = cli::unknown_option(Binary::RoundtripCheck, &args[1..]) {
+        eprintln!("[!]  Error: Unknown option '{}' (see --help).", unknown);
+        std::process::exit(1);
+    }
+
+    let mut fixtures: Vec<(String, Vec<u8>)> = ADVERSARIAL_FIXTURES.iter()
+        .map(|(name, _, build)| (name.to_string(), build()))
+        .collect();
+    for profile in Profile::ALL {
+        fixtures.push((format!("generate:{}", profile.name()), generate_vec(profile, PROFILE_FIXTURE_SIZE, DEFAULT_SEED)));
+    }
+
+    println!("\n[*]  Round-trip check");
+    println!("       Fixtures:    {} ({} adversarial, {} generated)", fixtures.len(), ADVERSARIAL_FIXTURES.len(), Profile::ALL.len());
+    println!("       Settings:    {} transform(s) x {} chunking(s) x {} backend(s), plus deduplication, authentication, crafted registries, corrupted cell counts, compressor reuse, id stream transforms, split layout determinism, column access and random access", TRANSFORMS.len(), CHUNKINGS.len(), BACKENDS.len());
+    println!("       Golden:      a version {} and a version {} archive of the same file", LEGACY_VERSION, FORMAT_VERSION);
+    println!("       Strategy:    a minified JSON sample under a {} byte heap bound, plus the mode of {} profile(s)", STRATEGY_MEMORY_BOUND, PROFILE_MODES.len());
+    println!("       Layout:      a {} column template and values of {} bytes, sampled within {} cells and {} bytes", WIDE_COLUMNS, HUGE_VALUE_LEN, LAYOUT_SAMPLE_CELLS, LAYOUT_SAMPLE_BYTES);
+    println!("       Restore:     {} threads writing groups of {} chunks in place and through temp parts", RESTORE_THREADS, RESTORE_GROUP_CHUNKS);
+    println!("       Panic:       a backend panicking on chunk {}", PANIC_CHUNK);
+    println!("       Bytes:       every byte value at the start, middle and end of a cell and as the whole cell, in {} and {} blocks", BYTE_CELL_LAYOUTS[0].0, BYTE_CELL_LAYOUTS[1].0);
+    #[cfg(feature = "async")]
+    println!("       Async:       {}KB chunks from a {}KB pipe on a single-thread runtime, which must tick {} times during each backend call", ASYNC_CHUNK_SIZE / 1024, ASYNC_PIPE_SIZE / 1024, ASYNC_TICKS);
+    #[cfg(unix)]
+    println!("       Sparse:      a {}MB file with {} data regions of {}KB, restored with holes", SPARSE_FIXTURE_SIZE / (1024 * 1024), SPARSE_DATA_OFFSETS.len(), SPARSE_DATA_LEN / 1024);
+    println!("       Overflow:    a backend returning {} bytes for streams over {}KB, on {}KB of input", MAX_SEGMENT_LEN + 1, OVERFLOW_STREAM_LEN / 1024, OVERFLOW_FIXTURE_SIZE / 1024);
+    println!("       Encoding:    {}KB restored as {}", ENCODED_FIXTURE_SIZE / 1024, OUTPUT_ENCODINGS.iter().map(|e| e.label()).collect::<Vec<_>>().join(" and "));
+    println!("       Progress:    a {}MB solid chunk decoded with a progress hook", PROGRESS_FIXTURE_SIZE / (1024 * 1024));
+    println!("       Cancel:      a token cancelled in chunk {} of {}KB chunks and partway through a restored chunk, with both --on-cancel policies", CANCEL_CHUNK, CANCEL_CHUNK_SIZE / 1024);
+    println!("       Row order:   a checksummed archive with two chunks swapped and with one left out");
+    println!("       Blocks:      an archive mixing {}KB and {}KB LZMA dictionaries, the pass-through backend, a duplicate and a passthrough chunk", BLOCK_DICTS[0] / 1024, BLOCK_DICTS[1] / 1024);
+    println!("       Parser:      variable length caps of {} and {} bytes", DEFAULT_MAX_VAR_LEN, PARSER_CHECK_MAX_VAR_LEN);
+
+    let start = Instant::now();
+    let mut runs = 0;
+    let mut failures = Vec::new();
+    for (fixture, data) in &fixtures {
+        for (transform, column_codecs, front_coding) in TRANSFORMS {
+            for (chunking, chunk_size) in CHUNKINGS {
+                for backend in BACKENDS {
+                    runs += 1;
+                    let result = match backend {
+                        "lzma" => round_trip(data, chunk_size, column_codecs, front_coding, &LzmaBackend::new(false, DICT_SIZE), LzmaDecompressorBackend::new()),
+                        _ => round_trip(data, chunk_size, column_codecs, front_coding, &IdentityBackend, IdentityBackend),
+                    };
+                    if let Err(e) = result {
+                        failures.push(format!("{} / {} / {} / {}: {}", fixture, transform, chunking, backend, e));
+                    }
+                }
+            }
+        }
+        runs += 1;
+        let result = dedup_round_trip(data, &LzmaBackend::new(false, DICT_SIZE), LzmaDecompressorBackend::new()).and_then(|(plain, deduped)| {
+            match DEDUP_FIXTURES.contains(&fixture.as_str()) && deduped >= plain {
+                true => Err(format!("archive of {} bytes, {} without deduplication", deduped, plain)),
+                false => Ok(()),
+            }
+        });
+        if let Err(e) = result {
+            failures.push(format!("{} / deduplication / 4KB chunks / lzma: {}", fixture, e));
+        }
+        runs += 1;
+        if let Err(e) = auth_round_trip(data, &LzmaBackend::new(false, DICT_SIZE), LzmaDecompressorBackend::new()) {
+            failures.push(format!("{} / authentication / 4KB chunks / lzma: {}", fixture, e));
+        }
+        runs += 1;
+        if let Err(e) = crafted_registry_check(data) {
+            failures.push(format!("{} / crafted registry / solid / identity: {}", fixture, e));
+        }
+        runs += 1;
+        if let Err(e) = cell_count_check(data) {
+            failures.push(format!("{} / corrupted cell counts / solid / identity: {}", fixture, e));
+        }
+        runs += 1;
+        if let Err(e) = reuse_round_trip(data) {
+            failures.push(format!("{} / compressor reuse / 4KB chunks / identity: {}", fixture, e));
+        }
+        runs += 1;
+        if let Err(e) = ids_transform_check(data) {
+            failures.push(format!("{} / id stream transforms / solid: {}", fixture, e));
+        }
+        runs += 1;
+        if let Err(e) = split_determinism_check(data) {
+            failures.push(format!("{} / split layout determinism / solid / lzma: {}", fixture, e));
+        }
+        runs += 1;
+        if let Err(e) = columns_check(data) {
+            failures.push(format!("{} / column access / solid / identity: {}", fixture, e));
+        }
+        runs += 1;
+        if let Err(e) = random_access_check(data) {
+            failures.push(format!("{} / random access / solid / identity: {}", fixture, e));
+        }
+    }
+    runs += 1;
+    if let Err(e) = binary_guard_check() {
+        failures.push(format!("binary guard / {} fixtures / solid / identity: {}", BINARY_GUARD_CASES.len(), e));
+    }
+    runs += 1;
+    if let Err(e) = every_flag_check() {
+        failures.push(format!("every-flag / every id_flag bit / solid / lzma: {}", e));
+    }
+    for (name, line, has_vars) in SINGLE_TEMPLATE_LINES {
+        for rows in SINGLE_TEMPLATE_ROWS {
+            runs += 1;
+            if let Err(e) = single_template_check(line, has_vars, rows) {
+                failures.push(format!("single template {} / {} row(s) / compact and legacy / identity: {}", name, rows, e));
+            }
+        }
+    }
+    runs += 1;
+    if let Err(e) = narrow_ids_memory_check() {
+        failures.push(format!("short-lines / u8 id stream memory / solid / identity: {}", e));
+    }
+    runs += 1;
+    if let Err(e) = wide_ids_check() {
+        failures.push(format!("distinct-templates / u32 ids / solid / identity: {}", e));
+    }
+    runs += 1;
+    if let Err(e) = strategy_sample_check() {
+        failures.push(format!("strategy sample: {}", e));
+    }
+    for (name, build) in [("wide-columns", wide_columns as fn() -> Vec<u8>), ("huge-values", huge_values)] {
+        runs += 1;
+        if let Err(e) = layout_sample_check(&build()) {
+            failures.push(format!("{} / layout sample / solid: {}", name, e));
+        }
+    }
+    for in_place in [true, false] {
+        runs += 1;
+        if let Err(e) = positioned_restore_check(&fixtures[ADVERSARIAL_FIXTURES.len()].1, in_place) {
+            failures.push(format!("{} / positioned restore / {} / identity: {}", fixtures[ADVERSARIAL_FIXTURES.len()].0, if in_place { "in place" } else { "temp parts" }, e));
+        }
+    }
+    runs += 1;
+    if let Err(e) = panic_check(&fixtures[ADVERSARIAL_FIXTURES.len()].1) {
+        failures.push(format!("{} / backend panic at chunk {} / 4KB chunks / lzma: {}", fixtures[ADVERSARIAL_FIXTURES.len()].0, PANIC_CHUNK, e));
+    }
+    for (layout, rows) in BYTE_CELL_LAYOUTS {
+        runs += 1;
+        if let Err(e) = byte_cells_check(layout, rows) {
+            failures.push(format!("byte values 0x00-0xFF / cells / {} / identity: {}", layout.to_lowercase(), e));
+        }
+    }
+    #[cfg(feature = "async")]
+    {
+        runs += 1;
+        if let Err(e) = bom_check() {
+            failures.push(format!("byte order mark / {} fixtures / async stream / lzma: {}", BOM_FIXTURES.len(), e));
+        }
+        runs += 1;
+        if let Err(e) = async_stream_check(&fixtures[ADVERSARIAL_FIXTURES.len()].1) {
+            failures.push(format!("{} / async streams / {}KB chunks / lzma: {}", fixtures[ADVERSARIAL_FIXTURES.len()].0, ASYNC_CHUNK_SIZE / 1024, e));
+        }
+    }
+    #[cfg(unix)]
+    {
+        runs += 1;
+        if let Err(e) = sparse_check() {
+            failures.push(format!("sparse file / passthrough / {}KB chunks / lzma: {}", SPARSE_CHUNK_SIZE / 1024, e));
+        }
+    }
+    runs += 1;
+    if let Err(e) = length_overflow_check(&fixtures[ADVERSARIAL_FIXTURES.len()].1[..OVERFLOW_FIXTURE_SIZE]) {
+        failures.push(format!("{} / segments past 4GB / solid / oversized: {}", fixtures[ADVERSARIAL_FIXTURES.len()].0, e));
+    }
+    runs += 1;
+    if let Err(e) = encoded_output_check() {
+        failures.push(format!("{} / output encodings / {}KB chunks / lzma: {}", Profile::ALL[1].name(), ENCODED_CHUNK_SIZE / 1024, e));
+    }
+    runs += 1;
+    if let Err(e) = progress_check() {
+        failures.push(format!("progress / solid / {}MB / lzma: {}", PROGRESS_FIXTURE_SIZE / (1024 * 1024), e));
+    }
+    runs += 1;
+    if let Err(e) = cancellation_check() {
+        failures.push(format!("cancellation / compression at chunk {} and restore / keep and delete: {}", CANCEL_CHUNK, e));
+    }
+    runs += 1;
+    if let Err(e) = parser_config_check(&fixtures[ADVERSARIAL_FIXTURES.len()].1) {
+        failures.push(format!("{} / parser configuration / 4KB chunks / identity: {}", fixtures[ADVERSARIAL_FIXTURES.len()].0, e));
+    }
+    runs += 1;
+    if let Err(e) = row_order_check(&fixtures[ADVERSARIAL_FIXTURES.len()].1) {
+        failures.push(format!("{} / row order / 4KB chunks / identity: {}", fixtures[ADVERSARIAL_FIXTURES.len()].0, e));
+    }
+    runs += 1;
+    if let Err(e) = block_settings_check(&fixtures[ADVERSARIAL_FIXTURES.len()].1) {
+        failures.push(format!("{} / block settings / {}KB chunks / lzma and identity: {}", fixtures[ADVERSARIAL_FIXTURES.len()].0, BLOCK_CHUNK_SIZE / 1024, e));
+    }
+    runs += 1;
+    if let Err(e) = legacy_golden_check() {
+        failures.push(format!("legacy golden / version 1 without header / {} chunks / lzma: {}", LEGACY_GOLDEN_CHUNKS, e));
+    }
+    runs += 1;
+    if let Err(e) = current_golden_check() {
+        failures.push(format!("current golden / version {} / lzma: {}", FORMAT_VERSION, e));
+    }
+    println!("       Time:        {:.2}s", start.elapsed().as_secs_f64());
+
+    if !failures.is_empty() {
+        println!("\n[!]  {} of {} round trip(s) failed:", failures.len(), runs);
+        for failure in &failures { println!("       {}", failure); }
+        std::process::exit(1);
+    }
+    println!("\n[+]  All {} round trips restored the original.", runs);
+}
+
+/// Compresses `data` as `cast -c` would with these settings, decodes every chunk and compares
+/// the result with `data`.
+fn round_trip<C: NativeCompressor, D: NativeDecompressor>(data: &[u8], chunk_size: Option<usize>, column_codecs: bool, front_coding: bool, compressor: &C, decompressor: D) -> Result<(), String> {
+    let decompressor = CASTDecompressor::new(decompressor);
+    let header = ArchiveHeader::new(ChecksumKind::Crc32).with_original_size(data.len() as u64);
+    let mut archive_len = header.encoded_len();
+    let mut restored = Vec::with_capacity(data.len());
+    let mut chunker = InputChunker::new(data, chunk_size, data.len());
+    let mut chunk_idx = 0;
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? {
+        let encoded = encode_chunk(compressor, chunk, ChecksumKind::Crc32, column_codecs, front_coding, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+        archive_len += encoded.parts.iter().map(|p| p.len()).sum::<usize>();
+        // parts holds header, reg, ids and vars of each (sub-)chunk
+        for ((_, header), segments) in encoded.chunks.iter().zip(encoded.parts.chunks(4)) {
+            chunk_idx += 1;
+            let chunk = Chunk::new(*header, segments[1..].concat(), FORMAT_VERSION);
+            decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(header.crc), header.id_flag, &mut restored)
+                .map_err(|e| format!("chunk {}: {}", chunk_idx, e))?;
+        }
+    }
+    if restored != data {
+        let at = restored.iter().zip(data).position(|(a, b)| a != b).unwrap_or(restored.len().min(data.len()));
+        return Err(format!("restored {} bytes instead of {}, first difference at byte {}", restored.len(), data.len(), at));
+    }
+    check_size_bound(data.len(), chunk_size, &header, compressor, archive_len)
+}
+
+/// Fails when an archive of `archive_len` bytes exceeds `max_compressed_size` for its input.
+fn check_size_bound<C: NativeCompressor>(input_len: usize, chunk_size: Option<usize>, header: &ArchiveHeader, compressor: &C, archive_len: usize) -> Result<(), String> {
+    match max_compressed_size(input_len, chunk_size, header, compressor) {
+        Some(bound) if archive_len > bound => Err(format!("archive of {} bytes exceeds the size bound of {} bytes", archive_len, bound)),
+        _ => Ok(()),
+    }
+}
+
+/// Writes `data` as a 4KB-chunk archive the way `cast -c --dedup-chunks` does, reads it back
+/// through `ChunkReader` (resolving duplicate chunks) and compares the result with `data`.
+/// Returns the archive size without and with deduplication.
+fn dedup_round_trip<C: NativeCompressor, D: NativeDecompressor>(data: &[u8], compressor: &C, decompressor: D) -> Result<(usize, usize), String> {
+    let mut archive = Vec::new();
+    ArchiveHeader::new(ChecksumKind::Crc32).write_to(&mut archive).map_err(|e| e.to_string())?;
+    let mut plain_len = archive.len();
+    let mut dedup = ChunkDedup::default();
+    let (mut offset, mut archive_chunks) = (0, 0);
+    let mut chunker = InputChunker::new(data, Some(SMALL_CHUNK_SIZE), data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? {
+        let key = ChunkDedup::key(chunk);
+        let encoded = encode_chunk(compressor, chunk, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+        plain_len += encoded.parts.iter().map(|p| p.len()).sum::<usize>();
+        let encoded = match dedup.get(&key) {
+            Some(target) if &data[target.data_offset as usize..][..chunk.len()] == chunk => encode_duplicate(chunk, ChecksumKind::Crc32, &target),
+            _ => encoded,
+        };
+        if let [(_, header)] = encoded.chunks.as_slice() {
+            if header.id_flag != ID_FLAG_DUPLICATE {
+                dedup.insert(key, DedupTarget { chunk: archive_chunks + 1, data_offset: offset as u64, compressed_len: (CHUNK_HEADER_SIZE + header.body_len()) as u64 });
+            }
+        }
+        archive_chunks += encoded.chunks.len();
+        offset += chunk.len();
+        for part in &encoded.parts { archive.extend_from_slice(part); }
+    }
+
+    let decompressor = CASTDecompressor::new(decompressor);
+    let mut reader = ChunkReader::new(Cursor::new(&archive)).map_err(|e| e.to_string())?;
+    let mut restored = Vec::with_capacity(data.len());
+    let mut chunk_idx = 0;
+    while let Some(chunk) = reader.next_resolved().map_err(|e| format!("chunk {}: {}", chunk_idx + 1, e))? {
+        chunk_idx += 1;
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(chunk.header.crc), chunk.header.id_flag, &mut restored)
+            .map_err(|e| format!("chunk {}: {}", chunk_idx, e))?;
+    }
+    if restored != data {
+        let at = restored.iter().zip(data).position(|(a, b)| a != b).unwrap_or(restored.len().min(data.len()));
+        return Err(format!("restored {} bytes instead of {}, first difference at byte {}", restored.len(), data.len(), at));
+    }
+    Ok((plain_len, archive.len()))
+}
+
+/// Writes `data` as a 4KB-chunk archive the way `cast -c --hmac-key-file` does and checks that
+/// it restores without the key, authenticates with it, reports a wrong key as such, and fails
+/// at chunk 1 once that chunk is replaced by a re-encoded, modified copy (CRC fixed up).
+fn auth_round_trip<C: NativeCompressor, D: NativeDecompressor>(data: &[u8], compressor: &C, decompressor: D) -> Result<(), String> {
+    let key = MacKey::new(AUTH_KEY);
+    let header = ArchiveHeader::new(ChecksumKind::Crc32).with_original_size(data.len() as u64).with_authentication(true);
+    let mut archive = header.to_bytes();
+    // Archive range of the first chunk and the input it holds
+    let mut first: Option<(std::ops::Range<usize>, Vec<u8>)> = None;
+    let mut chunk_macs: Vec<Mac> = Vec::new();
+    let mut chunker = InputChunker::new(data, Some(SMALL_CHUNK_SIZE), data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? {
+        let encoded = encode_chunk(compressor, chunk, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+        let start = archive.len();
+        for part in &encoded.parts { archive.extend_from_slice(part); }
+        if first.is_none() { first = Some((start..archive.len(), chunk.to_vec())); }
+        chunk_macs.push(key.chunk_mac(chunk_macs.len() as u64 + 1, chunk));
+    }
+    for part in key.trailer(&header.to_bytes(), chunk_macs).to_parts() { archive.extend_from_slice(&part); }
+    check_size_bound(data.len(), Some(SMALL_CHUNK_SIZE), &header, compressor, archive.len())?;
+
+    let decompressor = CASTDecompressor::new(decompressor);
+    // Restores the archive without the key, then authenticates it with `key`
+    let check = |archive: &[u8], key: &MacKey| -> Result<Result<(), AuthFailure>, String> {
+        let mut reader = ChunkReader::new(Cursor::new(archive)).map_err(|e| e.to_string())?;
+        let mut restored = Vec::with_capacity(data.len());
+        let mut macs = Vec::new();
+        while let Some(chunk) = reader.next_chunk().map_err(|e| format!("chunk {}: {}", macs.len() + 1, e))? {
+            let start = restored.len();
+            decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(chunk.header.crc), chunk.header.id_flag, &mut restored)
+                .map_err(|e| format!("chunk {}: {}", macs.len() + 1, e))?;
+            macs.push(key.chunk_mac(macs.len() as u64 + 1, &restored[start..]));
+        }
+        let trailer = reader.mac_trailer().ok_or("no authentication trailer read")?;
+        let trailer = MacTrailer::from_chunk(trailer, macs.len() + 1).map_err(|e| e.to_string())?;
+        Ok(trailer.check(key, &reader.archive_header().to_bytes(), &macs))
+    };
+
+    check(&archive, &key)?.map_err(|e| format!("with the key: {}", e))?;
+    match check(&archive, &MacKey::new(WRONG_KEY))? {
+        Err(AuthFailure::WrongKey) => {},
+        other => return Err(format!("with a wrong key: expected a wrong-key error, got {:?}", other)),
+    }
+
+    let Some((range, mut modified)) = first else { return Ok(()) };
+    modified[0] = if modified[0] == b'#' { b'@' } else { b'#' };
+    let encoded = encode_chunk(compressor, &modified, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+    let mut tampered = archive[..range.start].to_vec();
+    for part in &encoded.parts { tampered.extend_from_slice(part); }
+    tampered.extend_from_slice(&archive[range.end..]);
+    match check(&tampered, &key)? {
+        Err(AuthFailure::Chunk(1)) => Ok(()),
+        other => Err(format!("tampered chunk 1: expected an HMAC mismatch at chunk 1, got {:?}", other)),
+    }
+}
+
+/// Writes `data` as one block through the pass-through backend and, when it has the split
+/// layout (a registry of its own), appends placeholders to its first skeleton: as many as
+/// the vars stream has bytes plus one, then just one (a column more than the stream holds).
+/// Restoring and listing the columns must fail with the length error, not panic, and take
+/// no more heap than restoring the intact block plus `CRAFTED_REGISTRY_HEAP_PER_BYTE` per
+/// byte the registry grew.
+fn crafted_registry_check(data: &[u8]) -> Result<(), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let (reg, ids, vars, id_flag, _) = CASTCompressor::new(IdentityBackend).compress(data).map_err(|e| format!("compression: {}", e))?;
+    if decompressor.inspect(&reg, &ids, &vars, id_flag).map_err(|e| e.to_string())?.layout != "Split" { return Ok(()); }
+    let live_before = LIVE_BYTES.load(Ordering::Relaxed);
+    PEAK_BYTES.store(live_before, Ordering::Relaxed);
+    decompressor.decompress(&reg, &ids, &vars, None, id_flag, &mut std::io::sink()).map_err(|e| format!("intact block: {}", e))?;
+    let intact_peak = PEAK_BYTES.load(Ordering::Relaxed).saturating_sub(live_before);
+    let registry = std::str::from_utf8(&reg).map_err(|_| "the registry is not UTF-8".to_string())?;
+    let first_end = registry.find('\u{E001}').unwrap_or(registry.len());
+    for (what, extra) in [("placeholders past the vars bytes", vars.len() + 1), ("one column past the vars stream", 1)] {
+        let crafted = format!("{}{}{}", &registry[..first_end], "\u{E000}".repeat(extra), &registry[first_end..]);
+        let bound = intact_peak + CRAFTED_REGISTRY_HEAP_PER_BYTE * (crafted.len() - reg.len());
+        let live_before = LIVE_BYTES.load(Ordering::Relaxed);
+        PEAK_BYTES.store(live_before, Ordering::Relaxed);
+        let results = catch_panics(|| Ok((
+            decompressor.decompress(crafted.as_bytes(), &ids, &vars, None, id_flag, &mut std::io::sink()),
+            decompressor.columns(crafted.as_bytes(), &ids, &vars, id_flag).map(|_| ()),
+        )));
+        let peak = PEAK_BYTES.load(Ordering::Relaxed).saturating_sub(live_before);
+        let (restored, columns) = results.map_err(|e| format!("{}: {}", what, e))?;
+        for (call, result) in [("decompress", restored), ("columns", columns)] {
+            match result {
+                Err(CastError::LengthOverflow { detail, .. }) if detail.contains("more columns than the vars stream holds") => {},
+                Err(e) => return Err(format!("{}: {} failed with: {}", what, call, e)),
+                Ok(()) => return Err(format!("{}: {} accepted the registry", what, call)),
+            }
+        }
+        if peak > bound { return Err(format!("{}: {} bytes of heap before the rejection, more than {}", what, peak, bound)); }
+    }
+    Ok(())
+}
+
+/// Writes `data` as one chunk through the pass-through backend, flips the lowest bit of the
+/// expected cell count of its first column (the stored count, or the row count of a compact
+/// block) and checks that decoding reports exactly that mismatch. Blocks without columns and
+/// passthrough chunks have nothing to check.
+fn cell_count_check(data: &[u8]) -> Result<(), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let encoded = encode_chunk(&IdentityBackend, data, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+    for (chunk_idx, ((_, header), segments)) in encoded.chunks.iter().zip(encoded.parts.chunks(4)).enumerate() {
+        let chunk = Chunk::new(*header, segments[1..].concat(), FORMAT_VERSION);
+        let (reg, ids, vars) = (chunk.reg(), chunk.ids(), chunk.vars());
+        let columns = decompressor.columns(reg, ids, vars, header.id_flag).map_err(|e| format!("chunk {}: {}", chunk_idx + 1, e))?;
+        if !columns.is_some_and(|templates| templates.iter().any(|t| !t.columns.is_empty())) { continue; }
+
+        let layout = decompressor.inspect(reg, ids, vars, header.id_flag).map_err(|e| format!("chunk {}: {}", chunk_idx + 1, e))?;
+        // The pass-through backend stores the block (or, split, the vars region) as is
+        let (at, expected) = match layout.layout {
+            "Compact" => (layout.fields[0].1.start, layout.fields[0].2),
+            "Unified" => (layout.fields.last().map_or(0, |f| f.1.end) + layout.reg_len + layout.ids_len + varint_len(layout.cell_counts.len() as u64), layout.cell_counts[0]),
+            "Split" => (varint_len(layout.cell_counts.len() as u64), layout.cell_counts[0]),
+            other => return Err(format!("chunk {}: unexpected {} layout", chunk_idx + 1, other)),
+        };
+        let mut corrupted = vars.to_vec();
+        corrupted[at] ^= 1;
+        let wanted = format!("expected {} cells, found {}", expected ^ 1, expected);
+        match decompressor.decompress(reg, ids, &corrupted, Some(header.crc), header.id_flag, &mut std::io::sink()) {
+            Err(e) if e.to_string().contains(&wanted) => {},
+            Err(e) => return Err(format!("chunk {}: expected \"{}\", got: {}", chunk_idx + 1, wanted, e)),
+            Ok(()) => return Err(format!("chunk {}: a wrong cell count was not detected", chunk_idx + 1)),
+        }
+    }
+    Ok(())
+}
+
+/// Compresses the 4KB chunks of `data` with one compressor, forward and then backward (so each
+/// chunk follows a different one the second time), and checks every block against the one a
+/// fresh compressor writes for that chunk and against the chunk itself once decoded.
+fn reuse_round_trip(data: &[u8]) -> Result<(), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let new_compressor = || CASTCompressor::new(IdentityBackend).with_column_codecs(true).with_front_coded_registry(true);
+    let mut chunks = Vec::new();
+    let mut chunker = InputChunker::new(data, Some(SMALL_CHUNK_SIZE), data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? { chunks.push(chunk.to_vec()); }
+
+    let mut reused = new_compressor();
+    let order = (0..chunks.len()).chain((0..chunks.len()).rev());
+    for chunk_idx in order {
+        let chunk = &chunks[chunk_idx];
+        let (reg, ids, vars, id_flag, _) = reused.compress(chunk).map_err(|e| format!("chunk {}: compression: {}", chunk_idx + 1, e))?;
+        let fresh = new_compressor().compress(chunk).map_err(|e| format!("chunk {}: compression: {}", chunk_idx + 1, e))?;
+        if (&reg, &ids, &vars, id_flag) != (&fresh.0, &fresh.1, &fresh.2, fresh.3) {
+            return Err(format!("chunk {}: the reused compressor wrote a different block than a fresh one", chunk_idx + 1));
+        }
+        let mut restored = Vec::with_capacity(chunk.len());
+        decompressor.decompress(&reg, &ids, &vars, None, id_flag, &mut restored).map_err(|e| format!("chunk {}: {}", chunk_idx + 1, e))?;
+        if restored != *chunk {
+            return Err(format!("chunk {}: restored {} bytes that differ from the chunk", chunk_idx + 1, restored.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Compresses `data` solid with each id stream transform forced (pass-through backend), checks
+/// that the block reports it and restores `data`, then checks that the LZMA id stream the
+/// compressor picks a transform for is no larger than the one without.
+fn ids_transform_check(data: &[u8]) -> Result<(), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    for transform in [IdsTransform::MoveToFront, IdsTransform::Delta] {
+        let (reg, ids, vars, id_flag, _) = CASTCompressor::new(IdentityBackend).with_ids_transform(Some(transform)).compress(data)
+            .map_err(|e| format!("{}: compression: {}", transform.name(), e))?;
+        let layout = decompressor.inspect(&reg, &ids, &vars, id_flag).map_err(|e| format!("{}: {}", transform.name(), e))?;
+        // Single-template and passthrough blocks have no id stream to transform, and
+        // move-to-front skips u32 ids
+        let expected = if layout.ids_len > 0 && transform.supports(id_flag) { transform } else { IdsTransform::None };
+        if layout.ids_transform != expected {
+            return Err(format!("{} forced, the block reports {}", transform.name(), layout.ids_transform.name()));
+        }
+        let mut restored = Vec::with_capacity(data.len());
+        decompressor.decompress(&reg, &ids, &vars, None, id_flag, &mut restored).map_err(|e| format!("{}: {}", transform.name(), e))?;
+        if restored != data {
+            return Err(format!("{}: restored {} bytes that differ from the original", transform.name(), restored.len()));
+        }
+    }
+    let ids_len = |transform: Option<IdsTransform>| CASTCompressor::new(LzmaBackend::new(false, DICT_SIZE)).with_ids_transform(transform)
+        .compress(data).map(|chunk| chunk.1.len()).map_err(|e| format!("compression: {}", e));
+    let (picked, untransformed) = (ids_len(None)?, ids_len(Some(IdsTransform::None))?);
+    if picked > untransformed {
+        return Err(format!("the picked transform stores {} id bytes, {} without one", picked, untransformed));
+    }
+    Ok(())
+}
+
+/// Compresses `data` solid twice with the split layout forced (LZMA, a fresh compressor each
+/// time) and checks that both blocks are the same bytes and restore `data`. Blocks the override
+/// does not apply to (one template, 256 or more, passthrough) must equal the unified ones.
+fn split_determinism_check(data: &[u8]) -> Result<(), String> {
+    let compress = |split: bool| CASTCompressor::new(LzmaBackend::new(false, DICT_SIZE)).with_split_layout(Some(split)).compress(data)
+        .map(|(reg, ids, vars, id_flag, _)| (reg, ids, vars, id_flag)).map_err(|e| format!("compression: {}", e));
+    let (first, second) = (compress(true)?, compress(true)?);
+    if first != second {
+        return Err(format!("two compressions wrote blocks of {} and {} bytes that differ", first.0.len() + first.1.len() + first.2.len(), second.0.len() + second.1.len() + second.2.len()));
+    }
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new());
+    let (reg, ids, vars, id_flag) = &first;
+    if decompressor.inspect(reg, ids, vars, *id_flag).map_err(|e| e.to_string())?.layout != "Split" && compress(false)? != first {
+        return Err("the split layout was not used, and the block differs from the unified one".to_string());
+    }
+    let mut restored = Vec::with_capacity(data.len());
+    decompressor.decompress(reg, ids, vars, None, *id_flag, &mut restored).map_err(|e| e.to_string())?;
+    if restored != data {
+        return Err(format!("restored {} bytes that differ from the original", restored.len()));
+    }
+    Ok(())
+}
+
+/// Compresses `data` solid (pass-through backend) and reads it back the ways a partial restore
+/// does: the first row, half of the rows, all of them and a third of the bytes must be prefixes
+/// of `data`, and the last byte must be traced to the last row.
+fn random_access_check(data: &[u8]) -> Result<(), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let (reg, ids, vars, id_flag, _) = CASTCompressor::new(IdentityBackend).compress(data).map_err(|e| format!("compression: {}", e))?;
+    let line_ends: Vec<usize> = data.iter().enumerate().filter(|&(_, &b)| b == b'\n').map(|(i, _)| i + 1).collect();
+    let rows = line_ends.len() + usize::from(data.last().is_some_and(|&b| b != b'\n'));
+    for limit in [OutputLimit::Rows(1), OutputLimit::Rows(rows as u64 / 2), OutputLimit::Rows(rows as u64), OutputLimit::Bytes(data.len() as u64 / 3)] {
+        let expected = match limit {
+            OutputLimit::Rows(0) => &data[..0],
+            OutputLimit::Rows(n) => &data[..line_ends.get(n as usize - 1).copied().unwrap_or(data.len())],
+            OutputLimit::Bytes(n) => &data[..n as usize],
+        };
+        let mut restored = Vec::new();
+        decompressor.decompress_limited(&reg, &ids, &vars, None, id_flag, &mut restored, &mut { limit }).map_err(|e| format!("{:?}: {}", limit, e))?;
+        if restored != expected {
+            return Err(format!("{:?}: restored {} bytes, expected the first {}", limit, restored.len(), expected.len()));
+        }
+    }
+    if let (Some(last), false) = (data.len().checked_sub(1), is_passthrough(id_flag)) {
+        let found = decompressor.locate(&reg, &ids, &vars, id_flag, last).map_err(|e| format!("locate: {}", e))?;
+        if found.as_ref().map(|p| p.row) != Some(rows - 1) {
+            return Err(format!("the last byte is traced to {:?}, expected row {}", found.map(|p| p.row), rows - 1));
+        }
+    }
+    Ok(())
+}
+
+/// Compresses `data` solid (pass-through backend, with and without column codecs) and rebuilds
+/// each row from `CASTDecompressor::columns`: the skeleton of its template with the cells of its
+/// row in the placeholders must give the restored chunk. Integer and Float columns must also
+/// read back through `integers()` / `floats()` as the text of their cells parses.
+fn columns_check(data: &[u8]) -> Result<(), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    for column_codecs in [false, true] {
+        let what = if column_codecs { "column codecs" } else { "plain" };
+        let (reg, ids, vars, id_flag, _) = CASTCompressor::new(IdentityBackend).with_column_codecs(column_codecs).compress(data)
+            .map_err(|e| format!("{}: compression: {}", what, e))?;
+        let Some(templates) = decompressor.columns(&reg, &ids, &vars, id_flag).map_err(|e| format!("{}: {}", what, e))? else { continue };
+        let mut restored = Vec::with_capacity(data.len());
+        decompressor.decompress(&reg, &ids, &vars, None, id_flag, &mut restored).map_err(|e| format!("{}: {}", what, e))?;
+        // Cells and skeletons are UTF-8, also in Latin-1 chunks
+        let restored = match String::from_utf8(restored) {
+            Ok(text) => text,
+            Err(e) => e.into_bytes().iter().map(|&b| b as char).collect(),
+        };
+
+        let row_count = templates.iter().map(|t| t.rows.len()).sum();
+        let mut rows = vec![None; row_count];
+        for (template_id, template) in templates.iter().enumerate() {
+            let parts: Vec<&str> = template.skeleton.split('\u{E000}').collect();
+            if parts.len() != template.columns.len() + 1 {
+                return Err(format!("{}: template {} has {} placeholders and {} columns", what, template_id, parts.len() - 1, template.columns.len()));
+            }
+            for (i, &row) in template.rows.iter().enumerate() {
+                let mut line = parts[0].to_string();
+                for (column, part) in template.columns.iter().zip(&parts[1..]) {
+                    line.push_str(&String::from_utf8_lossy(column.get(i)));
+                    line.push_str(part);
+                }
+                let slot = rows.get_mut(row).ok_or_else(|| format!("{}: template {} claims row {} of {}", what, template_id, row, row_count))?;
+                if slot.replace(line).is_some() { return Err(format!("{}: row {} belongs to two templates", what, row)); }
+            }
+            for column in &template.columns {
+                let cells = || (0..column.len()).map(|i| String::from_utf8_lossy(column.get(i)).into_owned());
+                let integers: Option<Vec<i64>> = cells().map(|c| c.parse().ok()).collect();
+                let floats: Option<Vec<u64>> = cells().map(|c| c.parse::<f64>().ok().map(f64::to_bits)).collect();
+                // A column of i64 cells is an Integer column
+                if integers.is_some() && !column.is_empty() && column.kind != ColumnKind::Integer {
+                    return Err(format!("{}: template {} column {} holds integers but is {:?}", what, template_id, column.placeholder, column.kind));
+                }
+                let (expected_integers, expected_floats) = match column.kind {
+                    ColumnKind::Integer => (integers, floats),
+                    ColumnKind::Float => (None, floats),
+                    ColumnKind::Text => (None, None),
+                };
+                if column.integers() != expected_integers || column.floats().map(|f| f.into_iter().map(f64::to_bits).collect()) != expected_floats {
+                    return Err(format!("{}: template {} column {} ({:?}) does not read back as its text parses", what, template_id, column.placeholder, column.kind));
+                }
+            }
+        }
+        let rebuilt: String = rows.into_iter().collect::<Option<Vec<_>>>().ok_or_else(|| format!("{}: a row belongs to no template", what))?.concat();
+        if rebuilt != restored {
+            let at = rebuilt.bytes().zip(restored.bytes()).position(|(a, b)| a != b).unwrap_or(rebuilt.len().min(restored.len()));
+            return Err(format!("{}: the rows rebuilt from the columns differ from the restored chunk at byte {} ({} and {} bytes)", what, at, rebuilt.len(), restored.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Compresses the short-lines fixture in one block under the counting allocator: its few
+/// templates must give u8 ids, and the block must peak under SHORT_LINES_HEAP_PER_ROW per row
+/// of heap beyond the input (the bytes of the vars buffer reserved for the input).
+fn narrow_ids_memory_check() -> Result<(), String> {
+    let data = short_lines();
+    let live_before = LIVE_BYTES.load(Ordering::Relaxed);
+    PEAK_BYTES.store(live_before, Ordering::Relaxed);
+    let (_, _, _, id_flag, _) = CASTCompressor::new(IdentityBackend).compress(&data).map_err(|e| format!("compression: {}", e))?;
+    let peak = PEAK_BYTES.load(Ordering::Relaxed).saturating_sub(live_before);
+    if !describe_id_flag(id_flag).starts_with("u8 ids") {
+        return Err(format!("stored with id_flag {:#04x} ({}), not u8 ids", id_flag, describe_id_flag(id_flag)));
+    }
+    let bound = data.len() + (SHORT_LINES_HEAP_PER_ROW * SHORT_LINES_ROWS as f64) as usize;
+    if peak > bound {
+        return Err(format!("{} bytes of heap for {} rows, more than {}", peak, SHORT_LINES_ROWS, bound));
+    }
+    Ok(())
+}
+
+/// Restores `rows` copies of `line` from a compact block (id mode 4, as compressed; built from
+/// the one-row block for 0 rows, which the compressor never writes) and from the legacy block with the same registry and vars (id
+/// mode 3: the row count in place of len_ids without vars, 0 with vars), then lists its columns
+/// for the row count. Zero rows of a template with vars only fit the legacy block: the compact
+/// one must be rejected.
+fn single_template_check(line: &str, has_vars: bool, rows: usize) -> Result<(), String> {
+    let data = line.repeat(rows);
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let (reg, ids, vars, id_flag, _) = CASTCompressor::new(IdentityBackend).compress(line.repeat(rows.max(1)).as_bytes()).map_err(|e| format!("compression: {}", e))?;
+    if id_flag != 4 {
+        return Err(format!("stored with id_flag {:#04x} ({}), not compact", id_flag, describe_id_flag(id_flag)));
+    }
+    let layout = decompressor.inspect(&reg, &ids, &vars, id_flag).map_err(|e| e.to_string())?;
+    let head_len = layout.fields.last().map_or(0, |f| f.1.end);
+    let registry = &layout.block[head_len..head_len + layout.reg_len];
+    let columns = std::str::from_utf8(registry).map_err(|_| "the registry is not UTF-8".to_string())?.matches('\u{E000}').count();
+    if (columns > 0) != has_vars {
+        return Err(format!("{} column(s) in the template of {:?}", columns, line));
+    }
+    // Zero rows: the one-row registry (shorter than 128 bytes, a one-byte varint) and one empty
+    // column per placeholder
+    let (compact, cells) = match rows {
+        0 => ([&[0, registry.len() as u8], registry, &vec![0x02; columns][..]].concat(), vec![0x02; columns]),
+        _ => (layout.block.clone(), layout.block[head_len + layout.reg_len..].to_vec()),
+    };
+    let stored_rows = if columns == 0 { rows as u32 } else { 0 };
+    let legacy = [&(registry.len() as u32).to_le_bytes()[..], &stored_rows.to_le_bytes(), registry, &cells].concat();
+    for (what, block, flag) in [("compact", compact, 4), ("legacy", legacy, 3)] {
+        let mut restored = Vec::new();
+        match decompressor.decompress(&[], &[], &block, None, flag, &mut restored) {
+            Err(CastError::CorruptHeader { .. }) if what == "compact" && rows == 0 && columns > 0 => {},
+            Err(e) => return Err(format!("{} block: {}", what, e)),
+            Ok(()) if what == "compact" && rows == 0 && columns > 0 => return Err("compact block: zero rows with a column accepted".to_string()),
+            Ok(()) if restored != data.as_bytes() => return Err(format!("{} block: restored {} bytes instead of {}", what, restored.len(), data.len())),
+            Ok(()) => {
+                let templates = decompressor.columns(&[], &[], &block, flag).map_err(|e| format!("{} block: columns: {}", what, e))?.unwrap_or_default();
+                let listed = templates.first().map_or(0, |t| t.rows.len());
+                if listed != rows { return Err(format!("{} block: columns list {} row(s)", what, listed)); }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// The distinct-templates fixture must take u32 ids in one block (or it tests nothing), keep
+/// every template, and trace rows to their template: row r uses template r % DISTINCT_TEMPLATES.
+fn wide_ids_check() -> Result<(), String> {
+    let data = distinct_templates();
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let (reg, ids, vars, id_flag, _) = CASTCompressor::new(IdentityBackend).compress(&data).map_err(|e| format!("compression: {}", e))?;
+    if !describe_id_flag(id_flag).starts_with("u32 ids") {
+        return Err(format!("stored with id_flag {:#04x} ({}), not u32 ids", id_flag, describe_id_flag(id_flag)));
+    }
+    let templates = decompressor.templates(&reg, &ids, &vars, id_flag).map_err(|e| e.to_string())?.ok_or("no templates")?;
+    if templates.skeletons.len() != DISTINCT_TEMPLATES || templates.first_rows.iter().any(Option::is_none) {
+        return Err(format!("{} template(s), expected {} all in use", templates.skeletons.len(), DISTINCT_TEMPLATES));
+    }
+    let line_len = data.iter().position(|&b| b == b'\n').map_or(1, |p| p + 1);
+    for row in [0, DISTINCT_TEMPLATES - 1, DISTINCT_TEMPLATES, DISTINCT_TEMPLATES * DISTINCT_TEMPLATE_REPEATS - 1] {
+        let found = decompressor.locate(&reg, &ids, &vars, id_flag, row * line_len).map_err(|e| format!("locate: {}", e))?
+            .ok_or_else(|| format!("row {}: not located", row))?;
+        // Equal use keeps the first-appearance order of the templates
+        if (found.row, found.template_id) != (row, row % DISTINCT_TEMPLATES) {
+            return Err(format!("row {}: traced to row {} template {}, expected template {}", row, found.row, found.template_id, row % DISTINCT_TEMPLATES));
+        }
+    }
+    Ok(())
+}
+
+/// Samples the parsing mode of a minified JSON input under the counting allocator: the sample
+/// must stop at STRATEGY_SAMPLE_BYTES and peak under STRATEGY_MEMORY_BOUND of heap. Then checks
+/// PROFILE_MODES.
+fn strategy_sample_check() -> Result<(), String> {
+    let json = minified_json();
+    let text = std::str::from_utf8(&json).map_err(|e| e.to_string())?;
+    let live_before = LIVE_BYTES.load(Ordering::Relaxed);
+    PEAK_BYTES.store(live_before, Ordering::Relaxed);
+    let sample = sample_strategy(text, DEFAULT_MAX_VAR_LEN);
+    let peak = PEAK_BYTES.load(Ordering::Relaxed) - live_before;
+    if !sample.capped || sample.lines >= MINIFIED_JSON_LINES {
+        return Err(format!("minified JSON: sampled {} lines ({} skeleton bytes) without reaching the {} byte cap", sample.lines, sample.skeleton_bytes, STRATEGY_SAMPLE_BYTES));
+    }
+    if peak > STRATEGY_MEMORY_BOUND {
+        return Err(format!("minified JSON: the sample held {} bytes of heap, more than {}", peak, STRATEGY_MEMORY_BOUND));
+    }
+    for (profile, aggressive) in PROFILE_MODES {
+        let data = generate_vec(profile, PROFILE_FIXTURE_SIZE, DEFAULT_SEED);
+        let sample = sample_strategy(std::str::from_utf8(&data).map_err(|e| e.to_string())?, DEFAULT_MAX_VAR_LEN);
+        if sample.aggressive != aggressive {
+            let mode = |aggressive: bool| if aggressive { "Aggressive" } else { "Strict" };
+            return Err(format!("generate:{}: {} mode, expected {}", profile.name(), mode(sample.aggressive), mode(aggressive)));
+        }
+    }
+    Ok(())
+}
+
+/// Compresses `data` in one chunk through each backend configuration: every one must take the
+/// same layout sample, within LAYOUT_SAMPLE_CELLS and LAYOUT_SAMPLE_BYTES and stopped by one of
+/// them, and every LZMA configuration must choose the same layout and restore `data`.
+fn layout_sample_check(data: &[u8]) -> Result<(), String> {
+    let mut backends: Vec<(&str, RuntimeLzmaCompressor)> = vec![
+        ("lzma", RuntimeLzmaCompressor::Native(LzmaBackend::new(false, DICT_SIZE))),
+        ("lzma 2 threads", RuntimeLzmaCompressor::Native(LzmaBackend::new(true, DICT_SIZE).with_forced_multithread().with_threads(2))),
+        ("lzma preset 1", RuntimeLzmaCompressor::Native(LzmaBackend::new(false, DICT_SIZE).with_preset(1))),
+    ];
+    if try_find_7zip_path().is_some() {
+        backends.push(("7-zip", RuntimeLzmaCompressor::SevenZip(SevenZipBackend::new(DICT_SIZE))));
+    }
+    let taken = |sample: LayoutSample| (sample.cells, sample.bytes, sample.capped);
+    let mut identity = CASTCompressor::new(IdentityBackend);
+    identity.compress(data).map_err(|e| format!("identity: compression: {}", e))?;
+    let expected = identity.layout_sample();
+    if !expected.probed || !expected.capped || expected.cells > LAYOUT_SAMPLE_CELLS || expected.bytes > LAYOUT_SAMPLE_BYTES {
+        return Err(format!("sample {:?} not within {} cells and {} bytes", expected, LAYOUT_SAMPLE_CELLS, LAYOUT_SAMPLE_BYTES));
+    }
+    let mut split = None;
+    for (name, backend) in &backends {
+        let mut compressor = CASTCompressor::new(backend);
+        let (reg, ids, vars, id_flag, _) = compressor.compress(data).map_err(|e| format!("{}: compression: {}", name, e))?;
+        let sample = compressor.layout_sample();
+        if taken(sample) != taken(expected) {
+            return Err(format!("{}: sampled {:?}, the pass-through backend {:?}", name, sample, expected));
+        }
+        match split {
+            Some((first, split)) if split != sample.split => {
+                let layout = |split: bool| if split { "split" } else { "unified" };
+                return Err(format!("{}: {} layout, {} with {}", name, layout(sample.split), layout(split), first));
+            }
+            None => split = Some((*name, sample.split)),
+            _ => {}
+        }
+        let mut restored = Vec::with_capacity(data.len());
+        CASTDecompressor::new(LzmaDecompressorBackend::new()).decompress(&reg, &ids, &vars, None, id_flag, &mut restored)
+            .map_err(|e| format!("{}: {}", name, e))?;
+        if restored != data {
+            return Err(format!("{}: restored {} bytes that differ from the original", name, restored.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Restores every chunk of `archive` (each must pass its CRC) and returns the recorded row
+/// order with the one of the restored chunks, combined in groups of RESTORE_GROUP_CHUNKS taken
+/// from the end.
+fn restored_row_order(archive: &[u8]) -> Result<(Option<RowOrder>, RowOrder), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let mut reader = ChunkReader::new(Cursor::new(archive)).map_err(|e| e.to_string())?;
+    let recorded = reader.archive_header().row_order();
+    let header = reader.archive_header().clone();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = reader.next_chunk().map_err(|e| e.to_string())? {
+        let mut restored = Vec::new();
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&header), chunk.header.id_flag, &mut restored)
+            .map_err(|e| format!("chunk {}: {}", chunks.len() + 1, e))?;
+        chunks.push(RowOrder::of_chunk(&restored));
+    }
+    let mut groups: Vec<RowOrder> = chunks.chunks(RESTORE_GROUP_CHUNKS).rev().map(|group| {
+        let mut order = RowOrder::default();
+        for chunk in group { order.append(chunk); }
+        order
+    }).collect();
+    groups.reverse();
+    let mut combined = RowOrder::default();
+    for group in &groups { combined.append(group); }
+    let mut sequential = RowOrder::default();
+    for chunk in &chunks { sequential.append(chunk); }
+    if combined != sequential {
+        return Err(format!("groups combine to {:?}, chunk by chunk to {:?}", combined, sequential));
+    }
+    Ok((recorded, sequential))
+}
+
+/// Writes the 4KB chunks of `data` with their row order checksum, then checks that it matches
+/// the restored chunks, and no longer does once two chunks are swapped or one is left out.
+fn row_order_check(data: &[u8]) -> Result<(), String> {
+    let mut chunks = Vec::new();
+    let mut order = RowOrder::default();
+    let mut chunker = InputChunker::new(data, Some(SMALL_CHUNK_SIZE), data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? {
+        let encoded = encode_chunk(&IdentityBackend, chunk, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+        order.append(&RowOrder::of_chunk(chunk));
+        chunks.push(encoded.parts.concat());
+    }
+    if chunks.len() < 4 {
+        return Err(format!("{} chunk(s), too few to reorder", chunks.len()));
+    }
+    if RowOrder::parse(&order.to_label()) != Some(order) {
+        return Err(format!("label {} reads as {:?}", order.to_label(), RowOrder::parse(&order.to_label())));
+    }
+    let header = ArchiveHeader::new(ChecksumKind::Crc32).with_labels(vec![(ROW_ORDER_LABEL.to_string(), order.to_label())])?;
+    let archive = |chunks: &[Vec<u8>]| -> Result<Vec<u8>, String> {
+        let mut archive = Vec::new();
+        header.write_to(&mut archive).map_err(|e| e.to_string())?;
+        archive.extend(chunks.concat());
+        Ok(archive)
+    };
+    match restored_row_order(&archive(&chunks)?)? {
+        (Some(recorded), restored) if recorded == restored => {},
+        (recorded, restored) => return Err(format!("recorded {:?}, restored {:?}", recorded, restored)),
+    }
+    let mut swapped = chunks.clone();
+    swapped.swap(1, 2);
+    let mut missing = chunks.clone();
+    missing.remove(3);
+    for (name, chunks) in [("two chunks swapped", swapped), ("a chunk left out", missing)] {
+        let (recorded, restored) = restored_row_order(&archive(&chunks)?)?;
+        if recorded == Some(restored) {
+            return Err(format!("{}: the row order still matches", name));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the 4KB chunks of `data` as an archive, with `max_var_len` as variable length cap.
+fn parser_archive(data: &[u8], max_var_len: usize) -> Result<Vec<u8>, String> {
+    let mut archive = Vec::new();
+    ArchiveHeader::new(ChecksumKind::Crc32).write_to(&mut archive).map_err(|e| e.to_string())?;
+    let mut chunker = InputChunker::new(data, Some(SMALL_CHUNK_SIZE), data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? {
+        let encoded = encode_chunk(&IdentityBackend, chunk, ChecksumKind::Crc32, false, false, true, max_var_len).map_err(|e| format!("compression: {}", e))?;
+        for part in &encoded.parts { archive.extend_from_slice(part); }
+    }
+    Ok(archive)
+}
+
+/// Parser configuration of every chunk of `archive` and its distinct skeletons (rendered as
+/// template file lines), checking that skipping a body reads the same configurations.
+fn parser_configs(archive: &[u8]) -> Result<(Vec<Option<ParserConfig>>, Vec<String>), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let mut reader = ChunkReader::new(Cursor::new(archive)).map_err(|e| e.to_string())?;
+    let (mut parsers, mut templates) = (Vec::new(), Vec::new());
+    while let Some(chunk) = reader.next_chunk().map_err(|e| e.to_string())? {
+        parsers.push(chunk.parser_config());
+        let found = decompressor.templates(chunk.reg(), chunk.ids(), chunk.vars(), chunk.header.id_flag).map_err(|e| e.to_string())?;
+        for line in found.map(|t| t.skeletons).unwrap_or_default().iter().map(|s| render_template(s)) {
+            if !templates.contains(&line) { templates.push(line); }
+        }
+    }
+    let mut reader = ChunkReader::new(Cursor::new(archive)).map_err(|e| e.to_string())?;
+    let mut skipped = Vec::new();
+    while let Some((_, parser)) = reader.skip_chunk_parsed().map_err(|e| e.to_string())? { skipped.push(parser); }
+    if skipped != parsers {
+        return Err(format!("skipped chunks read {:?}, decoded ones {:?}", skipped, parsers));
+    }
+    Ok((parsers, templates))
+}
+
+/// Checks that the chunks of two archives of `data` written with different variable length
+/// caps record them, that a template file listed from the first accepts the first
+/// configuration and tells the cap apart from the second.
+fn parser_config_check(data: &[u8]) -> Result<(), String> {
+    let (first, first_templates) = parser_configs(&parser_archive(data, DEFAULT_MAX_VAR_LEN)?)?;
+    let (second, second_templates) = parser_configs(&parser_archive(data, PARSER_CHECK_MAX_VAR_LEN)?)?;
+    let (Some(Some(first)), Some(Some(second))) = (first.first().copied(), second.first().copied()) else {
+        return Err("first chunk without parser configuration".to_string());
+    };
+    if (first.max_var_len, second.max_var_len) != (DEFAULT_MAX_VAR_LEN as u64, PARSER_CHECK_MAX_VAR_LEN as u64) {
+        return Err(format!("recorded caps {} and {}, expected {} and {}", first.max_var_len, second.max_var_len, DEFAULT_MAX_VAR_LEN, PARSER_CHECK_MAX_VAR_LEN));
+    }
+    for parser in [first, second] {
+        if ParserConfig::parse(&parser.render()) != Some(parser) || ParserConfig::from_record(&parser.to_record()) != Some(parser) {
+            return Err(format!("{} does not read back as written", parser.render()));
+        }
+    }
+    if first_templates == second_templates {
+        return Err(format!("the cap of {} bytes changed no template", PARSER_CHECK_MAX_VAR_LEN));
+    }
+
+    // As `cast templates` lists the first archive
+    let mut listing = format!("{}{}\n", PARSER_COMMENT, first.render());
+    for line in &first_templates { listing.push_str(line); listing.push('\n'); }
+    let allowed = TemplateSet::parse(&listing)?;
+    if allowed.parsers() != [first] || allowed.comparable(&first).is_err() {
+        return Err(format!("the template file does not accept {}", first.render()));
+    }
+    // The cap also changes what the mode sample sees, so the mode may differ too
+    match allowed.comparable(&second) {
+        Err(differences) if differences.iter().any(|d| d.starts_with("max-var-len")) => {},
+        other => return Err(format!("{} compared as {:?}, expected the cap among the differences", second.render(), other)),
+    }
+
+    Ok(())
+}
+
+/// Compresses the every-flag fixture with column codecs and front coding, which sets every
+/// id_flag bit a data chunk can have, and checks that the flag still reads as a data chunk
+/// (not as one of the chunk kinds from 252 up), that the ids are RLE and that it restores.
+fn every_flag_check() -> Result<(), String> {
+    let data = every_flag();
+    let mut compressor = CASTCompressor::new(LzmaBackend::new(false, DICT_SIZE)).with_column_codecs(true).with_front_coded_registry(true);
+    let (reg, ids, vars, id_flag, _) = compressor.compress(&data).map_err(|e| format!("compression: {}", e))?;
+    let described = describe_id_flag(id_flag);
+    for bit in ["cell counts", "front-coded registry", "column streams", "column dictionaries", "Latin-1"] {
+        if !described.contains(bit) {
+            return Err(format!("id_flag {:#04x} ({}) without {}", id_flag, described, bit));
+        }
+    }
+    if id_flag >= ID_FLAG_MAC_TRAILER {
+        return Err(format!("id_flag {:#04x} reads as {}", id_flag, described));
+    }
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new());
+    let layout = decompressor.inspect(&reg, &ids, &vars, id_flag).map_err(|e| e.to_string())?;
+    if !layout.ids_rle {
+        return Err(format!("{} id stream not stored as RLE", layout.layout));
+    }
+    let mut restored = Vec::new();
+    decompressor.decompress(&reg, &ids, &vars, Some(chunk_crc(&data)), id_flag, &mut restored).map_err(|e| e.to_string())?;
+    if restored != data {
+        return Err(format!("restored {} bytes instead of {}", restored.len(), data.len()));
+    }
+    let (_, _, _, id_flag, _) = CASTCompressor::new(LzmaBackend::new(false, DICT_SIZE)).with_column_codecs(true).with_front_coded_registry(true)
+        .with_column_dictionaries(false).compress(&data).map_err(|e| format!("compression: {}", e))?;
+    if describe_id_flag(id_flag).contains("column dictionaries") {
+        return Err(format!("id_flag {:#04x} with column dictionaries turned off", id_flag));
+    }
+    Ok(())
+}
+
+/// Writes an archive whose chunks were encoded differently: the first two chunks of `data`
+/// by LZMA with each of BLOCK_DICTS, the third by the pass-through backend with column codecs,
+/// then a duplicate of the first and a passthrough chunk of noise. Checks the settings each
+/// chunk reports, that the duplicate still restores, and that the histogram tells them apart.
+fn block_settings_check(data: &[u8]) -> Result<(), String> {
+    let mut chunker = InputChunker::new(data, Some(BLOCK_CHUNK_SIZE), data.len());
+    let mut inputs = Vec::new();
+    while inputs.len() < 3 {
+        let chunk = chunker.next_chunk().map_err(|e| e.to_string())?.ok_or("fixture too small for three chunks")?;
+        inputs.push(chunk.to_vec());
+    }
+    let noise = noise(11, SMALL_CHUNK_SIZE * 4);
+    let compression = |e: CastError| format!("compression: {}", e);
+    let mut encoded = vec![
+        encode_chunk(LzmaBackend::new(false, BLOCK_DICTS[0]), &inputs[0], ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(compression)?,
+        encode_chunk(LzmaBackend::new(false, BLOCK_DICTS[1]), &inputs[1], ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(compression)?,
+        encode_chunk(&IdentityBackend, &inputs[2], ChecksumKind::Crc32, true, false, true, DEFAULT_MAX_VAR_LEN).map_err(compression)?,
+    ];
+    let first_len = (CHUNK_HEADER_SIZE + encoded[0].chunks[0].1.body_len()) as u64;
+    encoded.push(encode_duplicate(&inputs[0], ChecksumKind::Crc32, &DedupTarget { chunk: 1, data_offset: 0, compressed_len: first_len }));
+    encoded.push(encode_chunk(LzmaBackend::new(false, BLOCK_DICTS[0]), &noise, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(compression)?);
+    if let Some(split) = encoded.iter().position(|e| e.chunks.len() != 1) {
+        return Err(format!("input {} was stored as {} chunks", split + 1, encoded[split].chunks.len()));
+    }
+    let mut archive = Vec::new();
+    ArchiveHeader::new(ChecksumKind::Crc32).write_to(&mut archive).map_err(|e| e.to_string())?;
+    for part in encoded.iter().flat_map(|e| &e.parts) { archive.extend_from_slice(part); }
+
+    let mut reader = ChunkReader::new(Cursor::new(&archive)).map_err(|e| e.to_string())?;
+    let mut settings = Vec::new();
+    while let Some(chunk) = reader.next_chunk().map_err(|e| e.to_string())? {
+        settings.push(BlockSettings::of_chunk(&chunk, FORMAT_VERSION));
+        if chunk.duplicate_of().is_some() {
+            let resolved = reader.resolve(chunk).map_err(|e| format!("duplicate: {}", e))?;
+            let mut restored = Vec::new();
+            CASTDecompressor::new(LzmaDecompressorBackend::new()).decompress(resolved.reg(), resolved.ids(), resolved.vars(), Some(resolved.header.crc), resolved.header.id_flag, &mut restored)
+                .map_err(|e| format!("duplicate: {}", e))?;
+            if restored != inputs[0] { return Err("the duplicate did not restore the first chunk".to_string()); }
+        }
+    }
+    // (backend, dictionary, parse mode recorded, duplicate target) of each chunk
+    let expected = [
+        ("xz", Some(BLOCK_DICTS[0]), true, None),
+        ("xz", Some(BLOCK_DICTS[1]), true, None),
+        ("stored", None, true, None),
+        ("none", None, false, Some(1)),
+        ("xz", Some(noise.len().next_power_of_two() as u32), false, None),
+    ];
+    let found: Vec<_> = settings.iter().map(|s| (s.backend, s.dict_size, s.parser.is_some(), s.duplicate_of)).collect();
+    if found != expected {
+        return Err(format!("chunks report {:?}, expected {:?}", found, expected));
+    }
+    let layouts: Vec<&str> = settings.iter().map(|s| s.layout).collect();
+    if layouts[3..] != ["Duplicate", "Passthrough"] {
+        return Err(format!("layouts {:?}, expected a duplicate and a passthrough chunk last", layouts));
+    }
+    if let Some(s) = settings.iter().find(|s| s.column_codecs.is_empty() == (s.layout == "Column streams")) {
+        return Err(format!("{} chunk with column codecs {:?}", s.layout, s.column_codecs));
+    }
+    if settings.iter().any(|s| s.check != s.dict_size.map(|_| "CRC32")) {
+        return Err(format!("integrity checks {:?}", settings.iter().map(|s| s.check).collect::<Vec<_>>()));
+    }
+    let histogram = settings_histogram(&settings);
+    if histogram.len() != settings.len() || histogram.iter().any(|&(_, n)| n != 1) {
+        return Err(format!("histogram {:?}, expected five distinct settings", histogram));
+    }
+    Ok(())
+}
+
+/// Compresses the 4KB chunks of `data`, restores them with RESTORE_THREADS threads into a
+/// PositionedSink (in place or through temp parts), the last group first, and compares the
+/// SHA-256 of the file with the one of `data`.
+fn positioned_restore_check(data: &[u8], in_place: bool) -> Result<(), String> {
+    let mut chunks = Vec::new();
+    let mut offset = 0u64;
+    let mut chunker = InputChunker::new(data, Some(SMALL_CHUNK_SIZE), data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? {
+        let block = CASTCompressor::new(IdentityBackend).compress(chunk).map_err(|e| format!("compression: {}", e))?;
+        chunks.push((offset, block));
+        offset += chunk.len() as u64;
+    }
+    let groups: Vec<&[(u64, _)]> = chunks.chunks(RESTORE_GROUP_CHUNKS).collect();
+
+    let path = std::env::temp_dir().join(format!("cast_roundtrip_positioned_{}_{}", std::process::id(), in_place));
+    let sink = match in_place {
+        true => PositionedSink::in_place(&path, data.len() as u64),
+        false => PositionedSink::with_parts(&path, data.len() as u64),
+    }.map_err(|e| format!("cannot create '{}': {}", path.display(), e))?;
+    let next = AtomicUsize::new(groups.len());
+    let results: Vec<Result<(), String>> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..RESTORE_THREADS).map(|_| scope.spawn(|| -> Result<(), String> {
+            let decompressor = CASTDecompressor::new(IdentityBackend);
+            let mut restored = Vec::new();
+            // Groups are taken from the end, so every region is written before the ones ahead of it
+            while let Ok(g) = next.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |g| g.checked_sub(1)).map(|g| g - 1) {
+                let mut region = sink.region(groups[g][0].0).map_err(|e| e.to_string())?;
+                for (_, (reg, ids, vars, id_flag, _)) in groups[g] {
+                    restored.clear();
+                    decompressor.decompress(reg, ids, vars, None, *id_flag, &mut restored).map_err(|e| e.to_string())?;
+                    region.write_all(&restored).map_err(|e| e.to_string())?;
+                }
+                region.flush().map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        })).collect();
+        workers.into_iter().map(|w| w.join().unwrap_or_else(|_| Err("worker panicked".to_string()))).collect()
+    });
+    let written = results.into_iter().collect::<Result<(), String>>().and_then(|_| sink.finish().map_err(|e| e.to_string()));
+    let restored = written.and_then(|_| std::fs::read(&path).map_err(|e| e.to_string()));
+    let _ = std::fs::remove_file(&path);
+    let restored = restored?;
+    let digest = |bytes: &[u8]| { let mut h = Sha256::default(); h.update(bytes); h.finalize() };
+    if digest(&restored) != digest(data) {
+        return Err(format!("restored {} bytes whose SHA-256 differs from the original", restored.len()));
+    }
+    Ok(())
+}
+
+/// LZMA backend whose calls first wait until the runtime ticked `ASYNC_TICKS` times, which it
+/// can only do when the call does not run on the runtime's thread.
+#[cfg(feature = "async")]
+#[derive(Clone)]
+struct TickingBackend {
+    ticks: Arc<AtomicUsize>,
+}
+
+#[cfg(feature = "async")]
+impl TickingBackend {
+    fn wait_for_ticks(&self) -> Result<(), CastError> {
+        let start = self.ticks.load(Ordering::Relaxed);
+        let deadline = Instant::now() + ASYNC_TICK_TIMEOUT;
+        while self.ticks.load(Ordering::Relaxed) < start + ASYNC_TICKS {
+            if Instant::now() > deadline {
+                return Err(CastError::BackendFailure { backend: "ticking", detail: "the runtime did not run during the backend call".to_string() });
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl NativeCompressor for TickingBackend {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        self.wait_for_ticks()?;
+        LzmaBackend::new(false, DICT_SIZE).compress(data)
+    }
+}
+
+#[cfg(feature = "async")]
+impl NativeDecompressor for TickingBackend {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        self.wait_for_ticks()?;
+        LzmaDecompressorBackend::new().decompress(data)
+    }
+}
+
+/// Compresses `data` with `async_compress_stream` from a pipe another task writes it into, and
+/// restores the archive with `async_decompress_stream` into a buffer, on a single-thread tokio
+/// runtime that also runs a ticking task. Every backend call waits for ticks (see
+/// `TickingBackend`), so the streams fail if they ever run a chunk on the runtime's thread.
+/// The counts they return must match, and the archive must restore `data` through the
+/// synchronous reader too.
+#[cfg(feature = "async")]
+fn async_stream_check(data: &[u8]) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().map_err(|e| e.to_string())?;
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let done = Arc::new(AtomicBool::new(false));
+    let backend = TickingBackend { ticks: ticks.clone() };
+    let (archive, restored) = runtime.block_on(async {
+        let ticker = {
+            let (ticks, done) = (ticks.clone(), done.clone());
+            tokio::spawn(async move {
+                while !done.load(Ordering::Relaxed) {
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+        let (mut pipe_in, pipe_out) = tokio::io::duplex(ASYNC_PIPE_SIZE);
+        let input = data.to_vec();
+        let feeder = tokio::spawn(async move {
+            for piece in input.chunks(ASYNC_PIPE_SIZE) { pipe_in.write_all(piece).await?; }
+            pipe_in.shutdown().await
+        });
+
+        let mut archive = Vec::new();
+        let compressor = backend.clone();
+        let compressed = async_compress_stream(pipe_out, &mut archive, ASYNC_CHUNK_SIZE, ChecksumKind::Crc32, move || compressor.clone()).await;
+        let fed = feeder.await;
+        let mut restored = Vec::new();
+        let written = match compressed {
+            Ok(_) => async_decompress_stream(&archive[..], &mut restored, backend).await.map(Some),
+            Err(_) => Ok(None),
+        };
+        done.store(true, Ordering::Relaxed);
+        let _ = ticker.await;
+
+        let (read, archive_len) = compressed.map_err(|e| format!("compression: {}", e))?;
+        fed.map_err(|e| e.to_string())?.map_err(|e| format!("pipe: {}", e))?;
+        let written = written.map_err(|e| format!("restore: {}", e))?.unwrap_or(0);
+        if read != data.len() as u64 || archive_len != archive.len() as u64 || written != restored.len() as u64 {
+            return Err(format!("reported {} bytes read, {} archived and {} restored for {}, {} and {}", read, archive_len, written, data.len(), archive.len(), restored.len()));
+        }
+        Ok::<_, String>((archive, restored))
+    })?;
+    if restored != data { return Err(format!("restored {} bytes that differ from the original", restored.len())); }
+
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new());
+    let mut reader = ChunkReader::new(Cursor::new(&archive)).map_err(|e| e.to_string())?;
+    let mut synced = Vec::with_capacity(data.len());
+    while let Some(chunk) = reader.next_resolved().map_err(|e| e.to_string())? {
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(chunk.header.crc), chunk.header.id_flag, &mut synced).map_err(|e| e.to_string())?;
+    }
+    if synced != data { return Err("the synchronous reader restored different data from the async archive".to_string()); }
+    Ok(())
+}
+
+/// Writes a sparse file of `SPARSE_FIXTURE_SIZE` bytes holding pseudo-random (binary) data at
+/// `SPARSE_DATA_OFFSETS` and holes elsewhere, compresses it in `SPARSE_CHUNK_SIZE` chunks and
+/// restores it through `SparseWriter` (tests/cli.rs runs `cast -d --sparse`). The restore must
+/// equal the source byte for byte and allocate no more than `SPARSE_BLOCK_SLACK` blocks beyond
+/// it (st_blocks). On a filesystem without holes the source
+/// is fully allocated, and only the content is compared.
+#[cfg(unix)]
+fn sparse_check() -> Result<(), String> {
+    let root = env::temp_dir().join(format!("cast_roundtrip_sparse_{}", std::process::id()));
+    let result = sparse_restore_check(&root);
+    let _ = std::fs::remove_dir_all(&root);
+    result
+}
+
+#[cfg(unix)]
+fn sparse_restore_check(root: &Path) -> Result<(), String> {
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::fs::MetadataExt;
+
+    let io_err = |e: std::io::Error| e.to_string();
+    std::fs::create_dir_all(root).map_err(io_err)?;
+    let source = root.join("disk.img");
+    let mut file = std::fs::File::create(&source).map_err(io_err)?;
+    let mut state = DEFAULT_SEED | 1;
+    for offset in SPARSE_DATA_OFFSETS {
+        let region: Vec<u8> = (0..SPARSE_DATA_LEN).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect();
+        file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+        file.write_all(&region).map_err(io_err)?;
+    }
+    file.set_len(SPARSE_FIXTURE_SIZE).map_err(io_err)?;
+    drop(file);
+    let data = std::fs::read(&source).map_err(io_err)?;
+    let source_blocks = std::fs::metadata(&source).map_err(io_err)?.blocks();
+    let holes = source_blocks * 512 < SPARSE_FIXTURE_SIZE;
+
+    let check = |path: &Path, what: &str| -> Result<(), String> {
+        if std::fs::read(path).map_err(io_err)? != data { return Err(format!("{}: restored data differs from the source", what)); }
+        let blocks = std::fs::metadata(path).map_err(io_err)?.blocks();
+        if holes && blocks > source_blocks + SPARSE_BLOCK_SLACK {
+            return Err(format!("{}: {} blocks allocated, the source has {}", what, blocks, source_blocks));
+        }
+        Ok(())
+    };
+
+    let mut archive = Vec::new();
+    ArchiveHeader::new(ChecksumKind::Crc32).write_to(&mut archive).map_err(io_err)?;
+    // Chunks end on a line boundary as in the CLI, so the restored writes are not block-aligned
+    let mut chunker = InputChunker::new(&data[..], Some(SPARSE_CHUNK_SIZE), data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(io_err)? {
+        let encoded = encode_chunk(LzmaBackend::new(false, DICT_SIZE), chunk, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+        if !encoded.chunks.iter().all(|(_, header)| is_passthrough(header.id_flag)) { return Err("a chunk was not stored as passthrough".to_string()); }
+        encoded.parts.iter().for_each(|part| archive.extend_from_slice(part));
+    }
+    let restored = root.join("restored.img");
+    let mut writer = SparseWriter::create(&restored.to_string_lossy()).map_err(io_err)?;
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new());
+    let mut reader = ChunkReader::new(Cursor::new(&archive)).map_err(|e| e.to_string())?;
+    while let Some(chunk) = reader.next_resolved().map_err(|e| e.to_string())? {
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(chunk.header.crc), chunk.header.id_flag, &mut writer).map_err(|e| e.to_string())?;
+    }
+    if writer.finish().map_err(io_err)? != SPARSE_FIXTURE_SIZE { return Err("SparseWriter reported a different length".to_string()); }
+    check(&restored, "SparseWriter")
+}
+
+/// `rows` lines of two templates with a quoted cell (the only kind that captures any byte).
+/// Two rows in 16 hold `byte` at the start, middle or end of their cell, or as the whole cell, in turn; the others hold a plain cell, which keeps control
+/// bytes under the share that makes the chunk binary (passthrough). Bytes from 0x80 make the
+/// input Latin-1.
+fn byte_cells(byte: u8, rows: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    for row in 0..rows {
+        let cell = match (row % 16, (row / 16) % 4) {
+            (2.., _) => b"k7".to_vec(),
+            (_, 0) => vec![byte, b'k', b'7'],
+            (_, 1) => vec![b'k', byte, b'7'],
+            (_, 2) => vec![b'k', b'7', byte],
+            _ => vec![byte],
+        };
+        let (key, tail) = if row % 2 == 0 { ("user", "\" act=login\n") } else { ("code", "\" state=done\n") };
+        out.extend_from_slice(format!("ts={} {}=\"", row, key).as_bytes());
+        out.extend_from_slice(&cell);
+        out.extend_from_slice(tail.as_bytes());
+    }
+    out
+}
+
+/// Compresses `byte_cells` of every byte value through the pass-through backend: the block
+/// must have `layout` and restore the input byte for byte (the escaped 0x00-0x02, and the
+/// Latin-1 cells that must not come back as UTF-8), also when rebuilt from its columns.
+fn byte_cells_check(layout: &str, rows: usize) -> Result<(), String> {
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    for byte in 0..=u8::MAX {
+        let data = byte_cells(byte, rows);
+        let (reg, ids, vars, id_flag, _) = CASTCompressor::new(IdentityBackend).compress(&data).map_err(|e| format!("0x{:02X}: compression: {}", byte, e))?;
+        let found = decompressor.inspect(&reg, &ids, &vars, id_flag).map_err(|e| format!("0x{:02X}: {}", byte, e))?.layout;
+        if found != layout { return Err(format!("0x{:02X}: {} block", byte, found)); }
+        let mut restored = Vec::with_capacity(data.len());
+        decompressor.decompress(&reg, &ids, &vars, None, id_flag, &mut restored).map_err(|e| format!("0x{:02X}: {}", byte, e))?;
+        if restored != data {
+            let at = restored.iter().zip(&data).position(|(a, b)| a != b).unwrap_or(restored.len().min(data.len()));
+            return Err(format!("0x{:02X}: restored data differs at byte {}", byte, at));
+        }
+        columns_check(&data).map_err(|e| format!("0x{:02X}: column access: {}", byte, e))?;
+    }
+    Ok(())
+}
+
+/// Pass-through backend whose output for a stream over `OVERFLOW_STREAM_LEN` bytes is one
+/// byte too long for a u32 length field (zeroed, so its pages are never touched).
+struct OversizedBackend {
+    oversized: AtomicUsize,
+}
+
+impl NativeCompressor for OversizedBackend {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        if data.len() <= OVERFLOW_STREAM_LEN { return Ok(data.to_vec()); }
+        self.oversized.fetch_add(1, Ordering::Relaxed);
+        Ok(vec![0; MAX_SEGMENT_LEN as usize + 1])
+    }
+}
+
+/// Compresses `data` as one chunk through `OversizedBackend`: `encode_chunk` must split it
+/// into several chunks rather than write a length cut to 32 bits. Every header must give the
+/// exact length of its segments, and the chunks must restore `data` in order.
+fn length_overflow_check(data: &[u8]) -> Result<(), String> {
+    let backend = OversizedBackend { oversized: AtomicUsize::new(0) };
+    let encoded = encode_chunk(&backend, data, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+    if backend.oversized.load(Ordering::Relaxed) == 0 { return Err("the backend never returned an oversized stream".to_string()); }
+    if encoded.chunks.len() < 2 { return Err("the chunk was not split".to_string()); }
+    if encoded.chunks.iter().map(|(len, _)| len).sum::<usize>() != data.len() {
+        return Err("the split chunks do not cover the input".to_string());
+    }
+    let decompressor = CASTDecompressor::new(IdentityBackend);
+    let mut restored = Vec::with_capacity(data.len());
+    for (chunk_idx, ((_, header), segments)) in encoded.chunks.iter().zip(encoded.parts.chunks(4)).enumerate() {
+        let lens = [header.len_reg, header.len_ids, header.len_vars].map(|len| len as usize);
+        if lens != [segments[1].len(), segments[2].len(), segments[3].len()] {
+            return Err(format!("chunk {}: header lengths {:?} for segments of {:?} bytes", chunk_idx + 1, lens, segments[1..].iter().map(Vec::len).collect::<Vec<_>>()));
+        }
+        let chunk = Chunk::new(*header, segments[1..].concat(), FORMAT_VERSION);
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(header.crc), header.id_flag, &mut restored).map_err(|e| format!("chunk {}: {}", chunk_idx + 1, e))?;
+    }
+    if restored != data { return Err(format!("restored {} bytes that differ from the original", restored.len())); }
+    Ok(())
+}
+
+/// Native LZMA backend stopping on `token`, which it cancels itself when `cancel` is set, as a
+/// Ctrl+C arriving while the backend runs would.
+struct CancellingBackend {
+    inner: LzmaBackend,
+    token: CancellationToken,
+    cancel: bool,
+    calls: AtomicUsize,
+}
+
+impl CancellingBackend {
+    fn new(token: &CancellationToken, cancel: bool) -> Self {
+        let inner = LzmaBackend::new(false, DICT_SIZE).with_cancellation(token.clone());
+        Self { inner, token: token.clone(), cancel, calls: AtomicUsize::new(0) }
+    }
+}
+
+impl NativeCompressor for CancellingBackend {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if self.cancel { self.token.cancel(); }
+        self.inner.compress(data)
+    }
+
+    fn cancellation(&self) -> Option<&CancellationToken> { Some(&self.token) }
+}
+
+/// Output file that cancels `token` once more than `cancel_after` bytes reached it.
+struct CancellingWriter {
+    out: std::fs::File,
+    token: CancellationToken,
+    written: usize,
+    cancel_after: usize,
+}
+
+impl Write for CancellingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.written += n;
+        if self.written > self.cancel_after { self.token.cancel(); }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { self.out.flush() }
+}
+
+/// Cancels through `CancellationToken` alone (no signals): a token cancelled before a chunk
+/// must stop the transform before any backend call, and one cancelled by the backend of chunk
+/// `CANCEL_CHUNK` must end that chunk with `CastError::Cancelled` after that single call. The
+/// chunks written before it must restore under `CancelPolicy::Keep` and be gone under
+/// `Delete`. A restore whose output cancels the token partway through its second chunk must
+/// stop before the end of that chunk with a prefix of the original on disk, which `Keep` cuts
+/// back to the first chunk.
+fn cancellation_check() -> Result<(), String> {
+    let root = env::temp_dir().join(format!("cast_roundtrip_cancel_{}", std::process::id()));
+    std::fs::create_dir_all(&root).map_err(|e| e.to_string())?;
+    let data = generate_vec(Profile::ALL[0], CANCEL_FIXTURE_SIZE, DEFAULT_SEED);
+    let result = compress_cancellation_check(&root, &data).and_then(|_| restore_cancellation_check(&root, &data));
+    let _ = std::fs::remove_dir_all(&root);
+    result
+}
+
+fn compress_cancellation_check(root: &Path, data: &[u8]) -> Result<(), String> {
+    let token = CancellationToken::new();
+    token.cancel();
+    let backend = CancellingBackend::new(&token, false);
+    match encode_chunk(&backend, data, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN) {
+        Err(CastError::Cancelled) => {},
+        Err(e) => return Err(format!("token cancelled before the chunk: {}", e)),
+        Ok(_) => return Err("token cancelled before the chunk: compressed anyway".to_string()),
+    }
+    let calls = backend.calls.load(Ordering::Relaxed);
+    if calls != 0 { return Err(format!("token cancelled before the chunk: {} backend call(s)", calls)); }
+
+    for policy in [CancelPolicy::Keep, CancelPolicy::Delete] {
+        let token = CancellationToken::new();
+        let mut archive = Vec::new();
+        ArchiveHeader::new(ChecksumKind::Crc32).write_to(&mut archive).map_err(|e| e.to_string())?;
+        let mut archived = 0;
+        let mut stopped = None;
+        for (idx, chunk) in data.chunks(CANCEL_CHUNK_SIZE).enumerate() {
+            let backend = CancellingBackend::new(&token, idx + 1 == CANCEL_CHUNK);
+            match encode_chunk(&backend, chunk, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN) {
+                Ok(encoded) => {
+                    encoded.parts.iter().for_each(|part| archive.extend_from_slice(part));
+                    archived += chunk.len();
+                },
+                Err(e) => {
+                    stopped = Some((idx + 1, e, backend.calls.load(Ordering::Relaxed)));
+                    break;
+                },
+            }
+        }
+        match stopped {
+            Some((CANCEL_CHUNK, CastError::Cancelled, 1)) => {},
+            Some((chunk, e, calls)) => return Err(format!("chunk {} stopped after {} backend call(s) with: {}", chunk, calls, e)),
+            None => return Err("the cancelled run compressed every chunk".to_string()),
+        }
+        let path = root.join(format!("compress_{:?}.cast", policy));
+        std::fs::write(&path, &archive).map_err(|e| e.to_string())?;
+        policy.apply(&path, None).map_err(|e| format!("{:?}: {}", policy, e))?;
+        match policy {
+            CancelPolicy::Keep => {
+                let restored = restore_archive(&path).map_err(|e| format!("kept archive: {}", e))?;
+                if restored != data[..archived] { return Err("the kept archive does not restore the chunks before the cancelled one".to_string()); }
+            },
+            CancelPolicy::Delete if path.exists() => return Err("the cancelled archive was not deleted".to_string()),
+            CancelPolicy::Delete => {},
+        }
+    }
+    Ok(())
+}
+
+fn restore_cancellation_check(root: &Path, data: &[u8]) -> Result<(), String> {
+    let mut archive = Vec::new();
+    ArchiveHeader::new(ChecksumKind::Crc32).write_to(&mut archive).map_err(|e| e.to_string())?;
+    for chunk in [&data[..CANCEL_FIRST_CHUNK], &data[CANCEL_FIRST_CHUNK..]] {
+        let encoded = encode_chunk(&IdentityBackend, chunk, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN).map_err(|e| format!("compression: {}", e))?;
+        encoded.parts.iter().for_each(|part| archive.extend_from_slice(part));
+    }
+    for policy in [CancelPolicy::Keep, CancelPolicy::Delete] {
+        let token = CancellationToken::new();
+        let decompressor = CASTDecompressor::new(IdentityBackend).with_cancellation(token.clone());
+        let path = root.join(format!("restore_{:?}.log", policy));
+        let out = std::fs::File::create(&path).map_err(|e| e.to_string())?;
+        let mut writer = CancellingWriter { out, token, written: 0, cancel_after: CANCEL_FIRST_CHUNK };
+        let mut reader = ChunkReader::new(Cursor::new(&archive)).map_err(|e| e.to_string())?;
+        let mut results = Vec::new();
+        while let Some(chunk) = reader.next_resolved().map_err(|e| e.to_string())? {
+            results.push(decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(chunk.header.crc), chunk.header.id_flag, &mut writer));
+        }
+        drop(writer);
+        match results.as_slice() {
+            [Ok(()), Err(CastError::Cancelled)] => {},
+            other => return Err(format!("restore results {:?}, expected the second chunk cancelled", other.iter().map(|r| r.as_ref().map_err(|e| e.to_string())).collect::<Vec<_>>())),
+        }
+        let partial = std::fs::read(&path).map_err(|e| e.to_string())?;
+        if partial.len() <= CANCEL_FIRST_CHUNK || partial.len() >= data.len() {
+            return Err(format!("{} bytes restored, expected part of the second chunk", partial.len()));
+        }
+        if partial != data[..partial.len()] { return Err("the cancelled restore wrote data that differs from the original".to_string()); }
+        policy.apply(&path, Some(CANCEL_FIRST_CHUNK as u64)).map_err(|e| format!("{:?}: {}", policy, e))?;
+        match policy {
+            CancelPolicy::Keep if std::fs::read(&path).ok().as_deref() != Some(&data[..CANCEL_FIRST_CHUNK]) => {
+                return Err("the kept output is not the first chunk".to_string());
+            },
+            CancelPolicy::Delete if path.exists() => return Err("the cancelled output was not deleted".to_string()),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Number of threads of the process whose /proc status file is `status_path` (None off Linux).
+fn thread_count(status_path: &str) -> Option<usize> {
+    std::fs::read_to_string(status_path).ok()
+        .and_then(|s| s.lines().find_map(|l| l.strip_prefix("Threads:").and_then(|n| n.trim().parse::<usize>().ok())))
+}
+
+/// Native LZMA backend that panics instead when `panic` is set.
+struct PanickingBackend {
+    panic: bool,
+}
+
+impl NativeCompressor for PanickingBackend {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        if self.panic { panic!("backend panic injected by roundtrip_check"); }
+        LzmaBackend::new(false, DICT_SIZE).compress(data)
+    }
+}
+
+/// Compresses `data` in 4KB chunks through `WritePipeline` with a backend panicking on chunk
+/// `PANIC_CHUNK`, as the CLI does: the panic must come back as an internal error within
+/// `PANIC_TIME_BOUND`, the chunks before it must reach the file, and dropping the pipeline must
+/// leave no thread behind (tests/cli.rs runs a debug cast binary into the same panic).
+fn panic_check(data: &[u8]) -> Result<(), String> {
+    let root = env::temp_dir().join(format!("cast_roundtrip_panic_{}", std::process::id()));
+    std::fs::create_dir_all(&root).map_err(|e| e.to_string())?;
+    // The panic is expected: keep its message out of the report
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let result = panic_in_process_check(&root, data);
+    std::panic::set_hook(hook);
+    let _ = std::fs::remove_dir_all(&root);
+    result
+}
+
+fn panic_in_process_check(root: &Path, data: &[u8]) -> Result<(), String> {
+    let status_path = "/proc/self/status";
+    let threads_before = thread_count(status_path);
+    let path = root.join("in_process.cast");
+    let start = Instant::now();
+    let mut pipeline = WritePipeline::spawn(std::io::BufWriter::new(std::fs::File::create(&path).map_err(|e| e.to_string())?));
+    let mut written = 0;
+    let mut failure = None;
+    for (idx, chunk) in data.chunks(SMALL_CHUNK_SIZE).enumerate() {
+        let backend = PanickingBackend { panic: idx + 1 == PANIC_CHUNK };
+        match catch_panics(|| encode_chunk(backend, chunk, ChecksumKind::Crc32, false, false, true, DEFAULT_MAX_VAR_LEN)) {
+            Ok(encoded) => {
+                written += encoded.parts.iter().map(|p| p.len()).sum::<usize>();
+                pipeline.send(encoded.parts).map_err(|e| format!("chunk {}: {}", idx + 1, e))?;
+            },
+            Err(e) => {
+                failure = Some((idx + 1, e));
+                break;
+            },
+        }
+    }
+    drop(pipeline);
+    let elapsed = start.elapsed();
+    match failure {
+        Some((PANIC_CHUNK, CastError::Internal { detail })) if detail.contains("injected by roundtrip_check") => {},
+        Some((chunk, e)) => return Err(format!("chunk {} failed with: {}", chunk, e)),
+        None => return Err(format!("no error reported for the panic at chunk {}", PANIC_CHUNK)),
+    }
+    if elapsed > PANIC_TIME_BOUND { return Err(format!("the failed run took {:.2}s", elapsed.as_secs_f64())); }
+    let on_disk = std::fs::metadata(&path).map_err(|e| e.to_string())?.len();
+    if on_disk != written as u64 { return Err(format!("{} bytes on disk, {} sent before the panic", on_disk, written)); }
+    let threads_after = thread_count(status_path);
+    if threads_after != threads_before {
+        return Err(format!("{:?} threads before the run, {:?} after the pipeline was dropped", threads_before, threads_after));
+    }
+    Ok(())
+}
+
+/// Compresses each BOM_FIXTURES fixture through the async stream, which strips a leading BOM
+/// (tests/cli.rs covers `cast -c`). The archive header must record the BOM, the chunk reader
+/// must restore what follows it and `async_decompress_stream` the whole fixture.
+#[cfg(feature = "async")]
+fn bom_check() -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().map_err(|e| e.to_string())?;
+    for name in BOM_FIXTURES {
+        let (_, _, build) = ADVERSARIAL_FIXTURES.iter().find(|f| f.0 == name).ok_or_else(|| format!("{}: no such fixture", name))?;
+        let data = build();
+        let body = data.strip_prefix(&UTF8_BOM[..]).ok_or_else(|| format!("{}: fixture does not start with a BOM", name))?;
+        let (archive, restored) = runtime.block_on(async {
+            let mut archive = Vec::new();
+            async_compress_stream(&data[..], &mut archive, SMALL_CHUNK_SIZE, ChecksumKind::Crc32, || LzmaBackend::new(false, DICT_SIZE)).await.map_err(|e| format!("{}: compression: {}", name, e))?;
+            let mut restored = Vec::new();
+            async_decompress_stream(&archive[..], &mut restored, LzmaDecompressorBackend::new()).await.map_err(|e| format!("{}: restore: {}", name, e))?;
+            Ok::<_, String>((archive, restored))
+        })?;
+        let reader = ChunkReader::new(Cursor::new(&archive)).map_err(|e| format!("{}: {}", name, e))?;
+        if !reader.archive_header().has_bom() { return Err(format!("{}: the header does not record the BOM", name)); }
+        let chunks = restore_archive_bytes(&archive).map_err(|e| format!("{}: {}", name, e))?;
+        if chunks != body { return Err(format!("{}: the chunks hold {} bytes, expected the {} after the BOM", name, chunks.len(), body.len())); }
+        if restored != data { return Err(format!("{}: restored {} bytes that differ from the original", name, restored.len())); }
+    }
+    Ok(())
+}
+
+/// Decodes a .gz or .zst stream.
+fn decode_output(encoding: OutputEncoding, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    match encoding {
+        OutputEncoding::Gzip => { flate2::read::GzDecoder::new(data).read_to_end(&mut decoded).map_err(|e| e.to_string())?; },
+        OutputEncoding::Zstd => decoded = zstd::decode_all(data).map_err(|e| e.to_string())?,
+        OutputEncoding::None => decoded.extend_from_slice(data),
+    }
+    Ok(decoded)
+}
+
+/// Restores the chunks of an ENCODED_FIXTURE_SIZE byte fixture through an `EncodedWriter` for
+/// each OUTPUT_ENCODINGS encoding: decoding its output must give the fixture back, and `finish`
+/// must count the encoded bytes (tests/cli.rs runs `cast -d --output-encoding`).
+fn encoded_output_check() -> Result<(), String> {
+    let data = generate_vec(Profile::ALL[1], ENCODED_FIXTURE_SIZE, DEFAULT_SEED);
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new());
+    let mut chunks = Vec::new();
+    let mut chunker = InputChunker::new(&data[..], Some(ENCODED_CHUNK_SIZE), data.len());
+    while let Some(chunk) = chunker.next_chunk().map_err(|e| e.to_string())? {
+        chunks.push(CASTCompressor::new(LzmaBackend::new(false, DICT_SIZE)).compress(chunk).map_err(|e| format!("compression: {}", e))?);
+    }
+    for encoding in OUTPUT_ENCODINGS {
+        let mut encoded = Vec::new();
+        let mut writer = EncodedWriter::new(&mut encoded, encoding).map_err(|e| e.to_string())?;
+        for (c_reg, c_ids, c_vars, id_flag, _) in &chunks {
+            decompressor.decompress(c_reg, c_ids, c_vars, None, *id_flag, &mut writer).map_err(|e| format!("{}: {}", encoding.label(), e))?;
+        }
+        let counted = writer.finish().map_err(|e| e.to_string())?;
+        if counted != encoded.len() as u64 { return Err(format!("{}: finish counted {} bytes, {} were written", encoding.label(), counted, encoded.len())); }
+        if decode_output(encoding, &encoded)? != data { return Err(format!("{}: the output decodes to different data", encoding.label())); }
+    }
+    Ok(())
+}
+
+/// Stands in for 7-Zip in the strict verification test of tests/cli.rs: decodes the xz stream on stdin to stdout as `7z e
+/// -txz -si -so` would, with the native decoder (dropping the last byte for "truncate").
+/// Anything else (the `-h` probe) succeeds without output. Returns the exit status.
+fn fake_seven_zip(behaviour: &str, args: &[String]) -> i32 {
+    if !args.iter().any(|arg| arg == "-so") { return 0; }
+    let mut stream = Vec::new();
+    if std::io::stdin().read_to_end(&mut stream).is_err() { return 2; }
+    let Ok(mut decoded) = LzmaDecompressorBackend::new().decompress(&stream) else { return 2 };
+    if behaviour == "truncate" { decoded.pop(); }
+    if std::io::stdout().write_all(&decoded).is_err() { return 2; }
+    0
+}
+
+/// Decodes a solid chunk of PROGRESS_FIXTURE_SIZE bytes with `CASTDecompressor::with_progress`:
+/// the consumed bytes must grow to the chunk's compressed size, in at most one event per
+/// PROGRESS_MIN_STEP bytes (tests/cli.rs checks the progress events of `cast -d --json`).
+fn progress_check() -> Result<(), String> {
+    let data = generate_vec(Profile::ALL[0], PROGRESS_FIXTURE_SIZE, DEFAULT_SEED);
+    let (c_reg, c_ids, c_vars, id_flag, _) = CASTCompressor::new(LzmaBackend::new(false, DICT_SIZE)).compress(&data).map_err(|e| format!("compression: {}", e))?;
+    let total = (c_reg.len() + c_ids.len() + c_vars.len()) as u64;
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let hook_events = events.clone();
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new())
+        .with_progress(move |event| { let ProgressEvent::Backend { consumed, total } = event; hook_events.lock().unwrap().push((consumed, total)); });
+    let mut restored = Vec::with_capacity(data.len());
+    decompressor.decompress(&c_reg, &c_ids, &c_vars, None, id_flag, &mut restored).map_err(|e| e.to_string())?;
+    if restored != data { return Err("restored different data".to_string()); }
+    let events = events.lock().unwrap();
+    if events.len() < 2 || events.len() > (total as usize).div_ceil(PROGRESS_MIN_STEP) {
+        return Err(format!("{} progress event(s) for {} compressed bytes", events.len(), total));
+    }
+    if events.iter().any(|&(_, t)| t != total) || events.windows(2).any(|w| w[1].0 < w[0].0) || events.last().map(|e| e.0) != Some(total) {
+        return Err(format!("consumed bytes do not grow to {} ({:?} ... {:?})", total, events.first(), events.last()));
+    }
+    Ok(())
+}
+
+/// Restores the archive file at `path` with the native decoder.
+fn restore_archive(path: &Path) -> Result<Vec<u8>, String> {
+    restore_archive_bytes(&std::fs::read(path).map_err(|e| e.to_string())?)
+}
+
+/// Restores the chunks of `archive` with the native decoder (a BOM the header records is left
+/// out).
+fn restore_archive_bytes(archive: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = ChunkReader::new(Cursor::new(archive)).map_err(|e| e.to_string())?;
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new());
+    let mut restored = Vec::new();
+    while let Some(chunk) = reader.next_resolved().map_err(|e| e.to_string())? {
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(chunk.header.crc), chunk.header.id_flag, &mut restored).map_err(|e| e.to_string())?;
+    }
+    Ok(restored)
+}
+
+/// Reads `LEGACY_GOLDEN_ARCHIVE` as a version 1 archive without header and restores its
+/// chunks: they must give `LEGACY_GOLDEN_ORIGINAL`.
+fn legacy_golden_check() -> Result<(), String> {
+    let mut reader = ChunkReader::new(Cursor::new(LEGACY_GOLDEN_ARCHIVE)).map_err(|e| e.to_string())?;
+    let header = reader.archive_header().clone();
+    if header.version != LEGACY_VERSION || header.encoded_len() != 0 {
+        return Err(format!("read as version {} with a {} byte header", header.version, header.encoded_len()));
+    }
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new());
+    let (mut restored, mut chunks) = (Vec::new(), 0);
+    while let Some(chunk) = reader.next_resolved().map_err(|e| format!("chunk {}: {}", chunks + 1, e))? {
+        chunks += 1;
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(chunk.header.crc), chunk.header.id_flag, &mut restored).map_err(|e| format!("chunk {}: {}", chunks, e))?;
+    }
+    if chunks != LEGACY_GOLDEN_CHUNKS { return Err(format!("{} chunks, expected {}", chunks, LEGACY_GOLDEN_CHUNKS)); }
+    if restored != LEGACY_GOLDEN_ORIGINAL { return Err(format!("restored {} bytes that differ from the original", restored.len())); }
+    Ok(())
+}
+
+/// Restores `CURRENT_GOLDEN_ARCHIVE`, which must give `LEGACY_GOLDEN_ORIGINAL` (tests/cli.rs
+/// checks the `--dump-format` output of both golden archives).
+fn current_golden_check() -> Result<(), String> {
+    if restore_archive_bytes(CURRENT_GOLDEN_ARCHIVE)? != LEGACY_GOLDEN_ORIGINAL { return Err("current-v2.log.cast restores different data".to_string()); }
+    Ok(())
+}
+
+fn varint_len(mut v: u64) -> usize {
+    let mut n = 1;
+    while v >= 0x80 { v >>= 7; n += 1; }
+    n
+}
+
+// ============================================================================
+//  ADVERSARIAL FIXTURES
+// ============================================================================
+
+fn repeat_lines(count: usize, line: impl Fn(usize) -> String) -> Vec<u8> {
+    (0..count).map(line).collect::<String>().into_bytes()
+}
+
+// Same keys in different orders, repeated keys, and keys that only differ in spacing
+fn json_duplicate_keys() -> Vec<u8> {
+    repeat_lines(3000, |i| match i % 5 {
+        0 => format!("{{\"a\":{},\"b\":\"x{}\"}}\n", i, i % 7),
+        1 => format!("{{\"b\":\"x{}\",\"a\":{}}}\n", i % 7, i),
+        2 => format!("{{\"a\":{},\"a\":{}}}\n", i, i + 1),
+        3 => format!("{{ \"a\" : {} , \"b\" : \"x\" }}\n", i),
+        _ => format!("{{\"a\":{},\"b\":{{\"a\":{}}}}}\n", i, i % 3),
+    })
+}
+
+// Empty fields, trailing delimiters and rows with fewer columns than the header
+fn csv_trailing_delimiters() -> Vec<u8> {
+    let mut out = b"id,name,value,\n".to_vec();
+    out.extend(repeat_lines(3000, |i| match i % 4 {
+        0 => format!("{},n{},{},\n", i, i % 11, i * 3),
+        1 => format!("{},,,\n", i),
+        2 => format!("{},n{},{}\n", i, i % 11, i * 3),
+        _ => format!("{},n{},,,,\n", i, i % 11),
+    }));
+    out
+}
+
+// Whitespace that is data: inside quotes, doubled, tabs, and trailing before the newline
+fn quoted_whitespace() -> Vec<u8> {
+    repeat_lines(3000, |i| match i % 4 {
+        0 => format!("\"  {}  \",x\n", i),
+        1 => format!("\"a\tb {}\",  y  \n", i),
+        2 => format!("{}  ,\"\" , \" \"\n", i),
+        _ => format!("key = \"{}\"   \t\n", i),
+    })
+}
+
+// Quoted strings at the limits of the scanner: empty, doubled quotes (closing and not),
+// unterminated, escaped quotes and backslashes at the end of the line, multi-byte content
+fn quote_edges() -> Vec<u8> {
+    let mut out = repeat_lines(3000, |i| match i % 10 {
+        0 => format!("id={} name=\"\" ok\n", i),
+        1 => format!("id={} name=\"a\"\"\"\n", i),
+        2 => format!("id={} name=\"a\"\"\n", i),
+        3 => format!("id={} name=\"open {}\n", i, i),
+        4 => format!("id={} path=\"C:\\dir\\\n", i),
+        5 => format!("id={} msg=\"say \\\"hi\\\" {}\"\n", i, i),
+        6 => format!("id={} \"\"\"\"\"\" \"\"\"\n", i),
+        7 => format!("id={} txt=\"café \\é {}\"\n", i, i),
+        8 => format!("\"\",\"\",\"{}\",\"\"\n", i),
+        _ => format!("id={} end=\"\\\"\n", i),
+    });
+    out.extend_from_slice(b"last \"unterminated \\");
+    out
+}
+
+// \n and \r\n mixed, blank lines, a lone \r, and no final newline
+fn line_endings() -> Vec<u8> {
+    let mut out = repeat_lines(3000, |i| match i % 4 {
+        0 => format!("row {} ok\r\n", i),
+        1 => format!("row {} ok\n", i),
+        2 => "\n".to_string(),
+        _ => format!("row {}\rmid\n", i),
+    });
+    out.extend_from_slice(b"last row 1 without newline");
+    out
+}
+
+// CSV exported from a Windows tool: UTF-8 BOM, then CRLF lines
+fn bom_crlf() -> Vec<u8> {
+    let mut out = UTF8_BOM.to_vec();
+    out.extend_from_slice(b"id,name,status\r\n");
+    out.extend(repeat_lines(2000, |i| format!("{},user{},{}\r\n", i, i % 37, ["active", "idle"][i % 2])));
+    out
+}
+
+// A file holding nothing but its BOM
+fn bom_only() -> Vec<u8> {
+    UTF8_BOM.to_vec()
+}
+
+// Hundreds of templates that differ only in their last separators (tokens become variables
+// in Aggressive mode, punctuation stays in the template), some a prefix of the previous one,
+// plus empty lines: long shared prefixes for the front-coded registry
+fn similar_templates() -> Vec<u8> {
+    const SEPARATORS: [&str; 8] = ["=", ":", "/", "-", ".", ";", "+", "é"];
+    repeat_lines(4000, |i| {
+        let kind = i % 600;
+        let path: String = (0..3).map(|j| format!("w{}", SEPARATORS[(kind >> (3 * j)) & 7])).collect();
+        match i % 7 {
+            5 => "\n".to_string(),
+            6 => format!("[sched] job {} dispatched -> queue=main; path: {}\n", i, &path[..1]),
+            _ => format!("[sched] job {} dispatched -> queue=main; path: {} x\n", i, path),
+        }
+    })
+}
+
+// Bytes the transform uses as separators and escapes, inside otherwise regular lines
+fn separator_bytes() -> Vec<u8> {
+    let mut out = Vec::new();
+    for i in 0..3000 {
+        out.extend_from_slice(format!("event {} value=", i).as_bytes());
+        out.push([0x00, 0x01, 0x02, 0x03][i % 4]);
+        out.extend_from_slice(format!(" tail {}\n", i % 9).as_bytes());
+    }
+    out
+}
+
+// Aggressive-mode lines whose timestamps run into request ids, with and without zones, plus
+// timestamps that must stay whole (followed by a digit or ending the token)
+fn timestamp_request_ids() -> Vec<u8> {
+    repeat_lines(3000, |i| {
+        let session = (i as u64).wrapping_mul(2654435761) & 0xff_ffff;
+        let (m, s) = (i / 60 % 60, i % 60);
+        let stamp = match i % 6 {
+            0 => format!("2024-11-18T09:{:02}:{:02}.{:03}-req-{}", m, s, i % 1000, 4711 + i),
+            1 => format!("2024-11-18T09:{:02}:{:02}+02:00-req-{}", m, s, 4711 + i),
+            2 => format!("2024-11-18T09:{:02}:{:02}Z_req{}", m, s, i % 40),
+            3 => format!("2024-11-18-{}", i % 9),
+            4 => format!("09:{:02}:{:02}.5.job{}", m, s, i % 7),
+            _ => format!("2024-11-18T09:{:02}:{:02}{}", m, s, i % 10),
+        };
+        format!("[{}] session={:x} ok\n", stamp, session)
+    })
+}
+
+// Terminal log colored with ANSI escape sequences: ESC bytes that are text, not binary
+fn ansi_colors() -> Vec<u8> {
+    repeat_lines(3000, |i| {
+        let level = ["\x1b[32mINFO\x1b[0m", "\x1b[33mWARN\x1b[0m", "\x1b[1;31mERROR\x1b[0m"][i % 3];
+        format!("\x1b[90m10:{:02}:{:02}\x1b[0m {} job={} took {}ms\n", i / 60 % 60, i % 60, level, i % 17, i * 7 % 1000)
+    })
+}
+
+// Log lines in UTF-16LE after its BOM: a NUL in every other byte
+fn utf16_text() -> Vec<u8> {
+    let text = String::from_utf8(repeat_lines(500, |i| format!("10:{:02}:{:02} INFO job={} done\n", i / 60 % 60, i % 60, i % 17))).unwrap_or_default();
+    [0xFF, 0xFE].into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()
+}
+
+// Fixed 64-byte ASCII records padded with NULs
+fn nul_padded() -> Vec<u8> {
+    (0..1000).flat_map(|i| {
+        let mut record = format!("id={:05} name=user{} status=active\n", i, i % 37).into_bytes();
+        record.resize(64, 0);
+        record
+    }).collect()
+}
+
+// PDF objects whose streams hold pseudo-random bytes, as compressed page contents do
+fn pdf_like() -> Vec<u8> {
+    let mut state = 0x9E37_79B9u32;
+    let mut out = b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    for obj in 1..=40 {
+        out.extend_from_slice(format!("{} 0 obj\n<< /Length 512 /Filter /FlateDecode >>\nstream\n", obj).as_bytes());
+        out.extend((0..512).map(|_| { state ^= state << 13; state ^= state >> 17; state ^= state << 5; state as u8 }));
+        out.extend_from_slice(b"\nendstream\nendobj\n");
+    }
+    out.extend_from_slice(b"trailer\n<< /Root 1 0 R >>\n%%EOF\n");
+    out
+}
+
+/// Compresses each BINARY_GUARD_CASES fixture in one block: it must be stored as passthrough
+/// exactly when the case says so.
+fn binary_guard_check() -> Result<(), String> {
+    for (name, passthrough) in BINARY_GUARD_CASES {
+        let (_, _, build) = ADVERSARIAL_FIXTURES.iter().find(|f| f.0 == name).ok_or_else(|| format!("{}: no such fixture", name))?;
+        let (_, _, _, id_flag, _) = CASTCompressor::new(IdentityBackend).compress(&build()).map_err(|e| format!("{}: compression: {}", name, e))?;
+        if is_passthrough(id_flag) != passthrough {
+            return Err(format!("{}: stored with id_flag {:#04x} ({})", name, id_flag, describe_id_flag(id_flag)));
+        }
+    }
+    Ok(())
+}
+
+// Payloads longer than the variable length cap (a quoted blob and a bare token) between
+// ordinary lines
+fn long_values() -> Vec<u8> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut state = 0x2545_F491u32;
+    let mut payload = |len: usize| -> String {
+        (0..len).map(|_| {
+            state ^= state << 13; state ^= state >> 17; state ^= state << 5;
+            ALPHABET[state as usize % ALPHABET.len()] as char
+        }).collect()
+    };
+    let long = DEFAULT_MAX_VAR_LEN + DEFAULT_MAX_VAR_LEN / 2;
+    let mut out = String::new();
+    for i in 0..300 {
+        out.push_str(&format!("req={} status={} body=\"ok {}\"\n", i, 200 + i % 3, i % 7));
+        if i % 100 == 50 { out.push_str(&format!("req={} status=500 body=\"{}\"\n", i, payload(long))); }
+    }
+    out.push_str(&format!("dump {}\n", payload(long)));
+    out.into_bytes()
+}
+
+// Not valid UTF-8: Latin-1 accents in the variable parts
+fn latin1() -> Vec<u8> {
+    let mut out = Vec::new();
+    for i in 0..3000 {
+        out.extend_from_slice(format!("user {} city=", i).as_bytes());
+        out.extend_from_slice(&[b'M', 0xfc, b'n', b'c', b'h', b'e', b'n']);
+        out.extend_from_slice(format!(" n={}\n", i % 13).as_bytes());
+    }
+    out
+}
+
+// Degenerate inputs: a lone newline, one byte, one line without newline
+fn tiny() -> Vec<u8> {
+    b"\nx\n\n\nonly line".to_vec()
+}
+
+// Blocks of exactly 4KB (64 lines of 64 bytes) repeated out of order, so whole chunks repeat
+// at chunk boundaries, between blocks that appear once
+fn repeated_chunks() -> Vec<u8> {
+    let block = |seed: usize| (0..64).map(|i| format!("{:<63}\n", format!("heartbeat node={} seq={} ok", seed, i))).collect::<String>();
+    [1, 2, 1, 1, 3, 2, 4, 1].iter().map(|&seed| block(seed)).collect::<String>().into_bytes()
+}
+
+// Latin-1 lines of three templates sharing a long head, in runs of EVERY_FLAG_RUN rows, with a
+// column of ten values: with column codecs and front coding its block sets every id_flag bit
+// a data chunk can have (RLE ids, a column dictionary)
+fn every_flag() -> Vec<u8> {
+    let endings = ["queued", "queued for retry", "queued and dropped"];
+    let mut out = Vec::new();
+    for row in 0..EVERY_FLAG_ROWS {
+        out.extend_from_slice(format!("[{}] request accepted by the caf", row).as_bytes());
+        out.push(0xe9);
+        // Node names in scrambled order, so a dictionary beats the backend finding the repeats
+        let node = (row as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 60;
+        out.extend_from_slice(format!(" gateway and {} node=\"worker-node-{}.eu-west-1.compute.internal\"\n", endings[(row / EVERY_FLAG_RUN) % endings.len()], node).as_bytes());
+    }
+    out
+}
+
+fn stable_template_ids() -> Vec<u8> {
+    template_usage_vec(TemplateUsage::Stable, TEMPLATE_IDS_ROWS, DEFAULT_SEED)
+}
+
+fn shifting_template_ids() -> Vec<u8> {
+    template_usage_vec(TemplateUsage::Shifting, TEMPLATE_IDS_ROWS, DEFAULT_SEED)
+}
+
+fn alternating_template_ids() -> Vec<u8> {
+    template_usage_vec(TemplateUsage::Alternating, TEMPLATE_IDS_ROWS, DEFAULT_SEED)
+}
+
+fn short_lines() -> Vec<u8> {
+    repeat_lines(SHORT_LINES_ROWS, |i| SHORT_LINES[((i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 62) as usize].to_string())
+}
+
+fn distinct_templates() -> Vec<u8> {
+    distinct_templates_vec(DISTINCT_TEMPLATES, DISTINCT_TEMPLATE_REPEATS)
+}
+
+// One minified JSON document per line, about 20KB each. The tag count of the first items
+// spells the line number in binary, so every line has a skeleton of its own
+fn minified_json() -> Vec<u8> {
+    repeat_lines(MINIFIED_JSON_LINES, |i| {
+        let items: Vec<String> = (0..250).map(|j| {
+            let tags = if (i >> (j % 10)) & 1 == 1 { "[\"x\",\"y\"]" } else { "[\"x\"]" };
+            format!("{{\"sku\":\"A{}-{}\",\"qty\":{},\"tags\":{}}}", i, j, (i * j) % 97, tags)
+        }).collect();
+        format!("{{\"order\":{},\"items\":[{}]}}\n", i, items.join(","))
+    })
+}
+
+// Two templates of WIDE_COLUMNS numbers each, with few distinct values per column
+fn wide_columns() -> Vec<u8> {
+    repeat_lines(WIDE_ROWS, |i| {
+        let values: Vec<String> = (0..WIDE_COLUMNS).map(|j| ((i + j) % 7 * 100 + j % 13).to_string()).collect();
+        format!("wide{} {}\n", if i % 2 == 0 { ":" } else { " =" }, values.join(" "))
+    })
+}
+
+// Two templates whose only value is a hex string of HUGE_VALUE_LEN bytes: three of them pass
+// LAYOUT_SAMPLE_BYTES
+fn huge_values() -> Vec<u8> {
+    repeat_lines(HUGE_VALUE_ROWS, |i| {
+        let hex: String = noise(i as u64 + 1, HUGE_VALUE_LEN / 2).iter().map(|b| format!("{:02x}", b)).collect();
+        format!("blob{} {}\n", if i % 2 == 0 { ":" } else { " =" }, hex)
+    })
+}
+
+// Pseudo-random bytes (xorshift64), the same on every run
+fn noise(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 32) as u8
+    }).collect()
+}
+
+// Text lines of random tokens: nothing for the backend to find, so the columnar form (ids,
+// separators) can only add to the input
+fn incompressible_text() -> Vec<u8> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/=-_.:";
+    let bytes = noise(7, 3000 * 48);
+    repeat_lines(3000, |i| {
+        let row = &bytes[i * 48..(i + 1) * 48];
+        let token = |r: &[u8]| r.iter().map(|&b| ALPHABET[b as usize % ALPHABET.len()] as char).collect::<String>();
+        format!("{} {} {}\n", token(&row[..16]), token(&row[16..40]), u64::from_le_bytes(row[40..48].try_into().unwrap()))
+    })
+}
+
+// Values made of the separator and escape bytes after a clean first 4KB (the binary guard
+// only samples that much), so every value byte doubles when escaped
+fn escape_heavy() -> Vec<u8> {
+    let mut out = repeat_lines(100, |i| format!("event {} status=ok\n", i));
+    let bytes = noise(11, 3000 * 24);
+    for i in 0..3000 {
+        out.extend_from_slice(format!("event {} status=", i).as_bytes());
+        out.extend(bytes[i * 24..(i + 1) * 24].iter().map(|b| b % 3));
+        out.push(b'\n');
+    }
+    out
+}
+
+fn print_usage() {
+    let exe_name = env::args().next().and_then(|a| Path::new(&a).file_name().map(|n| n.to_string_lossy().into_owned())).unwrap_or_else(|| "roundtrip_check".to_string());
+    println!(
+        "\nCAST round-trip check (v{})\n\n\
+        Usage:\n  \
+          {} [OPTIONS]\n\n\
+        Options:\n{}\n\
+        Compresses adversarial and generated fixtures with every transform setting, chunking\n\
+        and backend, and with chunk deduplication, decodes them and compares the result with\n\
+        the original.\n\n\
+        Exit status: 0 = every round trip restored the original, 1 = at least one did not",
+        env!("CARGO_PKG_VERSION"),
+        exe_name, cli::render_options(Binary::RoundtripCheck)
+    );
+}
@@ -2,10 +2,16 @@ use std::env;
 use std::fs::File;
 use std::io::{self, Read, Write, BufRead, BufReader};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 use crc32fast::Hasher;
 
-use cast::cast::NativeCompressor;
+use cast::cast::{NativeCompressor, NativeDecompressor};
+use cast::archive::{ARCHIVE_HEADER_SIZE, CHUNK_HEADER_SIZE};
+use cast::auth::Sha256;
+use cast::positioned_out::PositionedSink;
+use cast::cli::{self, Binary};
+use cast::datagen::{generate, Profile, DEFAULT_SEED};
 
 use cast::cast_lzma::{
     LzmaBackend,
@@ -24,6 +30,15 @@ struct BenchmarkResult {
     name: String,
     size: usize,
     time: f64,
+    decomp_time: f64,
+    verified: bool,
+}
+
+// Outcome of decoding a compressed output back and comparing it with the original
+struct Verification {
+    ok: bool,
+    time: f64,
+    error: Option<String>,
 }
 
 fn main() {
@@ -43,9 +58,14 @@ fn main() {
         print_bench_usage(exe_name);
         return;
     }
+    if let Some(unknown) = cli::unknown_option(Binary::RunBenchmarks, &args[1..]) {
+        eprintln!("[!]  Error: Unknown option '{}' (see --help).", unknown);
+        std::process::exit(1);
+    }
 
     // 1. Multithread Flag
     let use_multithread = args.iter().any(|arg| arg == "--multithread");
+    let column_codecs = args.iter().any(|arg| arg == "--column-codecs");
 
     // 2. Parsing --chunk-size <SIZE>
     let mut chunk_size_bytes: Option<usize> = None;
@@ -82,19 +102,28 @@ fn main() {
         }
     }
 
-    // 5. Parsing --list
+    // 5. Parsing --restore-threads <LIST> (needs --chunk-size: a solid archive is one chunk)
+    let mut restore_threads: Vec<usize> = Vec::new();
+    if let Some(pos) = args.iter().position(|arg| arg == "--restore-threads") {
+        if pos + 1 < args.len() {
+            let val = &args[pos+1];
+            restore_threads = val.split(',').map(|t| t.trim().parse().ok().filter(|&n| n > 0)).collect::<Option<Vec<usize>>>().unwrap_or_else(|| {
+                eprintln!("[!]  Error: Invalid thread list: '{}' (e.g. 1,2,4).", val);
+                std::process::exit(1);
+            });
+        }
+        if chunk_size_bytes.is_none() {
+            eprintln!("[!]  Error: --restore-threads needs --chunk-size (a solid archive restores as one chunk).");
+            std::process::exit(1);
+        }
+    }
+
+    // 6. Parsing --list
     let list_path_opt = args.windows(2)
         .find(|w| w[0] == "--list")
         .map(|w| w[1].clone());
 
-    if list_path_opt.is_none() {
-        eprintln!("[!]  ERROR: Missing '--list <file.txt>'");
-        print_bench_usage(exe_name);
-        std::process::exit(1);
-    }
-    let list_path = list_path_opt.unwrap();
-
-    // 6. Parsing --compare-with
+    // 7. Parsing --compare-with
     let competitors_opt = args.windows(2)
         .find(|w| w[0] == "--compare-with")
         .map(|w| w[1].clone());
@@ -150,17 +179,22 @@ fn main() {
 
     // --- LOAD FILE LIST ---
     let mut files_to_test = Vec::new();
-    println!("\n[*]  Reading list: {}", list_path);
-    if let Ok(file) = File::open(&list_path) {
-        for line in BufReader::new(file).lines() {
-            if let Ok(l) = line {
+    if let Some(list_path) = list_path_opt {
+        println!("\n[*]  Reading list: {}", list_path);
+        if let Ok(file) = File::open(&list_path) {
+            #[allow(clippy::manual_flatten)]
+            for line in BufReader::new(file).lines() {
+                if let Ok(l) = line {
                 let t = l.trim();
                 if !t.is_empty() && !t.starts_with('#') { files_to_test.push(t.to_string()); }
             }
+            }
+        } else {
+            eprintln!("[!]  Cannot open list file.");
+            std::process::exit(1);
         }
     } else {
-        eprintln!("[!]  Cannot open list file.");
-        std::process::exit(1);
+        files_to_test = generate_fixtures();
     }
 
     if files_to_test.is_empty() {
@@ -190,6 +224,10 @@ fn main() {
         println!("CAST Chunking:      DISABLED (Global Optimization)");
     }
     println!("LZMA Dict Size:     {}", format_bytes(dict_size_bytes as usize));
+    println!("Column Codecs:      {}", if column_codecs { "ON (backend / zstd / raw per column)" } else { "OFF" });
+    if !restore_threads.is_empty() {
+        println!("Parallel Restore:   {:?} thread(s) ({} CPU(s) available)", restore_threads, threads);
+    }
     println!("Competitors:        {:?} (Always Global/Solid)", competitors);
     println!("Files to test:      {}", files_to_test.len());
     println!("--------------------------------------------------\n");
@@ -230,13 +268,16 @@ fn main() {
         // 1: CAST
         // ---------------------------------------------------------
         if let Some(chunk_size) = chunk_size_bytes {
-            run_cast_chunked_only(&file_path, chunk_size, file_len, use_multithread, dict_size_bytes, use_7zip, &mut results);
+            run_cast_chunked_only(&file_path, chunk_size, file_len, use_multithread, dict_size_bytes, use_7zip, column_codecs, &mut results);
+            if !restore_threads.is_empty() {
+                run_parallel_restore(&file_path, chunk_size, dict_size_bytes, use_7zip, column_codecs, &restore_threads);
+            }
         } else {
              let data = match std::fs::read(&file_path) {
                 Ok(d) => d,
                 Err(e) => { eprintln!("[!]  Read Error: {}", e); continue; }
             };
-            run_cast_solid_only(&data, use_multithread, dict_size_bytes, use_7zip, &mut results);
+            run_cast_solid_only(&data, use_multithread, dict_size_bytes, use_7zip, column_codecs, &mut results);
         }
 
         // ---------------------------------------------------------
@@ -278,12 +319,14 @@ fn main() {
                 "(WINNER)".to_string()
             };
 
-            println!("{}. {:<15} : {:>15} | Ratio: {:.2}x | Time: {:.2}s | {}",
+            println!("{}. {:<15} : {:>15} | Ratio: {:.2}x | Time: {:.2}s | Decomp: {:.2}s | Verify: {} | {}",
                 i + 1,
                 res.name,
                 format_bytes(res.size),
                 ratio,
                 res.time,
+                res.decomp_time,
+                if res.verified { "OK" } else { "FAIL" },
                 diff_str
             );
         }
@@ -312,9 +355,40 @@ fn main() {
     }
 }
 
+// --- GENERATED FIXTURES ---
+
+// Size of each generated dataset when no --list is given
+const FIXTURE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Writes one dataset per profile (fixed seed, so results are comparable across runs and
+/// machines) to a temporary directory and returns their paths.
+fn generate_fixtures() -> Vec<String> {
+    let dir = env::temp_dir().join("cast_bench_fixtures");
+    println!("\n[*]  No --list given: generating fixtures ({} each, seed {}) in {}", format_bytes(FIXTURE_SIZE as usize), DEFAULT_SEED, dir.display());
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("[!]  Cannot create fixture directory: {}", e);
+        std::process::exit(1);
+    }
+    let mut paths = Vec::new();
+    for profile in Profile::ALL {
+        let path = dir.join(format!("{}.{}", profile.name(), profile.extension()));
+        let written = File::create(&path).and_then(|f| {
+            let mut out = io::BufWriter::new(f);
+            generate(profile, FIXTURE_SIZE, DEFAULT_SEED, &mut out)?;
+            out.flush()
+        });
+        if let Err(e) = written {
+            eprintln!("[!]  Cannot write fixture {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        paths.push(path.to_string_lossy().into_owned());
+    }
+    paths
+}
+
 // --- CAST LOGIC ONLY ---
 
-fn run_cast_solid_only(data: &[u8], multithread: bool, dict_size: u32, use_7zip: bool, results: &mut Vec<BenchmarkResult>) {
+fn run_cast_solid_only(data: &[u8], multithread: bool, dict_size: u32, use_7zip: bool, column_codecs: bool, results: &mut Vec<BenchmarkResult>) {
     let orig_len = data.len();
     print!("\n[*] Running CAST (Global)...");
     io::stdout().flush().unwrap();
@@ -328,39 +402,24 @@ fn run_cast_solid_only(data: &[u8], multithread: bool, dict_size: u32, use_7zip:
         RuntimeLzmaCompressor::Native(LzmaBackend::new(multithread, dict_size))
     };
 
-    let mut compressor = CASTLzmaCompressor::new(backend);
-    let (r, i, v, flag, _) = compressor.compress(data);
+    let mut compressor = CASTLzmaCompressor::new(backend).with_column_codecs(column_codecs);
+    let (r, i, v, flag, _) = match compressor.compress(data) {
+        Ok(res) => res,
+        Err(e) => { println!(" ERROR: {}", e); return; }
+    };
     let duration = start.elapsed().as_secs_f64();
-    let size = 17 + r.len() + i.len() + v.len();
+    let size = ARCHIVE_HEADER_SIZE + CHUNK_HEADER_SIZE + r.len() + i.len() + v.len();
 
     print_result(duration, size, orig_len);
-    results.push(BenchmarkResult { name: "CAST (Global)".to_string(), size, time: duration });
 
     // Verify
-    print!("    [Verifying... ");
-    io::stdout().flush().unwrap();
-    let mut h = Hasher::new();
-    h.update(data);
-    let expected_crc = h.finalize();
-
-    let decompressor_backend = if use_7zip {
-        RuntimeLzmaDecompressor::SevenZip(SevenZipDecompressorBackend)
-    } else {
-        RuntimeLzmaDecompressor::Native(LzmaDecompressorBackend)
-    };
-    let decompressor = CASTLzmaDecompressor::new(decompressor_backend);
-
-    let mut output_buffer = Vec::with_capacity(data.len());
-
-    match decompressor.decompress(&r, &i, &v, expected_crc, flag, &mut output_buffer) {
-        Ok(_) => {
-            if output_buffer == data { println!("OK]"); } else { println!("FAIL - Mismatch]"); }
-        },
-        Err(e) => println!("ERROR: {}]", e),
-    }
+    let verification = verify_roundtrip(data, || decompress_cast_chunk(&r, &i, &v, flag, data, use_7zip));
+    print_verification(&verification);
+    results.push(BenchmarkResult { name: "CAST (Global)".to_string(), size, time: duration, decomp_time: verification.time, verified: verification.ok });
 }
 
-fn run_cast_chunked_only(file_path: &str, chunk_size: usize, file_len: usize, multithread: bool, dict_size: u32, use_7zip: bool, results: &mut Vec<BenchmarkResult>) {
+#[allow(clippy::too_many_arguments)]
+fn run_cast_chunked_only(file_path: &str, chunk_size: usize, file_len: usize, multithread: bool, dict_size: u32, use_7zip: bool, column_codecs: bool, results: &mut Vec<BenchmarkResult>) {
     print!("\n[*] Running CAST (Chunked)...");
     io::stdout().flush().unwrap();
 
@@ -368,7 +427,8 @@ fn run_cast_chunked_only(file_path: &str, chunk_size: usize, file_len: usize, mu
     let mut buffer = vec![0u8; chunk_size];
 
     let mut total_time = 0.0;
-    let mut total_size = 0;
+    let mut total_decomp_time = 0.0;
+    let mut total_size = ARCHIVE_HEADER_SIZE;
     let mut chunks = 0;
     let mut verify_ok = true;
 
@@ -394,42 +454,90 @@ fn run_cast_chunked_only(file_path: &str, chunk_size: usize, file_len: usize, mu
         } else {
             RuntimeLzmaCompressor::Native(LzmaBackend::new(multithread, dict_size))
         };
-        let mut compressor = CASTLzmaCompressor::new(backend);
+        let mut compressor = CASTLzmaCompressor::new(backend).with_column_codecs(column_codecs);
 
-        let (r, i, v, flag, _) = compressor.compress(chunk_data);
+        let (r, i, v, flag, _) = match compressor.compress(chunk_data) {
+            Ok(res) => res,
+            Err(e) => { println!(" ERROR at chunk {}: {}", chunks, e); return; }
+        };
         total_time += start.elapsed().as_secs_f64();
 
         // Accumulate Size
-        let chunk_output_size = 17 + r.len() + i.len() + v.len();
+        let chunk_output_size = CHUNK_HEADER_SIZE + r.len() + i.len() + v.len();
         total_size += chunk_output_size;
 
         // Immediate Verification
-        let mut h = Hasher::new();
-        h.update(chunk_data);
-        let expected_crc = h.finalize();
-
-        let decompressor_backend = if use_7zip {
-            RuntimeLzmaDecompressor::SevenZip(SevenZipDecompressorBackend)
-        } else {
-            RuntimeLzmaDecompressor::Native(LzmaDecompressorBackend)
-        };
-        let decompressor = CASTLzmaDecompressor::new(decompressor_backend);
-
-        let mut restored_chunk = Vec::new(); // Buffer temporaneo
-
-        match decompressor.decompress(&r, &i, &v, expected_crc, flag, &mut restored_chunk) {
-            Ok(_) => {
-                if restored_chunk != chunk_data { verify_ok = false; }
-            },
-            Err(_) => { verify_ok = false; }
-        }
+        let verification = verify_roundtrip(chunk_data, || decompress_cast_chunk(&r, &i, &v, flag, chunk_data, use_7zip));
+        total_decomp_time += verification.time;
+        if !verification.ok { verify_ok = false; }
     }
 
     print_result(total_time, total_size, file_len);
     if verify_ok { println!("    [Integrity: OK (Checked {} chunks)]", chunks); }
     else { println!("    [Integrity: FAILED]"); }
 
-    results.push(BenchmarkResult { name: "CAST (Ck)".to_string(), size: total_size, time: total_time });
+    results.push(BenchmarkResult { name: "CAST (Ck)".to_string(), size: total_size, time: total_time, decomp_time: total_decomp_time, verified: verify_ok });
+}
+
+/// Compresses the file in chunks, then restores it with each thread count of
+/// --restore-threads: every thread decodes whole chunks and writes them at their offset in one
+/// pre-sized output (PositionedSink). Prints the time and the speedup over the first count, and
+/// checks the SHA-256 of every output against the original.
+fn run_parallel_restore(file_path: &str, chunk_size: usize, dict_size: u32, use_7zip: bool, column_codecs: bool, thread_counts: &[usize]) {
+    println!("\n[*] Parallel restore ({} per chunk)...", format_bytes(chunk_size));
+    let data = match std::fs::read(file_path) {
+        Ok(d) => d,
+        Err(e) => { eprintln!("[!]  Read Error: {}", e); return; }
+    };
+    let digest = |bytes: &[u8]| { let mut h = Sha256::default(); h.update(bytes); h.finalize() };
+    let expected = digest(&data);
+
+    // (output offset, block) of every chunk
+    let mut blocks = Vec::new();
+    for (idx, chunk) in data.chunks(chunk_size).enumerate() {
+        let backend = if use_7zip {
+            RuntimeLzmaCompressor::SevenZip(SevenZipBackend::new(dict_size))
+        } else {
+            RuntimeLzmaCompressor::Native(LzmaBackend::new(false, dict_size))
+        };
+        match CASTLzmaCompressor::new(backend).with_column_codecs(column_codecs).compress(chunk) {
+            Ok(block) => blocks.push(((idx * chunk_size) as u64, block)),
+            Err(e) => { println!("    ERROR at chunk {}: {}", idx + 1, e); return; }
+        }
+    }
+
+    let out_path = env::temp_dir().join(format!("cast_bench_restore_{}", std::process::id()));
+    let mut baseline = None;
+    for &threads in thread_counts {
+        let start = Instant::now();
+        let result = PositionedSink::create(&out_path, data.len() as u64).map_err(|e| e.to_string()).and_then(|sink| {
+            let next = AtomicUsize::new(0);
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| -> Result<(), String> {
+                    let decompressor = CASTLzmaDecompressor::new(lzma_decompressor(use_7zip));
+                    let mut restored = Vec::new();
+                    while let Some((offset, (r, i, v, flag, _))) = blocks.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        restored.clear();
+                        decompressor.decompress(r, i, v, None, *flag, &mut restored).map_err(|e| e.to_string())?;
+                        sink.region(*offset).and_then(|mut region| region.write_all(&restored)).map_err(|e| e.to_string())?;
+                    }
+                    Ok(())
+                })).collect();
+                workers.into_iter().try_for_each(|w| w.join().unwrap_or_else(|_| Err("restore thread panicked".to_string())))
+            })?;
+            sink.finish().map_err(|e| e.to_string())
+        });
+        let elapsed = start.elapsed().as_secs_f64();
+        let verified = result.and_then(|_| std::fs::read(&out_path).map_err(|e| e.to_string())).map(|out| digest(&out) == expected);
+        let base = *baseline.get_or_insert(elapsed);
+        let check = match verified {
+            Ok(true) => "SHA-256 OK".to_string(),
+            Ok(false) => "SHA-256 MISMATCH".to_string(),
+            Err(e) => format!("ERROR: {}", e),
+        };
+        println!("    {:>3} thread(s): {:>6.2}s | Speedup: {:>5.2}x | {}", threads, elapsed, base / elapsed.max(1e-9), check);
+    }
+    let _ = std::fs::remove_file(&out_path);
 }
 
 // --- COMPETITORS LOGIC (ALWAYS SOLID) ---
@@ -449,12 +557,18 @@ fn run_competitor_solid(algo: &str, data: &[u8], multithread: bool, dict_size: u
                 RuntimeLzmaCompressor::Native(LzmaBackend::new(multithread, dict_size))
             };
 
-            let c = backend.compress(data);
+            let c = match backend.compress(data) {
+                Ok(c) => c,
+                Err(e) => { println!(" ERROR: {}", e); return; }
+            };
 
             let duration = start.elapsed().as_secs_f64();
             let size = c.len();
             print_result(duration, size, orig_len);
-            results.push(BenchmarkResult { name: name.to_string(), size, time: duration });
+
+            let verification = verify_roundtrip(data, || lzma_decompressor(use_7zip).decompress(&c).map_err(|e| e.to_string()));
+            print_verification(&verification);
+            results.push(BenchmarkResult { name: name.to_string(), size, time: duration, decomp_time: verification.time, verified: verification.ok });
         },
         "brotli" => {
             let name = "Brotli";
@@ -465,7 +579,10 @@ fn run_competitor_solid(algo: &str, data: &[u8], multithread: bool, dict_size: u
             let duration = start.elapsed().as_secs_f64();
             let size = c.len();
             print_result(duration, size, orig_len);
-            results.push(BenchmarkResult { name: name.to_string(), size, time: duration });
+
+            let verification = verify_roundtrip(data, || decompress_brotli(&c));
+            print_verification(&verification);
+            results.push(BenchmarkResult { name: name.to_string(), size, time: duration, decomp_time: verification.time, verified: verification.ok });
         },
         "zstd" => {
             let name = "Zstd";
@@ -476,7 +593,10 @@ fn run_competitor_solid(algo: &str, data: &[u8], multithread: bool, dict_size: u
             let duration = start.elapsed().as_secs_f64();
             let size = c.len();
             print_result(duration, size, orig_len);
-            results.push(BenchmarkResult { name: name.to_string(), size, time: duration });
+
+            let verification = verify_roundtrip(data, || decompress_zstd(&c));
+            print_verification(&verification);
+            results.push(BenchmarkResult { name: name.to_string(), size, time: duration, decomp_time: verification.time, verified: verification.ok });
         },
         _ => {}
     }
@@ -491,6 +611,56 @@ fn print_result(seconds: f64, size: usize, orig: usize) {
              seconds, format_bytes(size), ratio);
 }
 
+// --- VERIFICATION (SHARED BY CAST AND COMPETITORS) ---
+
+/// Runs `decode`, timing it, and compares its output byte-for-byte with `original`.
+fn verify_roundtrip<F: FnOnce() -> Result<Vec<u8>, String>>(original: &[u8], decode: F) -> Verification {
+    let start = Instant::now();
+    let decoded = decode();
+    let time = start.elapsed().as_secs_f64();
+    match decoded {
+        Ok(restored) => Verification { ok: restored == original, time, error: None },
+        Err(e) => Verification { ok: false, time, error: Some(e) },
+    }
+}
+
+fn print_verification(v: &Verification) {
+    match (&v.error, v.ok) {
+        (Some(e), _) => println!("    [Verifying... ERROR: {}]", e),
+        (None, true) => println!("    [Verifying... OK in {:.2}s]", v.time),
+        (None, false) => println!("    [Verifying... FAIL - Mismatch]"),
+    }
+}
+
+fn lzma_decompressor(use_7zip: bool) -> RuntimeLzmaDecompressor {
+    if use_7zip {
+        RuntimeLzmaDecompressor::SevenZip(SevenZipDecompressorBackend)
+    } else {
+        RuntimeLzmaDecompressor::Native(LzmaDecompressorBackend::new())
+    }
+}
+
+fn decompress_cast_chunk(r: &[u8], i: &[u8], v: &[u8], flag: u8, original: &[u8], use_7zip: bool) -> Result<Vec<u8>, String> {
+    let mut h = Hasher::new();
+    h.update(original);
+    let expected_crc = h.finalize();
+
+    let decompressor = CASTLzmaDecompressor::new(lzma_decompressor(use_7zip));
+    let mut restored = Vec::with_capacity(original.len());
+    decompressor.decompress(r, i, v, Some(expected_crc), flag, &mut restored).map_err(|e| e.to_string())?;
+    Ok(restored)
+}
+
+fn decompress_brotli(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    brotli::Decompressor::new(data, 4096).read_to_end(&mut out).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+fn decompress_zstd(data: &[u8]) -> Result<Vec<u8>, String> {
+    zstd::stream::decode_all(data).map_err(|e| e.to_string())
+}
+
 fn compress_brotli_max(data: &[u8]) -> Vec<u8> {
     let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
     writer.write_all(data).unwrap();
@@ -507,6 +677,7 @@ fn compress_zstd_max(data: &[u8], multithread: bool) -> Vec<u8> {
     encoder.finish().unwrap()
 }
 
+#[allow(clippy::is_digit_ascii_radix)]
 fn parse_size(input: &str) -> Option<usize> {
     let input = input.trim().to_uppercase();
     let digits: String = input.chars().take_while(|c| c.is_digit(10)).collect();
@@ -552,20 +723,13 @@ fn print_bench_usage(exe_name: &str) {
         Author: Andrea Olivari\n\
         GitHub: https://github.com/AndreaLVR/CAST\n\n\
         Usage:\n  \
-          {} --list <LIST> --compare-with <ALGOS> [OPTIONS]\n\n\
-        Arguments:\n  \
-          --list <file.txt>      File containing a list of paths to test (one per line)\n  \
-          --compare-with <algos> Comma-separated list of competitors (e.g. 'lzma2,zstd')\n                         or 'all' for [lzma2, brotli, zstd]\n\n\
-        Options:\n  \
-          --mode <TYPE>          Backend selection: 'native' or '7zip' (Default: 7zip for compression, Native for decompression)\n  \
-          --multithread          Enable multithreading compression for CAST and competitors\n  \
-          --chunk-size <SIZE>    Split input in chunks (Compression RAM Saver) (e.g., 512MB). Default: Solid Mode\n  \
-          --dict-size <SIZE>     Set LZMA Dictionary Size (Default: 128MB)\n  \
-          -h, --help             Show this help message\n\n\
+          {} [--list <LIST>] --compare-with <ALGOS> [OPTIONS]\n\n\
+        Options:\n{}\n\
         Examples:\n  \
+          {} --compare-with lzma2\n  \
           {} --list datasets.txt --compare-with lzma2 --multithread\n  \
           {} --list big_logs.txt --compare-with all --chunk-size 512MB --dict-size 256MB",
         env!("CARGO_PKG_VERSION"),
-        exe_name, exe_name, exe_name
+        exe_name, cli::render_options(Binary::RunBenchmarks), exe_name, exe_name, exe_name
     );
 }
\ No newline at end of file
@@ -0,0 +1,69 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::CastError;
+
+// ============================================================================
+//  COOPERATIVE CANCELLATION
+// ============================================================================
+
+/// Shared stop flag for long operations. Clones observe the same flag: the embedder (or a
+/// Ctrl+C handler) calls `cancel`, and the operation notices it at the next chunk boundary
+/// or between backend calls and returns `CastError::Cancelled`.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// `Err(CastError::Cancelled)` once `cancel` has been called.
+    pub fn check(&self) -> Result<(), CastError> {
+        if self.is_cancelled() { Err(CastError::Cancelled) } else { Ok(()) }
+    }
+}
+
+/// What happens to a partially written output when an operation is cancelled (--on-cancel).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CancelPolicy {
+    Delete,
+    /// The output stops after the last complete chunk (a valid, shorter archive when compressing)
+    Keep,
+}
+
+impl CancelPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "delete" => Some(CancelPolicy::Delete),
+            "keep" => Some(CancelPolicy::Keep),
+            _ => None,
+        }
+    }
+
+    /// Applies the policy to the closed output at `path`: removes it, or keeps it cut back to
+    /// its first `complete` bytes (the chunks finished before the cancellation; `None` keeps it
+    /// as it is, e.g. an archive whose writer only ever wrote whole chunks).
+    pub fn apply(self, path: &Path, complete: Option<u64>) -> io::Result<()> {
+        match (self, complete) {
+            (CancelPolicy::Delete, _) => match std::fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            (CancelPolicy::Keep, Some(len)) => std::fs::OpenOptions::new().write(true).open(path).and_then(|f| f.set_len(len)),
+            (CancelPolicy::Keep, None) => Ok(()),
+        }
+    }
+}
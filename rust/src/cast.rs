@@ -1,8 +1,15 @@
 use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher as _};
 use std::borrow::Cow;
+use std::cmp;
+use std::ops::Range;
 use std::io::{Write, BufWriter};
 use crc32fast::Hasher;
 use memchr::memchr2;
+
+use crate::auth::crc_eq;
+use crate::cancel::CancellationToken;
+use crate::error::CastError;
 //use std::time::Instant; // only for benchmarks
 
 // ============================================================================
@@ -10,11 +17,47 @@ use memchr::memchr2;
 // ============================================================================
 
 pub trait NativeCompressor {
-    fn compress(&self, data: &[u8]) -> Vec<u8>;
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError>;
+
+    /// Largest output `compress` can return for `input_len` bytes, or `None` when the backend
+    /// has no such bound. With a bound, `CASTCompressor` stores a chunk whose columnar form
+    /// would come out larger as passthrough, so no chunk exceeds it (see
+    /// `archive::max_compressed_size`).
+    fn max_compressed_len(&self, _input_len: usize) -> Option<usize> { None }
+
+    /// Token the backend stops on (see `LzmaBackend::with_cancellation`). `CASTCompressor`
+    /// checks it too while it parses a chunk and before each backend call, so one token on the
+    /// backend cancels the whole chunk.
+    fn cancellation(&self) -> Option<&CancellationToken> { None }
+}
+
+impl<T: NativeCompressor + ?Sized> NativeCompressor for &T {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> { (**self).compress(data) }
+    fn max_compressed_len(&self, input_len: usize) -> Option<usize> { (**self).max_compressed_len(input_len) }
+    fn cancellation(&self) -> Option<&CancellationToken> { (**self).cancellation() }
 }
 
 pub trait NativeDecompressor {
-    fn decompress(&self, data: &[u8]) -> Vec<u8>;
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError>;
+
+    /// Like `decompress`, calling `progress` with the number of input bytes consumed so far.
+    /// Backends that cannot observe their input (e.g. external processes) report nothing.
+    fn decompress_with_progress(&self, data: &[u8], _progress: &dyn Fn(u64)) -> Result<Vec<u8>, CastError> {
+        self.decompress(data)
+    }
+}
+
+/// Pass-through backend: segments are stored as-is. Isolates the cost of the CAST
+/// transform itself from the entropy coder (see `cast bench-self`). It declares no size
+/// bound: a columnar chunk stored as is is usually larger than its input.
+pub struct IdentityBackend;
+
+impl NativeCompressor for IdentityBackend {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> { Ok(data.to_vec()) }
+}
+
+impl NativeDecompressor for IdentityBackend {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> { Ok(data.to_vec()) }
 }
 
 // ============================================================================
@@ -22,23 +65,30 @@ pub trait NativeDecompressor {
 // ============================================================================
 
 // Safe Placeholders (Private Use Area)
-const VAR_PLACEHOLDER: char = '\u{E000}';
+pub(crate) const VAR_PLACEHOLDER: char = '\u{E000}';
 const VAR_PLACEHOLDER_STR: &str = "\u{E000}";
 const VAR_PLACEHOLDER_QUOTE: &str = "\"\u{E000}\"";
 const REG_SEPARATOR: &str = "\u{E001}";
-
+// Written over recycled column buffers in debug builds (see "BLOCK STATE REUSE")
+const BUFFER_POISON: u8 = 0xAA;
+// Input parsed between two checks of the backend's cancellation token (as much as the
+// decompressor rebuilds between two checks)
+const CANCEL_CHECK_INTERVAL: usize = 512 * 1024;
 
 #[derive(Clone)]
 struct ColumnBuffer {
     data: Vec<u8>,
-    offsets: Vec<usize>
+    offsets: Vec<usize>,
+    // Distinct values seen so far; dropped once the column exceeds DICT_MAX_DISTINCT
+    distinct: Option<HashSet<Vec<u8>>>
 }
 
 impl ColumnBuffer {
     fn new() -> Self {
         Self {
             data: Vec::new(),
-            offsets: Vec::new()
+            offsets: Vec::new(),
+            distinct: Some(HashSet::new())
         }
     }
 
@@ -46,6 +96,29 @@ impl ColumnBuffer {
     fn push(&mut self, s: &str) {
         self.data.extend_from_slice(s.as_bytes());
         self.offsets.push(self.data.len());
+        if let Some(set) = &mut self.distinct {
+            if !set.contains(s.as_bytes()) {
+                if set.len() >= DICT_MAX_DISTINCT { self.distinct = None; }
+                else { set.insert(s.as_bytes().to_vec()); }
+            }
+        }
+    }
+
+    /// Empties the column for the next block, keeping its allocations. Debug builds first
+    /// poison the whole allocation, so bytes that survive the clear show up as BUFFER_POISON.
+    fn clear(&mut self) {
+        #[cfg(debug_assertions)]
+        {
+            self.data.fill(BUFFER_POISON);
+            for byte in self.data.spare_capacity_mut() { byte.write(BUFFER_POISON); }
+            self.offsets.fill(usize::MAX);
+        }
+        self.data.clear();
+        self.offsets.clear();
+        match &mut self.distinct {
+            Some(set) => set.clear(),
+            None => self.distinct = Some(HashSet::new()),
+        }
     }
 
     #[inline(always)]
@@ -60,6 +133,26 @@ impl ColumnBuffer {
     fn len(&self) -> usize {
         self.offsets.len()
     }
+
+    /// Returns the distinct values (in order of first appearance) and one index per row when
+    /// the column is low-cardinality enough to be a dictionary candidate.
+    fn dictionary_encode(&self) -> Option<(Vec<&[u8]>, Vec<u8>)> {
+        let distinct = self.distinct.as_ref()?;
+        let rows = self.len();
+        if rows < DICT_MIN_ROWS || distinct.len() * DICT_MIN_REPEAT > rows { return None; }
+        let dict_bytes: usize = distinct.iter().map(|v| v.len()).sum();
+        if self.data.len() <= rows + dict_bytes { return None; }
+
+        let mut values: Vec<&[u8]> = Vec::with_capacity(distinct.len());
+        let mut lookup: HashMap<&[u8], u8> = HashMap::with_capacity(distinct.len());
+        let mut indices = Vec::with_capacity(rows);
+        for idx in 0..rows {
+            let v = self.get(idx);
+            let i = *lookup.entry(v).or_insert_with(|| { values.push(v); (values.len() - 1) as u8 });
+            indices.push(i);
+        }
+        Some((values, indices))
+    }
 }
 
 
@@ -67,32 +160,48 @@ impl ColumnBuffer {
 enum ParsingMode { Strict, Aggressive }
 
 #[inline(always)]
+#[allow(clippy::manual_range_contains)]
 fn is_digit(b: u8) -> bool { b >= b'0' && b <= b'9' }
 
 #[inline(always)]
+#[allow(clippy::manual_range_contains)]
 fn is_hex_digit(b: u8) -> bool {
     (b >= b'0' && b <= b'9') || (b >= b'a' && b <= b'f') || (b >= b'A' && b <= b'F')
 }
 
 #[inline(always)]
+#[allow(clippy::manual_range_contains)]
 fn is_aggr_char(b: u8) -> bool {
     (b >= b'a' && b <= b'z') || (b >= b'A' && b <= b'Z') ||
     (b >= b'0' && b <= b'9') || b == b'_' || b == b'.' || b == b'-' || b == b':'
 }
 
-// Helper per Binary Guard
-#[inline(always)]
+// ============================================================================
+//  BINARY GUARD
+// ============================================================================
+//
+//  Chunks that are not text are stored as passthrough before any parsing. Only the first
+//  BINARY_SAMPLE_LEN bytes are looked at. A byte counts as a control byte when it is below
+//  0x20 and is not whitespace (\t \n \v \f \r). DEL (0x7F) and bytes from 0x80 up (UTF-8,
+//  Latin-1) are text. ESC followed by '[' opens an ANSI escape sequence (terminal colors in
+//  logs) and is text too. A sample with more than BINARY_CONTROL_RATIO control bytes is
+//  binary. NUL-padded records and UTF-16 text (a NUL in every other byte) are binary under
+//  this rule, because the tokenizer works on bytes.
+//  py/cast.py and rust_random_access_PREVIEW keep copies of this rule that must stay in sync.
+
+const BINARY_SAMPLE_LEN: usize = 4096;
+const BINARY_CONTROL_RATIO: f64 = 0.01;
+
+/// Whether `data` looks like binary data rather than text (see BINARY GUARD).
 fn is_likely_binary(data: &[u8]) -> bool {
-    let limit = std::cmp::min(data.len(), 4096);
-    let sample = &data[..limit];
-    let mut control_count = 0;
-    for &b in sample {
-        // 0..8 (Bin), 9..13 (Space safe), 14..31 (Bin), 127 (DEL safe-ish)
-        if b < 9 || (b > 13 && b < 32) {
-            control_count += 1;
-        }
-    }
-    (control_count as f64 / limit as f64) > 0.01
+    let sample = &data[..cmp::min(data.len(), BINARY_SAMPLE_LEN)];
+    if sample.is_empty() { return false; }
+    let control_count = sample.iter().enumerate().filter(|&(i, &b)| match b {
+        b'\t' | b'\n' | 0x0B | 0x0C | b'\r' => false,
+        0x1B => sample.get(i + 1) != Some(&b'['),
+        _ => b < 0x20,
+    }).count();
+    (control_count as f64 / sample.len() as f64) > BINARY_CONTROL_RATIO
 }
 
 #[inline(always)]
@@ -102,11 +211,9 @@ fn match_strict_number(bytes: &[u8]) -> usize {
     if i < len && bytes[i] == b'-' { i += 1; }
     if i >= len || !is_digit(bytes[i]) { return 0; }
     while i < len && is_digit(bytes[i]) { i += 1; }
-    if i + 1 < len && bytes[i] == b'.' {
-        if is_digit(bytes[i+1]) {
-            i += 2;
-            while i < len && is_digit(bytes[i]) { i += 1; }
-        }
+    if i + 1 < len && bytes[i] == b'.' && is_digit(bytes[i+1]) {
+        i += 2;
+        while i < len && is_digit(bytes[i]) { i += 1; }
     }
     i
 }
@@ -122,8 +229,80 @@ fn match_strict_hex(bytes: &[u8]) -> usize {
     0
 }
 
+fn match_digits(bytes: &[u8], at: usize, count: usize) -> bool {
+    bytes.len() >= at + count && bytes[at..at + count].iter().all(|&b| is_digit(b))
+}
+
+/// Length of the timestamp at the start of `bytes`: `YYYY-MM-DD`, `HH:MM:SS[.frac]` or both
+/// joined by `T`, then an optional `Z` or `+HH:MM`/`-HH:MM` zone. 0 when there is none.
+fn match_timestamp(bytes: &[u8]) -> usize {
+    let mut i = 0;
+    if match_digits(bytes, 0, 4) && bytes.get(4) == Some(&b'-') && match_digits(bytes, 5, 2)
+        && bytes.get(7) == Some(&b'-') && match_digits(bytes, 8, 2) {
+        i = 10;
+        if bytes.get(i) != Some(&b'T') { return i; }
+        i += 1;
+    }
+    let time = &bytes[i..];
+    if !(match_digits(time, 0, 2) && time.get(2) == Some(&b':') && match_digits(time, 3, 2)
+        && time.get(5) == Some(&b':') && match_digits(time, 6, 2)) {
+        // Also rejects a date followed by a 'T' that does not start a time
+        return 0;
+    }
+    i += 8;
+    if bytes.get(i) == Some(&b'.') && bytes.get(i + 1).is_some_and(|&b| is_digit(b)) {
+        i += 1;
+        while i < bytes.len() && is_digit(bytes[i]) { i += 1; }
+    }
+    match bytes.get(i) {
+        Some(b'Z') => i + 1,
+        Some(b'+') | Some(b'-') if match_digits(bytes, i + 1, 2) && bytes.get(i + 3) == Some(&b':') && match_digits(bytes, i + 4, 2) => i + 6,
+        _ => i,
+    }
+}
+
+/// Where an aggressive token has to end because a timestamp at its start runs into an
+/// identifier ("2024-11-18T09:45:12-req-4711"): the end of the timestamp when it is followed
+/// by punctuation and more token, `None` when the token is kept whole.
+fn timestamp_cut(token: &[u8]) -> Option<usize> {
+    let end = match_timestamp(token);
+    if end == 0 || end + 1 >= token.len() { return None; }
+    if token[end].is_ascii_alphanumeric() { return None; }
+    Some(end)
+}
+
+/// Default longest value captured as a variable (`CASTCompressor::with_max_var_len`)
+pub const DEFAULT_MAX_VAR_LEN: usize = 1024 * 1024;
+
+/// Length of the quoted string at the start of `bytes` (which is a '"'), both quotes included,
+/// or `None` when it is not closed on this line.
+///
+/// Inside the quotes a backslash escapes the next byte, so `\"` does not close the string, and
+/// a doubled quote `""` is content (CSV style). The string ends at the first other quote. So:
+/// - `""` is an empty string: an empty variable, restored as `""` by its quoted placeholder
+/// - `"a"""` holds `a""`, while in `"a""` the final pair is content and the string stays open
+/// - a backslash as the last byte of the line has nothing to escape: the string stays open
+///
+/// An open quote is not a string: it stays in the skeleton and parsing resumes after it.
+fn quoted_len(bytes: &[u8]) -> Option<usize> {
+    let mut k = 1;
+    while k < bytes.len() {
+        match bytes[k] {
+            b'"' if bytes.get(k + 1) == Some(&b'"') => k += 2,
+            b'"' => return Some(k + 1),
+            b'\\' if k + 1 < bytes.len() => k += 2,
+            b'\\' => return None,
+            _ => k += 1,
+        }
+    }
+    None
+}
+
+/// Splits `line` into its skeleton and variables. Quoted strings and tokens longer than
+/// `max_var_len` are left in the skeleton (counted in `long_values`), so no cell grows without
+/// bound when a line embeds a large payload.
 #[inline(never)]
-fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec<&'a str>, buffer_skel: &mut String) -> bool {
+fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, split_timestamps: bool, max_var_len: usize, long_values: &mut u64, buffer_vars: &mut Vec<&'a str>, buffer_skel: &mut String) -> bool {
     // FAIL-SAFE: Collision detection
     if line.contains(VAR_PLACEHOLDER) || line.contains(REG_SEPARATOR) {
         return false;
@@ -139,31 +318,14 @@ fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec
 
         // 1. QUOTED STRING
         if b == b'"' {
-            let mut k = 1;
-            let mut closed = false;
-            let remaining = &bytes[i..];
-
-            while k < remaining.len() {
-                let curr = remaining[k];
-                if curr == b'"' {
-                    if k + 1 < remaining.len() && remaining[k+1] == b'"' {
-                         k += 2;
-                    } else {
-                        k += 1;
-                        closed = true;
-                        break;
-                    }
-                } else if curr == b'\\' {
-                    k += 2;
-                } else {
-                    k += 1;
+            if let Some(matched_len) = quoted_len(&bytes[i..]) {
+                // Both quotes are ASCII, so the content starts and ends on char boundaries
+                let content = &line[i+1 .. i+matched_len-1];
+                if content.len() > max_var_len {
+                    *long_values += 1;
+                    i += matched_len;
+                    continue;
                 }
-            }
-
-            if closed {
-                let matched_len = k;
-                let end_content = if matched_len > 1 { matched_len - 1 } else { 1 };
-                let content = &line[i+1 .. i+end_content];
 
                 if i > last_struct_start { buffer_skel.push_str(&line[last_struct_start..i]); }
                 buffer_vars.push(content);
@@ -177,6 +339,8 @@ fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec
 
         // 2. TOKENS
         let mut matched_len = 0;
+        // Punctuation between a cut timestamp and the rest of its token, kept in the skeleton
+        let mut separator_len = 0;
         let remaining = &bytes[i..];
 
         if mode == ParsingMode::Aggressive {
@@ -184,6 +348,12 @@ fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec
                 let mut k = 1;
                 while k < remaining.len() && is_aggr_char(remaining[k]) { k += 1; }
                 matched_len = k;
+                if split_timestamps {
+                    if let Some(cut) = timestamp_cut(&remaining[..k]) {
+                        matched_len = cut;
+                        separator_len = 1;
+                    }
+                }
             }
         } else {
             matched_len = match_strict_hex(remaining);
@@ -192,7 +362,10 @@ fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec
             }
         }
 
-        if matched_len > 0 {
+        if matched_len > max_var_len {
+            *long_values += 1;
+            i += matched_len;
+        } else if matched_len > 0 {
             if i > last_struct_start { buffer_skel.push_str(&line[last_struct_start..i]); }
 
             let token = &line[i .. i+matched_len];
@@ -201,6 +374,7 @@ fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec
 
             i += matched_len;
             last_struct_start = i;
+            i += separator_len;
         } else {
             i += 1;
         }
@@ -213,65 +387,1470 @@ fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec
     true
 }
 
-// ============================================================================
-//  UTILS
-// ============================================================================
+// ============================================================================
+//  UTILS
+// ============================================================================
+
+fn decode_python_latin1(data: &[u8]) -> String {
+    data.iter().map(|&b| b as char).collect()
+}
+
+/// Re-encodes `buf[start..]` (UTF-8 text of a Latin-1 chunk) back to one byte per char.
+fn utf8_tail_to_latin1(buf: &mut Vec<u8>, start: usize) {
+    if buf[start..].is_ascii() { return; }
+    let tail = buf.split_off(start);
+    match std::str::from_utf8(&tail) {
+        Ok(s) => buf.extend(s.chars().map(|c| c as u8)),
+        Err(_) => buf.extend_from_slice(&tail),
+    }
+}
+
+// ============================================================================
+//  SPARSE PASSTHROUGH
+// ============================================================================
+//
+//  Passthrough chunks with long zero runs are stored as records
+//  [literal len varint][literal][zero run len varint], so gigabytes of zeros in
+//  disk images never reach the backend. Zero runs are detected on whole blocks.
+
+const SPARSE_BLOCK: usize = 4096;
+// Shorter runs are left to the backend (which compresses them well anyway)
+const SPARSE_MIN_RUN: usize = 16 * SPARSE_BLOCK;
+
+/// Record stream of `data`, or `None` when it has no zero run worth skipping.
+fn encode_sparse(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut lit_start = 0;
+    let mut pos = 0;
+    let mut found = false;
+    while pos + SPARSE_BLOCK <= data.len() {
+        if data[pos..pos + SPARSE_BLOCK].iter().any(|&b| b != 0) { pos += SPARSE_BLOCK; continue; }
+        let run_start = pos;
+        while pos + SPARSE_BLOCK <= data.len() && data[pos..pos + SPARSE_BLOCK].iter().all(|&b| b == 0) { pos += SPARSE_BLOCK; }
+        if pos - run_start < SPARSE_MIN_RUN { continue; }
+        push_varint(&mut out, (run_start - lit_start) as u64);
+        out.extend_from_slice(&data[lit_start..run_start]);
+        push_varint(&mut out, (pos - run_start) as u64);
+        lit_start = pos;
+        found = true;
+    }
+    if !found { return None; }
+    push_varint(&mut out, (data.len() - lit_start) as u64);
+    out.extend_from_slice(&data[lit_start..]);
+    push_varint(&mut out, 0);
+    Some(out)
+}
+
+// ============================================================================
+//  TEMPLATE ID STREAM (RLE)
+// ============================================================================
+
+// id_flag layout: bits 0-2 = id mode, 0x08 = front-coded registry, 0x10 = column streams,
+// 0x20 = column dictionaries, 0x80 = Latin-1. 0x40 is never set on a data chunk, which keeps
+// every combination of the bits above under the chunk kinds holding no template block:
+// 255 = Passthrough, 254 = Sparse passthrough, 253 = Duplicate of an earlier chunk, resolved by
+// the archive reader, 252 = Authentication trailer, never a data chunk: see auth.rs.
+// id modes: 0 = u16 ids, 1 = u32 ids, 2 = u8 ids, 3 = single template (modes 0-3 are version 1,
+// read only), 4 = compact single template: backend([rows varint][skeleton len varint][skeleton]
+// [vars]), 5-7 = modes 0-2 with a cell count section in front of the vars region (see CELL
+// COUNTS) and an encoding byte in front of the id stream: 0 = raw ids, 1 = RLE (id, varint run
+// length) pairs, 2 = transformed ids (see ID STREAM TRANSFORMS)
+//
+// Limits per block: ids are u8 up to 255 templates, u16 up to U16_MAX_TEMPLATES and u32
+// beyond, so the format holds any template count. In practice a chunk whose distinct templates
+// pass STRICT_TEMPLATE_RATIO of its lines (AGGRESSIVE_TEMPLATE_RATIO in Aggressive mode) is
+// stored as passthrough ("Passthrough [Entropy]"), so u32 ids need about 262,144 lines
+// (163,840 in Aggressive mode) in one chunk. Rows are bounded by the u32 length fields: the
+// compact row count, and a raw id stream of at most 4GB (1,073,741,823 rows with u32 ids).
+
+/// Most templates a block can number with u16 ids (id mode 0); more take u32 ids (id mode 1)
+pub const U16_MAX_TEMPLATES: usize = 65535;
+/// Share of distinct templates among a chunk's lines past which it is stored as passthrough
+pub const STRICT_TEMPLATE_RATIO: f64 = 0.25;
+pub const AGGRESSIVE_TEMPLATE_RATIO: f64 = 0.40;
+
+/// About the fewest lines a chunk needs to keep `templates` distinct templates instead of
+/// falling back to passthrough.
+pub fn min_lines_for_templates(templates: usize, aggressive: bool) -> u64 {
+    let ratio = if aggressive { AGGRESSIVE_TEMPLATE_RATIO } else { STRICT_TEMPLATE_RATIO };
+    (templates as f64 / ratio).ceil() as u64
+}
+
+pub const ID_FLAG_PASSTHROUGH: u8 = 255;
+pub const ID_FLAG_SPARSE: u8 = 254;
+pub const ID_FLAG_DUPLICATE: u8 = 253;
+pub const ID_FLAG_MAC_TRAILER: u8 = 252;
+const ID_MODE_MASK: u8 = 0x07;
+const ID_MODE_CELL_COUNTS: u8 = 5;
+const FLAG_REG_FRONT_CODED: u8 = 0x08;
+const FLAG_COLUMN_STREAMS: u8 = 0x10;
+const FLAG_DICT_COLS: u8 = 0x20;
+// Never set on a data chunk (see above)
+const FLAG_RESERVED: u8 = 0x40;
+const FLAG_LATIN1: u8 = 0x80;
+// Encoding byte of the id stream of modes 5-7
+const IDS_RAW: u8 = 0;
+const IDS_RLE: u8 = 1;
+const IDS_TRANSFORMED: u8 = 2;
+
+pub fn is_passthrough(id_flag: u8) -> bool {
+    id_flag == ID_FLAG_PASSTHROUGH || id_flag == ID_FLAG_SPARSE
+}
+
+/// Id mode of a data chunk, with modes 5-7 folded onto the plain modes 0-2.
+fn id_mode_of(id_flag: u8) -> u8 {
+    match id_flag & ID_MODE_MASK {
+        m if m >= ID_MODE_CELL_COUNTS => m - ID_MODE_CELL_COUNTS,
+        m => m,
+    }
+}
+
+/// `id_flag` of a data chunk as composed by the compressor, or `Internal` when it would read as
+/// one of the chunk kinds from 252 up (which the layout rules out).
+fn checked_data_flag(id_flag: u8) -> Result<u8, CastError> {
+    if id_flag & FLAG_RESERVED != 0 || id_flag >= ID_FLAG_MAC_TRAILER {
+        return Err(CastError::Internal { detail: format!("data chunk composed with the reserved id_flag 0x{:02x}", id_flag) });
+    }
+    Ok(id_flag)
+}
+
+fn has_cell_counts(id_flag: u8) -> bool {
+    (id_flag & ID_MODE_MASK) >= ID_MODE_CELL_COUNTS
+}
+
+/// Readable meaning of an id_flag byte (e.g. "u16 ids, RLE ids").
+pub fn describe_id_flag(id_flag: u8) -> String {
+    match id_flag {
+        ID_FLAG_PASSTHROUGH => return "passthrough".to_string(),
+        ID_FLAG_SPARSE => return "sparse passthrough".to_string(),
+        ID_FLAG_DUPLICATE => return "duplicate chunk".to_string(),
+        ID_FLAG_MAC_TRAILER => return "authentication trailer".to_string(),
+        _ => {},
+    }
+    let mut parts = vec![match id_mode_of(id_flag) {
+        0 => "u16 ids".to_string(),
+        1 => "u32 ids".to_string(),
+        2 => "u8 ids".to_string(),
+        3 => "single template (legacy)".to_string(),
+        4 => "compact single template".to_string(),
+        m => format!("unknown id mode {}", m),
+    }];
+    if has_cell_counts(id_flag) { parts.push("cell counts".to_string()); }
+    for (flag, name) in [(FLAG_REG_FRONT_CODED, "front-coded registry"), (FLAG_COLUMN_STREAMS, "column streams"), (FLAG_DICT_COLS, "column dictionaries"), (FLAG_LATIN1, "Latin-1")] {
+        if id_flag & flag != 0 { parts.push(name.to_string()); }
+    }
+    parts.join(", ")
+}
+
+/// Fields of a backend-decoded block in front of the vars: `len_reg u32 | len_ids u32 | reg |
+/// ids` (in id mode 3 the second field is the row count and there are no ids), or
+/// `rows varint | len_reg varint | reg` in the compact single-template mode. The vars follow
+/// the ids.
+struct BlockHead {
+    /// (name, position, value) of the length fields
+    fields: Vec<(&'static str, Range<usize>, u64)>,
+    reg: Range<usize>,
+    ids: Range<usize>,
+    /// Row count of the single-template modes (0 otherwise)
+    rows: u32,
+}
+
+/// (start, end) of every column of a vars stream, separators excluded. `expected` (the
+/// placeholder count of the registry) only sizes the result.
+fn column_ranges(vars: &[u8], expected: usize) -> Vec<(usize, usize)> {
+    let (col_sep, esc_byte) = (0x02u8, 0x01u8);
+    let mut ranges = Vec::with_capacity(cmp::min(expected, vars.len() / 20 + 1));
+    let mut start = 0;
+    let mut cursor = 0;
+    while cursor < vars.len() {
+        match memchr2(col_sep, esc_byte, &vars[cursor..]) {
+            Some(pos) => {
+                let real_pos = cursor + pos;
+                if vars[real_pos] == esc_byte {
+                    cursor = real_pos + 2;
+                } else {
+                    ranges.push((start, real_pos));
+                    cursor = real_pos + 1;
+                    start = cursor;
+                }
+            },
+            None => { cursor = vars.len(); }
+        }
+    }
+    if start < vars.len() { ranges.push((start, vars.len())); }
+    ranges
+}
+
+/// Row count of a legacy id mode 3 block. It was stored (in place of len_ids) only when the
+/// template had no vars, 0 otherwise: the count then comes from the cells of the first column.
+/// New archives use id mode 4, whose row count has its own field.
+fn legacy_single_template_rows(stored: u32, first_column: Option<&[u8]>) -> u32 {
+    let Some(column) = first_column.filter(|_| stored == 0) else { return stored };
+    let (row_sep, esc_byte) = (0x00u8, 0x01u8);
+    let mut n = 0;
+    let mut pos = 0;
+    while pos < column.len() {
+        match memchr2(row_sep, esc_byte, &column[pos..]) {
+            Some(p) => {
+                let real = pos + p;
+                if column[real] == esc_byte { pos = real + 2; } else { n += 1; pos = real + 1; }
+            },
+            None => break,
+        }
+    }
+    if !column.is_empty() && column[column.len() - 1] != row_sep
+        && (column.len() < 2 || column[column.len() - 2] != esc_byte) { n += 1; }
+    n
+}
+
+fn block_head(block: &[u8], id_mode: u8) -> Result<BlockHead, CastError> {
+    let corrupt = |detail: &str| CastError::CorruptHeader { chunk: 0, detail: detail.to_string() };
+    let overflow = |detail: &str| CastError::LengthOverflow { chunk: 0, detail: detail.to_string() };
+    if id_mode == 4 {
+        let mut pos = 0;
+        let rows = read_varint(block, &mut pos).and_then(|n| u32::try_from(n).ok()).ok_or_else(|| corrupt("Compact row count"))?;
+        let rows_end = pos;
+        let lr = read_varint(block, &mut pos).ok_or_else(|| corrupt("Compact skeleton length"))? as usize;
+        let reg_end = pos.checked_add(lr).filter(|&e| e <= block.len()).ok_or_else(|| overflow("Registry length"))?;
+        let fields = vec![("rows", 0..rows_end, rows as u64), ("len_reg", rows_end..pos, lr as u64)];
+        return Ok(BlockHead { fields, reg: pos..reg_end, ids: reg_end..reg_end, rows });
+    }
+
+    if block.len() < 8 { return Err(corrupt("Unified header too short")); }
+    let lr = u32::from_le_bytes(block[0..4].try_into().unwrap());
+    let li = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    let reg_end = 8usize.checked_add(lr as usize).filter(|&e| e <= block.len()).ok_or_else(|| overflow("Registry length"))?;
+    if id_mode == 3 {
+        let fields = vec![("len_reg", 0..4, lr as u64), ("rows", 4..8, li as u64)];
+        return Ok(BlockHead { fields, reg: 8..reg_end, ids: reg_end..reg_end, rows: li });
+    }
+    let ids_end = reg_end.checked_add(li as usize).filter(|&e| e <= block.len()).ok_or_else(|| overflow("IDs length"))?;
+    let fields = vec![("len_reg", 0..4, lr as u64), ("len_ids", 4..8, li as u64)];
+    Ok(BlockHead { fields, reg: 8..reg_end, ids: reg_end..ids_end, rows: 0 })
+}
+
+// RLE is only chosen when runs are long enough to beat the raw stream
+const RLE_MIN_AVG_RUN: usize = 4;
+
+fn id_width(id_mode: u8) -> usize {
+    match id_mode { 2 => 1, 1 => 4, _ => 2 }
+}
+
+/// `len` as a 32-bit length field, or `LengthOverflow` (the caller splits the chunk) instead
+/// of a truncated value.
+pub(crate) fn u32_len(len: usize, what: &str) -> Result<u32, CastError> {
+    u32::try_from(len).map_err(|_| CastError::LengthOverflow { chunk: 0, detail: format!("{} of {} bytes exceeds the 4GB length field", what, len) })
+}
+
+fn push_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 { out.push((v as u8) | 0x80); v >>= 7; }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut v = 0u64;
+    let mut shift = 0;
+    loop {
+        let b = *data.get(*pos)?;
+        *pos += 1;
+        if shift > 63 { return None; }
+        v |= ((b & 0x7F) as u64) << shift;
+        if b & 0x80 == 0 { return Some(v); }
+        shift += 7;
+    }
+}
+
+/// Encodes ids as the encoding byte and (id, varint run length) pairs. Returns `None` when the
+/// average run is too short for RLE to pay off (e.g. alternating ids).
+fn encode_ids_rle<T: Copy + PartialEq + Into<u32>>(ids: &[T], width: usize) -> Option<Vec<u8>> {
+    if ids.is_empty() { return None; }
+    let runs = 1 + ids.windows(2).filter(|w| w[0] != w[1]).count();
+    if ids.len() / runs < RLE_MIN_AVG_RUN { return None; }
+
+    let mut out = Vec::with_capacity(1 + runs * (width + 2));
+    out.push(IDS_RLE);
+    let mut i = 0;
+    while i < ids.len() {
+        let id = ids[i];
+        let mut run = 1;
+        while i + run < ids.len() && ids[i + run] == id { run += 1; }
+        out.extend_from_slice(&id.into().to_le_bytes()[..width]);
+        push_varint(&mut out, run as u64);
+        i += run;
+    }
+    Some(out)
+}
+
+/// Expands (id, run length) pairs into `out`. Returns `None` on a truncated pair or once the
+/// runs add up to more than `max_rows`, before the rows are allocated.
+fn decode_ids_rle(data: &[u8], width: usize, max_rows: u64, out: &mut Vec<usize>) -> Option<()> {
+    let mut pos = 0;
+    let mut total = 0u64;
+    while pos < data.len() {
+        if pos + width > data.len() { return None; }
+        let mut id_bytes = [0u8; 4];
+        id_bytes[..width].copy_from_slice(&data[pos..pos + width]);
+        pos += width;
+        let run = read_varint(data, &mut pos)?;
+        total = total.checked_add(run).filter(|&t| t <= max_rows)?;
+        let run = run as usize;
+        out.extend(std::iter::repeat_n(u32::from_le_bytes(id_bytes) as usize, run));
+    }
+    Some(())
+}
+
+/// Encoding byte of a chunk's decoded id stream and the offset of its ids (the raw ids of the
+/// version 1 modes 0-2 have no encoding byte).
+fn ids_encoding_of(ids_data_bytes: &[u8], id_flag_raw: u8) -> Result<(u8, usize), CastError> {
+    if !has_cell_counts(id_flag_raw) { return Ok((IDS_RAW, 0)); }
+    match ids_data_bytes.first() {
+        Some(&encoding @ (IDS_RAW | IDS_RLE | IDS_TRANSFORMED)) => Ok((encoding, 1)),
+        Some(&encoding) => Err(CastError::CorruptHeader { chunk: 0, detail: format!("unknown id stream encoding {}", encoding) }),
+        None => Err(CastError::CorruptHeader { chunk: 0, detail: "id stream without encoding byte".to_string() }),
+    }
+}
+
+/// Template id of every row from a chunk's id stream (empty for single-template chunks).
+/// An RLE stream of more than `max_rows` rows is corrupt.
+fn decode_template_ids(ids_data_bytes: &[u8], id_flag_raw: u8, max_rows: u64) -> Result<Vec<usize>, CastError> {
+    let id_flag = id_mode_of(id_flag_raw);
+    if id_flag == 3 || id_flag == 4 { return Ok(Vec::new()); }
+    let width = id_width(id_flag);
+    let (encoding, start) = ids_encoding_of(ids_data_bytes, id_flag_raw)?;
+    let mut payload = &ids_data_bytes[start..];
+    // One id per `width` bytes in a raw stream; RLE streams hold more and grow the vector
+    let mut template_ids = Vec::with_capacity(payload.len() / width);
+    match encoding {
+        IDS_RLE => {
+            decode_ids_rle(payload, width, max_rows, &mut template_ids)
+                .ok_or_else(|| CastError::CorruptHeader { chunk: 0, detail: "RLE id stream (truncated, or more rows than the chunk holds)".to_string() })?;
+            return Ok(template_ids);
+        },
+        IDS_TRANSFORMED => {
+            let transform = payload.first().copied().and_then(IdsTransform::from_byte)
+                .ok_or_else(|| CastError::CorruptHeader { chunk: 0, detail: "unknown id stream transform".to_string() })?;
+            payload = &payload[1..];
+            if !payload.len().is_multiple_of(width) { return Err(CastError::CorruptHeader { chunk: 0, detail: "transformed id stream".to_string() }); }
+            template_ids.extend(payload.chunks_exact(width).map(|ch| {
+                let mut id_bytes = [0u8; 4];
+                id_bytes[..width].copy_from_slice(ch);
+                u32::from_le_bytes(id_bytes) as usize
+            }));
+            transform.invert(&mut template_ids, width)
+                .ok_or_else(|| CastError::CorruptHeader { chunk: 0, detail: "move-to-front id stream".to_string() })?;
+            return Ok(template_ids);
+        },
+        _ => {},
+    }
+    if id_flag == 2 { for &b in payload { template_ids.push(b as usize); } }
+    else if id_flag == 1 { for ch in payload.chunks_exact(4) { template_ids.push(u32::from_le_bytes(ch.try_into().unwrap()) as usize); } }
+    else if id_flag == 0 { for ch in payload.chunks_exact(2) { template_ids.push(u16::from_le_bytes(ch.try_into().unwrap()) as usize); } }
+    Ok(template_ids)
+}
+
+// ============================================================================
+//  ID STREAM TRANSFORMS
+// ============================================================================
+//
+//  Split chunks compress their id stream on its own, where a transform can make it smaller
+//  without touching the vars. When template use shifts over a chunk (one template for the
+//  first rows, another for the next), move-to-front turns every id into its rank among the
+//  recently used ones, which stays near 0 whichever template is current. Delta stores each id
+//  minus the previous one (wrapping at the id width), which turns repeats into zeros. The
+//  compressor tries both on a sample of the stream and keeps whichever the backend shrinks
+//  most, or the stream as it is. LZMA already models a skewed stream well, so most streams
+//  stay as they are; ids that step through the templates in order are where delta wins.
+//
+//  Move-to-front looks every id up among the templates seen so far, which costs up to one
+//  pass over them per row. Blocks with u32 ids (more than 65535 templates) would make that
+//  quadratic, so they only ever get delta, even when move-to-front is forced.
+//
+//  A transformed stream has the encoding byte IDS_TRANSFORMED, then the transform byte. The
+//  transformed ids follow as raw ids of the chunk's width.
+
+// Streams of fewer ids skip the trial: what a transform could save does not pay for it
+const IDS_TRANSFORM_MIN_IDS: usize = 4096;
+// The trial compresses this many ids, taken from evenly spaced windows of longer streams
+const IDS_TRANSFORM_SAMPLE: usize = 64 * 1024;
+const IDS_TRANSFORM_WINDOWS: usize = 4;
+
+/// Transform applied to a split chunk's id stream before the backend (see "ID STREAM TRANSFORMS").
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdsTransform {
+    #[default]
+    None,
+    MoveToFront,
+    Delta,
+}
+
+impl IdsTransform {
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            1 => Some(IdsTransform::MoveToFront),
+            2 => Some(IdsTransform::Delta),
+            _ => None,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            IdsTransform::None => 0,
+            IdsTransform::MoveToFront => 1,
+            IdsTransform::Delta => 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IdsTransform::None => "none",
+            IdsTransform::MoveToFront => "move-to-front",
+            IdsTransform::Delta => "delta",
+        }
+    }
+
+    /// Whether the transform can be applied to the id stream of a block with this id_flag
+    /// (move-to-front skips u32 ids, see "ID STREAM TRANSFORMS").
+    pub fn supports(self, id_flag: u8) -> bool {
+        !(self == IdsTransform::MoveToFront && id_mode_of(id_flag) == 1)
+    }
+
+    /// Transformed ids, each still within `width` bytes.
+    fn apply(self, ids: &[u32], width: usize) -> Vec<u32> {
+        match self {
+            IdsTransform::None => ids.to_vec(),
+            IdsTransform::MoveToFront => {
+                // A permutation of 0..len, extended with the identity as new ids appear, so the
+                // decoder can rebuild it from the ranks alone
+                let mut order: Vec<u32> = Vec::new();
+                ids.iter().map(|&id| {
+                    // An id past the permutation is still at its identity position
+                    let rank = if id as usize >= order.len() {
+                        order.extend(order.len() as u32..=id);
+                        id as usize
+                    } else {
+                        order.iter().position(|&o| o == id).unwrap_or(0)
+                    };
+                    order[..=rank].rotate_right(1);
+                    rank as u32
+                }).collect()
+            },
+            IdsTransform::Delta => {
+                let mask = id_mask(width);
+                let mut prev = 0u32;
+                ids.iter().map(|&id| {
+                    let d = id.wrapping_sub(prev) & mask;
+                    prev = id;
+                    d
+                }).collect()
+            },
+        }
+    }
+
+    /// Undoes `apply` in place. `None` when a rank points past every id a valid stream can
+    /// hold (ids are below the row count).
+    fn invert(self, ids: &mut [usize], width: usize) -> Option<()> {
+        match self {
+            IdsTransform::None => {},
+            IdsTransform::MoveToFront => {
+                let limit = ids.len();
+                let mut order: Vec<usize> = Vec::new();
+                for id in ids.iter_mut() {
+                    let rank = *id;
+                    if rank >= limit { return None; }
+                    if rank >= order.len() { order.extend(order.len()..=rank); }
+                    *id = order[rank];
+                    order[..=rank].rotate_right(1);
+                }
+            },
+            IdsTransform::Delta => {
+                let mask = id_mask(width) as usize;
+                let mut prev = 0usize;
+                for id in ids.iter_mut() {
+                    prev = prev.wrapping_add(*id) & mask;
+                    *id = prev;
+                }
+            },
+        }
+        Some(())
+    }
+}
+
+fn id_mask(width: usize) -> u32 {
+    if width >= 4 { u32::MAX } else { (1u32 << (8 * width)) - 1 }
+}
+
+/// Id stream of `ids` under `transform`: the encoding byte, the transform byte and the raw
+/// transformed ids.
+fn encode_transformed_ids(ids: &[u32], transform: IdsTransform, width: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(2 + ids.len() * width);
+    out.push(IDS_TRANSFORMED);
+    out.push(transform.to_byte());
+    for id in transform.apply(ids, width) { out.extend_from_slice(&id.to_le_bytes()[..width]); }
+    out
+}
+
+/// Whether a chunk's decoded id stream is RLE, and its transform.
+fn stored_ids_encoding(ids: &[u8], id_flag_raw: u8) -> Result<(bool, IdsTransform), CastError> {
+    let id_mode = id_mode_of(id_flag_raw);
+    if id_mode == 3 || id_mode == 4 { return Ok((false, IdsTransform::None)); }
+    match ids_encoding_of(ids, id_flag_raw)? {
+        (IDS_RLE, _) => Ok((true, IdsTransform::None)),
+        (IDS_TRANSFORMED, start) => {
+            let transform = ids.get(start).copied().and_then(IdsTransform::from_byte)
+                .ok_or_else(|| CastError::CorruptHeader { chunk: 0, detail: "unknown id stream transform".to_string() })?;
+            Ok((false, transform))
+        },
+        _ => Ok((false, IdsTransform::None)),
+    }
+}
+
+/// Template id of every row of a block, held in the narrowest width the templates seen so far
+/// allow: u8 until the 257th template, then u16, then u32. Each promotion rewrites the ids
+/// once, so a block of short lines costs one byte per row instead of four.
+enum TemplateIds {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl TemplateIds {
+    fn new() -> Self { TemplateIds::U8(Vec::new()) }
+
+    fn push(&mut self, id: u32) {
+        match self {
+            TemplateIds::U8(ids) if id <= u8::MAX as u32 => ids.push(id as u8),
+            TemplateIds::U16(ids) if id <= u16::MAX as u32 => ids.push(id as u16),
+            TemplateIds::U32(ids) => ids.push(id),
+            _ => {
+                self.promote();
+                self.push(id);
+            },
+        }
+    }
+
+    fn promote(&mut self) {
+        *self = match std::mem::replace(self, TemplateIds::U32(Vec::new())) {
+            TemplateIds::U8(ids) => TemplateIds::U16(ids.into_iter().map(u16::from).collect()),
+            TemplateIds::U16(ids) => TemplateIds::U32(ids.into_iter().map(u32::from).collect()),
+            wide => wide,
+        };
+    }
+
+    /// Empties the stream for the next block, back to the narrowest width (only a u8 stream
+    /// keeps its allocation).
+    fn clear(&mut self) {
+        match self {
+            TemplateIds::U8(ids) => ids.clear(),
+            _ => *self = TemplateIds::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            TemplateIds::U8(ids) => ids.len(),
+            TemplateIds::U16(ids) => ids.len(),
+            TemplateIds::U32(ids) => ids.len(),
+        }
+    }
+
+    /// Rows per template id.
+    fn counts(&self, num_templates: usize) -> Vec<u64> {
+        fn count<T: Copy + Into<u32>>(ids: &[T], counts: &mut [u64]) {
+            for &id in ids { counts[id.into() as usize] += 1; }
+        }
+        let mut counts = vec![0u64; num_templates];
+        match self {
+            TemplateIds::U8(ids) => count(ids, &mut counts),
+            TemplateIds::U16(ids) => count(ids, &mut counts),
+            TemplateIds::U32(ids) => count(ids, &mut counts),
+        }
+        counts
+    }
+
+    /// Ids of `range` as u32.
+    fn values(&self, range: Range<usize>) -> Vec<u32> {
+        match self {
+            TemplateIds::U8(ids) => ids[range].iter().map(|&id| id.into()).collect(),
+            TemplateIds::U16(ids) => ids[range].iter().map(|&id| id.into()).collect(),
+            TemplateIds::U32(ids) => ids[range].to_vec(),
+        }
+    }
+
+    /// Renumbers in place. `remap` is a permutation of the ids, so every new id fits the
+    /// current width.
+    fn remap(&mut self, remap: &[u32]) {
+        match self {
+            TemplateIds::U8(ids) => for id in ids.iter_mut() { *id = remap[*id as usize] as u8 },
+            TemplateIds::U16(ids) => for id in ids.iter_mut() { *id = remap[*id as usize] as u16 },
+            TemplateIds::U32(ids) => for id in ids.iter_mut() { *id = remap[*id as usize] },
+        }
+    }
+
+    /// Raw id stream: the encoding byte, then every id as a little-endian integer of `width` bytes.
+    fn encode_raw(&self, width: usize) -> Vec<u8> {
+        fn encode<T: Copy + Into<u32>>(ids: &[T], width: usize) -> Vec<u8> {
+            let mut out = Vec::with_capacity(1 + ids.len() * width);
+            out.push(IDS_RAW);
+            for &id in ids { out.extend_from_slice(&id.into().to_le_bytes()[..width]); }
+            out
+        }
+        match self {
+            TemplateIds::U8(ids) => encode(ids, width),
+            TemplateIds::U16(ids) => encode(ids, width),
+            TemplateIds::U32(ids) => encode(ids, width),
+        }
+    }
+
+    fn encode_rle(&self, width: usize) -> Option<Vec<u8>> {
+        match self {
+            TemplateIds::U8(ids) => encode_ids_rle(ids, width),
+            TemplateIds::U16(ids) => encode_ids_rle(ids, width),
+            TemplateIds::U32(ids) => encode_ids_rle(ids, width),
+        }
+    }
+}
+
+// ============================================================================
+//  REGISTRY FRONT CODING
+// ============================================================================
+//
+//  When FLAG_REG_FRONT_CODED is set the registry holds, for each skeleton in id order:
+//      shared varint | suffix_len varint | suffix
+//  where `shared` counts the leading bytes taken from the previous skeleton. Skeletons of
+//  the same log family often differ only after a long common head, which is then stored once.
+
+/// Front-codes the skeletons, or `None` when that would not be smaller than the plain
+/// registry of `plain_len` bytes.
+fn front_code_registry(skeletons: &[String], plain_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(plain_len);
+    let mut prev: &[u8] = &[];
+    for skel in skeletons {
+        let skel = skel.as_bytes();
+        let shared = prev.iter().zip(skel).take_while(|(a, b)| a == b).count();
+        push_varint(&mut out, shared as u64);
+        push_varint(&mut out, (skel.len() - shared) as u64);
+        out.extend_from_slice(&skel[shared..]);
+        if out.len() >= plain_len { return None; }
+        prev = skel;
+    }
+    Some(out)
+}
+
+/// Rebuilds the plain (separator-joined) registry from a front-coded one.
+fn expand_front_coded_registry(coded: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(coded.len() * 2);
+    let mut prev = 0..0;
+    let mut pos = 0;
+    let mut first = true;
+    while pos < coded.len() {
+        let shared = read_varint(coded, &mut pos)? as usize;
+        let suffix_len = read_varint(coded, &mut pos)? as usize;
+        let suffix = coded.get(pos..pos.checked_add(suffix_len)?)?;
+        pos += suffix_len;
+        if shared > prev.len() { return None; }
+        if !first { out.extend_from_slice(REG_SEPARATOR.as_bytes()); }
+        first = false;
+        let start = out.len();
+        out.extend_from_within(prev.start..prev.start + shared);
+        out.extend_from_slice(suffix);
+        prev = start..out.len();
+    }
+    Some(out)
+}
+
+/// The registry of a chunk as text, expanded first when it is front-coded.
+fn registry_text(reg: Vec<u8>, id_flag_raw: u8) -> Result<String, CastError> {
+    let corrupt = |detail: &str| CastError::CorruptHeader { chunk: 0, detail: detail.to_string() };
+    let reg = if (id_flag_raw & FLAG_REG_FRONT_CODED) != 0 {
+        expand_front_coded_registry(&reg).ok_or_else(|| corrupt("Front-coded registry"))?
+    } else {
+        reg
+    };
+    String::from_utf8(reg).map_err(|_| corrupt("Registry not UTF-8"))
+}
+
+// ============================================================================
+//  COLUMN DICTIONARIES
+// ============================================================================
+//
+//  When FLAG_DICT_COLS is set the vars region is prefixed by a dictionary section:
+//      len u32 | { column varint | count varint | { len varint | value }* }*
+//  Cells of a dictionary column hold a single (escaped) index byte instead of the value.
+//  A chunk keeps its candidate dictionaries only when a zstd probe of the vars region is
+//  smaller with them than without (trying the backend itself would cost two extra full
+//  compressions of the region).
+
+// Distinct-value tracking is abandoned past this many values, so indices always fit in a u8
+const DICT_MAX_DISTINCT: usize = 64;
+const DICT_MIN_ROWS: usize = 64;
+// Each distinct value must repeat at least this many times on average
+const DICT_MIN_REPEAT: usize = 8;
+// zstd level of that probe: level 3 misjudges some csv chunks, level 19 doubles compression time
+const DICT_PROBE_LEVEL: i32 = 9;
+
+fn dict_probe_len(data: &[u8]) -> usize {
+    zstd::bulk::compress(data, DICT_PROBE_LEVEL).map_or(usize::MAX, |c| c.len())
+}
+
+#[inline(always)]
+fn push_escaped(out: &mut Vec<u8>, bytes: &[u8]) {
+    for &b in bytes {
+        match b {
+            0x01 => out.extend_from_slice(b"\x01\x01"),
+            0x00 => out.extend_from_slice(b"\x01\x00"),
+            0x02 => out.extend_from_slice(b"\x01\x03"),
+            _ => out.push(b),
+        }
+    }
+}
+
+/// Expands dictionary columns back into plain escaped values, returning a regular vars stream.
+fn rehydrate_dict_columns(data: &[u8]) -> Option<Vec<u8>> {
+    let dict_len = u32::from_le_bytes(data.get(0..4)?.try_into().unwrap()) as usize;
+    let section = data.get(4..4 + dict_len)?;
+    let vars = &data[4 + dict_len..];
+
+    let mut dicts: HashMap<usize, Vec<&[u8]>> = HashMap::new();
+    let mut pos = 0;
+    while pos < section.len() {
+        let col = read_varint(section, &mut pos)? as usize;
+        let count = read_varint(section, &mut pos)? as usize;
+        let mut values = Vec::with_capacity(count.min(DICT_MAX_DISTINCT));
+        for _ in 0..count {
+            let len = read_varint(section, &mut pos)? as usize;
+            values.push(section.get(pos..pos.checked_add(len)?)?);
+            pos += len;
+        }
+        dicts.insert(col, values);
+    }
+
+    let mut out = Vec::with_capacity(vars.len() * 2);
+    let mut col = 0;
+    let mut pos = 0;
+    while pos < vars.len() {
+        if let Some(values) = dicts.get(&col) {
+            loop {
+                let idx = match *vars.get(pos)? {
+                    0x01 => { pos += 2; match *vars.get(pos - 1)? { 0x01 => 1, 0x00 => 0, 0x03 => 2, _ => return None } },
+                    b => { pos += 1; b }
+                };
+                push_escaped(&mut out, values.get(idx as usize)?);
+                let sep = *vars.get(pos)?;
+                out.push(sep);
+                pos += 1;
+                if sep == 0x02 { break; }
+                if sep != 0x00 { return None; }
+            }
+        } else {
+            let start = pos;
+            while pos < vars.len() && vars[pos] != 0x02 {
+                pos += if vars[pos] == 0x01 { 2 } else { 1 };
+            }
+            pos = (pos + 1).min(vars.len());
+            out.extend_from_slice(&vars[start..pos]);
+        }
+        col += 1;
+    }
+    Some(out)
+}
+
+// ============================================================================
+//  CELL COUNTS
+// ============================================================================
+//
+//  Every column holds one cell per row of its template. In id modes 5-7 the vars region
+//  (in front of the dictionary section, if any) starts with the cell count of each column:
+//      columns varint | { cells varint }*
+//  The decoder checks every column against it before rebuilding a line, so a column that
+//  lost or gained a cell fails at once instead of shifting every later value of the column.
+//  Compact single-template blocks need no section: each column holds `rows` cells.
+
+/// Cells of a column range: one more than its (unescaped) row separators.
+fn count_cells(column: &[u8]) -> u64 {
+    let (row_sep, esc_byte) = (0x00u8, 0x01u8);
+    let mut n = 1;
+    let mut pos = 0;
+    while let Some(p) = memchr2(row_sep, esc_byte, &column[pos..]) {
+        let real = pos + p;
+        if column[real] == esc_byte { pos = real + 2; } else { n += 1; pos = real + 1; }
+        if pos >= column.len() { break; }
+    }
+    n
+}
+
+fn push_cell_counts(out: &mut Vec<u8>, counts: &[u64]) {
+    push_varint(out, counts.len() as u64);
+    for &n in counts { push_varint(out, n); }
+}
+
+/// Splits the cell count section off the front of a vars region.
+fn split_cell_counts(vars: &[u8]) -> Option<(Vec<u64>, &[u8])> {
+    let mut pos = 0;
+    let columns = read_varint(vars, &mut pos)? as usize;
+    // Each count takes at least one byte
+    if columns > vars.len() { return None; }
+    let mut counts = Vec::with_capacity(columns);
+    for _ in 0..columns { counts.push(read_varint(vars, &mut pos)?); }
+    Some((counts, &vars[pos..]))
+}
+
+/// Cell counts stored in front of a vars region (empty for chunks without the section).
+fn stored_cell_counts(vars: &[u8], id_flag: u8) -> Result<Vec<u64>, CastError> {
+    if !has_cell_counts(id_flag) { return Ok(Vec::new()); }
+    split_cell_counts(vars).map(|(counts, _)| counts).ok_or_else(|| CastError::CorruptHeader { chunk: 0, detail: "Cell count section".to_string() })
+}
+
+/// Checks the cells of every column (`ranges`, in registry order) against the stored counts,
+/// or against `rows` for a compact single-template block.
+fn check_cell_counts(vars: &[u8], ranges: &[(usize, usize)], placeholder_counts: &[usize], stored: Option<&[u64]>, rows: u64) -> Result<(), CastError> {
+    let corrupt = |detail: String| CastError::CorruptHeader { chunk: 0, detail };
+    let total: usize = placeholder_counts.iter().sum();
+    if stored.is_some_and(|c| c.len() != total) {
+        return Err(corrupt(format!("Cell count section lists {} columns, the registry declares {}", stored.map_or(0, |c| c.len()), total)));
+    }
+    let mut global = 0;
+    for (template, &columns) in placeholder_counts.iter().enumerate() {
+        for column in 0..columns {
+            let (start, end) = ranges[global];
+            let expected = stored.map_or(rows, |c| c[global]);
+            let found = count_cells(&vars[start..end]);
+            if found != expected {
+                return Err(corrupt(format!("column {} of template {}: expected {} cells, found {}", column, template, expected, found)));
+            }
+            global += 1;
+        }
+    }
+    Ok(())
+}
+
+// ============================================================================
+//  COLUMN STREAMS (per-column codecs)
+// ============================================================================
+//
+//  Opt-in layout (FLAG_COLUMN_STREAMS, reg and ids empty): the vars region is cut
+//  at column boundaries and each column picks its own codec, so incompressible
+//  columns (UUIDs, hashes) skip the expensive backend:
+//      segments varint | { codec u8 | raw_len varint }*
+//      | backend_len varint | backend stream
+//      | { stored_len varint | bytes }* (the non-backend segments, in order)
+//  The backend stream is the unified (or, for id mode 4, compact) layout with
+//  only the backend segments as vars, so with every column on the backend the
+//  chunk compresses exactly like a regular one. Adjacent columns with the same
+//  codec share a segment.
+
+const CODEC_BACKEND: u8 = 0;
+const CODEC_ZSTD: u8 = 1;
+const CODEC_RAW: u8 = 2;
+const COLUMN_ZSTD_LEVEL: i32 = 3;
+// Smaller columns always stay in the backend stream
+const COLUMN_CODEC_MIN: usize = 16 * 1024;
+const COLUMN_SAMPLE: usize = 32 * 1024;
+// The probe uses a strong level (short matches count too), the stored column a fast one
+const COLUMN_PROBE_LEVEL: i32 = 19;
+// Below this many bits per byte LZMA's arithmetic coder still clearly beats zstd's Huffman
+// literals (e.g. random decimal digits), even with nothing to match
+const COLUMN_ZSTD_MIN_ENTROPY: f64 = 4.0;
+
+/// Picks the codec of one column from its order-0 entropy and a zstd probe on a sample: when
+/// LZ matching finds nothing beyond the byte statistics, LZMA cannot do much better either.
+fn choose_column_codec(column: &[u8]) -> u8 {
+    if column.len() < COLUMN_CODEC_MIN { return CODEC_BACKEND; }
+    let sample = &column[..column.len().min(COLUMN_SAMPLE)];
+    let mut counts = [0u32; 256];
+    for &b in sample { counts[b as usize] += 1; }
+    let n = sample.len() as f64;
+    let entropy: f64 = counts.iter().filter(|&&c| c > 0).map(|&c| { let p = c as f64 / n; -p * p.log2() }).sum();
+    if entropy > 7.5 { return CODEC_RAW; }
+    let Ok(probe) = zstd::bulk::compress(sample, COLUMN_PROBE_LEVEL) else { return CODEC_BACKEND };
+    let probe_bits = probe.len() as f64 * 8.0;
+    if probe_bits >= n * 8.0 * 0.97 { CODEC_RAW }
+    else if entropy >= COLUMN_ZSTD_MIN_ENTROPY && probe_bits >= entropy * n * 0.9 { CODEC_ZSTD }
+    else { CODEC_BACKEND }
+}
+
+/// Name of a column codec id as `info --detailed` and --dump-format show it.
+fn column_codec_name(codec: u8) -> &'static str {
+    match codec {
+        CODEC_BACKEND => "backend",
+        CODEC_ZSTD => "zstd",
+        CODEC_RAW => "raw",
+        _ => "unknown",
+    }
+}
+
+/// Codec of each segment of a column streams payload, and its backend stream, read from the
+/// table alone (nothing is decoded). `None` when the table is cut short.
+pub fn column_stream_codecs(data: &[u8]) -> Option<(Vec<&'static str>, &[u8])> {
+    let mut pos = 0;
+    let count = read_varint(data, &mut pos)? as usize;
+    if count > data.len() { return None; }
+    let mut codecs = Vec::with_capacity(count);
+    for _ in 0..count {
+        codecs.push(column_codec_name(*data.get(pos)?));
+        pos += 1;
+        read_varint(data, &mut pos)?;
+    }
+    let len = read_varint(data, &mut pos)? as usize;
+    Some((codecs, data.get(pos..pos.checked_add(len)?)?))
+}
+
+/// A decoded column streams payload.
+struct ColumnStreams {
+    /// Block head, registry, ids and the backend columns
+    backend: Vec<u8>,
+    head: BlockHead,
+    /// Reassembled vars region
+    vars: Vec<u8>,
+    /// (codec, raw length) of each segment, in order
+    segments: Vec<(u8, usize)>,
+}
+
+fn decode_column_streams<D: NativeDecompressor>(decompressor: &CASTDecompressor<D>, data: &[u8], id_mode: u8, base: usize, total: usize) -> Result<ColumnStreams, CastError> {
+    let overflow = |detail: &str| CastError::LengthOverflow { chunk: 0, detail: detail.to_string() };
+    let mut pos = 0;
+    let count = read_varint(data, &mut pos).ok_or_else(|| overflow("Column stream count"))? as usize;
+    if count > data.len() { return Err(overflow("Column stream count")); }
+    let mut segments = Vec::with_capacity(count);
+    for _ in 0..count {
+        let codec = *data.get(pos).ok_or_else(|| overflow("Column stream table"))?;
+        pos += 1;
+        let raw_len = read_varint(data, &mut pos).ok_or_else(|| overflow("Column stream table"))? as usize;
+        segments.push((codec, raw_len));
+    }
+    let take = |pos: &mut usize| -> Result<&[u8], CastError> {
+        let len = read_varint(data, pos).ok_or_else(|| overflow("Column stream length"))? as usize;
+        let bytes = data.get(*pos..pos.saturating_add(len)).ok_or_else(|| overflow("Column stream exceeds chunk"))?;
+        *pos += len;
+        Ok(bytes)
+    };
+    let backend_bytes = take(&mut pos)?;
+    let backend_start = pos - backend_bytes.len();
+    let backend = decompressor.backend_decode(backend_bytes, base + backend_start, total)?;
+    let head = block_head(&backend, id_mode)?;
+
+    let raw_total: usize = segments.iter().map(|s| s.1).fold(0usize, |a, b| a.saturating_add(b));
+    let mut out = Vec::with_capacity(raw_total.min(backend.len() + data.len() * 8));
+    let mut backend_pos = head.ids.end;
+    for &(codec, raw_len) in &segments {
+        match codec {
+            CODEC_BACKEND => {
+                let piece = backend.get(backend_pos..backend_pos.saturating_add(raw_len)).ok_or_else(|| overflow("Column exceeds backend stream"))?;
+                out.extend_from_slice(piece);
+                backend_pos += raw_len;
+            },
+            CODEC_ZSTD => {
+                let piece = zstd::bulk::decompress(take(&mut pos)?, raw_len).map_err(|e| CastError::BackendFailure { backend: "zstd", detail: format!("column stream: {}", e) })?;
+                if piece.len() != raw_len { return Err(overflow("zstd column length")); }
+                out.extend_from_slice(&piece);
+            },
+            CODEC_RAW => {
+                let piece = take(&mut pos)?;
+                if piece.len() != raw_len { return Err(overflow("Raw column length")); }
+                out.extend_from_slice(piece);
+            },
+            _ => return Err(CastError::CorruptHeader { chunk: 0, detail: format!("Unknown column codec {}", codec) }),
+        }
+    }
+    if backend_pos != backend.len() { return Err(overflow("Backend stream longer than its columns")); }
+    Ok(ColumnStreams { backend, head, vars: out, segments })
+}
+
+// ============================================================================
+//  STRATEGY SAMPLE
+// ============================================================================
+//
+//  The parsing mode of a chunk is decided on its first lines: Aggressive when more than 10%
+//  of them have a Strict skeleton of their own. Only a hash of each skeleton is kept and the
+//  lines go through one scratch buffer, and the sample stops at STRATEGY_SAMPLE_LINES lines or
+//  once their skeletons reach STRATEGY_SAMPLE_BYTES, whichever comes first. A chunk of huge
+//  lines (minified JSON) is then decided on the lines sampled so far, without the analysis
+//  holding more than one line's skeleton.
+
+const STRATEGY_SAMPLE_LINES: usize = 1000;
+// Longest part of a line parsed for the sample
+const STRATEGY_LINE_CAP: usize = 16384;
+pub const STRATEGY_SAMPLE_BYTES: usize = 8 * 1024 * 1024;
+
+/// What the parsing mode decision of a chunk looked at (see "STRATEGY SAMPLE").
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StrategySample {
+    pub lines: usize,
+    /// Skeleton bytes of the sampled lines
+    pub skeleton_bytes: usize,
+    /// Distinct skeletons (by hash; a collision only lowers the count)
+    pub distinct: usize,
+    /// The sample stopped at STRATEGY_SAMPLE_BYTES before STRATEGY_SAMPLE_LINES lines
+    pub capped: bool,
+    /// Aggressive parsing chosen
+    pub aggressive: bool,
+}
+
+/// Samples the first lines of `text` for the parsing mode (see "STRATEGY SAMPLE").
+/// `max_var_len` is the compressor's variable length cap.
+pub fn sample_strategy(text: &str, max_var_len: usize) -> StrategySample {
+    let mut sample = StrategySample::default();
+    let mut skeleton_hashes = HashSet::new();
+    let mut temp_vars = Vec::with_capacity(16);
+    let mut temp_skel = String::with_capacity(256);
+
+    for line in text.lines().take(STRATEGY_SAMPLE_LINES) {
+        if sample.skeleton_bytes >= STRATEGY_SAMPLE_BYTES {
+            sample.capped = true;
+            break;
+        }
+        sample.lines += 1;
+        temp_vars.clear();
+        temp_skel.clear();
+        let line_sample = &line[..line.floor_char_boundary(STRATEGY_LINE_CAP)];
+        // Analysis ignores collisions
+        parse_line_manual(line_sample, ParsingMode::Strict, false, max_var_len, &mut 0, &mut temp_vars, &mut temp_skel);
+        sample.skeleton_bytes += temp_skel.len();
+        let mut hasher = std::hash::DefaultHasher::new();
+        temp_skel.hash(&mut hasher);
+        skeleton_hashes.insert(hasher.finish());
+    }
+
+    sample.distinct = skeleton_hashes.len();
+    sample.aggressive = sample.lines > 0 && sample.distinct as f64 / sample.lines as f64 > 0.10;
+    sample
+}
+
+// ============================================================================
+//  LAYOUT SAMPLE
+// ============================================================================
+//
+//  Unified or split is decided by compressing a sample of the chunk's variables with the
+//  backend: split when it compresses less than 3x. The sample takes the first
+//  LAYOUT_SAMPLE_COLUMN_CELLS cells of each column of the first LAYOUT_SAMPLE_TEMPLATES
+//  templates, in template id order, each template's columns in placeholder order. Both budgets
+//  are checked before every cell: the sample stops at LAYOUT_SAMPLE_CELLS cells, and the cell
+//  that would take it past LAYOUT_SAMPLE_BYTES is cut to fit. A template with thousands of
+//  columns then samples its first LAYOUT_SAMPLE_CELLS / LAYOUT_SAMPLE_COLUMN_CELLS columns,
+//  and a few huge values cannot make the probe compress megabytes. The sample only depends on
+//  the columns, so every backend is handed the same bytes.
+
+const LAYOUT_SAMPLE_TEMPLATES: usize = 5;
+const LAYOUT_SAMPLE_COLUMN_CELLS: usize = 50;
+pub const LAYOUT_SAMPLE_CELLS: usize = 2000;
+pub const LAYOUT_SAMPLE_BYTES: usize = 128 * 1024;
+
+/// What the unified/split decision of a chunk looked at (see "LAYOUT SAMPLE").
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LayoutSample {
+    /// Cells sampled (a cut cell counts)
+    pub cells: usize,
+    /// Sample bytes handed to the backend
+    pub bytes: usize,
+    /// Backend output for the sample (0 when the sample was not compressed)
+    pub compressed: usize,
+    /// The sample stopped at a budget before running out of cells
+    pub capped: bool,
+    /// The chunk was probed at all: more than one template, fewer than 256 and an input of
+    /// at least 64KB
+    pub probed: bool,
+    /// Split layout chosen
+    pub split: bool,
+}
+
+// Fills `buffer` with the layout sample of `columns` (see "LAYOUT SAMPLE")
+fn collect_layout_sample(columns: &[Vec<ColumnBuffer>], buffer: &mut Vec<u8>) -> LayoutSample {
+    let mut sample = LayoutSample::default();
+    buffer.clear();
+    for col in columns.iter().take(LAYOUT_SAMPLE_TEMPLATES).flatten() {
+        for k in 0..cmp::min(col.len(), LAYOUT_SAMPLE_COLUMN_CELLS) {
+            if sample.cells == LAYOUT_SAMPLE_CELLS || buffer.len() == LAYOUT_SAMPLE_BYTES {
+                sample.capped = true;
+                sample.bytes = buffer.len();
+                return sample;
+            }
+            let cell = col.get(k);
+            buffer.extend_from_slice(&cell[..cmp::min(cell.len(), LAYOUT_SAMPLE_BYTES - buffer.len())]);
+            sample.cells += 1;
+        }
+    }
+    sample.bytes = buffer.len();
+    sample
+}
+
+// ============================================================================
+//  PARSER CONFIGURATION
+// ============================================================================
+//
+//  The settings a columnar chunk was tokenized with. Decoding never needs them (a block holds
+//  its skeletons and values), but templates only compare between chunks parsed the same way:
+//  another mode, matcher set, quote character or variable length cap turns the same line into
+//  another skeleton. Version 2 archives record them in front of the vars of every
+//  columnar chunk (see archive.rs), as a parser record:
+//      len u8 | tokenizer u8 | flags u8 | quote u8 | max_var_len varint
+//  `len` counts the bytes after it, so a later version can append fields that this one skips.
+//  `tokenizer` numbers the matcher set (hex, numbers, timestamps, Aggressive tokens, quoted
+//  strings as in `quoted_len`): a change to what they match must bump TOKENIZER_VERSION.
+//  Flags: 0x01 Aggressive mode, 0x02 timestamp split.
+//
+//  As text (template files, listings) the configuration is a row of key=value pairs, e.g.
+//      tokenizer=1 mode=aggressive quote=" timestamp-split=yes max-var-len=1048576
+
+pub const TOKENIZER_VERSION: u8 = 1;
+const QUOTE_CHAR: u8 = b'"';
+const PARSER_FLAG_AGGRESSIVE: u8 = 0x01;
+const PARSER_FLAG_TIMESTAMP_SPLIT: u8 = 0x02;
+/// Longest parser record this version writes
+pub const PARSER_RECORD_MAX_LEN: usize = 4 + 10;
+
+// A printable quote as itself, anything else (a space would split the text) as 0xNN
+fn render_quote(quote: u8) -> String {
+    if quote.is_ascii_graphic() { (quote as char).to_string() } else { format!("0x{:02x}", quote) }
+}
+
+/// How a columnar chunk was tokenized (see "PARSER CONFIGURATION").
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ParserConfig {
+    pub tokenizer: u8,
+    pub aggressive: bool,
+    pub split_timestamps: bool,
+    pub quote: u8,
+    pub max_var_len: u64,
+}
+
+impl ParserConfig {
+    /// The parser record of this configuration.
+    pub fn to_record(&self) -> Vec<u8> {
+        let mut fields = vec![self.tokenizer, 0, self.quote];
+        if self.aggressive { fields[1] |= PARSER_FLAG_AGGRESSIVE; }
+        if self.split_timestamps { fields[1] |= PARSER_FLAG_TIMESTAMP_SPLIT; }
+        push_varint(&mut fields, self.max_var_len);
+        let mut record = Vec::with_capacity(fields.len() + 1);
+        record.push(fields.len() as u8);
+        record.extend_from_slice(&fields);
+        record
+    }
+
+    /// Length of the parser record at the start of `bytes` (its `len` byte included), or
+    /// `None` when `bytes` is too short to hold it.
+    pub fn record_len(bytes: &[u8]) -> Option<usize> {
+        let len = 1 + *bytes.first()? as usize;
+        (len <= bytes.len()).then_some(len)
+    }
+
+    /// The configuration in the parser record at the start of `bytes`, or `None` when the
+    /// record is truncated or lacks a field.
+    pub fn from_record(bytes: &[u8]) -> Option<Self> {
+        let fields = &bytes[1..Self::record_len(bytes)?];
+        let (&tokenizer, &flags, &quote) = (fields.first()?, fields.get(1)?, fields.get(2)?);
+        let max_var_len = read_varint(&fields[3..], &mut 0)?;
+        Some(Self {
+            tokenizer,
+            aggressive: flags & PARSER_FLAG_AGGRESSIVE != 0,
+            split_timestamps: flags & PARSER_FLAG_TIMESTAMP_SPLIT != 0,
+            quote,
+            max_var_len,
+        })
+    }
+
+    /// The configuration as text (see "PARSER CONFIGURATION").
+    pub fn render(&self) -> String {
+        format!("tokenizer={} mode={} quote={} timestamp-split={} max-var-len={}",
+            self.tokenizer, if self.aggressive { "aggressive" } else { "strict" }, render_quote(self.quote),
+            if self.split_timestamps { "yes" } else { "no" }, self.max_var_len)
+    }
+
+    /// Reads the text of `render`; `None` when a field is missing, unknown or malformed.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (mut tokenizer, mut aggressive, mut quote, mut split_timestamps, mut max_var_len) = (None, None, None, None, None);
+        for field in s.split_whitespace() {
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "tokenizer" => tokenizer = Some(value.parse().ok()?),
+                "mode" => aggressive = Some(match value { "aggressive" => true, "strict" => false, _ => return None }),
+                "quote" => quote = Some(match value.as_bytes() {
+                    [b] => *b,
+                    _ => u8::from_str_radix(value.strip_prefix("0x")?, 16).ok()?,
+                }),
+                "timestamp-split" => split_timestamps = Some(match value { "yes" => true, "no" => false, _ => return None }),
+                "max-var-len" => max_var_len = Some(value.parse().ok()?),
+                _ => return None,
+            }
+        }
+        Some(Self { tokenizer: tokenizer?, aggressive: aggressive?, split_timestamps: split_timestamps?, quote: quote?, max_var_len: max_var_len? })
+    }
+
+    /// The settings that differ from `other`, each as "name: ours vs theirs".
+    pub fn differences(&self, other: &Self) -> Vec<String> {
+        let mode = |c: &Self| if c.aggressive { "aggressive" } else { "strict" };
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let mut found = Vec::new();
+        if self.tokenizer != other.tokenizer { found.push(format!("tokenizer: {} vs {}", self.tokenizer, other.tokenizer)); }
+        if self.aggressive != other.aggressive { found.push(format!("mode: {} vs {}", mode(self), mode(other))); }
+        if self.quote != other.quote { found.push(format!("quote: {} vs {}", render_quote(self.quote), render_quote(other.quote))); }
+        if self.split_timestamps != other.split_timestamps { found.push(format!("timestamp-split: {} vs {}", yes_no(self.split_timestamps), yes_no(other.split_timestamps))); }
+        if self.max_var_len != other.max_var_len { found.push(format!("max-var-len: {} vs {}", self.max_var_len, other.max_var_len)); }
+        found
+    }
+}
+
+// ============================================================================
+//  CAST COMPRESSOR (OPTIMIZED & SAFE)
+// ============================================================================
+//
+//  BLOCK STATE REUSE: one compressor may compress many chunks, and each `compress` call starts
+//  with `reset_block_state`. No byte of a chunk may reach the block of the next one. What the
+//  reset keeps:
+//    - template_map: emptied, keeps its table (the skeleton keys are dropped)
+//    - skeletons_list: emptied (the skeletons are dropped)
+//    - stream_template_ids: emptied; keeps its allocation only as a u8 stream
+//    - columns_storage: every ColumnBuffer is cleared (data, offsets and distinct values, all
+//      keeping their allocations) and moved to spare_columns, which hands them out again
+//      before new ones are allocated
+//    - mode, long_values, registry_saved, layout_sample, next_template_id: back to their initial values
+//  Line scratch (vars_cache, skel_cache, the analysis samples) and the serialized streams
+//  are locals of one call and never outlive it. Debug builds poison cleared column buffers
+//  with BUFFER_POISON and check that no serialized cell or skeleton holds it (for chunks
+//  whose text does not contain that byte itself), which turns a clear that misses part of
+//  a buffer into an assertion failure instead of a leak.
+
+// Inputs below this size skip the unified/split sample compression and stay unified
+const LAYOUT_PROBE_MIN_INPUT: usize = 64 * 1024;
+
+/// Compressed segments of one chunk: (c_reg, c_ids, c_vars, id_flag, parsing mode label).
+pub type CompressedChunk = (Vec<u8>, Vec<u8>, Vec<u8>, u8, String);
+
+pub struct CASTCompressor<C: NativeCompressor> {
+    template_map: HashMap<String, u32>,
+    skeletons_list: Vec<String>,
+    stream_template_ids: TemplateIds,
+    // Columns of each template, indexed by template id (the registry and vars order)
+    columns_storage: Vec<Vec<ColumnBuffer>>,
+    // Cleared columns of earlier blocks, reused before new ones are allocated
+    spare_columns: Vec<ColumnBuffer>,
+    next_template_id: u32,
+    mode: ParsingMode,
+    column_codecs: bool,
+    column_dicts: bool,
+    front_coding: bool,
+    split_timestamps: bool,
+    max_var_len: usize,
+    // Values of the last chunk left in the skeleton for exceeding max_var_len
+    long_values: u64,
+    // Raw registry bytes the last chunk saved through front coding
+    registry_saved: u64,
+    // Transform of the last chunk's id stream
+    ids_transform: IdsTransform,
+    // Unified/split sample of the last chunk
+    layout_sample: LayoutSample,
+    // Transform applied to every id stream instead of the trial on split chunks
+    forced_ids_transform: Option<IdsTransform>,
+    // Layout used instead of the sample's choice (true = split)
+    forced_split: Option<bool>,
+    backend: C, // Abstract Backend
+}
+
+impl<C: NativeCompressor> CASTCompressor<C> {
+    // NEW: Constructor takes the backend instance instead of config
+    pub fn new(backend: C) -> Self {
+        CASTCompressor {
+            template_map: HashMap::new(),
+            skeletons_list: Vec::new(),
+            stream_template_ids: TemplateIds::new(),
+            columns_storage: Vec::new(),
+            spare_columns: Vec::new(),
+            next_template_id: 0,
+            mode: ParsingMode::Strict,
+            column_codecs: false,
+            column_dicts: true,
+            front_coding: false,
+            split_timestamps: true,
+            max_var_len: DEFAULT_MAX_VAR_LEN,
+            long_values: 0,
+            registry_saved: 0,
+            ids_transform: IdsTransform::None,
+            layout_sample: LayoutSample::default(),
+            forced_ids_transform: None,
+            forced_split: None,
+            backend,
+        }
+    }
+
+    /// Opt into the column streams layout: each column is stored with the backend, zstd or
+    /// as is, whichever suits its entropy.
+    pub fn with_column_codecs(mut self, enabled: bool) -> Self {
+        self.column_codecs = enabled;
+        self
+    }
+
+    /// Store low-cardinality columns as a dictionary plus one index per row, for the columns
+    /// the backend compresses smaller that way. On by default; the decoder does not depend on it.
+    pub fn with_column_dictionaries(mut self, enabled: bool) -> Self {
+        self.column_dicts = enabled;
+        self
+    }
+
+    /// Opt into front coding the registry: each skeleton is stored as the length of the
+    /// prefix it shares with the previous one plus the rest. Used only for chunks where it
+    /// makes the registry smaller.
+    pub fn with_front_coded_registry(mut self, enabled: bool) -> Self {
+        self.front_coding = enabled;
+        self
+    }
+
+    /// In Aggressive mode, end a token after a leading timestamp that runs into an identifier
+    /// through punctuation, so the timestamp and the identifier become separate columns. On
+    /// by default; the decoder does not depend on it.
+    pub fn with_timestamp_split(mut self, enabled: bool) -> Self {
+        self.split_timestamps = enabled;
+        self
+    }
+
+    /// Longest quoted string or token captured as a variable (default `DEFAULT_MAX_VAR_LEN`).
+    /// Longer ones stay in the line's template, which keeps cells bounded when a line embeds
+    /// a large payload. The decoder does not depend on it.
+    pub fn with_max_var_len(mut self, max_len: usize) -> Self {
+        self.max_var_len = max_len;
+        self
+    }
+
+    /// Applies `transform` to the id stream of every chunk that has one, instead of trying
+    /// the transforms on split chunks (`None`, the default). For checks and benchmarks; the
+    /// decoder reads the transform from the stream in every layout.
+    pub fn with_ids_transform(mut self, transform: Option<IdsTransform>) -> Self {
+        self.forced_ids_transform = transform;
+        self
+    }
+
+    /// Uses the split (`Some(true)`) or unified (`Some(false)`) layout on every chunk with
+    /// between 2 and 255 templates, whatever its size, instead of the sample's choice (`None`,
+    /// the default). For checks and benchmarks; the decoder reads the layout from the block.
+    pub fn with_split_layout(mut self, split: Option<bool>) -> Self {
+        self.forced_split = split;
+        self
+    }
+
+    /// Values of the last compressed chunk that were too long to become variables.
+    pub fn long_values(&self) -> u64 {
+        self.long_values
+    }
+
+    /// Raw registry bytes the last compressed chunk saved through front coding.
+    pub fn registry_saved(&self) -> u64 {
+        self.registry_saved
+    }
+
+    /// Transform the last compressed chunk applied to its id stream (see "ID STREAM TRANSFORMS").
+    pub fn ids_transform(&self) -> IdsTransform {
+        self.ids_transform
+    }
+
+    /// Sample the last compressed chunk chose its layout on (see "LAYOUT SAMPLE").
+    pub fn layout_sample(&self) -> LayoutSample {
+        self.layout_sample
+    }
+
+    /// Configuration the last compressed chunk was tokenized with (see "PARSER CONFIGURATION").
+    pub fn parser_config(&self) -> ParserConfig {
+        ParserConfig {
+            tokenizer: TOKENIZER_VERSION,
+            aggressive: self.mode == ParsingMode::Aggressive,
+            split_timestamps: self.split_timestamps,
+            quote: QUOTE_CHAR,
+            max_var_len: self.max_var_len as u64,
+        }
+    }
+
+    /// Forgets everything the previous block recorded (see "BLOCK STATE REUSE").
+    fn reset_block_state(&mut self) {
+        self.template_map.clear();
+        self.skeletons_list.clear();
+        self.stream_template_ids.clear();
+        self.next_template_id = 0;
+        self.mode = ParsingMode::Strict;
+        self.long_values = 0;
+        self.registry_saved = 0;
+        self.ids_transform = IdsTransform::None;
+        self.layout_sample = LayoutSample::default();
+        for mut column in self.columns_storage.drain(..).flatten() {
+            column.clear();
+            self.spare_columns.push(column);
+        }
+    }
+
+    // Empty streams never reach the backend: they are stored as zero-length segments
+    fn backend_encode(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        if data.is_empty() { return Ok(Vec::new()); }
+        self.check_cancelled()?;
+        self.backend.compress(data)
+    }
 
-fn decode_python_latin1(data: &[u8]) -> String {
-    data.iter().map(|&b| b as char).collect()
-}
+    fn check_cancelled(&self) -> Result<(), CastError> {
+        match self.backend.cancellation() {
+            Some(token) => token.check(),
+            None => Ok(()),
+        }
+    }
 
-// ============================================================================
-//  CAST COMPRESSOR (OPTIMIZED & SAFE)
-// ============================================================================
+    /// Column streams payload for `vars`, cut after each offset in `boundaries`. `head`
+    /// (registry and ids) leads the backend stream.
+    fn encode_column_streams(&self, head: &[u8], vars: &[u8], boundaries: &[usize]) -> Result<Vec<u8>, CastError> {
+        // (codec, start, end), merging neighbours that chose the same codec
+        let mut segments: Vec<(u8, usize, usize)> = Vec::new();
+        let mut start = 0;
+        for &end in boundaries.iter().chain(std::iter::once(&vars.len())) {
+            if end <= start { continue; }
+            let codec = choose_column_codec(&vars[start..end]);
+            match segments.last_mut() {
+                Some(last) if last.0 == codec => last.2 = end,
+                _ => segments.push((codec, start, end)),
+            }
+            start = end;
+        }
 
-pub struct CASTCompressor<C: NativeCompressor> {
-    template_map: HashMap<String, u32>,
-    skeletons_list: Vec<String>,
-    stream_template_ids: Vec<u32>,
-    columns_storage: HashMap<u32, Vec<ColumnBuffer>>,
-    next_template_id: u32,
-    mode: ParsingMode,
-    backend: C, // Abstract Backend
-}
+        let mut backend_raw = head.to_vec();
+        for &(codec, s, e) in &segments {
+            if codec == CODEC_BACKEND { backend_raw.extend_from_slice(&vars[s..e]); }
+        }
+        let backend_stream = self.backend_encode(&backend_raw)?;
 
-impl<C: NativeCompressor> CASTCompressor<C> {
-    // NEW: Constructor takes the backend instance instead of config
-    pub fn new(backend: C) -> Self {
-        CASTCompressor {
-            template_map: HashMap::new(),
-            skeletons_list: Vec::new(),
-            stream_template_ids: Vec::new(),
-            columns_storage: HashMap::new(),
-            next_template_id: 0,
-            mode: ParsingMode::Strict,
-            backend,
+        let mut out = Vec::with_capacity(backend_stream.len() + 64);
+        push_varint(&mut out, segments.len() as u64);
+        for &(codec, s, e) in &segments {
+            out.push(codec);
+            push_varint(&mut out, (e - s) as u64);
+        }
+        push_varint(&mut out, backend_stream.len() as u64);
+        out.extend_from_slice(&backend_stream);
+        for &(codec, s, e) in &segments {
+            let stored = match codec {
+                CODEC_ZSTD => zstd::bulk::compress(&vars[s..e], COLUMN_ZSTD_LEVEL).map_err(|e| CastError::BackendFailure { backend: "zstd", detail: format!("column stream: {}", e) })?,
+                CODEC_RAW => vars[s..e].to_vec(),
+                _ => continue,
+            };
+            push_varint(&mut out, stored.len() as u64);
+            out.extend_from_slice(&stored);
         }
+        Ok(out)
     }
 
     fn analyze_strategy(&mut self, text: &str) {
-        let sample_limit = 1000;
-        let mut strict_templates = HashSet::new();
-        let mut line_count = 0;
-        let mut temp_vars = Vec::with_capacity(16);
-        let mut temp_skel = String::with_capacity(256);
-
-        for line in text.lines().take(sample_limit) {
-            line_count += 1;
-            temp_vars.clear();
-            temp_skel.clear();
-            let line_sample = if line.len() > 16384 { &line[..16384] } else { line };
-            // Analysis ignores collisions
-            parse_line_manual(line_sample, ParsingMode::Strict, &mut temp_vars, &mut temp_skel);
-            strict_templates.insert(temp_skel.clone());
-        }
+        let sample = sample_strategy(text, self.max_var_len);
+        if sample.lines == 0 { return; }
+        self.mode = if sample.aggressive { ParsingMode::Aggressive } else { ParsingMode::Strict };
+    }
 
-        if line_count == 0 { return; }
-        let ratio = strict_templates.len() as f64 / line_count as f64;
-        self.mode = if ratio > 0.10 { ParsingMode::Aggressive } else { ParsingMode::Strict };
+    /// Compresses one chunk. When the backend has a size bound (see
+    /// `NativeCompressor::max_compressed_len`) and the columnar form of the chunk, with the
+    /// parser record an archive stores next to it, would exceed it, the chunk is stored as
+    /// passthrough instead, which the bound covers.
+    pub fn compress(&mut self, input_data: &[u8]) -> Result<CompressedChunk, CastError> {
+        let chunk = self.compress_columnar(input_data)?;
+        let Some(bound) = self.backend.max_compressed_len(input_data.len()) else { return Ok(chunk) };
+        let (c_reg, c_ids, c_vars, id_flag, _) = &chunk;
+        if is_passthrough(*id_flag) || c_reg.len() + c_ids.len() + c_vars.len() + PARSER_RECORD_MAX_LEN <= bound { return Ok(chunk); }
+        self.long_values = 0;
+        self.registry_saved = 0;
+        self.ids_transform = IdsTransform::None;
+        self.create_passthrough(input_data, "Passthrough [Size Bound]")
     }
 
-    pub fn compress(&mut self, input_data: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>, u8, String) {
+    fn compress_columnar(&mut self, input_data: &[u8]) -> Result<CompressedChunk, CastError> {
+        self.reset_block_state();
         // [FIX] BINARY GUARD
         if is_likely_binary(input_data) {
             return self.create_passthrough(input_data, "Binary Guard Detected");
@@ -286,6 +1865,8 @@ impl<C: NativeCompressor> CASTCompressor<C> {
         };
 
         let text_slice = text_cow.as_ref();
+        // Debug builds: serialized cells and skeletons must not hold the poison of cleared buffers
+        let poison_free = cfg!(debug_assertions) && memchr::memchr(BUFFER_POISON, text_slice.as_bytes()).is_none();
         self.analyze_strategy(text_slice);
 
         let lines = text_slice.split_inclusive('\n');
@@ -293,16 +1874,22 @@ impl<C: NativeCompressor> CASTCompressor<C> {
         let mut skel_cache = String::with_capacity(512);
 
         let line_count_real = text_slice.as_bytes().iter().filter(|&&b| b == b'\n').count() + 1;
-        let unique_limit = (line_count_real as f64 * if self.mode == ParsingMode::Aggressive { 0.40 } else { 0.25 }) as u32;
+        let unique_limit = (line_count_real as f64 * if self.mode == ParsingMode::Aggressive { AGGRESSIVE_TEMPLATE_RATIO } else { STRICT_TEMPLATE_RATIO }) as u32;
 
+        let mut unchecked = 0;
         for line in lines {
             if line.is_empty() { continue; }
+            unchecked += line.len();
+            if unchecked >= CANCEL_CHECK_INTERVAL {
+                unchecked = 0;
+                self.check_cancelled()?;
+            }
 
             vars_cache.clear();
             skel_cache.clear();
 
             // Safe parsing
-            if !parse_line_manual(line, self.mode, &mut vars_cache, &mut skel_cache) {
+            if !parse_line_manual(line, self.mode, self.split_timestamps, self.max_var_len, &mut self.long_values, &mut vars_cache, &mut skel_cache) {
                  return self.create_passthrough(input_data, "Collision Protected");
             }
 
@@ -316,15 +1903,15 @@ impl<C: NativeCompressor> CASTCompressor<C> {
                 t_id = self.next_template_id;
                 self.template_map.insert(skel_cache.clone(), t_id);
                 self.skeletons_list.push(skel_cache.clone());
-                self.columns_storage.insert(t_id, Vec::new());
+                self.columns_storage.push(Vec::new());
                 self.next_template_id += 1;
             }
 
             self.stream_template_ids.push(t_id);
-            let cols = self.columns_storage.get_mut(&t_id).unwrap();
+            let cols = &mut self.columns_storage[t_id as usize];
 
             if cols.is_empty() {
-                for _ in 0..vars_cache.len() { cols.push(ColumnBuffer::new()); }
+                for _ in 0..vars_cache.len() { cols.push(self.spare_columns.pop().unwrap_or_else(ColumnBuffer::new)); }
             }
 
             let limit = std::cmp::min(vars_cache.len(), cols.len());
@@ -337,90 +1924,85 @@ impl<C: NativeCompressor> CASTCompressor<C> {
         let num_templates = self.skeletons_list.len();
         let mut decision_mode = "UNIFIED";
 
-        if num_templates < 256 {
+        // A single template has no id stream, so the layout choice does not apply. Small inputs
+        // skip the sample compression and stay unified: the probe would cost more than the
+        // choice can save. A forced layout skips the sample
+        if let (Some(split), true) = (self.forced_split, num_templates > 1 && num_templates < 256) {
+            if split { decision_mode = "SPLIT"; }
+            self.layout_sample.split = split;
+        } else if num_templates > 1 && num_templates < 256 && input_data.len() >= LAYOUT_PROBE_MIN_INPUT {
             let mut sample_buffer = Vec::new();
-            let mut collected = 0;
-            for t_id in 0..std::cmp::min(num_templates, 5) {
-                if let Some(cols) = self.columns_storage.get(&(t_id as u32)) {
-                    for col in cols {
-                        let limit_sample = std::cmp::min(col.len(), 50);
-                        for k in 0..limit_sample {
-                            sample_buffer.extend_from_slice(col.get(k));
-                            collected += 1;
-                        }
-                    }
-                }
-                if collected > 2000 { break; }
-            }
+            let mut sample = collect_layout_sample(&self.columns_storage, &mut sample_buffer);
+            sample.probed = true;
             if !sample_buffer.is_empty() {
-                // Heuristic compression - Using backend just for the heuristic check
-                // Note: The original code created a new XzEncoder(1) here.
-                // We will use the backend to simulate this or assume backend handles it.
-                // STRICTLY ADHERING TO "NO LOGIC CHANGE":
-                // We use the backend to compress. The backend implementation must match what was here.
-                let c_sample = self.backend.compress(&sample_buffer);
-                if (sample_buffer.len() as f64 / c_sample.len() as f64) < 3.0 {
+                sample.compressed = self.backend_encode(&sample_buffer)?.len();
+                sample.split = (sample_buffer.len() as f64 / sample.compressed as f64) < 3.0;
+                if sample.split {
                     decision_mode = "SPLIT";
                 }
             }
+            self.layout_sample = sample;
         }
 
         // 5. Unified Remapping
         if decision_mode == "UNIFIED" {
-            let mut counts = HashMap::new();
-            let mut first_appearance = HashMap::new();
-            for (idx, &id) in self.stream_template_ids.iter().enumerate() {
-                *counts.entry(id).or_insert(0) += 1;
-                first_appearance.entry(id).or_insert(idx);
-            }
-            let mut sorted_ids: Vec<u32> = counts.keys().cloned().collect();
-            sorted_ids.sort_by(|a, b| {
-                let count_a = counts.get(a).unwrap();
-                let count_b = counts.get(b).unwrap();
-                if count_a != count_b { count_b.cmp(count_a) }
-                else {
-                     let idx_a = first_appearance.get(a).unwrap();
-                     let idx_b = first_appearance.get(b).unwrap();
-                     idx_a.cmp(idx_b)
-                }
-            });
-            let mut remap = HashMap::new();
-            for (new, &old) in sorted_ids.iter().enumerate() { remap.insert(old, new as u32); }
-
-            let mut new_skels = vec![String::new(); num_templates];
-            let mut new_cols = HashMap::new();
-
-            for (old, &new) in &remap {
-                new_skels[new as usize] = self.skeletons_list[*old as usize].clone();
-                if let Some(buf) = self.columns_storage.remove(old) {
-                    new_cols.insert(new, buf);
-                }
-            }
-            self.skeletons_list = new_skels;
-            self.columns_storage = new_cols;
-            self.stream_template_ids = self.stream_template_ids.iter().map(|id| remap[id]).collect();
+            // Most frequent first; ties keep the first-seen order, which is the id order
+            let counts = self.stream_template_ids.counts(num_templates);
+            let mut sorted_ids: Vec<u32> = (0..num_templates as u32).collect();
+            sorted_ids.sort_by(|&a, &b| counts[b as usize].cmp(&counts[a as usize]));
+            let mut remap = vec![0u32; num_templates];
+            for (new, &old) in sorted_ids.iter().enumerate() { remap[old as usize] = new as u32; }
+
+            self.skeletons_list = sorted_ids.iter().map(|&old| std::mem::take(&mut self.skeletons_list[old as usize])).collect();
+            self.columns_storage = sorted_ids.iter().map(|&old| std::mem::take(&mut self.columns_storage[old as usize])).collect();
+            self.stream_template_ids.remap(&remap);
         }
+        // SPLIT keeps the first-seen ids. Either way the registry, the id stream and the vars
+        // below follow the template id order
 
         // 6. Serialization
-        let raw_registry = self.skeletons_list.join(REG_SEPARATOR).into_bytes();
+        let mut raw_registry = self.skeletons_list.join(REG_SEPARATOR).into_bytes();
+        debug_assert!(!poison_free || memchr::memchr(BUFFER_POISON, &raw_registry).is_none(), "registry holds bytes of a recycled buffer");
         let mut raw_ids = Vec::new();
-        let mut id_mode_flag;
+        let mut id_mode_flag = if num_templates == 1 { 4 }
+            else if num_templates < 256 { 2 }
+            else if num_templates > U16_MAX_TEMPLATES { 1 }
+            else { 0 };
 
-        let total_rows = self.stream_template_ids.len() as u32;
+        let total_rows = self.stream_template_ids.len() as u64;
 
-        if num_templates == 1 { id_mode_flag = 3; }
-        else if num_templates < 256 {
-            id_mode_flag = 2;
-            for &id in &self.stream_template_ids { raw_ids.push(id as u8); }
-        } else if num_templates > 65535 {
-            id_mode_flag = 1;
-            for &id in &self.stream_template_ids { raw_ids.extend_from_slice(&id.to_le_bytes()); }
-        } else {
-            id_mode_flag = 0;
-            for &id in &self.stream_template_ids { raw_ids.extend_from_slice(&(id as u16).to_le_bytes()); }
+        // RLE is tried first so the raw stream is only built when it is the one stored
+        if id_mode_flag != 4 {
+            if let Some(rle) = self.stream_template_ids.encode_rle(id_width(id_mode_flag)) {
+                raw_ids = rle;
+            } else {
+                raw_ids = self.stream_template_ids.encode_raw(id_width(id_mode_flag));
+            }
+        }
+
+        if id_mode_flag != 4 {
+            let width = id_width(id_mode_flag & ID_MODE_MASK);
+            self.ids_transform = match self.forced_ids_transform {
+                Some(transform) if transform.supports(id_mode_flag) => transform,
+                Some(_) => IdsTransform::None,
+                None if decision_mode == "SPLIT" => self.choose_ids_transform(&raw_ids, id_mode_flag, width)?,
+                None => IdsTransform::None,
+            };
+            if self.ids_transform != IdsTransform::None {
+                let ids = self.stream_template_ids.values(0..self.stream_template_ids.len());
+                raw_ids = encode_transformed_ids(&ids, self.ids_transform, width);
+            }
+        }
+
+        if self.front_coding && num_templates > 1 {
+            if let Some(coded) = front_code_registry(&self.skeletons_list, raw_registry.len()) {
+                self.registry_saved = (raw_registry.len() - coded.len()) as u64;
+                raw_registry = coded;
+                id_mode_flag |= FLAG_REG_FRONT_CODED;
+            }
         }
 
-        if is_latin1 { id_mode_flag |= 0x80; }
+        if is_latin1 { id_mode_flag |= FLAG_LATIN1; }
 
         // ALWAYS ESCAPED MODE
         let row_sep = b"\x00";
@@ -432,51 +2014,133 @@ impl<C: NativeCompressor> CASTCompressor<C> {
         let esc_seq_col = b"\x01\x03";
 
         let mut vars_buffer = Vec::with_capacity(input_data.len());
-
-        for t_id in 0..self.skeletons_list.len() {
-            if let Some(cols) = self.columns_storage.get(&(t_id as u32)) {
-                for col_buf in cols {
-                    for idx in 0..col_buf.len() {
-                        if idx > 0 { vars_buffer.extend_from_slice(row_sep); }
-                        let v_bytes = col_buf.get(idx);
-
-                        // Byte Stuffing (Always)
-                        for &b in v_bytes {
-                            if b == esc_char[0] { vars_buffer.extend_from_slice(esc_seq_esc); }
-                            else if b == row_sep[0] { vars_buffer.extend_from_slice(esc_seq_sep); }
-                            else if b == col_sep[0] { vars_buffer.extend_from_slice(esc_seq_col); }
-                            else { vars_buffer.push(b); }
-                        }
+        // Dictionary candidates: (column, dictionary section entry, index cells)
+        let mut dict_columns: Vec<(usize, Vec<u8>, Vec<u8>)> = Vec::new();
+        let mut global_col = 0u64;
+        // End offset of every column in the vars region (cut points for column streams)
+        let mut column_ends = Vec::new();
+        let mut cell_counts = Vec::new();
+        let template_rows = self.stream_template_ids.counts(num_templates);
+
+        for (template, cols) in self.columns_storage.iter().enumerate() {
+            for col_buf in cols {
+                debug_assert_eq!(col_buf.len() as u64, template_rows[template], "column of template {} does not hold one cell per row", template);
+                cell_counts.push(col_buf.len() as u64);
+                let col_start = vars_buffer.len();
+                for idx in 0..col_buf.len() {
+                    if idx > 0 { vars_buffer.extend_from_slice(row_sep); }
+                    let v_bytes = col_buf.get(idx);
+                    debug_assert!(!poison_free || memchr::memchr(BUFFER_POISON, v_bytes).is_none(), "cell {} of template {} holds bytes of a recycled buffer", idx, template);
+
+                    // Byte Stuffing (Always)
+                    for &b in v_bytes {
+                        if b == esc_char[0] { vars_buffer.extend_from_slice(esc_seq_esc); }
+                        else if b == row_sep[0] { vars_buffer.extend_from_slice(esc_seq_sep); }
+                        else if b == col_sep[0] { vars_buffer.extend_from_slice(esc_seq_col); }
+                        else { vars_buffer.push(b); }
                     }
-                    vars_buffer.extend_from_slice(col_sep);
                 }
+                if let Some((values, indices)) = self.column_dicts.then(|| col_buf.dictionary_encode()).flatten() {
+                    let mut entry = Vec::new();
+                    push_varint(&mut entry, global_col);
+                    push_varint(&mut entry, values.len() as u64);
+                    for v in &values {
+                        debug_assert!(!poison_free || memchr::memchr(BUFFER_POISON, v).is_none(), "dictionary value of template {} holds bytes of a recycled buffer", template);
+                        push_varint(&mut entry, v.len() as u64);
+                        entry.extend_from_slice(v);
+                    }
+                    let mut cells = Vec::with_capacity(indices.len() * 2);
+                    for (idx, &i) in indices.iter().enumerate() {
+                        if idx > 0 { cells.extend_from_slice(row_sep); }
+                        push_escaped(&mut cells, &[i]);
+                    }
+                    dict_columns.push((column_ends.len(), entry, cells));
+
+                }
+                vars_buffer.extend_from_slice(col_sep);
+                debug_assert_eq!(count_cells(&vars_buffer[col_start..vars_buffer.len() - 1]), col_buf.len() as u64);
+                column_ends.push(vars_buffer.len());
+                global_col += 1;
             }
         }
 
+        if !dict_columns.is_empty() {
+            let mut dict_section = Vec::new();
+            let mut with_dicts = Vec::with_capacity(vars_buffer.len());
+            let mut dict_ends = Vec::with_capacity(column_ends.len());
+            let mut candidates = dict_columns.iter().peekable();
+            for (col, &end) in column_ends.iter().enumerate() {
+                let start = if col == 0 { 0 } else { column_ends[col - 1] };
+                match candidates.next_if(|(c, _, _)| *c == col) {
+                    Some((_, entry, cells)) => {
+                        dict_section.extend_from_slice(entry);
+                        with_dicts.extend_from_slice(cells);
+                        with_dicts.extend_from_slice(col_sep);
+                    },
+                    None => with_dicts.extend_from_slice(&vars_buffer[start..end]),
+                }
+                dict_ends.push(with_dicts.len());
+            }
+            let mut prefixed = Vec::with_capacity(4 + dict_section.len() + with_dicts.len());
+            prefixed.extend_from_slice(&u32_len(dict_section.len(), "column dictionary")?.to_le_bytes());
+            prefixed.extend_from_slice(&dict_section);
+            prefixed.extend_from_slice(&with_dicts);
+            // Dictionaries cut repeats the backend finds anyway and move the values away from
+            // their neighbours, so they are judged on the whole vars region
+            if dict_probe_len(&prefixed) < dict_probe_len(&vars_buffer) {
+                let shift = 4 + dict_section.len();
+                column_ends = std::iter::once(shift).chain(dict_ends.iter().map(|e| e + shift)).collect();
+                vars_buffer = prefixed;
+                id_mode_flag |= FLAG_DICT_COLS;
+            }
+        }
+
+        if (id_mode_flag & ID_MODE_MASK) != 4 {
+            let mut section = Vec::with_capacity(cell_counts.len() * 2 + 4);
+            push_cell_counts(&mut section, &cell_counts);
+            let shift = section.len();
+            section.extend_from_slice(&vars_buffer);
+            column_ends = std::iter::once(shift).chain(column_ends.iter().map(|e| e + shift)).collect();
+            vars_buffer = section;
+            // u16/u32/u8 ids (modes 0-2) become modes 5-7, whose id stream opens with its encoding byte
+            id_mode_flag += ID_MODE_CELL_COUNTS;
+        }
+
         let mode_str = match self.mode {
             ParsingMode::Strict => "Strict",
             ParsingMode::Aggressive => "Aggressive"
         };
 
         // 7. Final compression (Delegated to Backend)
-        if decision_mode == "SPLIT" {
-            let c_reg = self.backend.compress(&raw_registry);
-            let c_ids = self.backend.compress(&raw_ids);
-            let c_vars = self.backend.compress(&vars_buffer);
-            (c_reg, c_ids, c_vars, id_mode_flag, mode_str.to_string())
-        } else {
-            let len_reg = raw_registry.len() as u32;
-
-            // [FIX SAFE] HYBRID LOGIC FOR BIT-PERFECT BACKWARDS COMPATIBILITY
-            let len_ids = if (id_mode_flag & 0x7F) == 3 {
-                 let has_vars = if let Some(cols) = self.columns_storage.get(&self.stream_template_ids[0]) {
-                     !cols.is_empty()
-                 } else { false };
-
-                 if has_vars { 0 } else { total_rows }
+        if self.column_codecs {
+            let mut head = Vec::with_capacity(16 + raw_registry.len() + raw_ids.len());
+            if (id_mode_flag & ID_MODE_MASK) == 4 {
+                push_varint(&mut head, total_rows);
+                push_varint(&mut head, raw_registry.len() as u64);
             } else {
-                raw_ids.len() as u32
-            };
+                head.extend_from_slice(&u32_len(raw_registry.len(), "registry")?.to_le_bytes());
+                head.extend_from_slice(&u32_len(raw_ids.len(), "id stream")?.to_le_bytes());
+            }
+            head.extend_from_slice(&raw_registry);
+            head.extend_from_slice(&raw_ids);
+            let c_vars = self.encode_column_streams(&head, &vars_buffer, &column_ends)?;
+            Ok((Vec::new(), Vec::new(), c_vars, checked_data_flag(id_mode_flag | FLAG_COLUMN_STREAMS)?, mode_str.to_string()))
+        } else if (id_mode_flag & ID_MODE_MASK) == 4 {
+            let mut solid = Vec::with_capacity(raw_registry.len() + vars_buffer.len() + 16);
+            push_varint(&mut solid, total_rows);
+            push_varint(&mut solid, raw_registry.len() as u64);
+            solid.extend_from_slice(&raw_registry);
+            solid.extend_from_slice(&vars_buffer);
+            let c_solid = self.backend_encode(&solid)?;
+            Ok((Vec::new(), Vec::new(), c_solid, checked_data_flag(id_mode_flag)?, mode_str.to_string()))
+        } else if decision_mode == "SPLIT" {
+            let c_reg = self.backend_encode(&raw_registry)?;
+            let c_ids = self.backend_encode(&raw_ids)?;
+            let c_vars = self.backend_encode(&vars_buffer)?;
+            Ok((c_reg, c_ids, c_vars, checked_data_flag(id_mode_flag)?, mode_str.to_string()))
+        } else {
+            let len_reg = u32_len(raw_registry.len(), "registry")?;
+            let len_ids = u32_len(raw_ids.len(), "id stream")?;
 
             let mut solid = Vec::new();
             solid.extend_from_slice(&len_reg.to_le_bytes());
@@ -484,40 +2148,608 @@ impl<C: NativeCompressor> CASTCompressor<C> {
             solid.extend_from_slice(&raw_registry);
             solid.extend_from_slice(&raw_ids);
             solid.extend_from_slice(&vars_buffer);
-            let c_solid = self.backend.compress(&solid);
-            (Vec::new(), Vec::new(), c_solid, id_mode_flag, mode_str.to_string())
+            let c_solid = self.backend_encode(&solid)?;
+            Ok((Vec::new(), Vec::new(), c_solid, checked_data_flag(id_mode_flag)?, mode_str.to_string()))
+        }
+    }
+
+    /// Transform that the backend shrinks a sample of the id stream most with, against the
+    /// stream as built (`raw_ids`, raw or RLE). See "ID STREAM TRANSFORMS".
+    fn choose_ids_transform(&self, raw_ids: &[u8], id_flag: u8, width: usize) -> Result<IdsTransform, CastError> {
+        let rle = raw_ids.first() == Some(&IDS_RLE);
+        let len = self.stream_template_ids.len();
+        if len < IDS_TRANSFORM_MIN_IDS { return Ok(IdsTransform::None); }
+        let sample: Vec<u32> = if len <= IDS_TRANSFORM_SAMPLE {
+            self.stream_template_ids.values(0..len)
+        } else {
+            let window = IDS_TRANSFORM_SAMPLE / IDS_TRANSFORM_WINDOWS;
+            let step = (len - window) / (IDS_TRANSFORM_WINDOWS - 1);
+            (0..IDS_TRANSFORM_WINDOWS).flat_map(|w| self.stream_template_ids.values(w * step..w * step + window)).collect()
+        };
+        let baseline = if len <= IDS_TRANSFORM_SAMPLE {
+            raw_ids.to_vec()
+        } else {
+            let rle_sample = if rle { encode_ids_rle(&sample, width) } else { None };
+            rle_sample.unwrap_or_else(|| std::iter::once(IDS_RAW).chain(sample.iter().flat_map(|id| id.to_le_bytes()[..width].to_vec())).collect())
+        };
+        let mut best = (self.backend_encode(&baseline)?.len(), IdsTransform::None);
+        for transform in [IdsTransform::MoveToFront, IdsTransform::Delta].into_iter().filter(|t| t.supports(id_flag)) {
+            let size = self.backend_encode(&encode_transformed_ids(&sample, transform, width))?.len();
+            if size < best.0 { best = (size, transform); }
+        }
+        Ok(best.1)
+    }
+
+    fn create_passthrough(&self, data: &[u8], reason: &str) -> Result<CompressedChunk, CastError> {
+        // Long zero runs (sparse disk images) are stored as lengths instead of going through the backend
+        if let Some(sparse) = encode_sparse(data) {
+            let c_vars = self.backend_encode(&sparse)?;
+            return Ok((Vec::new(), Vec::new(), c_vars, ID_FLAG_SPARSE, reason.to_string()));
+        }
+        let c_vars = self.backend_encode(data)?;
+        Ok((Vec::new(), Vec::new(), c_vars, ID_FLAG_PASSTHROUGH, reason.to_string()))
+    }
+}
+
+/// Output budget for previews: stop after N rows or N bytes. The remaining budget is
+/// decremented in place, so one limit can span several chunks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputLimit {
+    Rows(u64),
+    Bytes(u64),
+}
+
+impl OutputLimit {
+    pub fn is_exhausted(&self) -> bool {
+        matches!(self, OutputLimit::Rows(0) | OutputLimit::Bytes(0))
+    }
+
+    /// Bytes of a `len`-byte write that fit in the budget (rows are not counted here).
+    pub fn allow_bytes(&mut self, len: usize) -> usize {
+        match self {
+            OutputLimit::Bytes(left) => {
+                let n = (*left).min(len as u64);
+                *left -= n;
+                n as usize
+            },
+            OutputLimit::Rows(_) => len,
+        }
+    }
+
+    /// Counts a row once its newline has been emitted (a line split across chunks counts once).
+    pub fn finish_row(&mut self, row: &[u8]) {
+        if let OutputLimit::Rows(left) = self {
+            if row.last() == Some(&b'\n') { *left = left.saturating_sub(1); }
+        }
+    }
+
+    /// Length of the prefix of raw (passthrough) data that fits in the budget.
+    pub fn cut(&mut self, data: &[u8]) -> usize {
+        match self {
+            OutputLimit::Rows(left) => {
+                let mut end = 0;
+                while *left > 0 && end < data.len() {
+                    match memchr::memchr(b'\n', &data[end..]) {
+                        Some(p) => { end += p + 1; *left -= 1; },
+                        None => end = data.len(),
+                    }
+                }
+                end
+            },
+            OutputLimit::Bytes(_) => self.allow_bytes(data.len()),
+        }
+    }
+}
+
+/// Origin of a reconstructed byte: the row that produced it, its template, and the
+/// variable column it came from (`None` for literal skeleton text).
+#[derive(Clone, Copy, Debug)]
+pub struct Provenance {
+    pub row: usize,
+    pub template_id: usize,
+    pub part_index: usize,
+    pub column: Option<usize>,
+}
+
+/// Templates of one chunk, as returned by `CASTDecompressor::templates` (used by `--audit`).
+#[derive(Clone, Debug)]
+pub struct ChunkTemplates {
+    /// Skeletons in template id order: the line text with `VAR_PLACEHOLDER` for each
+    /// variable, line ending included
+    pub skeletons: Vec<String>,
+    /// Chunk-relative row where each template first appears (`None` if no row uses it)
+    pub first_rows: Vec<Option<usize>>,
+}
+
+/// Variable columns of one template of a chunk (see `CASTDecompressor::columns`).
+#[derive(Clone, Debug)]
+pub struct TemplateColumns {
+    /// The template's skeleton (see `ChunkTemplates::skeletons`)
+    pub skeleton: String,
+    /// Chunk-relative rows that use the template, in order: cell `i` of every column belongs to `rows[i]`
+    pub rows: Vec<usize>,
+    /// One column per variable of the skeleton, left to right
+    pub columns: Vec<ColumnValues>,
+}
+
+impl TemplateColumns {
+    /// The column filling placeholder `placeholder` (0-based) of the skeleton, `None` past
+    /// the template's last variable.
+    pub fn column(&self, placeholder: usize) -> Option<&ColumnValues> {
+        self.columns.iter().find(|c| c.placeholder == placeholder)
+    }
+}
+
+/// What every cell of a column parses as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnKind {
+    Text,
+    /// `i64`
+    Integer,
+    /// `f64` written as a decimal number (no "inf" or "NaN")
+    Float,
+}
+
+/// The cells of one variable column in the layout of an Arrow string array: cell `i` is
+/// `values[offsets[i]..offsets[i + 1]]`. Cells are UTF-8, also those of Latin-1 chunks.
+#[derive(Clone, Debug)]
+pub struct ColumnValues {
+    /// Ordinal (0-based) of the skeleton placeholder the column fills. Every layout stores a
+    /// template's columns in placeholder order, so the ordinal is also the column's position
+    /// in `TemplateColumns::columns`, in every chunk.
+    pub placeholder: usize,
+    pub offsets: Vec<usize>,
+    pub values: Vec<u8>,
+    pub kind: ColumnKind,
+}
+
+impl ColumnValues {
+    /// Unescapes the first `rows` cells of a column range (missing cells are empty, like in
+    /// `reconstruct`). Cells without escapes are copied whole.
+    fn read(column: &[u8], placeholder: usize, rows: usize) -> Self {
+        let (row_sep, esc_byte) = (0x00u8, 0x01u8);
+        let mut offsets = Vec::with_capacity(rows + 1);
+        let mut values = Vec::with_capacity(column.len());
+        offsets.push(0);
+        let mut cursor = 0;
+        for _ in 0..rows {
+            let rest = &column[cursor.min(column.len())..];
+            match memchr2(row_sep, esc_byte, rest) {
+                Some(pos) if rest[pos] == row_sep => {
+                    values.extend_from_slice(&rest[..pos]);
+                    cursor += pos + 1;
+                },
+                None => {
+                    values.extend_from_slice(rest);
+                    cursor = column.len();
+                },
+                Some(_) => {
+                    let mut r = 0;
+                    while r < rest.len() && rest[r] != row_sep {
+                        if rest[r] == esc_byte && r + 1 < rest.len() {
+                            values.push(match rest[r + 1] { 0x00 => 0x00, 0x03 => 0x02, _ => esc_byte });
+                            r += 2;
+                        } else {
+                            values.push(rest[r]);
+                            r += 1;
+                        }
+                    }
+                    cursor += r + 1;
+                },
+            }
+            offsets.push(values.len());
         }
+        let mut col = Self { placeholder, offsets, values, kind: ColumnKind::Text };
+        col.kind = col.infer_kind();
+        col
+    }
+
+    fn infer_kind(&self) -> ColumnKind {
+        if self.is_empty() { return ColumnKind::Text; }
+        let cells = || (0..self.len()).map(|i| std::str::from_utf8(self.get(i)).ok());
+        if cells().all(|c| c.is_some_and(|c| c.parse::<i64>().is_ok())) { return ColumnKind::Integer; }
+        let decimal = |c: &str| c.starts_with(|ch: char| ch.is_ascii_digit() || matches!(ch, '-' | '+' | '.'))
+            && c.bytes().any(|b| b.is_ascii_digit()) && !c.contains(['i', 'I', 'n', 'N']) && c.parse::<f64>().is_ok();
+        if cells().all(|c| c.is_some_and(decimal)) { return ColumnKind::Float; }
+        ColumnKind::Text
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    fn create_passthrough(&self, data: &[u8], reason: &str) -> (Vec<u8>, Vec<u8>, Vec<u8>, u8, String) {
-        println!("[!] Switching to Passthrough ({})", reason);
-        let c_vars = self.backend.compress(data);
-        (Vec::new(), Vec::new(), c_vars, 255, reason.to_string())
+    pub fn get(&self, index: usize) -> &[u8] {
+        &self.values[self.offsets[index]..self.offsets[index + 1]]
     }
+
+    /// The cells as integers (`Integer` columns only).
+    pub fn integers(&self) -> Option<Vec<i64>> {
+        if self.kind != ColumnKind::Integer { return None; }
+        (0..self.len()).map(|i| std::str::from_utf8(self.get(i)).ok()?.parse().ok()).collect()
+    }
+
+    /// The cells as floats (`Integer` and `Float` columns).
+    pub fn floats(&self) -> Option<Vec<f64>> {
+        if self.kind == ColumnKind::Text { return None; }
+        (0..self.len()).map(|i| std::str::from_utf8(self.get(i)).ok()?.parse().ok()).collect()
+    }
+}
+
+/// Backend-decoded regions of a chunk (see `CASTDecompressor::decode_regions`).
+struct Regions {
+    reg: Vec<u8>,
+    ids: Vec<u8>,
+    vars: Vec<u8>,
+    /// Row count of the single-template modes (0 otherwise)
+    rows: u32,
+}
+
+/// Progress inside a chunk, reported to the hook set with `CASTDecompressor::with_progress`.
+/// Reconstruction progress is visible to callers as the bytes reaching their writer.
+#[derive(Clone, Copy, Debug)]
+pub enum ProgressEvent {
+    /// Compressed bytes of the chunk decoded by the backend so far, out of `total`
+    Backend { consumed: u64, total: u64 },
+}
+
+type ProgressHook = Box<dyn Fn(ProgressEvent) + Send + Sync>;
+
+/// Structure of one chunk after backend decompression, as returned by
+/// `CASTDecompressor::inspect` (used by `--dump-format`).
+#[derive(Clone, Debug)]
+pub struct ChunkLayout {
+    /// "Unified", "Compact", "Split", "Column streams", "Passthrough" or "Sparse passthrough"
+    pub layout: &'static str,
+    /// Backend output that starts with the block header (empty for split and passthrough chunks)
+    pub block: Vec<u8>,
+    /// (name, position in `block`, value) of the block header fields
+    pub fields: Vec<(&'static str, Range<usize>, u64)>,
+    /// Decoded sizes of the registry, ids and vars regions (passthrough data counts as vars)
+    pub reg_len: usize,
+    pub ids_len: usize,
+    pub vars_len: usize,
+    /// Column streams only: (codec, raw length) of each segment
+    pub segments: Vec<(&'static str, usize)>,
+    /// Id modes 5-7 only: stored cell count of each column, in registry order
+    pub cell_counts: Vec<u64>,
+    /// Whether the id stream is stored as RLE pairs, and its transform (see "ID STREAM TRANSFORMS")
+    pub ids_rle: bool,
+    pub ids_transform: IdsTransform,
+}
+
+/// Layout of a stored chunk (the names of `ChunkLayout::layout`, plus "Duplicate"), told from
+/// its id flag and which streams it has, without running the backend.
+pub fn stored_layout(c_reg: &[u8], c_ids: &[u8], id_flag_raw: u8) -> &'static str {
+    if id_flag_raw == ID_FLAG_DUPLICATE { "Duplicate" }
+    else if id_flag_raw == ID_FLAG_SPARSE { "Sparse passthrough" }
+    else if is_passthrough(id_flag_raw) { "Passthrough" }
+    else if (id_flag_raw & FLAG_COLUMN_STREAMS) != 0 { "Column streams" }
+    else if id_mode_of(id_flag_raw) == 4 { "Compact" }
+    else if c_reg.is_empty() && c_ids.is_empty() { "Unified" }
+    else { "Split" }
 }
 
 pub struct CASTDecompressor<D: NativeDecompressor> {
-    backend: D
+    backend: D,
+    progress: Option<ProgressHook>,
+    cancel: Option<CancellationToken>,
+    // Most rows one chunk may declare (see `with_original_size`)
+    max_rows: u64,
 }
 
+// Rows one chunk can hold: the row loop of the decoder counts them in 32 bits
+const MAX_CHUNK_ROWS: u64 = u32::MAX as u64;
+
 impl<D: NativeDecompressor> CASTDecompressor<D> {
     pub fn new(backend: D) -> Self {
-        Self { backend }
+        Self { backend, progress: None, cancel: None, max_rows: MAX_CHUNK_ROWS }
+    }
+
+    /// Bounds the rows of every chunk by the original size recorded in the archive header
+    /// (each row restores at least its newline, the last one may be empty), so a corrupt RLE id
+    /// stream is rejected before its rows are allocated. Without it the bound is 2^32 - 1 rows.
+    pub fn with_original_size(mut self, original_size: Option<u64>) -> Self {
+        if let Some(size) = original_size { self.max_rows = size.saturating_add(1).min(MAX_CHUNK_ROWS); }
+        self
+    }
+
+    /// Checks `token` while lines are rebuilt (every 512KB of output), so a cancelled restore
+    /// of a huge chunk stops with `CastError::Cancelled` instead of running to the end of it.
+    /// The backend pass is cancelled by the backend's own token.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    fn check_cancelled(&self) -> Result<(), CastError> {
+        match &self.cancel {
+            Some(token) => token.check(),
+            None => Ok(()),
+        }
+    }
+
+    /// Calls `hook` while the backend decodes a chunk. The hook is called often (every few KB
+    /// of compressed input): throttle any output it produces.
+    pub fn with_progress<F: Fn(ProgressEvent) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.progress = Some(Box::new(hook));
+        self
+    }
+
+    // Backend decode of one stream starting `base` bytes into the chunk's `total` compressed bytes
+    // A zero-length segment is an empty stream, whatever the backend
+    fn backend_decode(&self, data: &[u8], base: usize, total: usize) -> Result<Vec<u8>, CastError> {
+        if data.is_empty() { return Ok(Vec::new()); }
+        match &self.progress {
+            Some(hook) => {
+                let report = |consumed: u64| hook(ProgressEvent::Backend { consumed: base as u64 + consumed, total: total as u64 });
+                self.backend.decompress_with_progress(data, &report)
+            },
+            None => self.backend.decompress(data),
+        }
+    }
+
+    /// `expected_crc` is `None` for archives written without checksums: the CRC is then neither computed nor checked.
+    pub fn decompress<W: Write>(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], expected_crc: Option<u32>, id_flag_raw: u8, output_writer: &mut W) -> Result<(), CastError> {
+        self.reconstruct(c_reg, c_ids, c_vars, expected_crc, id_flag_raw, output_writer, None, None).map(|_| ())
+    }
+
+    /// Like `decompress`, but stops as soon as `limit` is exhausted (the CRC is only checked
+    /// when the whole chunk was produced).
+    #[allow(clippy::too_many_arguments)]
+    pub fn decompress_limited<W: Write>(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], expected_crc: Option<u32>, id_flag_raw: u8, output_writer: &mut W, limit: &mut OutputLimit) -> Result<(), CastError> {
+        self.reconstruct(c_reg, c_ids, c_vars, expected_crc, id_flag_raw, output_writer, None, Some(limit)).map(|_| ())
+    }
+
+    /// Runs the backend on a chunk's streams and locates their fields with the same parsing
+    /// as `decompress`, without rebuilding the lines or checking the CRC.
+    pub fn inspect(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], id_flag_raw: u8) -> Result<ChunkLayout, CastError> {
+        let total = c_reg.len() + c_ids.len() + c_vars.len();
+        let base = c_reg.len() + c_ids.len();
+        let id_mode = id_mode_of(id_flag_raw);
+        let mut layout = ChunkLayout { layout: stored_layout(c_reg, c_ids, id_flag_raw), block: Vec::new(), fields: Vec::new(), reg_len: 0, ids_len: 0, vars_len: 0, segments: Vec::new(), cell_counts: Vec::new(), ids_rle: false, ids_transform: IdsTransform::None };
+
+        if id_flag_raw == ID_FLAG_DUPLICATE {
+            // Holds only the number of the chunk it repeats
+        } else if is_passthrough(id_flag_raw) {
+            layout.vars_len = self.backend_decode(c_vars, base, total)?.len();
+        } else if (id_flag_raw & FLAG_COLUMN_STREAMS) != 0 {
+            let streams = decode_column_streams(self, c_vars, id_mode, base, total)?;
+            layout.reg_len = streams.head.reg.len();
+            layout.ids_len = streams.head.ids.len();
+            (layout.ids_rle, layout.ids_transform) = stored_ids_encoding(&streams.backend[streams.head.ids.clone()], id_flag_raw)?;
+            layout.vars_len = streams.vars.len();
+            layout.fields = streams.head.fields;
+            layout.segments = streams.segments.iter().map(|&(codec, len)| (column_codec_name(codec), len)).collect();
+            layout.cell_counts = stored_cell_counts(&streams.vars, id_flag_raw)?;
+            layout.block = streams.backend;
+        } else if (c_reg.is_empty() && c_ids.is_empty()) || id_mode == 4 {
+            let block = self.backend_decode(c_vars, base, total)?;
+            let head = block_head(&block, id_mode)?;
+            layout.reg_len = head.reg.len();
+            layout.ids_len = head.ids.len();
+            (layout.ids_rle, layout.ids_transform) = stored_ids_encoding(&block[head.ids.clone()], id_flag_raw)?;
+            layout.vars_len = block.len() - head.ids.end;
+            layout.fields = head.fields;
+            layout.cell_counts = stored_cell_counts(&block[head.ids.end..], id_flag_raw)?;
+            layout.block = block;
+        } else {
+            layout.reg_len = self.backend_decode(c_reg, 0, total)?.len();
+            let ids = self.backend_decode(c_ids, c_reg.len(), total)?;
+            layout.ids_len = ids.len();
+            (layout.ids_rle, layout.ids_transform) = stored_ids_encoding(&ids, id_flag_raw)?;
+            let vars = self.backend_decode(c_vars, base, total)?;
+            layout.vars_len = vars.len();
+            layout.cell_counts = stored_cell_counts(&vars, id_flag_raw)?;
+        }
+        Ok(layout)
+    }
+
+    /// Decodes only what names the templates of a chunk: the registry and id streams of split
+    /// chunks, the block otherwise. Returns `None` for passthrough chunks, which have no templates.
+    pub fn templates(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], id_flag_raw: u8) -> Result<Option<ChunkTemplates>, CastError> {
+        if is_passthrough(id_flag_raw) { return Ok(None); }
+        let corrupt = |detail: &str| CastError::CorruptHeader { chunk: 0, detail: detail.to_string() };
+        let id_mode = id_mode_of(id_flag_raw);
+        let Regions { reg, ids, rows, .. } = self.decode_regions(c_reg, c_ids, c_vars, id_flag_raw, false)?;
+
+        let reg = registry_text(reg, id_flag_raw)?;
+        let skeletons: Vec<String> = reg.split(REG_SEPARATOR).map(str::to_string).collect();
+        let mut first_rows = vec![None; skeletons.len()];
+        if id_mode == 3 || id_mode == 4 {
+            // Legacy single-template chunks (id mode 3 in split layout) do not store their row count
+            if rows > 0 || id_mode == 3 { first_rows[0] = Some(0); }
+        } else {
+            for (row, id) in decode_template_ids(&ids, id_flag_raw, self.max_rows)?.into_iter().enumerate() {
+                let slot = first_rows.get_mut(id).ok_or_else(|| corrupt("Template id outside the registry"))?;
+                if slot.is_none() { *slot = Some(row); }
+            }
+        }
+        Ok(Some(ChunkTemplates { skeletons, first_rows }))
+    }
+
+    /// Reads the variable columns of every template of a chunk straight from the vars stream,
+    /// without rebuilding any line. Returns one entry per template in id order, or `None` for
+    /// passthrough chunks. Duplicate chunks are resolved first (see `ChunkReader::resolve`).
+    pub fn columns(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], id_flag_raw: u8) -> Result<Option<Vec<TemplateColumns>>, CastError> {
+        if is_passthrough(id_flag_raw) { return Ok(None); }
+        let corrupt = |detail: &str| CastError::CorruptHeader { chunk: 0, detail: detail.to_string() };
+        let overflow = |detail: &str| CastError::LengthOverflow { chunk: 0, detail: detail.to_string() };
+        let id_mode = id_mode_of(id_flag_raw);
+        let Regions { reg, ids, mut vars, rows } = self.decode_regions(c_reg, c_ids, c_vars, id_flag_raw, true)?;
+
+        let reg = registry_text(reg, id_flag_raw)?;
+        let skeletons: Vec<&str> = reg.split(REG_SEPARATOR).collect();
+        let mut stored_cells = None;
+        if has_cell_counts(id_flag_raw) {
+            let (counts, rest) = split_cell_counts(&vars).ok_or_else(|| corrupt("Cell count section"))?;
+            let section_len = vars.len() - rest.len();
+            stored_cells = Some(counts);
+            vars.drain(..section_len);
+        }
+        let vars = if (id_flag_raw & FLAG_DICT_COLS) != 0 {
+            rehydrate_dict_columns(&vars).ok_or_else(|| corrupt("Column dictionary section"))?
+        } else {
+            vars
+        };
+
+        // Same column map as `reconstruct`: the placeholders of the registry own the columns in order
+        let placeholder_counts: Vec<usize> = skeletons.iter().map(|skel| skel.matches(VAR_PLACEHOLDER).count()).collect();
+        let total_placeholders: usize = placeholder_counts.iter().sum();
+        let ranges = column_ranges(&vars, total_placeholders);
+        if total_placeholders > ranges.len() { return Err(overflow("Registry declares more columns than the vars stream holds")); }
+        if stored_cells.is_some() || id_mode == 4 {
+            check_cell_counts(&vars, &ranges, &placeholder_counts, stored_cells.as_deref(), rows as u64)?;
+        }
+
+        // Rows of each template, checked against the cells of its first column before the row
+        // lists are allocated (`reconstruct` skips rows with unknown ids too)
+        let template_ids = match id_mode {
+            3 | 4 => Vec::new(),
+            _ => decode_template_ids(&ids, id_flag_raw, self.max_rows)?,
+        };
+        let mut row_counts = vec![0u64; skeletons.len()];
+        match id_mode {
+            4 => row_counts[0] = rows as u64,
+            3 => row_counts[0] = legacy_single_template_rows(rows, ranges.first().map(|&(s, e)| &vars[s..e])) as u64,
+            _ => for &id in &template_ids {
+                if let Some(n) = row_counts.get_mut(id) { *n += 1; }
+            },
+        }
+        if row_counts.iter().sum::<u64>() > self.max_rows { return Err(corrupt("Row count past what the chunk holds")); }
+        let mut first_col = 0;
+        for (template, (&expected, &count)) in row_counts.iter().zip(&placeholder_counts).enumerate() {
+            // A legacy id mode 3 block reads an empty first column as no rows (see legacy_single_template_rows)
+            if count > 0 && !(id_mode == 3 && expected == 0 && ranges[first_col].0 == ranges[first_col].1) {
+                let (start, end) = ranges[first_col];
+                let cells = count_cells(&vars[start..end]);
+                if cells != expected {
+                    return Err(CastError::CorruptHeader { chunk: 0, detail: format!("template {}: {} rows in the id stream, {} cells in its first column", template, expected, cells) });
+                }
+            }
+            first_col += count;
+        }
+
+        let mut template_rows: Vec<Vec<usize>> = row_counts.iter().map(|&n| Vec::with_capacity(n as usize)).collect();
+        match id_mode {
+            3 | 4 => template_rows[0].extend(0..row_counts[0] as usize),
+            _ => for (row, &id) in template_ids.iter().enumerate() {
+                if let Some(rows) = template_rows.get_mut(id) { rows.push(row); }
+            },
+        }
+
+        let mut first_col = 0;
+        let mut templates = Vec::with_capacity(skeletons.len());
+        for ((skeleton, rows), count) in skeletons.iter().zip(template_rows).zip(placeholder_counts) {
+            let columns = ranges[first_col..first_col + count].iter().enumerate()
+                .map(|(placeholder, &(start, end))| ColumnValues::read(&vars[start..end], placeholder, rows.len()))
+                .collect();
+            first_col += count;
+            templates.push(TemplateColumns { skeleton: skeleton.to_string(), rows, columns });
+        }
+        Ok(Some(templates))
+    }
+
+    /// Backend-decodes the registry, id and (if `with_vars`) vars regions of a non-passthrough
+    /// chunk, whatever its layout. Split chunks decode only the regions asked for.
+    fn decode_regions(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], id_flag_raw: u8, with_vars: bool) -> Result<Regions, CastError> {
+        let total = c_reg.len() + c_ids.len() + c_vars.len();
+        let base = c_reg.len() + c_ids.len();
+        let id_mode = id_mode_of(id_flag_raw);
+        if id_flag_raw == ID_FLAG_DUPLICATE { return Err(CastError::CorruptHeader { chunk: 0, detail: "Duplicate chunk not resolved to the chunk it refers to".to_string() }); }
+
+        if (id_flag_raw & FLAG_COLUMN_STREAMS) != 0 {
+            let streams = decode_column_streams(self, c_vars, id_mode, base, total)?;
+            let (reg, ids) = (streams.backend[streams.head.reg].to_vec(), streams.backend[streams.head.ids].to_vec());
+            Ok(Regions { reg, ids, vars: streams.vars, rows: streams.head.rows })
+        } else if (c_reg.is_empty() && c_ids.is_empty()) || id_mode == 4 {
+            let mut block = self.backend_decode(c_vars, base, total)?;
+            let head = block_head(&block, id_mode)?;
+            let vars = if with_vars { block.split_off(head.ids.end) } else { Vec::new() };
+            Ok(Regions { reg: block[head.reg].to_vec(), ids: block[head.ids].to_vec(), vars, rows: head.rows })
+        } else {
+            let vars = if with_vars { self.backend_decode(c_vars, base, total)? } else { Vec::new() };
+            Ok(Regions { reg: self.backend_decode(c_reg, 0, total)?, ids: self.backend_decode(c_ids, c_reg.len(), total)?, vars, rows: 0 })
+        }
+    }
+
+    /// Re-runs the reconstruction of a chunk and reports which row/template/column emitted the
+    /// byte at `offset` (debugging aid). Returns `None` for passthrough chunks or out-of-range offsets.
+    pub fn locate(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], id_flag_raw: u8, offset: usize) -> Result<Option<Provenance>, CastError> {
+        self.reconstruct(c_reg, c_ids, c_vars, None, id_flag_raw, &mut std::io::sink(), Some(offset), None)
     }
 
-    pub fn decompress<W: Write>(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], expected_crc: u32, id_flag_raw: u8, output_writer: &mut W) -> Result<(), String> {
+    #[allow(clippy::too_many_arguments)]
+    fn reconstruct<W: Write>(&self, c_reg: &[u8], c_ids: &[u8], c_vars: &[u8], expected_crc: Option<u32>, id_flag_raw: u8, output_writer: &mut W, locate: Option<usize>, mut limit: Option<&mut OutputLimit>) -> Result<Option<Provenance>, CastError> {
+        let corrupt = |detail: &str| CastError::CorruptHeader { chunk: 0, detail: detail.to_string() };
+        let overflow = |detail: &str| CastError::LengthOverflow { chunk: 0, detail: detail.to_string() };
         //let t_start_total = Instant::now();
+        if id_flag_raw == ID_FLAG_DUPLICATE { return Err(corrupt("Duplicate chunk not resolved to the chunk it refers to")); }
+        if id_flag_raw == ID_FLAG_MAC_TRAILER { return Err(corrupt("Authentication trailer is not a data chunk")); }
 
         let mut writer = BufWriter::with_capacity(512 * 1024, output_writer);
-        let mut hasher = Hasher::new();
+        let mut hasher = expected_crc.map(|_| Hasher::new());
+        let compressed_total = c_reg.len() + c_ids.len() + c_vars.len();
+
+        // --- SPARSE PASSTHROUGH MODE ---
+        if id_flag_raw == ID_FLAG_SPARSE {
+            let records = self.backend_decode(c_vars, c_reg.len() + c_ids.len(), compressed_total)?;
+            let mut limit = limit;
+            let mut emit = |piece: &[u8], writer: &mut BufWriter<&mut W>, hasher: &mut Option<Hasher>| -> Result<bool, CastError> {
+                if let Some(h) = hasher.as_mut() { h.update(piece); }
+                match limit.as_deref_mut() {
+                    Some(l) => {
+                        let end = l.cut(piece);
+                        writer.write_all(&piece[..end])?;
+                        Ok(end == piece.len())
+                    },
+                    None => { writer.write_all(piece)?; Ok(true) },
+                }
+            };
+            let zeros = [0u8; SPARSE_BLOCK];
+            let mut pos = 0;
+            while pos < records.len() {
+                let lit_len = read_varint(&records, &mut pos).ok_or_else(|| overflow("Truncated sparse record"))? as usize;
+                let lit = records.get(pos..pos.saturating_add(lit_len)).ok_or_else(|| overflow("Sparse literal exceeds chunk"))?;
+                pos += lit_len;
+                if !emit(lit, &mut writer, &mut hasher)? { writer.flush()?; return Ok(None); }
+                self.check_cancelled()?;
+                let mut zero_len = read_varint(&records, &mut pos).ok_or_else(|| overflow("Truncated sparse record"))?;
+                while zero_len > 0 {
+                    self.check_cancelled()?;
+                    let n = cmp::min(zero_len, SPARSE_BLOCK as u64) as usize;
+                    if !emit(&zeros[..n], &mut writer, &mut hasher)? { writer.flush()?; return Ok(None); }
+                    zero_len -= n as u64;
+                }
+            }
+            writer.flush()?;
+            if let (Some(h), Some(expected)) = (hasher, expected_crc) {
+                let actual = h.finalize();
+                if !crc_eq(actual, expected) { return Err(CastError::CrcMismatch { chunk: 0, expected, actual }); }
+            }
+            return Ok(None);
+        }
 
         // --- PASSTHROUGH MODE ---
-        if id_flag_raw == 255 {
-            let data = self.backend.decompress(c_vars);
-            hasher.update(&data);
-            writer.write_all(&data).map_err(|e| e.to_string())?;
-            if hasher.finalize() != expected_crc { return Err("CRC Check Failed (Passthrough)".to_string()); }
-            return Ok(());
+        if id_flag_raw == ID_FLAG_PASSTHROUGH {
+            let data = self.backend_decode(c_vars, c_reg.len() + c_ids.len(), compressed_total)?;
+            if let Some(l) = limit {
+                let end = l.cut(&data);
+                writer.write_all(&data[..end])?;
+                writer.flush()?;
+                if end < data.len() { return Ok(None); }
+            } else {
+                writer.write_all(&data)?;
+            }
+            if let (Some(mut h), Some(expected)) = (hasher, expected_crc) {
+                h.update(&data);
+                let actual = h.finalize();
+                if !crc_eq(actual, expected) { return Err(CastError::CrcMismatch { chunk: 0, expected, actual }); }
+            }
+            return Ok(None);
         }
 
         // ====================================================================
@@ -537,98 +2769,88 @@ impl<D: NativeDecompressor> CASTDecompressor<D> {
         let vars_data_bytes: &[u8];
         let num_rows_single_template_header: u32;
 
-        if is_unified {
-            _storage_unified = self.backend.decompress(c_vars);
-            let full = &_storage_unified; // working on reference
-
-            // Parsing Header Unified (Senza Copiare!)
-            if full.len() < 8 { return Err("Corrupted Archive (Header)".to_string()); }
-            let lr = u32::from_le_bytes(full[0..4].try_into().unwrap()) as usize;
-            let li = u32::from_le_bytes(full[4..8].try_into().unwrap()) as usize;
-
-            let mut off = 8;
-            if off + lr > full.len() { return Err("Corrupted Archive (Reg Len)".to_string()); }
-
-            reg_data_bytes = &full[off..off+lr];
-            off += lr;
-
-            if (id_flag_raw & 0x7F) != 3 {
-                if off + li > full.len() { return Err("Corrupted Archive (IDs Len)".to_string()); }
-                ids_data_bytes = &full[off..off+li];
-                num_rows_single_template_header = 0;
-            } else {
-                ids_data_bytes = &[];
-                num_rows_single_template_header = li as u32;
-            }
-
-            let v_start = off + (if (id_flag_raw & 0x7F) != 3 { li } else { 0 });
-            if v_start > full.len() { return Err("Corrupted Archive (Vars)".to_string()); }
+        if (id_flag_raw & FLAG_COLUMN_STREAMS) != 0 {
+            let streams = decode_column_streams(self, c_vars, id_mode_of(id_flag_raw), c_reg.len() + c_ids.len(), compressed_total)?;
+            (_storage_unified, _storage_vars) = (streams.backend, streams.vars);
+            reg_data_bytes = &_storage_unified[streams.head.reg];
+            ids_data_bytes = &_storage_unified[streams.head.ids];
+            vars_data_bytes = &_storage_vars;
+            num_rows_single_template_header = streams.head.rows;
 
-            vars_data_bytes = &full[v_start..];
+        } else if is_unified || id_mode_of(id_flag_raw) == 4 {
+            // Unified block, or compact single template (row count and skeleton in front of the vars)
+            _storage_unified = self.backend_decode(c_vars, c_reg.len() + c_ids.len(), compressed_total)?;
+            let head = block_head(&_storage_unified, id_mode_of(id_flag_raw))?;
+            reg_data_bytes = &_storage_unified[head.reg];
+            vars_data_bytes = &_storage_unified[head.ids.end..];
+            ids_data_bytes = &_storage_unified[head.ids];
+            num_rows_single_template_header = head.rows;
 
         } else {
             // Split mode
-            _storage_reg = self.backend.decompress(c_reg);
+            _storage_reg = self.backend_decode(c_reg, 0, compressed_total)?;
             reg_data_bytes = &_storage_reg;
 
-            if (id_flag_raw & 0x7F) != 3 {
-                _storage_ids = self.backend.decompress(c_ids);
-                ids_data_bytes = &_storage_ids;
-            } else {
-                ids_data_bytes = &[];
-            }
+            // Legacy single-template chunks (id mode 3) have no id stream: an empty segment
+            _storage_ids = self.backend_decode(c_ids, c_reg.len(), compressed_total)?;
+            ids_data_bytes = &_storage_ids;
 
-            _storage_vars = self.backend.decompress(c_vars);
+            _storage_vars = self.backend_decode(c_vars, c_reg.len() + c_ids.len(), compressed_total)?;
             vars_data_bytes = &_storage_vars;
             num_rows_single_template_header = 0;
         }
 
         //let t_backend = t_backend_start.elapsed();
 
+        let mut stored_cells = None;
+        let vars_data_bytes = if has_cell_counts(id_flag_raw) {
+            let (counts, vars) = split_cell_counts(vars_data_bytes).ok_or_else(|| corrupt("Cell count section"))?;
+            stored_cells = Some(counts);
+            vars
+        } else {
+            vars_data_bytes
+        };
+
+        let _storage_dict: Vec<u8>;
+        let vars_data_bytes = if (id_flag_raw & FLAG_DICT_COLS) != 0 {
+            _storage_dict = rehydrate_dict_columns(vars_data_bytes).ok_or_else(|| corrupt("Column dictionary section"))?;
+            &_storage_dict[..]
+        } else {
+            vars_data_bytes
+        };
+
         // ====================================================================
         //  STEP 2: STRUCTURES SETUP
         // ====================================================================
-        let is_latin1 = (id_flag_raw & 0x80) != 0;
-        let id_flag = id_flag_raw & 0x7F;
+        let is_latin1 = (id_flag_raw & FLAG_LATIN1) != 0;
+        let id_flag = id_mode_of(id_flag_raw);
+        let single_template = id_flag == 3 || id_flag == 4;
 
-        let reg_str = String::from_utf8(reg_data_bytes.to_vec()).map_err(|_| "Registry corrupted (UTF-8 error)".to_string())?;
+        let reg_str = registry_text(reg_data_bytes.to_vec(), id_flag_raw)?;
         let skeletons: Vec<&str> = reg_str.split(REG_SEPARATOR).collect();
 
-        let mut template_ids = Vec::with_capacity(if id_flag == 3 { 0 } else { ids_data_bytes.len() / 2 });
-        if id_flag == 2 { for &b in ids_data_bytes { template_ids.push(b as usize); } }
-        else if id_flag == 1 { for ch in ids_data_bytes.chunks_exact(4) { template_ids.push(u32::from_le_bytes(ch.try_into().unwrap()) as usize); } }
-        else if id_flag == 0 { for ch in ids_data_bytes.chunks_exact(2) { template_ids.push(u16::from_le_bytes(ch.try_into().unwrap()) as usize); } }
+        let template_ids = decode_template_ids(ids_data_bytes, id_flag_raw, self.max_rows)?;
 
         // ====================================================================
         //  STEP 3: SIMD COLUMN MAP
         // ====================================================================
         //let t_cast_start = Instant::now();
 
-        let col_sep = 0x02u8;
         let row_sep = 0x00u8;
         let esc_byte = 0x01u8;
 
-        let mut global_col_ranges = Vec::with_capacity(vars_data_bytes.len() / 20);
-        let mut start = 0;
-        let mut cursor = 0;
-        let max_len = vars_data_bytes.len();
-
-        while cursor < max_len {
-            match memchr2(col_sep, esc_byte, &vars_data_bytes[cursor..]) {
-                Some(pos) => {
-                    let real_pos = cursor + pos;
-                    if vars_data_bytes[real_pos] == esc_byte {
-                        cursor = real_pos + 2;
-                    } else {
-                        global_col_ranges.push((start, real_pos));
-                        cursor = real_pos + 1;
-                        start = cursor;
-                    }
-                },
-                None => { cursor = max_len; }
-            }
+        // Every placeholder in the registry owns one column of the vars stream, and every column
+        // ends with at least its separator byte: reject registries that demand more columns than
+        // the stream can hold before sizing anything from them.
+        let placeholder_counts: Vec<usize> = skeletons.iter().map(|skel| skel.matches(VAR_PLACEHOLDER).count()).collect();
+        let total_placeholders: usize = placeholder_counts.iter().sum();
+        if total_placeholders > vars_data_bytes.len() { return Err(overflow("Registry declares more columns than the vars stream holds")); }
+
+        let global_col_ranges = column_ranges(vars_data_bytes, total_placeholders);
+        if total_placeholders > global_col_ranges.len() { return Err(overflow("Registry declares more columns than the vars stream holds")); }
+        if stored_cells.is_some() || id_flag == 4 {
+            check_cell_counts(vars_data_bytes, &global_col_ranges, &placeholder_counts, stored_cells.as_deref(), num_rows_single_template_header as u64)?;
         }
-        if start < max_len { global_col_ranges.push((start, max_len)); }
 
         let mut template_col_map = Vec::with_capacity(skeletons.len());
         let mut global_col_cursors = Vec::with_capacity(global_col_ranges.len());
@@ -640,8 +2862,7 @@ impl<D: NativeDecompressor> CASTDecompressor<D> {
         }
 
         let mut col_alloc_iter = 0..global_col_ranges.len();
-        for skel in &skeletons {
-            let num_vars = skel.matches(VAR_PLACEHOLDER).count();
+        for &num_vars in &placeholder_counts {
             let mut indices = Vec::with_capacity(num_vars);
             for _ in 0..num_vars {
                 if let Some(idx) = col_alloc_iter.next() { indices.push(idx); }
@@ -655,40 +2876,29 @@ impl<D: NativeDecompressor> CASTDecompressor<D> {
 
         const BUF_SIZE: usize = 512 * 1024;
         let mut out_buffer: Vec<u8> = Vec::with_capacity(BUF_SIZE * 2);
+        let mut flushed_bytes = 0usize;
+        let mut provenance: Option<Provenance> = None;
+        let mut limited = false;
 
         // ====================================================================
         //  STEP 4: SIMD + OUTER FLUSH
         // ====================================================================
 
-        let count_loop = if id_flag == 3 {
-             let mut n = num_rows_single_template_header;
-             if n == 0 && !global_col_ranges.is_empty() {
-                 let (s, e) = global_col_ranges[0];
-                 let slice = &vars_data_bytes[s..e];
-                 let mut iter_pos = 0;
-                 while iter_pos < slice.len() {
-                     match memchr2(row_sep, esc_byte, &slice[iter_pos..]) {
-                         Some(p) => {
-                             let real = iter_pos + p;
-                             if slice[real] == esc_byte { iter_pos = real + 2; }
-                             else { n += 1; iter_pos = real + 1; }
-                         },
-                         None => break,
-                     }
-                 }
-                 if slice.len() > 0 && slice[slice.len()-1] != row_sep {
-                     if slice.len() < 2 || slice[slice.len()-2] != esc_byte { n += 1; }
-                 }
-             }
-             n
+        let count_loop = if id_flag == 4 {
+            num_rows_single_template_header
+        } else if id_flag == 3 {
+            legacy_single_template_rows(num_rows_single_template_header, global_col_ranges.first().map(|&(s, e)| &vars_data_bytes[s..e]))
         } else { template_ids.len() as u32 };
 
-        for i in 0..count_loop {
-            let t_id = if id_flag == 3 { 0 } else { template_ids[i as usize] };
-            if t_id as usize >= skel_parts_cache.len() { continue; }
+        'rows: for i in 0..count_loop {
+            let t_id = if single_template { 0 } else { template_ids[i as usize] };
+            if t_id >= skel_parts_cache.len() { continue; }
 
-            let parts = &skel_parts_cache[t_id as usize];
-            let col_indices = &template_col_map[t_id as usize];
+            if limit.as_deref().is_some_and(|l| l.is_exhausted()) { limited = true; break; }
+            let row_start = out_buffer.len();
+
+            let parts = &skel_parts_cache[t_id];
+            let col_indices = &template_col_map[t_id];
 
             for (p_idx, part) in parts.iter().enumerate() {
                 if is_latin1 {
@@ -698,12 +2908,20 @@ impl<D: NativeDecompressor> CASTDecompressor<D> {
                     out_buffer.extend_from_slice(part.as_bytes());
                 }
 
+                if let Some(target) = locate {
+                    if flushed_bytes + out_buffer.len() > target {
+                        provenance = Some(Provenance { row: i as usize, template_id: t_id, part_index: p_idx, column: None });
+                        break 'rows;
+                    }
+                }
+
                 if p_idx < col_indices.len() {
                     let g_idx = col_indices[p_idx];
                     let cursor = global_col_cursors[g_idx];
                     let limit = global_col_limits[g_idx];
 
                     if cursor < limit {
+                        let cell_start = out_buffer.len();
                         let remaining_slice = &vars_data_bytes[cursor..limit];
                         let (len, found_sep, found_esc) = match memchr2(row_sep, esc_byte, remaining_slice) {
                             Some(pos) => {
@@ -741,26 +2959,43 @@ impl<D: NativeDecompressor> CASTDecompressor<D> {
                             }
                             global_col_cursors[g_idx] = cursor + local_end + (if ended { 1 } else { 0 });
                         }
+                        // Latin-1 chunks are parsed as text, so their cells were stored as UTF-8
+                        if is_latin1 { utf8_tail_to_latin1(&mut out_buffer, cell_start); }
+                    }
+
+                    if let Some(target) = locate {
+                        if flushed_bytes + out_buffer.len() > target {
+                            provenance = Some(Provenance { row: i as usize, template_id: t_id, part_index: p_idx, column: Some(p_idx) });
+                            break 'rows;
+                        }
                     }
                 }
             }
 
+            if let Some(l) = limit.as_deref_mut() {
+                let row_len = out_buffer.len() - row_start;
+                let allowed = l.allow_bytes(row_len);
+                l.finish_row(&out_buffer[row_start..]);
+                if allowed < row_len { out_buffer.truncate(row_start + allowed); limited = true; break; }
+            }
+
             if out_buffer.len() >= BUF_SIZE {
-                hasher.update(&out_buffer);
-                writer.write_all(&out_buffer).map_err(|e| e.to_string())?;
+                self.check_cancelled()?;
+                if let Some(h) = hasher.as_mut() { h.update(&out_buffer); }
+                writer.write_all(&out_buffer)?;
+                flushed_bytes += out_buffer.len();
                 out_buffer.clear();
             }
         }
 
         if !out_buffer.is_empty() {
-            hasher.update(&out_buffer);
-            writer.write_all(&out_buffer).map_err(|e| e.to_string())?;
+            if let Some(h) = hasher.as_mut() { h.update(&out_buffer); }
+            writer.write_all(&out_buffer)?;
         }
 
         //let t_cast = t_cast_start.elapsed();
 
-        writer.flush().map_err(|e| e.to_string())?;
-        let crc = hasher.finalize();
+        writer.flush()?;
 
         /*println!("\n🔍 [CAST DIAGNOSTICS] ---------------------------------");
         println!("   📦 Backend Time (Load & Unzip):  {:.2?}", t_backend);
@@ -768,10 +3003,106 @@ impl<D: NativeDecompressor> CASTDecompressor<D> {
         println!("   ⏱️  TOTAL WALL CLOCK:             {:.2?}", t_start_total.elapsed());
         println!("   -----------------------------------------------------\n");*/
 
-        if crc != expected_crc {
-            return Err(format!("CRC Check Failed. Expected: {}, Got: {}", expected_crc, crc));
+        if let (Some(h), Some(expected), false) = (hasher, expected_crc, limited) {
+            let actual = h.finalize();
+            if !crc_eq(actual, expected) {
+                return Err(CastError::CrcMismatch { chunk: 0, expected, actual });
+            }
+        }
+
+        Ok(provenance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The line a decoder rebuilds: each placeholder of `skel` replaced by the next variable.
+    fn fill(skel: &str, vars: &[&str]) -> String {
+        let mut parts = skel.split(VAR_PLACEHOLDER);
+        let mut line = parts.next().unwrap_or_default().to_string();
+        for (part, var) in parts.zip(vars) {
+            line.push_str(var);
+            line.push_str(part);
+        }
+        line
+    }
+
+    fn parse(line: &str, mode: ParsingMode, max_var_len: usize) -> (String, Vec<&str>, u64) {
+        let (mut vars, mut skel, mut long_values) = (Vec::new(), String::new(), 0);
+        assert!(parse_line_manual(line, mode, true, max_var_len, &mut long_values, &mut vars, &mut skel), "{:?} rejected", line);
+        (skel, vars, long_values)
+    }
+
+    #[test]
+    fn quoted_len_shapes() {
+        assert_eq!(quoted_len(b"\"\""), Some(2));
+        assert_eq!(quoted_len(b"\"\" tail"), Some(2));
+        assert_eq!(quoted_len(b"\"a\"\"\""), Some(5));
+        assert_eq!(quoted_len(b"\"a\"\""), None);
+        assert_eq!(quoted_len(b"\"a\\\"b\""), Some(6));
+        assert_eq!(quoted_len(b"\"a\\"), None);
+        assert_eq!(quoted_len(b"\"abc"), None);
+        assert_eq!(quoted_len(b"\""), None);
+    }
+
+    #[test]
+    fn parse_line_shapes() {
+        // (line, skeleton, variables) in strict mode
+        let cases: &[(&str, &str, &[&str])] = &[
+            // Empty quotes: an empty variable behind the quoted placeholder
+            ("a=\"\" b", "a=\"\u{E000}\" b", &[""]),
+            // Doubled quote at the end of the line: the string stays open, its last pair is an empty string
+            ("x \"a\"\"", "x \"a\"\u{E000}\"", &[""]),
+            ("x \"a\"\"\" 7", "x \"\u{E000}\" \u{E000}", &["a\"\"", "7"]),
+            // Unterminated quotes stay in the skeleton, the tokens after them are still variables
+            ("say \"hello 42", "say \"hello \u{E000}", &["42"]),
+            ("\"", "\"", &[]),
+            // Trailing backslash: nothing to escape, the string stays open
+            ("p \"x\\", "p \"x\\", &[]),
+            ("p \"x\\\" 5\\", "p \"x\\\" \u{E000}\\", &["5"]),
+            // An escaped quote does not close the string
+            ("\"a\\\"b\" 7", "\"\u{E000}\" \u{E000}", &["a\\\"b", "7"]),
+        ];
+        for &(line, skel, vars) in cases {
+            let (got_skel, got_vars, long_values) = parse(line, ParsingMode::Strict, DEFAULT_MAX_VAR_LEN);
+            assert_eq!((got_skel.as_str(), got_vars.as_slice(), long_values), (skel, vars, 0), "{:?}", line);
+            assert_eq!(fill(&got_skel, &got_vars), line);
         }
+    }
 
-        Ok(())
+    /// Every line of up to 9 bytes over quotes, backslashes, a digit and a space, in both
+    /// modes, with the default and a 1-byte variable limit: the line is rebuilt exactly, and no variable is
+    /// longer than the limit.
+    #[test]
+    fn parse_line_exhaustive() {
+        const ALPHABET: &[u8] = b"\"\\7 ";
+        const MAX_LEN: usize = 9;
+        let mut line = Vec::with_capacity(MAX_LEN);
+        let mut digits = [0usize; MAX_LEN];
+        for len in 0..=MAX_LEN {
+            digits[..len].fill(0);
+            loop {
+                line.clear();
+                line.extend(digits[..len].iter().map(|&d| ALPHABET[d]));
+                let text = std::str::from_utf8(&line).unwrap();
+                if let Some(n) = line.first().filter(|&&b| b == b'"').and_then(|_| quoted_len(&line)) {
+                    assert!(n >= 2 && n <= len && line[n - 1] == b'"', "{:?}", text);
+                }
+                for mode in [ParsingMode::Strict, ParsingMode::Aggressive] {
+                    for max_var_len in [DEFAULT_MAX_VAR_LEN, 1] {
+                        let (skel, vars, _) = parse(text, mode, max_var_len);
+                        assert_eq!(skel.matches(VAR_PLACEHOLDER).count(), vars.len(), "{:?}", text);
+                        assert!(vars.iter().all(|v| v.len() <= max_var_len), "{:?}", text);
+                        assert_eq!(fill(&skel, &vars), text, "{:?} (aggressive: {})", text, mode == ParsingMode::Aggressive);
+                    }
+                }
+                // Next line of this length (digits as a base-ALPHABET counter)
+                let Some(pos) = digits[..len].iter().position(|&d| d + 1 < ALPHABET.len()) else { break };
+                digits[pos] += 1;
+                digits[..pos].fill(0);
+            }
+        }
     }
 }
\ No newline at end of file
@@ -8,9 +8,23 @@ use xz2::stream::{Stream, MtStreamBuilder, Check, LzmaOptions, Filters};
 use std::process::{Command, Stdio};
 use std::thread;
 
+use crate::cancel::CancellationToken;
+use crate::error::CastError;
+use crate::memory::{available_memory, MemoryBudget};
 use crate::cast::{NativeCompressor, NativeDecompressor, CASTCompressor, CASTDecompressor};
 
 const LZMA_PRESET_EXTREME: u32 = 0x80000000;
+// Preset of the native encoder (the strongest); `LzmaBackend::with_preset` trades ratio for speed
+const LZMA_DEFAULT_PRESET: u32 = 9 | LZMA_PRESET_EXTREME;
+// Input fed to the encoder between two cancellation checks
+const CANCEL_CHECK_BYTES: usize = 4 * 1024 * 1024;
+// Smallest dictionary liblzma accepts
+const LZMA_DICT_SIZE_MIN: u32 = 4096;
+// Smallest block of a multithreaded stream (liblzma's own floor for LZMA2 blocks)
+const MT_MIN_BLOCK: u64 = 1024 * 1024;
+// liblzma's own decoder state next to the dictionary (stream, block and LZMA2 decoders):
+// measured at just over 64KB, with room to spare for other liblzma versions
+const XZ_DECODER_OVERHEAD: u64 = 96 * 1024;
 
 // ============================================================================
 //  HELPER: 7-Zip Detection
@@ -71,183 +85,495 @@ pub fn try_find_7zip_path() -> Option<String> {
 }
 
 
+// ============================================================================
+//  MULTITHREADING DECISION
+// ============================================================================
+//
+//  The multithreaded encoder cuts a stream into independent blocks, one per thread. A stream
+//  that fits in one block is encoded by a single thread whatever the thread count, so the
+//  native backend only goes multithreaded when the stream spans at least two blocks. Blocks
+//  are as large as the dictionary: smaller blocks would leave most of it unused.
+
+/// Largest .xz stream liblzma or 7-Zip can write for `input_len` bytes cut into blocks of at
+/// least `min_block` bytes: LZMA2 stores data it cannot shrink in chunks of up to 64KB with a
+/// 3-byte header, and each block adds at most its header (1024), padding (3), check (64) and
+/// index record (18). The stream header, footer and index frame add 41 bytes.
+pub fn xz_max_len(input_len: usize, min_block: u64) -> usize {
+    const LZMA2_CHUNK: usize = 64 * 1024;
+    const BLOCK_OVERHEAD: usize = 1 + 1024 + 3 + 64 + 18;
+    const STREAM_OVERHEAD: usize = 12 + 12 + 17;
+    let blocks = input_len.div_ceil(min_block.max(1) as usize).max(1);
+    let chunks = input_len.div_ceil(LZMA2_CHUNK) + blocks;
+    input_len.saturating_add(chunks.saturating_mul(3)).saturating_add(blocks.saturating_mul(BLOCK_OVERHEAD)).saturating_add(STREAM_OVERHEAD)
+}
+
+/// How the native encoder runs on one stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MtPlan {
+    pub multithread: bool,
+    pub dict_size: u32,
+    pub block_size: u64,
+}
+
+/// Block size of a multithreaded stream with `dict_size`.
+pub fn mt_block_size(dict_size: u32) -> u64 {
+    (dict_size as u64).max(MT_MIN_BLOCK)
+}
+
+/// Largest power-of-two dictionary that cuts `len` bytes into at least `threads` blocks (never
+/// below the minimum block size).
+pub fn mt_dict_size(len: u64, threads: u32) -> u32 {
+    let share = (len / threads.max(2) as u64).clamp(MT_MIN_BLOCK, u32::MAX as u64);
+    1u32 << (31 - (share as u32).leading_zeros())
+}
+
+/// Multithreading decision for a stream of `len` bytes. `force` shrinks the dictionary (to
+/// `mt_dict_size`) when that is what keeps the stream from being split.
+pub fn plan_multithread(len: u64, dict_size: u32, multithread: bool, force: bool, threads: u32) -> MtPlan {
+    let plan = |dict_size| MtPlan { multithread: multithread && len > mt_block_size(dict_size), dict_size, block_size: mt_block_size(dict_size) };
+    let requested = plan(dict_size);
+    if requested.multithread || !(multithread && force) || threads < 2 { return requested; }
+    // Streams too small to split even with the smallest block keep their dictionary
+    let forced = plan(dict_size.min(mt_dict_size(len, threads)));
+    if forced.multithread { forced } else { requested }
+}
+
+/// Dictionary the native encoder uses for a stream of `len` bytes: a dictionary larger than the
+/// input finds no further matches but is still allocated (about 10x its size by the encoder), so
+/// it is clamped to the input rounded up to a power of two, which compresses the same and makes
+/// small inputs cheap.
+pub fn stream_dict_size(len: u64, dict_size: u32) -> u32 {
+    len.max(LZMA_DICT_SIZE_MIN as u64).checked_next_power_of_two()
+        .filter(|&d| d < dict_size as u64)
+        .map_or(dict_size, |d| d as u32)
+}
+
 // ============================================================================
 //  BACKEND 1: NATIVE (XZ2 Lib)
 // ============================================================================
 
 pub struct LzmaBackend {
+    preset: u32,
     multithread: bool,
+    force_multithread: bool,
     dict_size: u32,
+    threads: Option<u32>,
+    cancel: Option<CancellationToken>,
 }
 
 impl LzmaBackend {
     pub fn new(multithread: bool, dict_size: u32) -> Self {
-        Self { multithread, dict_size }
+        Self { preset: LZMA_DEFAULT_PRESET, multithread, force_multithread: false, dict_size, threads: None, cancel: None }
+    }
+
+    /// Encodes with xz preset `level` (0-9, no extreme flag) instead of 9e: much faster, a
+    /// larger output, the same stream format. The dictionary stays capped at `dict_size`.
+    pub fn with_preset(mut self, level: u32) -> Self {
+        self.preset = level.min(9);
+        self
+    }
+
+    /// Multithreaded runs shrink the dictionary of streams that would otherwise fit in one
+    /// block, trading ratio for speed (see `plan_multithread`).
+    pub fn with_forced_multithread(mut self) -> Self {
+        self.multithread = true;
+        self.force_multithread = true;
+        self
+    }
+
+    /// Checks `token` while encoding (every `CANCEL_CHECK_BYTES` of input).
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Caps the encoder threads of multithreaded runs (default: one per CPU).
+    pub fn with_threads(mut self, threads: u32) -> Self {
+        self.threads = Some(threads.max(1));
+        self
     }
 }
 
 impl NativeCompressor for LzmaBackend {
-    fn compress(&self, data: &[u8]) -> Vec<u8> {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
         // EXACT LOGIC FROM ORIGINAL compress_buffer_native
-        if data.is_empty() { return Vec::new(); }
+        if data.is_empty() { return Ok(Vec::new()); }
 
-        let effective_multithread = if self.multithread && (data.len() as u32) < self.dict_size {
-            false
-        } else {
-            self.multithread
-        };
+        let threads = self.threads.unwrap_or(num_cpus::get() as u32);
+        let plan = plan_multithread(data.len() as u64, self.dict_size, self.multithread, self.force_multithread, threads);
 
-        let mut opts = LzmaOptions::new_preset(9 | LZMA_PRESET_EXTREME).unwrap();
-        opts.dict_size(self.dict_size); // Uses the passed dictionary size
+        let dict_size = stream_dict_size(data.len() as u64, plan.dict_size);
+
+        let mut opts = LzmaOptions::new_preset(self.preset).map_err(native_failure)?;
+        opts.dict_size(dict_size);
 
         let mut filters = Filters::new();
         filters.lzma2(&opts);
 
         let estimated = data.len() / 2;
-        let safe_capacity = cmp::min(estimated, self.dict_size as usize);
+        let safe_capacity = cmp::min(estimated, dict_size as usize);
         let output_buffer = Vec::with_capacity(safe_capacity);
         let writer = std::io::BufWriter::new(output_buffer);
 
-        if !effective_multithread {
-            let stream = Stream::new_stream_encoder(&filters, Check::Crc32).expect("LZMA Init Error");
-            let mut compressor = XzEncoder::new_stream(writer, stream);
-            compressor.write_all(data).expect("LZMA Write Error");
-            let finished = compressor.finish().expect("LZMA Finish Error");
-            return finished.into_inner().expect("Buffer extraction error");
-        }
-
-        let threads = num_cpus::get() as u32;
-        let stream = MtStreamBuilder::new()
-            .threads(threads)
-            .filters(filters)
-            .check(Check::Crc32)
-            .encoder()
-            .expect("LZMA MT Init Error");
+        let stream = if !plan.multithread {
+            Stream::new_stream_encoder(&filters, Check::Crc32).map_err(native_failure)?
+        } else {
+            MtStreamBuilder::new()
+                .threads(threads)
+                .block_size(plan.block_size)
+                .filters(filters)
+                .check(Check::Crc32)
+                .encoder()
+                .map_err(native_failure)?
+        };
 
         let mut compressor = XzEncoder::new_stream(writer, stream);
-        compressor.write_all(data).expect("LZMA MT Write Error");
-        let finished = compressor.finish().expect("LZMA MT Finish Error");
-        finished.into_inner().expect("Buffer extraction error")
+        for slice in data.chunks(CANCEL_CHECK_BYTES) {
+            if let Some(token) = &self.cancel { token.check()?; }
+            compressor.write_all(slice).map_err(native_failure)?;
+        }
+        let finished = compressor.finish().map_err(native_failure)?;
+        finished.into_inner().map_err(|e| native_failure(e.error()))
     }
+
+    fn max_compressed_len(&self, input_len: usize) -> Option<usize> {
+        if input_len == 0 { return Some(0); }
+        // Multithreaded blocks are never smaller than MT_MIN_BLOCK (see `mt_block_size`)
+        Some(xz_max_len(input_len, MT_MIN_BLOCK))
+    }
+
+    fn cancellation(&self) -> Option<&CancellationToken> { self.cancel.as_ref() }
+}
+
+#[derive(Default)]
+pub struct LzmaDecompressorBackend {
+    budget: Option<MemoryBudget>,
+    mem_limit: Option<u64>,
+    cancel: Option<CancellationToken>,
 }
 
-pub struct LzmaDecompressorBackend;
+impl LzmaDecompressorBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bounds the decoder memory and the up-front allocation of each block.
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Sets liblzma's memory limit: streams whose dictionary does not fit fail up front with
+    /// `CastError::DecoderMemory` instead of allocating. Combined with a budget, the lower wins.
+    pub fn with_mem_limit(mut self, bytes: u64) -> Self {
+        self.mem_limit = Some(bytes);
+        self
+    }
+
+    /// Checks `token` each time the decoder pulls more input.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    fn decoder_limit(&self) -> Option<u64> {
+        match (self.budget.map(|b| b.decoder_limit()), self.mem_limit) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
 
 impl NativeDecompressor for LzmaDecompressorBackend {
-    fn decompress(&self, data: &[u8]) -> Vec<u8> {
-        if data.is_empty() { return Vec::new(); }
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        self.decompress_with_progress(data, &|_| {})
+    }
 
-        let mut decompressor = XzDecoder::new(data);
+    fn decompress_with_progress(&self, data: &[u8], progress: &dyn Fn(u64)) -> Result<Vec<u8>, CastError> {
+        if data.is_empty() { return Ok(Vec::new()); }
+        let reader = ProgressReader { inner: data, consumed: 0, progress, cancel: self.cancel.as_ref() };
+        let limit = self.decoder_limit();
+        let mut decompressor = match limit {
+            Some(limit) => XzDecoder::new_stream(reader, Stream::new_stream_decoder(limit, 0).map_err(native_failure)?),
+            None => XzDecoder::new(reader),
+        };
 
         let estimated = data.len().saturating_mul(6);
 
-        let safe_capacity = std::cmp::min(estimated, 2 * 1024 * 1024 * 1024);
+        let reserve_cap = self.budget.map_or(2 * 1024 * 1024 * 1024, |b| b.decode_reserve());
+        let safe_capacity = std::cmp::min(estimated, reserve_cap);
 
         let mut output = Vec::with_capacity(safe_capacity);
-        decompressor.read_to_end(&mut output).expect("Decompression Error");
-        output
+        decompressor.read_to_end(&mut output).map_err(|e| match &self.cancel {
+            Some(token) if token.is_cancelled() => CastError::Cancelled,
+            _ if is_memory_error(&e) => memory_failure("Native (xz2)", data, limit, e.to_string()),
+            _ => native_failure(e),
+        })?;
+        Ok(output)
+    }
+}
+
+// Counts the compressed bytes pulled by the decoder, and stops feeding it once cancelled
+struct ProgressReader<'a> {
+    inner: &'a [u8],
+    consumed: u64,
+    progress: &'a dyn Fn(u64),
+    cancel: Option<&'a CancellationToken>,
+}
+
+impl Read for ProgressReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.cancel.is_some_and(|t| t.is_cancelled()) {
+            return Err(std::io::Error::other("cancelled"));
+        }
+        let n = self.inner.read(buf)?;
+        self.consumed += n as u64;
+        if n > 0 { (self.progress)(self.consumed); }
+        Ok(n)
+    }
+}
+
+fn native_failure<E: std::fmt::Display>(e: E) -> CastError {
+    CastError::BackendFailure { backend: "Native (xz2)", detail: e.to_string() }
+}
+
+// liblzma refusing the stream for its memory limit, or failing to allocate the dictionary
+fn is_memory_error(e: &std::io::Error) -> bool {
+    use xz2::stream::Error;
+    e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()).is_some_and(|e| matches!(e, Error::MemLimit | Error::Mem))
+}
+
+fn memory_failure(backend: &'static str, stream: &[u8], limit: Option<u64>, detail: String) -> CastError {
+    CastError::DecoderMemory { backend, required: xz_dict_size(stream).map(xz_decoder_memory), limit, available: available_memory(), detail }
+}
+
+// ============================================================================
+//  XZ STREAM HEADERS
+// ============================================================================
+//
+//  The memory an xz stream needs to decode is set by the LZMA2 dictionary of its blocks, which
+//  the first block header records. Reading it needs no decoder, so a memory failure can say
+//  what the archive actually requires, and `info --detailed` can list it for every chunk.
+
+const XZ_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const XZ_STREAM_HEADER_LEN: usize = 12;
+const LZMA2_FILTER_ID: u64 = 0x21;
+
+/// Dictionary size of the LZMA2 filter in the first block header of an xz stream. None when
+/// the stream has no block or its headers are damaged.
+pub fn xz_dict_size(stream: &[u8]) -> Option<u32> {
+    if !stream.starts_with(&XZ_MAGIC) { return None; }
+    let block = stream.get(XZ_STREAM_HEADER_LEN..)?;
+    // The first byte gives the header size in 4-byte units; 0 starts the index (no block)
+    let size_byte = *block.first()?;
+    if size_byte == 0 { return None; }
+    let header = block.get(..(size_byte as usize + 1) * 4)?;
+    let flags = header[1];
+    let mut pos = 2;
+    // Optional compressed and uncompressed sizes
+    if flags & 0x40 != 0 { xz_varint(header, &mut pos)?; }
+    if flags & 0x80 != 0 { xz_varint(header, &mut pos)?; }
+    for _ in 0..=(flags & 0x03) {
+        let id = xz_varint(header, &mut pos)?;
+        let props_len = usize::try_from(xz_varint(header, &mut pos)?).ok()?;
+        let props = header.get(pos..pos.checked_add(props_len)?)?;
+        pos += props_len;
+        if id == LZMA2_FILTER_ID {
+            return match props { [p] => lzma2_dict_size(*p), _ => None };
+        }
+    }
+    None
+}
+
+/// Integrity check named in the stream flags of an xz stream ("CRC32", "CRC64", "SHA-256",
+/// "none"). None when `stream` is not xz.
+pub fn xz_check_name(stream: &[u8]) -> Option<&'static str> {
+    if !stream.starts_with(&XZ_MAGIC) { return None; }
+    Some(match *stream.get(XZ_MAGIC.len() + 1)? & 0x0F {
+        0x00 => "none",
+        0x01 => "CRC32",
+        0x04 => "CRC64",
+        0x0A => "SHA-256",
+        _ => "unknown",
+    })
+}
+
+/// Memory the xz decoder needs for a dictionary of `dict_size` bytes.
+pub fn xz_decoder_memory(dict_size: u32) -> u64 {
+    dict_size as u64 + XZ_DECODER_OVERHEAD
+}
+
+// LZMA2 property byte: 2 or 3 times a power of two, from 4KB up to 4GB - 1
+fn lzma2_dict_size(props: u8) -> Option<u32> {
+    match props {
+        40 => Some(u32::MAX),
+        0..=39 => Some((2 | (props as u32 & 1)) << (props / 2 + 11)),
+        _ => None,
     }
 }
 
+// Variable-length integer of the xz headers: 7 bits per byte, low bits first, at most 9 bytes
+fn xz_varint(buf: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *buf.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 { return Some(value); }
+    }
+    None
+}
+
 
 // ============================================================================
 //  BACKEND 2: 7-ZIP (External Executable)
 // ============================================================================
 
+// Switches that would break the stdin/stdout pipe (also by sending messages to stdout with
+// -bs), change the container type (-sfx writes an executable), or read/write files of their
+// own. Matched as prefixes (case-insensitive).
+const SEVEN_ZIP_FORBIDDEN_SWITCHES: &[&str] = &["-si", "-so", "-an", "-o", "-t", "-v", "-w", "-i", "-x", "-ai", "-ax", "-sdel", "-spf", "-scrc", "-bs", "-sfx"];
+
+/// Splits and validates user-supplied 7-Zip switches (`--7z-args`). Only `-` switches are
+/// accepted: positional arguments (archive/file names, `@listfiles`), redirection tokens and the
+/// switches in `SEVEN_ZIP_FORBIDDEN_SWITCHES` are rejected.
+pub fn parse_7z_args(s: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for token in s.split_whitespace() {
+        if !token.starts_with('-') || token.len() < 2 {
+            return Err(format!("'{}' is not a switch (file arguments are managed by CAST)", token));
+        }
+        if token.contains(['>', '<', '|']) {
+            return Err(format!("'{}' looks like a redirection", token));
+        }
+        let lower = token.to_lowercase();
+        if let Some(sw) = SEVEN_ZIP_FORBIDDEN_SWITCHES.iter().find(|sw| lower.starts_with(*sw)) {
+            return Err(format!("'{}' is not allowed ({} is managed by CAST)", token, sw));
+        }
+        args.push(token.to_string());
+    }
+    Ok(args)
+}
+
 pub struct SevenZipBackend {
     dict_size: u32,
+    threads: Option<u32>,
+    extra_args: Vec<String>,
 }
 
 impl SevenZipBackend {
     pub fn new(dict_size: u32) -> Self {
-        Self { dict_size }
+        Self { dict_size, threads: None, extra_args: Vec::new() }
     }
-}
 
-impl NativeCompressor for SevenZipBackend {
-    fn compress(&self, data: &[u8]) -> Vec<u8> {
-        // 1. QUICK CHECK
-        if data.is_empty() { return Vec::new(); }
-
-        let dict_arg = format!("-m0=lzma2:d{}b", self.dict_size);
-        let cmd = get_7z_cmd();
-
-        let mut child = Command::new(&cmd)
-            .args(&["a", "-txz", "-mx=9", "-mmt=on", &dict_arg, "-si", "-so", "-an", "-y", "-bb0"])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .expect("Failed to spawn 7-Zip");
-
-        let input_data = data.to_vec();
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
-
-        // 4. THREAD ANTI-DEADLOCK
-        thread::spawn(move || {
-            stdin.write_all(&input_data).ok();
-        });
-
-        // 5. OUTPUT READING (Main Thread)
-        let mut output_data = Vec::new();
-        if let Some(mut stdout) = child.stdout.take() {
-            stdout.read_to_end(&mut output_data).expect("Failed to read 7z stdout");
-        }
+    /// Caps 7-Zip's threads (`-mmt=N` instead of `-mmt=on`).
+    pub fn with_threads(mut self, threads: u32) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
 
-        // 6. CLOSE AND CHECK
-        let status = child.wait().expect("Failed to wait on 7z");
+    /// Extra switches appended after the defaults (7-Zip lets later switches override earlier
+    /// ones). Expected to come from `parse_7z_args`.
+    pub fn with_extra_args(mut self, extra_args: Vec<String>) -> Self {
+        self.extra_args = extra_args;
+        self
+    }
 
-        if !status.success() {
-            panic!("7-Zip Compression Error: Process returned failure code");
-        }
+    fn args(&self) -> Vec<String> {
+        // PIPE MODE: -si (stdin), -so (stdout), -an (no name)
+        let mut args: Vec<String> = ["a", "-txz", "-mx=9"].iter().map(|a| a.to_string()).collect();
+        args.push(match self.threads { Some(n) => format!("-mmt={}", n), None => "-mmt=on".to_string() });
+        args.push(format!("-m0=lzma2:d{}b", self.dict_size));
+        args.extend(self.extra_args.iter().cloned());
+        args.extend(["-si", "-so", "-an", "-y", "-bb0"].iter().map(|a| a.to_string()));
+        args
+    }
 
-        output_data
+    /// Effective command line of the 7-Zip invocation (for display).
+    pub fn command_line(&self) -> String {
+        format!("{} {}", get_7z_cmd(), self.args().join(" "))
     }
 }
 
-pub struct SevenZipDecompressorBackend;
-
-impl NativeDecompressor for SevenZipDecompressorBackend {
-    fn decompress(&self, data: &[u8]) -> Vec<u8> {
-        if data.is_empty() { return Vec::new(); }
+impl NativeCompressor for SevenZipBackend {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        if data.is_empty() { return Ok(Vec::new()); }
 
-        let cmd = get_7z_cmd();
+        let args = self.args();
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        run_7z_pipe(&args, data, 0)
+    }
 
-        let mut child = Command::new(&cmd)
-            .args(&["e", "-txz", "-si", "-so", "-y", "-bb0"])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .expect("Failed to spawn 7-Zip");
+    fn max_compressed_len(&self, input_len: usize) -> Option<usize> {
+        if input_len == 0 { return Some(0); }
+        // 7-Zip picks its own block size (and extra arguments may change it): assume the
+        // smallest that still makes sense, one LZMA2 chunk per block
+        Some(xz_max_len(input_len, 64 * 1024))
+    }
+}
 
-        let input_data = data.to_vec();
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
+pub struct SevenZipDecompressorBackend;
 
-        thread::spawn(move || {
-            stdin.write_all(&input_data).ok();
-        });
+impl NativeDecompressor for SevenZipDecompressorBackend {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        if data.is_empty() { return Ok(Vec::new()); }
+
+        run_7z_pipe(&["e", "-txz", "-si", "-so", "-y", "-bb0"], data, data.len() * 5).map_err(|e| match e {
+            // e.g. "Can't allocate required memory"
+            CastError::BackendFailure { backend, detail } if detail.to_ascii_lowercase().contains("memory") => memory_failure(backend, data, None, detail),
+            e => e,
+        })
+    }
+}
 
-        let estimated_size = data.len() * 5;
-        let mut output_data = Vec::with_capacity(estimated_size);
+fn run_7z_pipe(args: &[&str], data: &[u8], capacity: usize) -> Result<Vec<u8>, CastError> {
+    let failure = |detail: String| CastError::BackendFailure { backend: "7-Zip", detail };
+    let cmd = get_7z_cmd();
 
-        if let Some(mut stdout) = child.stdout.take() {
-            if let Err(e) = stdout.read_to_end(&mut output_data) {
-                eprintln!("Error reading 7z output: {}", e);
-                return Vec::new();
-            }
+    let mut child = Command::new(&cmd)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| failure(format!("Failed to spawn '{}': {}", cmd, e)))?;
+
+    let input_data = data.to_vec();
+    let mut stdin = child.stdin.take().expect("Failed to open stdin");
+
+    thread::spawn(move || {
+        stdin.write_all(&input_data).ok();
+    });
+    // Collected so a failure can say why (7z reports on stderr); passed through otherwise
+    let mut stderr = child.stderr.take().expect("Failed to open stderr");
+    let stderr_reader = thread::spawn(move || {
+        let mut text = Vec::new();
+        stderr.read_to_end(&mut text).ok();
+        String::from_utf8_lossy(&text).into_owned()
+    });
+
+    let mut output_data = Vec::with_capacity(capacity);
+    if let Some(mut stdout) = child.stdout.take() {
+        if let Err(e) = stdout.read_to_end(&mut output_data) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(failure(format!("Error reading 7z output: {}", e)));
         }
+    }
 
-        let status = child.wait().expect("Failed to wait on 7z");
-
-        if status.success() {
-            output_data
-        } else {
-            eprintln!("\n[!] CRITICAL ERROR: 7-Zip backend returned a failure status.");
-            eprintln!("[!] The decompression process cannot continue safely.");
-            std::process::exit(1);
+    let status = child.wait().map_err(|e| failure(format!("Failed to wait on 7z: {}", e)))?;
+    let stderr_text = stderr_reader.join().unwrap_or_default();
+    let stderr_text = stderr_text.trim();
+    if !status.success() {
+        let mut detail = format!("Process returned failure status ({})", status);
+        if !stderr_text.is_empty() {
+            detail.push_str(": ");
+            detail.push_str(&stderr_text.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<_>>().join(" / "));
         }
+        return Err(failure(detail));
     }
+    if !stderr_text.is_empty() { eprintln!("{}", stderr_text); }
+    Ok(output_data)
 }
 
 
@@ -261,12 +587,26 @@ pub enum RuntimeLzmaCompressor {
 }
 
 impl NativeCompressor for RuntimeLzmaCompressor {
-    fn compress(&self, data: &[u8]) -> Vec<u8> {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
         match self {
             RuntimeLzmaCompressor::Native(b) => b.compress(data),
             RuntimeLzmaCompressor::SevenZip(b) => b.compress(data),
         }
     }
+
+    fn max_compressed_len(&self, input_len: usize) -> Option<usize> {
+        match self {
+            RuntimeLzmaCompressor::Native(b) => b.max_compressed_len(input_len),
+            RuntimeLzmaCompressor::SevenZip(b) => b.max_compressed_len(input_len),
+        }
+    }
+
+    fn cancellation(&self) -> Option<&CancellationToken> {
+        match self {
+            RuntimeLzmaCompressor::Native(b) => b.cancellation(),
+            RuntimeLzmaCompressor::SevenZip(b) => b.cancellation(),
+        }
+    }
 }
 
 pub enum RuntimeLzmaDecompressor {
@@ -275,12 +615,19 @@ pub enum RuntimeLzmaDecompressor {
 }
 
 impl NativeDecompressor for RuntimeLzmaDecompressor {
-    fn decompress(&self, data: &[u8]) -> Vec<u8> {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
         match self {
             RuntimeLzmaDecompressor::Native(b) => b.decompress(data),
             RuntimeLzmaDecompressor::SevenZip(b) => b.decompress(data),
         }
     }
+
+    fn decompress_with_progress(&self, data: &[u8], progress: &dyn Fn(u64)) -> Result<Vec<u8>, CastError> {
+        match self {
+            RuntimeLzmaDecompressor::Native(b) => b.decompress_with_progress(data, progress),
+            RuntimeLzmaDecompressor::SevenZip(b) => b.decompress_with_progress(data, progress),
+        }
+    }
 }
 
 // ============================================================================
@@ -288,4 +635,147 @@ impl NativeDecompressor for RuntimeLzmaDecompressor {
 // ============================================================================
 
 pub type CASTLzmaCompressor = CASTCompressor<RuntimeLzmaCompressor>;
-pub type CASTLzmaDecompressor = CASTDecompressor<RuntimeLzmaDecompressor>;
\ No newline at end of file
+pub type CASTLzmaDecompressor = CASTDecompressor<RuntimeLzmaDecompressor>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datagen::{generate_vec, Profile, DEFAULT_SEED};
+
+    const MB: u32 = 1024 * 1024;
+    const DICT_SIZE: u32 = MB;
+
+    // --7z-args values parse_7z_args must accept, with the switches they give (which the 7-Zip
+    // command line must carry between CAST's defaults and its pipe switches), and values it must
+    // reject: file arguments, redirections and switches that break the pipe or the container
+    const SEVEN_ZIP_ACCEPTED_ARGS: [(&str, &[&str]); 3] = [
+        ("", &[]),
+        ("-m0=lzma2:d192m:fb273:lc4 -mmt=4", &["-m0=lzma2:d192m:fb273:lc4", "-mmt=4"]),
+        ("  -mx=5\t-bb3 ", &["-mx=5", "-bb3"]),
+    ];
+    const SEVEN_ZIP_REJECTED_ARGS: [&str; 16] = [
+        "out.xz", "-mx=9 input.log", "@list.txt", "-", "-mx=9>out.xz", "-m0=lzma2|sh", "-so", "-SI",
+        "-o/tmp", "-tzip", "-v100m", "-ai!secret.txt", "-sdel", "-bso1", "-bse1", "-sfx7z.sfx",
+    ];
+
+    // Crafted xz headers: name, first block header fields after the flags byte (sizes and filter
+    // flags), block flags, dictionary size expected from xz_dict_size
+    type XzHeaderCase = (&'static str, &'static [u8], u8, Option<u32>);
+    const XZ_HEADER_CASES: [XzHeaderCase; 6] = [
+        ("lzma2 768MB", &[0x21, 0x01, 35], 0x00, Some(768 * 1024 * 1024)),
+        ("sizes + delta filter, lzma2 8MB", &[0xAC, 0x02, 0xE8, 0x07, 0x03, 0x01, 0x00, 0x21, 0x01, 22], 0xC1, Some(8 * 1024 * 1024)),
+        ("lzma2 4GB - 1", &[0x21, 0x01, 40], 0x00, Some(u32::MAX)),
+        ("lzma2 invalid property", &[0x21, 0x01, 41], 0x00, None),
+        ("no lzma2 filter", &[0x03, 0x01, 0x00], 0x00, None),
+        ("truncated size", &[0xAC], 0x40, None),
+    ];
+
+    // Memory limit of the decoder memory test: far below the 1MB dictionary of its stream
+    const TINY_MEM_LIMIT: u64 = 64 * 1024;
+
+    // Multithreading decision matrix: input size, dictionary size, --multithread, --force-multithread
+    // and threads, then the plan expected from plan_multithread (multithreaded, dictionary, block
+    // size). Blocks are the dictionary, at least MT_MIN_BLOCK (1MB); a stream goes multithreaded
+    // when it spans two of them, and forcing shrinks the dictionary to len / threads (a power of
+    // two, at least 1MB) only when that splits it
+    type MtPlanCase = (u64, u32, bool, bool, u32, (bool, u32, u64));
+    const MT_PLAN_CASES: [MtPlanCase; 14] = [
+        (512 * 1024, 128 * MB, true, false, 4, (false, 128 * MB, 128 * MB as u64)),
+        (512 * 1024, 128 * MB, true, true, 4, (false, 128 * MB, 128 * MB as u64)),
+        (64 * MB as u64, 128 * MB, true, false, 4, (false, 128 * MB, 128 * MB as u64)),
+        (64 * MB as u64, 128 * MB, true, true, 4, (true, 16 * MB, 16 * MB as u64)),
+        (64 * MB as u64, 128 * MB, true, true, 1, (false, 128 * MB, 128 * MB as u64)),
+        (64 * MB as u64, 128 * MB, false, true, 4, (false, 128 * MB, 128 * MB as u64)),
+        (256 * MB as u64, 128 * MB, true, false, 4, (true, 128 * MB, 128 * MB as u64)),
+        (256 * MB as u64, 128 * MB, true, true, 4, (true, 128 * MB, 128 * MB as u64)),
+        (256 * MB as u64, 128 * MB, false, false, 4, (false, 128 * MB, 128 * MB as u64)),
+        (3 * MB as u64, 64 * 1024, true, false, 4, (true, 64 * 1024, MB as u64)),
+        (MB as u64, 64 * 1024, true, false, 4, (false, 64 * 1024, MB as u64)),
+        (5 * MB as u64, 128 * MB, true, true, 8, (true, MB, MB as u64)),
+        (6 * MB as u64, 128 * MB, true, true, 2, (true, 2 * MB, 2 * MB as u64)),
+        (MB as u64 + 1, 128 * MB, true, true, 4, (true, MB, MB as u64)),
+    ];
+    // The same through LzmaBackend on a real stream: input size, threads, and the dictionary its
+    // xz header must report with and without --force-multithread (unforced, the dictionary is
+    // clamped to the next power of two of the input)
+    const MT_STREAM_INPUT: usize = 3 * 1024 * 1024;
+    const MT_STREAM_THREADS: u32 = 4;
+    const MT_STREAM_DICTS: [(bool, u32); 2] = [(false, 4 * MB), (true, MB)];
+
+    /// xz stream header followed by a first block header made of `flags` and `fields`, padded
+    /// and closed like a real one (the CRCs are not checked by `xz_dict_size`).
+    fn xz_header(flags: u8, fields: &[u8]) -> Vec<u8> {
+        let mut out = vec![0xFD, b'7', b'z', b'X', b'Z', 0x00, 0x00, 0x01, 0, 0, 0, 0];
+        let len = (2 + fields.len() + 4).div_ceil(4) * 4;
+        out.push((len / 4 - 1) as u8);
+        out.push(flags);
+        out.extend_from_slice(fields);
+        out.resize(12 + len, 0);
+        out
+    }
+
+    /// The accepted switches reach the 7-Zip command line after the defaults (so they override
+    /// them) and before the pipe switches. 7-Zip itself is not run.
+    #[test]
+    fn seven_zip_args() {
+        let defaults = SevenZipBackend::new(DICT_SIZE).command_line();
+        let (head, tail) = defaults.split_once(" -si").expect("no -si switch in the 7-Zip command line");
+        for (value, expected) in SEVEN_ZIP_ACCEPTED_ARGS {
+            let args = parse_7z_args(value).unwrap_or_else(|e| panic!("'{}' rejected: {}", value, e));
+            assert_eq!(args, expected, "'{}'", value);
+            let injected: String = expected.iter().map(|a| format!(" {}", a)).collect();
+            assert_eq!(SevenZipBackend::new(DICT_SIZE).with_extra_args(args).command_line(), format!("{}{} -si{}", head, injected, tail), "'{}'", value);
+        }
+        for value in SEVEN_ZIP_REJECTED_ARGS {
+            assert!(parse_7z_args(value).is_err(), "'{}' accepted", value);
+        }
+    }
+
+    #[test]
+    fn xz_header_dictionaries() {
+        for (name, fields, flags, expected) in XZ_HEADER_CASES {
+            assert_eq!(xz_dict_size(&xz_header(flags, fields)), expected, "{}", name);
+        }
+    }
+
+    /// A 1MB dictionary stream decoded under `TINY_MEM_LIMIT` must fail with the memory it
+    /// requires, and decode under exactly that requirement.
+    #[test]
+    fn decoder_memory_limit() {
+        let data = generate_vec(Profile::ALL[0], 2 * DICT_SIZE as usize, DEFAULT_SEED);
+        let stream = LzmaBackend::new(false, DICT_SIZE).compress(&data).expect("compression");
+        assert_eq!(xz_dict_size(&stream), Some(DICT_SIZE));
+        let required = xz_decoder_memory(DICT_SIZE);
+        match LzmaDecompressorBackend::new().with_mem_limit(TINY_MEM_LIMIT).decompress(&stream) {
+            Err(e @ CastError::DecoderMemory { .. }) => {
+                assert!(e.to_string().contains("requires ~1 MB"), "{}", e);
+                assert!(matches!(e, CastError::DecoderMemory { required: Some(r), limit: Some(TINY_MEM_LIMIT), .. } if r == required), "wrong sizes in: {:?}", e);
+            },
+            Err(e) => panic!("expected a decoder memory error, got: {}", e),
+            Ok(_) => panic!("decoded despite the memory limit"),
+        }
+        let restored = LzmaDecompressorBackend::new().with_mem_limit(required).decompress(&stream).expect("decoding under the required memory");
+        assert!(restored == data, "restored data differs");
+    }
+
+    #[test]
+    fn multithread_plans() {
+        for (len, dict_size, multithread, force, threads, (mt, dict, block)) in MT_PLAN_CASES {
+            assert_eq!(plan_multithread(len, dict_size, multithread, force, threads), MtPlan { multithread: mt, dict_size: dict, block_size: block },
+                "{} bytes, {} byte dictionary, mt {}, force {}, {} threads", len, dict_size, multithread, force, threads);
+        }
+    }
+
+    #[test]
+    fn multithread_stream_dictionary() {
+        let data = generate_vec(Profile::ALL[0], MT_STREAM_INPUT, DEFAULT_SEED);
+        for (force, dict) in MT_STREAM_DICTS {
+            let backend = LzmaBackend::new(true, 64 * MB).with_preset(0).with_threads(MT_STREAM_THREADS);
+            let backend = if force { backend.with_forced_multithread() } else { backend };
+            let stream = backend.compress(&data).expect("compression");
+            assert_eq!(xz_dict_size(&stream), Some(dict), "force {}", force);
+            let restored = LzmaDecompressorBackend::new().decompress(&stream).expect("decompression");
+            assert!(restored == data, "force {}: restored data differs", force);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,216 @@
+// ============================================================================
+//  CHUNK SIZE MODEL
+// ============================================================================
+//
+//  Every chunk starts from scratch: its templates, column dictionaries and LZMA window are
+//  learned again, so smaller chunks pay that warm-up more often. The cost does not stop at a
+//  fixed amount per chunk (a larger window keeps finding older repeats), and a power law
+//  follows it well: a chunk of L input bytes compresses to about a * L^b bytes, b <= 1. A run
+//  of N bytes in chunks of S bytes then takes N / S * a * S^b, so moving to chunks of S' bytes
+//  scales the archive by (S' / S)^(b - 1).
+//
+//  Chunks of one run mostly share a size, so the slope b comes from probes: parts of a chunk
+//  compressed again on their own (see `record_probe`). It is a least-squares fit of
+//  ln c = ln a_k + b ln L with one intercept per chunk k, so a chunk is only compared with its
+//  own probes and not with chunks whose content compresses differently. The common a is then
+//  fitted to all chunks with that slope.
+
+// Fewer samples than this (probes included) give no fit
+const MIN_SAMPLES: usize = 3;
+// Exponents this close to 1 mean the chunk size costs nothing measurable
+const MAX_LIMITING_EXPONENT: f64 = 0.995;
+// Smallest estimated saving worth a suggestion
+const MIN_SAVING: f64 = 0.02;
+
+/// Power law `size = a * len^b` fitted to the chunks of a run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerFit {
+    pub a: f64,
+    pub b: f64,
+    /// Samples behind the fit (probes included)
+    pub samples: usize,
+}
+
+impl PowerFit {
+    /// Estimated compressed size of one chunk of `len` bytes.
+    pub fn chunk_size_for(&self, len: f64) -> f64 {
+        self.a * len.powf(self.b)
+    }
+
+    /// Factor the compressed size of a run scales by when its chunks go from `from` to `to`
+    /// input bytes.
+    pub fn scale(&self, from: u64, to: u64) -> f64 {
+        (to as f64 / from as f64).powf(self.b - 1.0)
+    }
+}
+
+/// A larger chunk size that the model expects to shrink the archive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkAdvice {
+    pub chunk_size: u64,
+    /// Estimated archive size with `chunk_size`
+    pub estimated_size: u64,
+    /// Estimated saving as a fraction of the archive (0.08 = 8% smaller)
+    pub saving: f64,
+    pub fit: PowerFit,
+}
+
+/// Input and compressed size of the chunks of a run, for `fit` and `advise`.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkSizeModel {
+    /// (input bytes, compressed bytes, chunk) of every chunk and probe
+    samples: Vec<(u64, u64, usize)>,
+    chunks: usize,
+    /// Compressed bytes of the recorded chunks (probes excluded): the part of the archive
+    /// the chunk size scales
+    modeled_output: u64,
+}
+
+impl ChunkSizeModel {
+    /// Records a chunk written to the archive. Chunks whose size says nothing about their
+    /// content (passthrough, duplicates, chunks encoded at another preset) are not recorded.
+    pub fn record(&mut self, input_len: u64, compressed_len: u64) {
+        if input_len == 0 || compressed_len == 0 { return; }
+        self.chunks += 1;
+        self.samples.push((input_len, compressed_len, self.chunks));
+        self.modeled_output += compressed_len;
+    }
+
+    /// Records a probe of the chunk recorded last: part of it compressed again on its own, to
+    /// give the fit a second chunk size. It is not part of the archive.
+    pub fn record_probe(&mut self, input_len: u64, compressed_len: u64) {
+        if input_len == 0 || compressed_len == 0 || self.chunks == 0 { return; }
+        self.samples.push((input_len, compressed_len, self.chunks));
+    }
+
+    /// Fits the power law (see "CHUNK SIZE MODEL"). `None` with too few samples or without a
+    /// probe of a size other than its chunk's (no slope to fit).
+    pub fn fit(&self) -> Option<PowerFit> {
+        if self.samples.len() < MIN_SAMPLES { return None; }
+        let point = |&(l, c, _): &(u64, u64, usize)| ((l as f64).ln(), (c as f64).ln());
+        // Slope within each chunk and its probes
+        let (mut sxx, mut sxy) = (0.0, 0.0);
+        for group in self.samples.chunk_by(|x, y| x.2 == y.2).filter(|g| g.len() > 1) {
+            let points: Vec<(f64, f64)> = group.iter().map(point).collect();
+            let n = points.len() as f64;
+            let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+            let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+            sxx += points.iter().map(|p| (p.0 - mean_x).powi(2)).sum::<f64>();
+            sxy += points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum::<f64>();
+        }
+        // Sizes within a few percent of each other leave the slope to noise
+        if sxx < 1e-3 { return None; }
+        let b = (sxy / sxx).min(1.0);
+        let ln_a = self.samples.iter().map(point).map(|(x, y)| y - b * x).sum::<f64>() / self.samples.len() as f64;
+        Some(PowerFit { a: ln_a.exp(), b, samples: self.samples.len() })
+    }
+
+    /// Suggests `target` instead of `chunk_size` when the fit expects it to save at least
+    /// MIN_SAVING of `archive_len`, the archive of `input_len` bytes as written (of which only
+    /// the recorded chunks scale with the chunk size). No chunk grows past the input itself.
+    pub fn advise(&self, chunk_size: u64, target: u64, input_len: u64, archive_len: u64) -> Option<ChunkAdvice> {
+        let reach = target.min(input_len);
+        if reach <= chunk_size || archive_len == 0 { return None; }
+        let fit = self.fit()?;
+        if fit.b > MAX_LIMITING_EXPONENT { return None; }
+        let modeled = self.modeled_output as f64;
+        let estimated = archive_len as f64 - modeled + modeled * fit.scale(chunk_size, reach);
+        let saving = 1.0 - estimated / archive_len as f64;
+        (saving >= MIN_SAVING).then_some(ChunkAdvice { chunk_size: target, estimated_size: estimated as u64, saving, fit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Synthetic runs whose chunks of CHUNK bytes, and probes of their half and quarter, compress
+    // to exactly a_k * len^b (scaled by up to NOISE of seeded noise when noisy): name, exponent
+    // b, the intercept a_k of each chunk, noisy. The fit must find b, and advise the saving
+    // (TARGET / CHUNK)^(b - 1) gives, to within the tolerance of the case; a slope of 1 means
+    // the chunk size costs nothing and gets no advice
+    type Case = (&'static str, f64, &'static [f64], bool);
+    const CASES: [Case; 4] = [
+        ("one content", 0.85, &[4.0; 8], false),
+        ("mixed contents", 0.8, &[2.0, 6.0, 3.5, 9.0, 2.0, 6.0], false),
+        ("noisy", 0.9, &[4.0; 16], true),
+        ("no warm-up cost", 1.0, &[0.1; 8], false),
+    ];
+    const CHUNK: u64 = 1024 * 1024;
+    const TARGET: u64 = 16 * 1024 * 1024;
+    const NOISE: f64 = 0.01;
+    const TOLERANCE: f64 = 1e-3;
+    const NOISY_TOLERANCE: f64 = 0.02;
+    const SEED: u64 = 0x5EED;
+
+    /// Model of a run made from `b` and `intercepts` as the CASES comment describes, and the
+    /// size of its archive.
+    fn model_of(b: f64, intercepts: &[f64], noisy: bool, probes: bool) -> (ChunkSizeModel, u64) {
+        let chunk = CHUNK as f64;
+        let mut state = SEED;
+        let mut factors = std::iter::repeat_with(|| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            if noisy { 1.0 + NOISE * ((state >> 32) as u8 as f64 / 127.5 - 1.0) } else { 1.0 }
+        });
+        let mut model = ChunkSizeModel::default();
+        let mut archive_len = 0;
+        for a in intercepts {
+            let compressed = (a * chunk.powf(b) * factors.next().unwrap()).round() as u64;
+            model.record(CHUNK, compressed);
+            archive_len += compressed;
+            for len in [chunk / 2.0, chunk / 4.0].into_iter().filter(|_| probes) {
+                model.record_probe(len as u64, (a * len.powf(b) * factors.next().unwrap()).round() as u64);
+            }
+        }
+        (model, archive_len)
+    }
+
+    #[test]
+    fn fits_and_advises_power_laws() {
+        for (name, b, intercepts, noisy) in CASES {
+            let tolerance = if noisy { NOISY_TOLERANCE } else { TOLERANCE };
+            let (model, archive_len) = model_of(b, intercepts, noisy, true);
+            let input_len = CHUNK * intercepts.len() as u64 * 64;
+            let fit = model.fit().unwrap_or_else(|| panic!("{}: no fit", name));
+            assert!((fit.b - b).abs() <= tolerance, "{}: fitted b = {:.4}, made with {}", name, fit.b, b);
+            assert_eq!(fit.samples, intercepts.len() * 3, "{}", name);
+            if intercepts.iter().all(|&a| a == intercepts[0]) && !noisy {
+                assert!((fit.a / intercepts[0] - 1.0).abs() <= tolerance, "{}: fitted a = {:.4}, made with {}", name, fit.a, intercepts[0]);
+            }
+            let saving = 1.0 - (TARGET as f64 / CHUNK as f64).powf(b - 1.0);
+            match model.advise(CHUNK, TARGET, input_len, archive_len) {
+                None => assert!(b >= 1.0, "{}: no advice, expected a {:.1}% saving", name, saving * 100.0),
+                Some(advice) => {
+                    assert!(b < 1.0, "{}: advised {:?} with no warm-up cost", name, advice);
+                    assert_eq!(advice.chunk_size, TARGET, "{}", name);
+                    assert!((advice.saving - saving).abs() <= tolerance, "{}: a {:.2}% saving, expected {:.2}%", name, advice.saving * 100.0, saving * 100.0);
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn no_fit_without_probes() {
+        let (_, b, intercepts, _) = CASES[0];
+        assert_eq!(model_of(b, intercepts, false, false).0.fit(), None);
+    }
+
+    #[test]
+    fn no_advice_for_the_current_chunk_size() {
+        let (_, b, intercepts, _) = CASES[0];
+        let (model, archive_len) = model_of(b, intercepts, false, true);
+        assert_eq!(model.advise(CHUNK, CHUNK, CHUNK * intercepts.len() as u64 * 64, archive_len), None);
+    }
+
+    /// Chunks never grow past the input: the saving is the one of a single chunk.
+    #[test]
+    fn target_past_the_input() {
+        let (_, b, intercepts, _) = CASES[0];
+        let (model, archive_len) = model_of(b, intercepts, false, true);
+        let saving = 1.0 - (intercepts.len() as f64).powf(b - 1.0);
+        let advice = model.advise(CHUNK, TARGET, CHUNK * intercepts.len() as u64, archive_len).expect("no advice");
+        assert!((advice.saving - saving).abs() <= TOLERANCE, "a {:.2}% saving, expected {:.2}%", advice.saving * 100.0, saving * 100.0);
+    }
+}
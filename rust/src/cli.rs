@@ -0,0 +1,283 @@
+// ============================================================================
+//  COMMAND LINE OPTIONS (shared by the binaries)
+// ============================================================================
+//
+//  Every option of every binary is declared once in OPTIONS: its names, value, default, help
+//  text and the binaries that accept it. The "Options" part of each binary's --help is
+//  rendered from it, and so is the split between options that take a value and flags, which
+//  the binaries use to tell options from positional arguments. A binary rejects long options
+//  (`--name`) that are not declared for it, so a new option cannot be parsed without being
+//  documented: the first run that uses it fails until it has an entry here.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binary {
+    Cast,
+    RunBenchmarks,
+    BenchRegression,
+    RoundtripCheck,
+}
+
+/// One option: `names` are its spellings (`-y`, `--yes`), `value` the placeholder of its
+/// argument (`None` for flags). `help` lines are separated by '\n'.
+pub struct CliOption {
+    pub names: &'static [&'static str],
+    pub value: Option<&'static str>,
+    pub default: Option<&'static str>,
+    pub help: &'static str,
+    pub binaries: &'static [Binary],
+}
+
+const CAST: &[Binary] = &[Binary::Cast];
+const CAST_AND_BENCHMARKS: &[Binary] = &[Binary::Cast, Binary::RunBenchmarks];
+const BENCHMARKS: &[Binary] = &[Binary::RunBenchmarks];
+const REGRESSION: &[Binary] = &[Binary::BenchRegression];
+const ALL: &[Binary] = &[Binary::Cast, Binary::RunBenchmarks, Binary::BenchRegression, Binary::RoundtripCheck];
+
+// Help column: it starts after the longest names and value of the binary's options, up to
+// MAX_NAME_WIDTH; longer ones go on a line of their own, with the help below them
+const MAX_NAME_WIDTH: usize = 24;
+const LINE_WIDTH: usize = 100;
+
+pub const OPTIONS: &[CliOption] = &[
+    CliOption { names: &["--list"], value: Some("<file.txt>"), default: Some("generated 8MB datasets, one per 'cast generate' profile"),
+        help: "File containing a list of paths to test (one per line)", binaries: BENCHMARKS },
+    CliOption { names: &["--compare-with"], value: Some("<algos>"), default: None,
+        help: "Comma-separated list of competitors (e.g. 'lzma2,zstd')\nor 'all' for [lzma2, brotli, zstd] (required)", binaries: BENCHMARKS },
+    CliOption { names: &["--mode"], value: Some("<TYPE>"), default: Some("7zip for compression, Native for decompression"),
+        help: "Backend selection: 'native' or '7zip'", binaries: CAST_AND_BENCHMARKS },
+    CliOption { names: &["--multithread"], value: None, default: None,
+        help: "Enable parallel compression for higher speed", binaries: CAST_AND_BENCHMARKS },
+    CliOption { names: &["--force-multithread"], value: None, default: None,
+        help: "Like --multithread, shrinking the dictionary when the input\nwould otherwise fit in one block (faster, lower ratio)", binaries: CAST },
+    CliOption { names: &["--chunk-size"], value: Some("<S>"), default: Some("Solid Mode"),
+        help: "Split input in chunks (Compression RAM Saver) (e.g., 512MB)", binaries: CAST_AND_BENCHMARKS },
+    CliOption { names: &["--restore-threads"], value: Some("<LIST>"), default: None,
+        help: "Also time a parallel restore of the chunks with each thread count\n(e.g. 1,2,4), written in place and checked by SHA-256 (needs --chunk-size)", binaries: BENCHMARKS },
+    CliOption { names: &["--dict-size"], value: Some("<S>"), default: Some("128MB"),
+        help: "Set LZMA Dictionary size", binaries: CAST_AND_BENCHMARKS },
+    CliOption { names: &["--memory-limit"], value: Some("<S>"), default: None,
+        help: "Memory ceiling for the whole run, or 'auto' (available RAM): derives the\nchunk size, dictionary and threads; also bounds decoding", binaries: CAST },
+    CliOption { names: &["--mem-limit-decompress"], value: Some("<S>"), default: None,
+        help: "Memory limit of the native LZMA decoder, or 'auto' (available RAM): archives\nneeding more fail with the size they require instead of exhausting memory", binaries: CAST },
+    CliOption { names: &["--7z-args"], value: Some("\"<A>\""), default: None,
+        help: "Extra switches for the 7-Zip compressor, applied after the defaults\n(e.g. \"-m0=lzma2:d192m:fb273 -mmt=4\"; file/output switches are rejected)", binaries: CAST },
+    CliOption { names: &["--verbose"], value: None, default: None,
+        help: "Print the effective 7-Zip command line; when decompressing, the layout,\nbackend, dictionary and parse mode of every chunk first", binaries: CAST },
+    CliOption { names: &["--no-fallback"], value: None, default: None,
+        help: "Abort when 7-Zip fails on a chunk instead of re-encoding that chunk\nwith the native backend (same dictionary, same archive format)", binaries: CAST },
+    CliOption { names: &["--label"], value: Some("<K=V>"), default: None,
+        help: "Store a key=value label in the archive header (repeatable,\nup to 64KB in total; the value may contain '=')", binaries: CAST },
+    CliOption { names: &["--follow"], value: None, default: None,
+        help: "(Compression) Keep reading an input that is still being appended to\n(needs --chunk-size); finishes when idle or on Ctrl+C", binaries: CAST },
+    CliOption { names: &["--idle-timeout"], value: Some("<T>"), default: Some("30s"),
+        help: "With --follow, stop after T without new data", binaries: CAST },
+    CliOption { names: &["--max-time"], value: Some("<T>"), default: None,
+        help: "(Compression) Finish within T (e.g. 30m): when the full settings would not\nmake it, the remaining chunks are encoded fast (xz preset 1)", binaries: CAST },
+    CliOption { names: &["--allow-truncation"], value: None, default: None,
+        help: "(Compression) Finish the archive when the input shrinks while it is read,\nflagged as a changed input, instead of failing", binaries: CAST },
+    CliOption { names: &["--header"], value: Some("<H>"), default: Some("auto, only when clearly a header"),
+        help: "(Compression) Record the header row of delimited input as column names:\nyes, no or auto", binaries: CAST },
+    CliOption { names: &["--solid-limit"], value: Some("<S>"), default: Some("derived from available RAM, 0 = never ask"),
+        help: "Ask for confirmation before compressing inputs larger than S without\n--chunk-size", binaries: CAST },
+    CliOption { names: &["-y", "--yes"], value: None, default: None,
+        help: "Skip the confirmation for large solid-mode inputs", binaries: CAST },
+    CliOption { names: &["--io-limit"], value: Some("<R>"), default: None,
+        help: "Cap disk reads and writes together at R per second (e.g. 50MB/s) for\nbackground jobs: compression, decompression and verification", binaries: CAST },
+    CliOption { names: &["--batch"], value: None, default: None,
+        help: "(Compression) Compress each file of a list file or directory (see Modes)", binaries: CAST },
+    CliOption { names: &["--jobs"], value: Some("<N>"), default: Some("1"),
+        help: "(Batch) Compress up to N files at the same time, each with its own\ncompressor; the CPUs and --memory-limit are shared among them", binaries: CAST },
+    CliOption { names: &["--pack"], value: None, default: None,
+        help: "(Compression) Compress the files of a list file or directory as one\nstream (see Modes)", binaries: CAST },
+    CliOption { names: &["--convert"], value: None, default: None,
+        help: "Convert .gz/.xz (or plain) logs into CAST archives under --into (see Modes)", binaries: CAST },
+    CliOption { names: &["--into"], value: Some("<dir>"), default: None,
+        help: "(Convert) Output directory: archives keep the relative paths of their inputs", binaries: CAST },
+    CliOption { names: &["--delete-source"], value: None, default: None,
+        help: "(Convert) Delete each input once its archive has been verified", binaries: CAST },
+    CliOption { names: &["--ignore-errors"], value: None, default: None,
+        help: "(Batch, Convert) Exit with status 0 even if some inputs were skipped", binaries: CAST },
+    CliOption { names: &["--on-cancel"], value: Some("<P>"), default: None,
+        help: "On Ctrl+C: 'delete' the partial output (Default) or 'keep' it up to the\nlast complete chunk (exit status 130 either way)", binaries: CAST },
+    CliOption { names: &["--checksum"], value: Some("<T>"), default: Some("crc32"),
+        help: "Per-chunk checksum: 'crc32' or 'none'\n('none' is faster but corruption can no longer be detected)", binaries: CAST },
+    CliOption { names: &["--column-codecs"], value: None, default: None,
+        help: "(Compression) Store each column with LZMA, zstd or as is, depending on\nits entropy (faster on UUID/hash columns)", binaries: CAST_AND_BENCHMARKS },
+    CliOption { names: &["--front-coding"], value: None, default: None,
+        help: "(Compression) Store each template as the prefix it shares with the previous\none plus the rest (smaller registries for many similar templates)", binaries: CAST },
+    CliOption { names: &["--no-column-dict"], value: None, default: None,
+        help: "(Compression) Store every column as is (by default columns with few distinct\nvalues become a dictionary plus indices when that compresses smaller)", binaries: CAST },
+    CliOption { names: &["--dedup-chunks"], value: None, default: None,
+        help: "(Compression) Store a chunk identical to an earlier one as a reference to it\n(repeated regions, e.g. concatenated rotations)", binaries: CAST },
+    CliOption { names: &["--order-checksum"], value: None, default: None,
+        help: "(Compression) Record a checksum of the row order across chunks, checked by\nfull restores and -v (catches reordered or missing chunks)", binaries: CAST },
+    CliOption { names: &["--max-var-len"], value: Some("<S>"), default: Some("1MB"),
+        help: "(Compression) Keep quoted strings and tokens longer than S in the line\ntemplate instead of a column", binaries: CAST },
+    CliOption { names: &["--manifest"], value: Some("<F>"), default: None,
+        help: "(Compression) Also write a JSON manifest F: header hash, chunk entries with\ncompressed body CRCs, totals, settings and timing", binaries: CAST },
+    CliOption { names: &["--index"], value: None, default: None,
+        help: "(Compression) Also write a sidecar index (<out>.idx) for seeking,\nparallel verification and restore, and a faster 'info'", binaries: CAST },
+    CliOption { names: &["--limit"], value: Some("<N|S>"), default: None,
+        help: "(Decompression) Stop after N rows (e.g., 1000) or S bytes (e.g., 64KB)", binaries: CAST },
+    CliOption { names: &["--rows"], value: Some("<A-B>"), default: None,
+        help: "(Decompression) Restore only rows A to B (from 1; 'A-' = to the end)", binaries: CAST },
+    CliOption { names: &["--range"], value: Some("<A-B>"), default: None,
+        help: "(Decompression) Restore only bytes A to B (from 0; 'A-' = to the end)\n(seeks with a valid sidecar index, otherwise reads up to B)", binaries: CAST },
+    CliOption { names: &["--file"], value: Some("<F>"), default: None,
+        help: "(Decompression) Restore only the packed file F of a --pack archive (path or file name)", binaries: CAST },
+    CliOption { names: &["--shards"], value: Some("<N|auto>"), default: None,
+        help: "(Decompression) Restore into a directory of N parts (auto: one per chunk)\npart-00000.log, ... plus manifest.json (parallel with a sidecar index)", binaries: CAST },
+    CliOption { names: &["--skip-existing", "--skip-existing=strict"], value: None, default: None,
+        help: "(Decompression) Skip when the output already matches the archive (size and\nSHA-256); strict: fail when a same-size output cannot be confirmed", binaries: CAST },
+    CliOption { names: &["--chmod"], value: Some("<octal>"), default: None,
+        help: "(Decompression, Unix) Permission bits of restored files (e.g. 640),\nset once each file is complete", binaries: CAST },
+    CliOption { names: &["--chown"], value: Some("<U:G>"), default: None,
+        help: "(Decompression, Unix) Owner of restored files: user, user:group or :group\n(names or ids; needs root or CAP_CHOWN)", binaries: CAST },
+    CliOption { names: &["--preserve-permissions"], value: None, default: None,
+        help: "(Decompression, Unix) Give restored files the permission bits of the\ncompressed file, when the archive records them (--chmod wins)", binaries: CAST },
+    CliOption { names: &["--readonly"], value: None, default: None,
+        help: "(Decompression) Make restored files read-only", binaries: CAST },
+    CliOption { names: &["--hidden"], value: None, default: None,
+        help: "(Decompression, Windows) Set the hidden attribute on restored files", binaries: CAST },
+    CliOption { names: &["--mmap-out"], value: None, default: None,
+        help: "(Decompression) Write through a memory-mapped output file\n(requires the 'mmap' build feature)", binaries: CAST },
+    CliOption { names: &["--sparse"], value: None, default: None,
+        help: "(Decompression) Leave holes for zero blocks instead of writing them", binaries: CAST },
+    CliOption { names: &["--output-encoding"], value: Some("<E>"), default: None,
+        help: "(Decompression) Compress the restored data: gzip, zstd or none", binaries: CAST },
+    CliOption { names: &["-v", "--verify"], value: None, default: None,
+        help: "(During compression) Run an immediate integrity check", binaries: CAST },
+    CliOption { names: &["--strict-verify"], value: None, default: None,
+        help: "Verify with both backends (native and 7-Zip) and require the same output", binaries: CAST },
+    CliOption { names: &["--locked-reads"], value: None, default: None,
+        help: "Read the archive in parallel -v and --shards through one locked\nseek + read handle instead of positioned reads (for comparison)", binaries: CAST },
+    CliOption { names: &["--verify-sample"], value: Some("<P%|N>"), default: None,
+        help: "(Verification) Check only the first and last chunks plus a fixed\npseudo-random sample (P percent or N chunks in all), seeking to them", binaries: CAST },
+    CliOption { names: &["--hmac-key-file"], value: Some("<F>"), default: None,
+        help: "Authenticate with the key in F (its raw bytes): compression stores\nHMAC-SHA256s of every chunk, -v checks them to detect tampering", binaries: CAST },
+    CliOption { names: &["--debug-verify"], value: None, default: None,
+        help: "On the first mismatch, print the first differing line and the\ntemplate/column that produced it (uses the input during -c -v)", binaries: CAST },
+    CliOption { names: &["--verify-against"], value: Some("<F>"), default: None,
+        help: "Compare the restored data with the original file F", binaries: CAST },
+    CliOption { names: &["--manifest-check"], value: Some("<F>"), default: None,
+        help: "(Verification) First compare the archive with the manifest F written by\n--manifest (fails on any rewrite, even one with valid checksums)", binaries: CAST },
+    CliOption { names: &["--dump-format"], value: None, default: None,
+        help: "Print the structures of a CAST file (see Modes)", binaries: CAST },
+    CliOption { names: &["--max-chunks"], value: Some("<N>"), default: Some("16"),
+        help: "(Format dump) Decode the block headers of the first N chunks only", binaries: CAST },
+    CliOption { names: &["--detailed"], value: None, default: None,
+        help: "(Info) Also list the layout, backend, dictionary and parse mode of every\nchunk, with a histogram of the mix (reads the whole archive)", binaries: CAST },
+    CliOption { names: &["--list-templates"], value: None, default: None,
+        help: "List the templates of a CAST file with their row counts (see Modes)", binaries: CAST },
+    CliOption { names: &["--sort"], value: Some("<K>"), default: Some("rows"),
+        help: "(Template list) Order: rows, size (share of the bytes) or id (first appearance)", binaries: CAST },
+    CliOption { names: &["--min-rows"], value: Some("<N>"), default: None,
+        help: "(Template list) Only templates used by at least N rows", binaries: CAST },
+    CliOption { names: &["--top"], value: Some("<N>"), default: None,
+        help: "(Template list) Only the first N templates after sorting and filtering", binaries: CAST },
+    CliOption { names: &["--audit"], value: None, default: None,
+        help: "Check the templates of a CAST file against --allowed-templates (see Modes)", binaries: CAST },
+    CliOption { names: &["--allowed-templates"], value: Some("<T>"), default: None,
+        help: "(Audit) Template file listing the allowed templates", binaries: CAST },
+    CliOption { names: &["-n"], value: None, default: None,
+        help: "(grep) Prefix each line with its line number", binaries: CAST },
+    CliOption { names: &["-i"], value: None, default: None,
+        help: "(grep) Ignore case", binaries: CAST },
+    CliOption { names: &["-A", "-B", "-C"], value: Some("<N>"), default: None,
+        help: "(grep) Print N lines after, before or around each match", binaries: CAST },
+    CliOption { names: &["--seed"], value: Some("<N>"), default: Some("42"),
+        help: "(generate) Seed of the synthetic dataset", binaries: CAST },
+    CliOption { names: &["--files"], value: Some("<N>"), default: None,
+        help: "(generate) Split the dataset over N files in the directory <out>", binaries: CAST },
+    CliOption { names: &["--json"], value: None, default: None,
+        help: "Print progress and results as JSON lines on stdout (no banner)", binaries: CAST },
+    CliOption { names: &["--tolerance"], value: Some("<PCT>"), default: Some("0.5%"),
+        help: "Allowed size growth per fixture before failing", binaries: REGRESSION },
+    CliOption { names: &["--baselines"], value: Some("<FILE>"), default: Some("benches/baselines.toml in the crate"),
+        help: "Baselines file", binaries: REGRESSION },
+    CliOption { names: &["--update"], value: None, default: None,
+        help: "Record the current sizes as the new baselines", binaries: REGRESSION },
+    CliOption { names: &["-h", "--help"], value: None, default: None,
+        help: "Show this help message", binaries: ALL },
+];
+
+/// The options of `binary`, in help order.
+pub fn options(binary: Binary) -> impl Iterator<Item = &'static CliOption> {
+    OPTIONS.iter().filter(move |o| o.binaries.contains(&binary))
+}
+
+fn find(binary: Binary, arg: &str) -> Option<&'static CliOption> {
+    options(binary).find(|o| o.names.contains(&arg))
+}
+
+/// Whether `arg` is an option of `binary`.
+pub fn is_option(binary: Binary, arg: &str) -> bool {
+    find(binary, arg).is_some()
+}
+
+/// Whether `arg` is an option of `binary` followed by a value.
+pub fn takes_value(binary: Binary, arg: &str) -> bool {
+    find(binary, arg).is_some_and(|o| o.value.is_some())
+}
+
+/// The first long option (`--name`) in `args` that `binary` does not declare. Option values
+/// are skipped, so `--7z-args "--x"` is not reported.
+pub fn unknown_option(binary: Binary, args: &[String]) -> Option<&str> {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match find(binary, arg) {
+            Some(o) if o.value.is_some() => i += 1,
+            None if arg.starts_with("--") && arg.len() > 2 => return Some(arg),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The "Options" lines of `binary`'s help: names and value, then the help text (and default)
+/// in a column as wide as the table needs (see MAX_NAME_WIDTH).
+pub fn render_options(binary: Binary) -> String {
+    let names: Vec<(&CliOption, String)> = options(binary).map(|option| (option, display_name(option))).collect();
+    let width = names.iter().map(|(_, name)| name.len()).filter(|&len| len <= MAX_NAME_WIDTH).max().unwrap_or(0);
+    let indent = 2 + width + 1;
+    let mut out = String::new();
+    for (option, name) in &names {
+        let mut help = option.help.to_string();
+        if let Some(default) = option.default {
+            // On the last line when it still fits, otherwise on a line of its own
+            let last_width = indent + help.lines().last().map_or(0, str::len);
+            let note = format!("(Default: {})", default);
+            help.push(if last_width + note.len() < LINE_WIDTH { ' ' } else { '\n' });
+            help.push_str(&note);
+        }
+        out.push_str("  ");
+        out.push_str(name);
+        if name.len() > width {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent));
+        } else {
+            out.push_str(&" ".repeat(indent - 2 - name.len()));
+        }
+        for (i, line) in help.lines().enumerate() {
+            if i > 0 {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+            }
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// `-y, --yes`, or `--chunk-size <S>` for an option that takes a value.
+fn display_name(option: &CliOption) -> String {
+    let mut name = option.names.join(", ");
+    if let Some(value) = option.value {
+        name.push(' ');
+        name.push_str(value);
+    }
+    name
+}
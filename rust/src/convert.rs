@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+// ============================================================================
+//  CONVERSION (--convert)
+// ============================================================================
+//
+//  Logs kept as .gz or .xz are decoded on their way into the compressor, so the decoded data
+//  never exists as a file. The format is told by the magic bytes, not by the extension, and
+//  any other input is compressed as it is. Each archive goes to the same relative path under
+//  the output directory, with the .gz/.xz extension swapped for .cast (other names get .cast
+//  appended, as in --batch).
+//
+//  Relative paths start at the directory given on the command line, or, for files given one
+//  by one (e.g. expanded from a wildcard), at the deepest directory that holds all of them.
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const XZ_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0x00];
+// Stream footer: CRC32, backward size, stream flags, "YZ"
+const XZ_FOOTER_LEN: usize = 12;
+// Largest index read for the size hint (a record is at most 18 bytes)
+const XZ_MAX_INDEX_LEN: u64 = 16 * 1024 * 1024;
+const ARCHIVE_EXTENSION: &str = "cast";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEncoding { Plain, Gzip, Xz }
+
+impl InputEncoding {
+    /// Format of a file starting with `head`.
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(&XZ_MAGIC) {
+            InputEncoding::Xz
+        } else if head.starts_with(&GZIP_MAGIC) {
+            InputEncoding::Gzip
+        } else {
+            InputEncoding::Plain
+        }
+    }
+
+    /// Format of the file at `path`, from its first bytes.
+    pub fn sniff(path: &Path) -> io::Result<Self> {
+        let mut head = Vec::with_capacity(XZ_MAGIC.len());
+        File::open(path)?.take(XZ_MAGIC.len() as u64).read_to_end(&mut head)?;
+        Ok(Self::detect(&head))
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            InputEncoding::Plain => "plain",
+            InputEncoding::Gzip => "gzip",
+            InputEncoding::Xz => "xz",
+        }
+    }
+
+    /// File extension swapped for .cast (none for plain inputs).
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            InputEncoding::Plain => None,
+            InputEncoding::Gzip => Some("gz"),
+            InputEncoding::Xz => Some("xz"),
+        }
+    }
+}
+
+/// Reader of the decoded data of `inner`. Concatenated .gz members and .xz streams are read
+/// one after the other; a corrupted or truncated input fails the read (both formats end with
+/// a checksum of the decoded data).
+pub fn decoder<'a, R: Read + 'a>(inner: R, encoding: InputEncoding) -> Box<dyn Read + 'a> {
+    match encoding {
+        InputEncoding::Plain => Box::new(inner),
+        InputEncoding::Gzip => Box::new(MultiGzDecoder::new(inner)),
+        InputEncoding::Xz => Box::new(XzDecoder::new_multi_decoder(inner)),
+    }
+}
+
+/// Decoded size of the file at `path`, as recorded at its end: the size field of the last
+/// .gz member (modulo 4GB), or the index of the last .xz stream. Only a hint: earlier
+/// members or streams are not counted, and `None` when nothing usable is recorded.
+pub fn decoded_len_hint(path: &Path, encoding: InputEncoding) -> io::Result<Option<u64>> {
+    let mut f = File::open(path)?;
+    let len = f.metadata()?.len();
+    match encoding {
+        InputEncoding::Plain => Ok(Some(len)),
+        InputEncoding::Gzip => {
+            if len < 18 { return Ok(None); }
+            let mut isize = [0u8; 4];
+            f.seek(SeekFrom::End(-4))?;
+            f.read_exact(&mut isize)?;
+            Ok(Some(u32::from_le_bytes(isize) as u64))
+        },
+        InputEncoding::Xz => xz_len_hint(&mut f, len),
+    }
+}
+
+fn xz_len_hint(f: &mut File, len: u64) -> io::Result<Option<u64>> {
+    // Stream padding (zero bytes, a multiple of four) may follow the last stream
+    let mut end = len;
+    let mut word = [0u8; 4];
+    while end >= 4 {
+        f.seek(SeekFrom::Start(end - 4))?;
+        f.read_exact(&mut word)?;
+        if word != [0; 4] { break; }
+        end -= 4;
+    }
+    if end < (XZ_MAGIC.len() + XZ_FOOTER_LEN) as u64 { return Ok(None); }
+    let mut footer = [0u8; XZ_FOOTER_LEN];
+    f.seek(SeekFrom::Start(end - XZ_FOOTER_LEN as u64))?;
+    f.read_exact(&mut footer)?;
+    if &footer[10..] != b"YZ" { return Ok(None); }
+    let index_len = (u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]) as u64 + 1) * 4;
+    if index_len > XZ_MAX_INDEX_LEN || index_len + XZ_FOOTER_LEN as u64 > end { return Ok(None); }
+    let mut index = vec![0u8; index_len as usize];
+    f.seek(SeekFrom::Start(end - XZ_FOOTER_LEN as u64 - index_len))?;
+    f.read_exact(&mut index)?;
+
+    // Index indicator, record count, then (unpadded size, uncompressed size) per block
+    if index[0] != 0 { return Ok(None); }
+    let mut pos = 1;
+    let Some(records) = read_varint(&index, &mut pos) else { return Ok(None) };
+    let mut total: u64 = 0;
+    for _ in 0..records {
+        let (Some(_), Some(size)) = (read_varint(&index, &mut pos), read_varint(&index, &mut pos)) else { return Ok(None) };
+        total = total.saturating_add(size);
+    }
+    Ok(Some(total))
+}
+
+// Multibyte integer of the xz format: 7 bits per byte, low bits first, at most 9 bytes
+fn read_varint(buf: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    for i in 0..9 {
+        let byte = *buf.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 { return Some(value); }
+    }
+    None
+}
+
+/// One input of a conversion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConvertInput {
+    pub path: PathBuf,
+    /// Archive path relative to the output directory
+    pub output: PathBuf,
+    pub encoding: InputEncoding,
+}
+
+/// Inputs of a conversion: the files given, the files matching a wildcard (`*`, `?`) in the
+/// last part of a path the shell left unexpanded, and every regular file under a directory
+/// (sorted, subdirectories included). Files under `out_dir` are left out, so converting a
+/// tree into a directory inside it does not pick up earlier archives. Also returns the
+/// arguments that name nothing.
+pub fn collect_convert_inputs(args: &[String], out_dir: &Path) -> io::Result<(Vec<ConvertInput>, Vec<String>)> {
+    let out_dir = normalize(out_dir)?;
+    let mut inputs = Vec::new();
+    let mut files = Vec::new();
+    let mut missing = Vec::new();
+    for arg in args {
+        let path = Path::new(arg);
+        if path.is_dir() {
+            let root = normalize(path)?;
+            let mut found = Vec::new();
+            walk_files(path, &mut found)?;
+            found.sort();
+            for file in found {
+                let absolute = normalize(&file)?;
+                if absolute.starts_with(&out_dir) { continue; }
+                let relative = absolute.strip_prefix(&root).map(Path::to_path_buf).unwrap_or_else(|_| file_name(&absolute));
+                inputs.push(convert_input(file, &relative));
+            }
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        } else {
+            match expand_wildcard(path)? {
+                matched if matched.is_empty() => missing.push(arg.clone()),
+                matched => files.extend(matched),
+            }
+        }
+    }
+
+    // Files given one by one keep their paths below the directory they share
+    let absolute: Vec<PathBuf> = files.iter().map(|f| normalize(f)).collect::<io::Result<_>>()?;
+    let root = common_parent(&absolute);
+    for (file, absolute) in files.into_iter().zip(absolute) {
+        if absolute.starts_with(&out_dir) { continue; }
+        let relative = absolute.strip_prefix(&root).map(Path::to_path_buf).unwrap_or_else(|_| file_name(&absolute));
+        inputs.push(convert_input(file, &relative));
+    }
+    Ok((inputs, missing))
+}
+
+/// Archive name of an input at `relative`: the .gz/.xz extension of an encoded input swapped
+/// for .cast, .cast appended to anything else.
+pub fn converted_name(relative: &Path, encoding: InputEncoding) -> PathBuf {
+    let name = relative.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let stem = encoding.extension()
+        .and_then(|ext| {
+            let cut = name.len().checked_sub(ext.len() + 1)?;
+            let suffix = name.get(cut..)?;
+            (cut > 0 && suffix.starts_with('.') && suffix[1..].eq_ignore_ascii_case(ext)).then(|| &name[..cut])
+        })
+        .unwrap_or(&name);
+    relative.with_file_name(format!("{}.{}", stem, ARCHIVE_EXTENSION))
+}
+
+fn convert_input(path: PathBuf, relative: &Path) -> ConvertInput {
+    // An unreadable input is named as plain and fails when it is compressed
+    let encoding = InputEncoding::sniff(&path).unwrap_or(InputEncoding::Plain);
+    ConvertInput { output: converted_name(relative, encoding), path, encoding }
+}
+
+fn walk_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+// Files of the directory of `pattern` whose names match its last part
+fn expand_wildcard(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(name) = pattern.file_name().and_then(|n| n.to_str()) else { return Ok(Vec::new()) };
+    if !name.contains(['*', '?']) { return Ok(Vec::new()); }
+    let dir = match pattern.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() { return Ok(Vec::new()); }
+    let mut matched: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter(|e| e.file_name().to_str().is_some_and(|n| wildcard_match(name.as_bytes(), n.as_bytes())))
+        .map(|e| e.path())
+        .collect();
+    matched.sort();
+    Ok(matched)
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| wildcard_match(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && wildcard_match(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && wildcard_match(rest, &name[1..]),
+    }
+}
+
+// Absolute path with '.' and '..' resolved by name (symlinks are not followed)
+fn normalize(path: &Path) -> io::Result<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => { normal.pop(); },
+            c => normal.push(c),
+        }
+    }
+    Ok(normal)
+}
+
+// Deepest directory holding every file of `files`
+fn common_parent(files: &[PathBuf]) -> PathBuf {
+    let mut parents = files.iter().filter_map(|f| f.parent());
+    let Some(first) = parents.next() else { return PathBuf::new() };
+    parents.fold(first.to_path_buf(), |common, parent| {
+        common.components().zip(parent.components()).take_while(|(a, b)| a == b).map(|(a, _)| a).collect()
+    })
+}
+
+fn file_name(path: &Path) -> PathBuf {
+    path.file_name().map(PathBuf::from).unwrap_or_default()
+}
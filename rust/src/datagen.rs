@@ -0,0 +1,304 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+// ============================================================================
+//  SYNTHETIC DATASETS (cast generate)
+// ============================================================================
+//
+//  Deterministic generators for benchmarks and ratio checks: the output depends
+//  only on (profile, size, seed), so the same call produces the same bytes on
+//  every machine and every run. Output is whole lines, stopping at the first
+//  line that reaches `size` bytes.
+
+pub const DEFAULT_SEED: u64 = 42;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    /// Combined log format, zipfian URL popularity
+    WebAccess,
+    /// Mixed numeric and string columns with a header row
+    Csv,
+    /// One JSON object per line with nested tags
+    Ndjson,
+    /// BSD syslog lines with bursts of repeated messages
+    Syslog,
+    /// Near-random printable text (worst case for the transform)
+    Entropy,
+    /// CSV events keyed by random UUIDs and hashes (incompressible columns next to structured ones)
+    Uuid,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 6] = [Profile::WebAccess, Profile::Csv, Profile::Ndjson, Profile::Syslog, Profile::Entropy, Profile::Uuid];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|p| p.name() == s.to_lowercase())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::WebAccess => "web-access",
+            Profile::Csv => "csv",
+            Profile::Ndjson => "ndjson",
+            Profile::Syslog => "syslog",
+            Profile::Entropy => "entropy",
+            Profile::Uuid => "uuid",
+        }
+    }
+
+    /// File extension for generated fixtures.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Profile::WebAccess | Profile::Syslog => "log",
+            Profile::Csv | Profile::Uuid => "csv",
+            Profile::Ndjson => "ndjson",
+            Profile::Entropy => "txt",
+        }
+    }
+}
+
+/// Writes at least `size` bytes of `profile` data to `out` (whole lines). Returns the bytes written.
+pub fn generate<W: Write>(profile: Profile, size: u64, seed: u64, out: &mut W) -> io::Result<u64> {
+    let mut gen = Generator::new(profile, seed);
+    let mut line = String::with_capacity(512);
+    let mut written = 0u64;
+    while written < size {
+        line.clear();
+        gen.next_line(&mut line);
+        out.write_all(line.as_bytes())?;
+        written += line.len() as u64;
+    }
+    Ok(written)
+}
+
+/// In-memory variant of `generate`.
+pub fn generate_vec(profile: Profile, size: usize, seed: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(size + 512);
+    generate(profile, size as u64, seed, &mut out).expect("Writing to a Vec cannot fail");
+    out
+}
+
+// --- RANDOMNESS ---
+
+// SplitMix64: tiny, fast and identical on every platform
+/// Order in which `template_usage_vec` gives its rows their template, for the id stream
+/// transforms of split chunks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TemplateUsage {
+    /// One template for most rows throughout
+    Stable,
+    /// A different dominant template every TEMPLATE_SHIFT_ROWS rows
+    Shifting,
+    /// The next template (in a fixed cycle) on half of the rows, so no run lasts
+    Alternating,
+}
+
+impl TemplateUsage {
+    pub const ALL: [TemplateUsage; 3] = [TemplateUsage::Stable, TemplateUsage::Shifting, TemplateUsage::Alternating];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TemplateUsage::Stable => "stable",
+            TemplateUsage::Shifting => "shifting",
+            TemplateUsage::Alternating => "alternating",
+        }
+    }
+}
+
+// Line shapes of `template_usage_vec`, one template each: text around a random hex id and a
+// number, which keep the vars hard to compress so the chunk is stored split
+const USAGE_TEMPLATES: [(&str, &str, &str); 8] = [
+    ("GET /api id=", " v=", ""), ("user=", " [", "]"), ("<", "> (", ")"), ("job ", ": ", ";"),
+    ("", " -> ", " !"), ("key:", " val:", ""), ("{", "} @", ""), ("'", "' = #", ""),
+];
+// Rows between changes of the dominant template of TemplateUsage::Shifting
+const TEMPLATE_SHIFT_ROWS: usize = 1500;
+
+/// `rows` lines from USAGE_TEMPLATES, picked in the order `usage` describes. Stable and shifting
+/// rows use their dominant template 80% of the time and a random one otherwise.
+pub fn template_usage_vec(usage: TemplateUsage, rows: usize, seed: u64) -> Vec<u8> {
+    let mut rng = Rng(seed);
+    let count = USAGE_TEMPLATES.len();
+    let mut out = String::with_capacity(rows * 40);
+    let mut current = 0;
+    for row in 0..rows {
+        let dominant = match usage {
+            TemplateUsage::Stable => Some(0),
+            TemplateUsage::Shifting => Some(row / TEMPLATE_SHIFT_ROWS % count),
+            TemplateUsage::Alternating => None,
+        };
+        let template = match dominant {
+            Some(t) if rng.unit() < 0.8 => t,
+            Some(_) => rng.below(count as u64) as usize,
+            None => {
+                if rng.below(2) == 1 { current = (current + 1) % count; }
+                current
+            },
+        };
+        let (head, middle, tail) = USAGE_TEMPLATES[template];
+        let _ = writeln!(out, "{}{:016x}{}{}{}", head, rng.next(), middle, rng.below(1_000_000_000), tail);
+    }
+    out.into_bytes()
+}
+
+// Bytes that neither parsing mode takes into a variable, so a line spelled with them is a
+// skeleton of its own (digits of `distinct_templates_vec`)
+const SKELETON_DIGITS: &[u8] = b"!#$%&()*+,/;<=>?@[]^`{|}~";
+const SKELETON_DIGIT_COUNT: usize = 4;
+/// Largest `templates` of `distinct_templates_vec`
+pub const MAX_DISTINCT_TEMPLATES: usize = SKELETON_DIGITS.len().pow(SKELETON_DIGIT_COUNT as u32);
+
+/// `templates` distinct line shapes, each used `repeats` times (the whole set, then again):
+/// the template number spelled in punctuation, then a one-digit value. Lines are 7 bytes, so a
+/// block past the u16 id range (65535 templates) takes a few MB and no parsing effort. A chunk
+/// with more distinct templates than a quarter of its lines is stored as passthrough, so
+/// `repeats` should be at least 5.
+pub fn distinct_templates_vec(templates: usize, repeats: usize) -> Vec<u8> {
+    assert!(templates <= MAX_DISTINCT_TEMPLATES, "at most {} distinct templates", MAX_DISTINCT_TEMPLATES);
+    let mut out = Vec::with_capacity(templates * repeats * (SKELETON_DIGIT_COUNT + 3));
+    for repeat in 0..repeats {
+        for template in 0..templates {
+            let mut n = template;
+            for _ in 0..SKELETON_DIGIT_COUNT {
+                out.push(SKELETON_DIGITS[n % SKELETON_DIGITS.len()]);
+                n /= SKELETON_DIGITS.len();
+            }
+            out.extend_from_slice(&[b' ', b'0' + ((template * 7 + repeat) % 10) as u8, b'\n']);
+        }
+    }
+    out
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 { self.next() % n }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str { items[self.below(items.len() as u64) as usize] }
+
+    fn unit(&mut self) -> f64 { (self.next() >> 11) as f64 / (1u64 << 53) as f64 }
+}
+
+// Zipf(s = 1.1) over `n` ranks, sampled by binary search on the cumulative weights
+struct Zipf { cumulative: Vec<f64> }
+
+impl Zipf {
+    fn new(n: usize) -> Self {
+        let mut total = 0.0;
+        let cumulative = (1..=n).map(|k| { total += 1.0 / (k as f64).powf(1.1); total }).collect();
+        Self { cumulative }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> usize {
+        let target = rng.unit() * self.cumulative[self.cumulative.len() - 1];
+        self.cumulative.partition_point(|&c| c < target).min(self.cumulative.len() - 1)
+    }
+}
+
+// --- PROFILES ---
+
+const METHODS: [&str; 4] = ["GET", "GET", "POST", "PUT"];
+const STATUSES: [u32; 6] = [200, 200, 200, 304, 404, 500];
+const SECTIONS: [&str; 8] = ["api/v1/users", "api/v1/orders", "static/js", "static/css", "images", "blog", "search", "account"];
+const AGENTS: [&str; 4] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_1) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Safari/605.1.15",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0",
+    "curl/8.4.0",
+];
+const COUNTRIES: [&str; 8] = ["IT", "US", "DE", "FR", "JP", "BR", "IN", "GB"];
+const WORDS: [&str; 12] = ["fast", "delivery", "broken", "refund", "great", "late", "package", "support", "quality", "price", "again", "never"];
+const SERVICES: [&str; 5] = ["auth", "billing", "catalog", "gateway", "search"];
+const EVENTS: [&str; 6] = ["login", "logout", "purchase", "view", "error", "retry"];
+const HOSTS: [&str; 4] = ["web01", "web02", "db01", "cache01"];
+const DAEMONS: [&str; 5] = ["sshd", "cron", "kernel", "systemd", "nginx"];
+const MESSAGES: [&str; 6] = [
+    "Accepted publickey for deploy from 10.0.3.17 port 52144 ssh2",
+    "pam_unix(cron:session): session opened for user root by (uid=0)",
+    "TCP: request_sock_TCP: Possible SYN flooding on port 443. Sending cookies.",
+    "Started Daily apt download activities.",
+    "upstream timed out (110: Connection timed out) while reading response header",
+    "Out of memory: Killed process 4242 (java) total-vm:8123456kB",
+];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+struct Generator {
+    profile: Profile,
+    rng: Rng,
+    urls: Zipf,
+    // Seconds since 2024-01-01, advancing by a few seconds per line
+    clock: u64,
+    row: u64,
+    // Syslog burst in progress: (message index, lines left)
+    burst: (usize, u32),
+}
+
+impl Generator {
+    fn new(profile: Profile, seed: u64) -> Self {
+        Self { profile, rng: Rng(seed), urls: Zipf::new(2000), clock: 0, row: 0, burst: (0, 0) }
+    }
+
+    fn next_line(&mut self, line: &mut String) {
+        self.row += 1;
+        self.clock += self.rng.below(3);
+        let (day, h, m, s) = (1 + (self.clock / 86_400) % 28, (self.clock / 3600) % 24, (self.clock / 60) % 60, self.clock % 60);
+        let month = MONTHS[((self.clock / (86_400 * 28)) % 12) as usize];
+        let r = &mut self.rng;
+        // Writing to a String cannot fail
+        let _ = match self.profile {
+            Profile::WebAccess => {
+                let url = self.urls.sample(r);
+                writeln!(line, "{}.{}.{}.{} - - [{:02}/{}/2024:{:02}:{:02}:{:02} +0000] \"{} /{}/{} HTTP/1.1\" {} {} \"-\" \"{}\"",
+                    10 + r.below(200), r.below(256), r.below(256), 1 + r.below(254),
+                    day, month, h, m, s,
+                    r.pick(&METHODS), SECTIONS[url % SECTIONS.len()], url, STATUSES[r.below(6) as usize],
+                    200 + r.below(50_000), r.pick(&AGENTS))
+            },
+            Profile::Csv => {
+                if self.row == 1 { line.push_str("id,timestamp,user,country,amount,quantity,status,comment\n"); }
+                writeln!(line, "{},2024-{:02}-{:02} {:02}:{:02}:{:02},user{:05},{},{}.{:02},{},{},\"{} {}\"",
+                    self.row, 1 + (self.clock / (86_400 * 28)) % 12, day, h, m, s,
+                    r.below(20_000), r.pick(&COUNTRIES), r.below(1000), r.below(100), 1 + r.below(12),
+                    if r.below(10) == 0 { "refunded" } else { "paid" }, r.pick(&WORDS), r.pick(&WORDS))
+            },
+            Profile::Ndjson => {
+                writeln!(line, "{{\"ts\":\"2024-{:02}-{:02}T{:02}:{:02}:{:02}Z\",\"service\":\"{}\",\"event\":\"{}\",\"user_id\":{},\"latency_ms\":{}.{},\"ok\":{},\"tags\":[\"{}\",\"{}\"]}}",
+                    1 + (self.clock / (86_400 * 28)) % 12, day, h, m, s,
+                    r.pick(&SERVICES), r.pick(&EVENTS), r.below(50_000), r.below(2000), r.below(10),
+                    r.below(20) != 0, r.pick(&COUNTRIES), r.pick(&WORDS))
+            },
+            Profile::Syslog => {
+                if self.burst.1 == 0 {
+                    // Mostly single lines, sometimes a burst of the same message
+                    let len = if r.below(8) == 0 { 5 + r.below(60) as u32 } else { 1 };
+                    self.burst = (r.below(MESSAGES.len() as u64) as usize, len);
+                }
+                self.burst.1 -= 1;
+                writeln!(line, "{} {:>2} {:02}:{:02}:{:02} {} {}[{}]: {}",
+                    month, day, h, m, s, r.pick(&HOSTS), r.pick(&DAEMONS), 1000 + r.below(30_000), MESSAGES[self.burst.0])
+            },
+            Profile::Uuid => {
+                if self.row == 1 { line.push_str("event_id,timestamp,service,event,session,sha1\n"); }
+                let (a, b, c) = (r.next(), r.next(), r.next());
+                writeln!(line, "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x},2024-{:02}-{:02} {:02}:{:02}:{:02},{},{},{:016x},{:016x}{:016x}{:08x}",
+                    a >> 32, (a >> 16) & 0xFFFF, a & 0xFFF, 0x8000 | ((b >> 48) & 0x3FFF), b & 0xFFFF_FFFF_FFFF,
+                    1 + (self.clock / (86_400 * 28)) % 12, day, h, m, s,
+                    r.pick(&SERVICES), r.pick(&EVENTS), c, r.next(), r.next(), r.next() >> 32)
+            },
+            Profile::Entropy => {
+                let len = 60 + r.below(60);
+                for _ in 0..len { line.push((b'!' + r.below(94) as u8) as char); }
+                line.push('\n');
+                Ok(())
+            },
+        };
+    }
+}
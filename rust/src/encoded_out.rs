@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+// ============================================================================
+//  ENCODED OUTPUT (--output-encoding)
+// ============================================================================
+//
+//  The restored data is compressed on its way to the output, so an archive becomes a .gz or
+//  .zst file in one pass, with the memory of one encoder instead of a temporary copy.
+//  Everything upstream of the writer (CRC checks, limits, progress) still sees the restored
+//  bytes, uncompressed.
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputEncoding { None, Gzip, Zstd }
+
+impl OutputEncoding {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Some(OutputEncoding::None),
+            "gzip" | "gz" => Some(OutputEncoding::Gzip),
+            "zstd" | "zst" => Some(OutputEncoding::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            OutputEncoding::None => "none",
+            OutputEncoding::Gzip => "gzip",
+            OutputEncoding::Zstd => "zstd",
+        }
+    }
+}
+
+// Counts the encoded bytes that reach the destination
+struct Counted<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+enum Encoder<W: Write> {
+    Plain(Counted<W>),
+    Gzip(GzEncoder<Counted<W>>),
+    Zstd(zstd::Encoder<'static, Counted<W>>),
+}
+
+/// Writer that compresses everything written to it into `out`. The stream is only complete
+/// after `finish`: dropping the writer leaves a truncated .gz/.zst file.
+pub struct EncodedWriter<W: Write> {
+    encoder: Encoder<W>,
+}
+
+impl<W: Write> EncodedWriter<W> {
+    pub fn new(out: W, encoding: OutputEncoding) -> io::Result<Self> {
+        let out = Counted { inner: out, count: 0 };
+        let encoder = match encoding {
+            OutputEncoding::None => Encoder::Plain(out),
+            OutputEncoding::Gzip => Encoder::Gzip(GzEncoder::new(out, Compression::default())),
+            OutputEncoding::Zstd => Encoder::Zstd(zstd::Encoder::new(out, ZSTD_LEVEL)?),
+        };
+        Ok(Self { encoder })
+    }
+
+    /// Ends the encoded stream and flushes it. Returns the bytes written to `out`.
+    pub fn finish(self) -> io::Result<u64> {
+        let mut out = match self.encoder {
+            Encoder::Plain(out) => out,
+            Encoder::Gzip(e) => e.finish()?,
+            Encoder::Zstd(e) => e.finish()?,
+        };
+        out.flush()?;
+        Ok(out.count)
+    }
+}
+
+impl<W: Write> Write for EncodedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(w) => w.write(buf),
+            Encoder::Gzip(w) => w.write(buf),
+            Encoder::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gzip(w) => w.flush(),
+            Encoder::Zstd(w) => w.flush(),
+        }
+    }
+}
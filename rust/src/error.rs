@@ -0,0 +1,104 @@
+use std::any::Any;
+use std::fmt;
+use std::io;
+
+// ============================================================================
+//  LIBRARY ERROR TYPE
+// ============================================================================
+
+/// Errors returned by the compressor, decompressor, archive reader and backends.
+/// `chunk` is the 1-based chunk index where the problem was detected (0 until the
+/// caller attaches it with `at_chunk`, since the decompressor works on one chunk at a time).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CastError {
+    Io(io::Error),
+    CrcMismatch { chunk: usize, expected: u32, actual: u32 },
+    CorruptHeader { chunk: usize, detail: String },
+    LengthOverflow { chunk: usize, detail: String },
+    UnsupportedFormat { version: u8 },
+    BackendFailure { backend: &'static str, detail: String },
+    /// The decoder hit its memory limit, or could not allocate its dictionary. `required` is
+    /// read from the stream header when it is intact; `available` is what the system reports.
+    DecoderMemory { backend: &'static str, required: Option<u64>, limit: Option<u64>, available: Option<u64>, detail: String },
+    NotACastArchive,
+    /// A bug rather than bad input (e.g. a panic caught in a worker thread)
+    Internal { detail: String },
+    /// Stopped through a `CancellationToken`
+    Cancelled,
+}
+
+impl CastError {
+    /// True when the archive itself is damaged (as opposed to I/O or backend trouble).
+    pub fn is_corruption(&self) -> bool {
+        matches!(self, CastError::CrcMismatch { .. } | CastError::CorruptHeader { .. } | CastError::LengthOverflow { .. })
+    }
+
+    /// Attaches the chunk index to chunk-level errors.
+    pub fn at_chunk(mut self, index: usize) -> Self {
+        match &mut self {
+            CastError::CrcMismatch { chunk, .. } | CastError::CorruptHeader { chunk, .. } | CastError::LengthOverflow { chunk, .. } => *chunk = index,
+            _ => {}
+        }
+        self
+    }
+
+    /// Converts the payload of a caught panic into an `Internal` error, keeping its message.
+    pub fn from_panic(payload: Box<dyn Any + Send>) -> Self {
+        let detail = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        CastError::Internal { detail: format!("panicked: {}", detail) }
+    }
+}
+
+impl fmt::Display for CastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CastError::Io(e) => write!(f, "I/O error: {}", e),
+            CastError::CrcMismatch { chunk, expected, actual } => write!(f, "CRC Check Failed at Chunk {}. Expected: {}, Got: {}", chunk, expected, actual),
+            CastError::CorruptHeader { chunk, detail } => write!(f, "Corrupted Block Header at Chunk {} ({})", chunk, detail),
+            CastError::LengthOverflow { chunk, detail } => write!(f, "Segment length out of bounds at Chunk {} ({})", chunk, detail),
+            CastError::UnsupportedFormat { version } => write!(f, "Unsupported CAST format version {}", version),
+            CastError::BackendFailure { backend, detail } => write!(f, "{} backend failure: {}", backend, detail),
+            CastError::DecoderMemory { backend, required, limit, available, detail } => {
+                match required {
+                    Some(required) => write!(f, "This archive requires ~{} for decompression (LZMA dictionary)", megabytes(*required))?,
+                    None => write!(f, "{} decoder ran out of memory ({})", backend, detail)?,
+                }
+                if let Some(limit) = limit {
+                    write!(f, "; the decoder memory limit is {}", megabytes(*limit))?;
+                }
+                if let Some(available) = available {
+                    write!(f, "; this machine appears to have {} available", megabytes(*available))?;
+                }
+                write!(f, ". Decompress on a machine with more memory")?;
+                if limit.is_some() { write!(f, " or raise the limit")?; }
+                Ok(())
+            },
+            CastError::NotACastArchive => write!(f, "Invalid CAST file (Missing Magic)"),
+            CastError::Internal { detail } => write!(f, "Internal error: {}", detail),
+            CastError::Cancelled => write!(f, "Operation cancelled"),
+        }
+    }
+}
+
+// Whole megabytes, as the sizes in memory errors are estimates anyway
+fn megabytes(n: u64) -> String {
+    format!("{} MB", ((n + (1 << 19)) >> 20).max(1))
+}
+
+impl std::error::Error for CastError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CastError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CastError {
+    fn from(e: io::Error) -> Self {
+        CastError::Io(e)
+    }
+}
@@ -0,0 +1,190 @@
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+// ============================================================================
+//  OUTPUT ATTRIBUTES (--chmod, --chown, --preserve-permissions, --readonly, --hidden)
+// ============================================================================
+//
+//  Restored files are created with the defaults of the process: the umask of the caller for
+//  the mode, the caller as the owner. These settings are applied once an output is complete
+//  and closed, so a partial or cancelled output never carries them. Every requested attribute
+//  is tried even when another one failed, and the failures are reported together.
+//
+//  Ownership and permission bits are Unix concepts; on Windows the closest analogues are the
+//  read-only and hidden attributes. --readonly works everywhere (it clears the write bits on
+//  Unix), --hidden only on Windows.
+
+/// Label with the permission bits of the compressed input file (octal, e.g. "0644"), applied by
+/// --preserve-permissions
+pub const UNIX_MODE_LABEL: &str = "unix_mode";
+
+/// Owner of a restored file: a user, a group, or both (`None` keeps the current one).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let id = |v: Option<u32>| v.map_or("-".to_string(), |v| v.to_string());
+        write!(f, "{}:{}", id(self.uid), id(self.gid))
+    }
+}
+
+/// Attributes given to restored files. The default changes nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputAttributes {
+    /// --chmod: permission bits (wins over a stored mode)
+    pub mode: Option<u32>,
+    /// --chown
+    pub owner: Option<Owner>,
+    /// --preserve-permissions: the mode recorded in the archive (UNIX_MODE_LABEL), if any
+    pub preserve_permissions: bool,
+    pub readonly: bool,
+    pub hidden: bool,
+}
+
+impl OutputAttributes {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Applies the attributes to the finished file at `path`. `stored_mode` is the mode the
+    /// archive recorded, used by --preserve-permissions. Returns one message per attribute
+    /// that could not be applied.
+    pub fn apply(&self, path: &Path, stored_mode: Option<u32>) -> Vec<String> {
+        let mut failures = Vec::new();
+        // Owner first: changing it may clear setuid/setgid bits set by the mode
+        if let Some(owner) = self.owner {
+            if let Err(e) = set_owner(path, owner) {
+                let hint = if e.kind() == io::ErrorKind::PermissionDenied { " (changing the owner needs root or CAP_CHOWN)" } else { "" };
+                failures.push(format!("cannot change the owner to {}: {}{}", owner, e, hint));
+            }
+        }
+        let mode = self.mode.or(stored_mode.filter(|_| self.preserve_permissions));
+        if let Some(mode) = mode {
+            if let Err(e) = set_mode(path, mode) {
+                failures.push(format!("cannot set the mode {:04o}: {}", mode, e));
+            }
+        }
+        if self.readonly {
+            let result = std::fs::metadata(path).and_then(|m| {
+                let mut permissions = m.permissions();
+                permissions.set_readonly(true);
+                std::fs::set_permissions(path, permissions)
+            });
+            if let Err(e) = result { failures.push(format!("cannot make it read-only: {}", e)); }
+        }
+        if self.hidden {
+            if let Err(e) = set_hidden(path) { failures.push(format!("cannot hide it: {}", e)); }
+        }
+        failures
+    }
+}
+
+/// Parses the octal permission bits of --chmod ("640", "0640", up to "7777").
+pub fn parse_mode(s: &str) -> Option<u32> {
+    if s.is_empty() || s.len() > 5 { return None; }
+    u32::from_str_radix(s, 8).ok().filter(|&m| m <= 0o7777)
+}
+
+/// Parses the owner of --chown: `user`, `user:group` or `:group`, each a name or a numeric id.
+pub fn parse_owner(s: &str) -> Result<Owner, String> {
+    let (user, group) = match s.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (s, None),
+    };
+    let uid = match user {
+        "" => None,
+        _ => Some(user.parse().or_else(|_| lookup_user(user))?),
+    };
+    let gid = match group {
+        None | Some("") => None,
+        Some(group) => Some(group.parse().or_else(|_| lookup_group(group))?),
+    };
+    if uid.is_none() && gid.is_none() { return Err(format!("'{}' names neither a user nor a group", s)); }
+    Ok(Owner { uid, gid })
+}
+
+/// Permission bits of a file, where the platform has them.
+#[cfg(unix)]
+pub fn mode_of(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn mode_of(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_owner(path: &Path, owner: Owner) -> io::Result<()> {
+    std::os::unix::fs::chown(path, owner.uid, owner.gid)
+}
+
+#[cfg(not(unix))]
+fn set_owner(_path: &Path, _owner: Owner) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "file owners can only be set on Unix"))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "permission bits can only be set on Unix"))
+}
+
+#[cfg(windows)]
+fn set_hidden(path: &Path) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+    extern "system" {
+        fn SetFileAttributesW(name: *const u16, attributes: u32) -> i32;
+    }
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    let attributes = std::fs::metadata(path)?.file_attributes() | FILE_ATTRIBUTE_HIDDEN;
+    let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    match unsafe { SetFileAttributesW(name.as_ptr(), attributes) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(windows))]
+fn set_hidden(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the hidden attribute only exists on Windows"))
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<u32, String> {
+    let c_name = std::ffi::CString::new(name).map_err(|_| format!("unknown user '{}'", name))?;
+    // Looked up once while the options are parsed, before any other thread calls getpw*
+    let entry = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if entry.is_null() { return Err(format!("unknown user '{}'", name)); }
+    Ok(unsafe { (*entry).pw_uid })
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<u32, String> {
+    let c_name = std::ffi::CString::new(name).map_err(|_| format!("unknown group '{}'", name))?;
+    let entry = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if entry.is_null() { return Err(format!("unknown group '{}'", name)); }
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+#[cfg(not(unix))]
+fn lookup_user(name: &str) -> Result<u32, String> {
+    Err(format!("cannot look up user '{}': file owners only exist on Unix", name))
+}
+
+#[cfg(not(unix))]
+fn lookup_group(name: &str) -> Result<u32, String> {
+    Err(format!("cannot look up group '{}': file owners only exist on Unix", name))
+}
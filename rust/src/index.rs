@@ -0,0 +1,292 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+
+use crc32fast::Hasher;
+
+use crate::archive::{ArchiveHeader, ChunkHeader, ChunkReader, CHUNK_HEADER_SIZE, UTF8_BOM};
+use crate::cast::{CASTDecompressor, NativeDecompressor, ParserConfig, ID_FLAG_MAC_TRAILER};
+use crate::error::CastError;
+
+// ============================================================================
+//  SIDECAR INDEX (archive.cast.idx)
+// ============================================================================
+//
+//  Optional file next to a classic archive, mapping every chunk to its position
+//  in the archive and in the original data, so readers can seek instead of
+//  decoding everything before the part they need. The archive itself is never
+//  changed: without a (valid) sidecar everything works sequentially as before.
+//
+//  magic "CIDX" (4) | version u8 | archive_len u64 | fingerprint u32 | count u32
+//  [Entry]* offset u64 | compressed_len u64 | data_offset u64 | data_len u64
+//           | first_row u64 | lines u64 | crc u32 | id_flag u8
+//           | tokenizer u8 | aggressive u8 | timestamp_split u8 | quote u8 | max_var_len u64
+//  crc32 of everything above u32
+//
+//  The last five fields are the chunk's parser configuration (see "PARSER CONFIGURATION" in
+//  cast.rs), tokenizer 0 when it has none.
+//
+//  The fingerprint is the CRC32 of the archive header and of the last chunk
+//  header: together with the archive length it detects sidecars left behind by
+//  a rewritten archive (e.g. relabel or recompression). Each chunk header is
+//  checked again against its entry whenever a reader seeks to it.
+
+pub const INDEX_MAGIC: [u8; 4] = *b"CIDX";
+pub const INDEX_VERSION: u8 = 1;
+const INDEX_PREFIX_SIZE: usize = 21;
+const INDEX_ENTRY_SIZE: usize = 65;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndexEntry {
+    /// Archive offset of the chunk header
+    pub offset: u64,
+    /// Chunk header + body bytes
+    pub compressed_len: u64,
+    /// Offset of the chunk's data in the original input (UTF-8 BOM included)
+    pub data_offset: u64,
+    pub data_len: u64,
+    /// Newlines before the chunk, i.e. the 0-based row its first byte belongs to
+    pub first_row: u64,
+    /// Newlines inside the chunk
+    pub lines: u64,
+    pub crc: u32,
+    pub id_flag: u8,
+    /// Configuration the chunk was tokenized with (`Chunk::parser_config`)
+    pub parser: Option<ParserConfig>,
+}
+
+impl IndexEntry {
+    /// Whether `header` (read at `offset`) is the chunk this entry describes.
+    pub fn matches(&self, header: &ChunkHeader) -> bool {
+        header.crc == self.crc && header.id_flag == self.id_flag
+            && (CHUNK_HEADER_SIZE + header.body_len()) as u64 == self.compressed_len
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ArchiveIndex {
+    pub archive_len: u64,
+    pub fingerprint: u32,
+    pub entries: Vec<IndexEntry>,
+}
+
+/// Result of looking for the sidecar of an archive.
+pub enum IndexLookup {
+    Missing,
+    /// Present but unusable (the reason is printable)
+    Stale(String),
+    Valid(ArchiveIndex),
+}
+
+pub fn sidecar_path(archive_path: &str) -> String {
+    format!("{}.idx", archive_path)
+}
+
+/// Newlines in `data` (the rows a chunk completes).
+pub fn count_lines(data: &[u8]) -> u64 {
+    memchr::memchr_iter(b'\n', data).count() as u64
+}
+
+/// Chunks checked by a sampled verification (`-v --verify-sample`), in archive order: the
+/// first and the last, plus `wanted - 2` others drawn from `seed`. The same seed always picks
+/// the same chunks.
+pub fn sample_chunks(count: usize, wanted: usize, seed: u64) -> Vec<usize> {
+    if wanted >= count { return (0..count).collect(); }
+    if wanted == 0 { return Vec::new(); }
+    // SplitMix64: a fixed, portable sequence for a given seed
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    let mut picked = vec![0];
+    if wanted > 1 { picked.push(count - 1); }
+    // Partial Fisher-Yates over the chunks in between
+    let mut middle: Vec<usize> = (1..count - 1).collect();
+    for i in 0..wanted - picked.len() {
+        let j = i + (next() % (middle.len() - i) as u64) as usize;
+        middle.swap(i, j);
+    }
+    picked.extend_from_slice(&middle[..wanted - picked.len()]);
+    picked.sort_unstable();
+    picked
+}
+
+/// Offset and header of every chunk, found by seeking from one chunk header to the next
+/// (bodies are never read). For archives without a valid sidecar. Stops at the
+/// authentication trailer, which is no data chunk.
+pub fn walk_chunk_headers<R: Read + Seek>(r: &mut R, first_offset: u64) -> Result<Vec<(u64, ChunkHeader)>, CastError> {
+    let archive_len = r.seek(SeekFrom::End(0))?;
+    let mut chunks = Vec::new();
+    let mut offset = first_offset;
+    while offset < archive_len {
+        let chunk = chunks.len() + 1;
+        if archive_len - offset < CHUNK_HEADER_SIZE as u64 {
+            return Err(CastError::LengthOverflow { chunk, detail: "Truncated chunk header".to_string() });
+        }
+        let mut raw = [0u8; CHUNK_HEADER_SIZE];
+        r.seek(SeekFrom::Start(offset))?;
+        r.read_exact(&mut raw)?;
+        let header = ChunkHeader::from_bytes(&raw);
+        if header.id_flag == ID_FLAG_MAC_TRAILER { break; }
+        chunks.push((offset, header));
+        offset += (CHUNK_HEADER_SIZE + header.body_len()) as u64;
+    }
+    Ok(chunks)
+}
+
+/// CRC32 of the archive header and of the chunk header at `last_chunk` (if any).
+pub fn archive_fingerprint<R: Read + Seek>(r: &mut R, last_chunk: Option<u64>) -> Result<u32, CastError> {
+    r.seek(SeekFrom::Start(0))?;
+    let header_len = ArchiveHeader::read_from(&mut BufReader::new(&mut *r))?.encoded_len();
+    let mut bytes = vec![0u8; header_len];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut bytes)?;
+    if let Some(offset) = last_chunk {
+        let mut chunk_header = [0u8; CHUNK_HEADER_SIZE];
+        r.seek(SeekFrom::Start(offset))?;
+        r.read_exact(&mut chunk_header)?;
+        bytes.extend_from_slice(&chunk_header);
+    }
+    let mut h = Hasher::new();
+    h.update(&bytes);
+    Ok(h.finalize())
+}
+
+impl ArchiveIndex {
+    /// Index of the archive at `archive_path` from entries collected while writing it.
+    pub fn for_archive(archive_path: &str, entries: Vec<IndexEntry>) -> Result<Self, CastError> {
+        let mut f = File::open(archive_path)?;
+        let archive_len = f.metadata()?.len();
+        let fingerprint = archive_fingerprint(&mut f, entries.last().map(|e| e.offset))?;
+        Ok(Self { archive_len, fingerprint, entries })
+    }
+
+    /// Builds the index of an existing archive with one sequential pass (every chunk is
+    /// decoded to learn its size and line count).
+    pub fn build<D: NativeDecompressor>(archive_path: &str, decompressor: &CASTDecompressor<D>) -> Result<Self, CastError> {
+        let mut reader = ChunkReader::new(BufReader::new(File::open(archive_path)?))?;
+        let archive = reader.archive_header().clone();
+        let mut offset = archive.encoded_len() as u64;
+        let mut data_offset = if archive.has_bom() { UTF8_BOM.len() as u64 } else { 0 };
+        let mut first_row = 0;
+        let mut entries = Vec::new();
+        let mut restored = Vec::new();
+        while let Some(chunk) = reader.next_chunk()? {
+            // Entries describe the chunks as stored; a duplicate chunk is decoded as the one it refers to
+            let (compressed_len, header, parser) = ((CHUNK_HEADER_SIZE + chunk.body.len()) as u64, chunk.header, chunk.parser_config());
+            let chunk = reader.resolve(chunk).map_err(|e| e.at_chunk(entries.len() + 1))?;
+            restored.clear();
+            decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut restored)
+                .map_err(|e| e.at_chunk(entries.len() + 1))?;
+            let lines = count_lines(&restored);
+            entries.push(IndexEntry {
+                offset, compressed_len, data_offset, data_len: restored.len() as u64,
+                first_row, lines, crc: header.crc, id_flag: header.id_flag, parser,
+            });
+            offset += compressed_len;
+            data_offset += restored.len() as u64;
+            first_row += lines;
+        }
+        Self::for_archive(archive_path, entries)
+    }
+
+    /// Loads the sidecar of `archive_path` and checks that it still describes the archive.
+    pub fn lookup(archive_path: &str) -> IndexLookup {
+        let bytes = match std::fs::read(sidecar_path(archive_path)) {
+            Ok(b) => b,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return IndexLookup::Missing,
+            Err(e) => return IndexLookup::Stale(format!("Cannot read index: {}", e)),
+        };
+        let index = match Self::decode(&bytes) {
+            Some(i) => i,
+            None => return IndexLookup::Stale("Index file is damaged or from another version".to_string()),
+        };
+        let stale = || IndexLookup::Stale("Archive changed after the index was written".to_string());
+        let mut f = match File::open(archive_path) {
+            Ok(f) => f,
+            Err(e) => return IndexLookup::Stale(format!("Cannot check index against the archive: {}", e)),
+        };
+        if f.metadata().map(|m| m.len()).ok() != Some(index.archive_len) { return stale(); }
+        match archive_fingerprint(&mut f, index.entries.last().map(|e| e.offset)) {
+            Ok(fingerprint) if fingerprint == index.fingerprint => IndexLookup::Valid(index),
+            _ => stale(),
+        }
+    }
+
+    /// Writes the sidecar next to `archive_path` (via a temporary file, then renamed).
+    pub fn save(&self, archive_path: &str) -> io::Result<()> {
+        let path = sidecar_path(archive_path);
+        let tmp = format!("{}.tmp", path);
+        let mut f = File::create(&tmp)?;
+        f.write_all(&self.encode())?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, &path)
+    }
+
+    /// Moves every entry by `delta` bytes (the archive header changed size) and takes the
+    /// new archive length and fingerprint.
+    pub fn rebase(&mut self, delta: i64, archive_len: u64, fingerprint: u32) {
+        for e in &mut self.entries { e.offset = e.offset.wrapping_add_signed(delta); }
+        self.archive_len = archive_len;
+        self.fingerprint = fingerprint;
+    }
+
+    /// Total newlines of the original data.
+    pub fn lines(&self) -> u64 { self.entries.last().map(|e| e.first_row + e.lines).unwrap_or(0) }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(INDEX_PREFIX_SIZE + self.entries.len() * INDEX_ENTRY_SIZE + 4);
+        b.extend_from_slice(&INDEX_MAGIC);
+        b.push(INDEX_VERSION);
+        b.extend_from_slice(&self.archive_len.to_le_bytes());
+        b.extend_from_slice(&self.fingerprint.to_le_bytes());
+        b.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for e in &self.entries {
+            for v in [e.offset, e.compressed_len, e.data_offset, e.data_len, e.first_row, e.lines] {
+                b.extend_from_slice(&v.to_le_bytes());
+            }
+            b.extend_from_slice(&e.crc.to_le_bytes());
+            b.push(e.id_flag);
+            match e.parser {
+                Some(p) => {
+                    b.extend_from_slice(&[p.tokenizer, p.aggressive as u8, p.split_timestamps as u8, p.quote]);
+                    b.extend_from_slice(&p.max_var_len.to_le_bytes());
+                },
+                None => b.extend_from_slice(&[0; 12]),
+            }
+        }
+        let mut h = Hasher::new();
+        h.update(&b);
+        b.extend_from_slice(&h.finalize().to_le_bytes());
+        b
+    }
+
+    fn decode(b: &[u8]) -> Option<Self> {
+        if b.len() < INDEX_PREFIX_SIZE + 4 || b[0..4] != INDEX_MAGIC || b[4] != INDEX_VERSION { return None; }
+        let (body, tail) = b.split_at(b.len() - 4);
+        let mut h = Hasher::new();
+        h.update(body);
+        if h.finalize().to_le_bytes() != tail { return None; }
+
+        let u64_at = |p: usize| u64::from_le_bytes(body[p..p + 8].try_into().unwrap());
+        let u32_at = |p: usize| u32::from_le_bytes(body[p..p + 4].try_into().unwrap());
+        let count = u32_at(17) as usize;
+        if body.len() != INDEX_PREFIX_SIZE + count * INDEX_ENTRY_SIZE { return None; }
+        let entries = (0..count).map(|i| {
+            let p = INDEX_PREFIX_SIZE + i * INDEX_ENTRY_SIZE;
+            let parser = (body[p + 53] != 0).then(|| ParserConfig {
+                tokenizer: body[p + 53], aggressive: body[p + 54] != 0, split_timestamps: body[p + 55] != 0,
+                quote: body[p + 56], max_var_len: u64_at(p + 57),
+            });
+            IndexEntry {
+                offset: u64_at(p), compressed_len: u64_at(p + 8), data_offset: u64_at(p + 16),
+                data_len: u64_at(p + 24), first_row: u64_at(p + 32), lines: u64_at(p + 40),
+                crc: u32_at(p + 48), id_flag: body[p + 52], parser,
+            }
+        }).collect();
+        Some(Self { archive_len: u64_at(5), fingerprint: u32_at(13), entries })
+    }
+}
@@ -1,2 +1,25 @@
+pub mod archive;
+pub mod auth;
+pub mod cancel;
 pub mod cast;
-pub mod cast_lzma;
\ No newline at end of file
+pub mod cast_lzma;
+pub mod chunk_model;
+pub mod cli;
+pub mod convert;
+pub mod datagen;
+pub mod encoded_out;
+pub mod error;
+pub mod file_attrs;
+pub mod index;
+pub mod manifest;
+pub mod memory;
+pub mod pack;
+pub mod positioned_out;
+pub mod read_at;
+pub mod sparse_out;
+pub mod templates;
+pub mod throttle;
+#[cfg(feature = "mmap")]
+pub mod mmap_out;
+#[cfg(feature = "async")]
+pub mod async_io;
@@ -1,9 +1,38 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
 use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::Path;
-use std::time::Instant;
+use std::io::{self, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use crc32fast::Hasher;
+use regex::bytes::{Regex, RegexBuilder};
+
+use cast::cast::{describe_id_flag, is_passthrough, min_lines_for_templates, AGGRESSIVE_TEMPLATE_RATIO, STRICT_TEMPLATE_RATIO, U16_MAX_TEMPLATES, CASTCompressor, ID_FLAG_DUPLICATE, CASTDecompressor, IdentityBackend, IdsTransform, OutputLimit, ParserConfig, ProgressEvent, DEFAULT_MAX_VAR_LEN};
+use cast::archive::{catch_panics, chunk_crc, detect_column_names, encode_chunk, encode_duplicate, has_parser_record, line_align_overshoot, parse_label, set_label, ArchiveHeader, BlockSettings, Chunk, ChecksumKind, ChunkDedup, ChunkHeader, ChunkReader, CompressionStats, DedupTarget, EncodedChunk, FLAG_INPUT_CHANGED, FollowReader, HeaderRow, InputChunker, InputWatch, RowOrder, ARCHIVE_MAGIC, CHUNK_HEADER_SIZE, FORMAT_VERSION, LEGACY_VERSION, MAX_SEGMENT_LEN, COLUMNS_LABEL, CONTENT_DIGEST_LABEL, HEADER_SAMPLE_LEN, ROW_ORDER_LABEL, UTF8_BOM, WritePipeline, digest_hex, settings_histogram};
+use cast::auth::{AuthFailure, Mac, MacKey, MacTrailer, Sha256};
+#[cfg(feature = "mmap")]
+use cast::mmap_out::MmapWriter;
+use cast::datagen::{generate, Profile, DEFAULT_SEED};
+use cast::manifest::{body_crc, push_json_string, ArchiveRecord, Manifest, ManifestChunk, ManifestSettings, ManifestTiming, ManifestTotals};
+use cast::index::{archive_fingerprint, count_lines, sample_chunks, sidecar_path, walk_chunk_headers, ArchiveIndex, IndexEntry, IndexLookup};
+use cast::sparse_out::SparseWriter;
+use cast::encoded_out::{EncodedWriter, OutputEncoding};
+use cast::cancel::{CancelPolicy, CancellationToken};
+use cast::error::CastError;
+use cast::file_attrs::{mode_of, parse_mode, parse_owner, OutputAttributes, UNIX_MODE_LABEL};
+use cast::chunk_model::{ChunkAdvice, ChunkSizeModel};
+use cast::memory::{available_memory, MemoryBudget, FALLBACK_AVAILABLE_MEMORY};
+use cast::throttle::{IoLimit, ThrottledReader, ThrottledWriter};
+use cast::read_at::{open_shared, ReadAt, SeekLock};
+use cast::positioned_out::PositionedSink;
+use cast::cli::{self, Binary};
+use cast::convert::{collect_convert_inputs, decoded_len_hint, decoder, ConvertInput, InputEncoding};
+use cast::templates::{render_listing, render_template, TemplateMatch, TemplateSet, TemplateSort, TemplateTally, PARSER_COMMENT};
+use cast::pack::{file_table_path, find_packed_file, read_file_table, write_file_table, PackReader, PackedFile};
 
 // Import implementations including the new Runtime wrappers and 7z utils
 use cast::cast_lzma::{
@@ -13,11 +42,124 @@ use cast::cast_lzma::{
     SevenZipDecompressorBackend,
     RuntimeLzmaCompressor,
     RuntimeLzmaDecompressor,
-    CASTLzmaCompressor,
     CASTLzmaDecompressor,
+    mt_block_size,
+    mt_dict_size,
+    parse_7z_args,
+    plan_multithread,
+    stream_dict_size,
     try_find_7zip_path
 };
 
+// --- JSON EVENTS ---
+//
+// With --json, stdout carries only JSON lines (one event per line) and the decorated
+// report is suppressed; warnings and errors keep going to stderr.
+//
+// Schema (version 1, every event carries "v" and "event"):
+//   start  {command, input[, output][, backend_command]}
+//   chunk  {command, idx, bytes_in, bytes_out[, strategy][, backend]}
+//   progress {command, idx, stage, done[, total]}   (decompress only, throttled)
+//   done   {command, chunks, bytes_in, bytes_out, ratio, time[, strategy][, result][, fallback_chunks][, fast_chunks, fast_bytes][, cross_check][, bytes_encoded][, throttle_wait][, chunks_total]}
+//   error  {command, message}
+//   part   {idx, file, first_row, rows, bytes}   (decompress --shards, before done)
+//   block  {idx, layout, id_flag, backend[, dict_size][, check][, column_codecs][, parse][, duplicate_of]}
+//          (decompress --verbose, before the chunks; see "BLOCK SETTINGS" in archive.rs)
+//   batch  {files, archived, skipped, jobs, encoder_threads}   (-c --batch, after the files)
+//   crc    {idx, offset, bytes, crc}   (crc command; its done event has command, chunks,
+//          bytes_in and crc, the CRC32 of the whole file)
+// command is "compress", "decompress", "verify" or "crc"; strategy is the parsing mode of the
+// chunk ("Mixed" in done when chunks differ); result is "verified", "decoded", "authenticated"
+// (-v --hmac-key-file) or "sampled" (-v --verify-sample, whose done event adds chunks_total);
+// backend_command is the full 7-Zip command line when compressing through 7-Zip; backend
+// ("7zip", "native", or "native-fast" for a chunk --max-time encoded fast) is the encoder
+// that actually wrote a compressed chunk, and fallback_chunks counts the chunks re-encoded
+// natively after a 7-Zip failure; fast_chunks/fast_bytes count the chunks (and their input
+// bytes) that --max-time encoded fast;
+// cross_check (verify --strict-verify) is "passed", or "unavailable" without 7-Zip;
+// bytes_encoded is the size of the output file of decompress --output-encoding;
+// throttle_wait (with --io-limit) is the part of time spent waiting for the rate limit.
+// bytes_in/bytes_out are the bytes consumed/produced by the command (original and
+// archive bytes when compressing, the reverse otherwise).
+
+const JSON_SCHEMA_VERSION: u64 = 1;
+
+// --follow stops when the input has not grown for this long (--idle-timeout)
+const FOLLOW_DEFAULT_IDLE: Duration = Duration::from_secs(30);
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+fn json_output() -> bool { JSON_OUTPUT.load(Ordering::Relaxed) }
+
+// --jobs: each thread compressing a file of the batch reports it in one line instead of the
+// decorated report, and tags its JSON events and warnings with it
+thread_local! {
+    static JOB_FILE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Whether the decorated report is printed: not with --json, nor by the jobs of a batch.
+fn decorated_output() -> bool {
+    !json_output() && JOB_FILE.with(|f| f.borrow().is_none())
+}
+
+/// "<file>: " in the jobs of a batch, to prefix warnings with; empty otherwise.
+fn job_tag() -> String {
+    JOB_FILE.with(|f| f.borrow().as_ref().map_or(String::new(), |file| format!("{}: ", file)))
+}
+
+// Decorated report line: printed to stdout unless JSON events were requested
+macro_rules! say {
+    ($($arg:tt)*) => { if decorated_output() { println!($($arg)*); } };
+}
+
+// Failure diagnostic: printed to stdout in the decorated report, moved to stderr with --json
+macro_rules! diag {
+    ($($arg:tt)*) => { if json_output() { eprintln!($($arg)*); } else { println!($($arg)*); } };
+}
+
+enum JsonValue<'a> {
+    Int(u64),
+    Float(f64),
+    Str(&'a str),
+}
+
+fn emit_event(event: &str, fields: &[(&str, JsonValue)]) {
+    if !json_output() { return; }
+    let mut line = format!("{{\"v\":{},\"event\":\"{}\"", JSON_SCHEMA_VERSION, event);
+    for (key, value) in fields {
+        line.push_str(&format!(",\"{}\":", key));
+        match value {
+            JsonValue::Int(n) => line.push_str(&n.to_string()),
+            JsonValue::Float(f) if f.is_finite() => line.push_str(&format!("{:.4}", f)),
+            JsonValue::Float(_) => line.push_str("null"),
+            JsonValue::Str(s) => push_json_string(&mut line, s),
+        }
+    }
+    JOB_FILE.with(|f| if let Some(file) = f.borrow().as_ref() {
+        line.push_str(",\"file\":");
+        push_json_string(&mut line, file);
+    });
+    line.push('}');
+    let mut out = io::stdout().lock();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+fn emit_error(command: &str, message: &str) {
+    emit_event("error", &[("command", JsonValue::Str(command)), ("message", JsonValue::Str(message))]);
+}
+
+// Time spent sleeping for --io-limit, apart from the wall time: a summary line (starting with
+// `label`) when `print` is set, and throttle_wait in the done event. The budget is shared by
+// the whole run (e.g. -c -v), so the wait is counted from `waited_before`, the limit's total
+// when the command started.
+fn report_throttle_wait<'a>(limit: Option<&IoLimit>, waited_before: Duration, label: &str, print: bool, done_fields: &mut Vec<(&str, JsonValue<'a>)>) {
+    let Some(limit) = limit else { return };
+    let waited = limit.waited().saturating_sub(waited_before).as_secs_f64();
+    if print { say!("{}{:.2}s waiting for --io-limit ({}/s)", label, waited, format_bytes(limit.bytes_per_sec() as usize)); }
+    done_fields.push(("throttle_wait", JsonValue::Float(waited)));
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
@@ -33,10 +175,70 @@ fn main() {
         print_usage(exe_name);
         return;
     }
+    if let Some(unknown) = cli::unknown_option(Binary::Cast, &args[1..]) {
+        eprintln!("[!]  Error: Unknown option '{}' (see --help).", unknown);
+        std::process::exit(1);
+    }
 
     // --- ARGUMENT PARSING ---
-    let use_multithread = args.iter().any(|arg| arg == "--multithread");
-    let verify_flag = args.iter().any(|arg| arg == "-v" || arg == "--verify");
+    // --force-multithread also shrinks the dictionary when that is what keeps threads idle
+    let force_multithread = args.iter().any(|arg| arg == "--force-multithread");
+    let use_multithread = force_multithread || args.iter().any(|arg| arg == "--multithread");
+    // --strict-verify is a verification that decodes with both backends
+    let strict_verify = args.iter().any(|arg| arg == "--strict-verify");
+    let verify_flag = strict_verify || args.iter().any(|arg| arg == "-v" || arg == "--verify");
+    let debug_verify = args.iter().any(|arg| arg == "--debug-verify");
+    let mmap_out = args.iter().any(|arg| arg == "--mmap-out");
+    let sparse_out = args.iter().any(|arg| arg == "--sparse");
+    let assume_yes = args.iter().any(|arg| arg == "-y" || arg == "--yes");
+    let batch = args.iter().any(|arg| arg == "--batch");
+    let pack = args.iter().any(|arg| arg == "--pack");
+    let ignore_errors = args.iter().any(|arg| arg == "--ignore-errors");
+    // --convert: every positional argument is an input, archived under --into
+    let convert = args.iter().any(|arg| arg == "--convert");
+    let delete_source = args.iter().any(|arg| arg == "--delete-source");
+    let into_dir = args.windows(2).find(|w| w[0] == "--into").map(|w| w[1].clone());
+    let verbose = args.iter().any(|arg| arg == "--verbose");
+    let write_index = args.iter().any(|arg| arg == "--index");
+    let column_codecs = args.iter().any(|arg| arg == "--column-codecs");
+    let front_coding = args.iter().any(|arg| arg == "--front-coding");
+    let column_dicts = !args.iter().any(|arg| arg == "--no-column-dict");
+    let dedup_chunks = args.iter().any(|arg| arg == "--dedup-chunks");
+    let order_checksum = args.iter().any(|arg| arg == "--order-checksum");
+    let allow_truncation = args.iter().any(|arg| arg == "--allow-truncation");
+    let native_fallback = !args.iter().any(|arg| arg == "--no-fallback");
+    let dump_format = args.iter().any(|arg| arg == "--dump-format");
+    let audit = args.iter().any(|arg| arg == "--audit");
+    let list_templates = args.iter().any(|arg| arg == "--list-templates");
+    let locked_reads = args.iter().any(|arg| arg == "--locked-reads");
+    let skip_strict = args.iter().any(|arg| arg == "--skip-existing=strict");
+    let skip_existing = skip_strict || args.iter().any(|arg| arg == "--skip-existing");
+    JSON_OUTPUT.store(args.iter().any(|arg| arg == "--json"), Ordering::Relaxed);
+    if mmap_out && !cfg!(feature = "mmap") {
+        eprintln!("[!]  Error: --mmap-out requires a build with the 'mmap' feature (cargo build --release --features mmap).");
+        std::process::exit(1);
+    }
+
+    // Reference file for verification (defaults to the input during -c -v)
+    let mut verify_against: Option<String> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--verify-against") {
+        if pos + 1 < args.len() {
+            verify_against = Some(args[pos+1].clone());
+        }
+    }
+
+    // --manifest: JSON description of the archive written by compression; --manifest-check:
+    // one to compare an existing archive with during verification
+    let manifest_path = args.windows(2).find(|w| w[0] == "--manifest").map(|w| w[1].clone());
+    let manifest_check = args.windows(2).find(|w| w[0] == "--manifest-check").map(|w| w[1].clone());
+
+    // Template whitelist of --audit
+    let mut allowed_templates: Option<String> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--allowed-templates") {
+        if pos + 1 < args.len() {
+            allowed_templates = Some(args[pos+1].clone());
+        }
+    }
 
     // Chunk Size parsing
     let mut chunk_size_bytes: Option<usize> = None;
@@ -51,6 +253,34 @@ fn main() {
         }
     }
 
+    // Longest value captured as a variable (longer ones stay in the template)
+    let mut max_var_len = DEFAULT_MAX_VAR_LEN;
+    if let Some(pos) = args.iter().position(|arg| arg == "--max-var-len") {
+        if pos + 1 < args.len() {
+            match parse_size(&args[pos+1]) {
+                Some(s) if s > 0 => max_var_len = s,
+                _ => {
+                    eprintln!("[!]  Error: Invalid --max-var-len '{}'. Use a size (e.g. 1MB or 64KB).", args[pos+1]);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Solid-mode guard threshold (0 disables the check)
+    let mut solid_limit: Option<u64> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--solid-limit") {
+        if pos + 1 < args.len() {
+            match parse_size(&args[pos+1]) {
+                Some(s) => solid_limit = Some(s as u64),
+                None => {
+                    eprintln!("[!]  Error: Invalid solid limit format.");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
     // Dict Size parsing
     let mut dict_size_bytes: Option<u32> = None;
     if let Some(pos) = args.iter().position(|arg| arg == "--dict-size") {
@@ -65,6 +295,192 @@ fn main() {
         }
     }
 
+    // Memory ceiling ("auto" = currently available memory)
+    let mut memory_budget: Option<MemoryBudget> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--memory-limit") {
+        if pos + 1 < args.len() {
+            let val = &args[pos+1];
+            memory_budget = if val.eq_ignore_ascii_case("auto") {
+                Some(MemoryBudget::detect())
+            } else {
+                parse_size(val).filter(|&s| s > 0).map(|s| MemoryBudget::new(s as u64))
+            };
+            if memory_budget.is_none() {
+                eprintln!("[!]  Error: Invalid memory limit format.");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Fate of the partial output when the run is cancelled (Ctrl+C)
+    let mut on_cancel = CancelPolicy::Delete;
+    if let Some(pos) = args.iter().position(|arg| arg == "--on-cancel") {
+        if pos + 1 < args.len() {
+            on_cancel = CancelPolicy::parse(&args[pos+1]).unwrap_or_else(|| {
+                eprintln!("[!]  Error: Invalid --on-cancel value (use 'delete' or 'keep').");
+                std::process::exit(1);
+            });
+        }
+    }
+    let cancel = CancellationToken::new();
+
+    // Shared read+write budget for background jobs (--io-limit 50MB/s)
+    let mut io_limit: Option<IoLimit> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--io-limit") {
+        if pos + 1 < args.len() {
+            let val = &args[pos+1];
+            match parse_size(val.strip_suffix("/s").unwrap_or(val)).filter(|&s| s > 0) {
+                Some(rate) => io_limit = Some(IoLimit::new(rate as u64)),
+                None => {
+                    eprintln!("[!]  Error: Invalid --io-limit '{}'. Use a rate such as 50MB/s.", val);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Explicit liblzma memory limit for decoding ("auto" = currently available memory)
+    let mut decode_mem_limit: Option<u64> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--mem-limit-decompress") {
+        if pos + 1 < args.len() {
+            let val = &args[pos+1];
+            decode_mem_limit = if val.eq_ignore_ascii_case("auto") {
+                Some(available_memory().unwrap_or(FALLBACK_AVAILABLE_MEMORY))
+            } else {
+                parse_size(val).filter(|&s| s > 0).map(|s| s as u64)
+            };
+            if decode_mem_limit.is_none() {
+                eprintln!("[!]  Error: Invalid --mem-limit-decompress '{}'. Use a size such as 512MB, or 'auto'.", val);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Attributes of restored files (--chmod, --chown, --preserve-permissions, --readonly, --hidden)
+    let mut attributes = OutputAttributes {
+        preserve_permissions: args.iter().any(|arg| arg == "--preserve-permissions"),
+        readonly: args.iter().any(|arg| arg == "--readonly"),
+        hidden: args.iter().any(|arg| arg == "--hidden"),
+        ..OutputAttributes::default()
+    };
+    if let Some(pos) = args.iter().position(|arg| arg == "--chmod") {
+        if pos + 1 < args.len() {
+            let val = &args[pos+1];
+            attributes.mode = parse_mode(val);
+            if attributes.mode.is_none() {
+                eprintln!("[!]  Error: Invalid --chmod '{}'. Use octal permission bits such as 640 or 0644.", val);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--chown") {
+        if pos + 1 < args.len() {
+            match parse_owner(&args[pos+1]) {
+                Ok(owner) => attributes.owner = Some(owner),
+                Err(e) => {
+                    eprintln!("[!]  Error: Invalid --chown '{}': {}. Use user, user:group or :group (names or numeric ids).", args[pos+1], e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if !cfg!(unix) && (attributes.mode.is_some() || attributes.owner.is_some() || attributes.preserve_permissions) {
+        eprintln!("[!]  Error: --chmod, --chown and --preserve-permissions need Unix permissions: use --readonly or --hidden instead.");
+        std::process::exit(1);
+    }
+    if !cfg!(windows) && attributes.hidden {
+        eprintln!("[!]  Error: --hidden sets the Windows hidden attribute: there is none on this platform.");
+        std::process::exit(1);
+    }
+
+    // Header row of delimited inputs, recorded as column names (--header yes|no|auto)
+    let mut header_row = HeaderRow::Auto;
+    if let Some(pos) = args.iter().position(|arg| arg == "--header") {
+        if pos + 1 < args.len() {
+            header_row = HeaderRow::parse(&args[pos+1]).unwrap_or_else(|| {
+                eprintln!("[!]  Error: Invalid --header value (use 'yes', 'no' or 'auto').");
+                std::process::exit(1);
+            });
+        }
+    }
+
+    // Keep reading an input that is still being written (--follow) until it is idle this long
+    let follow = args.iter().any(|arg| arg == "--follow");
+    let mut idle_timeout = FOLLOW_DEFAULT_IDLE;
+    if let Some(pos) = args.iter().position(|arg| arg == "--idle-timeout") {
+        if pos + 1 < args.len() {
+            idle_timeout = parse_duration(&args[pos+1]).unwrap_or_else(|| {
+                eprintln!("[!]  Error: Invalid --idle-timeout value (e.g. 30s, 5m, 500ms).");
+                std::process::exit(1);
+            });
+        }
+    }
+
+    // --max-time: compression budget, started now (a batch shares it)
+    let mut max_time: Option<Duration> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--max-time") {
+        if pos + 1 < args.len() {
+            max_time = Some(parse_duration(&args[pos+1]).filter(|d| !d.is_zero()).unwrap_or_else(|| {
+                eprintln!("[!]  Error: Invalid --max-time value (e.g. 30m, 2h, 90s).");
+                std::process::exit(1);
+            }));
+        }
+    }
+
+    // --hmac-key-file: authenticates new archives, or checks the MACs of an existing one. Only
+    // the file name is ever shown: the key itself is never printed, logged or stored
+    let mut hmac_key: Option<(String, MacKey)> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--hmac-key-file") {
+        if pos + 1 < args.len() {
+            let path = args[pos+1].clone();
+            match MacKey::load(&path) {
+                Ok(key) => hmac_key = Some((path, key)),
+                Err(e) => {
+                    eprintln!("[!]  Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Compression applied to the restored data on its way to the output file
+    let mut output_encoding = OutputEncoding::None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--output-encoding") {
+        if pos + 1 < args.len() {
+            output_encoding = OutputEncoding::parse(&args[pos+1]).unwrap_or_else(|| {
+                eprintln!("[!]  Error: Invalid --output-encoding value (use 'gzip', 'zstd' or 'none').");
+                std::process::exit(1);
+            });
+        }
+    }
+
+    // Restore into a directory of parts instead of one file
+    let mut shards: Option<ShardCount> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--shards") {
+        if pos + 1 < args.len() {
+            shards = ShardCount::parse(&args[pos+1]);
+            if shards.is_none() {
+                eprintln!("[!]  Error: Invalid --shards value (use 'auto' or a positive number).");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // One file of a --pack archive (restore), and the number of files to generate
+    let packed_file: Option<String> = args.iter().position(|arg| arg == "--file").and_then(|pos| args.get(pos + 1)).cloned();
+    let mut generate_files: Option<usize> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--files") {
+        if pos + 1 < args.len() {
+            match args[pos+1].parse::<usize>() {
+                Ok(n) if n > 0 => generate_files = Some(n),
+                _ => {
+                    eprintln!("[!]  Error: Invalid --files value (use a positive number).");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
     // Mode Parsing (Native vs 7Zip) - Default is now handled via Option logic below
     let mut mode_arg: Option<String> = None;
     if let Some(pos) = args.iter().position(|arg| arg == "--mode") {
@@ -73,18 +489,168 @@ fn main() {
         }
     }
 
-    // Filter out args
-    let clean_args: Vec<String> = args.iter()
-        .filter(|arg| *arg != "--multithread" && *arg != "-v" && *arg != "--verify"
-                      && *arg != "--chunk-size"
-                      && *arg != "--dict-size"
-                      && *arg != "--mode"
-                      && args.iter().position(|x| x == *arg) != args.iter().position(|x| x == "--chunk-size").map(|p| p+1)
-                      && args.iter().position(|x| x == *arg) != args.iter().position(|x| x == "--dict-size").map(|p| p+1)
-                      && args.iter().position(|x| x == *arg) != args.iter().position(|x| x == "--mode").map(|p| p+1)
-                      && *arg != "-h" && *arg != "--help")
-        .cloned()
-        .collect();
+    // Extra 7-Zip switches, appended after the defaults of the 7-Zip compressor
+    let mut seven_zip_args: Vec<String> = Vec::new();
+    if let Some(pos) = args.iter().position(|arg| arg == "--7z-args") {
+        if pos + 1 < args.len() {
+            match parse_7z_args(&args[pos+1]) {
+                Ok(a) => seven_zip_args = a,
+                Err(e) => {
+                    eprintln!("[!]  Error: Invalid --7z-args: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Archive labels (repeatable, the last value of a key wins)
+    let mut labels: Vec<(String, String)> = Vec::new();
+    for (pos, arg) in args.iter().enumerate() {
+        if arg != "--label" || pos + 1 >= args.len() { continue; }
+        match parse_label(&args[pos+1]) {
+            Ok((key, value)) => set_label(&mut labels, key, value),
+            Err(e) => {
+                eprintln!("[!]  Error: Invalid --label: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Partial restore: 1-based inclusive rows, or 0-based inclusive bytes of the original
+    let mut selection: Option<Selection> = None;
+    for (flag, is_rows) in [("--rows", true), ("--range", false)] {
+        if let Some(pos) = args.iter().position(|arg| arg == flag) {
+            if pos + 1 < args.len() {
+                match (parse_span(&args[pos+1], is_rows), selection) {
+                    (_, Some(_)) => {
+                        eprintln!("[!]  Error: --rows and --range cannot be combined.");
+                        std::process::exit(1);
+                    },
+                    (Some((a, b)), None) => selection = Some(if is_rows { Selection::Rows(a, b) } else { Selection::Bytes(a, b) }),
+                    (None, None) => {
+                        eprintln!("[!]  Error: Invalid {} '{}'. Use A-B or A- ({}).", flag, args[pos+1], if is_rows { "rows from 1" } else { "byte offsets from 0" });
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+    }
+
+    // Seed of the synthetic datasets (generate)
+    let mut seed = DEFAULT_SEED;
+    if let Some(pos) = args.iter().position(|arg| arg == "--seed") {
+        if pos + 1 < args.len() {
+            match args[pos+1].parse::<u64>() {
+                Ok(s) => seed = s,
+                Err(_) => {
+                    eprintln!("[!]  Error: Invalid seed '{}'. Use a non-negative integer.", args[pos+1]);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Checksum parsing (CRC32 by default, 'none' skips hashing on the hot path)
+    let mut checksum = ChecksumKind::Crc32;
+    if let Some(pos) = args.iter().position(|arg| arg == "--checksum") {
+        if pos + 1 < args.len() {
+            match ChecksumKind::parse(&args[pos+1]) {
+                Some(c) => checksum = c,
+                None => {
+                    eprintln!("[!]  Error: Invalid checksum '{}'. Use 'crc32' or 'none'.", args[pos+1]);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Output limit parsing (plain number = rows, size with unit = bytes)
+    let mut output_limit: Option<OutputLimit> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--limit") {
+        if pos + 1 < args.len() {
+            output_limit = parse_limit(&args[pos+1]);
+            if output_limit.is_none() {
+                eprintln!("[!]  Error: Invalid limit. Use a row count (e.g., 1000) or a size (e.g., 64KB).");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Context lines of grep (-A/-B take precedence over -C, as in grep)
+    let mut context_lines: [Option<usize>; 3] = [None; 3];
+    for (slot, flag) in ["-A", "-B", "-C"].iter().enumerate() {
+        if let Some(pos) = args.iter().position(|arg| arg == flag) {
+            if pos + 1 < args.len() {
+                match args[pos+1].parse::<usize>() {
+                    Ok(n) => context_lines[slot] = Some(n),
+                    Err(_) => {
+                        eprintln!("[!]  Error: Invalid {} '{}'. Use a line count.", flag, args[pos+1]);
+                        std::process::exit(2);
+                    }
+                }
+            }
+        }
+    }
+
+    // Sampled verification of an existing archive (--verify-sample 5% or 25)
+    let mut verify_sample: Option<SampleSize> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--verify-sample") {
+        if pos + 1 < args.len() {
+            verify_sample = SampleSize::parse(&args[pos+1]);
+            if verify_sample.is_none() {
+                eprintln!("[!]  Error: Invalid --verify-sample '{}'. Use a percentage (5%) or a chunk count.", args[pos+1]);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Order and filters of --list-templates
+    let mut template_sort = TemplateSort::Rows;
+    if let Some(pos) = args.iter().position(|arg| arg == "--sort") {
+        if pos + 1 < args.len() {
+            template_sort = TemplateSort::parse(&args[pos+1]).unwrap_or_else(|| {
+                eprintln!("[!]  Error: Invalid --sort '{}'. Use rows, size or id.", args[pos+1]);
+                std::process::exit(1);
+            });
+        }
+    }
+    let count_arg = |name: &str| -> Option<u64> {
+        let pos = args.iter().position(|arg| arg == name)?;
+        let value = args.get(pos + 1)?;
+        Some(value.parse::<u64>().unwrap_or_else(|_| {
+            eprintln!("[!]  Error: Invalid {} '{}'. Use a count.", name, value);
+            std::process::exit(1);
+        }))
+    };
+    let min_rows = count_arg("--min-rows").unwrap_or(0);
+    let top = count_arg("--top");
+    // --jobs: files of a batch compressed side by side
+    let jobs_requested = count_arg("--jobs").map(|n| usize::try_from(n).unwrap_or(usize::MAX));
+    if jobs_requested == Some(0) {
+        eprintln!("[!]  Error: Invalid --jobs '0'. Use a count of 1 or more.");
+        std::process::exit(1);
+    }
+    if jobs_requested.is_some() && !batch {
+        eprintln!("[!]  Error: --jobs applies to -c --batch.");
+        std::process::exit(1);
+    }
+
+    // Chunks shown by --dump-format
+    let mut max_chunks = DUMP_DEFAULT_CHUNKS;
+    if let Some(pos) = args.iter().position(|arg| arg == "--max-chunks") {
+        if pos + 1 < args.len() {
+            match args[pos+1].parse::<usize>() {
+                Ok(n) => max_chunks = n,
+                Err(_) => {
+                    eprintln!("[!]  Error: Invalid --max-chunks '{}'. Use a chunk count.", args[pos+1]);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    // Filter out args (flags and the values they consume)
+    let clean_args = positional_args(&args);
 
     if clean_args.len() < 2 {
         print_usage(exe_name);
@@ -93,11 +659,110 @@ fn main() {
 
     let mode_or_file = &clean_args[1];
 
-    println!("\n\n|--    CAST: Columnar Agnostic Structural Transformation (v{})    --|", env!("CARGO_PKG_VERSION"));
-    println!("       Author: Andrea Olivari");
-    println!("       GitHub: https://github.com/AndreaLVR/CAST\n");
+    // Decompressing to stdout ('-'): keep stdout clean for the data
+    let to_stdout = mode_or_file == "-d" && clean_args.get(3).is_some_and(|o| o == "-");
+
+    if to_stdout && json_output() {
+        eprintln!("[!]  Error: --json cannot be used when writing the restored data to stdout.");
+        std::process::exit(1);
+    }
+
+    // grep and the template listings write their results to stdout: no banner either
+    if !to_stdout && mode_or_file != "grep" && mode_or_file != "templates" && !list_templates && !json_output() {
+        println!("\n\n|--    CAST: Columnar Agnostic Structural Transformation (v{})    --|", env!("CARGO_PKG_VERSION"));
+        println!("       Author: Andrea Olivari");
+        println!("       GitHub: https://github.com/AndreaLVR/CAST\n");
+    }
+
+    if mode_or_file == "bench-self" {
+        if json_output() {
+            eprintln!("[!]  Error: --json is not supported by bench-self.");
+            std::process::exit(1);
+        }
+        let size = match clean_args.get(2) {
+            Some(s) => parse_size(s).unwrap_or_else(|| {
+                eprintln!("[!]  Error: Invalid size format: '{}'.", s);
+                std::process::exit(1);
+            }),
+            None => BENCH_SELF_DEFAULT_SIZE,
+        };
+        do_bench_self(size);
+        return;
+    }
+
+    if mode_or_file == "info" {
+        if json_output() {
+            eprintln!("[!]  Error: --json is not supported by info.");
+            std::process::exit(1);
+        }
+        match clean_args.get(2) {
+            Some(path) => do_info(path, args.iter().any(|arg| arg == "--detailed")),
+            None => {
+                eprintln!("[!]  Missing archive path.");
+                print_usage(exe_name);
+            }
+        }
+        return;
+    }
+
+    if mode_or_file == "generate" {
+        if json_output() {
+            eprintln!("[!]  Error: --json is not supported by generate.");
+            std::process::exit(1);
+        }
+        if clean_args.len() < 5 {
+            eprintln!("[!]  Usage: generate <profile> <size> <out> [--seed N] [--files N]");
+            eprintln!("       Profiles: {}", Profile::ALL.iter().map(|p| p.name()).collect::<Vec<_>>().join(", "));
+            std::process::exit(1);
+        }
+        let Some(profile) = Profile::parse(&clean_args[2]) else {
+            eprintln!("[!]  Error: Unknown profile '{}'. Use one of: {}.", clean_args[2], Profile::ALL.iter().map(|p| p.name()).collect::<Vec<_>>().join(", "));
+            std::process::exit(1);
+        };
+        let Some(size) = parse_size(&clean_args[3]) else {
+            eprintln!("[!]  Error: Invalid size format: '{}'.", clean_args[3]);
+            std::process::exit(1);
+        };
+        cancel_on_interrupt(&cancel);
+        match generate_files {
+            Some(count) => do_generate_files(profile, size as u64, seed, &clean_args[4], count, &cancel, on_cancel),
+            None => do_generate(profile, size as u64, seed, &clean_args[4], &cancel, on_cancel),
+        }
+        return;
+    }
+
+    if mode_or_file == "relabel" {
+        if json_output() {
+            eprintln!("[!]  Error: --json is not supported by relabel.");
+            std::process::exit(1);
+        }
+        match clean_args.get(2) {
+            Some(path) if !labels.is_empty() => {
+                cancel_on_interrupt(&cancel);
+                do_relabel(path, &labels, &cancel)
+            },
+            Some(_) => eprintln!("[!]  Nothing to do: pass at least one --label key=value."),
+            None => {
+                eprintln!("[!]  Missing archive path.");
+                print_usage(exe_name);
+            }
+        }
+        return;
+    }
+
+    if mode_or_file == "crc" {
+        match clean_args.get(2) {
+            Some(path) => do_crc(path, chunk_size_bytes.or(memory_budget.map(|b| b.chunk_size()))),
+            None => {
+                eprintln!("[!]  Missing input path.");
+                print_usage(exe_name);
+            }
+        }
+        return;
+    }
 
     // DETERMINE BACKEND LOGIC
+    let compressing = mode_or_file == "-c" || convert;
     let (use_7zip, backend_label) = match mode_arg.as_deref() {
         Some("native") => {
             (false, "Native (xz2)".to_string())
@@ -118,9 +783,9 @@ fn main() {
             }
         },
         _ => {
-            if mode_or_file == "-c" {
+            if compressing {
                 if let Some(path) = try_find_7zip_path() {
-                    println!("[*]  Auto-detected 7-Zip at: {}", path);
+                    say!("[*]  Auto-detected 7-Zip at: {}", path);
                     (true, format!("7-Zip (External) [Found at: {}]", path))
                 } else {
                     (false, "Native (xz2) [Fallback]".to_string())
@@ -131,16 +796,235 @@ fn main() {
         }
     };
 
+    let decompress_opts = DecompressOptions { use_7zip, budget: memory_budget, mem_limit: decode_mem_limit, cancel: cancel.clone(), on_cancel, io_limit: io_limit.clone(), locked_reads, attributes };
+    if decode_mem_limit.is_some() && use_7zip && !compressing {
+        eprintln!("[!]  Note: --mem-limit-decompress only applies to the native decoder and is ignored by 7-Zip.");
+    }
+
+    // --jobs: the files compressed side by side share the CPUs (each job gets its part of the
+    // encoder threads, at least one) and the memory budget (which may allow fewer jobs)
+    let cpus = num_cpus::get();
+    let jobs = match (jobs_requested, memory_budget) {
+        (Some(requested), Some(budget)) if budget.compress_jobs(requested) < requested => {
+            let jobs = budget.compress_jobs(requested);
+            eprintln!("[!]  Note: --jobs {} reduced to {} by the memory limit ({}).", requested, jobs, format_bytes(budget.total() as usize));
+            jobs
+        },
+        (Some(requested), _) => requested,
+        (None, _) => 1,
+    };
+    let compress_budget = memory_budget.map(|b| b.per_job(jobs));
+
+    // Explicit sizes win over the allotments of the memory budget
+    let chunk_bytes_limit = chunk_size_bytes.or(compress_budget.map(|b| b.chunk_size()));
+    // Settings of the compression modes (-c, --convert)
+    let compress_opts = CompressOptions {
+        multithread: use_multithread,
+        force_multithread,
+        chunk_bytes_limit,
+        dict_size: dict_size_bytes
+            .or(compress_budget.zip(chunk_bytes_limit).map(|(b, chunk)| b.dict_size(chunk)))
+            .unwrap_or(128 * 1024 * 1024),
+        budget: compress_budget,
+        max_threads: (jobs > 1).then(|| (cpus / jobs).max(1) as u32),
+        cancel: cancel.clone(),
+        on_cancel,
+        use_7zip,
+        checksum,
+        seven_zip_args,
+        labels,
+        write_index,
+        column_codecs,
+        front_coding,
+        column_dicts,
+        dedup_chunks,
+        order_checksum,
+        max_var_len,
+        manifest: manifest_path.clone(),
+        native_fallback,
+        follow: follow.then(|| (idle_timeout, CancellationToken::new())),
+        allow_truncation,
+        deadline: max_time.map(Deadline::new),
+        hmac_key: hmac_key.as_ref().map(|(_, key)| key.clone()),
+        pack: None,
+        decode_input: false,
+        header_row,
+        io_limit: io_limit.clone(),
+    };
+
     match mode_or_file.as_str() {
-        "-c" => {
-            if clean_args.len() < 4 {
-                eprintln!("[!]  Missing output path.");
-                print_usage(exe_name);
-                return;
+        _ if convert => {
+            // cast --convert <file|dir>... --into <dir>: .gz/.xz logs (and plain ones) to CAST
+            let Some(out_dir) = into_dir.as_deref() else {
+                eprintln!("[!]  Error: --convert needs --into <dir> (where the archives are written).");
+                std::process::exit(1);
+            };
+            if batch || pack || follow || verify_sample.is_some() {
+                eprintln!("[!]  Error: --convert cannot be combined with --batch, --pack, --follow or --verify-sample.");
+                std::process::exit(1);
+            }
+            // Decoded inputs cannot be read back (--dedup-chunks) and have no size to plan
+            // with up front (--max-time); --manifest describes one archive
+            if dedup_chunks || max_time.is_some() || compress_opts.manifest.is_some() {
+                eprintln!("[!]  Error: --convert cannot be combined with --dedup-chunks, --max-time or --manifest.");
+                std::process::exit(1);
+            }
+            if let Err(e) = ArchiveHeader::new(checksum).with_labels(compress_opts.labels.clone()) {
+                eprintln!("[!]  Error: Invalid --label: {}", e);
+                std::process::exit(1);
+            }
+            let opts = CompressOptions { decode_input: true, ..compress_opts };
+            say!("\n[*]  Starting Conversion...");
+            say!("       Output Dir:  {}", out_dir);
+            say!("       Backend:     {}", backend_label);
+            say!("       Chunk Size:  {}", opts.chunk_bytes_limit.map_or("Solid Mode".to_string(), format_bytes));
+            if delete_source { say!("       Sources:     deleted once their archive is verified"); }
+            cancel_on_interrupt(&cancel);
+            let failed = do_convert(&clean_args[1..], out_dir, &opts, &decompress_opts, delete_source, solid_limit, assume_yes);
+            if cancel.is_cancelled() { std::process::exit(EXIT_CANCELLED); }
+            if failed > 0 && !ignore_errors { std::process::exit(1); }
+        },
+        "grep" => {
+            if json_output() {
+                eprintln!("[!]  Error: --json is not supported by grep.");
+                std::process::exit(2);
+            }
+            if clean_args.len() < 4 {
+                eprintln!("[!]  Usage: grep <pattern> <archive> [-n] [-i] [-A N] [-B N] [-C N]");
+                std::process::exit(2);
+            }
+            let pattern = match RegexBuilder::new(&clean_args[2]).case_insensitive(args.iter().any(|arg| arg == "-i")).multi_line(true).build() {
+                Ok(p) => p,
+                Err(e) => {
+                    eprintln!("[!]  Error: Invalid pattern: {}", e);
+                    std::process::exit(2);
+                }
+            };
+            let opts = GrepOptions {
+                line_numbers: args.iter().any(|arg| arg == "-n"),
+                before: context_lines[1].or(context_lines[2]).unwrap_or(0),
+                after: context_lines[0].or(context_lines[2]).unwrap_or(0),
+            };
+            if !Path::new(&clean_args[3]).exists() {
+                eprintln!("[!]  Error: Archive '{}' not found.", clean_args[3]);
+                std::process::exit(2);
+            }
+            // Exit status as in grep: 0 = lines selected, 1 = none, 2 = error
+            match do_grep(&clean_args[3], &pattern, &opts, &decompress_opts) {
+                Ok(true) => {},
+                Ok(false) => std::process::exit(1),
+                // The reader went away (e.g. piped into head)
+                Err(CastError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {},
+                Err(e) => {
+                    eprintln!("[!]  Error: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        },
+        "templates" => {
+            // cast templates <archive>: the archive's templates, as a template file
+            if json_output() {
+                eprintln!("[!]  Error: --json is not supported by templates.");
+                std::process::exit(1);
+            }
+            let Some(path) = clean_args.get(2) else {
+                eprintln!("[!]  Missing archive path.");
+                print_usage(exe_name);
+                std::process::exit(1);
+            };
+            match do_templates(path, &decompress_opts) {
+                Ok(()) => {},
+                Err(CastError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {},
+                Err(e) => {
+                    eprintln!("[!]  Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        },
+        "-c" => {
+            if clean_args.len() < 4 {
+                eprintln!("[!]  Missing output path.");
+                print_usage(exe_name);
+                return;
+            }
+            if verify_sample.is_some() {
+                eprintln!("[!]  Error: --verify-sample checks an existing archive: {} -v <archive> --verify-sample <N|P%>.", exe_name);
+                std::process::exit(1);
             }
             let input = &clean_args[2];
             let output = &clean_args[3];
 
+            if !compress_opts.seven_zip_args.is_empty() && !use_7zip {
+                eprintln!("[!]  Note: --7z-args only applies to the 7-Zip backend and is ignored.");
+            }
+
+            if pack && batch {
+                eprintln!("[!]  Error: --pack and --batch cannot be combined (--pack writes one archive, --batch one per file).");
+                std::process::exit(1);
+            }
+            // --pack: the files of a list or directory, compressed as one stream
+            let pack_inputs = pack.then(|| match collect_batch_inputs(input) {
+                Ok(files) if !files.is_empty() => files,
+                Ok(_) => {
+                    eprintln!("[!]  Error: '{}' holds no files to pack.", input);
+                    std::process::exit(1);
+                },
+                Err(e) => {
+                    eprintln!("[!]  Error: Cannot read pack source '{}': {}", input, e);
+                    std::process::exit(1);
+                }
+            });
+
+            let opts = CompressOptions {
+                // Extracting one packed file seeks with the sidecar index
+                write_index: write_index || pack,
+                pack: pack_inputs,
+                ..compress_opts
+            };
+            if let Err(e) = ArchiveHeader::new(checksum).with_labels(opts.labels.clone()) {
+                eprintln!("[!]  Error: Invalid --label: {}", e);
+                std::process::exit(1);
+            }
+
+            if follow && (batch || pack || opts.chunk_bytes_limit.is_none()) {
+                eprintln!("[!]  Error: --follow needs --chunk-size (or --memory-limit) and a single input file.");
+                std::process::exit(1);
+            }
+
+            if max_time.is_some() && (follow || opts.chunk_bytes_limit.is_none()) {
+                eprintln!("[!]  Error: --max-time decides chunk by chunk: it needs --chunk-size (or --memory-limit) and cannot be combined with --follow.");
+                std::process::exit(1);
+            }
+
+            if pack && dedup_chunks {
+                eprintln!("[!]  Error: --dedup-chunks reads matches back from a single input file: it cannot be combined with --pack.");
+                std::process::exit(1);
+            }
+            if batch && opts.manifest.is_some() {
+                eprintln!("[!]  Error: --manifest describes one archive: it cannot be combined with --batch.");
+                std::process::exit(1);
+            }
+            // --max-time plans the files of a batch one after the other
+            if jobs > 1 && max_time.is_some() {
+                eprintln!("[!]  Error: --max-time cannot be combined with --jobs above 1.");
+                std::process::exit(1);
+            }
+            if batch {
+                say!("\n[*]  Starting Batch Compression...");
+                say!("       Source:      {}", input);
+                say!("       Output Dir:  {}", output);
+                say!("       Backend:     {}", backend_label);
+                if jobs > 1 {
+                    say!("       Jobs:        {} file(s) at a time, up to {} encoder thread(s) each", jobs, opts.encoder_threads().unwrap_or(cpus as u32));
+                }
+                if verify_flag { eprintln!("[!]  Note: -v is not applied in batch mode."); }
+                cancel_on_interrupt(&cancel);
+                let skipped = do_compress_batch(input, output, &opts, jobs, solid_limit, assume_yes);
+                if cancel.is_cancelled() { std::process::exit(EXIT_CANCELLED); }
+                if skipped > 0 && !ignore_errors { std::process::exit(1); }
+                return;
+            }
+
             if !Path::new(input).exists() {
                  eprintln!("[!]  Error: Input file '{}' not found.", input);
                  std::process::exit(1);
@@ -154,22 +1038,77 @@ fn main() {
                 "SOLID (SINGLE THREAD)"
             };
 
-            println!("\n[*]  Starting Compression...");
-            println!("       Input:       {}", input);
-            println!("       Output:      {}", output);
-            println!("       Backend:     {}", backend_label);
-            println!("       Mode:        {}", mode_display);
+            let input_len = match &opts.pack {
+                Some(files) => files.iter().map(|f| file_len(f)).sum(),
+                None => file_len(input),
+            };
+
+            say!("\n[*]  Starting Compression...");
+            match &opts.pack {
+                Some(files) => say!("       Input:       {} ({} files packed, {})", input, files.len(), format_bytes(input_len as usize)),
+                None => say!("       Input:       {}", input),
+            }
+            say!("       Output:      {}", output);
+            say!("       Backend:     {}", backend_label);
+            say!("       Mode:        {}", mode_display);
+
+            // Decided per stream by the native encoder; the largest stream is at most a chunk (a
+            // growing input may fill every chunk)
+            let stream_len = match opts.chunk_bytes_limit {
+                Some(c) if follow => (c + line_align_overshoot(c)) as u64,
+                Some(c) => input_len.min((c + line_align_overshoot(c)) as u64),
+                None => input_len,
+            };
+            let dict_size = if use_7zip { opts.dict_size } else { stream_dict_size(stream_len, opts.dict_size) };
+            if dict_size < opts.dict_size {
+                say!("       Dict Size:   {} (--dict-size {}, lowered to the input)", format_bytes(dict_size as usize), format_bytes(opts.dict_size as usize));
+            } else {
+                say!("       Dict Size:   {}", format_bytes(dict_size as usize));
+            }
+            if use_multithread && !use_7zip {
+                if let Some(note) = opts.multithread_note(stream_len) { say!("       Threads:     {}", note); }
+            }
+            if let Some(budget) = opts.budget {
+                say!("       Memory:      {} (Chunk Size: {})", format_bytes(budget.total() as usize), format_bytes(opts.chunk_bytes_limit.unwrap_or(0)));
+            }
+            say!("       Checksum:    {}", checksum.label());
+            if follow {
+                say!("       Follow:      until idle for {:.1}s or Ctrl+C", idle_timeout.as_secs_f64());
+            }
+            if let Some(max_time) = max_time {
+                say!("       Max Time:    {:.1}s (later chunks switch to xz preset {} if needed)", max_time.as_secs_f64(), DEADLINE_FAST_PRESET);
+            }
+            if let Some((path, _)) = &hmac_key {
+                say!("       HMAC:        SHA-256, key from {}", path);
+            }
+            if verbose && use_7zip {
+                say!("       7z Command:  {}", opts.seven_zip_backend().command_line());
+            }
 
-            let final_dict = dict_size_bytes.unwrap_or(128 * 1024 * 1024);
-            println!("       Dict Size:   {}", format_bytes(final_dict as usize));
+            if opts.chunk_bytes_limit.is_none() && !confirm_solid_mode(input_len, solid_limit, assume_yes) {
+                emit_error("compress", "Solid mode not confirmed");
+                std::process::exit(1);
+            }
 
-            do_compress(input, output, use_multithread, chunk_size_bytes, final_dict, use_7zip);
+            // While following, Ctrl+C ends the input instead of cancelling: the archive is finished
+            match &opts.follow {
+                Some((_, stop)) => cancel_on_interrupt(stop),
+                None => cancel_on_interrupt(&cancel),
+            }
+            // A failed run removes the archive it created itself (see `CreatedOutput`)
+            if let Err(e) = do_compress(input, output, &opts) {
+                if cancel.is_cancelled() { exit_cancelled("compress", cancel_outcome(output, on_cancel)); }
+                eprintln!("\n[!]  Error: {}", e);
+                emit_error("compress", &e);
+                std::process::exit(1);
+            }
 
             if verify_flag {
-                println!("\n------------------------------------------------");
-                println!("[*]  Starting Post-Compression Verification...");
-                std::thread::sleep(std::time::Duration::from_millis(500));
-                do_verify_standalone(output, use_7zip);
+                say!("\n------------------------------------------------");
+                say!("[*]  Starting Post-Compression Verification...");
+                if !json_output() { std::thread::sleep(std::time::Duration::from_millis(500)); }
+                let reference = verify_against.as_deref().or(if debug_verify { Some(input.as_str()) } else { None });
+                do_verify_standalone(output, &decompress_opts, debug_verify, reference, strict_verify, hmac_key.as_ref().map(|(_, key)| key));
             }
         },
         "-d" => {
@@ -178,9 +1117,199 @@ fn main() {
                 print_usage(exe_name);
                 return;
             }
-            println!("\n[*]  Starting Decompression...");
-            println!("      Backend:     {}", backend_label);
-            do_decompress(&clean_args[2], &clean_args[3], use_7zip);
+            if !to_stdout {
+                say!("\n[*]  Starting Decompression...");
+                say!("      Backend:     {}", backend_label);
+                match output_limit {
+                    Some(OutputLimit::Rows(n)) => say!("      Limit:       {} rows", n),
+                    Some(OutputLimit::Bytes(n)) => say!("      Limit:       {}", format_bytes(n as usize)),
+                    None => {},
+                }
+                if let Some(budget) = memory_budget {
+                    say!("      Memory:      {}", format_bytes(budget.total() as usize));
+                }
+            }
+            if mmap_out && to_stdout {
+                eprintln!("[!]  Error: --mmap-out cannot be used when writing to stdout.");
+                std::process::exit(1);
+            }
+            if !attributes.is_empty() && to_stdout {
+                eprintln!("[!]  Error: --chmod, --chown, --preserve-permissions, --readonly and --hidden apply to output files, not to stdout.");
+                std::process::exit(1);
+            }
+            if sparse_out && (to_stdout || mmap_out) {
+                eprintln!("[!]  Error: --sparse needs a regular output file (not stdout, not --mmap-out).");
+                std::process::exit(1);
+            }
+            // Mapped pages and sparse holes reach the disk without going through writes
+            if io_limit.is_some() && (mmap_out || sparse_out) {
+                eprintln!("[!]  Error: --io-limit cannot be combined with --mmap-out or --sparse.");
+                std::process::exit(1);
+            }
+            if output_encoding != OutputEncoding::None {
+                if mmap_out || sparse_out {
+                    eprintln!("[!]  Error: --output-encoding cannot be combined with --mmap-out or --sparse.");
+                    std::process::exit(1);
+                }
+                // An encoded stream cannot be cut back to a chunk boundary
+                if on_cancel == CancelPolicy::Keep {
+                    eprintln!("[!]  Error: --on-cancel keep cannot be combined with --output-encoding.");
+                    std::process::exit(1);
+                }
+                if !to_stdout { say!("      Encoding:    {}", output_encoding.label()); }
+            }
+            // --verbose: the settings of every chunk, ahead of the restore (on stderr when the data goes to stdout)
+            if verbose {
+                let settings = decompress_opts.open(&clean_args[2]).map_err(CastError::from).and_then(read_block_settings).unwrap_or_else(|e| {
+                    eprintln!("[!]  Error: {}", e);
+                    emit_error("decompress", &e.to_string());
+                    std::process::exit(1);
+                });
+                settings.iter().enumerate().for_each(|(i, block)| emit_block_event(i + 1, block));
+                for (title, text) in block_settings_lines(&settings) {
+                    if to_stdout { eprintln!("      {:<12} {}", title, text); } else { say!("      {:<12} {}", title, text); }
+                }
+            }
+            // --file: one file of a --pack archive, restored as the byte range it occupies
+            let mut selection = selection;
+            if let Some(name) = &packed_file {
+                if selection.is_some() || output_limit.is_some() || shards.is_some() {
+                    eprintln!("[!]  Error: --file cannot be combined with --rows, --range, --limit or --shards.");
+                    std::process::exit(1);
+                }
+                let file = lookup_packed_file(&clean_args[2], name).unwrap_or_else(|e| {
+                    eprintln!("[!]  Error: {}", e);
+                    emit_error("decompress", &e);
+                    std::process::exit(1);
+                });
+                if !to_stdout { say!("      File:        {} ({} at byte {})", file.path, format_bytes(file.len as usize), file.offset); }
+                if file.len == 0 {
+                    // Nothing to decode: an empty file restores to an empty output
+                    let target = if output_encoding != OutputEncoding::None { RestoreTarget::Encoded(output_encoding) } else { RestoreTarget::Stream };
+                    if let Err(e) = RestoreOutput::open(&clean_args[3], target, 0, io_limit.clone()).and_then(|out| out.finish()) {
+                        eprintln!("[!]  Error: Cannot create output: {}", e);
+                        std::process::exit(1);
+                    }
+                    if !to_stdout && !attributes.is_empty() {
+                        let archive = decompress_opts.open(&clean_args[2]).map_err(CastError::from)
+                            .and_then(|f| ArchiveHeader::read_from(&mut io::BufReader::new(f)));
+                        match archive {
+                            Ok(archive) => apply_output_attributes(&[Path::new(&clean_args[3])], &archive, &decompress_opts),
+                            Err(e) => {
+                                eprintln!("[!]  Error: Cannot read the archive header: {}", e);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    if !to_stdout { say!("[+]  Restored 0 bytes (empty file)"); }
+                    return;
+                }
+                selection = Some(Selection::Bytes(file.offset, file.offset + file.len - 1));
+            }
+            let target = if mmap_out {
+                RestoreTarget::Mapped
+            } else if sparse_out {
+                RestoreTarget::Sparse
+            } else if output_encoding != OutputEncoding::None {
+                RestoreTarget::Encoded(output_encoding)
+            } else {
+                RestoreTarget::Stream
+            };
+            if skip_existing {
+                if to_stdout || shards.is_some() || output_limit.is_some() || selection.is_some() || output_encoding != OutputEncoding::None {
+                    eprintln!("[!]  Error: --skip-existing compares a whole restored file: it cannot be combined with stdout, --shards, --limit, --rows, --range, --file or --output-encoding.");
+                    std::process::exit(1);
+                }
+                if output_is_up_to_date(&clean_args[2], &clean_args[3], &decompress_opts, skip_strict) { return; }
+            }
+            if let Some(count) = shards {
+                if to_stdout || target != RestoreTarget::Stream || output_limit.is_some() || selection.is_some() {
+                    eprintln!("[!]  Error: --shards writes a directory of parts: it cannot be combined with stdout, --mmap-out, --sparse, --output-encoding, --limit, --rows or --range.");
+                    std::process::exit(1);
+                }
+                cancel_on_interrupt(&cancel);
+                do_decompress_shards(&clean_args[2], &clean_args[3], &decompress_opts, count);
+                return;
+            }
+            cancel_on_interrupt(&cancel);
+            match selection {
+                Some(_) if output_limit.is_some() => {
+                    eprintln!("[!]  Error: --limit cannot be combined with --rows or --range.");
+                    std::process::exit(1);
+                },
+                Some(sel) => do_decompress_selection(&clean_args[2], &clean_args[3], &decompress_opts, sel, target),
+                None => do_decompress(&clean_args[2], &clean_args[3], &decompress_opts, output_limit, target),
+            }
+        },
+        _ if list_templates => {
+            // cast --list-templates <archive> [--sort rows|size|id] [--min-rows N] [--top N]
+            if !Path::new(mode_or_file).exists() {
+                eprintln!("[!]  Error: File '{}' not found.", mode_or_file);
+                emit_error("list-templates", "File not found");
+                std::process::exit(1);
+            }
+            match do_list_templates(mode_or_file, &decompress_opts, template_sort, min_rows, top) {
+                Ok(()) => {},
+                Err(CastError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {},
+                Err(e) => {
+                    eprintln!("[!]  Error: {}", e);
+                    emit_error("list-templates", &e.to_string());
+                    std::process::exit(1);
+                }
+            }
+        },
+        _ if audit => {
+            // cast --audit <archive> --allowed-templates <file>
+            // Exit status: 0 = every template allowed, 1 = unknown templates or unauditable chunks, 2 = error
+            if json_output() {
+                eprintln!("[!]  Error: --json is not supported by --audit.");
+                std::process::exit(2);
+            }
+            let Some(allowed_path) = allowed_templates.as_deref() else {
+                eprintln!("[!]  Error: --audit needs --allowed-templates <file>.");
+                std::process::exit(2);
+            };
+            if !Path::new(mode_or_file).exists() {
+                eprintln!("[!]  Error: File '{}' not found.", mode_or_file);
+                std::process::exit(2);
+            }
+            let allowed = TemplateSet::load(allowed_path).unwrap_or_else(|e| {
+                eprintln!("[!]  Error: {}", e);
+                std::process::exit(2);
+            });
+            match do_audit(mode_or_file, allowed_path, &allowed, &decompress_opts) {
+                Ok(true) => {},
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("[!]  Error: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        },
+        _ if dump_format => {
+            // cast --dump-format <archive>: annotated walk of the archive structures
+            if json_output() {
+                eprintln!("[!]  Error: --json is not supported by --dump-format.");
+                std::process::exit(1);
+            }
+            if !Path::new(mode_or_file).exists() {
+                eprintln!("[!]  Error: File '{}' not found.", mode_or_file);
+                std::process::exit(1);
+            }
+            do_dump_format(mode_or_file, max_chunks, &decompress_opts);
+        },
+        _ if write_index && !verify_flag => {
+            // cast --index <archive>: (re)build the sidecar of an existing archive
+            if json_output() {
+                eprintln!("[!]  Error: --json is not supported by --index.");
+                std::process::exit(1);
+            }
+            if !Path::new(mode_or_file).exists() {
+                eprintln!("[!]  Error: File '{}' not found.", mode_or_file);
+                std::process::exit(1);
+            }
+            cancel_on_interrupt(&cancel);
+            do_build_index(mode_or_file, &decompress_opts);
         },
         _ => {
             if verify_flag || Path::new(mode_or_file).exists() {
@@ -189,9 +1318,33 @@ fn main() {
                     eprintln!("[!]  Error: File '{}' not found.", input_file);
                     return;
                 }
-                println!("\n[*]  Starting Verification...");
-                println!("       Backend:     {}", backend_label);
-                do_verify_standalone(input_file, use_7zip);
+                // A bare file is only verified when it is a CAST archive: anything else is most
+                // likely a forgotten -c (headerless v1 archives need an explicit -v)
+                if !verify_flag && !has_archive_magic(input_file) {
+                    eprintln!("[!]  '{}' is not a CAST archive, so it was not verified.", input_file);
+                    match clean_args.get(2) {
+                        Some(out) => eprintln!("     Did you mean: {} -c {} {}", exe_name, input_file, out),
+                        None => eprintln!("     Did you mean: {} -c {} <out.cast>  (or -v {} to verify anyway)", exe_name, input_file, input_file),
+                    }
+                    std::process::exit(1);
+                }
+                say!("\n[*]  Starting Verification...");
+                say!("       Backend:     {}", backend_label);
+                // The manifest comparison only reads headers and compressed bodies: a mismatch
+                // stops before anything is decoded
+                if let Some(path) = &manifest_check {
+                    if !do_manifest_check(input_file, path) { std::process::exit(1); }
+                }
+                cancel_on_interrupt(&cancel);
+                if let Some(sample) = verify_sample {
+                    // A sample skips data: nothing to compare byte for byte or line by line
+                    if debug_verify || strict_verify || verify_against.is_some() || hmac_key.is_some() {
+                        eprintln!("[!]  Error: --verify-sample cannot be combined with --debug-verify, --strict-verify, --verify-against or --hmac-key-file.");
+                        std::process::exit(1);
+                    }
+                    return do_verify_sampled(input_file, &decompress_opts, sample);
+                }
+                do_verify_standalone(input_file, &decompress_opts, debug_verify, verify_against.as_deref(), strict_verify, hmac_key.as_ref().map(|(_, key)| key));
             } else {
                 eprintln!("[!]  Unknown command or file not found: {}", mode_or_file);
                 print_usage(exe_name);
@@ -202,6 +1355,33 @@ fn main() {
 
 // --- HELPER PARSING ---
 
+/// The entry of a --pack archive's file table called `name` (see `find_packed_file`).
+fn lookup_packed_file(archive_path: &str, name: &str) -> Result<PackedFile, String> {
+    let mut f = io::BufReader::new(File::open(archive_path).map_err(|e| format!("Cannot open archive: {}", e))?);
+    let archive = ArchiveHeader::read_from(&mut f).map_err(|e| e.to_string())?;
+    let files = read_file_table(archive_path, archive.original_size)?;
+    find_packed_file(&files, name).cloned()
+}
+
+/// Whether `path` starts with the archive magic (version 2 archives).
+fn has_archive_magic(path: &str) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && magic == ARCHIVE_MAGIC
+}
+
+fn positional_args(args: &[String]) -> Vec<String> {
+    let mut clean = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if cli::takes_value(Binary::Cast, arg) { i += 2; continue; }
+        if !cli::is_option(Binary::Cast, arg) { clean.push(args[i].clone()); }
+        i += 1;
+    }
+    clean
+}
+
+#[allow(clippy::is_digit_ascii_radix)]
 fn parse_size(input: &str) -> Option<usize> {
     let input = input.trim().to_uppercase();
     let digits: String = input.chars().take_while(|c| c.is_digit(10)).collect();
@@ -217,14 +1397,52 @@ fn parse_size(input: &str) -> Option<usize> {
     }
 }
 
+/// Parses a duration: a number with an `ms`, `s`, `m` or `h` suffix (seconds without one).
+fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim().to_lowercase();
+    let digits: String = input.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.is_empty() { return None; }
+    let num = digits.parse::<u64>().ok()?;
+    match input[digits.len()..].trim() {
+        "ms" => Some(Duration::from_millis(num)),
+        "s" | "" => Some(Duration::from_secs(num)),
+        "m" => Some(Duration::from_secs(num.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(num.checked_mul(3600)?)),
+        _ => None,
+    }
+}
+
+/// Parses "A-B" or "A-" (open end). Rows are 1-based and converted to 0-based.
+fn parse_span(input: &str, rows: bool) -> Option<(u64, u64)> {
+    let (a, b) = input.trim().split_once('-')?;
+    let a: u64 = a.trim().parse().ok()?;
+    let b: u64 = if b.trim().is_empty() { u64::MAX } else { b.trim().parse().ok()? };
+    if rows && a == 0 { return None; }
+    if b < a { return None; }
+    if rows { Some((a - 1, b - 1)) } else { Some((a, b)) }
+}
+
+fn parse_limit(input: &str) -> Option<OutputLimit> {
+    let input = input.trim();
+    if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        return input.parse::<u64>().ok().map(OutputLimit::Rows);
+    }
+    parse_size(input).map(|n| OutputLimit::Bytes(n as u64))
+}
+
 fn format_bytes(n: usize) -> String {
+    format!("{} bytes", format_count(n as u64))
+}
+
+/// `n` with thousands separators.
+fn format_count(n: u64) -> String {
     let s = n.to_string();
     let mut result = String::new();
     for (i, c) in s.chars().rev().enumerate() {
         if i > 0 && i % 3 == 0 { result.push(','); }
         result.push(c);
     }
-    format!("{} bytes", result.chars().rev().collect::<String>())
+    result.chars().rev().collect()
 }
 
 fn print_usage(exe_name: &str) {
@@ -236,235 +1454,4029 @@ fn print_usage(exe_name: &str) {
           {} [MODE] [INPUT] [OUTPUT] [OPTIONS]\n\n\
         Modes:\n  \
           -c <in> <out>      Compress input file to CAST format\n  \
-          -d <in> <out>      Decompress CAST file to original format\n  \
-          -v <file>          Verify the integrity of a CAST file\n\n\
-        Options:\n  \
-          --mode <TYPE>      Backend selection: 'native' or '7zip'\n                         (Default: 7zip for compression, Native for decompression)\n  \
-          --multithread      Enable parallel compression for higher speed\n  \
-          --chunk-size <S>   Split input in chunks (Compression RAM Saver) (e.g., 512MB). Default: Solid Mode\n  \
-          --dict-size <S>    Set LZMA Dictionary size (Default: 128MB)\n  \
-          -v, --verify       (During compression) Run an immediate integrity check\n  \
-          -h, --help         Show this help message\n\n\
+          -c --batch <list|dir> <out_dir>\n                     Compress each file of a list file or directory into <out_dir>\n                         (--jobs N: N files at a time)\n  \
+          -c --pack <list|dir> <out>\n                     Compress the files of a list file or directory as one stream into one archive\n                         (file table in <out>.files; restore one file with -d --file)\n  \
+          --convert <file|dir>... --into <dir>\n                     Decode .gz/.xz logs (plain files as they are) into CAST archives under <dir>,\n                         mirroring relative paths; each archive is verified (--delete-source)\n  \
+          -d <in> <out>      Decompress CAST file to original format ('-' as <out> writes to stdout)\n  \
+          -v <file>          Verify the integrity of a CAST file\n  \
+          --index <file>     (Re)build the sidecar index (<file>.idx) of an existing CAST file\n  \
+          grep <P> <file>    Print the lines matching the regular expression P, one chunk at a time\n                         (-n line numbers, -i ignore case, -A/-B/-C N context lines)\n  \
+          --dump-format <file>\n                     Print the archive header, chunk headers and block headers of a CAST\n                         file with offsets and raw bytes (--max-chunks N, Default: 16)\n  \
+          templates <file>   Print the templates of a CAST file as a template file (one per line,\n                         <*> for a variable), e.g. to start an --allowed-templates list\n  \
+          --list-templates <file>\n                     List the templates of a CAST file merged over its chunks, with rows, share\n                         of the bytes and chunk count (--sort rows|size|id, --min-rows N, --top N)\n  \
+          --audit <file> --allowed-templates <T>\n                     Check that every template of a CAST file is listed in the template file T\n                         (exact or after whitespace normalization; passthrough chunks always\n                         fail). Exit status: 0 = clean, 1 = violations, 2 = error\n  \
+          crc <file>         Print the CRC32 compression would store for each chunk of a file (same\n                         boundaries with --chunk-size / --memory-limit) and of the whole file\n  \
+          info <file>        Show the format, original size, labels and chunk count of a CAST file\n                         (--detailed: also the layout, backend, dictionary and parse mode of\n                         every chunk, with a histogram of the mix)\n  \
+          relabel <file>     Set the labels given with --label on an existing CAST file\n                         (the compressed data is copied unchanged; 'key=' removes a key)\n  \
+          bench-self [S]     Time the CAST transform alone (no LZMA) on S bytes of generated logs\n                         (Default: 32MB)\n  \
+          generate <P> <S> <out>\n                     Write S bytes of a deterministic synthetic dataset (--seed N, Default: 42)\n                         (--files N: split over N files in the directory <out>)\n                         Profiles: web-access, csv, ndjson, syslog, entropy, uuid\n\n\
+        Options:\n{}\n\
         Examples:\n  \
           {} -c data.csv archive.cast --mode 7zip -v\n  \
           {} -c large_log.log archive.cast --chunk-size 256MB -v\n  \
           {} -d archive.cast restored.log\n  \
-          {} -v archive.cast",
+          {} -d archive.cast - --limit 1000\n  \
+          {} -d archive.cast - --rows 5000-5100\n  \
+          {} -v archive.cast\n  \
+          {} grep -n 'status=5[0-9]{{2}}' archive.cast -C 2\n  \
+          {} info archive.cast\n  \
+          {} relabel archive.cast --label host=web03 --label retention=90d\n  \
+          {} bench-self 64MB\n  \
+          {} generate web-access 100MB access.log --seed 7",
         env!("CARGO_PKG_VERSION"),
-        exe_name, exe_name, exe_name, exe_name, exe_name
+        exe_name, cli::render_options(Binary::Cast), exe_name, exe_name, exe_name, exe_name, exe_name, exe_name, exe_name, exe_name, exe_name, exe_name, exe_name
     );
 }
 
 // --- COMPRESSION ---
 
-fn do_compress(input_path: &str, output_path: &str, multithread: bool, chunk_bytes_limit: Option<usize>, dict_size: u32, use_7zip: bool) {
-    let start_total = Instant::now();
-    let mut f_in = File::open(input_path).expect("Error opening input");
-    let mut f_out = File::create(output_path).expect("Error creating output");
-    let file_len = f_in.metadata().unwrap().len();
-
-    let buffer_size = chunk_bytes_limit.unwrap_or(file_len as usize);
-    let mut buffer = vec![0u8; buffer_size];
-
-    let mut total_read = 0;
-    let mut total_written = 0;
-    let mut chunk_count = 0;
-
-    println!("\n[*]  Starting stream processing...");
+// Solid mode keeps the input, its columns and the LZMA working set in memory at once
+const SOLID_MEMORY_FACTOR: u64 = 6;
+// Inputs below this size never trigger the memory check
+const SOLID_CHECK_MIN: u64 = 256 * 1024 * 1024;
+// The chunk size model (see chunk_model) re-encodes the halves of some chunks: at most one
+// chunk in this many, and only on runs expected to take at least that many chunks
+const PROBE_MIN_CHUNKS: u64 = 8;
+// Chunks probed at most per run, spread over the input
+const MAX_PROBES: u64 = 4;
+/// Chunk size that keeps a chunked run comfortably within `available` bytes (power of two, 64MB..1GB).
+fn suggest_chunk_size(available: u64) -> u64 {
+    let target = (available / (SOLID_MEMORY_FACTOR * 2)).clamp(64 * 1024 * 1024, 1024 * 1024 * 1024);
+    1u64 << (63 - target.leading_zeros())
+}
 
-    loop {
-        let mut current_read = 0;
-        while current_read < buffer_size {
-            let n = f_in.read(&mut buffer[current_read..]).expect("Error reading chunk");
-            if n == 0 { break; }
-            current_read += n;
+/// Compresses the two halves of `chunk` (split at a line break) on their own and records them
+/// as probes of `model`. Halves stored as passthrough are left out.
+fn probe_chunk_halves(chunk: &[u8], opts: &CompressOptions, model: &mut ChunkSizeModel) -> Result<(), CastError> {
+    let mid = memchr::memrchr(b'\n', &chunk[..chunk.len() / 2]).map_or(chunk.len() / 2, |p| p + 1);
+    for half in [&chunk[..mid], &chunk[mid..]] {
+        let encoded = encode_chunk(opts.native_backend(), half, opts.checksum, opts.column_codecs, opts.front_coding, opts.column_dicts, opts.max_var_len)?;
+        if let [(_, header)] = encoded.chunks.as_slice() {
+            if !is_passthrough(header.id_flag) { model.record_probe(half.len() as u64, (CHUNK_HEADER_SIZE + header.body_len()) as u64); }
         }
-        if current_read == 0 { break; }
-
-        chunk_count += 1;
-        let chunk_data = &buffer[0..current_read];
-
-        print!("\r       Processing Chunk #{} ({})... ", chunk_count, format_bytes(chunk_data.len()));
-        io::stdout().flush().unwrap();
-
-        // CRC
-        let mut h = Hasher::new();
-        h.update(chunk_data);
-        let chunk_crc = h.finalize();
-
-        // CAST Compression (Backend Selection)
-        // Wraps the specific backend in the Runtime Enum
-        let backend = if use_7zip {
-            RuntimeLzmaCompressor::SevenZip(SevenZipBackend::new(dict_size))
-        } else {
-            RuntimeLzmaCompressor::Native(LzmaBackend::new(multithread, dict_size))
-        };
+    }
+    Ok(())
+}
 
-        let mut compressor = CASTLzmaCompressor::new(backend);
-        let (c_reg, c_ids, c_vars, id_flag, _) = compressor.compress(chunk_data);
+/// Largest chunk size worth suggesting for a run over `input_len` bytes: what memory allows
+/// (the --memory-limit budget, or the available memory), no larger than the input needs.
+fn advice_target(input_len: u64, budget: Option<MemoryBudget>) -> u64 {
+    let allowed = match budget {
+        Some(budget) => budget.chunk_size() as u64,
+        None => suggest_chunk_size(available_memory().unwrap_or(FALLBACK_AVAILABLE_MEMORY)),
+    };
+    allowed.min(input_len.max(1).next_power_of_two())
+}
 
-        let mut header = Vec::new();
-        header.extend_from_slice(&chunk_crc.to_le_bytes());
-        header.extend_from_slice(&(c_reg.len() as u32).to_le_bytes());
-        header.extend_from_slice(&(c_ids.len() as u32).to_le_bytes());
-        header.extend_from_slice(&(c_vars.len() as u32).to_le_bytes());
-        header.push(id_flag);
+/// Size of the file at `path`, 0 when it cannot be read (the error surfaces when it is opened).
+fn file_len(path: &str) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
 
-        f_out.write_all(&header).unwrap();
-        f_out.write_all(&c_reg).unwrap();
-        f_out.write_all(&c_ids).unwrap();
-        f_out.write_all(&c_vars).unwrap();
+/// Warns before a solid-mode run that is likely to exhaust memory. Returns `false` when the user
+/// declines, or when no confirmation can be asked (no TTY) and `--yes` was not given.
+fn confirm_solid_mode(input_len: u64, solid_limit: Option<u64>, assume_yes: bool) -> bool {
+    if solid_limit == Some(0) || (solid_limit.is_none() && input_len < SOLID_CHECK_MIN) { return true; }
 
-        total_read += current_read;
-        total_written += header.len() + c_reg.len() + c_ids.len() + c_vars.len();
+    let detected = available_memory();
+    let available = detected.unwrap_or(FALLBACK_AVAILABLE_MEMORY);
+    let threshold = solid_limit.unwrap_or(available / SOLID_MEMORY_FACTOR);
+    if input_len <= threshold { return true; }
 
-        if chunk_bytes_limit.is_none() { break; }
+    let suggested = suggest_chunk_size(available);
+    eprintln!("\n[!]  WARNING: {}Solid mode on a large input ({}).", job_tag(), format_bytes(input_len as usize));
+    eprintln!("       Without --chunk-size the whole file is processed in memory (roughly {}x its size).", SOLID_MEMORY_FACTOR);
+    match detected {
+        Some(m) => eprintln!("       Available memory: {}", format_bytes(m as usize)),
+        None => eprintln!("       Available memory: unknown (assuming {})", format_bytes(available as usize)),
     }
+    eprintln!("       Suggested:        --chunk-size {}MB", suggested / (1024 * 1024));
 
-    drop(f_out);
-
-    let ratio = if total_written > 0 { total_read as f64 / total_written as f64 } else { 0.0 };
+    if assume_yes { return true; }
+    if !io::stdin().is_terminal() {
+        eprintln!("[!]  Refusing to continue without confirmation. Pass --yes to proceed anyway, or use --chunk-size.");
+        return false;
+    }
 
-    println!("\n[+]  Compression completed!");
-    println!("       Total Input:    {}", format_bytes(total_read));
-    println!("       Total Output:   {}", format_bytes(total_written));
-    println!("       Ratio:          {:.2}x", ratio);
-    println!("       Time:           {:.2}s", start_total.elapsed().as_secs_f64());
+    eprint!("       Continue in solid mode? [y/N] ");
+    io::stderr().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() { return false; }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
 }
 
-// --- DECOMPRESSION ---
+struct CompressOptions {
+    multithread: bool,
+    force_multithread: bool,
+    chunk_bytes_limit: Option<usize>,
+    dict_size: u32,
+    use_7zip: bool,
+    checksum: ChecksumKind,
+    seven_zip_args: Vec<String>,
+    labels: Vec<(String, String)>,
+    write_index: bool,
+    column_codecs: bool,
+    // --front-coding
+    front_coding: bool,
+    // Cleared by --no-column-dict
+    column_dicts: bool,
+    // --dedup-chunks: store input chunks seen before as references to their first copy
+    dedup_chunks: bool,
+    // --order-checksum: record the row order checksum of the archive (see ROW_ORDER_LABEL)
+    order_checksum: bool,
+    max_var_len: usize,
+    // --manifest: where to write the JSON manifest of the archive
+    manifest: Option<String>,
+    // Re-encode a chunk natively when 7-Zip fails on it
+    native_fallback: bool,
+    // --follow: idle timeout, and the token (Ctrl+C) that ends the input early
+    follow: Option<(Duration, CancellationToken)>,
+    // --allow-truncation: finish (and flag) the archive when the input shrinks under the run
+    allow_truncation: bool,
+    // --max-time: picks full or fast compression for each chunk
+    deadline: Option<Deadline>,
+    // --hmac-key-file: MACs of every chunk and of the archive, written as its trailer
+    hmac_key: Option<MacKey>,
+    // --pack: the files compressed back to back (the input path is then their list/directory)
+    pack: Option<Vec<String>>,
+    // --convert: a .gz or .xz input is decoded on its way in
+    decode_input: bool,
+    header_row: HeaderRow,
+    budget: Option<MemoryBudget>,
+    // --jobs: encoder threads each file of the batch may use (None: all CPUs)
+    max_threads: Option<u32>,
+    cancel: CancellationToken,
+    on_cancel: CancelPolicy,
+    io_limit: Option<IoLimit>,
+}
 
-fn do_decompress(input_path: &str, output_path: &str, use_7zip: bool) {
-    let start = Instant::now();
-    let f_in = File::open(input_path).expect("Error opening archive");
+impl CompressOptions {
+    fn seven_zip_backend(&self) -> SevenZipBackend {
+        let backend = SevenZipBackend::new(self.dict_size);
+        let backend = match self.encoder_threads() { Some(n) => backend.with_threads(n), None => backend };
+        backend.with_extra_args(self.seven_zip_args.clone())
+    }
 
-    if f_in.metadata().unwrap().len() == 0 {
-        eprintln!("[!] ERROR: Input file is empty (0 bytes).");
-        return;
+    fn native_backend(&self) -> LzmaBackend {
+        let backend = LzmaBackend::new(self.multithread, self.dict_size).with_cancellation(self.cancel.clone());
+        let backend = if self.force_multithread { backend.with_forced_multithread() } else { backend };
+        match self.encoder_threads() { Some(n) => backend.with_threads(n), None => backend }
     }
 
-    let mut reader = std::io::BufReader::new(f_in);
-    let mut f_out = File::create(output_path).expect("Error creating output");
+    /// Explains how the native encoder will use --multithread on a stream of `len` bytes when
+    /// that is not simply "as asked" (None).
+    fn multithread_note(&self, len: u64) -> Option<String> {
+        let threads = self.encoder_threads().unwrap_or(num_cpus::get() as u32);
+        if threads < 2 {
+            return Some("multithread requested but disabled (one encoder thread available)".to_string());
+        }
+        let plan = plan_multithread(len, self.dict_size, true, self.force_multithread, threads);
+        if !plan.multithread {
+            let block = mt_block_size(mt_dict_size(len, threads));
+            if len <= block {
+                return Some(format!("multithread requested but disabled (input {} fits in one {} block)", format_bytes(len as usize), format_bytes(block as usize)));
+            }
+            return Some(format!("multithread requested but disabled (input {} <= dict {}); use --dict-size {}MB or --force-multithread to enable",
+                format_bytes(len as usize), format_bytes(self.dict_size as usize), mt_dict_size(len, threads) / (1024 * 1024)));
+        }
+        if plan.dict_size < self.dict_size {
+            return Some(format!("{} (dict shrunk to {} by --force-multithread)", threads, format_bytes(plan.dict_size as usize)));
+        }
+        None
+    }
 
-    // Backend Selection for Decompressor
-    let backend = if use_7zip {
-        RuntimeLzmaDecompressor::SevenZip(SevenZipDecompressorBackend)
-    } else {
-        RuntimeLzmaDecompressor::Native(LzmaDecompressorBackend)
-    };
+    /// Encoder threads allowed by the memory budget and --jobs (None: backend default).
+    fn encoder_threads(&self) -> Option<u32> {
+        let max = self.max_threads.unwrap_or(num_cpus::get() as u32);
+        match self.budget {
+            Some(b) => Some(b.encoder_threads(self.dict_size, max)),
+            None => self.max_threads,
+        }
+    }
+}
 
-    let decompressor = CASTLzmaDecompressor::new(backend);
-    let mut chunk_idx = 0;
+// --max-time: xz preset of the chunks encoded fast to meet the deadline
+const DEADLINE_FAST_PRESET: u32 = 1;
+// Speed-up of the fast chunks over the full ones assumed until one was timed
+const DEADLINE_FAST_SPEEDUP: f64 = 4.0;
+// Share of the time budget kept for finishing the archive (last writes, index, manifest)
+const DEADLINE_RESERVE: f64 = 0.05;
 
-    println!("\n[*]  Extracting stream...");
+/// --max-time: decides chunk by chunk whether the configured (full) compression still fits in
+/// the time budget, at the throughput measured so far. A chunk gets it as long as the rest of
+/// the input could then still be encoded fast before the deadline, so full-quality chunks
+/// come first and fast ones (xz preset `DEADLINE_FAST_PRESET`) only near the end. The files
+/// of a batch share one budget.
+struct Deadline {
+    // The deadline itself, and the one planned for (minus DEADLINE_RESERVE)
+    hard_end: Instant,
+    end: Instant,
+    state: Mutex<DeadlineState>,
+}
 
-    loop {
-        let mut header = [0u8; 17];
-        match reader.read_exact(&mut header) {
-            Ok(_) => {},
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                if chunk_idx == 0 {
-                    eprintln!("[!] ERROR: File header missing or corrupted.");
-                }
-                break;
-            },
-            Err(e) => panic!("Error reading header: {}", e),
-        };
+#[derive(Default)]
+struct DeadlineState {
+    // Input bytes of the current file not compressed yet, and of the batch files after it
+    pending: u64,
+    queued: u64,
+    // Bytes and seconds of the chunks compressed at full quality and fast so far
+    full: (u64, f64),
+    fast: (u64, f64),
+}
 
-        chunk_idx += 1;
-        let expected_crc = u32::from_le_bytes(header[0..4].try_into().unwrap());
-        let l_reg = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
-        let l_ids = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
-        let l_vars = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
-        let id_flag = header[16];
+impl Deadline {
+    fn new(budget: Duration) -> Self {
+        let start = Instant::now();
+        Self { hard_end: start + budget, end: start + budget.mul_f64(1.0 - DEADLINE_RESERVE), state: Mutex::new(DeadlineState::default()) }
+    }
 
-        let body_len = l_reg + l_ids + l_vars;
-        let mut body_buffer = vec![0u8; body_len];
-        reader.read_exact(&mut body_buffer).expect("Truncated file body");
+    /// How late the run is (zero while the deadline has not passed).
+    fn overrun(&self) -> Duration {
+        Instant::now().saturating_duration_since(self.hard_end)
+    }
 
-        print!("\r      Extracting Chunk #{}... ", chunk_idx);
-        io::stdout().flush().unwrap();
+    /// Bytes of the batch inputs still to come.
+    fn queue(&self, bytes: u64) {
+        self.state.lock().unwrap().queued += bytes;
+    }
 
-        let chunk_reg = &body_buffer[0 .. l_reg];
-        let chunk_ids = &body_buffer[l_reg .. l_reg+l_ids];
-        let chunk_vars = &body_buffer[l_reg+l_ids .. l_reg+l_ids+l_vars];
+    /// A new input of `len` bytes starts (taken off the queue).
+    fn begin_input(&self, len: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.queued = state.queued.saturating_sub(len);
+        state.pending = len;
+    }
 
-        match decompressor.decompress(chunk_reg, chunk_ids, chunk_vars, expected_crc, id_flag, &mut f_out) {
-            Ok(_) => {},
-            Err(e) => {
-                eprintln!("\n[!]  CRASH: Decompression error at Chunk {}: {}", chunk_idx, e);
-                std::process::exit(1);
-            }
-        }
+    /// Whether the next chunk of `len` bytes may get full compression.
+    fn allows_full(&self, len: u64) -> bool {
+        let state = self.state.lock().unwrap();
+        let rate = |(bytes, secs): (u64, f64)| (bytes > 0 && secs > 0.0).then(|| bytes as f64 / secs);
+        // The first chunk measures the full rate
+        let Some(full_rate) = rate(state.full) else { return true };
+        let fast_rate = rate(state.fast).unwrap_or(full_rate * DEADLINE_FAST_SPEEDUP);
+        let rest = (state.pending + state.queued).saturating_sub(len);
+        let needed = Duration::from_secs_f64(len as f64 / full_rate + rest as f64 / fast_rate);
+        Instant::now() + needed <= self.end
     }
 
-    if chunk_idx > 0 {
-        println!("\n[+]  Decompression done in {:.2}s", start.elapsed().as_secs_f64());
+    /// A chunk of `len` bytes took `elapsed` (read, encode and write).
+    fn record(&self, len: u64, elapsed: Duration, fast: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.pending = state.pending.saturating_sub(len);
+        let tier = if fast { &mut state.fast } else { &mut state.full };
+        tier.0 += len;
+        tier.1 += elapsed.as_secs_f64();
     }
 }
 
-// --- VERIFICATION ---
+/// Compresses one file. Returns (bytes read, bytes written); errors are returned as messages so
+/// batch runs can record them and move on.
+fn do_compress(input_path: &str, output_path: &str, opts: &CompressOptions) -> Result<(usize, usize), String> {
+    let CompressOptions { chunk_bytes_limit, use_7zip, checksum, .. } = *opts;
+    let start_total = Instant::now();
+    let waited_before = opts.io_limit.as_ref().map_or(Duration::ZERO, |l| l.waited());
+    let mut start_fields = vec![("command", JsonValue::Str("compress")), ("input", JsonValue::Str(input_path)), ("output", JsonValue::Str(output_path))];
+    let seven_zip_command = if use_7zip { opts.seven_zip_backend().command_line() } else { String::new() };
+    if use_7zip { start_fields.push(("backend_command", JsonValue::Str(&seven_zip_command))); }
+    emit_event("start", &start_fields);
+    let mut packed = match &opts.pack {
+        Some(files) => Some(PackReader::new(files.clone()).map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let encoding = match opts.decode_input && opts.pack.is_none() {
+        true => InputEncoding::sniff(Path::new(input_path)).map_err(|e| format!("Cannot open input: {}", e))?,
+        false => InputEncoding::Plain,
+    };
+    // Start of the decoded data of an encoded input, for the header row
+    let mut decoded_head: Option<Vec<u8>> = None;
+    let (reader, file_len, has_bom, mut watch): (Box<dyn Read + '_>, u64, bool, Option<InputWatch>) = match packed.as_mut() {
+        // Packed files are archived exactly as they are, BOMs included
+        Some(pack) => {
+            let len = pack.total_len();
+            (Box::new(ThrottledReader::new(pack, opts.io_limit.clone())), len, false, None)
+        },
+        None if encoding != InputEncoding::Plain => {
+            let f_in = File::open(input_path).map_err(|e| format!("Cannot open input: {}", e))?;
+            let mut decoded = decoder(ThrottledReader::new(f_in, opts.io_limit.clone()), encoding);
+            let mut head = Vec::with_capacity(HEADER_SAMPLE_LEN);
+            // Solid mode needs the exact decoded size up front: the whole input is decoded first
+            let head_len = if chunk_bytes_limit.is_some() { HEADER_SAMPLE_LEN as u64 } else { u64::MAX };
+            (&mut decoded).take(head_len).read_to_end(&mut head).map_err(|e| format!("Cannot decode {} input: {}", encoding.label(), e))?;
+            let has_bom = head.starts_with(&UTF8_BOM);
+            if has_bom { head.drain(..UTF8_BOM.len()); }
+            let file_len = match chunk_bytes_limit {
+                Some(_) => decoded_len_hint(Path::new(input_path), encoding).ok().flatten()
+                    .map_or(head.len() as u64, |hint| hint.saturating_sub(has_bom as u64 * UTF8_BOM.len() as u64).max(head.len() as u64)),
+                None => head.len() as u64,
+            };
+            decoded_head = Some(head[..head.len().min(HEADER_SAMPLE_LEN)].to_vec());
+            (Box::new(io::Cursor::new(head).chain(decoded)), file_len, has_bom, None)
+        },
+        None => {
+            let mut f_in = File::open(input_path).map_err(|e| format!("Cannot open input: {}", e))?;
+            // Checked at every chunk boundary and at the end: a truncated input is not archived as is
+            // (--follow also ends the input at once on a truncation instead of after the idle timeout)
+            let new_watch = || f_in.try_clone().and_then(InputWatch::new).map_err(|e| format!("Cannot read input metadata: {}", e));
+            let watch = new_watch()?;
+            let follow_watch = if opts.follow.is_some() { Some(new_watch()?) } else { None };
+            let mut file_len = f_in.metadata().map_err(|e| format!("Cannot read input metadata: {}", e))?.len();
 
-fn do_verify_standalone(input_path: &str, use_7zip: bool) {
-    let start = Instant::now();
-    let f_in = File::open(input_path).expect("Error opening archive");
-    let mut reader = std::io::BufReader::new(f_in);
+            // A leading UTF-8 BOM is kept out of the chunks (it would glue onto the first token)
+            // and recorded in the archive header instead
+            let has_bom = skip_utf8_bom(&mut f_in).map_err(|e| format!("Cannot read input: {}", e))?;
+            if has_bom { file_len -= UTF8_BOM.len() as u64; }
 
-    // Backend Selection for Verification
-    let backend = if use_7zip {
-        RuntimeLzmaDecompressor::SevenZip(SevenZipDecompressorBackend)
-    } else {
-        RuntimeLzmaDecompressor::Native(LzmaDecompressorBackend)
+            let f_in = ThrottledReader::new(f_in, opts.io_limit.clone());
+            let reader: Box<dyn Read> = match (&opts.follow, follow_watch) {
+                (Some((idle_timeout, stop)), Some(follow_watch)) => {
+                    say!("\n[*]  Following input: chunks are written as the file grows...");
+                    Box::new(FollowReader::new(f_in, *idle_timeout, stop.clone()).with_watch(follow_watch))
+                },
+                _ => Box::new(f_in),
+            };
+            (reader, file_len, has_bom, Some(watch))
+        },
     };
+    let f_out = File::create(output_path).map_err(|e| format!("Cannot create output: {}", e))?;
+    // Never leave a truncated archive behind (declared before the writer, so it is closed first)
+    let mut created = CreatedOutput::new(output_path, opts);
+    let mut f_out = BufWriter::new(ThrottledWriter::new(f_out, opts.io_limit.clone()));
 
-    let decompressor = CASTLzmaDecompressor::new(backend);
-    let mut chunk_idx = 0;
-
-    println!("[*]  Verifying Stream Integrity (RAM Optimized)...");
+    // Chunked runs read a little past the chunk size so every chunk ends with a whole line
+    let mut chunker = InputChunker::new(reader, chunk_bytes_limit, file_len as usize);
 
-    loop {
-        let mut header = [0u8; 17];
-        match reader.read_exact(&mut header) {
-            Ok(_) => {},
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(e) => panic!("Error reading header: {}", e),
+    let announced_size = file_len + if has_bom { UTF8_BOM.len() as u64 } else { 0 };
+    if let Some(deadline) = &opts.deadline { deadline.begin_input(file_len); }
+    let mut labels = opts.labels.clone();
+    // Column names of a header row (an explicit --label columns=... wins)
+    if opts.pack.is_none() && !labels.iter().any(|(k, _)| k == COLUMNS_LABEL) {
+        let names = match &decoded_head {
+            Some(head) => detect_column_names(head, opts.header_row),
+            None => read_column_names(input_path, opts.header_row),
         };
+        if let Some(names) = names {
+            say!("       Columns:     {} (from the header row)", names.join(", "));
+            labels.push((COLUMNS_LABEL.to_string(), names.join("\t")));
+        }
+    }
+    // Permission bits of a single input, for --preserve-permissions (an explicit label wins)
+    if opts.pack.is_none() && !labels.iter().any(|(k, _)| k == UNIX_MODE_LABEL) {
+        if let Some(mode) = std::fs::metadata(input_path).ok().as_ref().and_then(mode_of) {
+            labels.push((UNIX_MODE_LABEL.to_string(), format!("{:04o}", mode)));
+        }
+    }
+    let new_header = || ArchiveHeader::new(checksum).with_bom(has_bom).with_original_size(announced_size).with_authentication(opts.hmac_key.is_some());
+    // The content digest is a placeholder until the whole input is read
+    let with_digest = |mut labels: Vec<(String, String)>| {
+        set_label(&mut labels, CONTENT_DIGEST_LABEL.to_string(), digest_hex(&[0; 32]));
+        labels
+    };
+    // So is the row order checksum of --order-checksum
+    let with_order = |mut labels: Vec<(String, String)>| {
+        if opts.order_checksum { set_label(&mut labels, ROW_ORDER_LABEL.to_string(), RowOrder::default().to_label()); }
+        labels
+    };
+    let archive_header = new_header().with_labels(with_digest(with_order(labels)))
+        // Names too long for the labels section are dropped, then the digest, then the row
+        // order checksum: none is fatal
+        .or_else(|_| new_header().with_labels(with_digest(with_order(opts.labels.clone()))))
+        .or_else(|_| new_header().with_labels(with_order(opts.labels.clone())))
+        .or_else(|_| new_header().with_labels(opts.labels.clone()))?;
+    archive_header.write_to(&mut f_out).map_err(|e| format!("Cannot write archive header: {}", e))?;
+    let mut row_order = archive_header.labels.iter().any(|(k, _)| k == ROW_ORDER_LABEL).then(RowOrder::default);
+    if opts.order_checksum && row_order.is_none() {
+        eprintln!("[!]  Warning: {}The labels leave no room for the row order checksum: it is not recorded.", job_tag());
+    }
+    let mut content_hasher = archive_header.labels.iter().any(|(k, _)| k == CONTENT_DIGEST_LABEL).then(|| {
+        let mut hasher = Sha256::default();
+        if has_bom { hasher.update(&UTF8_BOM); }
+        hasher
+    });
 
-        chunk_idx += 1;
-        let expected_crc = u32::from_le_bytes(header[0..4].try_into().unwrap());
-        let l_reg = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
-        let l_ids = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
-        let l_vars = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
-        let id_flag = header[16];
+    // Writing chunk N overlaps with reading/compressing chunk N+1
+    let mut pipeline = WritePipeline::spawn(f_out);
 
-        let body_len = l_reg + l_ids + l_vars;
-        let mut body_buffer = vec![0u8; body_len];
-        reader.read_exact(&mut body_buffer).expect("Truncated file in body");
+    let mut total_read = if has_bom { UTF8_BOM.len() } else { 0 };
+    let mut total_written = archive_header.encoded_len();
+    let mut chunk_count = 0;
+    let mut strategy: Option<String> = None;
+    let mut stats = CompressionStats::default();
+    let mut index_entries: Vec<IndexEntry> = Vec::new();
+    // Per-entry extras of --manifest: backend and compressed body CRC
+    let mut manifest_extras: Vec<(&str, u32)> = Vec::new();
+    let collect_entries = opts.write_index || opts.manifest.is_some();
+    let (mut read_time, mut encode_time, mut write_wait) = (Duration::ZERO, Duration::ZERO, Duration::ZERO);
+    let mut rows_before = 0;
+    // Chunks that 7-Zip failed on and the native backend encoded instead
+    let mut fallback_chunks: Vec<usize> = Vec::new();
+    // --dedup-chunks: input chunks written so far, and the input reopened to confirm matches
+    let mut dedup = opts.dedup_chunks.then(ChunkDedup::default);
+    let mut dedup_source: Option<File> = None;
+    let mut archive_chunks = 0;
+    // Set when --allow-truncation lets a run go on after the input shrank
+    let mut input_changed = false;
+    // --max-time: chunks and input bytes encoded fast to meet the deadline
+    let (mut fast_chunks, mut fast_bytes) = (0, 0);
+    // --hmac-key-file: MAC of every chunk written (one per archive chunk, split ones included)
+    let mut chunk_macs: Vec<Mac> = Vec::new();
+    // Compressed size of every chunk against its input size, to tell whether a larger chunk
+    // size would compress better (advisory only: the archive is not affected)
+    let mut chunk_model = ChunkSizeModel::default();
+    let probe_every = chunk_bytes_limit
+        .filter(|&chunk| opts.deadline.is_none() && opts.follow.is_none() && file_len >= chunk as u64 * PROBE_MIN_CHUNKS)
+        .map(|chunk| (file_len / chunk as u64 / MAX_PROBES).max(PROBE_MIN_CHUNKS) as usize);
 
-        print!("\r       Verifying Chunk #{}... ", chunk_idx);
-        io::stdout().flush().unwrap();
+    say!("\n[*]  Starting stream processing...");
 
-        let chunk_reg = &body_buffer[0 .. l_reg];
-        let chunk_ids = &body_buffer[l_reg .. l_reg+l_ids];
-        let chunk_vars = &body_buffer[l_reg+l_ids .. l_reg+l_ids+l_vars];
+    loop {
+        if opts.cancel.is_cancelled() { break; }
+        let read_start = Instant::now();
+        let Some(chunk_data) = chunker.next_chunk().map_err(|e| format!("Cannot read chunk: {}", e))? else { break };
+        read_time += read_start.elapsed();
 
-        let mut temp_buffer = Vec::new();
+        chunk_count += 1;
+        let chunk_len = chunk_data.len();
+        check_input(watch.as_mut(), (total_read + chunk_len) as u64, opts.allow_truncation, &mut input_changed)?;
 
-        match decompressor.decompress(chunk_reg, chunk_ids, chunk_vars, expected_crc, id_flag, &mut temp_buffer) {
-            Ok(_) => {
-                let mut h = Hasher::new();
-                h.update(&temp_buffer);
-                if h.finalize() != expected_crc {
-                    println!("\n[!]   FAILURE: CRC Mismatch at Chunk {}!", chunk_idx);
-                    std::process::exit(1);
-                }
-            },
-            Err(e) => {
-                println!("\n[!]   CRASH: Decompression error at Chunk {}: {}", chunk_idx, e);
-                std::process::exit(1);
-            }
+        if decorated_output() {
+            print!("\r       Processing Chunk #{} ({})... ", chunk_count, format_bytes(chunk_data.len()));
+            io::stdout().flush().unwrap();
         }
-    }
 
-    println!("\n[+]  FILE INTEGRITY VERIFIED. Chunks: {}. Time: {:.2}s", chunk_idx, start.elapsed().as_secs_f64());
+        // CAST Compression (Backend Selection)
+        // Wraps the specific backend in the Runtime Enum
+        let fast = opts.deadline.as_ref().is_some_and(|d| !d.allows_full(chunk_len as u64));
+        let backend = if fast {
+            RuntimeLzmaCompressor::Native(opts.native_backend().with_preset(DEADLINE_FAST_PRESET))
+        } else if use_7zip {
+            RuntimeLzmaCompressor::SevenZip(opts.seven_zip_backend())
+        } else {
+            RuntimeLzmaCompressor::Native(opts.native_backend())
+        };
+
+        let mut chunk_backend = if fast { "native-fast" } else if use_7zip { "7zip" } else { "native" };
+        let encode_start = Instant::now();
+        let dedup_key = dedup.as_ref().map(|_| ChunkDedup::key(chunk_data));
+        // A hash match is only used once the input confirms it byte for byte
+        let duplicate = match dedup_key.as_ref().and_then(|key| dedup.as_ref()?.get(key)) {
+            Some(target) if input_matches(&mut dedup_source, input_path, target.data_offset, chunk_data)
+                .map_err(|e| format!("Cannot read the input back to confirm a duplicate chunk: {}", e))? => Some(target),
+            _ => None,
+        };
+        let encoded = match duplicate {
+            Some(target) => Ok(encode_duplicate(chunk_data, checksum, &target)),
+            None => match catch_panics(|| { debug_panic_at(chunk_count); encode_chunk(backend, chunk_data, checksum, opts.column_codecs, opts.front_coding, opts.column_dicts, opts.max_var_len) }) {
+                Err(e @ CastError::BackendFailure { backend: "7-Zip", .. }) if opts.native_fallback && !opts.cancel.is_cancelled() => {
+                    // The native encoder writes the same xz streams: only this chunk is re-encoded
+                    eprintln!("\n[!]  Warning: {}Chunk {}: {}", job_tag(), chunk_count, e);
+                    eprintln!("       Retrying the chunk with the native backend (--no-fallback to abort instead)...");
+                    chunk_backend = "native";
+                    fallback_chunks.push(chunk_count);
+                    catch_panics(|| encode_chunk(opts.native_backend(), chunk_data, checksum, opts.column_codecs, opts.front_coding, opts.column_dicts, opts.max_var_len))
+                        .inspect_err(|_| eprintln!("[!]  The native retry failed as well."))
+                },
+                result => result,
+            },
+        };
+        encode_time += encode_start.elapsed();
+        let EncodedChunk { parts, chunks, strategy: chunk_strategy, stats: chunk_stats } = match encoded {
+            Ok(encoded) => encoded,
+            Err(_) if opts.cancel.is_cancelled() => break,
+            Err(e) => return Err(format!("Compression failed at Chunk {}: {}", chunk_count, e)),
+        };
+        // Stdout carries the JSON events: the note goes to stderr (the strategy is in the chunk event)
+        if decorated_output() && chunks.iter().any(|(_, header)| is_passthrough(header.id_flag)) {
+            eprintln!("[!] Switching to Passthrough ({})", chunk_strategy);
+        }
+        stats.add(&chunk_stats);
+        if let [(_, header)] = chunks.as_slice() {
+            // Passthrough, duplicate and fast chunks say nothing about the chunk size
+            if !fast && !is_passthrough(header.id_flag) && header.id_flag != ID_FLAG_DUPLICATE {
+                chunk_model.record(chunk_len as u64, (CHUNK_HEADER_SIZE + header.body_len()) as u64);
+                if probe_every.is_some_and(|every| (chunk_count - 1) % every == 0) {
+                    let probe_start = Instant::now();
+                    match catch_panics(|| probe_chunk_halves(chunk_data, opts, &mut chunk_model)) {
+                        Err(_) if opts.cancel.is_cancelled() => break,
+                        // The probe only feeds the advice: a failure costs the advice, not the run
+                        _ => encode_time += probe_start.elapsed(),
+                    }
+                }
+            }
+        }
+        if let (Some(dedup), Some(key), [(_, header)]) = (dedup.as_mut(), dedup_key, chunks.as_slice()) {
+            // A chunk split at MAX_SEGMENT_LEN is never referenced: a reference names one chunk
+            if header.id_flag != ID_FLAG_DUPLICATE {
+                dedup.insert(key, DedupTarget { chunk: archive_chunks + 1, data_offset: total_read as u64, compressed_len: (CHUNK_HEADER_SIZE + header.body_len()) as u64 });
+            }
+        }
+        if let Some(key) = &opts.hmac_key {
+            let mut data = chunk_data;
+            for (i, (data_len, _)) in chunks.iter().enumerate() {
+                let (content, rest) = data.split_at(*data_len);
+                chunk_macs.push(key.chunk_mac((archive_chunks + i + 1) as u64, content));
+                data = rest;
+            }
+        }
+        if let Some(order) = row_order.as_mut() {
+            let mut data = chunk_data;
+            for (data_len, _) in &chunks {
+                let (content, rest) = data.split_at(*data_len);
+                order.append(&RowOrder::of_chunk(content));
+                data = rest;
+            }
+        }
+        archive_chunks += chunks.len();
+
+        let chunk_written: usize = parts.iter().map(|p| p.len()).sum();
+        if collect_entries {
+            // Several entries when the chunk had to be split (see MAX_SEGMENT_LEN)
+            let (mut offset, mut data_offset) = (total_written, total_read);
+            // Each chunk is written as its header followed by reg, ids and vars
+            for ((data_len, header), segments) in chunks.iter().zip(parts.chunks(4)) {
+                manifest_extras.push((chunk_backend, body_crc(&segments[1..])));
+                let lines = count_lines(&chunk_data[data_offset - total_read..][..*data_len]);
+                let compressed_len = CHUNK_HEADER_SIZE + header.body_len();
+                let parser = if has_parser_record(FORMAT_VERSION, header.id_flag) { ParserConfig::from_record(&segments[3]) } else { None };
+                index_entries.push(IndexEntry {
+                    offset: offset as u64, compressed_len: compressed_len as u64,
+                    data_offset: data_offset as u64, data_len: *data_len as u64,
+                    first_row: rows_before, lines, crc: header.crc, id_flag: header.id_flag, parser,
+                });
+                rows_before += lines;
+                offset += compressed_len;
+                data_offset += data_len;
+            }
+        }
+        if let Some(hasher) = content_hasher.as_mut() { hasher.update(chunk_data); }
+        total_read += chunk_len;
+        total_written += chunk_written;
+
+        emit_event("chunk", &[
+            ("command", JsonValue::Str("compress")),
+            ("idx", JsonValue::Int(chunk_count as u64)),
+            ("bytes_in", JsonValue::Int(chunk_len as u64)),
+            ("bytes_out", JsonValue::Int(chunk_written as u64)),
+            ("strategy", JsonValue::Str(&chunk_strategy)),
+            ("backend", JsonValue::Str(chunk_backend)),
+        ]);
+        strategy = match strategy {
+            Some(s) if s != chunk_strategy => Some("Mixed".to_string()),
+            Some(s) => Some(s),
+            None => Some(chunk_strategy),
+        };
+
+        let send_start = Instant::now();
+        pipeline.send(parts).map_err(|e| format!("Writing output failed: {}", e))?;
+        write_wait += send_start.elapsed();
+        if let Some(deadline) = &opts.deadline {
+            deadline.record(chunk_len as u64, read_start.elapsed(), fast);
+            if fast {
+                fast_chunks += 1;
+                fast_bytes += chunk_len;
+            }
+        }
+    }
+
+    drop(chunker);
+    if !opts.cancel.is_cancelled() { check_input(watch.as_mut(), total_read as u64, opts.allow_truncation, &mut input_changed)?; }
+    let flags = if input_changed { archive_header.flags | FLAG_INPUT_CHANGED } else { archive_header.flags };
+    let content_digest = content_hasher.map(|hasher| digest_hex(&hasher.finalize()));
+    let row_order_label = row_order.map(|order| order.to_label());
+    // The trailer covers the header as it reads once patched below
+    if let Some(key) = &opts.hmac_key {
+        let mut final_header = archive_header.clone().with_original_size(total_read as u64);
+        final_header.flags = flags;
+        if let Some(digest) = &content_digest { set_label(&mut final_header.labels, CONTENT_DIGEST_LABEL.to_string(), digest.clone()); }
+        if let Some(order) = &row_order_label { set_label(&mut final_header.labels, ROW_ORDER_LABEL.to_string(), order.clone()); }
+        let parts = key.trailer(&final_header.to_bytes(), chunk_macs).to_parts();
+        total_written += parts.iter().map(|p| p.len()).sum::<usize>();
+        pipeline.send(parts).map_err(|e| format!("Writing output failed: {}", e))?;
+    }
+
+    let finish_start = Instant::now();
+    pipeline.finish().map_err(|e| format!("Writing output failed: {}", e))?;
+    write_wait += finish_start.elapsed();
+    if input_changed {
+        let mut f = std::fs::OpenOptions::new().write(true).open(output_path).map_err(|e| format!("Cannot reopen output: {}", e))?;
+        ArchiveHeader::patch_flags(&mut f, flags).map_err(|e| format!("Cannot update archive header: {}", e))?;
+    }
+
+    // The input changed size while it was read (or the run was cancelled): record what was
+    // actually archived
+    if total_read as u64 != announced_size {
+        let mut f = std::fs::OpenOptions::new().write(true).open(output_path).map_err(|e| format!("Cannot reopen output: {}", e))?;
+        ArchiveHeader::patch_original_size(&mut f, total_read as u64).map_err(|e| format!("Cannot update archive header: {}", e))?;
+    }
+    if let Some(digest) = &content_digest {
+        let mut f = std::fs::OpenOptions::new().write(true).open(output_path).map_err(|e| format!("Cannot reopen output: {}", e))?;
+        archive_header.patch_label(&mut f, CONTENT_DIGEST_LABEL, digest).map_err(|e| format!("Cannot update archive header: {}", e))?;
+    }
+    if let Some(order) = &row_order_label {
+        let mut f = std::fs::OpenOptions::new().write(true).open(output_path).map_err(|e| format!("Cannot reopen output: {}", e))?;
+        archive_header.patch_label(&mut f, ROW_ORDER_LABEL, order).map_err(|e| format!("Cannot update archive header: {}", e))?;
+    }
+
+    if opts.cancel.is_cancelled() {
+        let _ = std::fs::remove_file(sidecar_path(output_path));
+        let _ = std::fs::remove_file(file_table_path(output_path));
+        say!("\n[!]  Cancelled after {} of {}.", format_bytes(total_read), format_bytes(announced_size as usize));
+        return Err(CastError::Cancelled.to_string());
+    }
+    // Solid runs stop reading at the announced size: the files listed after it are checked here
+    let packed = packed.map(|pack| pack.into_files()).transpose().map_err(|e| e.to_string())?;
+
+    // Sidecar index (written last: it fingerprints the finished archive). A sidecar left by
+    // an earlier archive at this path no longer matches, so it is removed
+    if opts.write_index {
+        ArchiveIndex::for_archive(output_path, index_entries.clone())
+            .map_err(|e| e.to_string())
+            .and_then(|index| index.save(output_path).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Cannot write index: {}", e))?;
+    } else {
+        let _ = std::fs::remove_file(sidecar_path(output_path));
+    }
+    // Same for the file table of --pack
+    let packed_files = match packed {
+        Some(files) => {
+            write_file_table(output_path, &files).map_err(|e| format!("Cannot write file table: {}", e))?;
+            Some(files.len())
+        },
+        None => {
+            let _ = std::fs::remove_file(file_table_path(output_path));
+            None
+        },
+    };
+
+    let ratio = if total_written > 0 { total_read as f64 / total_written as f64 } else { 0.0 };
+    let advice = chunk_bytes_limit.and_then(|chunk| {
+        chunk_model.advise(chunk as u64, advice_target(total_read as u64, opts.budget), total_read as u64, total_written as u64)
+    });
+
+    if let Some(path) = &opts.manifest {
+        let archive = File::open(output_path).map_err(CastError::from).and_then(|mut f| ArchiveRecord::read(&mut f))
+            .map_err(|e| format!("Cannot read the archive for the manifest: {}", e))?;
+        let manifest = Manifest {
+            tool: format!("cast {}", env!("CARGO_PKG_VERSION")),
+            archive,
+            settings: ManifestSettings {
+                input: input_path.to_string(),
+                backend: if use_7zip { "7zip" } else { "native" }.to_string(),
+                dict_size: opts.dict_size as u64,
+                chunk_size: chunk_bytes_limit.map(|c| c as u64),
+                multithread: opts.multithread,
+                column_codecs: opts.column_codecs,
+                max_var_len: opts.max_var_len as u64,
+                seven_zip_args: opts.seven_zip_args.join(" "),
+            },
+            totals: ManifestTotals {
+                chunks: index_entries.len() as u64,
+                input_bytes: total_read as u64,
+                output_bytes: total_written as u64,
+                rows: stats.rows(),
+                columnar_chunks: stats.columnar_chunks,
+                passthrough_chunks: stats.passthrough_chunks,
+                fallback_chunks: fallback_chunks.len() as u64,
+            },
+            timing: ManifestTiming {
+                total: start_total.elapsed().as_secs_f64(),
+                read: read_time.as_secs_f64(),
+                encode: encode_time.as_secs_f64(),
+                write_wait: write_wait.as_secs_f64(),
+            },
+            chunks: index_entries.into_iter().zip(manifest_extras)
+                .map(|(entry, (backend, body_crc))| ManifestChunk { entry, backend: backend.to_string(), body_crc })
+                .collect(),
+        };
+        manifest.save(path).map_err(|e| format!("Cannot write manifest '{}': {}", path, e))?;
+    }
+
+    let elapsed = start_total.elapsed().as_secs_f64();
+
+    say!("\n[+]  Compression completed!");
+    say!("       Total Input:    {}", format_bytes(total_read));
+    say!("       Total Output:   {}", format_bytes(total_written));
+    say!("       Ratio:          {:.2}x", ratio);
+    if let Some(count) = packed_files {
+        say!("       Files:          {} packed (table: {})", count, file_table_path(output_path));
+    }
+    if let Some(path) = &opts.manifest {
+        say!("       Manifest:       {}", path);
+    }
+    say!("       Columnar:       {:.1}% of input bytes ({} rows in {} chunk(s))", stats.columnar_percent(), stats.columnar_rows, stats.columnar_chunks);
+    if stats.passthrough_chunks > 0 {
+        say!("       Passthrough:    {} ({} rows in {} chunk(s))", format_bytes(stats.passthrough_bytes as usize), stats.passthrough_rows, stats.passthrough_chunks);
+    }
+    if stats.long_values > 0 {
+        say!("       Long values:    {} kept in their templates (over {}, see --max-var-len)", stats.long_values, format_bytes(opts.max_var_len));
+    }
+    if opts.front_coding {
+        say!("       Registry:       {} saved by front coding", format_bytes(stats.registry_saved as usize));
+    }
+    if stats.split_chunks > 0 {
+        say!("       Id streams:     {} split chunk(s): {} move-to-front, {} delta, {} as is", stats.split_chunks, stats.mtf_id_chunks,
+            stats.delta_id_chunks, stats.split_chunks - stats.mtf_id_chunks - stats.delta_id_chunks);
+    }
+    if opts.dedup_chunks {
+        say!("       Duplicates:     {} chunk(s), {} of input stored as references ({} saved)", stats.duplicate_chunks,
+            format_bytes(stats.duplicate_bytes as usize), format_bytes(stats.dedup_saved as usize));
+    }
+    if !fallback_chunks.is_empty() {
+        let list: Vec<String> = fallback_chunks.iter().map(|c| format!("#{}", c)).collect();
+        say!("       Fallback:       {} chunk(s) encoded natively after 7-Zip failed ({})", fallback_chunks.len(), list.join(", "));
+    }
+    if input_changed {
+        say!("       Input:          changed while it was read (archive flagged, see --allow-truncation)");
+    }
+    if opts.hmac_key.is_some() {
+        say!("       HMAC:           SHA-256 of {} chunk(s) and of the archive, in its trailer", archive_chunks);
+    }
+    if let Some(order) = &row_order {
+        say!("       Row order:      checksum of {} rows, checked by full restores and -v", format_count(order.rows));
+    }
+    if let Some(deadline) = &opts.deadline {
+        let full_share = if total_read > 0 { (total_read - fast_bytes) as f64 * 100.0 / total_read as f64 } else { 100.0 };
+        say!("       Max Time:       {:.1}% of input at full quality, {} chunk(s) ({}) encoded fast (xz preset {})",
+            full_share, fast_chunks, format_bytes(fast_bytes), DEADLINE_FAST_PRESET);
+        let overrun = deadline.overrun();
+        if !overrun.is_zero() { eprintln!("[!]  Warning: --max-time missed by {:.2}s: even the fast setting was too slow for the input.", overrun.as_secs_f64()); }
+    }
+    say!("       Time:           {:.2}s", elapsed);
+    if let Some(advice) = &advice { print_chunk_advice(advice, chunk_bytes_limit.unwrap_or(0), total_read, total_written); }
+    let mut done_fields = vec![
+        ("command", JsonValue::Str("compress")),
+        ("chunks", JsonValue::Int(chunk_count as u64)),
+        ("bytes_in", JsonValue::Int(total_read as u64)),
+        ("bytes_out", JsonValue::Int(total_written as u64)),
+        ("ratio", JsonValue::Float(ratio)),
+        ("time", JsonValue::Float(elapsed)),
+        ("strategy", JsonValue::Str(strategy.as_deref().unwrap_or("Empty"))),
+        ("columnar_chunks", JsonValue::Int(stats.columnar_chunks)),
+        ("columnar_rows", JsonValue::Int(stats.columnar_rows)),
+        ("columnar_bytes", JsonValue::Int(stats.columnar_bytes)),
+        ("passthrough_chunks", JsonValue::Int(stats.passthrough_chunks)),
+        ("passthrough_rows", JsonValue::Int(stats.passthrough_rows)),
+        ("passthrough_bytes", JsonValue::Int(stats.passthrough_bytes)),
+        ("long_values", JsonValue::Int(stats.long_values)),
+        ("registry_saved", JsonValue::Int(stats.registry_saved)),
+        ("split_chunks", JsonValue::Int(stats.split_chunks)),
+        ("mtf_id_chunks", JsonValue::Int(stats.mtf_id_chunks)),
+        ("delta_id_chunks", JsonValue::Int(stats.delta_id_chunks)),
+        ("duplicate_chunks", JsonValue::Int(stats.duplicate_chunks)),
+        ("duplicate_bytes", JsonValue::Int(stats.duplicate_bytes)),
+        ("dedup_saved", JsonValue::Int(stats.dedup_saved)),
+        ("fallback_chunks", JsonValue::Int(fallback_chunks.len() as u64)),
+        ("input_changed", JsonValue::Int(input_changed as u64)),
+        ("authenticated", JsonValue::Int(opts.hmac_key.is_some() as u64)),
+        ("order_rows", JsonValue::Int(row_order.map_or(0, |order| order.rows))),
+        ("fast_chunks", JsonValue::Int(fast_chunks)),
+        ("fast_bytes", JsonValue::Int(fast_bytes as u64)),
+    ];
+    if let Some(advice) = &advice {
+        done_fields.push(("suggested_chunk_size", JsonValue::Int(advice.chunk_size)));
+        done_fields.push(("estimated_bytes_out", JsonValue::Int(advice.estimated_size)));
+    }
+    report_throttle_wait(opts.io_limit.as_ref(), waited_before, "       Throttled:      ", true, &mut done_fields);
+    emit_event("done", &done_fields);
+    created.finished = true;
+    Ok((total_read, total_written))
+}
+
+/// Debug builds panic while encoding chunk `CAST_DEBUG_PANIC_AT_CHUNK` (1-based), so
+/// tests/cli.rs can run the binary into the path of a panicking backend.
+fn debug_panic_at(chunk: usize) {
+    if cfg!(debug_assertions) && env::var("CAST_DEBUG_PANIC_AT_CHUNK").ok().and_then(|n| n.parse().ok()) == Some(chunk) {
+        panic!("CAST_DEBUG_PANIC_AT_CHUNK reached chunk {}", chunk);
+    }
+}
+
+/// Archive created by the current run: removed when the run fails, unless it was cancelled
+/// and the cancel policy keeps partial output. Errors before the output is created leave a
+/// file already at that path alone.
+struct CreatedOutput<'a> {
+    path: &'a str,
+    opts: &'a CompressOptions,
+    finished: bool,
+}
+
+impl<'a> CreatedOutput<'a> {
+    fn new(path: &'a str, opts: &'a CompressOptions) -> Self {
+        Self { path, opts, finished: false }
+    }
+}
+
+impl Drop for CreatedOutput<'_> {
+    fn drop(&mut self) {
+        let kept = self.opts.cancel.is_cancelled() && self.opts.on_cancel == CancelPolicy::Keep;
+        if !self.finished && !kept {
+            let _ = std::fs::remove_file(self.path);
+            // An index or file table written before the failure describes a removed archive
+            let _ = std::fs::remove_file(sidecar_path(self.path));
+            let _ = std::fs::remove_file(file_table_path(self.path));
+        }
+    }
+}
+
+/// Explains after a chunked run that a larger chunk size is expected to compress better.
+fn print_chunk_advice(advice: &ChunkAdvice, chunk_size: usize, input_len: usize, archive_len: usize) {
+    // A chunk never holds more than the whole input
+    let working_set = advice.chunk_size.min(input_len as u64) * SOLID_MEMORY_FACTOR;
+    say!("\n[*]  Note: The ratio appears limited by the chunk size ({}): every chunk learns its templates and", format_bytes(chunk_size));
+    say!("       LZMA window again. Try --chunk-size {}MB (needs ~{}MB of RAM) for an estimated {:.1}% smaller",
+        advice.chunk_size / (1024 * 1024), working_set / (1024 * 1024), advice.saving * 100.0);
+    say!("       archive ({} instead of {}; chunks of L bytes fit ~L^{:.3} over {} sample(s)).",
+        format_bytes(advice.estimated_size as usize), format_bytes(archive_len), advice.fit.b, advice.fit.samples);
+}
+
+/// Fails the run when the input shrank or went back in time since the last check (`read`
+/// bytes of it archived so far). With --allow-truncation it warns once and sets `changed`
+/// instead, so the archive is finished and flagged.
+fn check_input(watch: Option<&mut InputWatch>, read: u64, allow_truncation: bool, changed: &mut bool) -> Result<(), String> {
+    let Some(watch) = watch else { return Ok(()) };
+    let Some(change) = watch.check(read).map_err(|e| format!("Cannot read input metadata: {}", e))? else { return Ok(()) };
+    if !allow_truncation {
+        return Err(format!("The input changed while it was read ({}): the archive would hold a torn view of it. \
+            Use --allow-truncation to keep it anyway (flagged as a changed input).", change));
+    }
+    if !*changed { eprintln!("\n[!]  Warning: {}The input changed while it was read ({}); the archive is flagged.", job_tag(), change); }
+    *changed = true;
+    Ok(())
+}
+
+/// Whether the input holds `data` at `offset`: --dedup-chunks reads the first copy back from
+/// the input file to confirm every hash match byte for byte before storing a reference.
+fn input_matches(source: &mut Option<File>, input_path: &str, offset: u64, data: &[u8]) -> io::Result<bool> {
+    let f = match source {
+        Some(f) => f,
+        None => source.insert(File::open(input_path)?),
+    };
+    f.seek(SeekFrom::Start(offset))?;
+    let mut block = vec![0u8; data.len().min(1024 * 1024)];
+    for expected in data.chunks(block.len().max(1)) {
+        let block = &mut block[..expected.len()];
+        if f.read_exact(block).is_err() || block != expected { return Ok(false); }
+    }
+    Ok(true)
+}
+
+/// Column names from the header row of `input_path` (see `detect_column_names`).
+fn read_column_names(input_path: &str, mode: HeaderRow) -> Option<Vec<String>> {
+    if mode == HeaderRow::No { return None; }
+    let mut head = Vec::with_capacity(HEADER_SAMPLE_LEN);
+    File::open(input_path).ok()?.take(HEADER_SAMPLE_LEN as u64).read_to_end(&mut head).ok()?;
+    let head = head.strip_prefix(&UTF8_BOM[..]).unwrap_or(&head);
+    detect_column_names(head, mode)
+}
+
+/// Consumes a UTF-8 BOM at the start of `f`. Returns whether one was found; otherwise the
+/// file position is left at the start.
+fn skip_utf8_bom(f: &mut File) -> io::Result<bool> {
+    let mut head = [0u8; 3];
+    let mut n = 0;
+    while n < head.len() {
+        let r = f.read(&mut head[n..])?;
+        if r == 0 { break; }
+        n += r;
+    }
+    if n == head.len() && head == UTF8_BOM { return Ok(true); }
+    f.seek(SeekFrom::Start(0))?;
+    Ok(false)
+}
+
+// --- BATCH COMPRESSION ---
+
+const BATCH_MANIFEST_NAME: &str = "manifest.tsv";
+
+/// Inputs of a batch: the regular files of a directory (sorted), or the paths listed in a text
+/// file (one per line, '#' for comments).
+fn collect_batch_inputs(source: &str) -> io::Result<Vec<String>> {
+    let path = Path::new(source);
+    if path.is_dir() {
+        let mut files: Vec<String> = std::fs::read_dir(path)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|e| e.path().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        return Ok(files);
+    }
+    let text = std::fs::read_to_string(path)?;
+    Ok(text.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')).map(String::from).collect())
+}
+
+/// One input of a batch, as listed in the summary and the manifest.
+struct BatchRow {
+    status: &'static str,
+    input: String,
+    /// Archive kept for the input (none for skipped inputs)
+    output: Option<String>,
+    /// Input bytes read and archive bytes written
+    sizes: Option<(usize, usize)>,
+    time: Duration,
+    detail: String,
+}
+
+/// Runs `f` as the job compressing `file` (see JOB_FILE).
+fn as_job<T>(file: &str, f: impl FnOnce() -> T) -> T {
+    JOB_FILE.with(|job| *job.borrow_mut() = Some(file.to_string()));
+    let result = f();
+    JOB_FILE.with(|job| *job.borrow_mut() = None);
+    result
+}
+
+/// Compresses every input into `out_dir/<name>.cast`, `jobs` files at a time (--jobs), each
+/// with its own compressor. Failed inputs are skipped with a warning and recorded in the
+/// manifest while the others go on. Returns the number of skipped files.
+fn do_compress_batch(source: &str, out_dir: &str, opts: &CompressOptions, jobs: usize, solid_limit: Option<u64>, assume_yes: bool) -> usize {
+    let inputs = match collect_batch_inputs(source) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("[!]  Error: Cannot read batch source '{}': {}", source, e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(out_dir) {
+        eprintln!("[!]  Error: Cannot create output directory '{}': {}", out_dir, e);
+        std::process::exit(1);
+    }
+
+    // --max-time plans for the whole batch
+    if let Some(deadline) = &opts.deadline { deadline.queue(inputs.iter().map(|input| file_len(input)).sum()); }
+
+    // Side by side, large solid inputs are confirmed before any job starts (prompts would interleave)
+    let jobs = jobs.clamp(1, inputs.len().max(1));
+    let confirmed: Vec<Option<bool>> = inputs.iter().map(|input| {
+        (jobs > 1 && opts.chunk_bytes_limit.is_none()).then(|| as_job(input, || confirm_solid_mode(file_len(input), solid_limit, assume_yes)))
+    }).collect();
+
+    let next = AtomicUsize::new(0);
+    let rows: Mutex<Vec<Option<BatchRow>>> = Mutex::new((0..inputs.len()).map(|_| None).collect());
+    let worker = |tagged: bool| loop {
+        if opts.cancel.is_cancelled() { break; }
+        let idx = next.fetch_add(1, Ordering::Relaxed);
+        let Some(input) = inputs.get(idx) else { break };
+        let compress = || compress_batch_file(idx, inputs.len(), input, out_dir, opts, confirmed[idx], solid_limit, assume_yes);
+        let row = if tagged { as_job(input, compress) } else { compress() };
+        rows.lock().unwrap()[idx] = Some(row);
+    };
+    if jobs > 1 {
+        std::thread::scope(|scope| {
+            for _ in 0..jobs { scope.spawn(|| worker(true)); }
+        });
+    } else {
+        worker(false);
+    }
+    // Inputs not started when the batch was cancelled are left out of the manifest
+    let rows: Vec<BatchRow> = rows.into_inner().unwrap().into_iter().flatten().collect();
+
+    let mut manifest = String::from("status\tinput\toutput\tinput_bytes\toutput_bytes\tdetail\n");
+    for row in &rows {
+        let (read, written) = row.sizes.map_or((String::new(), String::new()), |(r, w)| (r.to_string(), w.to_string()));
+        manifest.push_str(&format!("{}\t{}\t{}\t{}\t{}\t{}\n", row.status, row.input, row.output.as_deref().unwrap_or(""), read, written, row.detail));
+    }
+    let manifest_path = Path::new(out_dir).join(BATCH_MANIFEST_NAME);
+    if let Err(e) = std::fs::write(&manifest_path, manifest) {
+        eprintln!("[!]  Error: Cannot write manifest '{}': {}", manifest_path.display(), e);
+    }
+
+    let archived = rows.iter().filter(|r| r.status == "OK").count();
+    let skipped = rows.len() - archived;
+    say!("\n[+]  Batch done: archived {} of {} files, {} skipped", archived, inputs.len(), skipped);
+    say!("       {:<9} {:>15} {:>15} {:>8} {:>9}  File", "Status", "Input Size", "Output Size", "Ratio", "Time");
+    for row in &rows {
+        let (read, written, ratio) = match row.sizes {
+            Some((read, written)) if written > 0 => (format_count(read as u64), format_count(written as u64), format!("{:.2}x", read as f64 / written as f64)),
+            _ => ("-".to_string(), "-".to_string(), "-".to_string()),
+        };
+        let detail = if row.detail.is_empty() { String::new() } else { format!(" ({})", row.detail) };
+        say!("       {:<9} {:>15} {:>15} {:>8} {:>8.2}s  {}{}", row.status, read, written, ratio, row.time.as_secs_f64(), row.input, detail);
+    }
+    let (total_read, total_written) = rows.iter().filter_map(|r| r.sizes).fold((0, 0), |(r, w), (read, written)| (r + read, w + written));
+    if total_written > 0 {
+        say!("       {:<9} {:>15} {:>15} {:>7.2}x {:>9}  ({} archived file(s))", "Total", format_count(total_read as u64), format_count(total_written as u64), total_read as f64 / total_written as f64, "", archived);
+    }
+    say!("       Manifest:       {}", manifest_path.display());
+    emit_event("batch", &[
+        ("files", JsonValue::Int(inputs.len() as u64)),
+        ("archived", JsonValue::Int(archived as u64)),
+        ("skipped", JsonValue::Int(skipped as u64)),
+        ("jobs", JsonValue::Int(jobs as u64)),
+        ("encoder_threads", JsonValue::Int(opts.encoder_threads().unwrap_or(num_cpus::get() as u32) as u64)),
+    ]);
+    skipped
+}
+
+/// Compresses input `idx` (of `total`) of a batch. `confirmed` is the answer to the solid mode
+/// question when it was asked before the batch started.
+#[allow(clippy::too_many_arguments)]
+fn compress_batch_file(idx: usize, total: usize, input: &str, out_dir: &str, opts: &CompressOptions, confirmed: Option<bool>, solid_limit: Option<u64>, assume_yes: bool) -> BatchRow {
+    let name = Path::new(input).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| format!("input_{}", idx));
+    let output = Path::new(out_dir).join(format!("{}.cast", name)).to_string_lossy().into_owned();
+    let position = format!("[{}/{}]", idx + 1, total);
+    // A job reports its file in one line when it starts and one when it is done
+    let job_line = |line: String| if !decorated_output() && !json_output() { println!("{}", line); };
+
+    say!("\n[*]  {} {}", position, input);
+    job_line(format!("[*]  {} {}", position, input));
+    let start = Instant::now();
+    let result = if opts.chunk_bytes_limit.is_none() && !confirmed.unwrap_or_else(|| confirm_solid_mode(file_len(input), solid_limit, assume_yes)) {
+        Err("Solid mode not confirmed".to_string())
+    } else {
+        do_compress(input, &output, opts)
+    };
+    let time = start.elapsed();
+
+    let row = |status, output: Option<String>, sizes, detail| BatchRow { status, input: input.to_string(), output, sizes, time, detail };
+    match result {
+        Ok((read, written)) => {
+            job_line(format!("[+]  {} {}: {} -> {} ({:.2}x) in {:.2}s", position, input, format_bytes(read), format_bytes(written), read as f64 / written.max(1) as f64, time.as_secs_f64()));
+            row("OK", Some(output), Some((read, written)), String::new())
+        },
+        Err(_) if opts.cancel.is_cancelled() => {
+            let kept = opts.on_cancel == CancelPolicy::Keep;
+            eprintln!("\n[!]  Cancelled: batch stopped at '{}'.", input);
+            row("CANCELLED", kept.then_some(output), None, String::new())
+        },
+        Err(e) => {
+            eprintln!("\n[!]  WARNING: Skipping '{}': {}", input, e);
+            emit_error("compress", &format!("Skipping '{}': {}", input, e));
+            row("SKIPPED", None, None, e)
+        }
+    }
+}
+
+// --- CONVERSION ---
+
+/// One input of --convert, as listed in the summary.
+struct ConvertRow {
+    status: &'static str,
+    input: String,
+    /// Size of the input as stored (.gz/.xz) and of its archive
+    old_size: Option<u64>,
+    new_size: Option<u64>,
+    detail: String,
+}
+
+/// Converts every input into `out_dir` (see "CONVERSION" in `cast::convert`), verifies each
+/// archive and deletes the source of a verified one with `delete_source`. Failed inputs are
+/// reported and skipped; the summary lists every input. Returns the number of failures.
+fn do_convert(sources: &[String], out_dir: &str, opts: &CompressOptions, decompress_opts: &DecompressOptions, delete_source: bool, solid_limit: Option<u64>, assume_yes: bool) -> usize {
+    let (inputs, missing) = match collect_convert_inputs(sources, Path::new(out_dir)) {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("[!]  Error: Cannot read the inputs to convert: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if inputs.is_empty() && missing.is_empty() {
+        eprintln!("[!]  Error: No files to convert.");
+        std::process::exit(1);
+    }
+    if let Err(e) = std::fs::create_dir_all(out_dir) {
+        eprintln!("[!]  Error: Cannot create output directory '{}': {}", out_dir, e);
+        std::process::exit(1);
+    }
+    let count = |encoding: InputEncoding| inputs.iter().filter(|i| i.encoding == encoding).count();
+    say!("       Inputs:      {} file(s): {} gzip, {} xz, {} plain", inputs.len(), count(InputEncoding::Gzip), count(InputEncoding::Xz), count(InputEncoding::Plain));
+
+    let mut rows: Vec<ConvertRow> = Vec::new();
+    for arg in missing {
+        eprintln!("[!]  WARNING: Skipping '{}': no such file or directory", arg);
+        emit_error("compress", &format!("Skipping '{}': no such file or directory", arg));
+        rows.push(ConvertRow { status: "FAILED", input: arg, old_size: None, new_size: None, detail: "no such file or directory".to_string() });
+    }
+    // Archive paths already written, with the input they hold
+    let mut written: HashMap<PathBuf, String> = HashMap::new();
+
+    for (idx, ConvertInput { path, output, encoding }) in inputs.iter().enumerate() {
+        let input = path.to_string_lossy().into_owned();
+        let output_path = Path::new(out_dir).join(output);
+        let output = output_path.to_string_lossy().into_owned();
+        let old_size = std::fs::metadata(path).ok().map(|m| m.len());
+
+        say!("\n[*]  [{}/{}] {} ({}) -> {}", idx + 1, inputs.len(), input, encoding.label(), output);
+        let result = match written.get(&output_path) {
+            Some(first) => Err(format!("'{}' is converted to the same archive", first)),
+            None => {
+                written.insert(output_path.clone(), input.clone());
+                convert_file(&input, &output, *encoding, opts, decompress_opts, solid_limit, assume_yes)
+            },
+        };
+
+        match result {
+            Ok(new_size) => {
+                let detail = match delete_source {
+                    false => String::new(),
+                    true => match std::fs::remove_file(path) {
+                        Ok(()) => "source deleted".to_string(),
+                        Err(e) => {
+                            eprintln!("[!]  WARNING: Cannot delete '{}': {}", input, e);
+                            format!("source kept: {}", e)
+                        },
+                    },
+                };
+                rows.push(ConvertRow { status: "OK", input, old_size, new_size: Some(new_size), detail });
+            },
+            // The remaining inputs are left out of the summary
+            Err(_) if opts.cancel.is_cancelled() => {
+                eprintln!("\n[!]  Cancelled: conversion stopped at '{}'.", input);
+                rows.push(ConvertRow { status: "CANCELLED", input, old_size, new_size: None, detail: String::new() });
+                break;
+            },
+            Err(e) => {
+                eprintln!("\n[!]  WARNING: Skipping '{}': {}", input, e);
+                emit_error("compress", &format!("Skipping '{}': {}", input, e));
+                rows.push(ConvertRow { status: "FAILED", input, old_size, new_size: None, detail: e });
+            }
+        }
+    }
+
+    let converted = rows.iter().filter(|r| r.status == "OK").count();
+    let failed = rows.iter().filter(|r| r.status == "FAILED").count();
+    say!("\n[+]  Conversion done: converted {} of {} files, {} failed", converted, rows.len(), failed);
+    say!("       {:<9} {:>15} {:>15} {:>8}  File", "Status", "Old Size", "New Size", "Ratio");
+    let size = |n: Option<u64>| n.map_or("-".to_string(), format_count);
+    for row in &rows {
+        let ratio = match (row.old_size, row.new_size) {
+            (Some(old), Some(new)) if new > 0 => format!("{:.2}x", old as f64 / new as f64),
+            _ => "-".to_string(),
+        };
+        let detail = if row.detail.is_empty() { String::new() } else { format!(" ({})", row.detail) };
+        say!("       {:<9} {:>15} {:>15} {:>8}  {}{}", row.status, size(row.old_size), size(row.new_size), ratio, row.input, detail);
+    }
+    let ok_rows = || rows.iter().filter(|r| r.status == "OK");
+    let (total_old, total_new): (u64, u64) = (ok_rows().filter_map(|r| r.old_size).sum(), ok_rows().filter_map(|r| r.new_size).sum());
+    if total_new > 0 {
+        say!("       {:<9} {:>15} {:>15} {:>7.2}x  ({} converted file(s))", "Total", format_count(total_old), format_count(total_new), total_old as f64 / total_new as f64, converted);
+    }
+    failed
+}
+
+/// Compresses one input of --convert to `output_path` and verifies the archive. Returns the
+/// archive size.
+fn convert_file(input_path: &str, output_path: &str, encoding: InputEncoding, opts: &CompressOptions, decompress_opts: &DecompressOptions, solid_limit: Option<u64>, assume_yes: bool) -> Result<u64, String> {
+    if let Some(parent) = Path::new(output_path).parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Cannot create output directory: {}", e))?;
+    }
+    if opts.chunk_bytes_limit.is_none() {
+        // The decoded size recorded at the end of the input (a hint: see `decoded_len_hint`)
+        let decoded_len = decoded_len_hint(Path::new(input_path), encoding).ok().flatten().unwrap_or(0);
+        if !confirm_solid_mode(decoded_len, solid_limit, assume_yes) { return Err("Solid mode not confirmed".to_string()); }
+    }
+    do_compress(input_path, output_path, opts)?;
+    // An archive that fails its verification is not kept either
+    let mut created = CreatedOutput::new(output_path, opts);
+    let verify_start = Instant::now();
+    let restored = verify_converted(output_path, decompress_opts).map_err(|e| format!("Verification failed: {}", e))?;
+    created.finished = true;
+    say!("       Verified:       {} restored, SHA-256 matches ({:.2}s)", format_bytes(restored as usize), verify_start.elapsed().as_secs_f64());
+    Ok(file_len(output_path))
+}
+
+/// Decodes the archive at `path` and checks the restored data against the size and SHA-256
+/// its header records (chunk checksums are checked on the way). Returns the restored size.
+fn verify_converted(path: &str, decompress_opts: &DecompressOptions) -> Result<u64, String> {
+    let f_in = decompress_opts.open(path).map_err(|e| format!("Cannot open archive: {}", e))?;
+    let mut reader = ChunkReader::new(io::BufReader::new(f_in)).map_err(|e| e.to_string())?;
+    let archive = reader.archive_header().clone();
+    let decompressor = decompress_opts.decompressor(&archive);
+    let mut hasher = Sha256::default();
+    let mut restored = 0;
+    if archive.has_bom() {
+        hasher.update(&UTF8_BOM);
+        restored += UTF8_BOM.len() as u64;
+    }
+    let mut block = Vec::new();
+    let mut chunk_idx = 0;
+    while let Some(chunk) = reader.next_resolved().map_err(|e| e.to_string())? {
+        if decompress_opts.cancel.is_cancelled() { return Err(CastError::Cancelled.to_string()); }
+        chunk_idx += 1;
+        block.clear();
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut block)
+            .map_err(|e| e.at_chunk(chunk_idx).to_string())?;
+        hasher.update(&block);
+        restored += block.len() as u64;
+    }
+    if let Some(size) = archive.original_size.filter(|&size| size != restored) {
+        return Err(format!("restored {} bytes, the archive records {}", format_count(restored), format_count(size)));
+    }
+    if archive.content_digest().is_some_and(|digest| hasher.finalize() != digest) {
+        return Err("the restored data does not match the SHA-256 recorded in the archive".to_string());
+    }
+    Ok(restored)
+}
+
+// --- CANCELLATION ---
+
+// Exit status of a cancelled run (as for a shell command stopped by Ctrl+C)
+const EXIT_CANCELLED: i32 = 130;
+
+// Token cancelled by the interrupt handler (signal handlers cannot capture state)
+static INTERRUPT_TOKEN: std::sync::OnceLock<CancellationToken> = std::sync::OnceLock::new();
+
+/// Makes Ctrl+C (and SIGTERM / console close on Windows) cancel `token` instead of killing
+/// the process, so the run can clean up. A second Ctrl+C terminates as usual.
+fn cancel_on_interrupt(token: &CancellationToken) {
+    if INTERRUPT_TOKEN.set(token.clone()).is_err() { return; }
+    install_interrupt_handler();
+}
+
+#[cfg(unix)]
+fn install_interrupt_handler() {
+    extern "C" fn on_signal(_: libc::c_int) {
+        if let Some(token) = INTERRUPT_TOKEN.get() { token.cancel(); }
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGTERM, libc::SIG_DFL);
+        }
+    }
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(windows)]
+fn install_interrupt_handler() {
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<unsafe extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+    unsafe extern "system" fn on_console_event(_: u32) -> i32 {
+        match INTERRUPT_TOKEN.get() {
+            // Already cancelled: fall through to the default handler (terminate)
+            Some(token) if !token.is_cancelled() => { token.cancel(); 1 },
+            _ => 0,
+        }
+    }
+    unsafe { SetConsoleCtrlHandler(Some(on_console_event), 1); }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn install_interrupt_handler() {}
+
+/// Reports the cancellation with `note` (see `cancel_note`) and exits.
+fn exit_cancelled(command: &str, note: &str) -> ! {
+    eprintln!("\n[!]  Cancelled: {}.", note);
+    emit_error(command, &CastError::Cancelled.to_string());
+    std::process::exit(EXIT_CANCELLED);
+}
+
+/// Applies the cancel policy to `output_path` (already closed by the caller) and tells what it
+/// did.
+fn cancel_note(output_path: &str, policy: CancelPolicy) -> &'static str {
+    if output_path != "-" { let _ = policy.apply(Path::new(output_path), None); }
+    cancel_outcome(output_path, policy)
+}
+
+/// What the cancel policy does with `output_path` (applied already when compressing, see
+/// `CreatedOutput`).
+fn cancel_outcome(output_path: &str, policy: CancelPolicy) -> &'static str {
+    match policy {
+        _ if output_path == "-" => "output already written to stdout",
+        CancelPolicy::Delete => "partial output removed",
+        CancelPolicy::Keep => "partial output kept up to the last complete chunk",
+    }
+}
+
+/// Ends a cancelled restore: the output is closed and, when kept, cut back to the `complete`
+/// bytes of the chunks restored before the cancellation.
+fn exit_restore_cancelled(f_out: RestoreOutput, output_path: &str, complete: u64, policy: CancelPolicy) -> ! {
+    let _ = f_out.finish();
+    exit_restore_cut(output_path, complete, policy)
+}
+
+/// Ends a cancelled restore whose output is already closed (see `exit_restore_cancelled`).
+fn exit_restore_cut(output_path: &str, complete: u64, policy: CancelPolicy) -> ! {
+    if output_path != "-" { let _ = policy.apply(Path::new(output_path), Some(complete)); }
+    let note = cancel_outcome(output_path, policy);
+    eprintln!("\n[!]  Cancelled after restoring {} in complete chunks: {}.", format_bytes(complete as usize), note);
+    emit_error("decompress", &CastError::Cancelled.to_string());
+    std::process::exit(EXIT_CANCELLED);
+}
+
+/// Ends a cancelled verification (nothing was written, so there is nothing to clean up).
+fn exit_verify_cancelled(chunks: usize) -> ! {
+    eprintln!("\n[!]  Verification cancelled after {} chunk(s).", chunks);
+    emit_error("verify", &CastError::Cancelled.to_string());
+    std::process::exit(EXIT_CANCELLED);
+}
+
+// --- DECOMPRESSION ---
+
+// How the restored file is written (selected with --mmap-out / --sparse / --output-encoding)
+#[derive(Clone, Copy, PartialEq)]
+enum RestoreTarget { Stream, Mapped, Sparse, Encoded(OutputEncoding) }
+
+// Destination of a restore: a regular stream, a memory-mapped file (--mmap-out), a file
+// with holes for zero blocks (--sparse), or a gzip/zstd stream (--output-encoding)
+enum RestoreOutput {
+    Stream(Box<dyn Write>),
+    #[cfg(feature = "mmap")]
+    Mapped(MmapWriter),
+    Sparse(SparseWriter),
+    Encoded(EncodedWriter<BufWriter<Box<dyn Write>>>),
+}
+
+impl RestoreOutput {
+    #[cfg_attr(not(feature = "mmap"), allow(unused_variables))]
+    /// `size_hint` is the initial length of a mapped output (see `output_size_hint`).
+    /// `io_limit` throttles stream and encoded outputs (mapped and sparse ones reject it).
+    fn open(output_path: &str, target: RestoreTarget, size_hint: u64, io_limit: Option<IoLimit>) -> io::Result<Self> {
+        let out: Box<dyn Write> = if output_path == "-" {
+            Box::new(ThrottledWriter::new(io::stdout().lock(), io_limit))
+        } else {
+            #[cfg(feature = "mmap")]
+            if target == RestoreTarget::Mapped {
+                // Grown on demand past the hint, trimmed to the written length at the end
+                return Ok(RestoreOutput::Mapped(MmapWriter::create(output_path, size_hint)?));
+            }
+            if target == RestoreTarget::Sparse { return Ok(RestoreOutput::Sparse(SparseWriter::create(output_path)?)); }
+            Box::new(ThrottledWriter::new(File::create(output_path)?, io_limit))
+        };
+        match target {
+            // The encoders write in small pieces
+            RestoreTarget::Encoded(encoding) => Ok(RestoreOutput::Encoded(EncodedWriter::new(BufWriter::new(out), encoding)?)),
+            _ => Ok(RestoreOutput::Stream(out)),
+        }
+    }
+
+    /// Flushes the output. Returns the size of the encoded file for --output-encoding.
+    fn finish(self) -> io::Result<Option<u64>> {
+        match self {
+            RestoreOutput::Stream(mut w) => w.flush().map(|_| None),
+            #[cfg(feature = "mmap")]
+            RestoreOutput::Mapped(m) => m.finish().map(|_| None),
+            RestoreOutput::Sparse(s) => s.finish().map(|_| None),
+            RestoreOutput::Encoded(e) => e.finish().map(Some),
+        }
+    }
+}
+
+/// Initial length of a mapped output: the original size the archive header records when all of
+/// it is restored, otherwise a guess from the archive size (never past the original).
+fn output_size_hint(archive: &ArchiveHeader, archive_len: u64, whole: bool) -> u64 {
+    let guess = archive_len.saturating_mul(8);
+    match archive.original_size {
+        Some(size) if whole => size,
+        Some(size) => size.min(guess),
+        None => guess,
+    }
+}
+
+impl Write for RestoreOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RestoreOutput::Stream(w) => w.write(buf),
+            #[cfg(feature = "mmap")]
+            RestoreOutput::Mapped(m) => m.write(buf),
+            RestoreOutput::Sparse(s) => s.write(buf),
+            RestoreOutput::Encoded(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RestoreOutput::Stream(w) => w.flush(),
+            #[cfg(feature = "mmap")]
+            RestoreOutput::Mapped(m) => m.flush(),
+            RestoreOutput::Sparse(s) => s.flush(),
+            RestoreOutput::Encoded(e) => e.flush(),
+        }
+    }
+}
+
+// Fails every write once `cancel` is set, so the writes of a loop the run does not drive itself
+// (generate, the copy of relabel) stop at the next buffer flush
+struct CancellableWriter<'a, W: Write> {
+    inner: W,
+    cancel: &'a CancellationToken,
+}
+
+impl<W: Write> Write for CancellableWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.cancel.is_cancelled() { return Err(io::Error::other(CastError::Cancelled.to_string())); }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+// Counts the bytes passing through to the restore output (for the JSON chunk events and
+// the progress meter)
+struct CountingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    count: u64,
+    meter: &'a Mutex<ProgressMeter>,
+    // Row order the written bytes are folded into (see ROW_ORDER_LABEL)
+    order: Option<&'a mut RowOrder>,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        if let Ok(mut m) = self.meter.lock() { m.output(n as u64); }
+        if let Some(order) = self.order.as_deref_mut() { order.update(&buf[..n]); }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+// --- PROGRESS ---
+
+// Minimum time between two progress updates (they come from the backend every few KB)
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Progress of a restore, so single-chunk (solid) archives do not sit silent for minutes:
+/// backend decoding of the current chunk, then restored bytes against the original size
+/// when the archive records it. Updates are throttled to `PROGRESS_INTERVAL`.
+struct ProgressMeter {
+    enabled: bool,
+    // First restored byte (the rate leaves out the backend decoding time)
+    write_start: Option<Instant>,
+    last: Instant,
+    chunk: usize,
+    original_size: Option<u64>,
+    written: u64,
+}
+
+impl ProgressMeter {
+    fn new(enabled: bool, original_size: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self { enabled, write_start: None, last: now, chunk: 0, original_size, written: 0 }
+    }
+
+    fn due(&mut self) -> bool {
+        if !self.enabled || self.last.elapsed() < PROGRESS_INTERVAL { return false; }
+        self.last = Instant::now();
+        true
+    }
+
+    fn backend(&mut self, event: ProgressEvent) {
+        let ProgressEvent::Backend { consumed, total } = event;
+        if !self.due() { return; }
+        if json_output() {
+            self.emit("decode", consumed, Some(total));
+        } else {
+            print!("\r      Extracting Chunk #{}... decoding {:.0}%          ", self.chunk, percent(consumed, total));
+            io::stdout().flush().ok();
+        }
+    }
+
+    fn output(&mut self, bytes: u64) {
+        self.written += bytes;
+        let write_start = *self.write_start.get_or_insert_with(Instant::now);
+        // Let the rate settle before the first report
+        if write_start.elapsed() < PROGRESS_INTERVAL || !self.due() { return; }
+        if json_output() {
+            self.emit("write", self.written, self.original_size);
+            return;
+        }
+        let mb_s = self.written as f64 / (1024.0 * 1024.0) / write_start.elapsed().as_secs_f64().max(1e-3);
+        match self.original_size {
+            Some(total) => print!("\r      Extracting Chunk #{}... {:.0}% ({:.1} MB/s)          ", self.chunk, percent(self.written, total), mb_s),
+            None => print!("\r      Extracting Chunk #{}... {} ({:.1} MB/s)          ", self.chunk, format_bytes(self.written as usize), mb_s),
+        }
+        io::stdout().flush().ok();
+    }
+
+    fn emit(&self, stage: &str, done: u64, total: Option<u64>) {
+        let mut fields = vec![
+            ("command", JsonValue::Str("decompress")),
+            ("idx", JsonValue::Int(self.chunk as u64)),
+            ("stage", JsonValue::Str(stage)),
+            ("done", JsonValue::Int(done)),
+        ];
+        if let Some(t) = total { fields.push(("total", JsonValue::Int(t))); }
+        emit_event("progress", &fields);
+    }
+}
+
+fn percent(done: u64, total: u64) -> f64 {
+    if total == 0 { 100.0 } else { (done as f64 * 100.0 / total as f64).min(100.0) }
+}
+
+/// Backend choice, memory budget and cancellation shared by every command that decodes an archive.
+struct DecompressOptions {
+    use_7zip: bool,
+    budget: Option<MemoryBudget>,
+    /// liblzma memory limit of the native decoder (--mem-limit-decompress)
+    mem_limit: Option<u64>,
+    cancel: CancellationToken,
+    on_cancel: CancelPolicy,
+    io_limit: Option<IoLimit>,
+    /// Parallel workers share the archive through one locked handle (--locked-reads)
+    locked_reads: bool,
+    /// Given to restored files once they are complete (--chmod, --chown, ...)
+    attributes: OutputAttributes,
+}
+
+impl DecompressOptions {
+    /// Opens the archive (or any other input of the run) through the --io-limit budget.
+    fn open(&self, path: &str) -> io::Result<ThrottledReader<File>> {
+        File::open(path).map(|f| ThrottledReader::new(f, self.io_limit.clone()))
+    }
+
+    /// Opens the archive once for all parallel workers: positioned reads on one handle, or
+    /// seek + read behind a lock with --locked-reads.
+    fn open_shared(&self, path: &str) -> io::Result<ThrottledReader<Box<dyn ReadAt + Send + Sync>>> {
+        let f: Box<dyn ReadAt + Send + Sync> = if self.locked_reads { Box::new(SeekLock::new(File::open(path)?)) } else { open_shared(path)? };
+        Ok(ThrottledReader::new(f, self.io_limit.clone()))
+    }
+
+    /// Creates an output file that writes through the --io-limit budget.
+    fn create(&self, path: &Path) -> io::Result<ThrottledWriter<File>> {
+        File::create(path).map(|f| ThrottledWriter::new(f, self.io_limit.clone()))
+    }
+
+    fn backend(&self) -> RuntimeLzmaDecompressor {
+        if self.use_7zip {
+            return RuntimeLzmaDecompressor::SevenZip(SevenZipDecompressorBackend);
+        }
+        let mut backend = LzmaDecompressorBackend::new().with_cancellation(self.cancel.clone());
+        if let Some(b) = self.budget { backend = backend.with_budget(b); }
+        if let Some(limit) = self.mem_limit { backend = backend.with_mem_limit(limit); }
+        RuntimeLzmaDecompressor::Native(backend)
+    }
+
+    /// Decompressor for the chunks of `archive` (whose original size bounds their rows).
+    fn decompressor(&self, archive: &ArchiveHeader) -> CASTLzmaDecompressor {
+        CASTLzmaDecompressor::new(self.backend()).with_cancellation(self.cancel.clone()).with_original_size(archive.original_size)
+    }
+
+    /// Decompressor using the other backend (the second decode of --strict-verify).
+    fn other_decompressor(&self, archive: &ArchiveHeader) -> CASTLzmaDecompressor {
+        DecompressOptions { use_7zip: !self.use_7zip, cancel: self.cancel.clone(), io_limit: self.io_limit.clone(), ..*self }.decompressor(archive)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        if self.use_7zip { "7-Zip" } else { "native" }
+    }
+}
+
+// --- UP-TO-DATE OUTPUTS (--skip-existing) ---
+
+// Read size while hashing an existing output
+const EXISTING_HASH_BUFFER: usize = 1024 * 1024;
+
+/// How an existing output compares with the original recorded in the archive header.
+enum ExistingOutput {
+    Missing,
+    /// Same size and SHA-256
+    UpToDate,
+    /// Another size (or the archive does not record its size)
+    SizeDiffers,
+    /// Same size, other content
+    DigestDiffers,
+    /// Same size, but the archive records no SHA-256 to compare with
+    NoDigest,
+}
+
+/// Compares `output_path` with the original of `archive`. The output is only read (in
+/// EXISTING_HASH_BUFFER pieces) when its size already matches.
+fn check_existing_output(output_path: &str, archive: &ArchiveHeader, decompress_opts: &DecompressOptions) -> io::Result<ExistingOutput> {
+    let len = match std::fs::metadata(output_path) {
+        Ok(m) if m.is_file() => m.len(),
+        // Not a file: restoring reports the problem
+        Ok(_) => return Ok(ExistingOutput::Missing),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ExistingOutput::Missing),
+        Err(e) => return Err(e),
+    };
+    if archive.original_size != Some(len) { return Ok(ExistingOutput::SizeDiffers); }
+    let Some(expected) = archive.content_digest() else { return Ok(ExistingOutput::NoDigest) };
+    let mut f = decompress_opts.open(output_path)?;
+    let mut hasher = Sha256::default();
+    let mut buf = vec![0u8; EXISTING_HASH_BUFFER];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 { break; }
+        hasher.update(&buf[..n]);
+    }
+    Ok(if hasher.finalize() == expected { ExistingOutput::UpToDate } else { ExistingOutput::DigestDiffers })
+}
+
+/// --skip-existing: whether `output_path` already holds the original of the archive, so the
+/// restore can be skipped. A same-size output that cannot be confirmed is restored again, or
+/// is an error with `strict`.
+fn output_is_up_to_date(input_path: &str, output_path: &str, decompress_opts: &DecompressOptions, strict: bool) -> bool {
+    let fail = |msg: String| -> ! {
+        eprintln!("[!]  Error: {}", msg);
+        emit_error("decompress", &msg);
+        std::process::exit(1);
+    };
+    // An unreadable archive is reported by the restore itself
+    let Ok(archive) = decompress_opts.open(input_path).map_err(CastError::from)
+        .and_then(|f| ArchiveHeader::read_from(&mut io::BufReader::new(f))) else { return false };
+    let state = check_existing_output(output_path, &archive, decompress_opts)
+        .unwrap_or_else(|e| fail(format!("Cannot read '{}': {}", output_path, e)));
+    match state {
+        ExistingOutput::Missing => false,
+        ExistingOutput::UpToDate => {
+            say!("[+]  Up to date: {} matches the archive (size and SHA-256). Skipped.", output_path);
+            emit_event("done", &[
+                ("command", JsonValue::Str("decompress")),
+                ("bytes_out", JsonValue::Int(archive.original_size.unwrap_or(0))),
+                ("result", JsonValue::Str("up_to_date")),
+            ]);
+            true
+        },
+        ExistingOutput::SizeDiffers => {
+            say!("[*]  {} exists with another size: restoring it again.", output_path);
+            false
+        },
+        ExistingOutput::DigestDiffers if strict => fail(format!("'{}' has the size of the original but different content (SHA-256).", output_path)),
+        ExistingOutput::DigestDiffers => {
+            say!("[*]  {} has the size of the original but different content: restoring it again.", output_path);
+            false
+        },
+        ExistingOutput::NoDigest if strict => fail(format!("'{}' has the size of the original, but the archive records no SHA-256 to confirm it (compressed by an older version).", output_path)),
+        ExistingOutput::NoDigest => {
+            say!("[*]  {} has the size of the original, but the archive records no SHA-256 to confirm it: restoring it again.", output_path);
+            false
+        },
+    }
+}
+
+fn do_decompress(input_path: &str, output_path: &str, decompress_opts: &DecompressOptions, mut limit: Option<OutputLimit>, target: RestoreTarget) {
+    let start = Instant::now();
+    let waited_before = decompress_opts.io_limit.as_ref().map_or(Duration::ZERO, |l| l.waited());
+    emit_event("start", &[("command", JsonValue::Str("decompress")), ("input", JsonValue::Str(input_path)), ("output", JsonValue::Str(output_path))]);
+    let f_in = decompress_opts.open(input_path).expect("Error opening archive");
+    let archive_len = f_in.get_ref().metadata().unwrap().len();
+
+    if archive_len == 0 {
+        eprintln!("[!] ERROR: Input file is empty (0 bytes).");
+        emit_error("decompress", "Input file is empty (0 bytes)");
+        return;
+    }
+
+    let mut reader = match ChunkReader::new(std::io::BufReader::new(f_in)) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("[!] ERROR: {}", e);
+            emit_error("decompress", &e.to_string());
+            std::process::exit(1);
+        }
+    };
+    let archive = reader.archive_header().clone();
+    let to_stdout = output_path == "-";
+    // With a valid sidecar every chunk's place in the output is known: the chunks are
+    // restored in parallel, each group written in place (a limit or a transcoded, mapped or
+    // sparse output needs the sequential path)
+    if !to_stdout && limit.is_none() && target == RestoreTarget::Stream {
+        match ArchiveIndex::lookup(input_path) {
+            IndexLookup::Valid(index) if index.entries.len() > 1 => return do_decompress_indexed(input_path, output_path, decompress_opts, &archive, &index),
+            IndexLookup::Stale(reason) => eprintln!("[!]  Ignoring index ({}): restoring sequentially.", reason),
+            _ => {},
+        }
+    }
+    let size_hint = output_size_hint(&archive, archive_len, limit.is_none());
+    let mut f_out = RestoreOutput::open(output_path, target, size_hint, decompress_opts.io_limit.clone()).expect("Error creating output");
+
+    let meter = Arc::new(Mutex::new(ProgressMeter::new(!to_stdout, archive.original_size)));
+    let backend_meter = meter.clone();
+    let decompressor = CASTLzmaDecompressor::new(decompress_opts.backend()).with_cancellation(decompress_opts.cancel.clone()).with_progress(move |event| {
+        if let Ok(mut m) = backend_meter.lock() { m.backend(event); }
+    });
+    let mut chunk_idx = 0;
+    let mut total_in = archive.encoded_len() as u64;
+    let mut total_out = 0u64;
+    // Only a full restore can be checked against the recorded row order
+    let mut row_order = archive.row_order().filter(|_| limit.is_none()).map(|_| RowOrder::default());
+
+    if !to_stdout { say!("\n[*]  Extracting stream..."); }
+    if archive.checksum() == ChecksumKind::None {
+        eprintln!("[!]  Archive was created without checksums: integrity cannot be checked.");
+    }
+
+    if archive.has_bom() {
+        let end = match limit.as_mut() { Some(l) => l.cut(&UTF8_BOM), None => UTF8_BOM.len() };
+        if let Err(e) = f_out.write_all(&UTF8_BOM[..end]) {
+            eprintln!("\n[!]  Error: Writing output failed: {}", e);
+            emit_error("decompress", &format!("Writing output failed: {}", e));
+            std::process::exit(1);
+        }
+        total_out += end as u64;
+    }
+
+    loop {
+        if limit.is_some_and(|l| l.is_exhausted()) { break; }
+        if decompress_opts.cancel.is_cancelled() { exit_restore_cancelled(f_out, output_path, total_out, decompress_opts.on_cancel); }
+
+        let chunk = match reader.next_chunk() {
+            Ok(Some(c)) => c,
+            Ok(None) => {
+                // Headered archives of empty (or BOM-only) inputs legitimately hold no chunks
+                if chunk_idx == 0 && archive.version == LEGACY_VERSION {
+                    eprintln!("[!] ERROR: File header missing or corrupted.");
+                }
+                break;
+            },
+            Err(e) => {
+                eprintln!("\n[!]  CRASH: {}", e);
+                emit_error("decompress", &e.to_string());
+                std::process::exit(1);
+            },
+        };
+        // A duplicate chunk is counted as stored but restores the chunk it refers to
+        let chunk_in = (CHUNK_HEADER_SIZE + chunk.body.len()) as u64;
+        let chunk = match reader.resolve(chunk) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("\n[!]  CRASH: {}", e);
+                emit_error("decompress", &e.to_string());
+                std::process::exit(1);
+            },
+        };
+
+        chunk_idx += 1;
+        if let Ok(mut m) = meter.lock() { m.chunk = chunk_idx; }
+
+        if !to_stdout && !json_output() {
+            print!("\r      Extracting Chunk #{}... ", chunk_idx);
+            io::stdout().flush().unwrap();
+        }
+
+        let mut counted = CountingWriter { inner: &mut f_out, count: 0, meter: &meter, order: row_order.as_mut() };
+        let result = match limit.as_mut() {
+            Some(l) => decompressor.decompress_limited(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut counted, l),
+            None => decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut counted),
+        };
+        let chunk_out = counted.count;
+        if let Some(order) = row_order.as_mut() { order.end_chunk(); }
+
+        match result {
+            Ok(_) => {},
+            Err(_) if decompress_opts.cancel.is_cancelled() => exit_restore_cancelled(f_out, output_path, total_out, decompress_opts.on_cancel),
+            Err(e) => {
+                let message = format!("Decompression error at Chunk {}: {}", chunk_idx, e.at_chunk(chunk_idx));
+                eprintln!("\n[!]  CRASH: {}", message);
+                emit_error("decompress", &message);
+                std::process::exit(1);
+            }
+        }
+
+        total_in += chunk_in;
+        total_out += chunk_out;
+        emit_event("chunk", &[
+            ("command", JsonValue::Str("decompress")),
+            ("idx", JsonValue::Int(chunk_idx as u64)),
+            ("bytes_in", JsonValue::Int(chunk_in)),
+            ("bytes_out", JsonValue::Int(chunk_out)),
+        ]);
+    }
+
+    let encoded_len = match f_out.finish() {
+        Ok(len) => len,
+        Err(e) => {
+            eprintln!("\n[!]  Error: Writing output failed: {}", e);
+            emit_error("decompress", &format!("Writing output failed: {}", e));
+            std::process::exit(1);
+        }
+    };
+    // The output is complete: it is kept for inspection
+    if let Some(Err(message)) = row_order.map(|order| check_row_order(&archive, order)) {
+        eprintln!("\n[!]  CRASH: {}", message);
+        emit_error("decompress", &message);
+        std::process::exit(1);
+    }
+    if !to_stdout { apply_output_attributes(&[Path::new(output_path)], &archive, decompress_opts); }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    if chunk_idx > 0 && !to_stdout {
+        say!("\n[+]  Decompression done in {:.2}s", elapsed);
+    }
+    if let (Some(len), RestoreTarget::Encoded(encoding)) = (encoded_len, target) {
+        if !to_stdout {
+            say!("       Restored:    {}", format_bytes(total_out as usize));
+            say!("       Encoded:     {} ({})", format_bytes(len as usize), encoding.label());
+        }
+    }
+    let mut done_fields = vec![
+        ("command", JsonValue::Str("decompress")),
+        ("chunks", JsonValue::Int(chunk_idx as u64)),
+        ("bytes_in", JsonValue::Int(total_in)),
+        ("bytes_out", JsonValue::Int(total_out)),
+        ("ratio", JsonValue::Float(if total_in > 0 { total_out as f64 / total_in as f64 } else { 0.0 })),
+        ("time", JsonValue::Float(elapsed)),
+    ];
+    if let Some(len) = encoded_len { done_fields.push(("bytes_encoded", JsonValue::Int(len))); }
+    report_throttle_wait(decompress_opts.io_limit.as_ref(), waited_before, "       Throttled:   ", !to_stdout, &mut done_fields);
+    emit_event("done", &done_fields);
+}
+
+// Groups per restore thread: smaller groups balance chunks of uneven cost across the threads
+const RESTORE_GROUPS_PER_THREAD: usize = 4;
+
+// Outcome of each chunk (or group of chunks) decoded by the workers of an indexed restore or
+// verification: the bytes restored and their row order, `None` until it is decoded
+type IndexedResults = Mutex<Vec<Option<Result<(u64, RowOrder), String>>>>;
+
+/// Restores the archive with the chunk offsets of its sidecar index. The output is sized once,
+/// the chunks are split into contiguous groups and every worker decodes a group straight into
+/// its place in the file (see "POSITIONED OUTPUT"). The result is byte-identical to the
+/// sequential restore.
+fn do_decompress_indexed(input_path: &str, output_path: &str, decompress_opts: &DecompressOptions, archive: &ArchiveHeader, index: &ArchiveIndex) {
+    let start = Instant::now();
+    let waited_before = decompress_opts.io_limit.as_ref().map_or(Duration::ZERO, |l| l.waited());
+    let fail = |msg: String| -> ! {
+        eprintln!("\n[!]  CRASH: {}", msg);
+        emit_error("decompress", &msg);
+        std::process::exit(1);
+    };
+    let mut threads = num_cpus::get().min(index.entries.len()).max(1);
+    if let Some(budget) = decompress_opts.budget {
+        let largest = index.entries.iter().map(|e| e.data_len).max().unwrap_or(0);
+        threads = budget.decode_workers(largest, threads);
+    }
+    let groups = plan_shards(&index.entries, ShardCount::Count(threads * RESTORE_GROUPS_PER_THREAD));
+    say!("\n[*]  Restoring {} chunks with {} thread(s) (indexed)...", index.entries.len(), threads);
+    if archive.checksum() == ChecksumKind::None {
+        eprintln!("[!]  Archive was created without checksums: integrity cannot be checked.");
+    }
+
+    let bom: &[u8] = if archive.has_bom() { &UTF8_BOM } else { &[] };
+    let output_len = index.entries.last().map_or(0, |e| e.data_offset + e.data_len);
+    let f = decompress_opts.open_shared(input_path).unwrap_or_else(|e| fail(format!("Cannot open archive: {}", e)));
+    let sink = PositionedSink::create(Path::new(output_path), output_len).unwrap_or_else(|e| fail(format!("Cannot create output: {}", e)));
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let check_order = archive.row_order().is_some();
+    let results: IndexedResults = Mutex::new(vec![None; groups.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let decompressor = decompress_opts.decompressor(archive);
+                let mut restored = Vec::new();
+                while !failed.load(Ordering::Relaxed) && !decompress_opts.cancel.is_cancelled() {
+                    let g = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(group) = groups.get(g) else { break };
+                    // A panicking worker reports its group as failed, which stops the others
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(u64, RowOrder), String> {
+                        let mut order = RowOrder::default();
+                        let (offset, bom) = if g == 0 { (0, bom) } else { (index.entries[group.start].data_offset, &[][..]) };
+                        let region = sink.region(offset).map_err(|e| format!("Writing output failed: {}", e))?;
+                        let mut out = BufWriter::new(ThrottledWriter::new(region, decompress_opts.io_limit.clone()));
+                        out.write_all(bom).map_err(|e| format!("Writing output failed: {}", e))?;
+                        for i in group.clone() {
+                            if decompress_opts.cancel.is_cancelled() { return Err(CastError::Cancelled.to_string()); }
+                            let entry = &index.entries[i];
+                            let chunk = read_chunk_at(&f, entry, archive.version).map_err(|e| format!("Chunk {}: {}", i + 1, e))?;
+                            if !entry.matches(&chunk.header) {
+                                return Err(format!("Index does not match chunk {} of the archive (rebuild it with --index)", i + 1));
+                            }
+                            let chunk = resolve_indexed(&f, &index.entries, chunk, archive.version).map_err(|e| format!("Chunk {}: {}", i + 1, e))?;
+                            restored.clear();
+                            decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(archive), chunk.header.id_flag, &mut restored)
+                                .map_err(|e| format!("Decompression error at Chunk {}: {}", i + 1, e.at_chunk(i + 1)))?;
+                            if restored.len() as u64 != entry.data_len {
+                                return Err(format!("Chunk {}: restored {} but the index expects {}", i + 1, format_bytes(restored.len()), format_bytes(entry.data_len as usize)));
+                            }
+                            out.write_all(&restored).map_err(|e| format!("Writing output failed: {}", e))?;
+                            if check_order { order.append(&RowOrder::of_chunk(&restored)); }
+                        }
+                        out.flush().map_err(|e| format!("Writing output failed: {}", e))?;
+                        Ok((index.entries[group.clone()].iter().map(|e| e.data_len).sum::<u64>() + bom.len() as u64, order))
+                    })).unwrap_or_else(|p| Err(CastError::from_panic(p).to_string()));
+                    if result.is_err() { failed.store(true, Ordering::Relaxed); }
+                    results.lock().unwrap()[g] = Some(result);
+                }
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap();
+    // Groups finish out of order: only the run of finished groups from the start is complete
+    let complete_groups = results.iter().take_while(|r| matches!(r, Some(Ok(_)))).count();
+    let complete = match complete_groups {
+        0 => 0,
+        n => index.entries[groups[n - 1].end - 1].data_offset + index.entries[groups[n - 1].end - 1].data_len,
+    };
+    let cut = |sink: PositionedSink| {
+        sink.discard();
+        let _ = std::fs::OpenOptions::new().write(true).open(output_path).and_then(|f| f.set_len(complete));
+    };
+    if decompress_opts.cancel.is_cancelled() {
+        cut(sink);
+        exit_restore_cut(output_path, complete, decompress_opts.on_cancel);
+    }
+    if let Some(e) = results.iter().find_map(|r| r.as_ref().and_then(|r| r.as_ref().err())) {
+        // No zero-filled gaps: the output keeps the chunks restored before the failure
+        cut(sink);
+        fail(e.clone());
+    }
+    if let Err(e) = sink.finish() { fail(format!("Writing output failed: {}", e)); }
+    // Groups combine in archive order (see "ROW ORDER CHECKSUM")
+    let mut row_order = RowOrder::default();
+    for (_, order) in results.iter().flatten().flatten() { row_order.append(order); }
+    if let Err(message) = check_row_order(archive, row_order) { fail(message); }
+    apply_output_attributes(&[Path::new(output_path)], archive, decompress_opts);
+
+    let mut total_in = archive.encoded_len() as u64;
+    for (i, entry) in index.entries.iter().enumerate() {
+        total_in += entry.compressed_len;
+        emit_event("chunk", &[
+            ("command", JsonValue::Str("decompress")),
+            ("idx", JsonValue::Int(i as u64 + 1)),
+            ("bytes_in", JsonValue::Int(entry.compressed_len)),
+            ("bytes_out", JsonValue::Int(entry.data_len)),
+        ]);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    say!("\n[+]  Decompression done in {:.2}s", elapsed);
+    let mut done_fields = vec![
+        ("command", JsonValue::Str("decompress")),
+        ("chunks", JsonValue::Int(index.entries.len() as u64)),
+        ("bytes_in", JsonValue::Int(total_in)),
+        ("bytes_out", JsonValue::Int(output_len)),
+        ("ratio", JsonValue::Float(if total_in > 0 { output_len as f64 / total_in as f64 } else { 0.0 })),
+        ("time", JsonValue::Float(elapsed)),
+    ];
+    report_throttle_wait(decompress_opts.io_limit.as_ref(), waited_before, "       Throttled:   ", true, &mut done_fields);
+    emit_event("done", &done_fields);
+}
+
+/// Gives finished outputs the attributes of --chmod, --chown, --preserve-permissions,
+/// --readonly and --hidden. A failure ends the run with status 1; the outputs are kept.
+fn apply_output_attributes(paths: &[&Path], archive: &ArchiveHeader, decompress_opts: &DecompressOptions) {
+    let attributes = &decompress_opts.attributes;
+    if attributes.is_empty() { return; }
+    let stored_mode = archive.labels.iter().find(|(k, _)| k == UNIX_MODE_LABEL).and_then(|(_, v)| parse_mode(v));
+    if attributes.preserve_permissions && attributes.mode.is_none() && stored_mode.is_none() {
+        eprintln!("\n[!]  Note: The archive records no permissions (packed files, or written by an older version): --preserve-permissions keeps the defaults.");
+    }
+    let mut failures = Vec::new();
+    for path in paths {
+        failures.extend(attributes.apply(path, stored_mode).into_iter().map(|f| format!("'{}': {}", path.display(), f)));
+    }
+    if failures.is_empty() { return; }
+    eprintln!();
+    for failure in &failures { eprintln!("[!]  Error: Restored, but {}", failure); }
+    emit_error("decompress", &failures.join("; "));
+    std::process::exit(1);
+}
+
+// --- PARTIAL RESTORE (--rows / --range) ---
+
+// Part of the original data to restore, inclusive on both ends: 0-based rows or byte offsets
+#[derive(Clone, Copy)]
+enum Selection { Rows(u64, u64), Bytes(u64, u64) }
+
+impl Selection {
+    /// Whether a chunk holding `data_len` bytes from `data_offset`, starting in row `first_row`
+    /// and containing `lines` newlines, overlaps the selection.
+    fn overlaps(&self, data_offset: u64, data_len: u64, first_row: u64, lines: u64) -> bool {
+        match *self {
+            Selection::Rows(a, b) => first_row <= b && first_row + lines >= a,
+            Selection::Bytes(a, b) => data_offset <= b && data_offset + data_len > a,
+        }
+    }
+
+    /// Whether a chunk starting at `data_offset` / `first_row` lies entirely after the selection.
+    fn is_past(&self, data_offset: u64, first_row: u64) -> bool {
+        match *self {
+            Selection::Rows(_, b) => first_row > b,
+            Selection::Bytes(_, b) => data_offset > b,
+        }
+    }
+
+    /// The selected part of a restored chunk.
+    fn slice<'a>(&self, data: &'a [u8], data_offset: u64, first_row: u64) -> &'a [u8] {
+        // Position just after the k-th newline of the chunk (k >= 1), or its end
+        let after_newline = |k: u64| memchr::memchr_iter(b'\n', data).nth(k as usize - 1).map(|p| p + 1).unwrap_or(data.len());
+        let (start, end) = match *self {
+            Selection::Rows(a, b) => {
+                let start = if a <= first_row { 0 } else { after_newline(a - first_row) };
+                let end = if b < first_row { 0 } else { after_newline(b + 1 - first_row) };
+                (start, end)
+            },
+            Selection::Bytes(a, b) => {
+                let start = a.saturating_sub(data_offset).min(data.len() as u64) as usize;
+                let end = (b.saturating_add(1).saturating_sub(data_offset)).min(data.len() as u64) as usize;
+                (start, end)
+            },
+        };
+        &data[start..end.max(start)]
+    }
+
+    fn describe(&self) -> String {
+        let end = |b: u64, base: u64| if b >= u64::MAX - 1 { "end".to_string() } else { (b + base).to_string() };
+        match *self {
+            Selection::Rows(a, b) => format!("rows {}-{}", a + 1, end(b, 1)),
+            Selection::Bytes(a, b) => format!("bytes {}-{}", a, end(b, 0)),
+        }
+    }
+}
+
+/// Restores only the selected rows or bytes. With a valid sidecar index the chunks outside the
+/// selection are skipped with a seek; otherwise chunks are decoded in order up to the end of
+/// the selection.
+fn do_decompress_selection(input_path: &str, output_path: &str, decompress_opts: &DecompressOptions, selection: Selection, target: RestoreTarget) {
+    let start = Instant::now();
+    let waited_before = decompress_opts.io_limit.as_ref().map_or(Duration::ZERO, |l| l.waited());
+    let fail = |msg: String| -> ! {
+        diag!("\n[!]  ERROR: {}", msg);
+        emit_error("decompress", &msg);
+        std::process::exit(1);
+    };
+    emit_event("start", &[("command", JsonValue::Str("decompress")), ("input", JsonValue::Str(input_path)), ("output", JsonValue::Str(output_path))]);
+    let f_in = decompress_opts.open(input_path).unwrap_or_else(|e| fail(format!("Cannot open archive: {}", e)));
+    let archive_len = f_in.get_ref().metadata().map(|m| m.len()).unwrap_or(0);
+    let mut reader = ChunkReader::new(std::io::BufReader::new(f_in)).unwrap_or_else(|e| fail(e.to_string()));
+    let archive = reader.archive_header().clone();
+    let to_stdout = output_path == "-";
+
+    let index = match ArchiveIndex::lookup(input_path) {
+        IndexLookup::Valid(i) => Some(i),
+        IndexLookup::Stale(reason) => { eprintln!("[!]  Ignoring index ({}): reading sequentially.", reason); None },
+        IndexLookup::Missing => None,
+    };
+    if !to_stdout {
+        match &index {
+            Some(i) => say!("\n[*]  Restoring {} (indexed: {} of {} chunks)...", selection.describe(),
+                i.entries.iter().filter(|e| selection.overlaps(e.data_offset, e.data_len, e.first_row, e.lines)).count(), i.entries.len()),
+            None => say!("\n[*]  Restoring {} (no index: sequential scan)...", selection.describe()),
+        }
+    }
+
+    let decompressor = decompress_opts.decompressor(&archive);
+    let size_hint = output_size_hint(&archive, archive_len, false);
+    let mut f_out = RestoreOutput::open(output_path, target, size_hint, decompress_opts.io_limit.clone()).unwrap_or_else(|e| fail(format!("Cannot create output: {}", e)));
+    let mut total_out = 0u64;
+    let mut chunks = 0;
+
+    // The BOM is the first bytes of the original (and part of row 1)
+    if archive.has_bom() {
+        let bom = match selection {
+            Selection::Rows(a, _) => if a == 0 { &UTF8_BOM[..] } else { &[] },
+            Selection::Bytes(a, b) => &UTF8_BOM[(a.min(3) as usize)..(b.saturating_add(1).min(3) as usize).max(a.min(3) as usize)],
+        };
+        f_out.write_all(bom).unwrap_or_else(|e| fail(format!("Writing output failed: {}", e)));
+        total_out += bom.len() as u64;
+    }
+
+    let raw_file = index.as_ref().map(|_| decompress_opts.open(input_path).unwrap_or_else(|e| fail(format!("Cannot open archive: {}", e))));
+    let mut next_entry = 0;
+    let mut data_offset = if archive.has_bom() { UTF8_BOM.len() as u64 } else { 0 };
+    let mut first_row = 0;
+    let mut chunk_idx = 0;
+    let mut restored = Vec::new();
+    loop {
+        if decompress_opts.cancel.is_cancelled() { exit_restore_cancelled(f_out, output_path, total_out, decompress_opts.on_cancel); }
+        // Next chunk to decode, with its position in the original data
+        let chunk = match (&index, raw_file.as_ref()) {
+            (Some(index), Some(f)) => {
+                let Some(pos) = index.entries[next_entry..].iter().position(|e| selection.overlaps(e.data_offset, e.data_len, e.first_row, e.lines)) else { break };
+                next_entry += pos;
+                let entry = index.entries[next_entry];
+                next_entry += 1;
+                chunk_idx = next_entry;
+                data_offset = entry.data_offset;
+                first_row = entry.first_row;
+                let chunk = read_chunk_at(f, &entry, archive.version).unwrap_or_else(|e| fail(format!("Chunk {}: {}", chunk_idx, e)));
+                if !entry.matches(&chunk.header) {
+                    fail(format!("Index does not match chunk {} of the archive. Rebuild it with 'cast index {}'.", chunk_idx, input_path));
+                }
+                resolve_indexed(f, &index.entries, chunk, archive.version).unwrap_or_else(|e| fail(format!("Chunk {}: {}", chunk_idx, e)))
+            },
+            _ => match reader.next_resolved() {
+                Ok(Some(c)) => { chunk_idx += 1; c },
+                Ok(None) => break,
+                Err(e) => fail(e.to_string()),
+            },
+        };
+
+        restored.clear();
+        if let Err(e) = decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut restored) {
+            if decompress_opts.cancel.is_cancelled() { exit_restore_cancelled(f_out, output_path, total_out, decompress_opts.on_cancel); }
+            fail(format!("Decompression error at Chunk {}: {}", chunk_idx, e.at_chunk(chunk_idx)));
+        }
+        let lines = count_lines(&restored);
+        let piece = selection.slice(&restored, data_offset, first_row);
+        f_out.write_all(piece).unwrap_or_else(|e| fail(format!("Writing output failed: {}", e)));
+        total_out += piece.len() as u64;
+        chunks += 1;
+        emit_event("chunk", &[
+            ("command", JsonValue::Str("decompress")),
+            ("idx", JsonValue::Int(chunk_idx as u64)),
+            ("bytes_in", JsonValue::Int((CHUNK_HEADER_SIZE + chunk.body.len()) as u64)),
+            ("bytes_out", JsonValue::Int(piece.len() as u64)),
+        ]);
+
+        data_offset += restored.len() as u64;
+        first_row += lines;
+        if selection.is_past(data_offset, first_row) { break; }
+    }
+
+    f_out.finish().unwrap_or_else(|e| fail(format!("Writing output failed: {}", e)));
+    if !to_stdout { apply_output_attributes(&[Path::new(output_path)], &archive, decompress_opts); }
+    let elapsed = start.elapsed().as_secs_f64();
+    if !to_stdout {
+        say!("[+]  Restored {} from {} chunk(s) in {:.2}s", format_bytes(total_out as usize), chunks, elapsed);
+    }
+    let mut done_fields = vec![
+        ("command", JsonValue::Str("decompress")),
+        ("chunks", JsonValue::Int(chunks as u64)),
+        ("bytes_out", JsonValue::Int(total_out)),
+        ("time", JsonValue::Float(elapsed)),
+    ];
+    report_throttle_wait(decompress_opts.io_limit.as_ref(), waited_before, "       Throttled:   ", !to_stdout, &mut done_fields);
+    emit_event("done", &done_fields);
+}
+
+/// The chunk a duplicate chunk refers to, read through the index; any other chunk is returned
+/// unchanged. `version` is the archive's.
+fn resolve_indexed<R: ReadAt + ?Sized>(f: &R, entries: &[IndexEntry], chunk: Chunk, version: u8) -> io::Result<Chunk> {
+    let Some(target) = chunk.duplicate_of() else { return Ok(chunk) };
+    let mismatch = || io::Error::new(io::ErrorKind::InvalidData, format!("Duplicate of chunk {}, which does not match it", target));
+    let entry = target.checked_sub(1).and_then(|i| entries.get(i)).ok_or_else(mismatch)?;
+    let original = read_chunk_at(f, entry, version)?;
+    if !entry.matches(&original.header) || original.duplicate_of().is_some() || original.header.crc != chunk.header.crc {
+        return Err(mismatch());
+    }
+    Ok(original)
+}
+
+/// Reads the chunk an index entry points at, in an archive of `version`.
+fn read_chunk_at<R: ReadAt + ?Sized>(f: &R, entry: &IndexEntry, version: u8) -> io::Result<Chunk> {
+    let mut raw = [0u8; CHUNK_HEADER_SIZE];
+    f.read_exact_at(&mut raw, entry.offset)?;
+    let header = ChunkHeader::from_bytes(&raw);
+    // A stale entry may point anywhere: never trust its lengths beyond the entry's own size
+    if (CHUNK_HEADER_SIZE + header.body_len()) as u64 != entry.compressed_len {
+        return Ok(Chunk::new(header, Vec::new(), version));
+    }
+    let mut body = vec![0u8; header.body_len()];
+    f.read_exact_at(&mut body, entry.offset + CHUNK_HEADER_SIZE as u64)?;
+    Ok(Chunk::new(header, body, version))
+}
+
+// --- SHARDED RESTORE (--shards) ---
+
+const SHARD_MANIFEST_NAME: &str = "manifest.json";
+
+// Number of output parts of a sharded restore: one per chunk, or N (at most one per chunk)
+#[derive(Clone, Copy)]
+enum ShardCount { Auto, Count(usize) }
+
+impl ShardCount {
+    fn parse(s: &str) -> Option<Self> {
+        if s.eq_ignore_ascii_case("auto") { return Some(ShardCount::Auto); }
+        s.parse::<usize>().ok().filter(|&n| n > 0).map(ShardCount::Count)
+    }
+}
+
+// One written part: its file name, the chunks it holds (1-based, inclusive) and its content
+struct ShardPart {
+    file: String,
+    chunks: (usize, usize),
+    first_row: u64,
+    rows: u64,
+    bytes: u64,
+}
+
+/// Name of part `idx`: part-00000.<ext>, with the extension of the original file when the
+/// archive name shows one (data.csv.cast -> .csv), .log otherwise.
+fn shard_file_name(archive_path: &str, idx: usize) -> String {
+    let name = Path::new(archive_path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let stem = name.strip_suffix(".cast").unwrap_or(&name);
+    let ext = Path::new(stem).extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_else(|| "log".to_string());
+    format!("part-{:05}.{}", idx, ext)
+}
+
+/// Splits the indexed chunks into contiguous runs of roughly equal restored size, one per part
+/// (every part gets at least one chunk).
+fn plan_shards(entries: &[IndexEntry], count: ShardCount) -> Vec<std::ops::Range<usize>> {
+    let parts = match count {
+        ShardCount::Auto => entries.len(),
+        ShardCount::Count(n) => n.min(entries.len()),
+    }.max(1);
+    let total: u64 = entries.iter().map(|e| e.data_len).sum();
+    let mut runs = Vec::with_capacity(parts);
+    let (mut start, mut done) = (0, 0u64);
+    for (i, entry) in entries.iter().enumerate() {
+        let closed = runs.len() + 1;
+        if i > start && closed < parts {
+            // A part ends before the chunk whose middle lies past the part's share of the total
+            let chunks_left = entries.len() - i;
+            let past_share = (done + entry.data_len / 2) as u128 * parts as u128 >= total as u128 * closed as u128;
+            if (past_share && chunks_left >= parts - closed) || chunks_left == parts - closed {
+                runs.push(start..i);
+                start = i;
+            }
+        }
+        done += entry.data_len;
+    }
+    runs.push(start..entries.len());
+    runs
+}
+
+/// Restores the archive into `out_dir` as numbered parts that concatenate to the original,
+/// plus a manifest.json listing each part with its row range and size. With a valid sidecar
+/// index the parts are planned up front and decoded in parallel; otherwise the chunks are
+/// decoded in order and a part is closed at the chunk where it reaches its share.
+fn do_decompress_shards(input_path: &str, out_dir: &str, decompress_opts: &DecompressOptions, count: ShardCount) {
+    let start = Instant::now();
+    let waited_before = decompress_opts.io_limit.as_ref().map_or(Duration::ZERO, |l| l.waited());
+    let fail = |msg: String| -> ! {
+        diag!("\n[!]  ERROR: {}", msg);
+        emit_error("decompress", &msg);
+        std::process::exit(1);
+    };
+    emit_event("start", &[("command", JsonValue::Str("decompress")), ("input", JsonValue::Str(input_path)), ("output", JsonValue::Str(out_dir))]);
+    let f_in = decompress_opts.open(input_path).unwrap_or_else(|e| fail(format!("Cannot open archive: {}", e)));
+    let mut reader = ChunkReader::new(std::io::BufReader::new(f_in)).unwrap_or_else(|e| fail(e.to_string()));
+    let archive = reader.archive_header().clone();
+    std::fs::create_dir_all(out_dir).unwrap_or_else(|e| fail(format!("Cannot create '{}': {}", out_dir, e)));
+    let part_path = |idx: usize| Path::new(out_dir).join(shard_file_name(input_path, idx));
+    // The BOM is the first bytes of the original: it opens the first part
+    let bom: &[u8] = if archive.has_bom() { &UTF8_BOM } else { &[] };
+
+    let index = match ArchiveIndex::lookup(input_path) {
+        IndexLookup::Valid(i) => Some(i),
+        IndexLookup::Stale(reason) => { eprintln!("[!]  Ignoring index ({}): splitting sequentially.", reason); None },
+        IndexLookup::Missing => None,
+    };
+    let cancelled = |parts: usize| -> ! {
+        if decompress_opts.on_cancel == CancelPolicy::Delete {
+            for idx in 0..parts { let _ = std::fs::remove_file(part_path(idx)); }
+        }
+        exit_cancelled("decompress", cancel_note(out_dir, decompress_opts.on_cancel))
+    };
+
+    let mut parts: Vec<ShardPart> = Vec::new();
+    let mut chunk_count = 0;
+    if let Some(index) = &index {
+        let runs = plan_shards(&index.entries, count);
+        let mut threads = num_cpus::get().min(runs.len()).max(1);
+        if let Some(budget) = decompress_opts.budget {
+            let largest = index.entries.iter().map(|e| e.data_len).max().unwrap_or(0);
+            threads = budget.decode_workers(largest, threads);
+        }
+        say!("\n[*]  Restoring {} chunks into {} part(s) with {} thread(s) (indexed)...", index.entries.len(), runs.len(), threads);
+
+        let f = decompress_opts.open_shared(input_path).unwrap_or_else(|e| fail(format!("Cannot open archive: {}", e)));
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let results: Mutex<Vec<Option<Result<u64, String>>>> = Mutex::new(vec![None; runs.len()]);
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    let decompressor = decompress_opts.decompressor(&archive);
+                    let mut restored = Vec::new();
+                    while !failed.load(Ordering::Relaxed) && !decompress_opts.cancel.is_cancelled() {
+                        let p = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(run) = runs.get(p) else { break };
+                        // A panicking worker reports its part as failed, which stops the others
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<u64, String> {
+                            let path = part_path(p);
+                            let mut out = BufWriter::new(decompress_opts.create(&path).map_err(|e| format!("Cannot create '{}': {}", path.display(), e))?);
+                            let mut bytes = 0u64;
+                            if p == 0 {
+                                out.write_all(bom).map_err(|e| format!("Writing output failed: {}", e))?;
+                                bytes += bom.len() as u64;
+                            }
+                            for i in run.clone() {
+                                if decompress_opts.cancel.is_cancelled() { return Err(CastError::Cancelled.to_string()); }
+                                let entry = &index.entries[i];
+                                let chunk = read_chunk_at(&f, entry, archive.version).map_err(|e| format!("Chunk {}: {}", i + 1, e))?;
+                                if !entry.matches(&chunk.header) {
+                                    return Err(format!("Index does not match chunk {} of the archive (rebuild it with --index)", i + 1));
+                                }
+                                let chunk = resolve_indexed(&f, &index.entries, chunk, archive.version).map_err(|e| format!("Chunk {}: {}", i + 1, e))?;
+                                restored.clear();
+                                decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut restored)
+                                    .map_err(|e| format!("Decompression error at Chunk {}: {}", i + 1, e.at_chunk(i + 1)))?;
+                                if restored.len() as u64 != entry.data_len {
+                                    return Err(format!("Chunk {}: restored {} but the index expects {}", i + 1, format_bytes(restored.len()), format_bytes(entry.data_len as usize)));
+                                }
+                                out.write_all(&restored).map_err(|e| format!("Writing output failed: {}", e))?;
+                                bytes += restored.len() as u64;
+                            }
+                            out.flush().map_err(|e| format!("Writing output failed: {}", e))?;
+                            Ok(bytes)
+                        })).unwrap_or_else(|p| Err(CastError::from_panic(p).to_string()));
+                        if result.is_err() { failed.store(true, Ordering::Relaxed); }
+                        results.lock().unwrap()[p] = Some(result);
+                    }
+                });
+            }
+        });
+        if decompress_opts.cancel.is_cancelled() { cancelled(runs.len()); }
+
+        for (p, (run, result)) in runs.iter().zip(results.into_inner().unwrap()).enumerate() {
+            match result {
+                Some(Ok(bytes)) => {
+                    let first = index.entries.get(run.start);
+                    parts.push(ShardPart {
+                        file: shard_file_name(input_path, p),
+                        chunks: (run.start + 1, run.end),
+                        first_row: first.map_or(0, |e| e.first_row),
+                        rows: index.entries[run.clone()].iter().map(|e| e.lines).sum(),
+                        bytes,
+                    });
+                },
+                Some(Err(e)) => fail(e),
+                // Not reached because another part failed first
+                None => {},
+            }
+        }
+        chunk_count = index.entries.len();
+    } else {
+        // Without an index the chunk sizes are unknown up front: N parts are balanced on the
+        // original size recorded in the header, or on the chunk count when it is not recorded
+        let chunk_total = match (count, archive.original_size) {
+            (ShardCount::Count(_), None) => {
+                let mut counter = ChunkReader::new(std::io::BufReader::new(decompress_opts.open(input_path).unwrap_or_else(|e| fail(format!("Cannot open archive: {}", e)))))
+                    .unwrap_or_else(|e| fail(e.to_string()));
+                let mut n = 0;
+                while counter.skip_chunk().unwrap_or_else(|e| fail(e.to_string())).is_some() { n += 1; }
+                n
+            },
+            _ => 0,
+        };
+        let wanted = match count { ShardCount::Auto => usize::MAX, ShardCount::Count(n) => n };
+        say!("\n[*]  Restoring into {} (no index: sequential split at chunk boundaries)...",
+            if wanted == usize::MAX { "one part per chunk".to_string() } else { format!("{} part(s)", wanted) });
+
+        let decompressor = decompress_opts.decompressor(&archive);
+        let open_part = |idx: usize| {
+            let path = part_path(idx);
+            decompress_opts.create(&path).map(BufWriter::new).unwrap_or_else(|e| fail(format!("Cannot create '{}': {}", path.display(), e)))
+        };
+        let mut out = open_part(0);
+        out.write_all(bom).unwrap_or_else(|e| fail(format!("Writing output failed: {}", e)));
+        let mut current = ShardPart { file: shard_file_name(input_path, 0), chunks: (1, 0), first_row: 0, rows: 0, bytes: bom.len() as u64 };
+        let mut total_out = bom.len() as u64;
+        let mut restored = Vec::new();
+        let mut last_len = 0u64;
+        loop {
+            if decompress_opts.cancel.is_cancelled() { cancelled(parts.len() + 1); }
+            let Some(chunk) = reader.next_resolved().unwrap_or_else(|e| fail(e.to_string())) else { break };
+            // Start the next part when the current one has (about) its share: this chunk,
+            // assumed as large as the previous one, would end past its middle
+            let part_idx = parts.len();
+            let share_reached = match (count, archive.original_size) {
+                (ShardCount::Auto, _) => true,
+                (ShardCount::Count(n), Some(size)) => (total_out + last_len / 2) as u128 * n as u128 >= size as u128 * (part_idx + 1) as u128,
+                (ShardCount::Count(n), None) => (2 * chunk_count + 1) * n >= 2 * chunk_total * (part_idx + 1),
+            };
+            if current.chunks.1 > 0 && share_reached && part_idx + 1 < wanted {
+                out.flush().unwrap_or_else(|e| fail(format!("Writing output failed: {}", e)));
+                let next = ShardPart { file: shard_file_name(input_path, part_idx + 1), chunks: (chunk_count + 1, chunk_count), first_row: current.first_row + current.rows, rows: 0, bytes: 0 };
+                parts.push(std::mem::replace(&mut current, next));
+                out = open_part(part_idx + 1);
+            }
+
+            chunk_count += 1;
+            restored.clear();
+            if let Err(e) = decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut restored) {
+                if decompress_opts.cancel.is_cancelled() { cancelled(parts.len() + 1); }
+                fail(format!("Decompression error at Chunk {}: {}", chunk_count, e.at_chunk(chunk_count)));
+            }
+            out.write_all(&restored).unwrap_or_else(|e| fail(format!("Writing output failed: {}", e)));
+            current.chunks.1 = chunk_count;
+            current.rows += count_lines(&restored);
+            current.bytes += restored.len() as u64;
+            total_out += restored.len() as u64;
+            last_len = restored.len() as u64;
+        }
+        out.flush().unwrap_or_else(|e| fail(format!("Writing output failed: {}", e)));
+        parts.push(current);
+    }
+
+    for (p, part) in parts.iter().enumerate() {
+        emit_event("part", &[
+            ("idx", JsonValue::Int(p as u64)),
+            ("file", JsonValue::Str(&part.file)),
+            ("first_row", JsonValue::Int(part.first_row)),
+            ("rows", JsonValue::Int(part.rows)),
+            ("bytes", JsonValue::Int(part.bytes)),
+        ]);
+    }
+    let manifest_path = Path::new(out_dir).join(SHARD_MANIFEST_NAME);
+    std::fs::write(&manifest_path, shard_manifest(input_path, &parts))
+        .unwrap_or_else(|e| fail(format!("Cannot write '{}': {}", manifest_path.display(), e)));
+    let part_paths: Vec<_> = (0..parts.len()).map(part_path).collect();
+    let outputs: Vec<&Path> = part_paths.iter().map(|p| p.as_path()).chain([manifest_path.as_path()]).collect();
+    apply_output_attributes(&outputs, &archive, decompress_opts);
+
+    let total_out: u64 = parts.iter().map(|p| p.bytes).sum();
+    let elapsed = start.elapsed().as_secs_f64();
+    say!("[+]  Restored {} into {} part(s) in {:.2}s", format_bytes(total_out as usize), parts.len(), elapsed);
+    say!("       Manifest:    {}", manifest_path.display());
+    let mut done_fields = vec![
+        ("command", JsonValue::Str("decompress")),
+        ("chunks", JsonValue::Int(chunk_count as u64)),
+        ("bytes_out", JsonValue::Int(total_out)),
+        ("time", JsonValue::Float(elapsed)),
+        ("parts", JsonValue::Int(parts.len() as u64)),
+    ];
+    report_throttle_wait(decompress_opts.io_limit.as_ref(), waited_before, "       Throttled:   ", true, &mut done_fields);
+    emit_event("done", &done_fields);
+}
+
+/// manifest.json of a sharded restore: the parts in order, each with its file name, archive
+/// chunks, row range (0-based first row and newline count) and size.
+fn shard_manifest(archive_path: &str, parts: &[ShardPart]) -> String {
+    let mut out = String::from("{\n  \"archive\": ");
+    push_json_string(&mut out, archive_path);
+    out.push_str(",\n  \"parts\": [\n");
+    for (p, part) in parts.iter().enumerate() {
+        out.push_str("    {\"file\": ");
+        push_json_string(&mut out, &part.file);
+        out.push_str(&format!(", \"first_chunk\": {}, \"last_chunk\": {}, \"first_row\": {}, \"rows\": {}, \"bytes\": {}}}",
+            part.chunks.0, part.chunks.1, part.first_row, part.rows, part.bytes));
+        out.push_str(if p + 1 < parts.len() { ",\n" } else { "\n" });
+    }
+    out.push_str("  ]\n}\n");
+    out
+}
+
+// --- GREP ---
+
+/// Output options of `grep` (like grep -n / -B / -A).
+struct GrepOptions {
+    line_numbers: bool,
+    before: usize,
+    after: usize,
+}
+
+/// Prints the lines of the original data matching `pattern`, decoding one chunk at a time
+/// (memory stays at one chunk plus the context lines). Returns whether any line matched.
+fn do_grep(input_path: &str, pattern: &Regex, opts: &GrepOptions, decompress_opts: &DecompressOptions) -> Result<bool, CastError> {
+    let mut reader = ChunkReader::new(io::BufReader::new(File::open(input_path)?))?;
+    let archive = reader.archive_header().clone();
+    let decompressor = decompress_opts.decompressor(&archive);
+    let mut printer = GrepPrinter::new(io::stdout().lock(), opts);
+
+    // The unfinished last line of the previous chunk, followed by the current chunk
+    let mut block: Vec<u8> = Vec::new();
+    if archive.has_bom() { block.extend_from_slice(&UTF8_BOM); }
+    let mut chunk_idx = 0;
+    while let Some(chunk) = reader.next_resolved()? {
+        chunk_idx += 1;
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut block)
+            .map_err(|e| e.at_chunk(chunk_idx))?;
+        let complete = memchr::memrchr(b'\n', &block).map_or(0, |p| p + 1);
+        printer.scan(pattern, &block[..complete])?;
+        block.drain(..complete);
+    }
+    // Last line without a trailing newline (printed with one, like grep)
+    if !block.is_empty() {
+        block.push(b'\n');
+        printer.scan(pattern, &block)?;
+    }
+    Ok(printer.finish()?)
+}
+
+struct GrepPrinter<W: Write> {
+    out: BufWriter<W>,
+    line_numbers: bool,
+    before: usize,
+    after: usize,
+    /// 0-based number of the next line to scan
+    line: u64,
+    last_printed: Option<u64>,
+    /// After-context lines still to print
+    after_left: usize,
+    /// Last `before` lines of the blocks already scanned, oldest first
+    tail: VecDeque<Vec<u8>>,
+    matched: bool,
+}
+
+impl<W: Write> GrepPrinter<W> {
+    fn new(out: W, opts: &GrepOptions) -> Self {
+        Self {
+            out: BufWriter::new(out), line_numbers: opts.line_numbers, before: opts.before, after: opts.after,
+            line: 0, last_printed: None, after_left: 0, tail: VecDeque::new(), matched: false,
+        }
+    }
+
+    /// Scans whole lines (`data` ends with a newline). The pattern is first searched in the
+    /// whole block, so runs of lines that cannot match are skipped without testing each one.
+    fn scan(&mut self, pattern: &Regex, data: &[u8]) -> io::Result<()> {
+        let block_first = self.line;
+        let mut pos = 0;
+        while pos < data.len() {
+            let Some(m) = pattern.find_at(data, pos).filter(|m| m.start() < data.len()) else { break };
+            // A block match may span lines: only the line where it starts is a candidate
+            let start = memchr::memrchr(b'\n', &data[pos..m.start()]).map_or(pos, |i| pos + i + 1);
+            let end = memchr::memchr(b'\n', &data[m.start()..]).map_or(data.len(), |i| m.start() + i + 1);
+            self.skip(&data[pos..start])?;
+            if pattern.is_match(&data[start..end - 1]) {
+                self.print_before(data, block_first, start)?;
+                self.print(self.line, b':', &data[start..end])?;
+                self.matched = true;
+                self.after_left = self.after;
+                self.line += 1;
+            } else {
+                self.skip(&data[start..end])?;
+            }
+            pos = end;
+        }
+        self.skip(&data[pos..])?;
+        self.keep_tail(data);
+        Ok(())
+    }
+
+    /// Moves past non-matching lines, printing the pending after-context.
+    fn skip(&mut self, lines: &[u8]) -> io::Result<()> {
+        let mut pos = 0;
+        while self.after_left > 0 && pos < lines.len() {
+            let end = memchr::memchr(b'\n', &lines[pos..]).map_or(lines.len(), |i| pos + i + 1);
+            self.print(self.line, b'-', &lines[pos..end])?;
+            self.after_left -= 1;
+            self.line += 1;
+            pos = end;
+        }
+        self.line += count_lines(&lines[pos..]);
+        Ok(())
+    }
+
+    /// Prints the not yet printed lines among the `before` ones preceding the line at
+    /// `start` (which may reach back into the previous blocks).
+    fn print_before(&mut self, data: &[u8], block_first: u64, start: usize) -> io::Result<()> {
+        let first = self.line.saturating_sub(self.before as u64).max(self.last_printed.map_or(0, |l| l + 1));
+        if first >= self.line { return Ok(()); }
+        if first < block_first {
+            let tail_first = block_first - self.tail.len() as u64;
+            let tail = std::mem::take(&mut self.tail);
+            for (no, line) in (tail_first..).zip(&tail) {
+                if no >= first { self.print(no, b'-', line)?; }
+            }
+            self.tail = tail;
+        }
+        let mut from = start;
+        for _ in 0..self.line - first.max(block_first) {
+            from = memchr::memrchr(b'\n', &data[..from - 1]).map_or(0, |i| i + 1);
+        }
+        let mut no = first.max(block_first);
+        while from < start {
+            let end = from + memchr::memchr(b'\n', &data[from..start]).map_or(start - from, |i| i + 1);
+            self.print(no, b'-', &data[from..end])?;
+            no += 1;
+            from = end;
+        }
+        Ok(())
+    }
+
+    /// Keeps the last `before` lines for a match at the start of the next block.
+    fn keep_tail(&mut self, data: &[u8]) {
+        if self.before == 0 { return; }
+        let mut lines = Vec::new();
+        let mut end = data.len();
+        while end > 0 && lines.len() < self.before {
+            let start = memchr::memrchr(b'\n', &data[..end - 1]).map_or(0, |i| i + 1);
+            lines.push(data[start..end].to_vec());
+            end = start;
+        }
+        self.tail.extend(lines.into_iter().rev());
+        while self.tail.len() > self.before { self.tail.pop_front(); }
+    }
+
+    /// Prints line `no` (newline included), with '--' between groups that are not adjacent.
+    fn print(&mut self, no: u64, separator: u8, line: &[u8]) -> io::Result<()> {
+        let context = self.before > 0 || self.after > 0;
+        if context && self.last_printed.is_some_and(|l| no > l + 1) { self.out.write_all(b"--\n")?; }
+        if self.line_numbers { write!(self.out, "{}{}", no + 1, separator as char)?; }
+        self.out.write_all(line)?;
+        self.last_printed = Some(no);
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<bool> {
+        self.out.flush()?;
+        Ok(self.matched)
+    }
+}
+
+// --- SIDECAR INDEX ---
+
+/// (Re)builds the sidecar index of an existing archive with one sequential pass.
+fn do_build_index(input_path: &str, decompress_opts: &DecompressOptions) {
+    let start = Instant::now();
+    say!("\n[*]  Indexing: {}", input_path);
+    let index = File::open(input_path).map_err(CastError::from)
+        .and_then(|f| ArchiveHeader::read_from(&mut io::BufReader::new(f)))
+        .and_then(|archive| ArchiveIndex::build(input_path, &decompress_opts.decompressor(&archive))).unwrap_or_else(|e| {
+        if decompress_opts.cancel.is_cancelled() { exit_cancelled("index", "no index written"); }
+        eprintln!("[!]  Error: {}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = index.save(input_path) {
+        eprintln!("[!]  Error: Cannot write '{}': {}", sidecar_path(input_path), e);
+        std::process::exit(1);
+    }
+    say!("[+]  Index written: {}", sidecar_path(input_path));
+    say!("       Chunks:         {}", index.entries.len());
+    say!("       Lines:          {}", index.lines());
+    say!("       Time:           {:.2}s", start.elapsed().as_secs_f64());
+}
+
+/// Verifies every chunk in parallel, seeking to them through the sidecar index.
+/// Results are still reported in archive order.
+fn do_verify_indexed(input_path: &str, decompress_opts: &DecompressOptions, archive: &ArchiveHeader, index: &ArchiveIndex) {
+    let start = Instant::now();
+    let waited_before = decompress_opts.io_limit.as_ref().map_or(Duration::ZERO, |l| l.waited());
+    let mut threads = num_cpus::get().min(index.entries.len()).max(1);
+    if let Some(budget) = decompress_opts.budget {
+        let largest = index.entries.iter().map(|e| e.data_len).max().unwrap_or(0);
+        threads = budget.decode_workers(largest, threads);
+    }
+    say!("[*]  Verifying {} chunks with {} thread(s) (indexed)...", index.entries.len(), threads);
+    if archive.checksum() == ChecksumKind::None {
+        diag!("[!]  Archive was created without checksums: only decodability can be verified.");
+    }
+
+    // One handle for all workers, each reading its chunks at their offsets
+    let f = decompress_opts.open_shared(input_path).unwrap_or_else(|e| {
+        let message = format!("Cannot open archive: {}", e);
+        diag!("\n[!]   FAILURE: {}", message);
+        emit_error("verify", &message);
+        std::process::exit(1);
+    });
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let check_order = archive.row_order().is_some();
+    let results: IndexedResults = Mutex::new(vec![None; index.entries.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let decompressor = decompress_opts.decompressor(archive);
+                let mut restored = Vec::new();
+                while !failed.load(Ordering::Relaxed) && !decompress_opts.cancel.is_cancelled() {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(entry) = index.entries.get(i) else { break };
+                    restored.clear();
+                    // A panicking worker reports its chunk as failed, which stops the others
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| read_chunk_at(&f, entry, archive.version).map_err(|e| e.to_string()).and_then(|chunk| {
+                        if !entry.matches(&chunk.header) {
+                            return Err("Index does not match the archive (rebuild it with --index)".to_string());
+                        }
+                        let chunk = resolve_indexed(&f, &index.entries, chunk, archive.version).map_err(|e| e.to_string())?;
+                        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(archive), chunk.header.id_flag, &mut restored)
+                            .map_err(|e| format!("Decompression error: {}", e.at_chunk(i + 1)))?;
+                        if restored.len() as u64 != entry.data_len {
+                            return Err(format!("Restored {} but the index expects {}", format_bytes(restored.len()), format_bytes(entry.data_len as usize)));
+                        }
+                        let order = if check_order { RowOrder::of_chunk(&restored) } else { RowOrder::default() };
+                        Ok((restored.len() as u64, order))
+                    }))).unwrap_or_else(|p| Err(CastError::from_panic(p).to_string()));
+                    if result.is_err() { failed.store(true, Ordering::Relaxed); }
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap();
+    if decompress_opts.cancel.is_cancelled() {
+        exit_verify_cancelled(results.iter().filter(|r| matches!(r, Some(Ok(_)))).count());
+    }
+    let mut total_out = 0u64;
+    let mut row_order = RowOrder::default();
+    for (i, (entry, result)) in index.entries.iter().zip(results).enumerate() {
+        match result {
+            Some(Ok((bytes_out, order))) => {
+                total_out += bytes_out;
+                row_order.append(&order);
+                emit_event("chunk", &[
+                    ("command", JsonValue::Str("verify")),
+                    ("idx", JsonValue::Int(i as u64 + 1)),
+                    ("bytes_in", JsonValue::Int(entry.compressed_len)),
+                    ("bytes_out", JsonValue::Int(bytes_out)),
+                ]);
+            },
+            Some(Err(e)) => {
+                let message = format!("{} at Chunk {}", e, i + 1);
+                diag!("\n[!]   FAILURE: {}", message);
+                emit_error("verify", &message);
+                std::process::exit(1);
+            },
+            // Not reached because another chunk failed first
+            None => {},
+        }
+    }
+    if let Err(message) = check_row_order(archive, row_order) {
+        diag!("\n[!]   FAILURE: {}!", message);
+        emit_error("verify", &message);
+        std::process::exit(1);
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let total_in = index.archive_len;
+    if archive.checksum() == ChecksumKind::None {
+        say!("\n[+]  FILE DECODED (NO CHECKSUMS). Chunks: {}. Time: {:.2}s", index.entries.len(), elapsed);
+    } else {
+        say!("\n[+]  FILE INTEGRITY VERIFIED. Chunks: {}. Time: {:.2}s", index.entries.len(), elapsed);
+    }
+    if check_order {
+        say!("       Row order:   {} rows in the recorded order", format_count(row_order.rows));
+    }
+    let mut done_fields = vec![
+        ("command", JsonValue::Str("verify")),
+        ("chunks", JsonValue::Int(index.entries.len() as u64)),
+        ("bytes_in", JsonValue::Int(total_in)),
+        ("bytes_out", JsonValue::Int(total_out)),
+        ("ratio", JsonValue::Float(if total_in > 0 { total_out as f64 / total_in as f64 } else { 0.0 })),
+        ("time", JsonValue::Float(elapsed)),
+        ("result", JsonValue::Str(if archive.checksum() == ChecksumKind::None { "decoded" } else { "verified" })),
+    ];
+    if check_order { done_fields.push(("row_order", JsonValue::Str("verified"))); }
+    report_throttle_wait(decompress_opts.io_limit.as_ref(), waited_before, "       Throttled:   ", true, &mut done_fields);
+    emit_event("done", &done_fields);
+}
+
+// --- VERIFICATION ---
+
+/// Checks that the archive decodes, against the stored CRCs and, when given, the original file.
+/// With `strict` every chunk is decoded a second time by the other backend (native and 7-Zip)
+/// and both results must agree.
+/// Compares an archive with a manifest written by `-c --manifest`. Returns whether it matches.
+fn do_manifest_check(input_path: &str, manifest_path: &str) -> bool {
+    let result = Manifest::load(manifest_path).and_then(|m| m.check_file(input_path).map(|found| (m, found)).map_err(|e| e.to_string()));
+    match result {
+        Ok((manifest, found)) if found.is_empty() => {
+            say!("       Manifest:    matches {} ({} chunk(s), header and compressed bodies)", manifest_path, manifest.chunks.len());
+            emit_event("manifest_check", &[("manifest", JsonValue::Str(manifest_path)), ("mismatches", JsonValue::Int(0))]);
+            true
+        },
+        Ok((_, found)) => {
+            diag!("[!]  ARCHIVE DOES NOT MATCH THE MANIFEST {}", manifest_path);
+            for difference in &found { diag!("       {}", difference); }
+            emit_event("manifest_check", &[("manifest", JsonValue::Str(manifest_path)), ("mismatches", JsonValue::Int(found.len() as u64))]);
+            false
+        },
+        Err(e) => {
+            eprintln!("[!]  Error: {}", e);
+            emit_error("verify", &e);
+            false
+        },
+    }
+}
+
+fn do_verify_standalone(input_path: &str, decompress_opts: &DecompressOptions, debug_verify: bool, reference_path: Option<&str>, strict: bool, key: Option<&MacKey>) {
+    let start = Instant::now();
+    let waited_before = decompress_opts.io_limit.as_ref().map_or(Duration::ZERO, |l| l.waited());
+    emit_event("start", &[("command", JsonValue::Str("verify")), ("input", JsonValue::Str(input_path))]);
+    let f_in = decompress_opts.open(input_path).expect("Error opening archive");
+    let mut reader = match ChunkReader::new(std::io::BufReader::new(f_in)) {
+        Ok(r) => r,
+        Err(e) => {
+            diag!("[!]   FAILURE: {}", e);
+            emit_error("verify", &e.to_string());
+            std::process::exit(1);
+        }
+    };
+    let archive = reader.archive_header().clone();
+
+    // Second decoder of --strict-verify: without 7-Zip there is nothing to compare with
+    let cross_check = match strict {
+        true if try_find_7zip_path().is_some() => Some(decompress_opts.other_decompressor(&archive)),
+        true => {
+            diag!("[!]  Note: 7-Zip not found: --strict-verify falls back to a normal verification.");
+            None
+        },
+        false => None,
+    };
+    let cross_check_name = if decompress_opts.use_7zip { "native" } else { "7-Zip" };
+
+    match key {
+        Some(_) if !archive.authenticated() => fail_authentication("Archive holds no HMACs (it was not compressed with --hmac-key-file): its authenticity cannot be checked"),
+        Some(_) => {},
+        None => note_unauthenticated(&archive),
+    }
+
+    // With a valid sidecar the chunks can be checked independently (the byte-for-byte
+    // comparison, debug mode, the cross-check and the MACs need the sequential path)
+    if reference_path.is_none() && !debug_verify && !strict && key.is_none() {
+        match ArchiveIndex::lookup(input_path) {
+            IndexLookup::Valid(index) if index.entries.len() > 1 => return do_verify_indexed(input_path, decompress_opts, &archive, &index),
+            IndexLookup::Stale(reason) => eprintln!("[!]  Ignoring index ({}): verifying sequentially.", reason),
+            _ => {},
+        }
+    }
+
+    let decompressor = decompress_opts.decompressor(&archive);
+    let mut chunk_idx = 0;
+    let mut total_in = archive.encoded_len() as u64;
+    let mut total_out = 0u64;
+    // MACs of the restored chunks, compared with the trailer at the end
+    let mut chunk_macs: Vec<Mac> = Vec::new();
+    // Row order of the restored chunks, when the archive records one
+    let mut row_order = archive.row_order().map(|_| RowOrder::default());
+
+    // Original data streamed alongside the archive (byte-for-byte comparison)
+    let mut reference = reference_path.map(|p| {
+        say!("       Reference:   {}", p);
+        std::io::BufReader::new(decompress_opts.open(p).expect("Error opening reference file"))
+    });
+
+    if let (true, Some(r)) = (archive.has_bom(), reference.as_mut()) {
+        let mut head = Vec::new();
+        r.take(UTF8_BOM.len() as u64).read_to_end(&mut head).expect("Error reading reference file");
+        if head != UTF8_BOM {
+            diag!("[!]   FAILURE: Archive restores a UTF-8 BOM the reference does not start with!");
+            emit_error("verify", "Archive restores a UTF-8 BOM the reference does not start with");
+            std::process::exit(1);
+        }
+    }
+
+    say!("[*]  Verifying Stream Integrity (RAM Optimized)...");
+    if cross_check.is_some() {
+        say!("       Cross-check: every chunk is also decoded with the {} backend", cross_check_name);
+    }
+    if key.is_some() {
+        say!("       HMAC:        every chunk is also checked against its HMAC-SHA256");
+    } else if archive.checksum() == ChecksumKind::None && reference.is_none() {
+        diag!("[!]  Archive was created without checksums: only decodability can be verified.");
+    }
+
+    loop {
+        if decompress_opts.cancel.is_cancelled() { exit_verify_cancelled(chunk_idx); }
+        let chunk = match reader.next_chunk() {
+            Ok(Some(c)) => c,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("\n[!]  CRASH: {}", e);
+                emit_error("verify", &e.to_string());
+                std::process::exit(1);
+            },
+        };
+        // A duplicate chunk is counted as stored but verified as the chunk it refers to
+        let chunk_in = (CHUNK_HEADER_SIZE + chunk.body.len()) as u64;
+        let chunk = match reader.resolve(chunk) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("\n[!]  CRASH: {}", e);
+                emit_error("verify", &e.to_string());
+                std::process::exit(1);
+            },
+        };
+
+        chunk_idx += 1;
+        let expected_crc = chunk.expected_crc(&archive);
+
+        if !json_output() {
+            print!("\r       Verifying Chunk #{}... ", chunk_idx);
+            io::stdout().flush().unwrap();
+        }
+
+        let mut temp_buffer = Vec::new();
+
+        // In debug mode the CRC is checked here, so the failing chunk can still be inspected;
+        // the cross-check compares both decodes before looking at the CRC
+        let inner_crc = if debug_verify || cross_check.is_some() { None } else { expected_crc };
+
+        match decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), inner_crc, chunk.header.id_flag, &mut temp_buffer) {
+            Ok(_) => {
+                let mut original = Vec::new();
+                if let Some(r) = reference.as_mut() {
+                    r.take(temp_buffer.len() as u64).read_to_end(&mut original).expect("Error reading reference file");
+                }
+
+                if let Some(other) = &cross_check {
+                    if let Err(message) = cross_check_chunk(other, &chunk, chunk_idx, &temp_buffer, (decompress_opts.backend_name(), cross_check_name)) {
+                        diag!("\n[!]   FAILURE: {}", message);
+                        emit_error("verify", &message);
+                        std::process::exit(1);
+                    }
+                }
+
+                let mut failure = None;
+                if let Some(expected) = expected_crc {
+                    let mut h = Hasher::new();
+                    h.update(&temp_buffer);
+                    if h.finalize() != expected { failure = Some("CRC Mismatch"); }
+                }
+                if failure.is_none() && reference.is_some() && original != temp_buffer {
+                    failure = Some("Content differs from reference");
+                }
+
+                if let Some(reason) = failure {
+                    diag!("\n[!]   FAILURE: {} at Chunk {}!", reason, chunk_idx);
+                    emit_error("verify", &format!("{} at Chunk {}", reason, chunk_idx));
+                    if debug_verify {
+                        let original = if reference.is_some() { Some(original.as_slice()) } else { None };
+                        report_first_difference(&decompressor, &chunk, &temp_buffer, original);
+                    }
+                    std::process::exit(1);
+                }
+
+                if let Some(key) = key { chunk_macs.push(key.chunk_mac(chunk_idx as u64, &temp_buffer)); }
+                if let Some(order) = row_order.as_mut() { order.append(&RowOrder::of_chunk(&temp_buffer)); }
+                total_in += chunk_in;
+                total_out += temp_buffer.len() as u64;
+                emit_event("chunk", &[
+                    ("command", JsonValue::Str("verify")),
+                    ("idx", JsonValue::Int(chunk_idx as u64)),
+                    ("bytes_in", JsonValue::Int(chunk_in)),
+                    ("bytes_out", JsonValue::Int(temp_buffer.len() as u64)),
+                ]);
+            },
+            Err(_) if decompress_opts.cancel.is_cancelled() => exit_verify_cancelled(chunk_idx - 1),
+            Err(e) => {
+                let mut message = format!("Decompression error at Chunk {}: {}", chunk_idx, e.at_chunk(chunk_idx));
+                // Name the faulty backend when the other one decodes the chunk
+                if let Some(other) = &cross_check {
+                    if other.decompress(chunk.reg(), chunk.ids(), chunk.vars(), expected_crc, chunk.header.id_flag, &mut Vec::new()).is_ok() {
+                        message = format!("{} backend failed where {} succeeded: {}", decompress_opts.backend_name(), cross_check_name, message);
+                    }
+                }
+                diag!("\n[!]   CRASH: {}", message);
+                emit_error("verify", &message);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(key) = key {
+        // The trailer is only known once every chunk was read
+        let trailer = reader.mac_trailer().ok_or_else(|| AuthFailure::Missing.to_string())
+            .and_then(|t| MacTrailer::from_chunk(t, chunk_idx + 1).map_err(|e| e.to_string()));
+        let trailer = trailer.unwrap_or_else(|e| fail_authentication(&e));
+        trailer.check(key, &archive.to_bytes(), &chunk_macs).unwrap_or_else(|e| fail_authentication(&e.to_string()));
+        total_in += reader.mac_trailer().map_or(0, |t| (CHUNK_HEADER_SIZE + t.body.len()) as u64);
+    }
+    if let Some(Err(message)) = row_order.map(|order| check_row_order(&archive, order)) {
+        diag!("\n[!]   FAILURE: {}!", message);
+        emit_error("verify", &message);
+        std::process::exit(1);
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    if key.is_some() {
+        say!("\n[+]  FILE INTEGRITY AND AUTHENTICITY VERIFIED. Chunks: {}. Time: {:.2}s", chunk_idx, elapsed);
+    } else if archive.checksum() == ChecksumKind::None {
+        say!("\n[+]  FILE DECODED (NO CHECKSUMS). Chunks: {}. Time: {:.2}s", chunk_idx, elapsed);
+    } else {
+        say!("\n[+]  FILE INTEGRITY VERIFIED. Chunks: {}. Time: {:.2}s", chunk_idx, elapsed);
+    }
+    if cross_check.is_some() {
+        say!("       Native and 7-Zip restored the same data for every chunk.");
+    }
+    if let Some(order) = &row_order {
+        say!("       Row order:   {} rows in the recorded order", format_count(order.rows));
+    }
+    let mut done_fields = vec![
+        ("command", JsonValue::Str("verify")),
+        ("chunks", JsonValue::Int(chunk_idx as u64)),
+        ("bytes_in", JsonValue::Int(total_in)),
+        ("bytes_out", JsonValue::Int(total_out)),
+        ("ratio", JsonValue::Float(if total_in > 0 { total_out as f64 / total_in as f64 } else { 0.0 })),
+        ("time", JsonValue::Float(elapsed)),
+        ("result", JsonValue::Str(if key.is_some() { "authenticated" } else if archive.checksum() == ChecksumKind::None { "decoded" } else { "verified" })),
+    ];
+    if strict {
+        done_fields.push(("cross_check", JsonValue::Str(if cross_check.is_some() { "passed" } else { "unavailable" })));
+    }
+    if row_order.is_some() { done_fields.push(("row_order", JsonValue::Str("verified"))); }
+    report_throttle_wait(decompress_opts.io_limit.as_ref(), waited_before, "       Throttled:   ", true, &mut done_fields);
+    emit_event("done", &done_fields);
+}
+
+/// Compares the row order checksum of the restored chunks with the one the archive records
+/// (see ROW_ORDER_LABEL). Archives without one pass.
+fn check_row_order(archive: &ArchiveHeader, mut restored: RowOrder) -> Result<(), String> {
+    let Some(expected) = archive.row_order() else { return Ok(()) };
+    restored.end_chunk();
+    if (restored.hash, restored.rows) == (expected.hash, expected.rows) { return Ok(()); }
+    Err(match restored.rows == expected.rows {
+        true => format!("Row order check failed: the {} rows restore in another order than the archive recorded (chunks reordered)", format_count(expected.rows)),
+        false => format!("Row order check failed: the chunks restore {} rows, the archive recorded {} (chunks missing or added)", format_count(restored.rows), format_count(expected.rows)),
+    })
+}
+
+/// Ends a verification whose MAC check failed: reported apart from corruption, which the
+/// CRCs and the decoder catch before.
+fn fail_authentication(message: &str) -> ! {
+    diag!("\n[!]   AUTHENTICATION FAILED: {}!", message);
+    emit_error("verify", &format!("Authentication failed: {}", message));
+    std::process::exit(1);
+}
+
+/// Tells that the MACs of an authenticated archive go unchecked without --hmac-key-file.
+fn note_unauthenticated(archive: &ArchiveHeader) {
+    if archive.authenticated() {
+        diag!("[!]  Note: Archive carries HMACs but no --hmac-key-file was given: authenticity is not checked, only the CRCs.");
+    }
+}
+
+/// Decodes chunk `idx` with the second backend of --strict-verify and compares the result with
+/// `restored`, the first backend's output. `names` are (first, second) backend names.
+fn cross_check_chunk(other: &CASTLzmaDecompressor, chunk: &Chunk, idx: usize, restored: &[u8], names: (&str, &str)) -> Result<(), String> {
+    let mut second = Vec::with_capacity(restored.len());
+    other.decompress(chunk.reg(), chunk.ids(), chunk.vars(), None, chunk.header.id_flag, &mut second)
+        .map_err(|e| format!("{} backend failed where {} succeeded: {}", names.1, names.0, e.at_chunk(idx)))?;
+    let (crc_first, crc_second) = (chunk_crc(restored), chunk_crc(&second));
+    if restored.len() != second.len() || crc_first != crc_second {
+        return Err(format!("Backends disagree at Chunk {}: {} restored {} (CRC 0x{:08x}), {} restored {} (CRC 0x{:08x})",
+            idx, names.0, format_bytes(restored.len()), crc_first, names.1, format_bytes(second.len()), crc_second));
+    }
+    Ok(())
+}
+
+// --- INFO ---
+
+// Sampled chunk numbers shown before verification starts
+const SAMPLE_LISTED_CHUNKS: usize = 24;
+
+// Size of a sampled verification (--verify-sample): a share of the chunks or a chunk count
+#[derive(Clone, Copy)]
+enum SampleSize { Percent(f64), Chunks(usize) }
+
+impl SampleSize {
+    fn parse(s: &str) -> Option<Self> {
+        match s.strip_suffix('%') {
+            Some(p) => p.trim().parse::<f64>().ok().filter(|p| *p > 0.0 && *p <= 100.0).map(SampleSize::Percent),
+            None => s.parse::<usize>().ok().filter(|&n| n > 0).map(SampleSize::Chunks),
+        }
+    }
+
+    /// Chunks to check out of `total` (at least one).
+    fn count(&self, total: usize) -> usize {
+        match *self {
+            SampleSize::Percent(p) => ((total as f64 * p / 100.0).ceil() as usize).max(1),
+            SampleSize::Chunks(n) => n,
+        }
+    }
+}
+
+/// Verifies the first and last chunks plus a pseudo-random sample of the others, seeking
+/// straight to them (with the sidecar index, or from chunk header to chunk header without
+/// one). The sample is seeded with the CRC32 of the archive header, so repeated runs check
+/// the same chunks. Chunks outside the sample are never read.
+fn do_verify_sampled(input_path: &str, decompress_opts: &DecompressOptions, sample: SampleSize) {
+    let start = Instant::now();
+    let waited_before = decompress_opts.io_limit.as_ref().map_or(Duration::ZERO, |l| l.waited());
+    emit_event("start", &[("command", JsonValue::Str("verify")), ("input", JsonValue::Str(input_path))]);
+    let fail = |msg: String| -> ! {
+        diag!("\n[!]   FAILURE: {}", msg);
+        emit_error("verify", &msg);
+        std::process::exit(1);
+    };
+    let mut f = decompress_opts.open(input_path).unwrap_or_else(|e| fail(format!("Cannot open archive: {}", e)));
+    let archive = ArchiveHeader::read_from(&mut io::BufReader::new(&mut f)).unwrap_or_else(|e| fail(e.to_string()));
+    let seed = archive_fingerprint(&mut f, None).unwrap_or_else(|e| fail(e.to_string()));
+    note_unauthenticated(&archive);
+
+    let index = match ArchiveIndex::lookup(input_path) {
+        IndexLookup::Valid(i) => Some(i),
+        IndexLookup::Stale(reason) => { eprintln!("[!]  Ignoring index ({}): locating chunks from their headers.", reason); None },
+        IndexLookup::Missing => None,
+    };
+    // Without an index only the archive side of each entry is known (no restored size to check)
+    let entries: Vec<IndexEntry> = match &index {
+        Some(i) => i.entries.clone(),
+        None => walk_chunk_headers(&mut f, archive.encoded_len() as u64).unwrap_or_else(|e| fail(e.to_string())).into_iter()
+            .map(|(offset, header)| IndexEntry {
+                offset, compressed_len: (CHUNK_HEADER_SIZE + header.body_len()) as u64, data_offset: 0, data_len: 0,
+                first_row: 0, lines: 0, crc: header.crc, id_flag: header.id_flag, parser: None,
+            }).collect(),
+    };
+    let picked = sample_chunks(entries.len(), sample.count(entries.len()), seed as u64);
+    say!("[*]  Sampled verification: {} of {} chunk(s) ({}, sample seed {:08x})...", picked.len(), entries.len(),
+        if index.is_some() { "indexed" } else { "no index: located from chunk headers" }, seed);
+    let mut listed: Vec<String> = picked.iter().take(SAMPLE_LISTED_CHUNKS).map(|i| (i + 1).to_string()).collect();
+    if picked.len() > SAMPLE_LISTED_CHUNKS { listed.push(format!("... ({} more)", picked.len() - SAMPLE_LISTED_CHUNKS)); }
+    say!("       Chunks:      {}", listed.join(", "));
+    if archive.checksum() == ChecksumKind::None {
+        diag!("[!]  Archive was created without checksums: only decodability can be verified.");
+    }
+
+    let decompressor = decompress_opts.decompressor(&archive);
+    let mut restored = Vec::new();
+    let (mut total_in, mut total_out) = (0u64, 0u64);
+    for (checked, &i) in picked.iter().enumerate() {
+        if decompress_opts.cancel.is_cancelled() { exit_verify_cancelled(checked); }
+        let entry = &entries[i];
+        if !json_output() {
+            print!("\r       Verifying Chunk #{}... ", i + 1);
+            io::stdout().flush().unwrap();
+        }
+        let chunk = read_chunk_at(&f, entry, archive.version).unwrap_or_else(|e| fail(format!("Chunk {}: {}", i + 1, e)));
+        if !entry.matches(&chunk.header) {
+            fail(format!("Chunk {}: the index does not match the archive (rebuild it with --index)", i + 1));
+        }
+        let chunk = resolve_indexed(&f, &entries, chunk, archive.version).unwrap_or_else(|e| fail(format!("Chunk {}: {}", i + 1, e)));
+        restored.clear();
+        if let Err(e) = decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut restored) {
+            if decompress_opts.cancel.is_cancelled() { exit_verify_cancelled(checked); }
+            fail(format!("Chunk {}: {}", i + 1, e.at_chunk(i + 1)));
+        }
+        if index.is_some() && restored.len() as u64 != entry.data_len {
+            fail(format!("Chunk {}: restored {} but the index expects {}", i + 1, format_bytes(restored.len()), format_bytes(entry.data_len as usize)));
+        }
+        total_in += entry.compressed_len;
+        total_out += restored.len() as u64;
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let outcome = if archive.checksum() == ChecksumKind::None { "all decoded (no checksums)" } else { "all OK" };
+    say!("\n[+]  Sampled verification: {}/{} chunks checked, {}. Time: {:.2}s", picked.len(), entries.len(), outcome, elapsed);
+    if picked.len() < entries.len() {
+        say!("       Not a full check: the other {} chunk(s) were not read, so damage in them", entries.len() - picked.len());
+        say!("       goes unnoticed. Run -v without --verify-sample for certainty.");
+    }
+    let mut done_fields = vec![
+        ("command", JsonValue::Str("verify")),
+        ("chunks", JsonValue::Int(picked.len() as u64)),
+        ("chunks_total", JsonValue::Int(entries.len() as u64)),
+        ("bytes_in", JsonValue::Int(total_in)),
+        ("bytes_out", JsonValue::Int(total_out)),
+        ("ratio", JsonValue::Float(if total_in > 0 { total_out as f64 / total_in as f64 } else { 0.0 })),
+        ("time", JsonValue::Float(elapsed)),
+        ("result", JsonValue::Str("sampled")),
+    ];
+    report_throttle_wait(decompress_opts.io_limit.as_ref(), waited_before, "       Throttled:   ", true, &mut done_fields);
+    emit_event("done", &done_fields);
+}
+
+/// Prints the archive header details and the chunk count. Only chunk headers are read:
+/// nothing is decompressed, so this is fast even on huge archives. With a valid sidecar
+/// index not even those are read, and the line count is shown as well.
+/// `detailed` (--detailed) also lists the settings of every chunk, which reads the whole archive.
+fn do_info(input_path: &str, detailed: bool) {
+    let f_in = match File::open(input_path) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("[!]  Error: Cannot open '{}': {}", input_path, e);
+            std::process::exit(1);
+        }
+    };
+    let archive_size = f_in.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut reader = match ChunkReader::new(std::io::BufReader::new(f_in)) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("[!]  Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let archive = reader.archive_header().clone();
+
+    let lookup = ArchiveIndex::lookup(input_path);
+    let mut chunks = 0;
+    let mut passthrough = 0;
+    let mut duplicates = 0;
+    // Parser configurations of the columnar chunks, in order of first appearance, with their chunk counts
+    let mut parsers: Vec<(ParserConfig, usize)> = Vec::new();
+    let mut count_parser = |parser: Option<ParserConfig>| {
+        let Some(parser) = parser else { return };
+        match parsers.iter_mut().find(|(p, _)| *p == parser) {
+            Some((_, n)) => *n += 1,
+            None => parsers.push((parser, 1)),
+        }
+    };
+    if let IndexLookup::Valid(index) = &lookup {
+        chunks = index.entries.len();
+        passthrough = index.entries.iter().filter(|e| is_passthrough(e.id_flag)).count();
+        duplicates = index.entries.iter().filter(|e| e.id_flag == ID_FLAG_DUPLICATE).count();
+        index.entries.iter().for_each(|e| count_parser(e.parser));
+    } else {
+        loop {
+            match reader.skip_chunk_parsed() {
+                Ok(Some((h, parser))) => {
+                    chunks += 1;
+                    if is_passthrough(h.id_flag) { passthrough += 1; }
+                    if h.id_flag == ID_FLAG_DUPLICATE { duplicates += 1; }
+                    count_parser(parser);
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("[!]  Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    println!("[*]  Inspecting: {}", input_path);
+    if archive.version == LEGACY_VERSION {
+        println!("       Format:         v{} (no archive header)", archive.version);
+    } else {
+        println!("       Format:         v{}", archive.version);
+    }
+    println!("       Checksum:       {}", archive.checksum().label());
+    if archive.authenticated() { println!("       HMAC:           SHA-256 of every chunk (check with -v --hmac-key-file)"); }
+    println!("       Archive Size:   {}", format_bytes(archive_size as usize));
+    match archive.original_size {
+        Some(size) => {
+            println!("       Original Size:  {}", format_bytes(size as usize));
+            if archive_size > 0 { println!("       Ratio:          {:.2}x", size as f64 / archive_size as f64); }
+        },
+        None => println!("       Original Size:  unknown (not recorded by this archive version)"),
+    }
+    if duplicates > 0 {
+        println!("       Chunks:         {} ({} passthrough, {} duplicate)", chunks, passthrough, duplicates);
+    } else {
+        println!("       Chunks:         {} ({} passthrough)", chunks, passthrough);
+    }
+    if archive.version == LEGACY_VERSION {
+        println!("       Parser:         not recorded (format v{})", LEGACY_VERSION);
+    }
+    for (i, (parser, n)) in parsers.iter().enumerate() {
+        let title = if i == 0 { "Parser:" } else { "" };
+        println!("       {:<15} {} ({} chunk(s))", title, parser.render(), n);
+    }
+    if archive.has_bom() { println!("       UTF-8 BOM:      yes (restored on output)"); }
+    if archive.input_changed() { println!("       Input:          changed while it was read (kept with --allow-truncation): may mix old and new content"); }
+    if let Some(names) = archive.column_names() { println!("       Columns:        {}", names.join(", ")); }
+    if let Some(digest) = archive.content_digest() { println!("       SHA-256:        {}", digest_hex(&digest)); }
+    if let Some(order) = archive.row_order() { println!("       Row order:      checksum of {} rows ({:016x})", format_count(order.rows), order.hash); }
+    for (i, (key, value)) in archive.labels.iter().filter(|(k, _)| k != COLUMNS_LABEL && k != CONTENT_DIGEST_LABEL && k != ROW_ORDER_LABEL).enumerate() {
+        let title = if i == 0 { "Labels:" } else { "" };
+        println!("       {:<15} {}={}", title, key, value.escape_debug());
+    }
+    match lookup {
+        IndexLookup::Valid(index) => {
+            println!("       Lines:          {}", index.lines());
+            println!("       Index:          valid ({})", sidecar_path(input_path));
+        },
+        IndexLookup::Stale(reason) => println!("       Index:          stale ({}): rebuild with --index", reason),
+        IndexLookup::Missing => println!("       Index:          none"),
+    }
+    // Only archives made with --pack have a file table
+    if Path::new(&file_table_path(input_path)).exists() {
+        match read_file_table(input_path, archive.original_size) {
+            Ok(files) => println!("       Files:          {} packed ({})", files.len(), file_table_path(input_path)),
+            Err(e) => println!("       Files:          unusable ({})", e),
+        }
+    }
+    if detailed {
+        let settings = File::open(input_path).map_err(CastError::from).and_then(read_block_settings).unwrap_or_else(|e| {
+            eprintln!("[!]  Error: {}", e);
+            std::process::exit(1);
+        });
+        for (title, text) in block_settings_lines(&settings) {
+            println!("       {:<15} {}", title, text);
+        }
+    }
+}
+
+/// Settings recorded by every chunk of an archive, in order (see "BLOCK SETTINGS" in archive.rs).
+/// Reads the whole archive, but decodes nothing.
+fn read_block_settings<R: Read>(input: R) -> Result<Vec<BlockSettings>, CastError> {
+    let mut reader = ChunkReader::new(io::BufReader::new(input))?;
+    let version = reader.archive_header().version;
+    let mut settings = Vec::new();
+    while let Some(chunk) = reader.next_chunk()? {
+        settings.push(BlockSettings::of_chunk(&chunk, version));
+    }
+    Ok(settings)
+}
+
+/// (title, text) lines of `info --detailed` and `-d --verbose`: one per chunk, then the
+/// histogram of their settings and what the archive does not record.
+fn block_settings_lines(settings: &[BlockSettings]) -> Vec<(&'static str, String)> {
+    let mut lines = Vec::new();
+    for (i, block) in settings.iter().enumerate() {
+        lines.push((if i == 0 { "Blocks:" } else { "" }, format!("#{} {}", i + 1, block.describe())));
+    }
+    for (i, (summary, n)) in settings_histogram(settings).into_iter().enumerate() {
+        lines.push((if i == 0 { "Mix:" } else { "" }, format!("{} chunk(s) {}", n, summary)));
+    }
+    lines.push(("Unrecorded:", "xz preset and threads; --max-time fast chunks and 7-Zip fallbacks look like the others (see the run's --manifest)".to_string()));
+    lines
+}
+
+/// `block` JSON event of `-d --verbose --json`.
+fn emit_block_event(idx: usize, block: &BlockSettings) {
+    let mut fields = vec![
+        ("idx", JsonValue::Int(idx as u64)),
+        ("layout", JsonValue::Str(block.layout)),
+        ("id_flag", JsonValue::Int(block.id_flag as u64)),
+        ("backend", JsonValue::Str(block.backend)),
+    ];
+    if let Some(size) = block.dict_size { fields.push(("dict_size", JsonValue::Int(size as u64))); }
+    if let Some(check) = block.check { fields.push(("check", JsonValue::Str(check))); }
+    let codecs = block.column_codecs.join(",");
+    if !codecs.is_empty() { fields.push(("column_codecs", JsonValue::Str(&codecs))); }
+    if let Some(parser) = block.parser { fields.push(("parse", JsonValue::Str(if parser.aggressive { "aggressive" } else { "strict" }))); }
+    if let Some(target) = block.duplicate_of { fields.push(("duplicate_of", JsonValue::Int(target as u64))); }
+    emit_event("block", &fields);
+}
+
+// --- CHECKSUMS ---
+
+/// Prints the CRC32 that compression with `chunk_size` would store for each chunk of a file
+/// (same chunk boundaries, same hashing), plus the CRC32 of the whole file. Comparing them with
+/// the CRCs in an archive tells whether a mismatch comes from the source or from the archive.
+fn do_crc(input_path: &str, chunk_size: Option<usize>) {
+    let fail = |message: String| -> ! {
+        emit_error("crc", &message);
+        eprintln!("[!]  Error: {}", message);
+        std::process::exit(1);
+    };
+    let mut f_in = File::open(input_path).unwrap_or_else(|e| fail(format!("Cannot open '{}': {}", input_path, e)));
+    let file_len = f_in.metadata().map(|m| m.len()).unwrap_or_else(|e| fail(format!("Cannot read input metadata: {}", e)));
+    // As when compressing: a leading BOM goes to the archive header, not into the first chunk
+    let has_bom = skip_utf8_bom(&mut f_in).unwrap_or_else(|e| fail(format!("Cannot read input: {}", e)));
+    let bom_len = if has_bom { UTF8_BOM.len() } else { 0 };
+
+    let boundaries = match chunk_size {
+        Some(size) => format!("--chunk-size {}", format_bytes(size)),
+        None => "solid (no --chunk-size)".to_string(),
+    };
+    emit_event("start", &[("command", JsonValue::Str("crc")), ("input", JsonValue::Str(input_path))]);
+    say!("[*]  Chunk checksums: {}", input_path);
+    say!("       Boundaries:  {}", boundaries);
+    if has_bom { say!("       UTF-8 BOM:   yes (kept out of the chunks, as in the archive)"); }
+    say!("\n       {:>6} {:>18} {:>18} {:>12}", "Chunk", "Offset", "Length", "CRC32");
+
+    let mut whole = crc32fast::Hasher::new();
+    whole.update(&UTF8_BOM[..bom_len]);
+    let mut chunker = InputChunker::new(f_in, chunk_size, file_len as usize - bom_len);
+    let mut offset = bom_len;
+    let mut chunks = 0;
+    while let Some(chunk) = chunker.next_chunk().unwrap_or_else(|e| fail(format!("Cannot read chunk: {}", e))) {
+        chunks += 1;
+        let crc = chunk_crc(chunk);
+        whole.update(chunk);
+        let crc_hex = format!("0x{:08x}", crc);
+        // Compression stores such a chunk as several when its segments overflow (see MAX_SEGMENT_LEN)
+        let note = if chunk.len() as u64 >= MAX_SEGMENT_LEN { "  (may be split in the archive)" } else { "" };
+        say!("       {:>6} {:>18} {:>18} {:>12}{}", chunks, offset, chunk.len(), crc_hex, note);
+        emit_event("crc", &[
+            ("idx", JsonValue::Int(chunks as u64)),
+            ("offset", JsonValue::Int(offset as u64)),
+            ("bytes", JsonValue::Int(chunk.len() as u64)),
+            ("crc", JsonValue::Str(&crc_hex)),
+        ]);
+        offset += chunk.len();
+    }
+
+    let whole_hex = format!("0x{:08x}", whole.finalize());
+    say!("\n[+]  Whole file:  CRC32 {} ({}, {} chunk(s))", whole_hex, format_bytes(offset), chunks);
+    emit_event("done", &[
+        ("command", JsonValue::Str("crc")),
+        ("chunks", JsonValue::Int(chunks as u64)),
+        ("bytes_in", JsonValue::Int(offset as u64)),
+        ("crc", JsonValue::Str(&whole_hex)),
+    ]);
+}
+
+// --- FORMAT DUMP ---
+
+// Chunks shown by --dump-format unless --max-chunks says otherwise
+const DUMP_DEFAULT_CHUNKS: usize = 16;
+// Raw bytes printed per field (longer fields end with "..")
+const DUMP_FIELD_BYTES: usize = 12;
+
+/// One annotated line of the dump: offset, raw bytes, field name and decoded meaning.
+fn dump_field(offset: &str, bytes: &[u8], name: &str, meaning: &str) {
+    let mut hex: Vec<String> = bytes.iter().take(DUMP_FIELD_BYTES).map(|b| format!("{:02x}", b)).collect();
+    if bytes.len() > DUMP_FIELD_BYTES { hex.push("..".to_string()); }
+    println!("  {:>12}  {:<38} {:<14} {}", offset, hex.join(" "), name, meaning);
+}
+
+fn le_value(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |v, &b| (v << 8) | b as u64)
+}
+
+/// How the compressor numbers the templates of a chunk with this layout (None: one template
+/// or no columnar data).
+fn template_order(layout: &str) -> Option<&'static str> {
+    match layout {
+        "Split" => Some("first appearance"),
+        "Unified" => Some("most frequent first, ties in first-appearance order"),
+        "Column streams" => Some("most frequent first (first appearance when the layout heuristic chose split)"),
+        _ => None,
+    }
+}
+
+/// Whether the file ends like a random-access preview archive ("CAST" + footer version).
+fn ends_with_footer_magic(path: &str) -> bool {
+    let mut tail = [0u8; 5];
+    File::open(path).and_then(|mut f| {
+        f.seek(SeekFrom::End(-(tail.len() as i64)))?;
+        f.read_exact(&mut tail)
+    }).is_ok() && tail[..4] == ARCHIVE_MAGIC
+}
+
+/// Prints an annotated walk of the archive structures, parsed by the same code as the
+/// decompressor: archive header, chunk headers and the block header of each chunk.
+fn do_dump_format(input_path: &str, max_chunks: usize, decompress_opts: &DecompressOptions) {
+    let fail = |e: &dyn std::fmt::Display| -> ! {
+        eprintln!("[!]  Error: {}", e);
+        std::process::exit(1);
+    };
+    let archive_size = std::fs::metadata(input_path).map(|m| m.len()).unwrap_or_else(|e| fail(&e));
+    let mut reader = ChunkReader::new(io::BufReader::new(File::open(input_path).unwrap_or_else(|e| fail(&e)))).unwrap_or_else(|e| fail(&e));
+    let archive = reader.archive_header().clone();
+    if archive.version == LEGACY_VERSION && ends_with_footer_magic(input_path) {
+        // The random-access preview format keeps its metadata in a footer this build does not parse
+        eprintln!("[!]  '{}' looks like a random-access (preview) archive: its footer cannot be dumped by this tool.", input_path);
+        std::process::exit(1);
+    }
+    let decompressor = decompress_opts.decompressor(&archive);
+
+    println!("[*]  Format dump: {} ({})", input_path, format_bytes(archive_size as usize));
+    println!("\n     Archive header (v{}, {} bytes)", archive.version, archive.encoded_len());
+    if archive.version == LEGACY_VERSION {
+        println!("  {:>12}  (none: version 1 archives start with the first chunk)", 0);
+    } else {
+        let mut raw = vec![0u8; archive.encoded_len()];
+        File::open(input_path).and_then(|mut f| f.read_exact(&mut raw)).unwrap_or_else(|e| fail(&e));
+        let mut reencoded = Vec::new();
+        archive.write_to(&mut reencoded).unwrap_or_else(|e| fail(&e));
+        for (name, range) in archive.layout() {
+            let bytes = &raw[range.clone()];
+            let meaning = match name {
+                "magic" => format!("{:?}", String::from_utf8_lossy(bytes)),
+                "flags" => format!("checksum {}{}{}{}", archive.checksum().label(), if archive.has_bom() { ", input BOM" } else { "" },
+                    if archive.input_changed() { ", input changed" } else { "" }, if archive.authenticated() { ", authenticated" } else { "" }),
+                "original_size" => archive.original_size.map_or("unknown".to_string(), |n| format_bytes(n as usize)),
+                "key" | "value" => format!("{:?}", String::from_utf8_lossy(bytes)),
+                _ => le_value(bytes).to_string(),
+            };
+            dump_field(&range.start.to_string(), bytes, name, &meaning);
+        }
+        if reencoded != raw { println!("[!]  The header does not re-encode to the same bytes"); }
+    }
+
+    println!("\n     Limits: len_reg/len_ids/len_vars are u32, at most {} per segment", format_bytes(MAX_SEGMENT_LEN as usize));
+    println!("             (an input chunk that would exceed it is written as several chunks)");
+    println!("             template ids are u8 up to 255 templates per block, u16 up to {}, u32 beyond", format_count(U16_MAX_TEMPLATES as u64));
+    println!("             (a chunk with more distinct templates than {:.0}% of its lines, {:.0}% in Aggressive mode, is stored",
+        STRICT_TEMPLATE_RATIO * 100.0, AGGRESSIVE_TEMPLATE_RATIO * 100.0);
+    println!("             as passthrough: u32 ids take about {} lines in one chunk, {} in Aggressive mode)",
+        format_count(min_lines_for_templates(U16_MAX_TEMPLATES + 1, false)), format_count(min_lines_for_templates(U16_MAX_TEMPLATES + 1, true)));
+
+    let mut offset = archive.encoded_len();
+    let mut shown = 0;
+    loop {
+        if shown == max_chunks {
+            let mut rest = 0;
+            while reader.skip_chunk().unwrap_or_else(|e| fail(&e)).is_some() { rest += 1; }
+            if rest > 0 { println!("\n     ... {} more chunk(s) not shown (--max-chunks)", rest); }
+            break;
+        }
+        let Some(chunk) = reader.next_chunk().unwrap_or_else(|e| fail(&e)) else { break };
+        shown += 1;
+        println!("\n     Chunk {} at {} ({} + {} bytes)", shown, offset, CHUNK_HEADER_SIZE, chunk.body.len());
+        dump_chunk_header(offset, &chunk.header, archive.checksum());
+        let mut body_offset = offset + CHUNK_HEADER_SIZE;
+        for (name, bytes) in [("reg", chunk.reg()), ("ids", chunk.ids()), ("parser", chunk.parser_record()), ("vars", chunk.vars())] {
+            let meaning = match name {
+                "parser" if bytes.is_empty() => continue,
+                "parser" => chunk.parser_config().map_or("unreadable".to_string(), |p| p.render()),
+                _ => format!("{} (compressed)", format_bytes(bytes.len())),
+            };
+            dump_field(&body_offset.to_string(), bytes, name, &meaning);
+            body_offset += bytes.len();
+        }
+
+        if let Some(target) = chunk.duplicate_of() {
+            println!("     Duplicate of chunk {} (restored from it, nothing to decode)", target);
+            offset = body_offset;
+            continue;
+        }
+        let inspected = decompressor.inspect(chunk.reg(), chunk.ids(), chunk.vars(), chunk.header.id_flag);
+        let order = inspected.as_ref().ok().and_then(|layout| template_order(layout.layout));
+        let cell_counts = inspected.as_ref().map(|layout| layout.cell_counts.clone()).unwrap_or_default();
+        let ids_transform = inspected.as_ref().map(|layout| layout.ids_transform).unwrap_or_default();
+        let ids_rle = inspected.as_ref().is_ok_and(|layout| layout.ids_rle);
+        match inspected {
+            Ok(layout) if layout.block.is_empty() => {
+                println!("     {} after the backend: reg {}, ids {}, vars {}", layout.layout,
+                    format_bytes(layout.reg_len), format_bytes(layout.ids_len), format_bytes(layout.vars_len));
+            },
+            Ok(layout) => {
+                println!("     {} block after the backend ({})", layout.layout, format_bytes(layout.block.len()));
+                for (name, range, value) in &layout.fields {
+                    dump_field(&format!("+{}", range.start), &layout.block[range.clone()], name, &value.to_string());
+                }
+                let reg_start = layout.fields.last().map_or(0, |f| f.1.end);
+                let ids_start = reg_start + layout.reg_len;
+                let vars_start = ids_start + layout.ids_len;
+                dump_field(&format!("+{}", reg_start), &layout.block[reg_start..ids_start], "reg", &format_bytes(layout.reg_len));
+                dump_field(&format!("+{}", ids_start), &layout.block[ids_start..vars_start], "ids", &format_bytes(layout.ids_len));
+                let in_block = layout.block.len() - vars_start;
+                let vars_meaning = if in_block == layout.vars_len { format_bytes(layout.vars_len) } else { format!("{} ({} in this stream)", format_bytes(layout.vars_len), format_bytes(in_block)) };
+                dump_field(&format!("+{}", vars_start), &layout.block[vars_start..], "vars", &vars_meaning);
+                if !layout.segments.is_empty() {
+                    let segments: Vec<String> = layout.segments.iter().map(|(codec, len)| format!("{} {}", codec, len)).collect();
+                    println!("     Column segments: {}", segments.join(" | "));
+                }
+            },
+            Err(e) => println!("[!]  Block cannot be decoded: {}", e.at_chunk(shown)),
+        }
+        if let Some(order) = order {
+            println!("     Template ids: {} (registry entries, ids and var columns follow this order)", order);
+        }
+        if ids_rle {
+            println!("     Id stream: RLE (id, run length) pairs");
+        } else if ids_transform != IdsTransform::None {
+            println!("     Id stream: {} transform (undone after the backend)", ids_transform.name());
+        }
+        if !cell_counts.is_empty() {
+            let mut shown_counts: Vec<String> = cell_counts.iter().take(16).map(|n| n.to_string()).collect();
+            if cell_counts.len() > 16 { shown_counts.push("...".to_string()); }
+            println!("     Cell counts: {} column(s): {}", cell_counts.len(), shown_counts.join(" | "));
+        }
+        offset = body_offset;
+    }
+    if shown == 0 { println!("\n     (no chunks)"); }
+
+    if let Some(trailer) = reader.mac_trailer() {
+        let offset = archive_size as usize - CHUNK_HEADER_SIZE - trailer.body.len();
+        println!("\n     Authentication trailer at {} ({} + {} bytes)", offset, CHUNK_HEADER_SIZE, trailer.body.len());
+        dump_chunk_header(offset, &trailer.header, ChecksumKind::Crc32);
+        let meaning = match MacTrailer::from_chunk(trailer, 0) {
+            Ok(t) => format!("HMAC-SHA256 of {} chunk(s) and of the archive", t.chunk_macs.len()),
+            Err(e) => format!("unreadable: {}", e),
+        };
+        dump_field(&(offset + CHUNK_HEADER_SIZE).to_string(), trailer.vars(), "vars", &meaning);
+    }
+}
+
+/// Prints the fields of a chunk header stored at `offset`.
+fn dump_chunk_header(offset: usize, header: &ChunkHeader, checksum: ChecksumKind) {
+    let raw = header.to_bytes();
+    for (name, range) in ChunkHeader::layout() {
+        let bytes = &raw[range.clone()];
+        let meaning = match name {
+            "crc" if checksum == ChecksumKind::None => "not stored".to_string(),
+            "crc" => format!("0x{:08x}", header.crc),
+            "id_flag" => format!("0x{:02x}: {}", header.id_flag, describe_id_flag(header.id_flag)),
+            _ => le_value(bytes).to_string(),
+        };
+        dump_field(&(offset + range.start).to_string(), bytes, name, &meaning);
+    }
+}
+
+// --- TEMPLATE AUDIT ---
+
+// Characters of a sample row shown by --audit
+const AUDIT_SAMPLE_CHARS: usize = 160;
+
+/// Prints the distinct templates of an archive as a template file (see `cast::templates`),
+/// in order of first appearance.
+fn do_templates(input_path: &str, decompress_opts: &DecompressOptions) -> Result<(), CastError> {
+    let mut reader = ChunkReader::new(io::BufReader::new(File::open(input_path)?))?;
+    let decompressor = decompress_opts.decompressor(reader.archive_header());
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut seen = HashSet::new();
+    let mut rendered = Vec::new();
+    let mut passthrough = Vec::new();
+    let mut parsers = Vec::new();
+    let mut chunk_idx = 0;
+    while let Some(chunk) = reader.next_chunk()? {
+        chunk_idx += 1;
+        // A duplicate chunk has the templates of the earlier chunk it refers to
+        if chunk.duplicate_of().is_some() { continue; }
+        if let Some(parser) = chunk.parser_config().filter(|p| !parsers.contains(p)) { parsers.push(parser); }
+        match decompressor.templates(chunk.reg(), chunk.ids(), chunk.vars(), chunk.header.id_flag).map_err(|e| e.at_chunk(chunk_idx))? {
+            Some(templates) => {
+                for skeleton in templates.skeletons {
+                    let line = render_template(&skeleton);
+                    if seen.insert(line.clone()) { rendered.push(line); }
+                }
+            },
+            None => passthrough.push(chunk_idx),
+        }
+    }
+    writeln!(out, "# Templates of {}: {} from {} chunk(s)", input_path, rendered.len(), chunk_idx)?;
+    for parser in parsers { writeln!(out, "{}{}", PARSER_COMMENT, parser.render())?; }
+    for idx in passthrough { writeln!(out, "# chunk {}: passthrough, no templates", idx)?; }
+    for line in rendered { writeln!(out, "{}", line)?; }
+    out.flush()?;
+    Ok(())
+}
+
+/// Prints the templates of an archive merged over its chunks by skeleton (see
+/// `TemplateTally`), with their rows, share of the restored bytes and chunk count, in `sort`
+/// order, keeping those with at least `min_rows` rows and then the first `top`.
+fn do_list_templates(input_path: &str, decompress_opts: &DecompressOptions, sort: TemplateSort, min_rows: u64, top: Option<u64>) -> Result<(), CastError> {
+    let mut reader = ChunkReader::new(io::BufReader::new(File::open(input_path)?))?;
+    let decompressor = decompress_opts.decompressor(reader.archive_header());
+    let mut tally = TemplateTally::default();
+    let (mut chunk_idx, mut passthrough) = (0, 0);
+    let mut parsers = Vec::new();
+    // A duplicate chunk counts with the templates of the chunk it refers to
+    while let Some(chunk) = reader.next_resolved()? {
+        chunk_idx += 1;
+        if let Some(parser) = chunk.parser_config().filter(|p| !parsers.contains(p)) { parsers.push(parser); }
+        match decompressor.columns(chunk.reg(), chunk.ids(), chunk.vars(), chunk.header.id_flag).map_err(|e| e.at_chunk(chunk_idx))? {
+            Some(templates) => tally.add_chunk(&templates),
+            None => passthrough += 1,
+        }
+    }
+    let mut usage = tally.into_usage();
+    let (distinct, total_bytes) = (usage.len(), usage.iter().map(|u| u.bytes).sum::<u64>());
+    sort.sort(&mut usage);
+    usage.retain(|u| u.rows >= min_rows);
+    let matching = usage.len();
+    if let Some(top) = top { usage.truncate(top as usize); }
+    let share = |bytes: u64| if total_bytes > 0 { bytes as f64 * 100.0 / total_bytes as f64 } else { 0.0 };
+
+    if json_output() {
+        for u in &usage {
+            emit_event("template", &[
+                ("id", JsonValue::Int(u.id as u64)),
+                ("rows", JsonValue::Int(u.rows)),
+                ("bytes", JsonValue::Int(u.bytes)),
+                ("share", JsonValue::Float(share(u.bytes))),
+                ("chunks", JsonValue::Int(u.chunks)),
+                ("template", JsonValue::Str(&render_listing(&u.skeleton))),
+            ]);
+        }
+        emit_event("done", &[
+            ("command", JsonValue::Str("list-templates")),
+            ("chunks", JsonValue::Int(chunk_idx as u64)),
+            ("passthrough_chunks", JsonValue::Int(passthrough as u64)),
+            ("templates", JsonValue::Int(distinct as u64)),
+            ("parsers", JsonValue::Int(parsers.len() as u64)),
+            ("matching", JsonValue::Int(matching as u64)),
+            ("shown", JsonValue::Int(usage.len() as u64)),
+        ]);
+        return Ok(());
+    }
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    writeln!(out, "[*]  Templates of {}: {} distinct in {} chunk(s), sorted by {}", input_path, distinct, chunk_idx, sort.name())?;
+    if passthrough > 0 { writeln!(out, "       Passthrough: {} chunk(s) without templates", passthrough)?; }
+    for (i, parser) in parsers.iter().enumerate() {
+        writeln!(out, "       {:<12} {}", if i == 0 { "Parser:" } else { "" }, parser.render())?;
+    }
+    if parsers.len() > 1 { writeln!(out, "       (chunks parsed differently may list one line under different templates)")?; }
+    if min_rows > 0 || usage.len() < matching || matching < distinct {
+        writeln!(out, "       Shown:       {} of {} ({} with at least {} row(s))", usage.len(), distinct, matching, min_rows)?;
+    }
+    writeln!(out, "
+  {:>7}  {:>12}  {:>6}  {:>6}  Template", "Id", "Rows", "Share", "Chunks")?;
+    for u in &usage {
+        writeln!(out, "  {:>7}  {:>12}  {:>5.1}%  {:>6}  {}", u.id, format_count(u.rows), share(u.bytes), u.chunks, render_listing(&u.skeleton))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Checks every template of the archive against `allowed`, decoding only the registries (and
+/// ids), and prints one sample row per unknown template. Returns whether the archive is clean:
+/// passthrough chunks have no templates to check and always count against it.
+fn do_audit(input_path: &str, allowed_path: &str, allowed: &TemplateSet, decompress_opts: &DecompressOptions) -> Result<bool, CastError> {
+    let mut reader = ChunkReader::new(io::BufReader::new(File::open(input_path)?))?;
+    let archive = reader.archive_header().clone();
+    let decompressor = decompress_opts.decompressor(&archive);
+
+    println!("[*]  Template audit: {}", input_path);
+    println!("       Allowed:     {} template(s) from {}", allowed.len(), allowed_path);
+    if allowed.parsers().is_empty() {
+        println!("       Parser:      not recorded in {}: templates are compared whatever the parser configuration", allowed_path);
+    } else if archive.version == LEGACY_VERSION {
+        println!("       Parser:      not recorded in {} (format v{}): templates are compared as they are", input_path, archive.version);
+    }
+
+    let mut distinct = HashSet::new();
+    let mut whitespace_matches = HashSet::new();
+    let (mut rogue_chunks, mut unauditable_chunks, mut incomparable_chunks) = (0, 0, 0);
+    let mut chunk_idx = 0;
+    while let Some(chunk) = reader.next_chunk()? {
+        chunk_idx += 1;
+        // Audited with the earlier chunk it refers to
+        if chunk.duplicate_of().is_some() { continue; }
+        let Some(templates) = decompressor.templates(chunk.reg(), chunk.ids(), chunk.vars(), chunk.header.id_flag).map_err(|e| e.at_chunk(chunk_idx))? else {
+            println!("[!]  Chunk {}: unauditable (passthrough: stored as plain data, without templates)", chunk_idx);
+            unauditable_chunks += 1;
+            continue;
+        };
+        // Skeletons of another tokenizer configuration would all look unknown (or match by chance)
+        if let Some(parser) = chunk.parser_config() {
+            if let Err(differences) = allowed.comparable(&parser) {
+                println!("[!]  Chunk {}: not comparable (parsed with {})", chunk_idx, parser.render());
+                println!("       differs from the configuration of {}: {}", allowed_path, differences.join(", "));
+                incomparable_chunks += 1;
+                continue;
+            }
+        }
+        let mut unknown = Vec::new();
+        for (skeleton, first_row) in templates.skeletons.iter().zip(&templates.first_rows) {
+            distinct.insert(skeleton.clone());
+            match allowed.check(skeleton) {
+                Some(TemplateMatch::Exact) => {},
+                Some(TemplateMatch::Whitespace) => { whitespace_matches.insert(skeleton.clone()); },
+                // A registry entry no row uses cannot put anything in the archive
+                None if first_row.is_none() => {},
+                None => unknown.push((skeleton, first_row.unwrap_or(0))),
+            }
+        }
+        if unknown.is_empty() { continue; }
+
+        rogue_chunks += 1;
+        println!("[!]  Chunk {}: {} unknown template(s) of {}", chunk_idx, unknown.len(), templates.skeletons.len());
+        // Samples need the rows themselves: only this chunk is restored
+        let mut restored = Vec::new();
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), chunk.expected_crc(&archive), chunk.header.id_flag, &mut restored)
+            .map_err(|e| e.at_chunk(chunk_idx))?;
+        let lines: Vec<&[u8]> = restored.split_inclusive(|&b| b == b'\n').collect();
+        for (skeleton, row) in unknown {
+            println!("       {}", render_template(skeleton));
+            let sample = String::from_utf8_lossy(lines.get(row).copied().unwrap_or_default());
+            let sample = sample.trim_end_matches(['\r', '\n']);
+            let shown: String = sample.chars().take(AUDIT_SAMPLE_CHARS).collect();
+            let more = if shown.len() < sample.len() { " ..." } else { "" };
+            println!("         line {} of the chunk: {}{}", row + 1, shown.escape_debug(), more);
+        }
+    }
+
+    println!("       Chunks:      {}", chunk_idx);
+    println!("       Templates:   {} distinct{}", distinct.len(),
+        if whitespace_matches.is_empty() { String::new() } else { format!(" ({} allowed only after whitespace normalization)", whitespace_matches.len()) });
+    if rogue_chunks + unauditable_chunks + incomparable_chunks == 0 {
+        println!("\n[+]  Audit clean: every template is allowed.");
+        return Ok(true);
+    }
+    let mut problems = Vec::new();
+    if rogue_chunks > 0 { problems.push(format!("{} with unknown templates", rogue_chunks)); }
+    if unauditable_chunks > 0 { problems.push(format!("{} unauditable", unauditable_chunks)); }
+    if incomparable_chunks > 0 { problems.push(format!("{} not comparable: parsed with another configuration", incomparable_chunks)); }
+    println!("\n[!]  Audit failed: {} of {} chunk(s) ({}).", rogue_chunks + unauditable_chunks + incomparable_chunks, chunk_idx, problems.join(", "));
+    Ok(false)
+}
+
+// --- RELABEL ---
+
+/// Rewrites the archive header with `changes` applied (an empty value removes the key).
+/// The new header and the untouched chunk bytes go to a temporary file that then replaces
+/// the archive, so an interrupted run never leaves a half-written archive behind.
+fn do_relabel(input_path: &str, changes: &[(String, String)], cancel: &CancellationToken) {
+    let fail = |msg: String| -> ! {
+        eprintln!("[!]  Error: {}", msg);
+        std::process::exit(1);
+    };
+    let f_in = File::open(input_path).unwrap_or_else(|e| fail(format!("Cannot open '{}': {}", input_path, e)));
+    let mut reader = std::io::BufReader::new(f_in);
+    let old = ArchiveHeader::read_from(&mut reader).unwrap_or_else(|e| fail(e.to_string()));
+    // The archive HMAC covers the header, labels included
+    if old.authenticated() {
+        fail(format!("'{}' is authenticated (--hmac-key-file): new labels would fail its HMAC check. Compress it again with the labels instead.", input_path));
+    }
+    // Version 1 chunks have no parser records, which every version 2 columnar chunk opens with
+    if old.version == LEGACY_VERSION {
+        fail(format!("'{}' is a version 1 archive, which has no header to hold labels. Compress it again with the labels instead.", input_path));
+    }
+
+    let mut labels = old.labels.clone();
+    for (key, value) in changes {
+        if value.is_empty() { labels.retain(|(k, _)| k != key); } else { set_label(&mut labels, key.clone(), value.clone()); }
+    }
+    let header = old.clone().with_labels(labels).unwrap_or_else(|e| fail(e));
+    // A valid sidecar only needs its offsets moved; a stale one stays stale
+    let index = match ArchiveIndex::lookup(input_path) { IndexLookup::Valid(i) => Some(i), _ => None };
+
+    let tmp_path = format!("{}.relabel.tmp", input_path);
+    let result = (|| -> io::Result<u64> {
+        let mut out = BufWriter::new(CancellableWriter { inner: File::create(&tmp_path)?, cancel });
+        header.write_to(&mut out)?;
+        let copied = io::copy(&mut reader, &mut out)?;
+        let out = out.into_inner().map_err(|e| e.into_error())?.inner;
+        out.sync_all()?;
+        std::fs::rename(&tmp_path, input_path)?;
+        Ok(copied)
+    })();
+    let copied = result.unwrap_or_else(|e| {
+        let _ = std::fs::remove_file(&tmp_path);
+        if cancel.is_cancelled() { exit_cancelled("relabel", "archive left unchanged"); }
+        fail(format!("Cannot rewrite '{}': {}", input_path, e))
+    });
+    if let Some(mut index) = index {
+        let delta = header.encoded_len() as i64 - old.encoded_len() as i64;
+        let last = index.entries.last().map(|e| e.offset.wrapping_add_signed(delta));
+        let rebased = (|| -> Result<(), String> {
+            let mut f = File::open(input_path).map_err(|e| e.to_string())?;
+            let fingerprint = archive_fingerprint(&mut f, last).map_err(|e| e.to_string())?;
+            index.rebase(delta, f.metadata().map_err(|e| e.to_string())?.len(), fingerprint);
+            index.save(input_path).map_err(|e| e.to_string())
+        })();
+        if let Err(e) = rebased { eprintln!("[!]  Warning: Cannot update '{}': {}", sidecar_path(input_path), e); }
+    }
+
+    println!("[+]  Relabeled: {}", input_path);
+    println!("       Header:         {} -> {}", format_bytes(old.encoded_len()), format_bytes(header.encoded_len()));
+    println!("       Chunk Data:     {} (copied unchanged)", format_bytes(copied as usize));
+    if header.labels.is_empty() { println!("       Labels:         none"); }
+    for (i, (key, value)) in header.labels.iter().enumerate() {
+        let title = if i == 0 { "Labels:" } else { "" };
+        println!("       {:<15} {}={}", title, key, value.escape_debug());
+    }
+}
+
+// --- DEBUG VERIFICATION ---
+
+const DEBUG_PREVIEW_LINES: usize = 5;
+const DEBUG_LINE_WINDOW: usize = 100;
+
+fn report_first_difference(decompressor: &CASTLzmaDecompressor, chunk: &Chunk, restored: &[u8], original: Option<&[u8]>) {
+    let Some(original) = original else {
+        // Nothing to compare against: show the beginning of what was reconstructed
+        diag!("[*]  No reference data available (use --verify-against). First reconstructed lines:");
+        let mut offset = 0;
+        for line in restored.split(|&b| b == b'\n').take(DEBUG_PREVIEW_LINES) {
+            let (text, _) = excerpt(line, 0);
+            diag!("       [{}]\n         {}", describe_origin(decompressor, chunk, offset), text);
+            offset += line.len() + 1;
+        }
+        return;
+    };
+
+    let common = restored.len().min(original.len());
+    let diff = restored.iter().zip(original).position(|(a, b)| a != b).unwrap_or(common);
+    let line_start = original[..diff].iter().rposition(|&b| b == b'\n').map(|p| p + 1).unwrap_or(0);
+    let line_no = original[..line_start].iter().filter(|&&b| b == b'\n').count() + 1;
+    let line_of = |buf: &[u8]| {
+        let s = line_start.min(buf.len());
+        let end = buf[s..].iter().position(|&b| b == b'\n').map(|p| s + p).unwrap_or(buf.len());
+        buf[s..end].to_vec()
+    };
+
+    let (orig_text, caret) = excerpt(&line_of(original), diff - line_start);
+    let (rest_text, _) = excerpt(&line_of(restored), diff - line_start);
+
+    diag!("[*]  First difference at byte {} of the chunk (line {}):", diff, line_no);
+    diag!("       Original:  {}", orig_text);
+    diag!("       Restored:  {}", rest_text);
+    diag!("                  {}^", " ".repeat(caret));
+    if diff >= restored.len() {
+        diag!("       Origin:    Restored chunk ends early ({} vs {})", format_bytes(restored.len()), format_bytes(original.len()));
+    } else {
+        diag!("       Origin:    {}", describe_origin(decompressor, chunk, diff));
+    }
+}
+
+fn describe_origin(decompressor: &CASTLzmaDecompressor, chunk: &Chunk, offset: usize) -> String {
+    match decompressor.locate(chunk.reg(), chunk.ids(), chunk.vars(), chunk.header.id_flag, offset) {
+        Ok(Some(p)) => match p.column {
+            Some(col) => format!("Row #{} | Template #{} | Column {}", p.row + 1, p.template_id, col),
+            None => format!("Row #{} | Template #{} | Literal part {}", p.row + 1, p.template_id, p.part_index),
+        },
+        Ok(None) => if is_passthrough(chunk.header.id_flag) { "Passthrough chunk".to_string() } else { "Unknown".to_string() },
+        Err(e) => format!("Unavailable ({})", e),
+    }
+}
+
+/// Printable window of a line around byte `pos`. Returns the text and the caret column.
+fn excerpt(line: &[u8], pos: usize) -> (String, usize) {
+    let from = pos.saturating_sub(DEBUG_LINE_WINDOW / 2);
+    let to = (from + DEBUG_LINE_WINDOW).min(line.len());
+    let prefix = if from > 0 { "..." } else { "" };
+    let clean = |b: &[u8]| -> String {
+        String::from_utf8_lossy(b).chars().map(|c| if c.is_control() { '.' } else { c }).collect()
+    };
+    let head = clean(&line[from.min(line.len())..pos.min(line.len())]);
+    let caret = prefix.len() + head.chars().count();
+    (format!("{}{}", prefix, clean(&line[from.min(line.len())..to])), caret)
+}
+
+// --- SYNTHETIC DATASETS ---
+
+fn do_generate(profile: Profile, size: u64, seed: u64, output_path: &str, cancel: &CancellationToken, on_cancel: CancelPolicy) {
+    let start = Instant::now();
+    let result = File::create(output_path).and_then(|f| {
+        let mut out = BufWriter::with_capacity(1024 * 1024, CancellableWriter { inner: f, cancel });
+        let written = generate(profile, size, seed, &mut out)?;
+        out.flush()?;
+        Ok(written)
+    });
+    match result {
+        Ok(written) => {
+            println!("[+]  Generated: {}", output_path);
+            println!("       Profile:        {} (seed {})", profile.name(), seed);
+            println!("       Size:           {}", format_bytes(written as usize));
+            println!("       Time:           {:.2}s", start.elapsed().as_secs_f64());
+        },
+        Err(_) if cancel.is_cancelled() => exit_cancelled("generate", cancel_note(output_path, on_cancel)),
+        Err(e) => {
+            eprintln!("[!]  Error: Cannot write '{}': {}", output_path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Splits the dataset over `count` files of about `size / count` bytes in the directory
+/// `output_dir` (small-file fixtures for --pack). File i uses seed `seed + i`.
+fn do_generate_files(profile: Profile, size: u64, seed: u64, output_dir: &str, count: usize, cancel: &CancellationToken, on_cancel: CancelPolicy) {
+    let start = Instant::now();
+    let per_file = (size / count as u64).max(1);
+    // The file being written when a run is cancelled, and the files complete before it
+    let mut current = (String::new(), 0);
+    let result = std::fs::create_dir_all(output_dir).and_then(|_| {
+        let mut written = 0u64;
+        for i in 0..count {
+            let path = Path::new(output_dir).join(format!("{}-{:06}.{}", profile.name(), i, profile.extension()));
+            current = (path.to_string_lossy().into_owned(), i);
+            let mut out = BufWriter::new(CancellableWriter { inner: File::create(path)?, cancel });
+            written += generate(profile, per_file, seed.wrapping_add(i as u64), &mut out)?;
+            out.flush()?;
+        }
+        Ok(written)
+    });
+    match result {
+        Ok(written) => {
+            println!("[+]  Generated: {}", output_dir);
+            println!("       Profile:        {} (seed {})", profile.name(), seed);
+            println!("       Files:          {} of about {}", count, format_bytes(per_file as usize));
+            println!("       Size:           {}", format_bytes(written as usize));
+            println!("       Time:           {:.2}s", start.elapsed().as_secs_f64());
+        },
+        Err(_) if cancel.is_cancelled() => {
+            let note = cancel_note(&current.0, on_cancel);
+            exit_cancelled("generate", &format!("{} of {} files complete, {}", current.1, count, note))
+        },
+        Err(e) => {
+            eprintln!("[!]  Error: Cannot write '{}': {}", output_dir, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// --- SELF BENCHMARK (TRANSFORM ONLY) ---
+
+const BENCH_SELF_DEFAULT_SIZE: usize = 32 * 1024 * 1024;
+const BENCH_SELF_WARMUP_RUNS: usize = 1;
+const BENCH_SELF_RUNS: usize = 5;
+
+fn do_bench_self(size: usize) {
+    println!("\n[*]  Self Benchmark (Identity Backend, no LZMA)");
+    let data = generate_bench_data(size);
+    println!("       Data:        {} (generated logs)", format_bytes(data.len()));
+    println!("       Runs:        {} (+{} warm-up)", BENCH_SELF_RUNS, BENCH_SELF_WARMUP_RUNS);
+
+    let mut compress_secs = Vec::with_capacity(BENCH_SELF_RUNS);
+    let mut decompress_secs = Vec::with_capacity(BENCH_SELF_RUNS);
+    let mut restored = Vec::with_capacity(data.len());
+
+    for run in 0..BENCH_SELF_WARMUP_RUNS + BENCH_SELF_RUNS {
+        let mut compressor = CASTCompressor::new(IdentityBackend);
+        let t = Instant::now();
+        let (r, i, v, flag, _) = match compressor.compress(&data) {
+            Ok(res) => res,
+            Err(e) => { eprintln!("[!]  Compression error: {}", e); std::process::exit(1); }
+        };
+        let compress_time = t.elapsed().as_secs_f64();
+
+        restored.clear();
+        let decompressor = CASTDecompressor::new(IdentityBackend);
+        let t = Instant::now();
+        if let Err(e) = decompressor.decompress(&r, &i, &v, None, flag, &mut restored) {
+            eprintln!("[!]  Decompression error: {}", e);
+            std::process::exit(1);
+        }
+        let decompress_time = t.elapsed().as_secs_f64();
+
+        if restored != data {
+            eprintln!("[!]  Round trip mismatch on run {}", run + 1);
+            std::process::exit(1);
+        }
+        if run >= BENCH_SELF_WARMUP_RUNS {
+            compress_secs.push(compress_time);
+            decompress_secs.push(decompress_time);
+        }
+    }
+
+    let mb = data.len() as f64 / (1024.0 * 1024.0);
+    let report = |label: &str, secs: &[f64]| {
+        let avg = secs.iter().sum::<f64>() / secs.len() as f64;
+        let best = secs.iter().cloned().fold(f64::INFINITY, f64::min);
+        println!("       {:<12} avg {:>8.2} MB/s | best {:>8.2} MB/s | avg {:.3}s", label, mb / avg, mb / best, avg);
+    };
+    println!("\n[+]  Results");
+    report("Tokenize:", &compress_secs);
+    report("Reconstruct:", &decompress_secs);
+}
+
+/// Deterministic, log-like data (a handful of templates with timestamps, IPs, levels and ids),
+/// so numbers are comparable across commits.
+fn generate_bench_data(size: usize) -> Vec<u8> {
+    const LEVELS: [&str; 4] = ["INFO", "WARN", "ERROR", "DEBUG"];
+    const PATHS: [&str; 5] = ["/api/v1/users", "/api/v1/orders", "/login", "/static/app.js", "/health"];
+
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut out = Vec::with_capacity(size + 256);
+    let mut row = 0u64;
+    while out.len() < size {
+        let r = next();
+        let ts = 1_700_000_000 + row;
+        let line = match r % 3 {
+            0 => format!("{} [{}] 10.0.{}.{} GET {} status={} took={}ms\n",
+                ts, LEVELS[(r >> 8) as usize % 4], (r >> 16) % 256, (r >> 24) % 256,
+                PATHS[(r >> 32) as usize % 5], [200, 404, 500][(r >> 40) as usize % 3], (r >> 44) % 1000),
+            1 => format!("{} [{}] worker-{} processed job id={:x} in {}us\n",
+                ts, LEVELS[(r >> 8) as usize % 4], (r >> 16) % 16, r >> 20, (r >> 50) % 10000),
+            _ => format!("{},user{},{}.{:02},\"{}\"\n",
+                ts, (r >> 8) % 100_000, (r >> 24) % 1000, (r >> 34) % 100, PATHS[(r >> 40) as usize % 5]),
+        };
+        out.extend_from_slice(line.as_bytes());
+        row += 1;
+    }
+    out
 }
\ No newline at end of file
@@ -0,0 +1,536 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use crc32fast::Hasher;
+
+use crate::archive::{ArchiveHeader, ChecksumKind, CHUNK_HEADER_SIZE};
+use crate::cast::{is_passthrough, ID_FLAG_DUPLICATE};
+use crate::error::CastError;
+use crate::index::{walk_chunk_headers, IndexEntry};
+
+// ============================================================================
+//  COMPRESSION MANIFEST (--manifest, -v --manifest-check)
+// ============================================================================
+//
+//  JSON document written next to an archive for pipelines that want to assert properties of
+//  it without parsing the CLI report:
+//
+//  {
+//    "schema": "cast-manifest", "schema_version": 1, "tool": "cast 1.0.0",
+//    "archive":  { "size", "format_version", "header_len", "header_crc32", "original_size",
+//                  "checksum", "labels": { key: value } },
+//    "settings": { "input", "backend", "dict_size", "chunk_size" (null: solid), "multithread",
+//                  "column_codecs", "max_var_len", "seven_zip_args" },
+//    "totals":   { "chunks", "input_bytes", "output_bytes", "rows", "columnar_chunks",
+//                  "passthrough_chunks", "fallback_chunks" },
+//    "timing":   { "total", "read", "encode", "write_wait" }  (seconds)
+//    "chunks":   [ { "offset", "compressed_len", "data_offset", "data_len", "first_row", "rows",
+//                    "crc32", "id_flag", "kind", "backend", "body_crc32" } ]
+//  }
+//
+//  Chunk entries are those of the sidecar index (see index.rs), plus the backend that encoded
+//  the chunk and the CRC32 of its compressed body. CRC32 values are 8 hex digits. A check
+//  compares the archive's size, header and every chunk header and body with the manifest, so
+//  it notices an archive rewritten after the manifest was made (e.g. recompressed with other
+//  settings) even though that archive's own checksums are all valid. CRC32 catches accidents
+//  and stale files, not deliberate forgeries.
+//
+//  Readers must reject a schema_version they do not know; fields may be added within a version.
+
+pub const MANIFEST_SCHEMA: &str = "cast-manifest";
+pub const MANIFEST_VERSION: u64 = 1;
+
+// Mismatches listed by a check before the rest are only counted
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    /// Name and version of the program that wrote the archive
+    pub tool: String,
+    pub archive: ArchiveRecord,
+    pub settings: ManifestSettings,
+    pub totals: ManifestTotals,
+    pub timing: ManifestTiming,
+    pub chunks: Vec<ManifestChunk>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveRecord {
+    pub size: u64,
+    pub format_version: u8,
+    pub header_len: u64,
+    /// CRC32 of the archive header bytes (labels included)
+    pub header_crc: u32,
+    pub original_size: Option<u64>,
+    pub checksum: ChecksumKind,
+    pub labels: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestSettings {
+    pub input: String,
+    /// "native" or "7zip" (chunks that fell back to the native backend say so themselves)
+    pub backend: String,
+    pub dict_size: u64,
+    /// None in solid mode
+    pub chunk_size: Option<u64>,
+    pub multithread: bool,
+    pub column_codecs: bool,
+    pub max_var_len: u64,
+    pub seven_zip_args: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ManifestTotals {
+    pub chunks: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub rows: u64,
+    pub columnar_chunks: u64,
+    pub passthrough_chunks: u64,
+    pub fallback_chunks: u64,
+}
+
+/// Where the wall time of the compression went, in seconds. `write_wait` is the time spent
+/// waiting for the writer thread; the rest of `total` is setup, index and file table.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ManifestTiming {
+    pub total: f64,
+    pub read: f64,
+    pub encode: f64,
+    pub write_wait: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestChunk {
+    pub entry: IndexEntry,
+    /// "native", "7zip" or "native-fast" (encoded with xz preset 1 to meet --max-time)
+    pub backend: String,
+    /// CRC32 of the compressed body (reg, ids and vars as stored)
+    pub body_crc: u32,
+}
+
+impl ManifestChunk {
+    pub fn kind(&self) -> &'static str {
+        if self.entry.id_flag == ID_FLAG_DUPLICATE { "duplicate" }
+        else if is_passthrough(self.entry.id_flag) { "passthrough" }
+        else { "columnar" }
+    }
+}
+
+/// CRC32 of the segments following a chunk header, as written (see `EncodedChunk::parts`).
+pub fn body_crc(segments: &[Vec<u8>]) -> u32 {
+    let mut h = Hasher::new();
+    for segment in segments { h.update(segment); }
+    h.finalize()
+}
+
+impl ArchiveRecord {
+    /// Reads the header of the archive in `r` and its length.
+    pub fn read<R: Read + Seek>(r: &mut R) -> Result<Self, CastError> {
+        let size = r.seek(SeekFrom::End(0))?;
+        r.seek(SeekFrom::Start(0))?;
+        let header = ArchiveHeader::read_from(&mut BufReader::new(&mut *r))?;
+        let mut bytes = vec![0u8; header.encoded_len()];
+        r.seek(SeekFrom::Start(0))?;
+        r.read_exact(&mut bytes)?;
+        let mut h = Hasher::new();
+        h.update(&bytes);
+        Ok(Self {
+            size,
+            format_version: header.version,
+            header_len: bytes.len() as u64,
+            header_crc: h.finalize(),
+            original_size: header.original_size,
+            checksum: header.checksum(),
+            labels: header.labels,
+        })
+    }
+}
+
+impl Manifest {
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+
+    /// Reads and parses a manifest file. Errors are printable.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read manifest '{}': {}", path, e))?;
+        Self::parse(&text).map_err(|e| format!("Manifest '{}': {}", path, e))
+    }
+
+    pub fn to_json(&self) -> String {
+        let a = &self.archive;
+        let s = &self.settings;
+        let t = &self.totals;
+        let mut out = String::from("{\n  \"schema\": ");
+        push_json_string(&mut out, MANIFEST_SCHEMA);
+        out.push_str(&format!(",\n  \"schema_version\": {},\n  \"tool\": ", MANIFEST_VERSION));
+        push_json_string(&mut out, &self.tool);
+
+        out.push_str(&format!(",\n  \"archive\": {{\"size\": {}, \"format_version\": {}, \"header_len\": {}, \"header_crc32\": \"{:08x}\", \"original_size\": {}, \"checksum\": \"{}\", \"labels\": {{",
+            a.size, a.format_version, a.header_len, a.header_crc, json_opt(a.original_size), checksum_name(a.checksum)));
+        for (i, (key, value)) in a.labels.iter().enumerate() {
+            if i > 0 { out.push_str(", "); }
+            push_json_string(&mut out, key);
+            out.push_str(": ");
+            push_json_string(&mut out, value);
+        }
+        out.push_str("}},\n  \"settings\": {\"input\": ");
+        push_json_string(&mut out, &s.input);
+        out.push_str(", \"backend\": ");
+        push_json_string(&mut out, &s.backend);
+        out.push_str(&format!(", \"dict_size\": {}, \"chunk_size\": {}, \"multithread\": {}, \"column_codecs\": {}, \"max_var_len\": {}, \"seven_zip_args\": ",
+            s.dict_size, json_opt(s.chunk_size), s.multithread, s.column_codecs, s.max_var_len));
+        push_json_string(&mut out, &s.seven_zip_args);
+        out.push_str(&format!("}},\n  \"totals\": {{\"chunks\": {}, \"input_bytes\": {}, \"output_bytes\": {}, \"rows\": {}, \"columnar_chunks\": {}, \"passthrough_chunks\": {}, \"fallback_chunks\": {}}},\n",
+            t.chunks, t.input_bytes, t.output_bytes, t.rows, t.columnar_chunks, t.passthrough_chunks, t.fallback_chunks));
+        out.push_str(&format!("  \"timing\": {{\"total\": {:.4}, \"read\": {:.4}, \"encode\": {:.4}, \"write_wait\": {:.4}}},\n  \"chunks\": [\n",
+            self.timing.total, self.timing.read, self.timing.encode, self.timing.write_wait));
+        for (i, c) in self.chunks.iter().enumerate() {
+            let e = &c.entry;
+            out.push_str(&format!("    {{\"offset\": {}, \"compressed_len\": {}, \"data_offset\": {}, \"data_len\": {}, \"first_row\": {}, \"rows\": {}, \"crc32\": \"{:08x}\", \"id_flag\": {}, \"kind\": \"{}\", \"backend\": ",
+                e.offset, e.compressed_len, e.data_offset, e.data_len, e.first_row, e.lines, e.crc, e.id_flag, c.kind()));
+            push_json_string(&mut out, &c.backend);
+            out.push_str(&format!(", \"body_crc32\": \"{:08x}\"}}", c.body_crc));
+            out.push_str(if i + 1 < self.chunks.len() { ",\n" } else { "\n" });
+        }
+        out.push_str("  ]\n}\n");
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let root = Json::parse(text)?;
+        if root.field("schema")?.str()? != MANIFEST_SCHEMA { return Err("not a CAST manifest".to_string()); }
+        let version = root.field("schema_version")?.u64()?;
+        if version != MANIFEST_VERSION { return Err(format!("unsupported schema version {}", version)); }
+
+        let a = root.field("archive")?;
+        let checksum = match a.field("checksum")?.str()? {
+            "crc32" => ChecksumKind::Crc32,
+            "none" => ChecksumKind::None,
+            other => return Err(format!("unknown checksum '{}'", other)),
+        };
+        let labels = match a.field("labels")? {
+            Json::Object(fields) => fields.iter().map(|(k, v)| Ok((k.clone(), v.str()?.to_string()))).collect::<Result<_, String>>()?,
+            _ => return Err("'labels' is not an object".to_string()),
+        };
+        let archive = ArchiveRecord {
+            size: a.field("size")?.u64()?,
+            format_version: u8::try_from(a.field("format_version")?.u64()?).map_err(|_| "format_version out of range".to_string())?,
+            header_len: a.field("header_len")?.u64()?,
+            header_crc: a.field("header_crc32")?.crc()?,
+            original_size: a.field("original_size")?.opt_u64()?,
+            checksum,
+            labels,
+        };
+
+        let s = root.field("settings")?;
+        let settings = ManifestSettings {
+            input: s.field("input")?.str()?.to_string(),
+            backend: s.field("backend")?.str()?.to_string(),
+            dict_size: s.field("dict_size")?.u64()?,
+            chunk_size: s.field("chunk_size")?.opt_u64()?,
+            multithread: s.field("multithread")?.bool()?,
+            column_codecs: s.field("column_codecs")?.bool()?,
+            max_var_len: s.field("max_var_len")?.u64()?,
+            seven_zip_args: s.field("seven_zip_args")?.str()?.to_string(),
+        };
+
+        let t = root.field("totals")?;
+        let totals = ManifestTotals {
+            chunks: t.field("chunks")?.u64()?,
+            input_bytes: t.field("input_bytes")?.u64()?,
+            output_bytes: t.field("output_bytes")?.u64()?,
+            rows: t.field("rows")?.u64()?,
+            columnar_chunks: t.field("columnar_chunks")?.u64()?,
+            passthrough_chunks: t.field("passthrough_chunks")?.u64()?,
+            fallback_chunks: t.field("fallback_chunks")?.u64()?,
+        };
+
+        let tm = root.field("timing")?;
+        let timing = ManifestTiming {
+            total: tm.field("total")?.f64()?,
+            read: tm.field("read")?.f64()?,
+            encode: tm.field("encode")?.f64()?,
+            write_wait: tm.field("write_wait")?.f64()?,
+        };
+
+        let Json::Array(items) = root.field("chunks")? else { return Err("'chunks' is not an array".to_string()) };
+        let chunks = items.iter().map(|c| Ok(ManifestChunk {
+            entry: IndexEntry {
+                offset: c.field("offset")?.u64()?,
+                compressed_len: c.field("compressed_len")?.u64()?,
+                data_offset: c.field("data_offset")?.u64()?,
+                data_len: c.field("data_len")?.u64()?,
+                first_row: c.field("first_row")?.u64()?,
+                lines: c.field("rows")?.u64()?,
+                crc: c.field("crc32")?.crc()?,
+                id_flag: u8::try_from(c.field("id_flag")?.u64()?).map_err(|_| "id_flag out of range".to_string())?,
+                // Not recorded by manifests: the chunk headers and bodies are checked as bytes
+                parser: None,
+            },
+            backend: c.field("backend")?.str()?.to_string(),
+            body_crc: c.field("body_crc32")?.crc()?,
+        })).collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self { tool: root.field("tool")?.str()?.to_string(), archive, settings, totals, timing, chunks })
+    }
+
+    /// Compares the archive in `r` with this manifest: size, header and every chunk header
+    /// and compressed body. Returns the differences found (empty: the archive matches), at
+    /// most MAX_REPORTED_MISMATCHES of them followed by a count of the rest.
+    pub fn check<R: Read + Seek>(&self, r: &mut R) -> Result<Vec<String>, CastError> {
+        let mut found = Vec::new();
+        let actual = ArchiveRecord::read(r)?;
+        let expected = &self.archive;
+        if actual.size != expected.size { found.push(format!("archive size: {} bytes, manifest says {}", actual.size, expected.size)); }
+        if actual.header_crc != expected.header_crc || actual.header_len != expected.header_len {
+            found.push(format!("archive header: crc32 {:08x} ({} bytes), manifest says {:08x} ({} bytes)", actual.header_crc, actual.header_len, expected.header_crc, expected.header_len));
+        }
+
+        let headers = walk_chunk_headers(r, actual.header_len)?;
+        if headers.len() != self.chunks.len() {
+            found.push(format!("chunk count: {}, manifest says {}", headers.len(), self.chunks.len()));
+        }
+        let mut body = Vec::new();
+        for (i, ((offset, header), chunk)) in headers.iter().zip(&self.chunks).enumerate() {
+            let n = i + 1;
+            let e = &chunk.entry;
+            if *offset != e.offset {
+                found.push(format!("chunk {}: at offset {}, manifest says {}", n, offset, e.offset));
+                continue;
+            }
+            if !e.matches(header) {
+                found.push(format!("chunk {}: header differs (crc32 {:08x}, id flag {}, {} bytes; manifest: {:08x}, {}, {})",
+                    n, header.crc, header.id_flag, CHUNK_HEADER_SIZE + header.body_len(), e.crc, e.id_flag, e.compressed_len));
+                continue;
+            }
+            body.resize(header.body_len(), 0);
+            r.seek(SeekFrom::Start(offset + CHUNK_HEADER_SIZE as u64))?;
+            r.read_exact(&mut body)?;
+            let crc = body_crc(std::slice::from_ref(&body));
+            if crc != chunk.body_crc {
+                found.push(format!("chunk {}: compressed body crc32 {:08x}, manifest says {:08x}", n, crc, chunk.body_crc));
+            }
+        }
+
+        if found.len() > MAX_REPORTED_MISMATCHES {
+            let more = found.len() - MAX_REPORTED_MISMATCHES;
+            found.truncate(MAX_REPORTED_MISMATCHES);
+            found.push(format!("... and {} more", more));
+        }
+        Ok(found)
+    }
+
+    /// `check` on the archive at `path`.
+    pub fn check_file(&self, path: &str) -> Result<Vec<String>, CastError> {
+        self.check(&mut File::open(path)?)
+    }
+}
+
+fn checksum_name(kind: ChecksumKind) -> &'static str {
+    match kind {
+        ChecksumKind::Crc32 => "crc32",
+        ChecksumKind::None => "none",
+    }
+}
+
+fn json_opt(v: Option<u64>) -> String {
+    v.map_or_else(|| "null".to_string(), |n| n.to_string())
+}
+
+/// Appends `s` as a JSON string literal.
+pub fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// ============================================================================
+//  MINIMAL JSON READER
+// ============================================================================
+//
+//  Just enough JSON for manifests. Numbers keep their text so u64 values are not rounded
+//  through f64.
+
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut p = JsonParser { bytes: text.as_bytes(), pos: 0 };
+        let value = p.value()?;
+        p.skip_ws();
+        if p.pos != p.bytes.len() { return Err(format!("unexpected data at byte {}", p.pos)); }
+        Ok(value)
+    }
+
+    fn field(&self, name: &str) -> Result<&Json, String> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == name).map(|(_, v)| v).ok_or_else(|| format!("missing field '{}'", name)),
+            _ => Err(format!("expected an object around '{}'", name)),
+        }
+    }
+
+    fn str(&self) -> Result<&str, String> {
+        match self { Json::String(s) => Ok(s), _ => Err("expected a string".to_string()) }
+    }
+
+    fn bool(&self) -> Result<bool, String> {
+        match self { Json::Bool(b) => Ok(*b), _ => Err("expected true or false".to_string()) }
+    }
+
+    fn u64(&self) -> Result<u64, String> {
+        match self { Json::Number(n) => n.parse().map_err(|_| format!("expected an unsigned integer, found {}", n)), _ => Err("expected a number".to_string()) }
+    }
+
+    fn opt_u64(&self) -> Result<Option<u64>, String> {
+        match self { Json::Null => Ok(None), v => v.u64().map(Some) }
+    }
+
+    fn f64(&self) -> Result<f64, String> {
+        match self { Json::Number(n) => n.parse().map_err(|_| format!("invalid number {}", n)), _ => Err("expected a number".to_string()) }
+    }
+
+    fn crc(&self) -> Result<u32, String> {
+        let s = self.str()?;
+        u32::from_str_radix(s, 16).map_err(|_| format!("invalid crc32 '{}'", s))
+    }
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn skip_ws(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) { self.pos += 1; }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), String> {
+        self.skip_ws();
+        if self.bytes.get(self.pos) != Some(&b) { return Err(format!("expected '{}' at byte {}", b as char, self.pos)); }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.bytes[self.pos..].starts_with(word.as_bytes()) { return Err(format!("invalid value at byte {}", self.pos)); }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_ws();
+        match self.bytes.get(self.pos) {
+            None => Err("unexpected end of document".to_string()),
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_ws();
+                if self.bytes.get(self.pos) == Some(&b'}') { self.pos += 1; return Ok(Json::Object(fields)); }
+                loop {
+                    self.skip_ws();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value()?));
+                    self.skip_ws();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => { self.pos += 1; return Ok(Json::Object(fields)); },
+                        _ => return Err(format!("expected ',' or '}}' at byte {}", self.pos)),
+                    }
+                }
+            },
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_ws();
+                if self.bytes.get(self.pos) == Some(&b']') { self.pos += 1; return Ok(Json::Array(items)); }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_ws();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => { self.pos += 1; return Ok(Json::Array(items)); },
+                        _ => return Err(format!("expected ',' or ']' at byte {}", self.pos)),
+                    }
+                }
+            },
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(_) => {
+                let start = self.pos;
+                while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_digit() || b"+-.eE".contains(b)) { self.pos += 1; }
+                if start == self.pos { return Err(format!("invalid value at byte {}", start)); }
+                Ok(Json::Number(String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned()))
+            },
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.bytes.get(self.pos) != Some(&b'"') { return Err(format!("expected a string at byte {}", self.pos)); }
+        self.pos += 1;
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let Some(&b) = self.bytes.get(self.pos) else { return Err("unterminated string".to_string()) };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&esc) = self.bytes.get(self.pos) else { return Err("unterminated string".to_string()) };
+                    self.pos += 1;
+                    match esc {
+                        b'"' | b'\\' | b'/' => out.push(esc),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'u' => {
+                            let c = self.hex4()?;
+                            // Surrogate pairs encode characters outside the BMP
+                            let c = if (0xD800..0xDC00).contains(&c) && self.bytes[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                0x10000 + ((c - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+                            } else { c };
+                            let c = char::from_u32(c).ok_or_else(|| format!("invalid \\u escape before byte {}", self.pos))?;
+                            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        },
+                        _ => return Err(format!("invalid escape at byte {}", self.pos - 1)),
+                    }
+                },
+                _ => out.push(b),
+            }
+        }
+        String::from_utf8(out).map_err(|_| "string is not valid UTF-8".to_string())
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.bytes.get(self.pos..self.pos + 4).ok_or("truncated \\u escape")?;
+        let value = std::str::from_utf8(digits).ok().and_then(|d| u32::from_str_radix(d, 16).ok()).ok_or_else(|| format!("invalid \\u escape at byte {}", self.pos))?;
+        self.pos += 4;
+        Ok(value)
+    }
+}
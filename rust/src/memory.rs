@@ -0,0 +1,122 @@
+// ============================================================================
+//  MEMORY BUDGET
+// ============================================================================
+
+/// Assumed available memory when it cannot be detected
+pub const FALLBACK_AVAILABLE_MEMORY: u64 = 4 * 1024 * 1024 * 1024;
+
+// LZMA2 at preset 9e needs roughly this many times the dictionary to encode
+const ENCODER_DICT_FACTOR: u64 = 11;
+// Each extra multithreaded encoder also buffers an input and an output block (3x dict each)
+const ENCODER_BLOCK_FACTOR: u64 = 6;
+// A chunk lives next to its registry/ids/vars columns and its compressed output
+const CHUNK_WORKING_FACTOR: u64 = 4;
+// Decoding a chunk keeps its decompressed columns, the restored text and the read buffer
+const DECODE_WORKING_FACTOR: u64 = 3;
+
+const MIN_CHUNK_SIZE: u64 = 1024 * 1024;
+const MAX_CHUNK_SIZE: u64 = 1024 * 1024 * 1024;
+const MIN_DICT_SIZE: u64 = 1024 * 1024;
+const MAX_DICT_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Available memory in bytes (MemAvailable from /proc/meminfo), if detectable.
+pub fn available_memory() -> Option<u64> {
+    let info = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = info.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// One memory ceiling shared by every stage, split as 40% chunk buffers, 30% LZMA dictionary,
+/// 20% chunks in flight (extra encoder threads, parallel decoders) and 10% slack. Each stage
+/// asks for its allotment; explicit settings (chunk size, dictionary size) take precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    total: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(total: u64) -> Self {
+        Self { total }
+    }
+
+    /// Budget covering the currently available memory (`FALLBACK_AVAILABLE_MEMORY` if unknown).
+    pub fn detect() -> Self {
+        Self::new(available_memory().unwrap_or(FALLBACK_AVAILABLE_MEMORY))
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn chunk_buffers(&self) -> u64 {
+        self.share(40)
+    }
+
+    pub fn dictionary(&self) -> u64 {
+        self.share(30)
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.share(20)
+    }
+
+    fn share(&self, percent: u64) -> u64 {
+        self.total / 100 * percent
+    }
+
+    /// Largest power-of-two chunk whose working set fits the chunk buffers (1MB..1GB).
+    pub fn chunk_size(&self) -> usize {
+        floor_pow2((self.chunk_buffers() / CHUNK_WORKING_FACTOR).clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)) as usize
+    }
+
+    /// Largest power-of-two dictionary whose encoder fits the dictionary share (1MB..1GB).
+    /// Never larger than `chunk_size`, since the window cannot reach past one chunk.
+    pub fn dict_size(&self, chunk_size: usize) -> u32 {
+        let fit = (self.dictionary() / ENCODER_DICT_FACTOR).min(chunk_size as u64);
+        floor_pow2(fit.clamp(MIN_DICT_SIZE, MAX_DICT_SIZE)) as u32
+    }
+
+    /// Encoder threads for multithreaded LZMA, at most `max`: the first encoder is paid by the
+    /// dictionary share, each extra one by the in-flight share.
+    pub fn encoder_threads(&self, dict_size: u32, max: u32) -> u32 {
+        let per_thread = dict_size as u64 * (ENCODER_DICT_FACTOR + ENCODER_BLOCK_FACTOR);
+        let extra = self.in_flight() / per_thread.max(1);
+        (1 + extra).min(max as u64).max(1) as u32
+    }
+
+    /// Compressions that may run side by side (`--jobs`), at most `max`: the share of each
+    /// (see `per_job`) must still hold the smallest chunk and the encoder of the smallest
+    /// dictionary. At least one.
+    pub fn compress_jobs(&self, max: usize) -> usize {
+        let per_job = (MIN_CHUNK_SIZE * CHUNK_WORKING_FACTOR * 100 / 40).max(MIN_DICT_SIZE * ENCODER_DICT_FACTOR * 100 / 30);
+        ((self.total / per_job) as usize).clamp(1, max.max(1))
+    }
+
+    /// The budget of one of `jobs` compressions running side by side.
+    pub fn per_job(&self, jobs: usize) -> Self {
+        Self::new(self.total / jobs.max(1) as u64)
+    }
+
+    /// Chunks of up to `chunk_len` decoded bytes that may be restored at the same time, at
+    /// most `max` (one chunk is always allowed).
+    pub fn decode_workers(&self, chunk_len: u64, max: usize) -> usize {
+        let per_worker = chunk_len.saturating_mul(DECODE_WORKING_FACTOR).max(1);
+        let fit = (self.chunk_buffers() + self.in_flight()) / per_worker;
+        (fit as usize).clamp(1, max.max(1))
+    }
+
+    /// Memory the LZMA decoder may use. Streams needing more (larger dictionary) are refused.
+    pub fn decoder_limit(&self) -> u64 {
+        self.dictionary().max(MIN_DICT_SIZE * 2)
+    }
+
+    /// Upper bound for the up-front allocation of a decoded block.
+    pub fn decode_reserve(&self) -> usize {
+        (self.chunk_buffers() / DECODE_WORKING_FACTOR) as usize
+    }
+}
+
+fn floor_pow2(v: u64) -> u64 {
+    1u64 << (63 - v.max(1).leading_zeros())
+}
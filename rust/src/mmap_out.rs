@@ -0,0 +1,64 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use memmap2::MmapMut;
+
+// ============================================================================
+//  MEMORY-MAPPED OUTPUT (feature "mmap")
+// ============================================================================
+//
+//  Reconstructed bytes are copied straight into a shared mapping of the output
+//  file. The file is grown geometrically while writing and truncated to the
+//  bytes actually written by `finish`.
+
+const MIN_MAP_SIZE: u64 = 1024 * 1024;
+
+pub struct MmapWriter {
+    file: File,
+    map: Option<MmapMut>,
+    capacity: u64,
+    len: usize,
+}
+
+impl MmapWriter {
+    /// Creates (or truncates) `path` and maps `size_hint` bytes of it up front.
+    pub fn create(path: &str, size_hint: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let mut w = Self { file, map: None, capacity: 0, len: 0 };
+        w.remap(size_hint.max(MIN_MAP_SIZE))?;
+        Ok(w)
+    }
+
+    fn remap(&mut self, capacity: u64) -> io::Result<()> {
+        // The mapping must be released before resizing (required on Windows)
+        if let Some(map) = self.map.take() { map.flush_async()?; }
+        self.file.set_len(capacity)?;
+        // SAFETY: the file was created by us and is only accessed through this mapping
+        self.map = Some(unsafe { MmapMut::map_mut(&self.file)? });
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Flushes the mapping and trims the file to the written length.
+    pub fn finish(mut self) -> io::Result<u64> {
+        if let Some(map) = self.map.take() { map.flush()?; }
+        self.file.set_len(self.len as u64)?;
+        Ok(self.len as u64)
+    }
+}
+
+impl Write for MmapWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.len + buf.len();
+        if end as u64 > self.capacity {
+            let mut capacity = self.capacity;
+            while capacity < end as u64 { capacity *= 2; }
+            self.remap(capacity)?;
+        }
+        let map = self.map.as_mut().expect("mapping released");
+        map[self.len..end].copy_from_slice(buf);
+        self.len = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
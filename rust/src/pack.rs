@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// ============================================================================
+//  PACKED INPUTS (-c --pack, archive.cast.files)
+// ============================================================================
+//
+//  Many small files are compressed as one stream, back to back with nothing in between: the
+//  archive restores to their concatenation, and templates learned on one file serve every
+//  later file of the same chunk. Where each file lies in the restored data is kept in a text
+//  file table next to the archive:
+//      # CAST files v1 <tab> total_len
+//      offset <tab> len <tab> path        (one line per file, in archive order)
+//  total_len is the original size of the archive: a table that does not add up to it belongs
+//  to another archive. The archive itself is a regular one and never depends on the table.
+
+pub const FILE_TABLE_MAGIC: &str = "# CAST files v1";
+
+pub fn file_table_path(archive_path: &str) -> String {
+    format!("{}.files", archive_path)
+}
+
+/// One packed file: `len` bytes from `offset` of the restored data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackedFile {
+    pub path: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Reads a list of files one after the other, recording where each one starts and ends.
+/// Files are opened only when the previous one is exhausted.
+pub struct PackReader {
+    paths: Vec<String>,
+    current: Option<File>,
+    files: Vec<PackedFile>,
+    offset: u64,
+}
+
+impl PackReader {
+    /// Fails on a path the file table cannot hold (tab or newline).
+    pub fn new(paths: Vec<String>) -> io::Result<Self> {
+        if let Some(bad) = paths.iter().find(|p| p.contains(['\t', '\n', '\r'])) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Cannot pack '{}': tabs and newlines are not allowed in packed paths", bad.escape_debug())));
+        }
+        Ok(Self { paths, current: None, files: Vec::new(), offset: 0 })
+    }
+
+    /// Current size of all the files (what the stream will hold unless they change meanwhile).
+    pub fn total_len(&self) -> u64 {
+        self.paths.iter().filter_map(|p| std::fs::metadata(p).ok()).map(|m| m.len()).sum()
+    }
+
+    /// The files with their actual sizes, once the stream is read to the end. Solid runs stop
+    /// reading at `total_len`, so the files listed after that point (empty or missing ones) are
+    /// opened here; data past that point means the files grew while they were packed.
+    pub fn into_files(mut self) -> io::Result<Vec<PackedFile>> {
+        let mut extra = 0;
+        let mut buf = [0u8; 8192];
+        loop {
+            match self.read(&mut buf)? {
+                0 => break,
+                n => extra += n as u64,
+            }
+        }
+        if extra > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("The packed files grew by {} bytes while they were read", extra)));
+        }
+        Ok(self.files)
+    }
+}
+
+impl Read for PackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.current.is_none() {
+                let Some(path) = self.paths.get(self.files.len()) else { return Ok(0) };
+                let f = File::open(path).map_err(|e| io::Error::new(e.kind(), format!("Cannot open '{}': {}", path, e)))?;
+                self.files.push(PackedFile { path: path.clone(), offset: self.offset, len: 0 });
+                self.current = Some(f);
+            }
+            let n = self.current.as_mut().unwrap().read(buf)?;
+            if n == 0 && !buf.is_empty() {
+                self.current = None;
+                continue;
+            }
+            if let Some(file) = self.files.last_mut() { file.len += n as u64; }
+            self.offset += n as u64;
+            return Ok(n);
+        }
+    }
+}
+
+/// Writes the file table of `archive_path` (through a temporary file, like the sidecar index).
+pub fn write_file_table(archive_path: &str, files: &[PackedFile]) -> io::Result<()> {
+    let total: u64 = files.iter().map(|f| f.len).sum();
+    let mut text = format!("{}\t{}\n", FILE_TABLE_MAGIC, total);
+    for f in files { text.push_str(&format!("{}\t{}\t{}\n", f.offset, f.len, f.path)); }
+    let path = file_table_path(archive_path);
+    let tmp = format!("{}.tmp", path);
+    let mut out = File::create(&tmp)?;
+    out.write_all(text.as_bytes())?;
+    out.sync_all()?;
+    std::fs::rename(&tmp, &path)
+}
+
+/// Reads the file table of `archive_path` and checks it against the archive's original size.
+/// Errors are printable.
+pub fn read_file_table(archive_path: &str, original_size: Option<u64>) -> Result<Vec<PackedFile>, String> {
+    let path = file_table_path(archive_path);
+    let text = std::fs::read_to_string(&path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => format!("No file table ({}): the archive was not made with --pack", path),
+        _ => format!("Cannot read file table '{}': {}", path, e),
+    })?;
+    let damaged = |line: usize| format!("File table '{}' is damaged (line {})", path, line);
+
+    let mut lines = text.lines();
+    let total = lines.next()
+        .and_then(|l| l.strip_prefix(FILE_TABLE_MAGIC))
+        .and_then(|l| l.strip_prefix('\t'))
+        .and_then(|n| n.parse::<u64>().ok())
+        .ok_or_else(|| damaged(1))?;
+    let mut files = Vec::new();
+    let mut expected_offset = 0u64;
+    for (i, line) in lines.enumerate() {
+        let mut fields = line.splitn(3, '\t');
+        let (Some(offset), Some(len), Some(file)) = (fields.next(), fields.next(), fields.next()) else { return Err(damaged(i + 2)) };
+        let (Ok(offset), Ok(len)) = (offset.parse::<u64>(), len.parse::<u64>()) else { return Err(damaged(i + 2)) };
+        if offset != expected_offset { return Err(damaged(i + 2)); }
+        expected_offset = offset.checked_add(len).ok_or_else(|| damaged(i + 2))?;
+        files.push(PackedFile { path: file.to_string(), offset, len });
+    }
+    if expected_offset != total || original_size.is_some_and(|size| size != total) {
+        return Err(format!("File table '{}' does not match the archive (it describes {} bytes)", path, total));
+    }
+    Ok(files)
+}
+
+/// The packed file called `name`: its exact path, or else the only file with that file name.
+pub fn find_packed_file<'a>(files: &'a [PackedFile], name: &str) -> Result<&'a PackedFile, String> {
+    if let Some(f) = files.iter().find(|f| f.path == name) { return Ok(f); }
+    let file_name = |p: &str| Path::new(p).file_name().map(|n| n.to_string_lossy().into_owned());
+    let matches: Vec<&PackedFile> = files.iter().filter(|f| file_name(&f.path).as_deref() == Some(name)).collect();
+    match matches.as_slice() {
+        [one] => Ok(one),
+        [] => Err(format!("No packed file '{}'", name)),
+        many => Err(format!("'{}' matches {} packed files: use the full path ({}, ...)", name, many.len(), many[0].path)),
+    }
+}
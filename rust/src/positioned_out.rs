@@ -0,0 +1,181 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// ============================================================================
+//  POSITIONED OUTPUT
+// ============================================================================
+//
+//  A parallel restore writes every group of chunks straight into its place in one output file.
+//  The file is sized once up front and each worker writes at the offset of its group (pwrite on
+//  Unix, WriteFile with an offset on Windows), so there is no shared cursor and no worker waits
+//  for another. Platforms without positioned writes get one temp file per region next to the
+//  output instead, copied to their offsets by `finish`.
+
+/// A destination written at explicit offsets, usable from several threads at once.
+pub trait WriteAt {
+    /// Writes all of `buf` at `offset`.
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+}
+
+#[cfg(unix)]
+impl WriteAt for File {
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::write_all_at(self, buf, offset)
+    }
+}
+
+#[cfg(windows)]
+impl WriteAt for File {
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+        // seek_write also moves the cursor, which no caller of WriteAt relies on
+        while !buf.is_empty() {
+            match self.seek_write(buf, offset) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+                Ok(n) => {
+                    buf = &buf[n..];
+                    offset += n as u64;
+                },
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+enum SinkTarget {
+    File(File),
+    // (offset, temp file) of every region handed out
+    Parts(Mutex<Vec<(u64, PathBuf)>>),
+}
+
+/// One output file of known length, written in regions by several threads at once.
+pub struct PositionedSink {
+    path: PathBuf,
+    len: u64,
+    target: SinkTarget,
+}
+
+impl PositionedSink {
+    /// Creates (or truncates) `path` with room for `len` bytes: written in place where the
+    /// platform has positioned writes, through temp parts otherwise.
+    #[cfg(any(unix, windows))]
+    pub fn create(path: &Path, len: u64) -> io::Result<Self> {
+        Self::in_place(path, len)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn create(path: &Path, len: u64) -> io::Result<Self> {
+        Self::with_parts(path, len)
+    }
+
+    /// Sizes `path` to `len` bytes once; regions are written into it directly.
+    #[cfg(any(unix, windows))]
+    pub fn in_place(path: &Path, len: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+        file.set_len(len)?;
+        Ok(Self { path: path.to_path_buf(), len, target: SinkTarget::File(file) })
+    }
+
+    /// Writes every region to a temp file next to `path` (`<path>.part-<offset>`), copied into
+    /// place by `finish`. `path` is created right away, so an unwritable output fails here.
+    pub fn with_parts(path: &Path, len: u64) -> io::Result<Self> {
+        File::create(path)?;
+        Ok(Self { path: path.to_path_buf(), len, target: SinkTarget::Parts(Mutex::new(Vec::new())) })
+    }
+
+    /// Length the output was created with.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Writer for the region that starts at `offset`, filled in order from there. Regions of
+    /// different threads must not overlap.
+    pub fn region(&self, offset: u64) -> io::Result<RegionWriter<'_>> {
+        let target = match &self.target {
+            SinkTarget::File(file) => RegionTarget::File(file),
+            SinkTarget::Parts(parts) => {
+                let mut name = self.path.clone().into_os_string();
+                name.push(format!(".part-{}", offset));
+                let part_path = PathBuf::from(name);
+                let part = File::create(&part_path)?;
+                parts.lock().map_err(|_| io::Error::other("part list lock poisoned"))?.push((offset, part_path));
+                RegionTarget::Part(part)
+            },
+        };
+        Ok(RegionWriter { target, offset })
+    }
+
+    /// Completes the output and returns its length. Temp parts are copied to their offsets
+    /// and removed; bytes no region wrote read as zeros.
+    pub fn finish(self) -> io::Result<u64> {
+        match self.target {
+            SinkTarget::File(file) => file.sync_data()?,
+            SinkTarget::Parts(parts) => {
+                let mut parts = parts.into_inner().map_err(|_| io::Error::other("part list lock poisoned"))?;
+                parts.sort();
+                let mut out = OpenOptions::new().write(true).open(&self.path)?;
+                out.set_len(self.len)?;
+                for (offset, part_path) in &parts {
+                    out.seek(SeekFrom::Start(*offset))?;
+                    io::copy(&mut File::open(part_path)?, &mut out)?;
+                    std::fs::remove_file(part_path)?;
+                }
+                out.sync_data()?;
+            },
+        }
+        Ok(self.len)
+    }
+
+    /// Removes the temp parts without copying them (a cancelled or failed restore). The
+    /// output itself is left to the caller.
+    pub fn discard(self) {
+        if let SinkTarget::Parts(parts) = self.target {
+            for (_, part_path) in parts.into_inner().unwrap_or_default() { let _ = std::fs::remove_file(part_path); }
+        }
+    }
+}
+
+enum RegionTarget<'a> {
+    File(&'a File),
+    Part(File),
+}
+
+/// Sequential writer for one region of a `PositionedSink`.
+pub struct RegionWriter<'a> {
+    target: RegionTarget<'a>,
+    /// Output offset of the next byte
+    offset: u64,
+}
+
+impl RegionWriter<'_> {
+    /// Output offset of the next byte written.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl Write for RegionWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.target {
+            RegionTarget::File(file) => file.write_all_at(buf, self.offset)?,
+            RegionTarget::Part(part) => part.write_all(buf)?,
+        }
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.target {
+            RegionTarget::File(_) => Ok(()),
+            RegionTarget::Part(part) => part.flush(),
+        }
+    }
+}
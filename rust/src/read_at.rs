@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+
+// ============================================================================
+//  POSITIONED READS
+// ============================================================================
+//
+//  Parallel workers read their chunks from one shared archive handle. Every read names its own
+//  offset (pread on Unix, ReadFile with an offset on Windows), so there is no shared cursor to
+//  move and nothing to lock between workers. Readers without positioned reads go through
+//  `SeekLock`, which serializes seek + read on a single handle.
+
+/// A source read at explicit offsets, usable from several threads at once.
+pub trait ReadAt {
+    /// Fills `buf` with the bytes at `offset` (`UnexpectedEof` if the source ends first).
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+}
+
+#[cfg(unix)]
+impl ReadAt for File {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+    }
+}
+
+#[cfg(windows)]
+impl ReadAt for File {
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+        // seek_read also moves the cursor, which no caller of ReadAt relies on
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                },
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        (**self).read_exact_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Box<T> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        (**self).read_exact_at(buf, offset)
+    }
+}
+
+/// Fallback for sources without positioned reads: one reader behind a mutex, seeked before
+/// every read. Concurrent readers wait for each other.
+pub struct SeekLock<R>(Mutex<R>);
+
+impl<R: Read + Seek> SeekLock<R> {
+    pub fn new(inner: R) -> Self {
+        Self(Mutex::new(inner))
+    }
+}
+
+impl<R: Read + Seek> ReadAt for SeekLock<R> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let mut inner = self.0.lock().map_err(|_| io::Error::other("reader lock poisoned"))?;
+        inner.seek(SeekFrom::Start(offset))?;
+        inner.read_exact(buf)
+    }
+}
+
+/// Opens `path` for shared positioned reads: the file itself where the platform has them,
+/// otherwise a `SeekLock` around it.
+#[cfg(any(unix, windows))]
+pub fn open_shared(path: &str) -> io::Result<Box<dyn ReadAt + Send + Sync>> {
+    Ok(Box::new(File::open(path)?))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn open_shared(path: &str) -> io::Result<Box<dyn ReadAt + Send + Sync>> {
+    Ok(Box::new(SeekLock::new(File::open(path)?)))
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+// ============================================================================
+//  SPARSE OUTPUT (--sparse)
+// ============================================================================
+//
+//  Whole blocks of zeros (aligned on the output offset) are skipped with a seek
+//  instead of being written, so filesystems that support it leave holes. A block
+//  reaching the writer in several writes is held until it is complete, so holes
+//  do not depend on where the writes end. The file is extended to its full
+//  length by `finish`. On filesystems without sparse files the skipped regions
+//  simply read back as zeros.
+
+const HOLE_BLOCK: u64 = 4096;
+
+pub struct SparseWriter {
+    out: BufWriter<File>,
+    // Logical length written so far (holes included)
+    len: u64,
+    // Zero bytes skipped since the last real write
+    pending_hole: u64,
+    // Start of the current block, until the block is complete (ends at `len`)
+    block: Vec<u8>,
+}
+
+impl SparseWriter {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Self { out: BufWriter::with_capacity(1024 * 1024, File::create(path)?), len: 0, pending_hole: 0, block: Vec::new() })
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.pending_hole > 0 {
+            self.out.seek(SeekFrom::Current(self.pending_hole as i64))?;
+            self.pending_hole = 0;
+        }
+        self.out.write_all(data)
+    }
+
+    /// Writes the held part of the current block, unless it is all zeros and its block is
+    /// complete or the last one (a hole).
+    fn end_block(&mut self, complete: bool) -> io::Result<()> {
+        let block = std::mem::take(&mut self.block);
+        if complete && block.iter().all(|&b| b == 0) {
+            self.pending_hole += block.len() as u64;
+            Ok(())
+        } else {
+            let result = self.write_data(&block);
+            self.block = block;
+            self.block.clear();
+            result
+        }
+    }
+
+    /// Flushes and extends the file over a trailing hole. Returns the logical length.
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.end_block(true)?;
+        self.out.flush()?;
+        let file = self.out.get_ref();
+        if self.pending_hole > 0 { file.set_len(self.len)?; }
+        Ok(self.len)
+    }
+}
+
+impl Write for SparseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            // Split on block boundaries of the output offset
+            let in_block = (HOLE_BLOCK - self.len % HOLE_BLOCK) as usize;
+            let n = in_block.min(rest.len());
+            let (piece, tail) = rest.split_at(n);
+            self.len += n as u64;
+            if !self.block.is_empty() || (n as u64) < HOLE_BLOCK {
+                self.block.extend_from_slice(piece);
+                if n == in_block { self.end_block(self.block.len() as u64 == HOLE_BLOCK)?; }
+            } else if piece.iter().all(|&b| b == 0) {
+                self.pending_hole += n as u64;
+            } else {
+                self.write_data(piece)?;
+            }
+            rest = tail;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // A held block of zeros may still become a hole
+        if self.block.iter().any(|&b| b != 0) { self.end_block(false)?; }
+        self.out.flush()
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::cast::{ParserConfig, TemplateColumns, VAR_PLACEHOLDER};
+
+// ============================================================================
+//  TEMPLATE FILES (cast templates, --audit --allowed-templates)
+// ============================================================================
+//
+//  A template file lists line templates as text, one per line:
+//      user <*> logged in from <*>
+//  `<*>` stands for one variable (`"<*>"` for a quoted one), everything else is literal. The
+//  line ending is not part of a template. Backslash escapes keep any template on one line:
+//  `\\`, `\t`, `\r`, `\n`, `\<` (a literal `<`, so `\<*>` is the text "<*>") and `\#` (a
+//  template that starts with '#'). Blank lines and lines starting with '#' are ignored.
+//  `cast templates <archive>` writes this format, so a whitelist can start from a known-good
+//  archive.
+//
+//  Templates depend on how lines were tokenized, so `cast templates` also writes one comment
+//      # parser: tokenizer=1 mode=strict quote=" timestamp-split=yes max-var-len=1048576
+//  per parser configuration of the archive (see "PARSER CONFIGURATION" in cast.rs). An audit
+//  reports chunks parsed with a configuration the file does not list as not comparable.
+//  Files without such lines (hand-written ones) are compared whatever the configuration.
+
+pub const TEMPLATE_VARIABLE: &str = "<*>";
+pub const PARSER_COMMENT: &str = "# parser: ";
+
+/// A skeleton without its line ending (the "\n"; a "\r" before it stays part of the template).
+fn strip_line_ending(skeleton: &str) -> &str {
+    skeleton.strip_suffix('\n').unwrap_or(skeleton)
+}
+
+/// One line of a template file for `skeleton`.
+pub fn render_template(skeleton: &str) -> String {
+    let skeleton = strip_line_ending(skeleton);
+    let mut out = String::with_capacity(skeleton.len() + 8);
+    for (i, c) in skeleton.char_indices() {
+        match c {
+            VAR_PLACEHOLDER => out.push_str(TEMPLATE_VARIABLE),
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            '<' if skeleton[i..].starts_with(TEMPLATE_VARIABLE) => out.push_str("\\<"),
+            '#' if i == 0 => out.push_str("\\#"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// The skeleton a template file line stands for (without line ending), or `None` for a line
+/// with an unknown escape.
+pub fn parse_template(line: &str) -> Option<String> {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with(TEMPLATE_VARIABLE) {
+            out.push(VAR_PLACEHOLDER);
+            rest = &rest[TEMPLATE_VARIABLE.len()..];
+            continue;
+        }
+        rest = &rest[c.len_utf8()..];
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let escaped = rest.chars().next()?;
+        rest = &rest[escaped.len_utf8()..];
+        out.push(match escaped {
+            '\\' => '\\',
+            't' => '\t',
+            'r' => '\r',
+            'n' => '\n',
+            '<' => '<',
+            '#' => '#',
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+/// `skeleton` with every run of whitespace made one space and none at either end.
+pub fn normalize_whitespace(skeleton: &str) -> String {
+    skeleton.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// How a skeleton matched an allowed template.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TemplateMatch {
+    Exact,
+    /// Only after `normalize_whitespace` on both sides
+    Whitespace,
+}
+
+/// The templates of a template file, ready for lookups.
+pub struct TemplateSet {
+    exact: HashSet<String>,
+    normalized: HashSet<String>,
+    parsers: Vec<ParserConfig>,
+}
+
+impl TemplateSet {
+    /// Parses the text of a template file. Errors name the offending line.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut exact = HashSet::new();
+        let mut parsers = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if let Some(config) = line.strip_prefix(PARSER_COMMENT) {
+                parsers.push(ParserConfig::parse(config).ok_or_else(|| format!("line {}: unknown parser configuration {:?}", i + 1, config))?);
+                continue;
+            }
+            if line.trim().is_empty() || line.starts_with('#') { continue; }
+            let template = parse_template(line).ok_or_else(|| format!("line {}: unknown escape in {:?}", i + 1, line))?;
+            exact.insert(template);
+        }
+        let normalized = exact.iter().map(|t| normalize_whitespace(t)).collect();
+        Ok(Self { exact, normalized, parsers })
+    }
+
+    /// Reads and parses a template file. Errors are printable.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read template file '{}': {}", path, e))?;
+        Self::parse(&text).map_err(|e| format!("Template file '{}', {}", path, e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.exact.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty()
+    }
+
+    /// Parser configurations the templates were listed with (empty when the file names none).
+    pub fn parsers(&self) -> &[ParserConfig] {
+        &self.parsers
+    }
+
+    /// Whether templates of a chunk tokenized with `parser` compare with these: always when the
+    /// file names no configuration. Otherwise `Err` holds the differences from the closest
+    /// configuration it names.
+    pub fn comparable(&self, parser: &ParserConfig) -> Result<(), Vec<String>> {
+        if self.parsers.is_empty() || self.parsers.contains(parser) { return Ok(()); }
+        Err(self.parsers.iter().map(|p| parser.differences(p)).min_by_key(|d| d.len()).unwrap_or_default())
+    }
+
+    /// Whether the chunk skeleton `skeleton` (line ending included) is allowed, and how.
+    pub fn check(&self, skeleton: &str) -> Option<TemplateMatch> {
+        let skeleton = strip_line_ending(skeleton);
+        if self.exact.contains(skeleton) { return Some(TemplateMatch::Exact); }
+        if self.normalized.contains(&normalize_whitespace(skeleton)) { return Some(TemplateMatch::Whitespace); }
+        None
+    }
+}
+
+// ============================================================================
+//  TEMPLATE USAGE (cast --list-templates)
+// ============================================================================
+//
+//  Template ids are local to a chunk, so the listing merges templates over chunks by skeleton
+//  text and numbers them in order of first appearance in the archive.
+
+/// Placeholder shown for a variable in a listing (template files use `<*>`)
+pub const LISTING_VARIABLE: &str = "⟨*⟩";
+
+/// How much of an archive one template produced.
+#[derive(Clone, Debug)]
+pub struct TemplateUsage {
+    /// Order of first appearance in the archive (1-based)
+    pub id: usize,
+    /// The skeleton (see `ChunkTemplates::skeletons`)
+    pub skeleton: String,
+    pub rows: u64,
+    /// Restored bytes: the literal text once per row plus the variable values (Latin-1 chunks
+    /// are counted in UTF-8, so this is an estimate)
+    pub bytes: u64,
+    /// Chunks where at least one row uses the template
+    pub chunks: u64,
+}
+
+/// Merges the templates of the chunks of an archive by skeleton text.
+#[derive(Default)]
+pub struct TemplateTally {
+    usage: Vec<TemplateUsage>,
+    by_skeleton: HashMap<String, usize>,
+}
+
+impl TemplateTally {
+    /// Adds the templates of one chunk (from `CASTDecompressor::columns`). Registry entries
+    /// that no row uses are left out.
+    pub fn add_chunk(&mut self, templates: &[TemplateColumns]) {
+        for template in templates.iter().filter(|t| !t.rows.is_empty()) {
+            let rows = template.rows.len() as u64;
+            let literal = template.skeleton.len() - template.columns.len() * VAR_PLACEHOLDER.len_utf8();
+            let values: usize = template.columns.iter().map(|c| c.values.len()).sum();
+            let next_id = self.usage.len();
+            let idx = *self.by_skeleton.entry(template.skeleton.clone()).or_insert(next_id);
+            if idx == next_id {
+                self.usage.push(TemplateUsage { id: next_id + 1, skeleton: template.skeleton.clone(), rows: 0, bytes: 0, chunks: 0 });
+            }
+            let usage = &mut self.usage[idx];
+            usage.rows += rows;
+            usage.bytes += literal as u64 * rows + values as u64;
+            usage.chunks += 1;
+        }
+    }
+
+    /// The merged templates, in order of first appearance.
+    pub fn into_usage(self) -> Vec<TemplateUsage> {
+        self.usage
+    }
+}
+
+/// Order of a template listing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TemplateSort {
+    /// Most rows first
+    Rows,
+    /// Most restored bytes first
+    Size,
+    /// Order of first appearance
+    Id,
+}
+
+impl TemplateSort {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "rows" => Some(TemplateSort::Rows),
+            "size" | "bytes" => Some(TemplateSort::Size),
+            "id" => Some(TemplateSort::Id),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TemplateSort::Rows => "rows",
+            TemplateSort::Size => "size",
+            TemplateSort::Id => "id",
+        }
+    }
+
+    /// Sorts `usage`; ties keep the order of first appearance.
+    pub fn sort(&self, usage: &mut [TemplateUsage]) {
+        match self {
+            TemplateSort::Rows => usage.sort_by(|a, b| b.rows.cmp(&a.rows).then(a.id.cmp(&b.id))),
+            TemplateSort::Size => usage.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.id.cmp(&b.id))),
+            TemplateSort::Id => usage.sort_by_key(|u| u.id),
+        }
+    }
+}
+
+/// `skeleton` for display: `LISTING_VARIABLE` for each variable, control characters escaped,
+/// without the line ending.
+pub fn render_listing(skeleton: &str) -> String {
+    let mut out = String::with_capacity(skeleton.len() + 8);
+    for c in strip_line_ending(skeleton).chars() {
+        match c {
+            VAR_PLACEHOLDER => out.push_str(LISTING_VARIABLE),
+            c if c.is_control() => out.extend(c.escape_default()),
+            _ => out.push(c),
+        }
+    }
+    out
+}
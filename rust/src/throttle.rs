@@ -0,0 +1,251 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::read_at::ReadAt;
+
+// ============================================================================
+//  I/O RATE LIMIT (--io-limit)
+// ============================================================================
+//
+//  One token bucket for the whole run: every throttled reader and writer draws from the same
+//  budget, so reads and writes together stay under the limit. Bytes are charged after they
+//  went through, and a caller that overdraws the bucket sleeps until the debt is paid back.
+//  Single calls are cut to a tenth of a second's worth of bytes, so the disk sees a steady
+//  trickle instead of large bursts followed by long pauses.
+
+// Bucket capacity (and largest single read or write), in seconds of budget
+const BURST_SECONDS: f64 = 0.1;
+// Smallest piece a call is cut to, so very low limits do not degrade into tiny syscalls
+const MIN_BURST: usize = 4096;
+
+/// Time source of an `IoLimit`: the bucket refills by `now` and pays its debt with `sleep`.
+/// Tests inject a clock that advances only when slept on.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since an arbitrary fixed start.
+    fn now(&self) -> Duration;
+    fn sleep(&self, pause: Duration);
+}
+
+/// Wall clock: `Instant` and `std::thread::sleep`.
+pub struct SystemClock(Instant);
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self(Instant::now())
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration { self.0.elapsed() }
+    fn sleep(&self, pause: Duration) { std::thread::sleep(pause); }
+}
+
+struct Bucket {
+    bytes_per_sec: f64,
+    /// Bytes that may pass right now (negative: debt still to be slept off)
+    tokens: f64,
+    last_refill: Duration,
+    /// Total time callers spent sleeping for the limit
+    waited: Duration,
+}
+
+/// Shared I/O budget of a run. Clones draw from the same bucket.
+#[derive(Clone)]
+pub struct IoLimit {
+    bucket: Arc<Mutex<Bucket>>,
+    clock: Arc<dyn Clock>,
+    burst: usize,
+}
+
+impl IoLimit {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self::with_clock(bytes_per_sec, Arc::new(SystemClock::new()))
+    }
+
+    /// Same as `new`, timed by `clock` instead of the wall clock.
+    pub fn with_clock(bytes_per_sec: u64, clock: Arc<dyn Clock>) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1) as f64;
+        let burst = ((bytes_per_sec * BURST_SECONDS) as usize).max(MIN_BURST);
+        let bucket = Bucket { bytes_per_sec, tokens: burst as f64, last_refill: clock.now(), waited: Duration::ZERO };
+        Self { bucket: Arc::new(Mutex::new(bucket)), clock, burst }
+    }
+
+    /// Largest single read or write the limit lets through (the bucket capacity).
+    pub fn burst(&self) -> usize {
+        self.burst
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bucket.lock().map(|b| b.bytes_per_sec as u64).unwrap_or(0)
+    }
+
+    /// Time spent sleeping for the limit so far, over all readers and writers.
+    pub fn waited(&self) -> Duration {
+        self.bucket.lock().map(|b| b.waited).unwrap_or_default()
+    }
+
+    /// Charges `n` bytes that just went through, sleeping if the budget is overdrawn.
+    pub fn charge(&self, n: usize) {
+        if n == 0 { return; }
+        let pause = {
+            let Ok(mut b) = self.bucket.lock() else { return };
+            let now = self.clock.now();
+            let capacity = self.burst as f64;
+            let refill = now.saturating_sub(b.last_refill).as_secs_f64() * b.bytes_per_sec;
+            b.tokens = (b.tokens + refill).min(capacity) - n as f64;
+            b.last_refill = now;
+            if b.tokens >= 0.0 { return; }
+            let pause = Duration::from_secs_f64(-b.tokens / b.bytes_per_sec);
+            b.waited += pause;
+            pause
+        };
+        self.clock.sleep(pause);
+    }
+
+    fn cap(&self, len: usize) -> usize {
+        len.min(self.burst)
+    }
+}
+
+/// Reader that charges every byte it returns to an `IoLimit` (passes through without one).
+pub struct ThrottledReader<R> {
+    inner: R,
+    limit: Option<IoLimit>,
+}
+
+impl<R> ThrottledReader<R> {
+    pub fn new(inner: R, limit: Option<IoLimit>) -> Self {
+        Self { inner, limit }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(limit) = &self.limit else { return self.inner.read(buf) };
+        let len = limit.cap(buf.len());
+        let n = self.inner.read(&mut buf[..len])?;
+        limit.charge(n);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for ThrottledReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<R: ReadAt> ReadAt for ThrottledReader<R> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let Some(limit) = &self.limit else { return self.inner.read_exact_at(buf, offset) };
+        let mut done = 0;
+        while done < buf.len() {
+            let len = limit.cap(buf.len() - done);
+            self.inner.read_exact_at(&mut buf[done..done + len], offset + done as u64)?;
+            limit.charge(len);
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+/// Writer that charges every byte it accepts to an `IoLimit` (passes through without one).
+pub struct ThrottledWriter<W> {
+    inner: W,
+    limit: Option<IoLimit>,
+}
+
+impl<W> ThrottledWriter<W> {
+    pub fn new(inner: W, limit: Option<IoLimit>) -> Self {
+        Self { inner, limit }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(limit) = &self.limit else { return self.inner.write(buf) };
+        let len = limit.cap(buf.len());
+        let n = self.inner.write(&buf[..len])?;
+        limit.charge(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (reads, writes, rate) in bytes pushed through one IoLimit on a mock clock, which must take
+    // (reads + writes - burst) / rate of mock time to within TOLERANCE; the last case is under
+    // the smallest burst
+    const CASES: [(usize, usize, u64); 3] = [
+        (0, 10 * 1024 * 1024, 1024 * 1024),
+        (5 * 1024 * 1024, 5 * 1024 * 1024, 1024 * 1024),
+        (256 * 1024, 256 * 1024, 16 * 1024),
+    ];
+    const TOLERANCE: f64 = 0.01;
+
+    /// Clock that only moves when slept on, so a throttled run takes no wall time and its timing
+    /// is exact.
+    struct MockClock(Mutex<Duration>);
+
+    impl Clock for MockClock {
+        fn now(&self) -> Duration { *self.0.lock().unwrap() }
+        fn sleep(&self, pause: Duration) { *self.0.lock().unwrap() += pause; }
+    }
+
+    /// Alternates reads and writes through a ThrottledReader and a ThrottledWriter sharing one
+    /// IoLimit. No call may pass more than the burst, the data must go through unchanged, the
+    /// mock time must match the rate and the reported wait must equal it.
+    #[test]
+    fn shared_budget_on_a_mock_clock() {
+        for (reads, writes, rate) in CASES {
+            let case = format!("{} read and {} written at {}/s", reads, writes, rate);
+            let clock = Arc::new(MockClock(Mutex::new(Duration::ZERO)));
+            let limit = IoLimit::with_clock(rate, clock.clone());
+            let source: Vec<u8> = (0..reads).map(|i| (i * 31 % 251) as u8).collect();
+            let mut reader = ThrottledReader::new(&source[..], Some(limit.clone()));
+            let mut writer = ThrottledWriter::new(Vec::new(), Some(limit.clone()));
+            let (mut read, mut buf) = (Vec::new(), vec![0u8; 1024 * 1024]);
+            while read.len() < reads || writer.get_ref().len() < writes {
+                let n = reader.read(&mut buf).unwrap();
+                read.extend_from_slice(&buf[..n]);
+                let left = writes - writer.get_ref().len();
+                let m = writer.write(&buf[..left.min(buf.len())]).unwrap();
+                assert!(n.max(m) <= limit.burst(), "{}: a call passed {} bytes, over the {} byte burst", case, n.max(m), limit.burst());
+            }
+            assert!(read == source, "{}: read different data", case);
+            let expected = (reads + writes - limit.burst()) as f64 / rate as f64;
+            let elapsed = clock.now().as_secs_f64();
+            assert!((elapsed - expected).abs() <= expected * TOLERANCE, "{}: took {:.3}s, expected {:.3}s", case, elapsed, expected);
+            assert_eq!(limit.waited(), clock.now(), "{}", case);
+        }
+    }
+}
@@ -0,0 +1,969 @@
+// Ties the option table (src/cli.rs) to the parsers: every option a binary's source spells out
+// must be declared for it, every option declared for it must be spelled out in its source, and
+// its --help must keep the help text in one column.
+//
+// Then runs the cast binary (which cargo builds for integration tests) through the CLI modes
+// the round-trip check does not cover: conversion, batches, --skip-existing, permissions,
+// interrupts, sampled and strict verification, --pack, output encodings, progress events,
+// --io-limit, sparse restores, the golden archives and a panicking backend. The runs take one
+// lock, as several of them are timed.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use cast::archive::{digest_hex, ArchiveHeader, ChunkReader, CHUNK_HEADER_SIZE, CONTENT_DIGEST_LABEL, UTF8_BOM};
+use cast::cast::{CASTDecompressor, NativeCompressor};
+use cast::cast_lzma::{LzmaBackend, LzmaDecompressorBackend};
+use cast::cli::{self, Binary};
+use cast::convert::{collect_convert_inputs, decoded_len_hint, decoder};
+use cast::datagen::{generate_vec, Profile, DEFAULT_SEED};
+use cast::encoded_out::OutputEncoding;
+use cast::index::{archive_fingerprint, sample_chunks, walk_chunk_headers};
+use cast::pack::read_file_table;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::Regex;
+
+// Each binary, its source, and the option-like literals of the source that are not options:
+// the modes of cast (listed under "Modes" in its help)
+const SOURCES: [(Binary, &str, &[&str]); 3] = [
+    (Binary::Cast, include_str!("../src/main.rs"), &["-c", "-d"]),
+    (Binary::RunBenchmarks, include_str!("../src/bin/run_benchmarks.rs"), &[]),
+    (Binary::BenchRegression, include_str!("../src/bin/bench_regression.rs"), &[]),
+];
+// roundtrip_check only parses --help (and rejects undeclared options with cli::unknown_option):
+// the other options in its source are those of the 7-Zip it stands in for
+const ROUNDTRIP_CHECK_OPTIONS: [&str; 2] = ["-h", "--help"];
+const BINARIES: [Binary; 4] = [Binary::Cast, Binary::RunBenchmarks, Binary::BenchRegression, Binary::RoundtripCheck];
+
+const CAST: &str = env!("CARGO_BIN_EXE_cast");
+// Stands in for 7-Zip in the strict verification test (see `fake_seven_zip` there)
+const ROUNDTRIP_CHECK: &str = env!("CARGO_BIN_EXE_roundtrip_check");
+const DICT_SIZE: u32 = 1024 * 1024;
+
+// Size of each generated fixture of the conversion, batch, --skip-existing and permissions
+// tests, and the chunk size they compress with
+const CONVERT_FIXTURE_SIZE: usize = 64 * 1024;
+const CONVERT_CHUNK_SIZE: &str = "16KB";
+
+// Files compressed at the same time by the batch test, and the threads the cast process may
+// run on top of them: the main thread and the Ctrl+C handler, then per job the one running it
+// and the chunk writer
+const BATCH_JOBS: usize = 3;
+const BATCH_SHARED_THREADS: usize = 2;
+const BATCH_JOB_THREADS: usize = 2;
+
+// Sparse test (Unix): size of the sparse source, offsets of its data regions (the last one
+// ends the file), their length, the chunk size it is compressed with, and the blocks (of 512
+// bytes) a sparse restore may allocate beyond those of the source
+#[cfg(unix)]
+const SPARSE_FIXTURE_SIZE: u64 = 8 * 1024 * 1024;
+#[cfg(unix)]
+const SPARSE_DATA_OFFSETS: [u64; 3] = [0, 3 * 1024 * 1024, SPARSE_FIXTURE_SIZE - SPARSE_DATA_LEN as u64];
+#[cfg(unix)]
+const SPARSE_DATA_LEN: usize = 64 * 1024;
+#[cfg(unix)]
+const SPARSE_CHUNK_SIZE: usize = 1024 * 1024;
+#[cfg(unix)]
+const SPARSE_BLOCK_SLACK: u64 = 16;
+
+// Panic test: its input, the chunk (1-based) a debug cast binary panics on, and how long the
+// failed run may take at most
+const PANIC_FIXTURE_SIZE: usize = 512 * 1024;
+const PANIC_CHUNK: usize = 3;
+const PANIC_TIME_BOUND: Duration = Duration::from_secs(10);
+
+// I/O limit test: input compressed by `cast -c --io-limit` and its rate, and how far the wait
+// and the wall time may fall from (input + archive) / rate
+const IO_LIMIT_WALL_INPUT: usize = 10 * 1024 * 1024;
+const IO_LIMIT_WALL_RATE: usize = 1024 * 1024;
+const IO_LIMIT_WALL_TOLERANCE: f64 = 0.15;
+
+// Sampled verification test: a fixture in chunks of this size, and the chunks -v --verify-sample
+// checks (the first, the last and a seeded draw of the others)
+const VERIFY_SAMPLE_FIXTURE_SIZE: usize = 640 * 1024;
+const VERIFY_SAMPLE_CHUNK_SIZE: usize = 16 * 1024;
+const VERIFY_SAMPLE_CHUNKS: usize = 6;
+
+// Pack test: `cast generate ndjson --files` fixture (files, total size) packed with --pack and
+// compared with `cast -c` of the files concatenated, both in chunks of PACK_CHUNK_SIZE. The
+// archives must be within PACK_SIZE_TOLERANCE of each other and --pack may take at most
+// PACK_TIME_FACTOR times as long (plus PACK_TIME_SLACK of process start and file opening). Files
+// restored one by one with -d --file: the first, the last and every PACK_EXTRACT_STEP-th, plus
+// the file holding each chunk boundary
+const PACK_FILES: usize = 10_000;
+const PACK_FIXTURE_SIZE: &str = "10MB";
+const PACK_CHUNK_SIZE: usize = 1024 * 1024;
+const PACK_SIZE_TOLERANCE: f64 = 0.01;
+const PACK_TIME_FACTOR: f64 = 1.5;
+const PACK_TIME_SLACK: Duration = Duration::from_secs(1);
+const PACK_EXTRACT_STEP: usize = 2_500;
+
+// Progress test: a solid archive of this size restored by `cast -d --json` under this
+// --io-limit, so the restore lasts a few update intervals; it may report at most once per
+// PROGRESS_INTERVAL (see main.rs)
+const PROGRESS_FIXTURE_SIZE: usize = 4 * 1024 * 1024;
+const PROGRESS_IO_LIMIT: usize = 2 * 1024 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// Strict verification test: cast -v --strict-verify runs roundtrip_check as its 7-Zip
+// (SEVEN_ZIP_PATH) with FAKE_7Z_ENV set to one of FAKE_7Z_BEHAVIOURS; "truncate" drops the
+// last byte of every stream it decodes. The archive holds STRICT_VERIFY_CHUNKS passthrough
+// chunks of noise, whose lengths the truncation changes
+const FAKE_7Z_ENV: &str = "CAST_ROUNDTRIP_FAKE_7Z";
+const FAKE_7Z_BEHAVIOURS: [&str; 2] = ["faithful", "truncate"];
+const STRICT_VERIFY_CHUNKS: usize = 2;
+const STRICT_VERIFY_CHUNK_SIZE: usize = 32 * 1024;
+
+// Encoded output test: the fixture, in chunks of this size, restored with each of these
+// --output-encoding values
+const ENCODED_FIXTURE_SIZE: usize = 512 * 1024;
+const ENCODED_CHUNK_SIZE: usize = 64 * 1024;
+const OUTPUT_ENCODINGS: [OutputEncoding; 2] = [OutputEncoding::Gzip, OutputEncoding::Zstd];
+
+// Interrupt test: cast generate of a dataset far larger than it gets to write, as one file and
+// as many small ones, interrupted by SIGINT once INTERRUPT_AFTER_FILES files (or the first
+// buffer of the single file) are on disk; it must stop within INTERRUPT_DEADLINE
+#[cfg(unix)]
+const INTERRUPT_GENERATE_SIZE: &str = "64GB";
+#[cfg(unix)]
+const INTERRUPT_FILES: usize = 1_000_000;
+#[cfg(unix)]
+const INTERRUPT_AFTER_FILES: usize = 100;
+#[cfg(unix)]
+const INTERRUPT_DEADLINE: Duration = Duration::from_secs(10);
+
+// Mode of the compressed file, the cast -d flags tried on its archive with the mode each must
+// leave on the output, and the owner --chown must give it when run as root
+#[cfg(unix)]
+const PERMISSIONS_SOURCE_MODE: u32 = 0o640;
+#[cfg(unix)]
+const PERMISSIONS_CASES: [(&[&str], u32); 4] = [
+    (&["--chmod", "600"], 0o600),
+    (&["--chmod", "0604"], 0o604),
+    (&["--preserve-permissions"], PERMISSIONS_SOURCE_MODE),
+    (&["--preserve-permissions", "--chmod", "400"], 0o400),
+];
+#[cfg(unix)]
+const PERMISSIONS_OWNER: (u32, u32) = (1, 1);
+
+// Headerless version 1 archive written by the first release and the file it holds, and the
+// `cast --dump-format` output expected for it and for the current version archive of the same
+// file (from the "[*]  Format dump" line on, run in tests/golden): a change of the layout or of
+// its annotations must come with new .dump files
+const LEGACY_GOLDEN_ARCHIVE: &[u8] = include_bytes!("golden/legacy-v1.log.cast");
+const LEGACY_GOLDEN_ORIGINAL: &[u8] = include_bytes!("golden/legacy-v1.log");
+const DUMP_GOLDENS: [(&str, &[u8], &str); 2] = [
+    ("legacy-v1.log.cast", LEGACY_GOLDEN_ARCHIVE, include_str!("golden/legacy-v1.log.cast.dump")),
+    ("current-v2.log.cast", include_bytes!("golden/current-v2.log.cast"), include_str!("golden/current-v2.log.cast.dump")),
+];
+
+// Held by every test that runs the cast binary
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// The string literals of `source` that look like an option: `"-x"`, `"--name"`, `"--name=value"`.
+fn option_literals(source: &str) -> BTreeSet<&str> {
+    let literal = Regex::new(r#""(--?[A-Za-z0-9][A-Za-z0-9=-]*)""#).unwrap();
+    literal.captures_iter(source).map(|c| c.get(1).unwrap().as_str()).collect()
+}
+
+fn declared(binary: Binary) -> BTreeSet<&'static str> {
+    cli::options(binary).flat_map(|o| o.names.iter().copied()).collect()
+}
+
+#[test]
+fn parsed_options_are_declared() {
+    for (binary, source, not_options) in SOURCES {
+        let parsed: BTreeSet<&str> = option_literals(source).into_iter().filter(|l| !not_options.contains(l)).collect();
+        let declared = declared(binary);
+        let undeclared: Vec<_> = parsed.difference(&declared).collect();
+        assert!(undeclared.is_empty(), "{:?} parses options missing from cli::OPTIONS: {:?}", binary, undeclared);
+        let unparsed: Vec<_> = declared.difference(&parsed).collect();
+        assert!(unparsed.is_empty(), "cli::OPTIONS declares options {:?} does not parse: {:?}", binary, unparsed);
+    }
+    assert_eq!(declared(Binary::RoundtripCheck), ROUNDTRIP_CHECK_OPTIONS.into_iter().collect(), "options of roundtrip_check");
+}
+
+#[test]
+fn help_column_is_aligned() {
+    for binary in BINARIES {
+        let rendered = cli::render_options(binary);
+        let lines: Vec<&str> = rendered.lines().collect();
+        // Help lines below a name all start at the help column
+        let indents: BTreeSet<usize> = lines.iter().filter(|l| l.starts_with("   ")).map(|l| l.len() - l.trim_start().len()).collect();
+        assert!(indents.len() <= 1, "{:?}: help lines start at columns {:?}", binary, indents);
+        let mut column = indents.first().copied();
+        let mut i = 0;
+        for option in cli::options(binary) {
+            let name = format!("  {}{}", option.names.join(", "), option.value.map_or(String::new(), |v| format!(" {}", v)));
+            let line = lines.get(i).copied().unwrap_or_default();
+            let help = line.strip_prefix(&*name).unwrap_or_else(|| panic!("{:?}: {:?} is not where {:?} should be", binary, line, name));
+            if help.is_empty() {
+                // A name too long for the column, with its help on the next line
+                assert!(lines.get(i + 1).is_some_and(|next| next.starts_with("   ")), "{:?}: {:?} has no help", binary, name);
+            } else {
+                let start = name.len() + help.len() - help.trim_start().len();
+                assert!(start > name.len() && *column.get_or_insert(start) == start, "{:?}: the help of {:?} starts at column {}, not {:?}", binary, name, start, column);
+            }
+            i += 1;
+            while lines.get(i).is_some_and(|next| next.starts_with("   ")) { i += 1; }
+        }
+        assert_eq!(i, lines.len(), "{:?}: lines after the last option", binary);
+    }
+}
+
+/// Runs `check` in a fresh temporary directory named after the test, under `SERIAL`, and
+/// fails the test with the error it returns.
+fn in_temp_dir(name: &str, check: impl FnOnce(&Path) -> Result<(), String>) {
+    let _serial = SERIAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let root = env::temp_dir().join(format!("cast_cli_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap_or_else(|e| panic!("{}: {}", root.display(), e));
+    let result = check(&root);
+    let _ = std::fs::remove_dir_all(&root);
+    if let Err(e) = result { panic!("{}", e); }
+}
+
+/// Runs cast with `args`, its output dropped; fails unless it succeeds.
+fn run_cast(args: &[&str]) -> Result<(), String> {
+    let status = Command::new(CAST).args(args).stdout(Stdio::null()).stderr(Stdio::null()).status()
+        .map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+    if !status.success() { return Err(format!("cast {} failed ({})", args[0], status)); }
+    Ok(())
+}
+
+/// Runs cast with `args` and returns its output (stderr dropped).
+fn cast_output(args: &[&str]) -> Result<std::process::Output, String> {
+    Command::new(CAST).args(args).stderr(Stdio::null()).output().map_err(|e| format!("cannot run '{}': {}", CAST, e))
+}
+
+fn io_err(e: std::io::Error) -> String {
+    e.to_string()
+}
+
+/// Restores the archive file at `path` with the native decoder.
+fn restore_archive(path: &Path) -> Result<Vec<u8>, String> {
+    restore_archive_bytes(&std::fs::read(path).map_err(io_err)?)
+}
+
+/// Restores the chunks of `archive` with the native decoder (a BOM the header records is left
+/// out).
+fn restore_archive_bytes(archive: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = ChunkReader::new(Cursor::new(archive)).map_err(|e| e.to_string())?;
+    let decompressor = CASTDecompressor::new(LzmaDecompressorBackend::new());
+    let mut restored = Vec::new();
+    while let Some(chunk) = reader.next_resolved().map_err(|e| e.to_string())? {
+        decompressor.decompress(chunk.reg(), chunk.ids(), chunk.vars(), Some(chunk.header.crc), chunk.header.id_flag, &mut restored).map_err(|e| e.to_string())?;
+    }
+    Ok(restored)
+}
+
+/// `len` pseudo-random bytes (xorshift from `seed`).
+fn noise(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 32) as u8
+    }).collect()
+}
+
+/// Number of threads of the process whose /proc status file is `status_path` (None off Linux).
+fn thread_count(status_path: &str) -> Option<usize> {
+    std::fs::read_to_string(status_path).ok()
+        .and_then(|s| s.lines().find_map(|l| l.strip_prefix("Threads:").and_then(|n| n.trim().parse::<usize>().ok())))
+}
+
+/// Decodes a .gz or .zst stream.
+fn decode_output(encoding: OutputEncoding, data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::new();
+    match encoding {
+        OutputEncoding::Gzip => { flate2::read::GzDecoder::new(data).read_to_end(&mut decoded).map_err(io_err)?; },
+        OutputEncoding::Zstd => decoded = zstd::decode_all(data).map_err(io_err)?,
+        OutputEncoding::None => decoded.extend_from_slice(data),
+    }
+    Ok(decoded)
+}
+
+// Input of the conversion test: path under the source tree, stored bytes, original, archive
+// path under the output directory (None: the input must fail)
+type ConvertFixture<'a> = (&'static str, Vec<u8>, &'a [u8], Option<&'static str>);
+
+/// Builds a tree of gzip, xz and plain fixtures plus a .gz with a flipped byte, checks what
+/// `collect_convert_inputs` finds in it, then converts it with `cast --convert
+/// --delete-source`: the intact inputs must be listed OK, restore and be deleted, the damaged
+/// one listed FAILED and kept, without an archive.
+#[test]
+fn convert() {
+    in_temp_dir("convert", |root| {
+        let (src, out) = (root.join("src"), root.join("out"));
+        let web = generate_vec(Profile::WebAccess, CONVERT_FIXTURE_SIZE, DEFAULT_SEED);
+        let syslog = generate_vec(Profile::Syslog, CONVERT_FIXTURE_SIZE, DEFAULT_SEED);
+        let csv = generate_vec(Profile::Csv, CONVERT_FIXTURE_SIZE, DEFAULT_SEED);
+        let gzip = |data: &[u8]| -> Result<Vec<u8>, String> {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).map_err(io_err)?;
+            encoder.finish().map_err(io_err)
+        };
+        let xz = LzmaBackend::new(false, DICT_SIZE).compress(&syslog).map_err(|e| format!("xz fixture: {}", e))?;
+        let mut damaged = gzip(&web)?;
+        let middle = damaged.len() / 2;
+        damaged[middle] ^= 0xff;
+
+        let tree: [ConvertFixture; 4] = [
+            ("2024/web.log.gz", gzip(&web)?, &web, Some("2024/web.log.cast")),
+            ("2025/syslog.log.xz", xz, &syslog, Some("2025/syslog.log.cast")),
+            ("data.csv", csv.clone(), &csv, Some("data.csv.cast")),
+            ("damaged.log.gz", damaged, &web, None),
+        ];
+        for (path, stored, _, _) in &tree {
+            let path = src.join(path);
+            std::fs::create_dir_all(path.parent().unwrap_or(&src)).map_err(io_err)?;
+            std::fs::write(&path, stored).map_err(io_err)?;
+        }
+
+        let (inputs, missing) = collect_convert_inputs(&[src.to_string_lossy().into_owned()], &out).map_err(io_err)?;
+        if !missing.is_empty() { return Err(format!("arguments matching nothing: {:?}", missing)); }
+        for (path, _, original, archive) in &tree {
+            let Some(input) = inputs.iter().find(|i| i.path == src.join(path)) else { return Err(format!("'{}' was not collected", path)) };
+            let expected = Path::new(archive.unwrap_or("damaged.log.cast"));
+            if input.output != expected {
+                return Err(format!("'{}' goes to '{}', expected '{}'", path, input.output.display(), expected.display()));
+            }
+            let mut decoded = Vec::new();
+            let read = decoder(std::fs::File::open(&input.path).map_err(io_err)?, input.encoding).read_to_end(&mut decoded);
+            match (archive, read) {
+                (Some(_), Ok(_)) if decoded != *original => return Err(format!("'{}' ({}) decodes to different data", path, input.encoding.label())),
+                (Some(_), Err(e)) => return Err(format!("'{}' ({}) fails to decode: {}", path, input.encoding.label(), e)),
+                (None, Ok(_)) => return Err(format!("'{}' decodes despite the flipped byte", path)),
+                _ => {},
+            }
+            let hint = decoded_len_hint(&input.path, input.encoding).map_err(io_err)?;
+            if archive.is_some() && hint != Some(original.len() as u64) {
+                return Err(format!("'{}' ({}): decoded size hint {:?}, expected {}", path, input.encoding.label(), hint, original.len()));
+            }
+        }
+        if inputs.len() != tree.len() { return Err(format!("collected {} inputs, expected {}", inputs.len(), tree.len())); }
+
+        let run = Command::new(CAST)
+            .args(["--convert", &src.to_string_lossy(), "--into", &out.to_string_lossy()])
+            .args(["--chunk-size", CONVERT_CHUNK_SIZE, "--mode", "native", "--delete-source"])
+            .output().map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+        let stdout = String::from_utf8_lossy(&run.stdout);
+        if run.status.code() != Some(1) {
+            return Err(format!("cast --convert exited with {:?}, expected 1 (one failed input)", run.status.code()));
+        }
+        for (path, _, original, archive) in &tree {
+            // The summary row comes last
+            let source = src.join(path);
+            let listed = stdout.lines().rfind(|l| l.contains(&*source.to_string_lossy()));
+            match archive {
+                Some(archive) => {
+                    if !listed.is_some_and(|l| l.trim_start().starts_with("OK")) { return Err(format!("'{}' is not listed as OK", path)); }
+                    if source.exists() { return Err(format!("'{}' was converted but not deleted", path)); }
+                    let restored = restore_archive(&out.join(archive)).map_err(|e| format!("'{}': {}", archive, e))?;
+                    if restored != *original { return Err(format!("'{}' restores different data", archive)); }
+                },
+                None => {
+                    if !listed.is_some_and(|l| l.trim_start().starts_with("FAILED")) { return Err(format!("'{}' is not listed as FAILED", path)); }
+                    if !source.exists() { return Err(format!("'{}' failed but was deleted", path)); }
+                    if out.join("damaged.log.cast").exists() { return Err(format!("'{}' failed but left its archive", path)); }
+                },
+            }
+        }
+        Ok(())
+    });
+}
+
+/// Writes a sparse file of `SPARSE_FIXTURE_SIZE` bytes holding pseudo-random (binary) data at
+/// `SPARSE_DATA_OFFSETS` and holes elsewhere, compresses it in `SPARSE_CHUNK_SIZE` chunks and
+/// restores it with `cast -d --sparse`: the restore must equal the source byte for byte and
+/// allocate no more than `SPARSE_BLOCK_SLACK` blocks beyond it (st_blocks). On a filesystem
+/// without holes the source is fully allocated, and only the content is compared.
+#[cfg(unix)]
+#[test]
+fn sparse_restore() {
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::fs::MetadataExt;
+
+    in_temp_dir("sparse", |root| {
+        let source = root.join("disk.img");
+        let mut file = std::fs::File::create(&source).map_err(io_err)?;
+        for (i, offset) in SPARSE_DATA_OFFSETS.into_iter().enumerate() {
+            file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+            file.write_all(&noise(DEFAULT_SEED + i as u64, SPARSE_DATA_LEN)).map_err(io_err)?;
+        }
+        file.set_len(SPARSE_FIXTURE_SIZE).map_err(io_err)?;
+        drop(file);
+        let data = std::fs::read(&source).map_err(io_err)?;
+        let source_blocks = std::fs::metadata(&source).map_err(io_err)?.blocks();
+        let holes = source_blocks * 512 < SPARSE_FIXTURE_SIZE;
+
+        let (archive, restored) = (root.join("disk.img.cast"), root.join("restored.img"));
+        run_cast(&["-c", &source.to_string_lossy(), &archive.to_string_lossy(), "--chunk-size", &format!("{}KB", SPARSE_CHUNK_SIZE / 1024), "--mode", "native"])?;
+        run_cast(&["-d", &archive.to_string_lossy(), &restored.to_string_lossy(), "--sparse"])?;
+        if std::fs::read(&restored).map_err(io_err)? != data { return Err("cast -d --sparse: restored data differs from the source".to_string()); }
+        let blocks = std::fs::metadata(&restored).map_err(io_err)?.blocks();
+        if holes && blocks > source_blocks + SPARSE_BLOCK_SLACK {
+            return Err(format!("cast -d --sparse: {} blocks allocated, the source has {}", blocks, source_blocks));
+        }
+        Ok(())
+    });
+}
+
+/// Compresses a list of three generated fixtures and a directory with `--jobs`: the fixtures
+/// must be archived, the directory reported as skipped, and the process must stay within the
+/// threads the jobs share (sampled on Linux while it runs).
+#[test]
+fn batch() {
+    in_temp_dir("batch", |root| {
+        let (src, out) = (root.join("src"), root.join("out"));
+        std::fs::create_dir_all(&src).map_err(io_err)?;
+        let fixtures = [
+            ("web.log", Some(generate_vec(Profile::WebAccess, CONVERT_FIXTURE_SIZE, DEFAULT_SEED))),
+            ("syslog.log", Some(generate_vec(Profile::Syslog, CONVERT_FIXTURE_SIZE, DEFAULT_SEED))),
+            ("unreadable.log", None),
+            ("data.csv", Some(generate_vec(Profile::Csv, CONVERT_FIXTURE_SIZE, DEFAULT_SEED))),
+        ];
+        let mut list = String::new();
+        for (name, data) in &fixtures {
+            let path = src.join(name);
+            match data {
+                Some(data) => std::fs::write(&path, data).map_err(io_err)?,
+                None => std::fs::create_dir_all(&path).map_err(io_err)?,
+            }
+            list.push_str(&format!("{}\n", path.display()));
+        }
+        let list_path = root.join("list.txt");
+        std::fs::write(&list_path, list).map_err(io_err)?;
+
+        let mut child = Command::new(CAST)
+            .args(["-c", "--batch", &list_path.to_string_lossy(), &out.to_string_lossy()])
+            .args(["--jobs", &BATCH_JOBS.to_string(), "--chunk-size", CONVERT_CHUNK_SIZE, "--mode", "native", "--multithread", "--json"])
+            .stdout(Stdio::piped()).stderr(Stdio::null())
+            .spawn().map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+        let mut stdout = child.stdout.take().ok_or("no stdout")?;
+        let reader = std::thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stdout.read_to_string(&mut text);
+            text
+        });
+        let status_path = format!("/proc/{}/status", child.id());
+        let mut peak_threads = 0;
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(io_err)? { break status; }
+            peak_threads = peak_threads.max(thread_count(&status_path).unwrap_or(0));
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        let stdout = reader.join().map_err(|_| "stdout reader panicked")?;
+        if status.code() != Some(1) {
+            return Err(format!("cast -c --batch exited with {:?}, expected 1 (one skipped input)", status.code()));
+        }
+
+        // Every event of a job carries its file
+        let events_of = |event: &str, path: &Path| stdout.lines()
+            .filter(|l| l.contains(&format!("\"event\":\"{}\"", event)) && l.contains(&format!("\"file\":\"{}\"", path.display())))
+            .count();
+        for (name, data) in &fixtures {
+            let path = src.join(name);
+            let (done, errors) = (events_of("done", &path), events_of("error", &path));
+            match data {
+                Some(data) => {
+                    if (done, errors) != (1, 0) { return Err(format!("'{}': {} done and {} error event(s), expected 1 and 0", name, done, errors)); }
+                    let archive = out.join(format!("{}.cast", name));
+                    let restored = restore_archive(&archive).map_err(|e| format!("'{}': {}", archive.display(), e))?;
+                    if restored != *data { return Err(format!("'{}' restores different data", archive.display())); }
+                },
+                None => {
+                    if (done, errors) != (0, 1) { return Err(format!("'{}': {} done and {} error event(s), expected 0 and 1", name, done, errors)); }
+                    if out.join(format!("{}.cast", name)).exists() { return Err(format!("'{}' was skipped but left an archive", name)); }
+                },
+            }
+        }
+        let batch = stdout.lines().find(|l| l.contains("\"event\":\"batch\"")).ok_or("no batch event")?;
+        let field = |key: &str| batch.split(&format!("\"{}\":", key)).nth(1)
+            .and_then(|rest| rest.split([',', '}']).next()).and_then(|n| n.parse::<usize>().ok());
+        let expected = [("files", fixtures.len()), ("archived", fixtures.len() - 1), ("skipped", 1), ("jobs", BATCH_JOBS)];
+        for (key, value) in expected {
+            if field(key) != Some(value) { return Err(format!("batch event reports {} {:?}, expected {}", key, field(key), value)); }
+        }
+        // The CPUs are split among the jobs rather than handed to each of them
+        let encoder_threads = field("encoder_threads").ok_or("batch event without encoder_threads")?;
+        if encoder_threads != (num_cpus::get() / BATCH_JOBS).max(1) {
+            return Err(format!("{} encoder thread(s) per job with {} CPU(s) and {} jobs", encoder_threads, num_cpus::get(), BATCH_JOBS));
+        }
+        let bound = BATCH_SHARED_THREADS + BATCH_JOBS * (BATCH_JOB_THREADS + encoder_threads);
+        if peak_threads > bound { return Err(format!("{} threads at peak, bound {}", peak_threads, bound)); }
+        let manifest = std::fs::read_to_string(out.join("manifest.tsv")).map_err(io_err)?;
+        let ok = manifest.lines().filter(|l| l.starts_with("OK\t")).count();
+        let skipped = manifest.lines().filter(|l| l.starts_with("SKIPPED\t")).count();
+        if (ok, skipped) != (fixtures.len() - 1, 1) { return Err(format!("manifest lists {} OK and {} SKIPPED", ok, skipped)); }
+        Ok(())
+    });
+}
+
+/// Runs `cast -d --skip-existing` (plain or `=strict`) against an existing output: an up-to-date
+/// output must be kept with an `up_to_date` event, an output with the size of the original but
+/// other bytes must be restored again (an error with `=strict`, which keeps it), and so must a
+/// matching output when the archive records no SHA-256.
+#[test]
+fn skip_existing() {
+    in_temp_dir("skip_existing", |root| {
+        let data = generate_vec(Profile::WebAccess, CONVERT_FIXTURE_SIZE, DEFAULT_SEED);
+        let (source, archive, output) = (root.join("web.log"), root.join("web.log.cast"), root.join("restored.log"));
+        std::fs::write(&source, &data).map_err(io_err)?;
+        run_cast(&["-c", &source.to_string_lossy(), &archive.to_string_lossy(), "--chunk-size", CONVERT_CHUNK_SIZE, "--mode", "native"])?;
+        let mut flipped = data.clone();
+        flipped[data.len() / 2] ^= 1;
+
+        // (archive, flag, existing output, exit status, up to date); a failed run keeps the output
+        let no_digest = root.join("no-digest.cast");
+        let cases: [(&Path, &str, &[u8], i32, bool); 6] = [
+            (&archive, "--skip-existing", &data, 0, true),
+            (&archive, "--skip-existing=strict", &data, 0, true),
+            (&archive, "--skip-existing", &flipped, 0, false),
+            (&archive, "--skip-existing=strict", &flipped, 1, false),
+            (&no_digest, "--skip-existing", &data, 0, false),
+            (&no_digest, "--skip-existing=strict", &data, 1, false),
+        ];
+        // The same archive with the placeholder of an unfinished digest, which reads as none
+        std::fs::copy(&archive, &no_digest).map_err(io_err)?;
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&no_digest).map_err(io_err)?;
+        let header = ArchiveHeader::read_from(&mut std::io::BufReader::new(&file)).map_err(|e| e.to_string())?;
+        if header.content_digest().is_none() { return Err("the archive records no SHA-256".to_string()); }
+        header.patch_label(&mut file, CONTENT_DIGEST_LABEL, &digest_hex(&[0; 32])).map_err(io_err)?;
+        drop(file);
+
+        for (archive, flag, existing, expected_status, up_to_date) in cases {
+            let what = format!("{} on {} output ({})", flag, if existing == data { "a matching" } else { "a modified" }, archive.file_name().unwrap_or_default().to_string_lossy());
+            std::fs::write(&output, existing).map_err(io_err)?;
+            let run = cast_output(&["-d", &archive.to_string_lossy(), &output.to_string_lossy(), flag, "--json"])?;
+            if run.status.code() != Some(expected_status) { return Err(format!("{}: exited with {:?}, expected {}", what, run.status.code(), expected_status)); }
+            if String::from_utf8_lossy(&run.stdout).contains("\"result\":\"up_to_date\"") != up_to_date {
+                return Err(format!("{}: {} as up to date", what, if up_to_date { "not reported" } else { "reported" }));
+            }
+            let after = if expected_status == 0 { &data[..] } else { existing };
+            if std::fs::read(&output).map_err(io_err)? != after {
+                return Err(format!("{}: the output {}", what, if expected_status == 0 { "does not hold the original" } else { "was replaced" }));
+            }
+        }
+        Ok(())
+    });
+}
+
+/// Restores an archive of a file with mode `PERMISSIONS_SOURCE_MODE` through `cast -d` with
+/// each of `PERMISSIONS_CASES` and checks the mode of the output, then with `--chown` to
+/// `PERMISSIONS_OWNER` when running as root (changing the owner needs privileges).
+#[cfg(unix)]
+#[test]
+fn permissions() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    in_temp_dir("permissions", |root| {
+        let data = generate_vec(Profile::Syslog, CONVERT_FIXTURE_SIZE, DEFAULT_SEED);
+        let (source, archive, output) = (root.join("syslog.log"), root.join("syslog.log.cast"), root.join("restored.log"));
+        std::fs::write(&source, &data).map_err(io_err)?;
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(PERMISSIONS_SOURCE_MODE)).map_err(io_err)?;
+        run_cast(&["-c", &source.to_string_lossy(), &archive.to_string_lossy(), "--mode", "native"])?;
+
+        let restore = |flags: &[&str]| -> Result<std::fs::Metadata, String> {
+            let _ = std::fs::remove_file(&output);
+            let (archive, output) = (archive.to_string_lossy(), output.to_string_lossy());
+            let mut args = vec!["-d", &*archive, &*output];
+            args.extend_from_slice(flags);
+            run_cast(&args).map_err(|e| format!("{} with {}", e, flags.join(" ")))?;
+            if std::fs::read(&*output).map_err(io_err)? != data { return Err(format!("cast -d {}: restored data differs", flags.join(" "))); }
+            std::fs::metadata(&*output).map_err(io_err)
+        };
+        for (flags, expected) in PERMISSIONS_CASES {
+            let mode = restore(flags)?.permissions().mode() & 0o7777;
+            if mode != expected { return Err(format!("cast -d {}: mode {:04o}, expected {:04o}", flags.join(" "), mode, expected)); }
+        }
+        if unsafe { libc::geteuid() } != 0 { return Ok(()); }
+        let (uid, gid) = PERMISSIONS_OWNER;
+        let metadata = restore(&["--chown", &format!("{}:{}", uid, gid), "--preserve-permissions"])?;
+        if (metadata.uid(), metadata.gid()) != PERMISSIONS_OWNER {
+            return Err(format!("cast -d --chown {}:{}: owned by {}:{}", uid, gid, metadata.uid(), metadata.gid()));
+        }
+        // The mode is set after the owner, so chown cannot clear it
+        if metadata.permissions().mode() & 0o7777 != PERMISSIONS_SOURCE_MODE {
+            return Err(format!("cast -d --chown {}:{} --preserve-permissions: mode {:04o}", uid, gid, metadata.permissions().mode() & 0o7777));
+        }
+        Ok(())
+    });
+}
+
+/// Interrupts `cast generate` with SIGINT while it writes one file, then many small ones (with
+/// --files): it must exit with status 130 within INTERRUPT_DEADLINE, remove the file it was
+/// writing and, with --files, keep exactly the files it reports complete.
+#[cfg(unix)]
+#[test]
+fn interrupt() {
+    // Runs `cast generate` with `extra`, sends SIGINT once `started` holds and returns its stderr
+    let interrupted = |out: &Path, extra: &[&str], started: &dyn Fn() -> bool| -> Result<String, String> {
+        let mut child = Command::new(CAST).args(["generate", Profile::ALL[0].name(), INTERRUPT_GENERATE_SIZE, &*out.to_string_lossy()]).args(extra)
+            .stdout(Stdio::null()).stderr(Stdio::piped())
+            .spawn().map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+        let start = Instant::now();
+        while !started() {
+            if child.try_wait().map_err(io_err)?.is_some() { return Err("cast generate ended before it was interrupted".to_string()); }
+            if start.elapsed() > INTERRUPT_DEADLINE { let _ = child.kill(); return Err("cast generate wrote nothing".to_string()); }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT); }
+        let interrupt = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(io_err)? { break status; }
+            if interrupt.elapsed() > INTERRUPT_DEADLINE { let _ = child.kill(); return Err(format!("still running {:?} after SIGINT", INTERRUPT_DEADLINE)); }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        let mut stderr = String::new();
+        child.stderr.take().ok_or("no stderr")?.read_to_string(&mut stderr).map_err(io_err)?;
+        if status.code() != Some(130) { return Err(format!("exited with {:?} after SIGINT, expected 130: {}", status.code(), stderr.trim())); }
+        Ok(stderr)
+    };
+    in_temp_dir("interrupt", |root| {
+        let file = root.join("one.log");
+        interrupted(&file, &[], &|| std::fs::metadata(&file).is_ok_and(|m| m.len() > 0)).map_err(|e| format!("one file: {}", e))?;
+        if file.exists() { return Err("one file: the partial file is still there".to_string()); }
+
+        let dir = root.join("files");
+        let files = || std::fs::read_dir(&dir).map_or(0, |entries| entries.count());
+        let stderr = interrupted(&dir, &["--files", &INTERRUPT_FILES.to_string()], &|| files() >= INTERRUPT_AFTER_FILES).map_err(|e| format!("--files: {}", e))?;
+        let complete: usize = stderr.split(" of ").next().and_then(|head| head.rsplit(' ').next()?.parse().ok())
+            .ok_or_else(|| format!("--files: no count of complete files in: {}", stderr.trim()))?;
+        if files() != complete { return Err(format!("--files: {} files on disk, {} reported complete", files(), complete)); }
+        Ok(())
+    });
+}
+
+/// A run failing before it creates its archive must not remove a file already at its output
+/// path. A debug binary is then run into a backend panic at chunk `PANIC_CHUNK` (see
+/// `debug_panic_at` in main.rs): it must report it within `PANIC_TIME_BOUND` and fail without
+/// leaving its archive.
+#[test]
+fn panicking_backend() {
+    in_temp_dir("panic", |root| {
+        let input = root.join("input.log");
+        std::fs::write(&input, generate_vec(Profile::ALL[0], PANIC_FIXTURE_SIZE, DEFAULT_SEED)).map_err(io_err)?;
+
+        // A file at the output path of a run that fails before it creates its archive is kept
+        let kept = root.join("kept.cast");
+        std::fs::write(&kept, b"not written by this run").map_err(io_err)?;
+        let run = Command::new(CAST).args(["-c", &root.to_string_lossy(), &kept.to_string_lossy(), "-y"]).output()
+            .map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+        if run.status.code() != Some(1) { return Err(format!("compressing a directory exited with {:?}, expected 1", run.status.code())); }
+        if std::fs::read(&kept).ok().as_deref() != Some(b"not written by this run".as_slice()) {
+            return Err("a run that failed before creating its archive removed the file at its output path".to_string());
+        }
+
+        // Only debug builds panic on request
+        if !cfg!(debug_assertions) { return Ok(()); }
+        let output = root.join("panicked.cast");
+        let start = Instant::now();
+        let run = Command::new(CAST)
+            .args(["-c", &input.to_string_lossy(), &output.to_string_lossy(), "--chunk-size", CONVERT_CHUNK_SIZE, "--mode", "native", "-y"])
+            .env("CAST_DEBUG_PANIC_AT_CHUNK", PANIC_CHUNK.to_string())
+            .output().map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+        let elapsed = start.elapsed();
+        let stderr = String::from_utf8_lossy(&run.stderr);
+        if run.status.code() != Some(1) { return Err(format!("the panicking run exited with {:?}, expected 1", run.status.code())); }
+        if !stderr.contains(&format!("Compression failed at Chunk {}: Internal error", PANIC_CHUNK)) {
+            return Err(format!("the panicking run did not report the panic: {}", stderr.lines().last().unwrap_or("")));
+        }
+        if elapsed > PANIC_TIME_BOUND { return Err(format!("the panicking run took {:.2}s", elapsed.as_secs_f64())); }
+        if output.exists() { return Err("the panicking run left its archive behind".to_string()); }
+        Ok(())
+    });
+}
+
+/// Compresses a BOM + CRLF file and a file holding only its BOM with `cast -c` and restores them
+/// with `cast -d`: the archive header must record the BOM, the chunks must hold what follows it
+/// and the restore must give the file back.
+#[test]
+fn byte_order_mark() {
+    let mut crlf = UTF8_BOM.to_vec();
+    crlf.extend_from_slice(b"id,name,status\r\n");
+    (0..2000).for_each(|i| crlf.extend_from_slice(format!("{},user{},{}\r\n", i, i % 37, ["active", "idle"][i % 2]).as_bytes()));
+    in_temp_dir("bom", |root| {
+        for (name, data) in [("bom-crlf", crlf), ("bom-only", UTF8_BOM.to_vec())] {
+            let (input, archive, output) = (root.join(name), root.join(format!("{}.cast", name)), root.join(format!("{}.out", name)));
+            std::fs::write(&input, &data).map_err(io_err)?;
+            run_cast(&["-c", &input.to_string_lossy(), &archive.to_string_lossy(), "--mode", "native", "-y"]).map_err(|e| format!("{}: {}", name, e))?;
+            run_cast(&["-d", &archive.to_string_lossy(), &output.to_string_lossy(), "-y"]).map_err(|e| format!("{}: {}", name, e))?;
+            let bytes = std::fs::read(&archive).map_err(io_err)?;
+            let reader = ChunkReader::new(Cursor::new(&bytes)).map_err(|e| format!("{}: {}", name, e))?;
+            if !reader.archive_header().has_bom() { return Err(format!("{}: the header does not record the BOM", name)); }
+            if restore_archive_bytes(&bytes)? != data[UTF8_BOM.len()..] { return Err(format!("{}: the chunks do not hold the bytes after the BOM", name)); }
+            if std::fs::read(&output).map_err(io_err)? != data { return Err(format!("{}: cast -d restored different data", name)); }
+        }
+        Ok(())
+    });
+}
+
+/// Restores an archive with `cast -d --output-encoding --json` for each OUTPUT_ENCODINGS
+/// encoding, whose CRC checks see the restored bytes: the output file must decode to the
+/// fixture, and the done event must report the restored size as bytes_out and the file size as
+/// bytes_encoded.
+#[test]
+fn output_encoding() {
+    in_temp_dir("encoded", |root| {
+        let data = generate_vec(Profile::ALL[1], ENCODED_FIXTURE_SIZE, DEFAULT_SEED);
+        let (input, archive) = (root.join("input.csv"), root.join("input.csv.cast"));
+        std::fs::write(&input, &data).map_err(io_err)?;
+        run_cast(&["-c", &input.to_string_lossy(), &archive.to_string_lossy(), "--mode", "native", "--chunk-size", &format!("{}KB", ENCODED_CHUNK_SIZE / 1024), "-y"])?;
+        for encoding in OUTPUT_ENCODINGS {
+            let output = root.join(format!("restored.{}", encoding.label()));
+            let run = cast_output(&["-d", &archive.to_string_lossy(), &output.to_string_lossy(), "--output-encoding", encoding.label(), "--json", "-y"])?;
+            if !run.status.success() { return Err(format!("{}: cast -d failed ({})", encoding.label(), run.status)); }
+            let encoded = std::fs::read(&output).map_err(io_err)?;
+            if decode_output(encoding, &encoded)? != data { return Err(format!("{}: cast -d wrote an output that decodes to different data", encoding.label())); }
+            let stdout = String::from_utf8_lossy(&run.stdout);
+            let done = stdout.lines().find(|l| l.contains("\"event\":\"done\"")).unwrap_or_default();
+            if !done.contains(&format!("\"bytes_out\":{},", data.len())) || !done.contains(&format!("\"bytes_encoded\":{}", encoded.len())) {
+                return Err(format!("{}: the done event does not report {} restored and {} encoded bytes: {}", encoding.label(), data.len(), encoded.len(), done));
+            }
+        }
+        Ok(())
+    });
+}
+
+/// Verifies an archive of STRICT_VERIFY_CHUNKS chunks with `cast -v --strict-verify --json`, the
+/// second backend being roundtrip_check (see `fake_seven_zip` there). A faithful second backend
+/// must pass with cross_check "passed", a truncating one must fail at chunk 1 with the sizes
+/// both backends restored (one byte apart), and a missing one must fall back to a normal
+/// verification with cross_check "unavailable".
+#[test]
+fn strict_verify() {
+    in_temp_dir("strict_verify", |root| {
+        let (input, archive) = (root.join("noise.bin"), root.join("noise.bin.cast"));
+        std::fs::write(&input, noise(7, STRICT_VERIFY_CHUNKS * STRICT_VERIFY_CHUNK_SIZE)).map_err(io_err)?;
+        run_cast(&["-c", &input.to_string_lossy(), &archive.to_string_lossy(), "--mode", "native", "--chunk-size", &format!("{}B", STRICT_VERIFY_CHUNK_SIZE), "-y"])?;
+
+        let (fake, missing) = (Path::new(ROUNDTRIP_CHECK), root.join("no-7z"));
+        for (seven_zip, behaviour) in [(fake, FAKE_7Z_BEHAVIOURS[0]), (fake, FAKE_7Z_BEHAVIOURS[1]), (missing.as_path(), FAKE_7Z_BEHAVIOURS[0])] {
+            let case = if seven_zip == missing { "no 7-Zip" } else { behaviour };
+            let run = Command::new(CAST).args(["-v", &*archive.to_string_lossy(), "--strict-verify", "--json"])
+                .env("SEVEN_ZIP_PATH", seven_zip).env(FAKE_7Z_ENV, behaviour)
+                .stderr(Stdio::null()).output().map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+            let stdout = String::from_utf8_lossy(&run.stdout);
+            let event = |name: &str| stdout.lines().find(|l| l.contains(&format!("\"event\":\"{}\"", name))).unwrap_or_default().to_string();
+            let ok = match case {
+                "faithful" => run.status.success() && event("done").contains("\"cross_check\":\"passed\""),
+                "truncate" => {
+                    let error = event("error");
+                    let restored = |backend: &str| error.split(&format!("{} restored ", backend)).nth(1)
+                        .and_then(|rest| rest.split(' ').next()?.replace(',', "").parse::<u64>().ok());
+                    run.status.code() == Some(1) && error.contains("Backends disagree at Chunk 1:")
+                        && restored("native").zip(restored("7-Zip")).is_some_and(|(native, other)| native == other + 1)
+                },
+                _ => run.status.success() && event("done").contains("\"cross_check\":\"unavailable\""),
+            };
+            if !ok { return Err(format!("{}: cast -v --strict-verify exited with {:?}: {}", case, run.status.code(), stdout.trim())); }
+        }
+        Ok(())
+    });
+}
+
+/// Restores a solid archive of PROGRESS_FIXTURE_SIZE bytes with `cast -d --json` under
+/// PROGRESS_IO_LIMIT: it must emit progress events for its single chunk, no more than one per
+/// PROGRESS_INTERVAL of its run.
+#[test]
+fn progress_events() {
+    in_temp_dir("progress", |root| {
+        let data = generate_vec(Profile::ALL[0], PROGRESS_FIXTURE_SIZE, DEFAULT_SEED);
+        let (input, archive, output) = (root.join("input.log"), root.join("input.log.cast"), root.join("restored.log"));
+        std::fs::write(&input, &data).map_err(io_err)?;
+        run_cast(&["-c", &input.to_string_lossy(), &archive.to_string_lossy(), "--mode", "native", "-y"])?;
+        let start = Instant::now();
+        let run = cast_output(&["-d", &archive.to_string_lossy(), &output.to_string_lossy(), "--io-limit", &format!("{}B/s", PROGRESS_IO_LIMIT), "--json", "-y"])?;
+        let wall = start.elapsed();
+        if !run.status.success() { return Err(format!("cast -d failed ({})", run.status)); }
+        if std::fs::read(&output).map_err(io_err)? != data { return Err("cast -d restored different data".to_string()); }
+        let updates = String::from_utf8_lossy(&run.stdout).lines().filter(|l| l.contains("\"event\":\"progress\"")).count();
+        let most = (wall.as_secs_f64() / PROGRESS_INTERVAL.as_secs_f64()) as usize + 1;
+        if updates < 2 || updates > most {
+            return Err(format!("cast -d --json emitted {} progress event(s) in {:.2}s, expected 2 to {}", updates, wall.as_secs_f64(), most));
+        }
+        Ok(())
+    });
+}
+
+/// Compresses IO_LIMIT_WALL_INPUT bytes with `cast -c --io-limit`: the `throttle_wait` of its
+/// done event and its wall time must match the rate.
+#[test]
+fn io_limit() {
+    in_temp_dir("io_limit", |root| {
+        let (input, archive) = (root.join("input.log"), root.join("input.log.cast"));
+        std::fs::write(&input, generate_vec(Profile::ALL[0], IO_LIMIT_WALL_INPUT, DEFAULT_SEED)).map_err(io_err)?;
+        let start = Instant::now();
+        let run = cast_output(&["-c", &input.to_string_lossy(), &archive.to_string_lossy(), "--mode", "native", "--io-limit", &format!("{}B/s", IO_LIMIT_WALL_RATE), "--json", "-y"])?;
+        let wall = start.elapsed().as_secs_f64();
+        if !run.status.success() { return Err(format!("cast -c --io-limit failed ({})", run.status)); }
+        let stdout = String::from_utf8_lossy(&run.stdout);
+        let waited: f64 = stdout.lines().find(|l| l.contains("\"event\":\"done\""))
+            .and_then(|l| l.split("\"throttle_wait\":").nth(1))
+            .and_then(|v| v.trim_end_matches('}').split(',').next()?.parse().ok())
+            .ok_or("no throttle_wait in the done event")?;
+        let moved = IO_LIMIT_WALL_INPUT as u64 + std::fs::metadata(&archive).map_err(io_err)?.len();
+        let expected = moved as f64 / IO_LIMIT_WALL_RATE as f64;
+        if (waited - expected).abs() > expected * IO_LIMIT_WALL_TOLERANCE || wall < expected * (1.0 - IO_LIMIT_WALL_TOLERANCE) || wall < waited {
+            return Err(format!("cast -c moved {} bytes at {} bytes/s in {:.2}s ({:.2}s waiting), expected about {:.2}s", moved, IO_LIMIT_WALL_RATE, wall, waited, expected));
+        }
+        Ok(())
+    });
+}
+
+/// `cast -v --verify-sample` on an archive of VERIFY_SAMPLE_FIXTURE_SIZE in chunks: the chunks it
+/// lists must be the `sample_chunks` draw seeded by the archive fingerprint, on every run, with
+/// and without the sidecar index and on copies of the archive. A copy with a damaged chunk inside
+/// the sample must fail naming it; a copy damaged outside the sample passes (a full -v fails).
+#[test]
+fn verify_sample() {
+    let run = |args: &[&str]| -> Result<(bool, String), String> {
+        let out = Command::new(CAST).args(args).output().map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+        Ok((out.status.success(), format!("{}{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr))))
+    };
+    // Chunk numbers (from 0) of the "Chunks:" line of a sampled verification
+    let listed = |text: &str| -> Option<Vec<usize>> {
+        let line = text.lines().find_map(|l| l.trim_start().strip_prefix("Chunks:"))?;
+        line.split(',').map(|n| n.trim().parse::<usize>().ok().and_then(|n| n.checked_sub(1))).collect()
+    };
+    in_temp_dir("verify_sample", |root| {
+        let (input, archive) = (root.join("input.log"), root.join("input.log.cast"));
+        std::fs::write(&input, generate_vec(Profile::ALL[0], VERIFY_SAMPLE_FIXTURE_SIZE, DEFAULT_SEED)).map_err(io_err)?;
+        let (ok, text) = run(&["-c", &input.to_string_lossy(), &archive.to_string_lossy(), "--chunk-size", &format!("{}KB", VERIFY_SAMPLE_CHUNK_SIZE / 1024), "--mode", "native", "--index", "-y"])?;
+        if !ok { return Err(format!("cast -c --index failed:\n{}", text)); }
+        let bytes = std::fs::read(&archive).map_err(io_err)?;
+        let header_len = ArchiveHeader::read_from(&mut Cursor::new(&bytes)).map_err(|e| e.to_string())?.encoded_len();
+        let chunks = walk_chunk_headers(&mut Cursor::new(&bytes), header_len as u64).map_err(|e| e.to_string())?;
+        let seed = archive_fingerprint(&mut Cursor::new(&bytes), None).map_err(|e| e.to_string())?;
+        let expected = sample_chunks(chunks.len(), VERIFY_SAMPLE_CHUNKS, seed as u64);
+        if expected.len() != VERIFY_SAMPLE_CHUNKS || expected[0] != 0 || expected[expected.len() - 1] != chunks.len() - 1 || expected != sample_chunks(chunks.len(), VERIFY_SAMPLE_CHUNKS, seed as u64) {
+            return Err(format!("sample_chunks drew {:?} of {} chunks", expected, chunks.len()));
+        }
+
+        // A copy with the body of chunk `idx` damaged (a middle byte flipped)
+        let damaged = |name: &str, idx: usize| -> Result<std::path::PathBuf, String> {
+            let (offset, header) = &chunks[idx];
+            let mut copy = bytes.clone();
+            copy[*offset as usize + CHUNK_HEADER_SIZE + header.body_len() / 2] ^= 0xFF;
+            let path = root.join(name);
+            std::fs::write(&path, copy).map_err(io_err)?;
+            Ok(path)
+        };
+        let inside = expected[1];
+        let outside = (1..chunks.len()).find(|i| !expected.contains(i)).ok_or("every chunk is sampled")?;
+        let intact = root.join("copy.cast");
+        std::fs::write(&intact, &bytes).map_err(io_err)?;
+        let sample = VERIFY_SAMPLE_CHUNKS.to_string();
+        for (name, path, located) in [("indexed", &archive, "indexed"), ("indexed again", &archive, "indexed"), ("copy without index", &intact, "no index")] {
+            let (ok, text) = run(&["-v", &path.to_string_lossy(), "--verify-sample", &sample])?;
+            if !text.contains(&format!("({}", located)) { return Err(format!("{}: chunks not located as '{}':\n{}", name, located, text)); }
+            if !ok { return Err(format!("{}: --verify-sample failed:\n{}", name, text)); }
+            if listed(&text).as_ref() != Some(&expected) { return Err(format!("{}: checked {:?}, expected {:?}", name, listed(&text), expected)); }
+            if !text.contains(&format!("{}/{} chunks checked", VERIFY_SAMPLE_CHUNKS, chunks.len())) { return Err(format!("{}: no sampled verification report:\n{}", name, text)); }
+        }
+
+        let path = damaged("inside.cast", inside)?;
+        let (ok, text) = run(&["-v", &path.to_string_lossy(), "--verify-sample", &sample])?;
+        if ok || !text.contains(&format!("Chunk {}", inside + 1)) { return Err(format!("chunk {} damaged inside the sample: {}", inside + 1, if ok { "passed".to_string() } else { text })); }
+        let path = damaged("outside.cast", outside)?;
+        let (ok, text) = run(&["-v", &path.to_string_lossy(), "--verify-sample", &sample])?;
+        if !ok || listed(&text).as_ref() != Some(&expected) { return Err(format!("chunk {} damaged outside the sample:\n{}", outside + 1, text)); }
+        if run(&["-v", &path.to_string_lossy()])?.0 { return Err(format!("chunk {} damaged outside the sample: a full -v passed", outside + 1)); }
+        Ok(())
+    });
+}
+
+/// Generates PACK_FILES small files with `cast generate --files`, packs them with `cast -c --pack`
+/// and compresses their concatenation (in file table order) with `cast -c`: sizes and times must
+/// stay within the PACK_* bounds, the whole pack must restore to the concatenation and the files
+/// picked by the PACK_EXTRACT_STEP rule must restore on their own with `-d --file`.
+#[test]
+fn pack() {
+    let timed = |args: &[&str]| -> Result<Duration, String> {
+        let start = Instant::now();
+        run_cast(args).map_err(|e| format!("{}: cast {}", e, args.join(" ")))?;
+        Ok(start.elapsed())
+    };
+    in_temp_dir("pack", |root| {
+        let (files, packed, joined, archive) = (root.join("files"), root.join("files.cast"), root.join("joined.ndjson"), root.join("joined.ndjson.cast"));
+        let chunk_size = format!("{}B", PACK_CHUNK_SIZE);
+        timed(&["generate", Profile::Ndjson.name(), PACK_FIXTURE_SIZE, &files.to_string_lossy(), "--files", &PACK_FILES.to_string()])?;
+        let pack_time = timed(&["-c", "--pack", &files.to_string_lossy(), &packed.to_string_lossy(), "--chunk-size", &chunk_size, "--mode", "native", "-y"])?;
+        let table = read_file_table(&packed.to_string_lossy(), None)?;
+        if table.len() != PACK_FILES { return Err(format!("file table lists {} files, packed {}", table.len(), PACK_FILES)); }
+        let mut concatenation = Vec::new();
+        for file in &table {
+            if file.offset != concatenation.len() as u64 { return Err(format!("{} at offset {}, expected {}", file.path, file.offset, concatenation.len())); }
+            concatenation.extend(std::fs::read(&file.path).map_err(io_err)?);
+        }
+        std::fs::write(&joined, &concatenation).map_err(io_err)?;
+        let joined_time = timed(&["-c", &joined.to_string_lossy(), &archive.to_string_lossy(), "--chunk-size", &chunk_size, "--mode", "native", "-y"])?;
+        let (packed_len, joined_len) = (std::fs::metadata(&packed).map_err(io_err)?.len(), std::fs::metadata(&archive).map_err(io_err)?.len());
+        if packed_len.abs_diff(joined_len) as f64 > joined_len as f64 * PACK_SIZE_TOLERANCE {
+            return Err(format!("--pack wrote {} bytes, the concatenation {}", packed_len, joined_len));
+        }
+        if pack_time > joined_time.mul_f64(PACK_TIME_FACTOR) + PACK_TIME_SLACK {
+            return Err(format!("--pack took {:.2}s, the concatenation {:.2}s", pack_time.as_secs_f64(), joined_time.as_secs_f64()));
+        }
+        if restore_archive(&packed)? != concatenation { return Err("the pack does not restore to the concatenation".to_string()); }
+
+        let boundaries = (1..).map(|k| (k * PACK_CHUNK_SIZE) as u64).take_while(|&b| b < concatenation.len() as u64);
+        let mut picked: Vec<usize> = (0..table.len()).step_by(PACK_EXTRACT_STEP).chain([table.len() - 1]).collect();
+        picked.extend(boundaries.filter_map(|b| table.iter().position(|f| f.offset <= b && b < f.offset + f.len)));
+        picked.sort_unstable();
+        picked.dedup();
+        for i in picked {
+            let (file, output) = (&table[i], root.join(format!("extracted-{}", i)));
+            timed(&["-d", &packed.to_string_lossy(), &output.to_string_lossy(), "--file", &file.path, "-y"])?;
+            let extracted = std::fs::read(&output).map_err(io_err)?;
+            if extracted != std::fs::read(&file.path).map_err(io_err)? { return Err(format!("-d --file {} restored {} bytes that differ from the file", file.path, extracted.len())); }
+        }
+        Ok(())
+    });
+}
+
+/// `cast -d` of the version 1 golden archive, which has no header, must give the file it was
+/// made from.
+#[test]
+fn legacy_golden() {
+    in_temp_dir("legacy_golden", |root| {
+        let (archive, output) = (root.join("legacy-v1.log.cast"), root.join("legacy-v1.log"));
+        std::fs::write(&archive, LEGACY_GOLDEN_ARCHIVE).map_err(io_err)?;
+        run_cast(&["-d", &archive.to_string_lossy(), &output.to_string_lossy()])?;
+        if std::fs::read(&output).map_err(io_err)? != LEGACY_GOLDEN_ORIGINAL { return Err("cast -d restored different data".to_string()); }
+        Ok(())
+    });
+}
+
+/// `cast --dump-format` of each DUMP_GOLDENS archive must match its golden dump line for line.
+#[test]
+fn dump_golden() {
+    in_temp_dir("dump_golden", |root| {
+        for (name, archive, golden) in DUMP_GOLDENS {
+            std::fs::write(root.join(name), archive).map_err(io_err)?;
+            let run = Command::new(CAST).args(["--dump-format", name]).current_dir(root).stderr(Stdio::null()).output()
+                .map_err(|e| format!("cannot run '{}': {}", CAST, e))?;
+            if !run.status.success() { return Err(format!("{}: cast --dump-format failed ({})", name, run.status)); }
+            let stdout = String::from_utf8_lossy(&run.stdout);
+            let dump = stdout.find("[*]  Format dump").map_or("", |at| &stdout[at..]);
+            let (mut got, mut want) = (dump.lines(), golden.lines());
+            for line in 1.. {
+                match (got.next(), want.next()) {
+                    (None, None) => break,
+                    (g, w) if g == w => {},
+                    (g, w) => return Err(format!("{}: line {} of the dump is {:?}, the golden one {:?}", name, line, g.unwrap_or("(end)"), w.unwrap_or("(end)"))),
+                }
+            }
+        }
+        Ok(())
+    });
+}
@@ -0,0 +1,10 @@
+// Runs the round-trip check (src/bin/roundtrip_check.rs) under `cargo test`. The checks that run
+// the cast binary are in tests/cli.rs.
+
+use std::process::Command;
+
+#[test]
+fn roundtrip_check() {
+    let output = Command::new(env!("CARGO_BIN_EXE_roundtrip_check")).output().expect("roundtrip_check did not start");
+    assert!(output.status.success(), "roundtrip_check failed:\n{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
@@ -0,0 +1,227 @@
+use std::env;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+use cast::cast::{read_footer, CASTCompressor, CASTDecompressor, NativeCompressor, NativeDecompressor};
+use cast::cast_lzma::{LzmaBackend, LzmaDecompressorBackend, SevenZipDecompressorBackend};
+use cast::error::CastError;
+
+// ============================================================================
+//  CORRUPTION CHECK
+// ============================================================================
+//
+//  Every kind of damage the reader detects must surface as its own CastError
+//  variant, so library users can tell a damaged archive from I/O or backend
+//  trouble without matching messages. A small archive is written through a
+//  pass-through backend (its blocks can be edited in place) and through LZMA,
+//  each case damages one copy, and restoring it must fail with the expected
+//  variant, with `is_corruption` set for damage to the archive only. Groups have
+//  no CRC in this format, so `CrcMismatch` is never produced here.
+//
+//  Run with `cargo run --bin corruption_check`.
+
+// Rows of the fixture and the rows per group it is compressed with (several groups)
+const FIXTURE_ROWS: usize = 2_000;
+const GROUP_ROWS: usize = 500;
+// Rows of one shape in a row of the fixture with long template runs (RLE id streams)
+const RLE_RUN: usize = 125;
+
+// Bytes after the footer offset: [offset u64] [magic "CAST" + version]
+const TAIL_LEN: usize = 13;
+// Fields of a checksummed footer after the group entries: [original size u64] [flags u8] [crc u32]
+const FOOTER_FIELDS_LEN: usize = 13;
+const GROUP_ENTRY_LEN: usize = 25;
+
+/// Stores blocks as they are, so the check can edit them.
+struct IdentityBackend;
+
+impl NativeCompressor for IdentityBackend {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> { Ok(data.to_vec()) }
+}
+
+impl NativeDecompressor for IdentityBackend {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> { Ok(data.to_vec()) }
+}
+
+/// Reader whose reads fail once the footer was read (the first read of a group).
+struct FailingReader {
+    inner: Cursor<Vec<u8>>,
+    footer_start: u64,
+}
+
+impl Read for FailingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inner.position() < self.footer_start {
+            return Err(io::Error::other("device went away"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for FailingReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> { self.inner.seek(pos) }
+}
+
+/// Backend a case restores with.
+#[derive(Clone, Copy)]
+enum Backend {
+    Identity,
+    Lzma,
+    SevenZip,
+}
+
+struct Case {
+    name: &'static str,
+    archive: Vec<u8>,
+    backend: Backend,
+    expected: fn(&CastError) -> bool,
+    corruption: bool,
+}
+
+fn main() {
+    let data = fixture();
+    let identity = compress(IdentityBackend, &data);
+    let lzma = compress(LzmaBackend::new(false, 1024 * 1024), &data);
+    let rle = compress(IdentityBackend, &fixture_rows(|row| row / RLE_RUN % 3));
+    println!("[*] Corruption check: {} rows in groups of {}, {} groups", FIXTURE_ROWS, GROUP_ROWS, read_footer(&mut Cursor::new(&identity)).map(|g| g.len()).unwrap_or(0));
+
+    let mut failures = Vec::new();
+    // The intact archives restore the fixture
+    for (name, archive, backend) in [("identity", &identity, Backend::Identity), ("lzma", &lzma, Backend::Lzma)] {
+        match restore(archive, backend) {
+            Ok(restored) if restored == data => {},
+            Ok(_) => failures.push(format!("intact {} archive: restored data differs", name)),
+            Err(e) => failures.push(format!("intact {} archive: {}", name, e)),
+        }
+    }
+
+    let cases = cases(&identity, &lzma, &rle);
+    for case in &cases {
+        match restore(&case.archive, case.backend) {
+            Ok(_) => failures.push(format!("{}: restored without an error", case.name)),
+            Err(e) if !(case.expected)(&e) => failures.push(format!("{}: wrong variant {:?}", case.name, e)),
+            Err(e) if e.is_corruption() != case.corruption => failures.push(format!("{}: is_corruption() is {} for {:?}", case.name, e.is_corruption(), e)),
+            Err(_) => {},
+        }
+    }
+
+    // Reads that fail after the footer are I/O errors, not damage
+    let footer_start = footer_offset(&identity) as u64;
+    let reader = FailingReader { inner: Cursor::new(identity.clone()), footer_start };
+    match CASTDecompressor::new(IdentityBackend).decompress_stream(reader, io::sink(), None) {
+        Err(CastError::Io(_)) => {},
+        other => failures.push(format!("failing reader: expected an I/O error, got {:?}", other.err())),
+    }
+
+    let runs = cases.len() + 3;
+    if failures.is_empty() {
+        println!("[+] All {} corruption case(s) failed with the expected error.", runs);
+    } else {
+        println!("[!] {} of {} corruption case(s) failed:", failures.len(), runs);
+        for failure in &failures { println!("    {}", failure); }
+        std::process::exit(1);
+    }
+}
+
+/// Every damaged archive and the variant restoring it must fail with.
+fn cases(identity: &[u8], lzma: &[u8], rle: &[u8]) -> Vec<Case> {
+    // The first block starts the archive: [id mode u8] [registry length u32] [ids length u32] ...
+    let footer = footer_offset(identity);
+    let len = identity.len();
+    let edit = |archive: &[u8], f: &dyn Fn(&mut Vec<u8>)| { let mut a = archive.to_vec(); f(&mut a); a };
+
+    vec![
+        Case { name: "empty file", archive: Vec::new(), backend: Backend::Identity, expected: |e| matches!(e, CastError::NotACastArchive), corruption: false },
+        Case { name: "shorter than the footer tail", archive: identity[len - 5..].to_vec(), backend: Backend::Identity, expected: |e| matches!(e, CastError::NotACastArchive), corruption: false },
+        Case { name: "damaged magic", archive: edit(identity, &|a| { let n = a.len(); a[n - 5] = b'X'; }), backend: Backend::Identity, expected: |e| matches!(e, CastError::NotACastArchive), corruption: false },
+        Case { name: "future footer version", archive: edit(identity, &|a| { let n = a.len(); a[n - 1] = 0x7F; }), backend: Backend::Identity, expected: |e| matches!(e, CastError::UnsupportedFormat { version: 0x7F }), corruption: false },
+        Case { name: "footer offset past the end", archive: edit(identity, &|a| { let n = a.len(); a[n - TAIL_LEN..n - 5].copy_from_slice(&u64::MAX.to_le_bytes()); }), backend: Backend::Identity, expected: |e| matches!(e, CastError::CorruptFooter { .. }), corruption: true },
+        Case { name: "group count past the footer", archive: edit(identity, &|a| a[footer..footer + 4].copy_from_slice(&u32::MAX.to_le_bytes())), backend: Backend::Identity, expected: |e| matches!(e, CastError::CorruptFooter { .. }), corruption: true },
+        Case { name: "group entry flipped (footer checksum)", archive: edit(identity, &|a| a[footer + 4 + 16] ^= 1), backend: Backend::Identity, expected: |e| matches!(e, CastError::CorruptFooter { .. }), corruption: true },
+        Case { name: "first group cut to 4 bytes", archive: edit(identity, &|a| { let at = footer + 4 + 8; a[at..at + 8].copy_from_slice(&4u64.to_le_bytes()); reseal_footer(a); }), backend: Backend::Identity, expected: |e| matches!(e, CastError::CorruptHeader { chunk: 1, .. }), corruption: true },
+        Case { name: "registry length past the block", archive: edit(identity, &|a| a[1..5].copy_from_slice(&u32::MAX.to_le_bytes())), backend: Backend::Identity, expected: |e| matches!(e, CastError::LengthOverflow { chunk: 1, .. }), corruption: true },
+        Case { name: "registry not UTF-8", archive: edit(identity, &|a| { let at = registry_start(a); a[at] = 0xFF; }), backend: Backend::Identity, expected: |e| matches!(e, CastError::CorruptHeader { chunk: 1, .. }), corruption: true },
+        Case { name: "RLE run past the group's rows", archive: edit(rle, &|a| inflate_first_run(a)), backend: Backend::Identity, expected: |e| matches!(e, CastError::CorruptHeader { chunk: 1, .. }), corruption: true },
+        Case { name: "xz stream flipped", archive: edit(lzma, &|a| { let at = a.len() / 4; a[at] ^= 0x55; }), backend: Backend::Lzma, expected: |e| matches!(e, CastError::BackendFailure { backend: "Native (xz2)", .. }), corruption: false },
+        Case { name: "7-Zip missing", archive: lzma.to_vec(), backend: Backend::SevenZip, expected: |e| matches!(e, CastError::BackendFailure { backend: "7-Zip", .. }), corruption: false },
+    ]
+}
+
+/// Rows of three shapes with numbers that change from row to row.
+fn fixture() -> Vec<u8> {
+    fixture_rows(|row| row % 3)
+}
+
+/// The fixture rows, each in the shape `shape` picks for it.
+fn fixture_rows(shape: fn(usize) -> usize) -> Vec<u8> {
+    let mut out = String::new();
+    for row in 0..FIXTURE_ROWS {
+        match shape(row) {
+            0 => out.push_str(&format!("2024-01-01 10:{:02}:{:02} INFO user={} login ok\n", row / 60 % 60, row % 60, row * 7)),
+            1 => out.push_str(&format!("2024-01-01 10:{:02}:{:02} WARN disk {} at {}%\n", row / 60 % 60, row % 60, row % 8, row % 100)),
+            _ => out.push_str(&format!("2024-01-01 10:{:02}:{:02} INFO request id={:x} took {}ms\n", row / 60 % 60, row % 60, row * 31, row % 250)),
+        }
+    }
+    out.into_bytes()
+}
+
+fn compress<C: NativeCompressor>(backend: C, data: &[u8]) -> Vec<u8> {
+    let mut compressor = CASTCompressor::new(backend);
+    compressor.set_chunk_size(GROUP_ROWS);
+    let mut archive = Vec::new();
+    compressor.compress_stream(data, &mut archive, |_, _| {}).expect("fixture compression");
+    archive
+}
+
+fn restore(archive: &[u8], backend: Backend) -> Result<Vec<u8>, CastError> {
+    let mut restored = Vec::new();
+    match backend {
+        Backend::Identity => CASTDecompressor::new(IdentityBackend).decompress_stream(Cursor::new(archive), &mut restored, None)?,
+        Backend::Lzma => CASTDecompressor::new(LzmaDecompressorBackend).decompress_stream(Cursor::new(archive), &mut restored, None)?,
+        Backend::SevenZip => {
+            env::set_var("SEVEN_ZIP_PATH", env::temp_dir().join("corruption_check_no_7z").join("7z"));
+            let result = CASTDecompressor::new(SevenZipDecompressorBackend).decompress_stream(Cursor::new(archive), &mut restored, None);
+            env::remove_var("SEVEN_ZIP_PATH");
+            result?
+        },
+    }
+    Ok(restored)
+}
+
+fn footer_offset(archive: &[u8]) -> usize {
+    let n = archive.len();
+    u64::from_le_bytes(archive[n - TAIL_LEN..n - 5].try_into().unwrap()) as usize
+}
+
+/// Start of the registry of the first block (after the id mode, the two lengths and, for
+/// single-template blocks, the row count).
+fn registry_start(archive: &[u8]) -> usize {
+    match archive[0] & 0x3F {
+        4 => 13,
+        _ => 9,
+    }
+}
+
+/// Rewrites the RLE id stream of the first block as a run of more than 2^35 rows of its first
+/// id followed by empty runs of id 0, keeping the length of the stream.
+fn inflate_first_run(archive: &mut [u8]) {
+    assert!(archive[0] & 0x40 != 0, "the first block of the RLE fixture has a raw id stream");
+    let width = match archive[0] & 0x3F { 2 => 1, 1 => 4, _ => 2 };
+    let len_reg = u32::from_le_bytes(archive[1..5].try_into().unwrap()) as usize;
+    let len_ids = u32::from_le_bytes(archive[5..9].try_into().unwrap()) as usize;
+    let ids = registry_start(archive) + len_reg;
+    let rest = &mut archive[ids + width..ids + len_ids];
+    // A 6 to 9 byte varint, so that the empty (id, 0) pairs fill the rest exactly
+    let run_len = (6..=9).rev().find(|k| rest.len() >= *k && (rest.len() - k).is_multiple_of(width + 1)).expect("id stream too short");
+    rest.fill(0);
+    rest[..run_len].fill(0xFF);
+    rest[run_len - 1] = 0x7F;
+}
+
+/// Recomputes the footer checksum after its entries were edited.
+fn reseal_footer(archive: &mut [u8]) {
+    let footer = footer_offset(archive);
+    let crc_at = archive.len() - TAIL_LEN - 4;
+    debug_assert_eq!((crc_at - footer - 4 - (FOOTER_FIELDS_LEN - 4)) % GROUP_ENTRY_LEN, 0);
+    let crc = crc32fast::hash(&archive[footer..crc_at]);
+    archive[crc_at..crc_at + 4].copy_from_slice(&crc.to_le_bytes());
+}
@@ -0,0 +1,124 @@
+use std::io::Cursor;
+
+use cast::cast::{read_footer, CASTCompressor, NativeCompressor};
+use cast::error::CastError;
+
+// ============================================================================
+//  ROW GROUPS CHECK
+// ============================================================================
+//
+//  `--chunk-size` sets the byte limit of the row groups directly, so their size
+//  must not depend on an estimate of the average row. The fixture opens with
+//  short lines and goes on with long ones (an estimate taken from its head would
+//  be off about tenfold): every group but the last must hold at least the byte
+//  limit of input and less than the limit plus one line. With a row limit as
+//  well, a group is flushed at whichever limit it reaches first.
+//
+//  Run with `cargo run --bin row_groups_check`.
+
+// Short lines first, then long ones, and their sizes in bytes (newline included)
+const SHORT_ROWS: usize = 2_000;
+const LONG_ROWS: usize = 400;
+const SHORT_LINE_LEN: usize = 32;
+const LONG_LINE_LEN: usize = 320;
+// Byte limit of the groups, and a row limit that groups of short lines reach first (6.4KB)
+// and groups of long lines never reach (52 of them pass the byte limit)
+const GROUP_BYTES: usize = 16 * 1024;
+const GROUP_ROWS: usize = 200;
+
+/// Stores blocks as they are (the check only reads the footer).
+struct IdentityBackend;
+
+impl NativeCompressor for IdentityBackend {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> { Ok(data.to_vec()) }
+}
+
+fn main() {
+    let data = fixture();
+    let line_lens: Vec<usize> = data.split_inclusive(|&b| b == b'\n').map(|line| line.len()).collect();
+    println!("[*] Row groups check: {} short then {} long lines, {} byte groups", SHORT_ROWS, LONG_ROWS, GROUP_BYTES);
+
+    let mut failures = Vec::new();
+    let mut runs = 0;
+    for (name, rows) in [("byte limit", None), ("byte and row limits", Some(GROUP_ROWS))] {
+        runs += 1;
+        let mut compressor = CASTCompressor::new(IdentityBackend);
+        compressor.set_chunk_bytes(GROUP_BYTES);
+        if let Some(rows) = rows { compressor.set_chunk_size(rows); }
+        if compressor.chunk_limits() != (rows, Some(GROUP_BYTES)) {
+            failures.push(format!("{}: limits {:?}", name, compressor.chunk_limits()));
+            continue;
+        }
+        match group_bytes(compressor, &data, &line_lens) {
+            Ok(groups) => if let Err(e) = check_groups(&groups, rows) { failures.push(format!("{}: {}", name, e)); },
+            Err(e) => failures.push(format!("{}: {}", name, e)),
+        }
+    }
+    runs += 1;
+    let defaults = CASTCompressor::new(IdentityBackend).chunk_limits();
+    if defaults.1.is_some() || defaults.0.is_none() {
+        failures.push(format!("no limit set: limits {:?}, expected a row limit only", defaults));
+    }
+
+    if failures.is_empty() {
+        println!("[+] All {} row group check(s) passed.", runs);
+    } else {
+        println!("[!] {} of {} row group check(s) failed:", failures.len(), runs);
+        for failure in &failures { println!("    {}", failure); }
+        std::process::exit(1);
+    }
+}
+
+/// Compresses `data` and returns the (rows, input bytes) of each group, the bytes summed from
+/// `line_lens` over the rows the footer gives it.
+fn group_bytes<C: NativeCompressor>(mut compressor: CASTCompressor<C>, data: &[u8], line_lens: &[usize]) -> Result<Vec<(usize, usize)>, String> {
+    let mut archive = Vec::new();
+    compressor.compress_stream(data, &mut archive, |_, _| {}).map_err(|e| format!("compression: {}", e))?;
+    let groups = read_footer(&mut Cursor::new(&archive)).map_err(|e| format!("footer: {}", e))?;
+    let mut first = 0;
+    let mut sizes = Vec::with_capacity(groups.len());
+    for group in &groups {
+        let rows = group.num_rows as usize;
+        let lens = line_lens.get(first..first + rows).ok_or_else(|| format!("groups hold more than the {} rows", line_lens.len()))?;
+        sizes.push((rows, lens.iter().sum()));
+        first += rows;
+    }
+    if first != line_lens.len() { return Err(format!("groups hold {} of the {} rows", first, line_lens.len())); }
+    Ok(sizes)
+}
+
+/// Every group but the last reached a limit (the byte limit, or `rows`) and stopped within one
+/// line of it; the last one stays within the limits too. With `rows`, both limits must have
+/// ended groups.
+fn check_groups(groups: &[(usize, usize)], rows: Option<usize>) -> Result<(), String> {
+    if groups.len() < 2 { return Err(format!("{} group(s)", groups.len())); }
+    if let Some(r) = rows {
+        if !groups.iter().any(|&(n, bytes)| n == r && bytes < GROUP_BYTES) || !groups.iter().any(|&(n, bytes)| n < r && bytes >= GROUP_BYTES) {
+            return Err(format!("groups {:?} were not ended by both limits", groups));
+        }
+    }
+    for (i, &(group_rows, bytes)) in groups.iter().enumerate() {
+        let row_full = rows.is_some_and(|r| group_rows == r);
+        let byte_full = bytes >= GROUP_BYTES;
+        let last = i == groups.len() - 1;
+        if bytes >= GROUP_BYTES + LONG_LINE_LEN || rows.is_some_and(|r| group_rows > r) {
+            return Err(format!("group {}: {} rows, {} bytes (past the limits)", i, group_rows, bytes));
+        }
+        if !last && !row_full && !byte_full {
+            return Err(format!("group {}: flushed at {} rows, {} bytes (under the limits)", i, group_rows, bytes));
+        }
+    }
+    Ok(())
+}
+
+fn fixture() -> Vec<u8> {
+    let mut out = String::new();
+    for row in 0..SHORT_ROWS + LONG_ROWS {
+        let len = if row < SHORT_ROWS { SHORT_LINE_LEN } else { LONG_LINE_LEN };
+        let head = format!("2024-01-01 INFO id={:05} ", row);
+        out.push_str(&head);
+        out.push_str(&"x".repeat(len - head.len() - 1));
+        out.push('\n');
+    }
+    out.into_bytes()
+}
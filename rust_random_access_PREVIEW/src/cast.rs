@@ -1,16 +1,20 @@
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::io::{Read, Write, BufRead, BufReader, Seek, SeekFrom};
+use std::io::{self, Read, Write, BufRead, BufReader, Seek, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use crc32fast::Hasher;
+use crate::error::CastError;
 
 // ============================================================================
 //  TRAITS FOR ABSTRACTION
 // ============================================================================
 
 pub trait NativeCompressor {
-    fn compress(&self, data: &[u8]) -> Vec<u8>;
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError>;
 }
 
 pub trait NativeDecompressor {
-    fn decompress(&self, data: &[u8]) -> Vec<u8>;
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError>;
 }
 
 // ============================================================================
@@ -21,7 +25,19 @@ const VAR_PLACEHOLDER: char = '\u{E000}';
 const VAR_PLACEHOLDER_STR: &str = "\u{E000}";
 const VAR_PLACEHOLDER_QUOTE: &str = "\"\u{E000}\"";
 const REG_SEPARATOR: &str = "\u{E001}";
-const FOOTER_MAGIC: [u8; 5] = [b'C', b'A', b'S', b'T', 0x01];
+// Last byte = format version. 0x02 added RLE id streams, single-template blocks with a row
+// count field (id mode 4), and three fields between the group entries and the footer offset:
+// the original size, the footer flags byte and a CRC32 of the footer body (group count
+// through flags). 0x01 archives are still readable.
+const FOOTER_MAGIC: [u8; 5] = [b'C', b'A', b'S', b'T', 0x02];
+const MIN_FOOTER_VERSION: u8 = 0x01;
+// Bytes of one group entry: start offset, compressed size, rows (u64 each) and kind
+const GROUP_ENTRY_LEN: usize = 25;
+
+// Footer flags
+// The input started with a UTF-8 BOM, stripped before parsing and re-emitted on full restores
+pub const FOOTER_FLAG_INPUT_BOM: u8 = 0x01;
+const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
 const DEFAULT_CHUNK_ROWS: usize = 100_000;
 
 #[derive(Clone, Debug)]
@@ -68,16 +84,24 @@ enum ParsingMode { Strict, Aggressive }
 //  PARSING HELPERS
 // ============================================================================
 
+#[allow(clippy::manual_range_contains)]
 fn is_digit(b: u8) -> bool { b >= b'0' && b <= b'9' }
+#[allow(clippy::manual_range_contains)]
 fn is_hex_digit(b: u8) -> bool { (b >= b'0' && b <= b'9') || (b >= b'a' && b <= b'f') || (b >= b'A' && b <= b'F') }
+#[allow(clippy::manual_range_contains)]
 fn is_aggr_char(b: u8) -> bool { (b >= b'a' && b <= b'z') || (b >= b'A' && b <= b'Z') || (b >= b'0' && b <= b'9') || b == b'_' || b == b'.' || b == b'-' || b == b':' }
 
+// Same rule as is_likely_binary in rust/src/cast.rs (see BINARY GUARD there): control bytes
+// other than whitespace, except ESC '[' (ANSI colors), above 1% of the first 4KB
 fn is_likely_binary(data: &[u8]) -> bool {
-    let limit = std::cmp::min(data.len(), 4096);
-    let sample = &data[..limit];
-    let mut control_count = 0;
-    for &b in sample { if b < 9 || (b > 13 && b < 32) { control_count += 1; } }
-    (control_count as f64 / limit as f64) > 0.01
+    let sample = &data[..std::cmp::min(data.len(), 4096)];
+    if sample.is_empty() { return false; }
+    let control_count = sample.iter().enumerate().filter(|&(i, &b)| match b {
+        b'\t' | b'\n' | 0x0B | 0x0C | b'\r' => false,
+        0x1B => sample.get(i + 1) != Some(&b'['),
+        _ => b < 0x20,
+    }).count();
+    (control_count as f64 / sample.len() as f64) > 0.01
 }
 
 fn match_strict_number(bytes: &[u8]) -> usize {
@@ -86,11 +110,9 @@ fn match_strict_number(bytes: &[u8]) -> usize {
     if i < len && bytes[i] == b'-' { i += 1; }
     if i >= len || !is_digit(bytes[i]) { return 0; }
     while i < len && is_digit(bytes[i]) { i += 1; }
-    if i + 1 < len && bytes[i] == b'.' {
-        if is_digit(bytes[i+1]) {
-            i += 2;
-            while i < len && is_digit(bytes[i]) { i += 1; }
-        }
+    if i + 1 < len && bytes[i] == b'.' && is_digit(bytes[i+1]) {
+        i += 2;
+        while i < len && is_digit(bytes[i]) { i += 1; }
     }
     i
 }
@@ -105,6 +127,24 @@ fn match_strict_hex(bytes: &[u8]) -> usize {
     0
 }
 
+/// Length of the quoted string starting at `bytes[0]` (a '"'), quotes included, or `None` when
+/// it is not closed on this line. A backslash escapes the next byte and `""` is content, so `""`
+/// alone is an empty string and a trailing backslash leaves the string open (same rules as the
+/// standard implementation).
+fn quoted_len(bytes: &[u8]) -> Option<usize> {
+    let mut k = 1;
+    while k < bytes.len() {
+        match bytes[k] {
+            b'"' if bytes.get(k + 1) == Some(&b'"') => k += 2,
+            b'"' => return Some(k + 1),
+            b'\\' if k + 1 < bytes.len() => k += 2,
+            b'\\' => return None,
+            _ => k += 1,
+        }
+    }
+    None
+}
+
 fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec<&'a str>, buffer_skel: &mut String) -> bool {
     if line.contains(VAR_PLACEHOLDER) || line.contains(REG_SEPARATOR) { return false; }
     let bytes = line.as_bytes();
@@ -114,20 +154,8 @@ fn parse_line_manual<'a>(line: &'a str, mode: ParsingMode, buffer_vars: &mut Vec
     while i < len {
         let b = bytes[i];
         if b == b'"' {
-            let mut k = 1;
-            let mut closed = false;
-            let remaining = &bytes[i..];
-            while k < remaining.len() {
-                let curr = remaining[k];
-                if curr == b'"' {
-                    if k + 1 < remaining.len() && remaining[k+1] == b'"' { k += 2; }
-                    else { k += 1; closed = true; break; }
-                } else if curr == b'\\' { k += 2; } else { k += 1; }
-            }
-            if closed {
-                let matched_len = k;
-                let end_content = if matched_len > 1 { matched_len - 1 } else { 1 };
-                let content = &line[i+1 .. i+end_content];
+            if let Some(matched_len) = quoted_len(&bytes[i..]) {
+                let content = &line[i+1 .. i+matched_len-1];
                 if i > last_struct_start { buffer_skel.push_str(&line[last_struct_start..i]); }
                 buffer_vars.push(content);
                 buffer_skel.push_str(VAR_PLACEHOLDER_QUOTE);
@@ -166,6 +194,91 @@ fn decode_python_latin1(data: &[u8]) -> String {
     data.iter().map(|&b| b as char).collect()
 }
 
+// ============================================================================
+//  TEMPLATE ID STREAM (RLE)
+// ============================================================================
+
+// Block flag layout: bits 0-5 = id width mode, 0x40 = RLE id stream (footer version 0x02)
+// id modes: 0 = u16 ids, 1 = u32 ids, 2 = u8 ids, 3 = single template (legacy, read only),
+// 4 = single template: rows u32 follows len_ids (footer version 0x02)
+const ID_MODE_MASK: u8 = 0x3F;
+const FLAG_IDS_RLE: u8 = 0x40;
+const ID_MODE_SINGLE_LEGACY: u8 = 3;
+const ID_MODE_SINGLE: u8 = 4;
+
+/// Row count of a single-template block (no id stream). Id mode 4 stores it in its own field;
+/// legacy id mode 3 stored it in len_ids only when the template had no vars (0 otherwise),
+/// and the count then comes from the cells of the first column.
+fn single_template_rows(id_mode: u8, stored: u32, first_column: Option<&VecDeque<(usize, usize)>>) -> usize {
+    match first_column {
+        Some(cells) if id_mode == ID_MODE_SINGLE_LEGACY && stored == 0 => cells.len(),
+        _ => stored as usize,
+    }
+}
+
+// RLE is only chosen when runs are long enough to beat the raw stream
+const RLE_MIN_AVG_RUN: usize = 4;
+
+fn id_width(id_mode: u8) -> usize {
+    match id_mode { 2 => 1, 1 => 4, _ => 2 }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 { out.push((v as u8) | 0x80); v >>= 7; }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut v = 0u64;
+    let mut shift = 0;
+    loop {
+        let b = *data.get(*pos)?;
+        *pos += 1;
+        if shift > 63 { return None; }
+        v |= ((b & 0x7F) as u64) << shift;
+        if b & 0x80 == 0 { return Some(v); }
+        shift += 7;
+    }
+}
+
+/// Encodes ids as (id, varint run length) pairs. Returns `None` when the average run is too
+/// short for RLE to pay off (e.g. alternating ids).
+fn encode_ids_rle(ids: &[u32], width: usize) -> Option<Vec<u8>> {
+    if ids.is_empty() { return None; }
+    let runs = 1 + ids.windows(2).filter(|w| w[0] != w[1]).count();
+    if ids.len() / runs < RLE_MIN_AVG_RUN { return None; }
+
+    let mut out = Vec::with_capacity(runs * (width + 2));
+    let mut i = 0;
+    while i < ids.len() {
+        let id = ids[i];
+        let mut run = 1;
+        while i + run < ids.len() && ids[i + run] == id { run += 1; }
+        out.extend_from_slice(&id.to_le_bytes()[..width]);
+        push_varint(&mut out, run as u64);
+        i += run;
+    }
+    Some(out)
+}
+
+/// Expands (id, run length) pairs into `out`. Returns `None` on a truncated pair or once the
+/// runs add up to more than `max_rows`, before the rows are allocated.
+fn decode_ids_rle(data: &[u8], width: usize, max_rows: u64, out: &mut Vec<usize>) -> Option<()> {
+    let mut pos = 0;
+    let mut total = 0u64;
+    while pos < data.len() {
+        if pos + width > data.len() { return None; }
+        let mut id_bytes = [0u8; 4];
+        id_bytes[..width].copy_from_slice(&data[pos..pos + width]);
+        pos += width;
+        let run = read_varint(data, &mut pos)?;
+        total = total.checked_add(run).filter(|&t| t <= max_rows)?;
+        let run = run as usize;
+        out.extend(std::iter::repeat_n(u32::from_le_bytes(id_bytes) as usize, run));
+    }
+    Some(())
+}
+
 // ============================================================================
 //  CAST COMPRESSOR
 // ============================================================================
@@ -179,7 +292,9 @@ pub struct CASTCompressor<C: NativeCompressor> {
     mode: ParsingMode,
     backend: C,
     rows_in_current_block: usize,
-    chunk_limit_rows: usize,
+    bytes_in_current_block: usize,
+    chunk_limit_rows: Option<usize>,
+    chunk_limit_bytes: Option<usize>,
 }
 
 impl<C: NativeCompressor> CASTCompressor<C> {
@@ -193,10 +308,32 @@ impl<C: NativeCompressor> CASTCompressor<C> {
             mode: ParsingMode::Strict,
             backend,
             rows_in_current_block: 0,
-            chunk_limit_rows: DEFAULT_CHUNK_ROWS,
+            bytes_in_current_block: 0,
+            chunk_limit_rows: None,
+            chunk_limit_bytes: None,
         }
     }
-    pub fn set_chunk_size(&mut self, rows: usize) { self.chunk_limit_rows = rows; }
+
+    /// Flushes a row group every `rows` rows.
+    pub fn set_chunk_size(&mut self, rows: usize) { self.chunk_limit_rows = Some(rows.max(1)); }
+
+    /// Flushes a row group once it holds `bytes` of input. When a row limit is also set, the
+    /// group is flushed as soon as either limit is reached.
+    pub fn set_chunk_bytes(&mut self, bytes: usize) { self.chunk_limit_bytes = Some(bytes.max(1)); }
+
+    /// Effective (rows, bytes) limits per row group. Without any explicit limit, groups hold
+    /// DEFAULT_CHUNK_ROWS rows.
+    pub fn chunk_limits(&self) -> (Option<usize>, Option<usize>) {
+        match (self.chunk_limit_rows, self.chunk_limit_bytes) {
+            (None, None) => (Some(DEFAULT_CHUNK_ROWS), None),
+            limits => limits,
+        }
+    }
+
+    fn block_is_full(&self) -> bool {
+        let (rows, bytes) = self.chunk_limits();
+        rows.is_some_and(|r| self.rows_in_current_block >= r) || bytes.is_some_and(|b| self.bytes_in_current_block >= b)
+    }
 
     fn reset_block_state(&mut self) {
         self.template_map.clear();
@@ -205,6 +342,7 @@ impl<C: NativeCompressor> CASTCompressor<C> {
         for cols in self.columns_storage.values_mut() { for col in cols.iter_mut() { col.clear(); } }
         self.next_template_id = 0;
         self.rows_in_current_block = 0;
+        self.bytes_in_current_block = 0;
     }
 
     fn analyze_strategy_from_sample(&mut self, text: &str) {
@@ -226,8 +364,8 @@ impl<C: NativeCompressor> CASTCompressor<C> {
         self.mode = if ratio > 0.10 { ParsingMode::Aggressive } else { ParsingMode::Strict };
     }
 
-    fn flush_current_block(&mut self) -> (Vec<u8>, u8) {
-        if self.rows_in_current_block == 0 { return (Vec::new(), 0); }
+    fn flush_current_block(&mut self) -> Result<(Vec<u8>, u8), CastError> {
+        if self.rows_in_current_block == 0 { return Ok((Vec::new(), 0)); }
         let num_templates = self.skeletons_list.len();
         let mut decision_mode = "UNIFIED";
         if num_templates < 256 {
@@ -246,7 +384,7 @@ impl<C: NativeCompressor> CASTCompressor<C> {
                 if collected > 2000 { break; }
             }
             if !sample_buffer.is_empty() {
-                let c_sample = self.backend.compress(&sample_buffer);
+                let c_sample = self.backend.compress(&sample_buffer)?;
                 if (sample_buffer.len() as f64 / (c_sample.len() as f64 + 1.0)) < 3.0 { decision_mode = "SPLIT"; }
             }
         }
@@ -282,9 +420,9 @@ impl<C: NativeCompressor> CASTCompressor<C> {
         }
         let raw_registry = self.skeletons_list.join(REG_SEPARATOR).into_bytes();
         let mut raw_ids = Vec::new();
-        let id_mode_flag;
+        let mut id_mode_flag;
         let total_rows = self.stream_template_ids.len() as u32;
-        if num_templates == 1 { id_mode_flag = 3; }
+        if num_templates == 1 { id_mode_flag = ID_MODE_SINGLE; }
         else if num_templates < 256 {
             id_mode_flag = 2;
             for &id in &self.stream_template_ids { raw_ids.push(id as u8); }
@@ -295,6 +433,12 @@ impl<C: NativeCompressor> CASTCompressor<C> {
             id_mode_flag = 0;
             for &id in &self.stream_template_ids { raw_ids.extend_from_slice(&(id as u16).to_le_bytes()); }
         }
+        if id_mode_flag != ID_MODE_SINGLE {
+            if let Some(rle) = encode_ids_rle(&self.stream_template_ids, id_width(id_mode_flag)) {
+                raw_ids = rle;
+                id_mode_flag |= FLAG_IDS_RLE;
+            }
+        }
         let row_sep = b"\x00"; let col_sep = b"\x02"; let esc_char = b"\x01";
         let esc_seq_esc = b"\x01\x01"; let esc_seq_sep = b"\x01\x00"; let esc_seq_col = b"\x01\x03";
         let mut vars_buffer = Vec::with_capacity(total_rows as usize * 50);
@@ -316,22 +460,20 @@ impl<C: NativeCompressor> CASTCompressor<C> {
             }
         }
         let len_reg = raw_registry.len() as u32;
-        let len_ids = if (id_mode_flag & 0x7F) == 3 {
-             let has_vars = if let Some(cols) = self.columns_storage.get(&self.stream_template_ids[0]) { !cols.is_empty() } else { false };
-             if has_vars { 0 } else { total_rows }
-        } else { raw_ids.len() as u32 };
+        let len_ids = raw_ids.len() as u32;
         let mut solid = Vec::new();
         solid.push(id_mode_flag);
         solid.extend_from_slice(&len_reg.to_le_bytes());
         solid.extend_from_slice(&len_ids.to_le_bytes());
+        if id_mode_flag == ID_MODE_SINGLE { solid.extend_from_slice(&total_rows.to_le_bytes()); }
         solid.extend_from_slice(&raw_registry);
         solid.extend_from_slice(&raw_ids);
         solid.extend_from_slice(&vars_buffer);
-        (self.backend.compress(&solid), 0)
+        Ok((self.backend.compress(&solid)?, 0))
     }
 
     // [MODIFICATO] Aggiunto parametro `on_progress` (closure)
-    pub fn compress_stream<R: Read, W: Write, F>(&mut self, input: R, mut output: W, mut on_progress: F) -> std::io::Result<(u64, u64)>
+    pub fn compress_stream<R: Read, W: Write, F>(&mut self, input: R, mut output: W, mut on_progress: F) -> Result<(u64, u64), CastError>
     where F: FnMut(usize, u64) // (Chunk Index, Total Bytes In)
     {
         let mut reader = BufReader::new(input);
@@ -343,9 +485,16 @@ impl<C: NativeCompressor> CASTCompressor<C> {
 
         let mut initial_buf = Vec::with_capacity(4096);
         let mut buf = [0u8; 4096];
-        let n = reader.read(&mut buf)?;
+        let mut n = reader.read(&mut buf)?;
 
         initial_buf.extend_from_slice(&buf[..n]);
+        let mut footer_flags = 0u8;
+        if initial_buf.starts_with(&UTF8_BOM) {
+            initial_buf.drain(..UTF8_BOM.len());
+            n -= UTF8_BOM.len();
+            total_in += UTF8_BOM.len() as u64;
+            footer_flags |= FOOTER_FLAG_INPUT_BOM;
+        }
         let is_binary = if n > 0 { is_likely_binary(&initial_buf) } else { false };
 
         if is_binary {
@@ -356,7 +505,7 @@ impl<C: NativeCompressor> CASTCompressor<C> {
                 chunk_counter += 1;
                 on_progress(chunk_counter, total_in);
 
-                let compressed = self.backend.compress(&initial_buf);
+                let compressed = self.backend.compress(&initial_buf)?;
                 output.write_all(&compressed)?;
                 total_out += compressed.len() as u64;
 
@@ -377,7 +526,7 @@ impl<C: NativeCompressor> CASTCompressor<C> {
                 chunk_counter += 1;
                 on_progress(chunk_counter, total_in);
 
-                let compressed = self.backend.compress(&chunk_buf[..n]);
+                let compressed = self.backend.compress(&chunk_buf[..n])?;
                 output.write_all(&compressed)?;
                 total_out += compressed.len() as u64;
 
@@ -405,6 +554,7 @@ impl<C: NativeCompressor> CASTCompressor<C> {
                 if bytes_read == 0 { break; }
 
                 total_in += bytes_read as u64;
+                self.bytes_in_current_block += bytes_read;
 
                 let line = line_buf.trim_end_matches(&['\r', '\n'][..]);
                 if line.is_empty() { continue; }
@@ -428,8 +578,8 @@ impl<C: NativeCompressor> CASTCompressor<C> {
                 for i in 0..limit { cols[i].push(vars_cache[i]); }
                 self.rows_in_current_block += 1;
 
-                if self.rows_in_current_block >= self.chunk_limit_rows {
-                    let (bytes, kind) = self.flush_current_block();
+                if self.block_is_full() {
+                    let (bytes, kind) = self.flush_current_block()?;
                     if !bytes.is_empty() {
                         output.write_all(&bytes)?;
                         total_out += bytes.len() as u64;
@@ -449,7 +599,7 @@ impl<C: NativeCompressor> CASTCompressor<C> {
                 }
             }
             if self.rows_in_current_block > 0 {
-                let (bytes, kind) = self.flush_current_block();
+                let (bytes, kind) = self.flush_current_block()?;
                 output.write_all(&bytes)?;
                 total_out += bytes.len() as u64;
 
@@ -471,6 +621,10 @@ impl<C: NativeCompressor> CASTCompressor<C> {
             footer_bytes.extend_from_slice(&rg.num_rows.to_le_bytes());
             footer_bytes.push(rg.kind);
         }
+        footer_bytes.extend_from_slice(&total_in.to_le_bytes());
+        footer_bytes.push(footer_flags);
+        let footer_crc = crc32fast::hash(&footer_bytes);
+        footer_bytes.extend_from_slice(&footer_crc.to_le_bytes());
         footer_bytes.extend_from_slice(&footer_start.to_le_bytes());
         footer_bytes.extend_from_slice(&FOOTER_MAGIC);
         output.write_all(&footer_bytes)?;
@@ -481,37 +635,139 @@ impl<C: NativeCompressor> CASTCompressor<C> {
 }
 
 // ============================================================================
-//  CAST DECOMPRESSOR
+//  FOOTER & BLOCK PARSING (Shared by Decompressor and Reader)
 // ============================================================================
 
-pub struct CASTDecompressor<D: NativeDecompressor> {
-    backend: D
+pub fn read_footer<R: Read + Seek>(input: &mut R) -> Result<Vec<RowGroupMetadata>, CastError> {
+    read_footer_info(input).map(|f| f.groups)
 }
 
-impl<D: NativeDecompressor> CASTDecompressor<D> {
-    pub fn new(backend: D) -> Self { Self { backend } }
+/// Whole-archive metadata stored in the footer.
+pub struct FooterInfo {
+    pub groups: Vec<RowGroupMetadata>,
+    /// FOOTER_FLAG_* bits (always 0 in footer version 0x01)
+    pub flags: u8,
+    /// Total bytes of the original input; `None` in footer version 0x01
+    pub original_size: Option<u64>,
+}
 
-    fn decompress_block_blob<W: Write>(&self, data: &[u8], writer: &mut W, current_global_idx: u64, target_rows: Option<(u64, u64)>) -> Result<(), String> {
-        let decompressed = self.backend.decompress(data);
-        if decompressed.is_empty() { return Ok(()); }
-        if decompressed.len() < 9 { return Err("Block too short".to_string()); }
+/// Like `read_footer`, also returning the archive-level fields of the footer.
+pub fn read_footer_info<R: Read + Seek>(input: &mut R) -> Result<FooterInfo, CastError> {
+    let file_len = input.seek(SeekFrom::End(0))?;
+    if file_len < 13 { return Err(CastError::NotACastArchive); }
+    input.seek(SeekFrom::End(-13))?;
+    let mut footer_tail = [0u8; 13];
+    input.read_exact(&mut footer_tail)?;
+    if footer_tail[8..12] != FOOTER_MAGIC[0..4] { return Err(CastError::NotACastArchive); }
+    if !(MIN_FOOTER_VERSION..=FOOTER_MAGIC[4]).contains(&footer_tail[12]) { return Err(CastError::UnsupportedFormat { version: footer_tail[12] }); }
+
+    let version = footer_tail[12];
+    let tail_len: u64 = if version == MIN_FOOTER_VERSION { 13 } else { 26 };
+    if file_len < tail_len { return Err(CastError::NotACastArchive); }
+    // Fields between the group entries and the footer offset: [original size u64] [flags u8] [crc u32]
+    let mut extra = [0u8; 13];
+    let extra = &mut extra[..tail_len as usize - 13];
+    if !extra.is_empty() {
+        input.seek(SeekFrom::End(-(tail_len as i64)))?;
+        input.read_exact(extra)?;
+    }
+    let (fields, stored_crc) = if extra.is_empty() {
+        (&extra[..], None)
+    } else {
+        (&extra[..9], Some(u32::from_le_bytes(extra[9..13].try_into().unwrap())))
+    };
+    let flags = fields.last().copied().unwrap_or(0);
+    let original_size = (fields.len() == 9).then(|| u64::from_le_bytes(fields[0..8].try_into().unwrap()));
+
+    let footer_offset = u64::from_le_bytes(footer_tail[0..8].try_into().unwrap());
+    if footer_offset > file_len - tail_len { return Err(CastError::CorruptFooter { detail: "Footer offset out of range".to_string() }); }
+    input.seek(SeekFrom::Start(footer_offset))?;
+
+    let mut count_buf = [0u8; 4];
+    if input.read_exact(&mut count_buf).is_err() { return Err(CastError::CorruptFooter { detail: "Empty Footer".to_string() }); }
+    let num_groups = u32::from_le_bytes(count_buf);
+    let entries_len = num_groups as u64 * GROUP_ENTRY_LEN as u64;
+    let room = (file_len - tail_len - footer_offset).saturating_sub(4);
+    if entries_len > room { return Err(CastError::CorruptFooter { detail: "Group count exceeds footer size".to_string() }); }
+    // Checksummed footers have nothing between the entries and the tail
+    if stored_crc.is_some() && entries_len != room { return Err(CastError::CorruptFooter { detail: "Group count does not match footer size".to_string() }); }
+
+    let mut entries = vec![0u8; entries_len as usize];
+    input.read_exact(&mut entries).map_err(|_| CastError::CorruptFooter { detail: "Read group meta failed".to_string() })?;
+    if let Some(expected) = stored_crc {
+        let mut hasher = Hasher::new();
+        hasher.update(&count_buf);
+        hasher.update(&entries);
+        hasher.update(fields);
+        if hasher.finalize() != expected { return Err(CastError::CorruptFooter { detail: "Footer checksum mismatch (torn or damaged footer)".to_string() }); }
+    }
+
+    let groups = entries.chunks_exact(GROUP_ENTRY_LEN).map(|entry| RowGroupMetadata {
+        start_offset: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+        compressed_size: u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+        num_rows: u64::from_le_bytes(entry[16..24].try_into().unwrap()),
+        kind: entry[24],
+    }).collect();
+    Ok(FooterInfo { groups, flags, original_size })
+}
+
+fn read_group_bytes<R: Read + Seek>(input: &mut R, group: &RowGroupMetadata) -> Result<Vec<u8>, CastError> {
+    input.seek(SeekFrom::Start(group.start_offset))?;
+    let mut handle = input.by_ref().take(group.compressed_size);
+    let mut buffer = Vec::with_capacity(group.compressed_size as usize);
+    handle.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+// Decoded view over a decompressed row group: skeleton parts, per-template column queues and row order.
+struct DecodedBlock<'a> {
+    skeletons: Vec<&'a str>,
+    skel_parts: Vec<Vec<&'a str>>,
+    columns_storage: Vec<Vec<VecDeque<(usize, usize)>>>,
+    template_ids: Vec<usize>,
+    /// Row count of a single-template block (which has no id stream to materialize)
+    single_rows: Option<usize>,
+    vars_data: &'a [u8],
+}
+
+impl<'a> DecodedBlock<'a> {
+    /// `rows` is the row count the footer records for the group: an id stream holding more is
+    /// corrupt.
+    fn parse(decompressed: &'a [u8], chunk: usize, rows: u64) -> Result<Self, CastError> {
+        let corrupt = |detail: &str| CastError::CorruptHeader { chunk, detail: detail.to_string() };
+        if decompressed.len() < 9 { return Err(corrupt("Block too short")); }
 
         let id_mode_flag = decompressed[0];
+        let flag_val = id_mode_flag & ID_MODE_MASK;
         let mut cursor = 1;
         let len_reg = u32::from_le_bytes(decompressed[cursor..cursor+4].try_into().unwrap()) as usize; cursor += 4;
-        let len_ids = u32::from_le_bytes(decompressed[cursor..cursor+4].try_into().unwrap()) as usize; cursor += 4;
-        if cursor + len_reg + len_ids > decompressed.len() { return Err("Corrupted Block Header".to_string()); }
+        let mut len_ids = u32::from_le_bytes(decompressed[cursor..cursor+4].try_into().unwrap()) as usize; cursor += 4;
+        // Single-template blocks have no id stream, only a row count
+        let mut stored_rows = 0;
+        if flag_val == ID_MODE_SINGLE_LEGACY {
+            stored_rows = len_ids as u32;
+            len_ids = 0;
+        } else if flag_val == ID_MODE_SINGLE {
+            let rows = decompressed.get(cursor..cursor + 4).ok_or_else(|| corrupt("Block too short"))?;
+            stored_rows = u32::from_le_bytes(rows.try_into().unwrap());
+            cursor += 4;
+        }
+        if cursor + len_reg + len_ids > decompressed.len() { return Err(CastError::LengthOverflow { chunk, detail: "Segment lengths exceed block size".to_string() }); }
 
         let reg_data = &decompressed[cursor .. cursor+len_reg]; cursor += len_reg;
         let ids_data = &decompressed[cursor .. cursor+len_ids]; cursor += len_ids;
         let vars_data = &decompressed[cursor..];
 
-        let reg_str = std::str::from_utf8(reg_data).map_err(|_| "Registry not UTF-8")?;
+        let reg_str = std::str::from_utf8(reg_data).map_err(|_| corrupt("Registry not UTF-8"))?;
         let skeletons: Vec<&str> = reg_str.split(REG_SEPARATOR).collect();
 
-        let mut template_ids = Vec::with_capacity(len_ids);
-        let flag_val = id_mode_flag & 0x7F;
-        if flag_val == 3 { }
+        let single_template = flag_val == ID_MODE_SINGLE_LEGACY || flag_val == ID_MODE_SINGLE;
+        // len_ids counts bytes: one id per id_width bytes in a raw stream (RLE streams grow it)
+        let mut template_ids = Vec::with_capacity(if single_template { 0 } else { len_ids / id_width(flag_val) });
+        if single_template { }
+        else if (id_mode_flag & FLAG_IDS_RLE) != 0 {
+            decode_ids_rle(ids_data, id_width(flag_val), rows, &mut template_ids).ok_or_else(|| corrupt("RLE id stream"))?;
+        }
         else if flag_val == 2 { for &b in ids_data { template_ids.push(b as usize); } }
         else if flag_val == 1 { for ch in ids_data.chunks_exact(4) { template_ids.push(u32::from_le_bytes(ch.try_into().unwrap()) as usize); } }
         else { for ch in ids_data.chunks_exact(2) { template_ids.push(u16::from_le_bytes(ch.try_into().unwrap()) as usize); } }
@@ -545,106 +801,405 @@ impl<D: NativeDecompressor> CASTDecompressor<D> {
             }
         }
 
+        let single_rows = single_template.then(|| single_template_rows(flag_val, stored_rows, columns_storage.first().and_then(|c| c.first())));
+
         let skel_parts: Vec<Vec<&str>> = skeletons.iter().map(|s| s.split(VAR_PLACEHOLDER_STR).collect()).collect();
-        let count_flag3 = if flag_val == 3 {
-            if !columns_storage.is_empty() && !columns_storage[0].is_empty() { columns_storage[0][0].len() } else { 0 }
-        } else { 0 };
-
-        let mut write_stream = |slice: &[u8]| { writer.write_all(slice).map_err(|e| e.to_string()) };
-
-        let mut reconstruct = |t_id: usize, should_write: bool| -> Result<(), String> {
-            if t_id >= skel_parts.len() { return Ok(()); }
-            let parts = &skel_parts[t_id];
-            let queues = &mut columns_storage[t_id];
-
-            for (idx, part) in parts.iter().enumerate() {
-                if should_write { write_stream(part.as_bytes())?; }
-
-                if idx < queues.len() {
-                    if let Some((s, e)) = queues[idx].pop_front() {
-                        if should_write {
-                            let slice = &vars_data[s..e];
-                            let mut k = 0;
-                            while k < slice.len() {
-                                if slice[k] == 0x01 && k+1 < slice.len() {
-                                    let nb = slice[k+1];
-                                    let b = if nb == 0x01 { 0x01 } else if nb == 0x00 { 0x00 } else { 0x02 };
-                                    write_stream(&[b])?; k += 2;
-                                } else { write_stream(&[slice[k]])?; k += 1; }
-                            }
+        Ok(Self { skeletons, skel_parts, columns_storage, template_ids, single_rows, vars_data })
+    }
+
+    /// Template id of every row, in order (moved out so rows can be rebuilt while iterating).
+    fn take_row_ids(&mut self) -> Box<dyn Iterator<Item = usize>> {
+        match self.single_rows {
+            Some(rows) => Box::new(std::iter::repeat_n(0, rows)),
+            None => Box::new(std::mem::take(&mut self.template_ids).into_iter()),
+        }
+    }
+
+    // Consumes the next cell of every column of `t_id`; bytes are emitted only when `should_write` is set.
+    fn reconstruct<F: FnMut(&[u8]) -> Result<(), CastError>>(&mut self, t_id: usize, should_write: bool, write_stream: &mut F) -> Result<(), CastError> {
+        if t_id >= self.skel_parts.len() { return Ok(()); }
+        let parts = &self.skel_parts[t_id];
+        let queues = &mut self.columns_storage[t_id];
+
+        for (idx, part) in parts.iter().enumerate() {
+            if should_write { write_stream(part.as_bytes())?; }
+
+            if idx < queues.len() {
+                if let Some((s, e)) = queues[idx].pop_front() {
+                    if should_write {
+                        let slice = &self.vars_data[s..e];
+                        let mut k = 0;
+                        while k < slice.len() {
+                            if slice[k] == 0x01 && k+1 < slice.len() {
+                                let nb = slice[k+1];
+                                let b = if nb == 0x01 { 0x01 } else if nb == 0x00 { 0x00 } else { 0x02 };
+                                write_stream(&[b])?; k += 2;
+                            } else { write_stream(&[slice[k]])?; k += 1; }
                         }
                     }
                 }
             }
-            if should_write { write_stream(b"\n")?; }
-            Ok(())
-        };
+        }
+        if should_write { write_stream(b"\n")?; }
+        Ok(())
+    }
+}
+
+// ============================================================================
+//  CAST DECOMPRESSOR
+// ============================================================================
+
+/// Output budget for previews: stop after N rows or N bytes. The remaining budget is
+/// decremented in place, so one limit can span several row groups.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputLimit {
+    Rows(u64),
+    Bytes(u64),
+}
+
+impl OutputLimit {
+    pub fn is_exhausted(&self) -> bool {
+        matches!(self, OutputLimit::Rows(0) | OutputLimit::Bytes(0))
+    }
+
+    /// Bytes of a `len`-byte write that fit in the budget (rows are not counted here).
+    pub fn allow_bytes(&mut self, len: usize) -> usize {
+        match self {
+            OutputLimit::Bytes(left) => {
+                let n = (*left).min(len as u64);
+                *left -= n;
+                n as usize
+            },
+            OutputLimit::Rows(_) => len,
+        }
+    }
+
+    /// Counts a row once its newline has been emitted.
+    pub fn finish_row(&mut self, row: &[u8]) {
+        if let OutputLimit::Rows(left) = self {
+            if row.last() == Some(&b'\n') { *left = left.saturating_sub(1); }
+        }
+    }
+
+    /// Length of the prefix of raw (passthrough) data that fits in the budget.
+    pub fn cut(&mut self, data: &[u8]) -> usize {
+        match self {
+            OutputLimit::Rows(left) => {
+                let mut end = 0;
+                while *left > 0 && end < data.len() {
+                    match data[end..].iter().position(|&b| b == b'\n') {
+                        Some(p) => { end += p + 1; *left -= 1; },
+                        None => end = data.len(),
+                    }
+                }
+                end
+            },
+            OutputLimit::Bytes(_) => self.allow_bytes(data.len()),
+        }
+    }
+}
+
+/// Set of row indices (0-based) as sorted, merged, inclusive intervals. Used to select the
+/// rows of a partial restore: `--rows` gives one interval, `--exclude-rows` removes some.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RangeSet {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl RangeSet {
+    /// Every row.
+    pub fn all() -> Self {
+        Self { ranges: vec![(0, u64::MAX)] }
+    }
+
+    /// The union of the inclusive intervals `ranges` (reversed ones are ignored).
+    pub fn from_ranges<I: IntoIterator<Item = (u64, u64)>>(ranges: I) -> Self {
+        let mut sorted: Vec<(u64, u64)> = ranges.into_iter().filter(|(s, e)| s <= e).collect();
+        sorted.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(sorted.len());
+        for (start, end) in sorted {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Self { ranges: merged }
+    }
+
+    /// Rows in `self` but not in `other`.
+    pub fn difference(&self, other: &RangeSet) -> RangeSet {
+        let mut out = Vec::new();
+        for &(first, last) in &self.ranges {
+            let mut start = Some(first);
+            let from = other.ranges.partition_point(|r| r.1 < first);
+            for &(cut_start, cut_end) in &other.ranges[from..] {
+                let Some(s) = start else { break };
+                if cut_start > last { break; }
+                if cut_start > s { out.push((s, cut_start - 1)); }
+                start = if cut_end >= last { None } else { Some(cut_end + 1) };
+            }
+            if let Some(s) = start { out.push((s, last)); }
+        }
+        RangeSet { ranges: out }
+    }
+
+    pub fn is_empty(&self) -> bool { self.ranges.is_empty() }
+
+    pub fn intervals(&self) -> &[(u64, u64)] { &self.ranges }
+
+    /// Last row of the set, if any.
+    pub fn last(&self) -> Option<u64> { self.ranges.last().map(|r| r.1) }
+
+    pub fn contains(&self, row: u64) -> bool {
+        let i = self.ranges.partition_point(|r| r.1 < row);
+        self.ranges.get(i).is_some_and(|r| r.0 <= row)
+    }
+
+    /// Whether any row of `start..=end` is in the set.
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        let i = self.ranges.partition_point(|r| r.1 < start);
+        self.ranges.get(i).is_some_and(|r| r.0 <= end)
+    }
+}
+
+pub struct CASTDecompressor<D: NativeDecompressor> {
+    backend: D
+}
+
+impl<D: NativeDecompressor> CASTDecompressor<D> {
+    pub fn new(backend: D) -> Self { Self { backend } }
 
-        let mut local_row_counter = 0;
-        let mut process_row = |id: usize| -> Result<(), String> {
-            let actual_idx = current_global_idx + local_row_counter;
-            let write_this = if let Some((start, end)) = target_rows {
-                actual_idx >= start && actual_idx <= end
+    #[allow(clippy::too_many_arguments)]
+    fn decompress_block_blob<W: Write>(&self, data: &[u8], writer: &mut W, chunk: usize, rows: u64, current_global_idx: u64, target_rows: Option<&RangeSet>, limit: &mut Option<OutputLimit>) -> Result<(), CastError> {
+        let decompressed = self.backend.decompress(data)?;
+        if decompressed.is_empty() { return Ok(()); }
+        let mut block = DecodedBlock::parse(&decompressed, chunk, rows)?;
+
+        let row_ids = block.take_row_ids();
+        let mut row_buf: Vec<u8> = Vec::new();
+
+        for (local_row_counter, id) in row_ids.enumerate() {
+            let actual_idx = current_global_idx + local_row_counter as u64;
+            let write_this = if let Some(rows) = target_rows {
+                if rows.last().is_none_or(|last| actual_idx > last) { break; }
+                rows.contains(actual_idx)
             } else { true };
-            reconstruct(id, write_this)?;
-            local_row_counter += 1;
-            Ok(())
-        };
 
-        if flag_val == 3 { for _ in 0..count_flag3 { process_row(0)?; } }
-        else { for &id in &template_ids { process_row(id)?; } }
+            match limit.as_mut() {
+                None => {
+                    let mut write_stream = |slice: &[u8]| -> Result<(), CastError> { writer.write_all(slice)?; Ok(()) };
+                    block.reconstruct(id, write_this, &mut write_stream)?;
+                },
+                Some(l) => {
+                    if write_this && l.is_exhausted() { break; }
+                    row_buf.clear();
+                    block.reconstruct(id, write_this, &mut |slice: &[u8]| { row_buf.extend_from_slice(slice); Ok(()) })?;
+                    let n = l.allow_bytes(row_buf.len());
+                    l.finish_row(&row_buf[..n]);
+                    writer.write_all(&row_buf[..n])?;
+                },
+            }
+        }
 
         Ok(())
     }
 
-    pub fn decompress_stream<R: Read + Seek, W: Write>(&self, mut input: R, mut output: W, target_rows: Option<(u64, u64)>) -> Result<(), String> {
-        input.seek(SeekFrom::End(-13)).map_err(|_| "Seek failed")?;
-        let mut footer_tail = [0u8; 13];
-        input.read_exact(&mut footer_tail).map_err(|_| "Read footer tail failed")?;
-        if &footer_tail[8..13] != &FOOTER_MAGIC { return Err("Invalid CAST file (Missing Magic Footer)".to_string()); }
-
-        let footer_offset = u64::from_le_bytes(footer_tail[0..8].try_into().unwrap());
-        input.seek(SeekFrom::Start(footer_offset)).map_err(|_| "Seek footer failed")?;
-
-        let mut count_buf = [0u8; 4];
-        if input.read_exact(&mut count_buf).is_err() { return Err("Empty Footer".to_string()); }
-        let num_groups = u32::from_le_bytes(count_buf);
-
-        let mut groups = Vec::with_capacity(num_groups as usize);
-        let mut entry_buf = [0u8; 25];
-        for _ in 0..num_groups {
-            input.read_exact(&mut entry_buf).map_err(|_| "Read group meta failed")?;
-            groups.push(RowGroupMetadata {
-                start_offset: u64::from_le_bytes(entry_buf[0..8].try_into().unwrap()),
-                compressed_size: u64::from_le_bytes(entry_buf[8..16].try_into().unwrap()),
-                num_rows: u64::from_le_bytes(entry_buf[16..24].try_into().unwrap()),
-                kind: entry_buf[24],
-            });
+    /// Restores the archive, or only the rows in `target_rows` (0-based).
+    pub fn decompress_stream<R: Read + Seek, W: Write>(&self, input: R, output: W, target_rows: Option<&RangeSet>) -> Result<(), CastError> {
+        self.decompress_stream_limited(input, output, target_rows, None)
+    }
+
+    /// Like `decompress_stream`, but stops once `limit` rows/bytes have been written.
+    /// Row groups past the limit are never read or decompressed.
+    pub fn decompress_stream_limited<R: Read + Seek, W: Write>(&self, mut input: R, mut output: W, target_rows: Option<&RangeSet>, mut limit: Option<OutputLimit>) -> Result<(), CastError> {
+        let FooterInfo { groups, flags, .. } = read_footer_info(&mut input)?;
+
+        // The BOM belongs to the start of the stream: only restores that begin at row 0 get it
+        if flags & FOOTER_FLAG_INPUT_BOM != 0 && target_rows.is_none_or(|rows| rows.contains(0)) {
+            let end = match limit.as_mut() { Some(l) => l.cut(&UTF8_BOM), None => UTF8_BOM.len() };
+            output.write_all(&UTF8_BOM[..end])?;
         }
 
         let mut current_row_start = 0u64;
-        for group in groups {
+        for (g_idx, group) in groups.into_iter().enumerate() {
+            if limit.is_some_and(|l| l.is_exhausted()) { break; }
             let group_rows = group.num_rows;
             let group_end_row = current_row_start + group_rows;
-            let should_process = if let Some((req_start, req_end)) = target_rows {
-                if group_rows > 0 { group_end_row > req_start && current_row_start <= req_end } else { false }
+            // Groups without a selected row are skipped without being read
+            let should_process = if let Some(rows) = target_rows {
+                group_rows > 0 && rows.overlaps(current_row_start, group_end_row - 1)
             } else { true };
 
             if should_process {
-                input.seek(SeekFrom::Start(group.start_offset)).map_err(|_| "Seek group failed")?;
-                let mut handle = input.by_ref().take(group.compressed_size);
-                let mut buffer = Vec::with_capacity(group.compressed_size as usize);
-                handle.read_to_end(&mut buffer).map_err(|_| "Read block failed")?;
+                let buffer = read_group_bytes(&mut input, &group)?;
 
                 if group.kind == 1 {
-                    let raw = self.backend.decompress(&buffer);
-                    output.write_all(&raw).map_err(|e| e.to_string())?;
+                    let raw = self.backend.decompress(&buffer)?;
+                    let end = match limit.as_mut() { Some(l) => l.cut(&raw), None => raw.len() };
+                    output.write_all(&raw[..end])?;
                 } else {
-                    self.decompress_block_blob(&buffer, &mut output, current_row_start, target_rows)?;
+                    self.decompress_block_blob(&buffer, &mut output, g_idx + 1, group_rows, current_row_start, target_rows, &mut limit)?;
                 }
             }
             current_row_start += group_rows;
         }
         Ok(())
     }
+
+    /// Decodes every row group independently on up to `jobs` threads, hashing the output.
+    /// Each worker opens its own reader through `reader_factory` so seeks never contend.
+    /// Per-group failures are reported in the results; only footer/open errors abort.
+    pub fn verify_groups_parallel<R, F>(&self, reader_factory: F, jobs: usize) -> Result<Vec<GroupVerification>, CastError>
+    where D: Sync, R: Read + Seek, F: Fn() -> io::Result<R> + Sync {
+        let groups = read_footer(&mut reader_factory()?)?;
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<GroupVerification>> = Mutex::new(Vec::with_capacity(groups.len()));
+        let workers = jobs.clamp(1, groups.len().max(1));
+
+        std::thread::scope(|scope| -> Result<(), CastError> {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(|| -> Result<(), CastError> {
+                let mut input = reader_factory()?;
+                loop {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(group) = groups.get(idx) else { return Ok(()); };
+                    let mut sink = HashingSink::new();
+                    let result = read_group_bytes(&mut input, group).and_then(|buffer| {
+                        if group.kind == 1 {
+                            sink.write_all(&self.backend.decompress(&buffer)?)?;
+                            Ok(())
+                        } else {
+                            self.decompress_block_blob(&buffer, &mut sink, idx + 1, group.num_rows, 0, None, &mut None)
+                        }
+                    });
+                    results.lock().unwrap().push(GroupVerification { index: idx, rows: group.num_rows, bytes: sink.bytes(), crc: sink.crc(), result });
+                }
+            })).collect();
+            for h in handles { h.join().map_err(|_| io::Error::other("verification worker panicked"))??; }
+            Ok(())
+        })?;
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|r| r.index);
+        Ok(results)
+    }
+}
+
+/// Outcome of decoding one row group during `verify_groups_parallel`.
+pub struct GroupVerification {
+    pub index: usize,
+    pub rows: u64,
+    pub bytes: u64,
+    pub crc: u32,
+    pub result: Result<(), CastError>,
+}
+
+/// Write sink that only counts and CRC32-hashes what it receives.
+pub struct HashingSink {
+    hasher: Hasher,
+    bytes: u64,
+}
+
+impl HashingSink {
+    pub fn new() -> Self { Self { hasher: Hasher::new(), bytes: 0 } }
+    pub fn bytes(&self) -> u64 { self.bytes }
+    pub fn crc(&self) -> u32 { self.hasher.clone().finalize() }
+}
+
+impl Default for HashingSink {
+    fn default() -> Self { Self::new() }
+}
+
+impl Write for HashingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.bytes += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+// ============================================================================
+//  CAST READER (Archive Inspection)
+// ============================================================================
+
+const TEMPLATE_SAMPLE_ROWS: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct TemplateInfo {
+    pub id: usize,
+    /// Skeleton with variables marked by the internal placeholder (see `render_skeleton`).
+    pub skeleton: String,
+    /// Rows using this template inside the sampled row group (counts are not stored in the footer).
+    pub occurrences: u64,
+    /// First reconstructed rows of this template, without the trailing newline.
+    pub samples: Vec<String>,
+}
+
+pub struct CastReader<R: Read + Seek, D: NativeDecompressor> {
+    input: R,
+    backend: D,
+    groups: Vec<RowGroupMetadata>,
+    original_size: Option<u64>,
+}
+
+impl<R: Read + Seek, D: NativeDecompressor> CastReader<R, D> {
+    /// Reads only the footer index; row groups are decompressed on demand.
+    pub fn open(mut input: R, backend: D) -> Result<Self, CastError> {
+        let footer = read_footer_info(&mut input)?;
+        Ok(Self { input, backend, groups: footer.groups, original_size: footer.original_size })
+    }
+
+    pub fn row_groups(&self) -> &[RowGroupMetadata] { &self.groups }
+
+    /// Total bytes of the original input, if the archive records it (footer version 0x02).
+    pub fn original_size(&self) -> Option<u64> { self.original_size }
+
+    pub fn total_rows(&self) -> u64 { self.groups.iter().map(|g| g.num_rows).sum() }
+
+    /// Templates of the first columnar row group, with occurrence counts and a few sample rows.
+    /// Only that group is read and decompressed, so previewing a huge archive stays cheap.
+    pub fn templates(&mut self) -> Result<impl Iterator<Item = TemplateInfo>, CastError> {
+        let (g_idx, first) = match self.groups.iter().enumerate().find(|(_, g)| g.kind == 0 && g.num_rows > 0) {
+            Some((i, g)) => (i, g.clone()),
+            None => return Ok(Vec::new().into_iter()),
+        };
+        let buffer = read_group_bytes(&mut self.input, &first)?;
+        let decompressed = self.backend.decompress(&buffer)?;
+        if decompressed.is_empty() { return Ok(Vec::new().into_iter()); }
+        let mut block = DecodedBlock::parse(&decompressed, g_idx + 1, first.num_rows)?;
+
+        let mut infos: Vec<TemplateInfo> = block.skeletons.iter().enumerate()
+            .map(|(id, s)| TemplateInfo { id, skeleton: s.to_string(), occurrences: 0, samples: Vec::new() })
+            .collect();
+
+        let row_ids = block.take_row_ids();
+        let mut row_buf = Vec::new();
+        for id in row_ids {
+            let Some(info) = infos.get_mut(id) else { continue; };
+            info.occurrences += 1;
+            let wants_sample = info.samples.len() < TEMPLATE_SAMPLE_ROWS;
+            row_buf.clear();
+            block.reconstruct(id, wants_sample, &mut |slice: &[u8]| { row_buf.extend_from_slice(slice); Ok(()) })?;
+            if wants_sample {
+                if row_buf.last() == Some(&b'\n') { row_buf.pop(); }
+                info.samples.push(String::from_utf8_lossy(&row_buf).into_owned());
+            }
+        }
+        Ok(infos.into_iter())
+    }
+}
+
+/// Human-readable skeleton: placeholders shown as `⟨*⟩`, control characters escaped.
+pub fn render_skeleton(skeleton: &str) -> String {
+    let mut out = String::with_capacity(skeleton.len());
+    for c in skeleton.chars() {
+        match c {
+            VAR_PLACEHOLDER => out.push_str("⟨*⟩"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\x{:02X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
 }
\ No newline at end of file
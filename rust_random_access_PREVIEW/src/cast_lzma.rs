@@ -8,7 +8,8 @@ use xz2::read::XzDecoder;
 use xz2::write::XzEncoder;
 use xz2::stream::{Stream, MtStreamBuilder, Check, LzmaOptions, Filters};
 
-use crate::cast::{NativeCompressor, NativeDecompressor, CASTCompressor, CASTDecompressor};
+use crate::error::CastError;
+use crate::cast::{NativeCompressor, NativeDecompressor, CASTCompressor, CASTDecompressor, CastReader};
 
 const LZMA_PRESET_EXTREME: u32 = 0x80000000;
 
@@ -87,9 +88,9 @@ impl LzmaBackend {
 }
 
 impl NativeCompressor for LzmaBackend {
-    fn compress(&self, data: &[u8]) -> Vec<u8> {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
         // EXACT LOGIC FROM ORIGINAL compress_buffer_native
-        if data.is_empty() { return Vec::new(); }
+        if data.is_empty() { return Ok(Vec::new()); }
 
         let effective_multithread = if self.multithread && (data.len() as u32) < self.dict_size {
             false
@@ -97,7 +98,7 @@ impl NativeCompressor for LzmaBackend {
             self.multithread
         };
 
-        let mut opts = LzmaOptions::new_preset(9 | LZMA_PRESET_EXTREME).unwrap();
+        let mut opts = LzmaOptions::new_preset(9 | LZMA_PRESET_EXTREME).map_err(native_failure)?;
         opts.dict_size(self.dict_size); // Uses the passed dictionary size
 
         let mut filters = Filters::new();
@@ -108,41 +109,41 @@ impl NativeCompressor for LzmaBackend {
         let output_buffer = Vec::with_capacity(safe_capacity);
         let writer = std::io::BufWriter::new(output_buffer);
 
-        if !effective_multithread {
-            let stream = Stream::new_stream_encoder(&filters, Check::Crc32).expect("LZMA Init Error");
-            let mut compressor = XzEncoder::new_stream(writer, stream);
-            compressor.write_all(data).expect("LZMA Write Error");
-            let finished = compressor.finish().expect("LZMA Finish Error");
-            return finished.into_inner().expect("Buffer extraction error");
-        }
-
-        let threads = num_cpus::get() as u32;
-        let stream = MtStreamBuilder::new()
-            .threads(threads)
-            .filters(filters)
-            .check(Check::Crc32)
-            .encoder()
-            .expect("LZMA MT Init Error");
+        let stream = if !effective_multithread {
+            Stream::new_stream_encoder(&filters, Check::Crc32).map_err(native_failure)?
+        } else {
+            let threads = num_cpus::get() as u32;
+            MtStreamBuilder::new()
+                .threads(threads)
+                .filters(filters)
+                .check(Check::Crc32)
+                .encoder()
+                .map_err(native_failure)?
+        };
 
         let mut compressor = XzEncoder::new_stream(writer, stream);
-        compressor.write_all(data).expect("LZMA MT Write Error");
-        let finished = compressor.finish().expect("LZMA MT Finish Error");
-        finished.into_inner().expect("Buffer extraction error")
+        compressor.write_all(data).map_err(native_failure)?;
+        let finished = compressor.finish().map_err(native_failure)?;
+        finished.into_inner().map_err(|e| native_failure(e.error()))
     }
 }
 
 pub struct LzmaDecompressorBackend;
 
 impl NativeDecompressor for LzmaDecompressorBackend {
-    fn decompress(&self, data: &[u8]) -> Vec<u8> {
-        if data.is_empty() { return Vec::new(); }
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        if data.is_empty() { return Ok(Vec::new()); }
         let mut decompressor = XzDecoder::new(data);
         let mut output = Vec::with_capacity(data.len() * 3);
-        decompressor.read_to_end(&mut output).expect("Decompression Error");
-        output
+        decompressor.read_to_end(&mut output).map_err(native_failure)?;
+        Ok(output)
     }
 }
 
+fn native_failure<E: std::fmt::Display>(e: E) -> CastError {
+    CastError::BackendFailure { backend: "Native (xz2)", detail: e.to_string() }
+}
+
 
 // ============================================================================
 //  BACKEND 2: 7-ZIP (External Executable)
@@ -159,86 +160,64 @@ impl SevenZipBackend {
 }
 
 impl NativeCompressor for SevenZipBackend {
-    fn compress(&self, data: &[u8]) -> Vec<u8> {
-        if data.is_empty() { return Vec::new(); }
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        if data.is_empty() { return Ok(Vec::new()); }
 
         let dict_arg = format!("-m0=lzma2:d{}b", self.dict_size);
-        let cmd = get_7z_cmd();
-
         // PIPE MODE: -si (stdin), -so (stdout), -an (no name)
-        let mut child = Command::new(&cmd)
-            .args(&["a", "-txz", "-mx=9", "-mmt=on", &dict_arg, "-si", "-so", "-an", "-y", "-bb0"])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .expect("Failed to spawn 7-Zip");
-
-        let input_data = data.to_vec();
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
-
-        thread::spawn(move || {
-            stdin.write_all(&input_data).ok();
-        });
-
-        let mut output_data = Vec::new();
-        if let Some(mut stdout) = child.stdout.take() {
-            stdout.read_to_end(&mut output_data).expect("Failed to read 7z stdout");
-        }
-
-        let status = child.wait().expect("Failed to wait on 7z");
-
-        if !status.success() {
-            panic!("7-Zip Compression Error");
-        }
-
-        output_data
+        run_7z_pipe(&["a", "-txz", "-mx=9", "-mmt=on", &dict_arg, "-si", "-so", "-an", "-y", "-bb0"], data, 0)
     }
 }
 
 pub struct SevenZipDecompressorBackend;
 
 impl NativeDecompressor for SevenZipDecompressorBackend {
-    fn decompress(&self, data: &[u8]) -> Vec<u8> {
-        if data.is_empty() { return Vec::new(); }
-
-        let cmd = get_7z_cmd();
-
-        let mut child = Command::new(&cmd)
-            .args(&["e", "-txz", "-si", "-so", "-y", "-bb0"])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .expect("Failed to spawn 7-Zip");
-
-        let input_data = data.to_vec();
-        let mut stdin = child.stdin.take().expect("Failed to open stdin");
-
-        thread::spawn(move || {
-            stdin.write_all(&input_data).ok();
-        });
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
+        if data.is_empty() { return Ok(Vec::new()); }
 
         let estimated = data.len().saturating_mul(6);
         let safe_capacity = std::cmp::min(estimated, 2 * 1024 * 1024 * 1024); // Cap 2GB
-        let mut output_data = Vec::with_capacity(safe_capacity);
+        run_7z_pipe(&["e", "-txz", "-si", "-so", "-y", "-bb0"], data, safe_capacity)
+    }
+}
 
-        if let Some(mut stdout) = child.stdout.take() {
-            if let Err(e) = stdout.read_to_end(&mut output_data) {
-                eprintln!("Error reading 7z output: {}", e);
-                return Vec::new();
-            }
-        }
+fn run_7z_pipe(args: &[&str], data: &[u8], capacity: usize) -> Result<Vec<u8>, CastError> {
+    let failure = |detail: String| CastError::BackendFailure { backend: "7-Zip", detail };
+    let cmd = get_7z_cmd();
 
-        let status = child.wait().expect("Failed to wait on 7z");
+    let mut child = Command::new(&cmd)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| failure(format!("Failed to spawn '{}': {}", cmd, e)))?;
+
+    let input_data = data.to_vec();
+    let Some(mut stdin) = child.stdin.take() else {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(failure("Failed to open 7z stdin".to_string()));
+    };
 
-        if status.success() {
-            output_data
-        } else {
-            eprintln!("\n[!] CRITICAL ERROR: 7-Zip backend returned a failure status.");
-            std::process::exit(1);
+    thread::spawn(move || {
+        stdin.write_all(&input_data).ok();
+    });
+
+    let mut output_data = Vec::with_capacity(capacity);
+    if let Some(mut stdout) = child.stdout.take() {
+        if let Err(e) = stdout.read_to_end(&mut output_data) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(failure(format!("Error reading 7z output: {}", e)));
         }
     }
+
+    let status = child.wait().map_err(|e| failure(format!("Failed to wait on 7z: {}", e)))?;
+    if !status.success() {
+        return Err(failure(format!("Process returned failure status ({})", status)));
+    }
+    Ok(output_data)
 }
 
 
@@ -252,7 +231,7 @@ pub enum RuntimeLzmaCompressor {
 }
 
 impl NativeCompressor for RuntimeLzmaCompressor {
-    fn compress(&self, data: &[u8]) -> Vec<u8> {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
         match self {
             RuntimeLzmaCompressor::Native(b) => b.compress(data),
             RuntimeLzmaCompressor::SevenZip(b) => b.compress(data),
@@ -266,7 +245,7 @@ pub enum RuntimeLzmaDecompressor {
 }
 
 impl NativeDecompressor for RuntimeLzmaDecompressor {
-    fn decompress(&self, data: &[u8]) -> Vec<u8> {
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CastError> {
         match self {
             RuntimeLzmaDecompressor::Native(b) => b.decompress(data),
             RuntimeLzmaDecompressor::SevenZip(b) => b.decompress(data),
@@ -279,4 +258,5 @@ impl NativeDecompressor for RuntimeLzmaDecompressor {
 // ============================================================================
 
 pub type CASTLzmaCompressor = CASTCompressor<RuntimeLzmaCompressor>;
-pub type CASTLzmaDecompressor = CASTDecompressor<RuntimeLzmaDecompressor>;
\ No newline at end of file
+pub type CASTLzmaDecompressor = CASTDecompressor<RuntimeLzmaDecompressor>;
+pub type CASTLzmaReader<R> = CastReader<R, RuntimeLzmaDecompressor>;
\ No newline at end of file
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::io;
+
+// ============================================================================
+//  LIBRARY ERROR TYPE
+// ============================================================================
+
+/// Errors returned by the compressor, decompressor and backends.
+/// `chunk` is the 1-based row group (or chunk) index where the problem was detected.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CastError {
+    Io(io::Error),
+    CrcMismatch { chunk: usize, expected: u32, actual: u32 },
+    CorruptHeader { chunk: usize, detail: String },
+    LengthOverflow { chunk: usize, detail: String },
+    CorruptFooter { detail: String },
+    UnsupportedFormat { version: u8 },
+    BackendFailure { backend: &'static str, detail: String },
+    NotACastArchive,
+}
+
+impl CastError {
+    /// True when the archive itself is damaged (as opposed to I/O or backend trouble).
+    pub fn is_corruption(&self) -> bool {
+        matches!(self, CastError::CrcMismatch { .. } | CastError::CorruptHeader { .. } | CastError::LengthOverflow { .. } | CastError::CorruptFooter { .. })
+    }
+}
+
+impl fmt::Display for CastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CastError::Io(e) => write!(f, "I/O error: {}", e),
+            CastError::CrcMismatch { chunk, expected, actual } => write!(f, "CRC Check Failed at Chunk {}. Expected: {}, Got: {}", chunk, expected, actual),
+            CastError::CorruptHeader { chunk, detail } => write!(f, "Corrupted Block Header at Chunk {} ({})", chunk, detail),
+            CastError::LengthOverflow { chunk, detail } => write!(f, "Segment length out of bounds at Chunk {} ({})", chunk, detail),
+            CastError::CorruptFooter { detail } => write!(f, "Corrupted Footer ({})", detail),
+            CastError::UnsupportedFormat { version } => write!(f, "Unsupported CAST format version {}", version),
+            CastError::BackendFailure { backend, detail } => write!(f, "{} backend failure: {}", backend, detail),
+            CastError::NotACastArchive => write!(f, "Invalid CAST file (Missing Magic Footer)"),
+        }
+    }
+}
+
+impl std::error::Error for CastError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CastError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CastError {
+    fn from(e: io::Error) -> Self {
+        CastError::Io(e)
+    }
+}
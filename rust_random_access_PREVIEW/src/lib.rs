@@ -1,2 +1,3 @@
 pub mod cast;
-pub mod cast_lzma;
\ No newline at end of file
+pub mod cast_lzma;
+pub mod error;
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::env;
 use std::fs::File;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufReader, Write};
 use std::path::Path;
 use std::time::Instant;
 
@@ -13,14 +13,10 @@ use cast::cast_lzma::{
     RuntimeLzmaDecompressor,
     CASTLzmaCompressor,
     CASTLzmaDecompressor,
+    CASTLzmaReader,
     try_find_7zip_path
 };
-
-struct SinkWriter;
-impl Write for SinkWriter {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { Ok(buf.len()) }
-    fn flush(&mut self) -> io::Result<()> { Ok(()) }
-}
+use cast::cast::{render_skeleton, OutputLimit, RangeSet};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -33,7 +29,9 @@ fn main() {
     }
 
     let use_multithread = args.iter().any(|arg| arg == "--multithread");
+    let show_templates = args.iter().any(|arg| arg == "--templates");
     let verify_flag = args.iter().any(|arg| arg == "-v" || arg == "--verify");
+    let fsync = !args.iter().any(|arg| arg == "--no-fsync");
 
     let mut chunk_size_bytes: Option<usize> = None;
     if let Some(pos) = args.iter().position(|arg| arg == "--chunk-size") {
@@ -63,21 +61,58 @@ fn main() {
     let mut target_rows: Option<(u64, u64)> = None;
     if let Some(pos) = args.iter().position(|arg| arg == "--rows") {
         if pos + 1 < args.len() {
-            let val = &args[pos+1];
-            let parts: Vec<&str> = val.split('-').collect();
-            if parts.len() == 2 {
-                let user_start = parts[0].parse::<u64>().unwrap_or(1);
-                let user_end = parts[1].parse::<u64>().unwrap_or(u64::MAX);
-                let start = user_start.saturating_sub(1);
-                let end = user_end.saturating_sub(1);
-                target_rows = Some((start, end));
-            } else {
+            target_rows = parse_row_range(&args[pos+1]);
+            if target_rows.is_none() {
                 eprintln!("[!] Error: Invalid rows format. Use START-END (e.g., --rows 1-1000)");
                 std::process::exit(1);
             }
         }
     }
 
+    // --exclude-rows may be repeated; combined with --rows, only the rows of that range that
+    // are not excluded are restored
+    let mut excluded_rows: Vec<(u64, u64)> = Vec::new();
+    for pair in args.windows(2).filter(|w| w[0] == "--exclude-rows") {
+        match parse_row_range(&pair[1]) {
+            Some(range) => excluded_rows.push(range),
+            None => {
+                eprintln!("[!] Error: Invalid exclude-rows format. Use START-END (e.g., --exclude-rows 105000-106000)");
+                std::process::exit(1);
+            }
+        }
+    }
+    let row_selection = if target_rows.is_none() && excluded_rows.is_empty() {
+        None
+    } else {
+        let base = target_rows.map_or_else(RangeSet::all, |r| RangeSet::from_ranges([r]));
+        Some(base.difference(&RangeSet::from_ranges(excluded_rows.iter().copied())))
+    };
+
+    let mut output_limit: Option<OutputLimit> = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--limit") {
+        if pos + 1 < args.len() {
+            output_limit = parse_limit(&args[pos+1]);
+            if output_limit.is_none() {
+                eprintln!("[!] Error: Invalid limit. Use a row count (e.g., 1000) or a size (e.g., 64KB).");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut jobs: usize = 1;
+    if let Some(pos) = args.iter().position(|arg| arg == "--jobs") {
+        if pos + 1 < args.len() {
+            match args[pos+1].parse::<usize>() {
+                Ok(0) => jobs = num_cpus::get(),
+                Ok(n) => jobs = n,
+                Err(_) => {
+                    eprintln!("[!] Error: Invalid jobs count. Use a number (0 = all cores).");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
     let mut mode_arg: Option<String> = None;
     if let Some(pos) = args.iter().position(|arg| arg == "--mode") {
         if pos + 1 < args.len() {
@@ -90,16 +125,21 @@ fn main() {
         return;
     }
 
-    let command_idx = args.iter().position(|a| a.starts_with("-") && (a == "-c" || a == "-d" || a == "-v")).unwrap_or(0);
+    let command_idx = args.iter().position(|a| a.starts_with("-") && (a == "-c" || a == "-d" || a == "-v" || a == "-i" || a == "--info")).unwrap_or(0);
     if command_idx == 0 { print_usage(exe_name); return; }
 
     let mode_cmd = &args[command_idx];
     let input_path = if command_idx + 1 < args.len() { &args[command_idx+1] } else { "" };
     let output_path = if command_idx + 2 < args.len() { &args[command_idx+2] } else { "" };
 
-    println!("\n\n|--    CAST: Columnar Agnostic Structural Transformation (Random Access *PREVIEW* v{})    --|", env!("CARGO_PKG_VERSION"));
-    println!("       Author: Andrea Olivari");
-    println!("       GitHub: https://github.com/AndreaLVR/CAST/tree/main/rust_random_access_PREVIEW\n");
+    // Decompressing to stdout ('-'): keep stdout clean for the data
+    let to_stdout = mode_cmd == "-d" && output_path == "-";
+
+    if !to_stdout {
+        println!("\n\n|--    CAST: Columnar Agnostic Structural Transformation (Random Access *PREVIEW* v{})    --|", env!("CARGO_PKG_VERSION"));
+        println!("       Author: Andrea Olivari");
+        println!("       GitHub: https://github.com/AndreaLVR/CAST/tree/main/rust_random_access_PREVIEW\n");
+    }
 
     // ==================================================================================
     //  BACKEND SELECTION LOGIC (Hybrid Strategy)
@@ -141,13 +181,13 @@ fn main() {
             println!("       Backend:     {}", backend_label_comp);
 
             let final_dict = dict_size_bytes.unwrap_or(128 * 1024 * 1024);
-            do_compress(input_path, output_path, use_multithread, final_dict, chunk_size_bytes, use_7zip_comp);
+            do_compress(input_path, output_path, use_multithread, final_dict, chunk_size_bytes, use_7zip_comp, fsync);
 
             if verify_flag {
                 println!("\n------------------------------------------------");
                 println!("[*]  Verifying...");
                 std::thread::sleep(std::time::Duration::from_millis(500));
-                do_verify_stream(output_path, use_7zip_decomp);
+                do_verify_stream(output_path, use_7zip_decomp, jobs);
             }
         },
         "-d" => {
@@ -156,13 +196,27 @@ fn main() {
                 print_usage(exe_name);
                 return;
             }
-            if let Some((s, e)) = target_rows {
-                println!("\n[*]  Starting Partial Decompression (Rows {}-{})...", s+1, e+1);
-            } else {
-                println!("\n[*]  Starting Full Decompression...");
+            if !to_stdout {
+                if let Some((s, e)) = target_rows {
+                    println!("\n[*]  Starting Partial Decompression (Rows {}-{})...", s+1, e+1);
+                } else if !excluded_rows.is_empty() {
+                    println!("\n[*]  Starting Partial Decompression...");
+                } else {
+                    println!("\n[*]  Starting Full Decompression...");
+                }
+                println!("       Backend:     {}", backend_label_decomp);
+                if !excluded_rows.is_empty() {
+                    let ranges: Vec<String> = RangeSet::from_ranges(excluded_rows.iter().copied()).intervals().iter()
+                        .map(|(s, e)| format!("{}-{}", s + 1, e.saturating_add(1))).collect();
+                    println!("       Excluding:   rows {}", ranges.join(", "));
+                }
+                match output_limit {
+                    Some(OutputLimit::Rows(n)) => println!("       Limit:       {} rows", n),
+                    Some(OutputLimit::Bytes(n)) => println!("       Limit:       {}", format_bytes(n as usize)),
+                    None => {},
+                }
             }
-            println!("       Backend:     {}", backend_label_decomp);
-            do_decompress(input_path, output_path, target_rows, use_7zip_decomp);
+            do_decompress(input_path, output_path, row_selection.as_ref(), output_limit, use_7zip_decomp);
         },
         "-v" | "--verify" => {
              let target = if !input_path.is_empty() { input_path } else { &args[2] };
@@ -173,33 +227,23 @@ fn main() {
              }
              println!("\n[*]  Verifying: {}", target);
              println!("       Backend:     {}", backend_label_decomp);
-             do_verify_stream(target, use_7zip_decomp);
+             println!("       Jobs:        {}", jobs);
+             do_verify_stream(target, use_7zip_decomp, jobs);
         }
-        _ => print_usage(exe_name),
-    }
-}
-
-// [FIX] Nuova funzione smart per stimare la media
-fn estimate_avg_row_size(path: &str) -> usize {
-    let f = match File::open(path) {
-        Ok(f) => f,
-        Err(_) => return 200,
-    };
-    let reader = io::BufReader::new(f);
-    let mut total_bytes = 0;
-    let mut count = 0;
-
-    for line in reader.lines().take(1000) {
-        if let Ok(l) = line {
-            total_bytes += l.len() + 1;
-            count += 1;
+        "-i" | "--info" => {
+            if input_path.is_empty() {
+                eprintln!("[!] Error: Missing archive to inspect.");
+                print_usage(exe_name);
+                return;
+            }
+            println!("\n[*]  Inspecting: {}", input_path);
+            do_info(input_path, show_templates, use_7zip_decomp);
         }
+        _ => print_usage(exe_name),
     }
-
-    if count == 0 { return 200; }
-    std::cmp::max(1, total_bytes / count)
 }
 
+#[allow(clippy::is_digit_ascii_radix)]
 fn parse_size(input: &str) -> Option<usize> {
     let input = input.trim().to_uppercase();
     let digits: String = input.chars().take_while(|c| c.is_digit(10)).collect();
@@ -215,6 +259,24 @@ fn parse_size(input: &str) -> Option<usize> {
     }
 }
 
+fn parse_limit(input: &str) -> Option<OutputLimit> {
+    let input = input.trim();
+    if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        return input.parse::<u64>().ok().map(OutputLimit::Rows);
+    }
+    parse_size(input).map(|n| OutputLimit::Bytes(n as u64))
+}
+
+/// 1-based inclusive "START-END" as a 0-based range. A missing or invalid bound means the
+/// first (or last) row, as --rows always accepted.
+fn parse_row_range(input: &str) -> Option<(u64, u64)> {
+    let (start, end) = input.split_once('-')?;
+    if end.contains('-') { return None; }
+    let start = start.parse::<u64>().unwrap_or(1).saturating_sub(1);
+    let end = end.parse::<u64>().unwrap_or(u64::MAX).saturating_sub(1);
+    Some((start, end))
+}
+
 fn format_bytes(n: usize) -> String {
     let s = n.to_string();
     let mut result = String::new();
@@ -234,27 +296,36 @@ fn print_usage(exe_name: &str) {
           {} [MODE] [INPUT] [OUTPUT] [OPTIONS]\n\n\
         Modes:\n  \
           -c <in> <out>      Compress input file to CAST format\n  \
-          -d <in> <out>      Decompress CAST file to original format\n  \
-          -v <file>          Verify the integrity of a CAST file\n\n\
+          -d <in> <out>      Decompress CAST file to original format ('-' as <out> writes to stdout)\n  \
+          -v <file>          Verify the integrity of a CAST file\n  \
+          -i, --info <file>  Show the row group index of a CAST file\n\n\
         Options:\n  \
           --mode <TYPE>      Backend selection: 'native' or '7zip'\n                         (Default: Hybrid - 7zip for Comp, Native for Decomp)\n  \
           --multithread      Enable parallel compression for higher speed\n  \
           --chunk-size <S>   Split input in chunks (e.g., 64MB) to enable Indexing & Random Access.\n                         Default: Solid Mode (Max Compression, NO INDEX/SEEKING))\n  \
           --dict-size <S>    Set LZMA Dictionary size (Default: 128MB)\n  \
           --rows <S-E>       (Decompression) Extract only specific row range (e.g. 100-200)\n  \
+          --exclude-rows <S-E>\n                     (Decompression) Skip a row range (repeatable); with --rows, only the\n                         rows of that range outside every excluded range are extracted\n  \
+          --limit <N|S>      (Decompression) Stop after N rows (e.g., 1000) or S bytes (e.g., 64KB)\n  \
+          --templates        (Info) List the templates of the first row group with sample rows\n  \
           -v, --verify       (Compression) Run an immediate integrity check\n  \
+          --no-fsync         (Compression) Do not wait for the archive to reach the disk before finishing\n  \
+          --jobs <N>         (Verification) Decode row groups on N threads (0 = all cores, Default: 1)\n  \
           -h, --help         Show this help message\n\n\
         Examples:\n  \
           {} -c data.csv archive.cast --mode 7zip\n  \
           {} -c big.log archive.cast --chunk-size 64MB\n  \
           {} -d archive.cast partial.log --rows 25000-26000\n  \
-          {} -v archive.cast",
+          {} -d archive.cast clean.log --exclude-rows 105000-106000\n  \
+          {} -d archive.cast - --limit 1000\n  \
+          {} -v archive.cast --jobs 0\n  \
+          {} -i archive.cast --templates",
         env!("CARGO_PKG_VERSION"),
-        exe_name, exe_name, exe_name, exe_name, exe_name
+        exe_name, exe_name, exe_name, exe_name, exe_name, exe_name, exe_name, exe_name
     );
 }
 
-fn do_compress(input_path: &str, output_path: &str, multithread: bool, dict_size: u32, chunk_bytes: Option<usize>, use_7zip: bool) {
+fn do_compress(input_path: &str, output_path: &str, multithread: bool, dict_size: u32, chunk_bytes: Option<usize>, use_7zip: bool, fsync: bool) {
     let start_total = Instant::now();
     let f_in = File::open(input_path).expect("Error opening input");
     let f_out = File::create(output_path).expect("Error creating output");
@@ -268,23 +339,29 @@ fn do_compress(input_path: &str, output_path: &str, multithread: bool, dict_size
 
     let mut compressor = CASTLzmaCompressor::new(backend);
 
-    if let Some(bytes) = chunk_bytes {
-        let avg_row_size = estimate_avg_row_size(input_path);
-        let estimated_rows = std::cmp::max(100, bytes / avg_row_size);
-
-        println!("       Chunking:    ACTIVE (Target ~{} bytes)", format_bytes(bytes));
-        println!("                    - Sampled Avg Row Size: {} bytes", avg_row_size);
-        println!("                    - Estimated Rows/Chunk: {}", estimated_rows);
-
-        compressor.set_chunk_size(estimated_rows);
+    if let Some(bytes) = chunk_bytes { compressor.set_chunk_bytes(bytes); }
+    let limits_label = match compressor.chunk_limits() {
+        (Some(rows), None) => format!("every {} rows", rows),
+        (None, Some(bytes)) => format!("every {} of input", format_bytes(bytes)),
+        (Some(rows), Some(bytes)) => format!("every {} rows or {} of input, whichever comes first", rows, format_bytes(bytes)),
+        (None, None) => "single group".to_string(),
+    };
+    if chunk_bytes.is_some() {
+        println!("       Chunking:    ACTIVE (Row group {})", limits_label);
     } else {
-        println!("       Chunking:    DEFAULT (Solid or ~100k rows)");
+        println!("       Chunking:    DEFAULT (Row group {})", limits_label);
     }
 
     let result = compressor.compress_stream(f_in, &mut writer, |chunk_idx, bytes_read| {
         print!("\r       Processing Chunk #{} (Read: {})... ", chunk_idx, format_bytes(bytes_read as usize));
         std::io::stdout().flush().unwrap();
     });
+    // Not done until the footer is on disk: a crash right after "completed" must not tear it
+    let result = result.and_then(|totals| {
+        writer.flush()?;
+        if fsync { writer.get_ref().sync_all()?; }
+        Ok(totals)
+    });
 
     match result {
         Ok((bytes_in, bytes_out)) => {
@@ -293,16 +370,22 @@ fn do_compress(input_path: &str, output_path: &str, multithread: bool, dict_size
             println!("       Total Input:    {}", format_bytes(bytes_in as usize));
             println!("       Total Output:   {}", format_bytes(bytes_out as usize));
             println!("       Ratio:          {:.2}x", ratio);
+            println!("       Row Groups:     Flushed {}", limits_label);
             println!("       Time:           {:.2}s", start_total.elapsed().as_secs_f64());
         },
         Err(e) => eprintln!("\n[!]  Error: {}", e),
     }
 }
 
-fn do_decompress(input_path: &str, output_path: &str, target_rows: Option<(u64, u64)>, use_7zip: bool) {
+fn do_decompress(input_path: &str, output_path: &str, target_rows: Option<&RangeSet>, limit: Option<OutputLimit>, use_7zip: bool) {
     let start = Instant::now();
     let f_in = File::open(input_path).expect("Error opening archive");
-    let f_out = File::create(output_path).expect("Error creating output");
+    let to_stdout = output_path == "-";
+    let f_out: Box<dyn Write> = if to_stdout {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(output_path).expect("Error creating output"))
+    };
     let mut writer = std::io::BufWriter::with_capacity(4 * 1024 * 1024, f_out);
 
     let backend = if use_7zip {
@@ -313,27 +396,85 @@ fn do_decompress(input_path: &str, output_path: &str, target_rows: Option<(u64,
 
     let decompressor = CASTLzmaDecompressor::new(backend);
 
-    match decompressor.decompress_stream(f_in, &mut writer, target_rows) {
+    match decompressor.decompress_stream_limited(f_in, &mut writer, target_rows, limit) {
         Ok(_) => {
              writer.flush().unwrap();
-             println!("[+]  Decompression done in {:.2}s", start.elapsed().as_secs_f64());
+             if !to_stdout { println!("[+]  Decompression done in {:.2}s", start.elapsed().as_secs_f64()); }
         },
         Err(e) => eprintln!("[!]  Error: {}", e),
     }
 }
 
-fn do_verify_stream(input_path: &str, use_7zip: bool) {
-    let f_in = File::open(input_path).expect("Error opening archive");
+fn do_verify_stream(input_path: &str, use_7zip: bool, jobs: usize) {
+    let start = Instant::now();
     let backend = if use_7zip {
         RuntimeLzmaDecompressor::SevenZip(SevenZipDecompressorBackend)
     } else {
         RuntimeLzmaDecompressor::Native(LzmaDecompressorBackend)
     };
     let decompressor = CASTLzmaDecompressor::new(backend);
-    let mut sink = SinkWriter;
 
-    match decompressor.decompress_stream(f_in, &mut sink, None) {
-        Ok(_) => println!("[+]  Integrity Verified."),
-        Err(e) => println!("[!]  Verification Failed: {}", e),
+    let results = match decompressor.verify_groups_parallel(|| File::open(input_path).map(BufReader::new), jobs) {
+        Ok(r) => r,
+        Err(e) => { println!("[!]  Verification Failed: {}", e); return; }
+    };
+
+    let mut failed = 0;
+    let mut total_bytes = 0u64;
+    for r in &results {
+        total_bytes += r.bytes;
+        match &r.result {
+            Ok(_) => println!("       Group #{:<6} OK    {:>12} rows | {:>20} | CRC32 {:08x}", r.index + 1, r.rows, format_bytes(r.bytes as usize), r.crc),
+            Err(e) => { failed += 1; println!("       Group #{:<6} FAIL  {}", r.index + 1, e); }
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let mb_s = if elapsed > 0.0 { total_bytes as f64 / (1024.0 * 1024.0) / elapsed } else { 0.0 };
+    println!("       Restored:    {} in {:.2}s ({:.2} MB/s)", format_bytes(total_bytes as usize), elapsed, mb_s);
+    if failed == 0 { println!("[+]  Integrity Verified ({} groups decodable).", results.len()); }
+    else { println!("[!]  Verification Failed: {} of {} groups could not be decoded.", failed, results.len()); }
+}
+
+fn do_info(input_path: &str, show_templates: bool, use_7zip: bool) {
+    let f_in = File::open(input_path).expect("Error opening archive");
+    let archive_size = f_in.metadata().map(|m| m.len()).unwrap_or(0);
+    let backend = if use_7zip {
+        RuntimeLzmaDecompressor::SevenZip(SevenZipDecompressorBackend)
+    } else {
+        RuntimeLzmaDecompressor::Native(LzmaDecompressorBackend)
+    };
+
+    let mut reader = match CASTLzmaReader::open(f_in, backend) {
+        Ok(r) => r,
+        Err(e) => { eprintln!("[!]  Error: {}", e); std::process::exit(1); }
+    };
+
+    let groups = reader.row_groups();
+    let passthrough = groups.iter().filter(|g| g.kind == 1).count();
+    println!("       Archive Size:   {}", format_bytes(archive_size as usize));
+    println!("       Row Groups:     {} ({} passthrough)", groups.len(), passthrough);
+    println!("       Total Rows:     {}", reader.total_rows());
+    match reader.original_size() {
+        Some(size) => {
+            println!("       Original Size:  {}", format_bytes(size as usize));
+            if archive_size > 0 { println!("       Ratio:          {:.2}x", size as f64 / archive_size as f64); }
+        },
+        None => println!("       Original Size:  unknown (not recorded by this archive version)"),
     }
-}
\ No newline at end of file
+
+    if !show_templates { return; }
+
+    match reader.templates() {
+        Ok(templates) => {
+            println!("\n[*]  Templates (first row group):");
+            for t in templates {
+                println!("\n       #{} ({} rows): {}", t.id, t.occurrences, render_skeleton(&t.skeleton));
+                for sample in &t.samples {
+                    println!("           > {}", sample);
+                }
+            }
+        },
+        Err(e) => eprintln!("[!]  Error: {}", e),
+    }
+}
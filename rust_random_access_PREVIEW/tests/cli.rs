@@ -0,0 +1,38 @@
+// Ties the help of cast_ra_preview to its parser: every option the source spells out must be
+// listed under "Modes" or "Options" in --help, and every one listed there must be parsed.
+
+use std::collections::BTreeSet;
+use std::process::Command;
+
+const SOURCE: &str = include_str!("../src/main.rs");
+
+/// The string literals of the source that look like an option: `"-x"`, `"--name"`.
+fn parsed_options() -> BTreeSet<String> {
+    let mut options = BTreeSet::new();
+    for literal in SOURCE.split('"').skip(1).step_by(2) {
+        let name = literal.trim_start_matches('-');
+        if literal.starts_with('-') && !name.is_empty() && literal.len() - name.len() <= 2 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            options.insert(literal.to_string());
+        }
+    }
+    options
+}
+
+/// The names on the "Modes" and "Options" lines of --help (`-v, --verify`, `--mode <TYPE>`).
+fn listed_options() -> BTreeSet<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_cast_ra_preview")).arg("--help").output().expect("cast_ra_preview did not start");
+    let help = String::from_utf8_lossy(&output.stdout).into_owned();
+    help.lines()
+        .filter(|line| line.starts_with("  -"))
+        .flat_map(|line| line.split_whitespace().take_while(|word| word.starts_with('-')).map(|word| word.trim_end_matches(',').to_string()).collect::<Vec<_>>())
+        .collect()
+}
+
+#[test]
+fn parsed_options_are_listed() {
+    let (parsed, listed) = (parsed_options(), listed_options());
+    let unlisted: Vec<_> = parsed.difference(&listed).collect();
+    assert!(unlisted.is_empty(), "options parsed but missing from --help: {:?}", unlisted);
+    let unparsed: Vec<_> = listed.difference(&parsed).collect();
+    assert!(unparsed.is_empty(), "options in --help that are not parsed: {:?}", unparsed);
+}